@@ -0,0 +1,75 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+use artifact_executor::fs::HostFilesystem;
+use artifact_executor::identity::sha256_backend;
+use artifact_executor::identity::IdentityScheme as _;
+use artifact_executor::transport::ContentSha256;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use criterion::Throughput;
+
+const FILE_SIZES_BYTES: [usize; 4] = [1 << 10, 1 << 16, 1 << 20, 1 << 26];
+
+fn bench_identify_file(criterion: &mut Criterion) {
+    let temporary_directory = tempfile::tempdir().expect("temporary directory");
+    let mut filesystem =
+        HostFilesystem::try_new(temporary_directory.path().to_path_buf()).expect("host filesystem");
+
+    let mut group = criterion.benchmark_group("identify_file");
+    for file_size in FILE_SIZES_BYTES {
+        let file_name = format!("file_{}", file_size);
+        let contents: Vec<u8> = (0..file_size).map(|index| (index % 251) as u8).collect();
+        std::fs::write(temporary_directory.path().join(&file_name), &contents)
+            .expect("write benchmark file");
+
+        group.throughput(Throughput::Bytes(file_size as u64));
+        group.bench_with_input(
+            BenchmarkId::new("content_sha256", file_size),
+            &file_name,
+            |bencher, file_name| {
+                bencher.iter(|| {
+                    ContentSha256::identify_file(&mut filesystem, file_name).expect("identify file")
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("content_sha256_read", file_size),
+            &file_name,
+            |bencher, file_name| {
+                bencher.iter(|| {
+                    let file = std::fs::File::open(temporary_directory.path().join(file_name))
+                        .expect("open file");
+                    ContentSha256::identify_content(file).expect("identify content")
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_identify_content(criterion: &mut Criterion) {
+    let backend = sha256_backend();
+    let mut group = criterion.benchmark_group("identify_content");
+    for content_size in FILE_SIZES_BYTES {
+        let contents: Vec<u8> = (0..content_size).map(|index| (index % 251) as u8).collect();
+
+        group.throughput(Throughput::Bytes(content_size as u64));
+        group.bench_with_input(
+            BenchmarkId::new(format!("content_sha256_{}", backend), content_size),
+            &contents,
+            |bencher, contents| {
+                bencher.iter(|| {
+                    ContentSha256::identify_content(contents.as_slice()).expect("identify content")
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_identify_file, bench_identify_content);
+criterion_main!(benches);
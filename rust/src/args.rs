@@ -2,6 +2,7 @@
 // Use of this source code is governed by a Apache-style license that can be
 // found in the LICENSE file.
 
+use crate::transport::IdentityScheme;
 use argh::FromArgs;
 use std::path::PathBuf;
 
@@ -26,6 +27,10 @@ pub struct Args {
     #[argh(option, default = "default_cache_directory()")]
     pub cache_directory: PathBuf,
 
+    /// write a chrome://tracing profile of where the invocation spends its time to this file.
+    #[argh(option)]
+    pub profile: Option<PathBuf>,
+
     #[argh(subcommand)]
     pub command: Command,
 }
@@ -34,14 +39,71 @@ pub struct Args {
 #[derive(Debug, FromArgs, PartialEq)]
 #[argh(subcommand)]
 pub enum Command {
+    Daemon(Daemon),
+    DaemonClient(DaemonClient),
     Execute(Execute),
+    Gc(Gc),
+    ImportNinja(ImportNinja),
+    ImportPackage(ImportPackage),
+    Package(Package),
+    Query(Query),
+    Rekey(Rekey),
+    RunPipeline(RunPipeline),
+    Serve(Serve),
+    ServeRemoteApi(ServeRemoteApi),
+    Sync(Sync),
+    Verify(Verify),
+    Watch(Watch),
+}
+
+/// keep the cache index and file fingerprints in memory, serving clients over a unix socket.
+#[derive(Debug, FromArgs, PartialEq)]
+#[argh(subcommand, name = "daemon")]
+pub struct Daemon {
+    /// socket on which to listen; default: `daemon.sock` in the cache directory.
+    #[argh(option)]
+    pub socket: Option<PathBuf>,
+
+    /// address on which to serve metrics in the Prometheus text format at `/metrics`; default:
+    /// metrics are not served.
+    #[argh(option)]
+    pub metrics_address: Option<String>,
+}
+
+/// send a request to a running daemon and print its response.
+#[derive(Debug, FromArgs, PartialEq)]
+#[argh(subcommand, name = "daemon-client")]
+pub struct DaemonClient {
+    /// socket of the daemon; default: `daemon.sock` in the cache directory.
+    #[argh(option)]
+    pub socket: Option<PathBuf>,
+
+    /// identify a file; may be repeated.
+    #[argh(option)]
+    pub identify_file: Vec<PathBuf>,
+
+    /// identify the input files of the task in this task file.
+    #[argh(option)]
+    pub identify_inputs: Option<PathBuf>,
+
+    /// look up the outputs cached for the task with these inputs.
+    #[argh(option)]
+    pub lookup_outputs: Option<String>,
+
+    /// load or execute the task in this task file.
+    #[argh(option)]
+    pub execute_task: Option<PathBuf>,
+
+    /// stop the daemon.
+    #[argh(switch)]
+    pub shutdown: bool,
 }
 
 /// execute a program.
 #[derive(Debug, FromArgs, PartialEq)]
 #[argh(subcommand, name = "execute")]
 pub struct Execute {
-    /// file where program executable is stored.
+    /// file where program executable is stored, followed by its arguments, one per line.
     #[argh(option)]
     pub program: PathBuf,
 
@@ -56,12 +118,240 @@ pub struct Execute {
     /// file where manifest of output files is stored.
     #[argh(option)]
     pub outputs: PathBuf,
+
+    /// directory in which to write a reproduction bundle if the task fails.
+    #[argh(option)]
+    pub capture_repro: Option<PathBuf>,
+
+    /// include the content of input files in reproduction bundles.
+    #[argh(switch)]
+    pub capture_repro_inputs: bool,
+
+    /// URL of a cache server (see the `serve` subcommand) through which outputs are read and
+    /// written.
+    #[argh(option)]
+    pub remote_cache: Option<String>,
+
+    /// file containing the bearer token to present to the remote cache.
+    #[argh(option)]
+    pub remote_cache_token_file: Option<PathBuf>,
+
+    /// do not replay the recorded stdout and stderr of the task.
+    #[argh(switch)]
+    pub no_replay: bool,
+
+    /// store the blobs written to the cache directory zstd-compressed.
+    #[argh(switch)]
+    pub compress_blobs: bool,
+
+    /// hard-link cached outputs into the working directory, rather than copying them; modifying a
+    /// hard-linked output in place corrupts the cache.
+    #[argh(switch)]
+    pub hard_link_outputs: bool,
+
+    /// report whether the task's outputs are cached, as JSON, without executing it.
+    #[argh(switch)]
+    pub dry_run: bool,
+
+    /// file to which a JSON report of the execution is written, for ingestion by CI systems.
+    #[argh(option)]
+    pub report: Option<PathBuf>,
+
+    /// kill the task if it runs for longer than this many seconds.
+    #[argh(option)]
+    pub timeout_secs: Option<u64>,
+
+    /// identity scheme of the task and the cache directory; one of `content_sha256` (the default)
+    /// or `content_blake2b256`.
+    #[argh(option, default = "IdentityScheme::default()")]
+    pub identity_scheme: IdentityScheme,
+}
+
+/// remove unused tasks and blobs from the cache directory.
+#[derive(Debug, FromArgs, PartialEq)]
+#[argh(subcommand, name = "gc")]
+pub struct Gc {
+    /// evict least recently used tasks until the cache's blobs total at most this many bytes.
+    #[argh(option)]
+    pub max_bytes: Option<u64>,
+
+    /// remove tasks last used more than this many seconds ago.
+    #[argh(option)]
+    pub max_age_secs: Option<u64>,
+}
+
+/// convert a Ninja build file to a task graph.
+#[derive(Debug, FromArgs, PartialEq)]
+#[argh(subcommand, name = "import-ninja")]
+pub struct ImportNinja {
+    /// ninja build file to import.
+    #[argh(option, default = "PathBuf::from(\"build.ninja\")")]
+    pub build_file: PathBuf,
+
+    /// file to which the task graph is written, in the format implied by its extension; default:
+    /// JSON on stdout.
+    #[argh(option)]
+    pub output: Option<PathBuf>,
+}
+
+/// list cached tasks by label and tags.
+#[derive(Debug, FromArgs, PartialEq)]
+#[argh(subcommand, name = "query")]
+pub struct Query {
+    /// only list tasks with this label.
+    #[argh(option)]
+    pub label: Option<String>,
+
+    /// only list tasks with this tag; may be repeated to require several tags.
+    #[argh(option)]
+    pub tag: Vec<String>,
+
+    /// identity scheme by which the cache directory is keyed; one of `content_sha256` (the
+    /// default) or `content_blake2b256`.
+    #[argh(option, default = "IdentityScheme::default()")]
+    pub identity_scheme: IdentityScheme,
+}
+
+/// copy the cache directory to a new cache directory keyed by another identity scheme.
+#[derive(Debug, FromArgs, PartialEq)]
+#[argh(subcommand, name = "rekey")]
+pub struct Rekey {
+    /// directory to which the re-keyed cache is written.
+    #[argh(option)]
+    pub destination: PathBuf,
+
+    /// identity scheme of the re-keyed cache; one of `content_blake2b256`.
+    #[argh(option)]
+    pub identity_scheme: IdentityScheme,
+
+    /// directory against which to resolve input files whose content is not in the cache, to
+    /// re-identify them; default: tasks with such input files are not migrated.
+    #[argh(option)]
+    pub inputs_directory: Option<PathBuf>,
+}
+
+/// run every task in a task graph file, each after the tasks it depends on.
+#[derive(Debug, FromArgs, PartialEq)]
+#[argh(subcommand, name = "run-pipeline")]
+pub struct RunPipeline {
+    /// task graph file, in the format implied by its extension.
+    #[argh(positional)]
+    pub pipeline: PathBuf,
+}
+
+/// install a tarball written by `package` in the cache directory.
+#[derive(Debug, FromArgs, PartialEq)]
+#[argh(subcommand, name = "import-package")]
+pub struct ImportPackage {
+    /// tarball to install.
+    #[argh(positional)]
+    pub package: PathBuf,
+}
+
+/// export a cached task's output files, manifests, and metadata as a tarball.
+#[derive(Debug, FromArgs, PartialEq)]
+#[argh(subcommand, name = "package")]
+pub struct Package {
+    /// identity of the task's inputs, as listed by `query`.
+    #[argh(positional)]
+    pub inputs_identity: String,
+
+    /// tarball to write; default: `package-<inputs identity>.tar` in the working directory.
+    #[argh(option)]
+    pub output: Option<PathBuf>,
+}
+
+/// serve the cache directory over HTTP.
+#[derive(Debug, FromArgs, PartialEq)]
+#[argh(subcommand, name = "serve")]
+pub struct Serve {
+    /// address on which to listen.
+    #[argh(option, default = "String::from(crate::serve::DEFAULT_ADDRESS)")]
+    pub address: String,
+
+    /// file containing the bearer token that clients must present.
+    #[argh(option)]
+    pub token_file: Option<PathBuf>,
+
+    /// refuse uploads.
+    #[argh(switch)]
+    pub read_only: bool,
+}
+
+/// serve the cache directory as a Remote Execution API (REAPI) endpoint over gRPC.
+#[derive(Debug, FromArgs, PartialEq)]
+#[argh(subcommand, name = "serve-remote-api")]
+pub struct ServeRemoteApi {
+    /// address on which to listen.
+    #[argh(option, default = "String::from(crate::grpc::DEFAULT_ADDRESS)")]
+    pub address: String,
+}
+
+/// copy the blobs and task pointers missing from another cache directory to it.
+#[derive(Debug, FromArgs, PartialEq)]
+#[argh(subcommand, name = "sync")]
+pub struct Sync {
+    /// cache directory to synchronize with.
+    #[argh(positional)]
+    pub destination: PathBuf,
+
+    /// also copy what the cache directory lacks from the destination.
+    #[argh(switch)]
+    pub bidirectional: bool,
+
+    /// number of blobs checked for existence at a time.
+    #[argh(option, default = "crate::sync::DEFAULT_BATCH_SIZE")]
+    pub batch_size: usize,
+}
+
+/// check that every blob in the cache directory matches its identity and every pointer refers to
+/// an intact blob.
+#[derive(Debug, FromArgs, PartialEq)]
+#[argh(subcommand, name = "verify")]
+pub struct Verify {
+    /// delete corrupt blobs and broken pointers.
+    #[argh(switch)]
+    pub repair: bool,
+}
+
+/// watch a task's inputs, running the task whenever they change.
+#[derive(Debug, FromArgs, PartialEq)]
+#[argh(subcommand, name = "watch")]
+pub struct Watch {
+    /// task file whose inputs are watched.
+    #[argh(option)]
+    pub task: PathBuf,
+
+    /// milliseconds between checks for changes to inputs, where the operating system does not
+    /// report changes to files.
+    #[argh(option, default = "200")]
+    pub poll_interval_ms: u64,
+
+    /// milliseconds for which inputs must be unchanged before the task is run.
+    #[argh(option, default = "500")]
+    pub debounce_ms: u64,
 }
 
 #[cfg(test)]
 mod tests {
     use super::Args;
+    use super::Command;
+    use super::Daemon;
+    use super::DaemonClient;
+    use super::ImportNinja;
+    use super::ImportPackage;
+    use super::Package;
+    use super::Query;
+    use super::Rekey;
+    use super::RunPipeline;
+    use super::Serve;
+    use super::ServeRemoteApi;
+    use super::Sync;
+    use super::Verify;
+    use super::Watch;
+    use crate::transport::IdentityScheme;
     use argh::FromArgs as _;
+    use std::path::PathBuf;
 
     const OK_EXECUTE_ARGS: [&'static str; 8] = [
         "--program",
@@ -82,6 +372,56 @@ mod tests {
         let _args = Args::from_args(&cmd, &args).expect("args with defaults to work");
     }
 
+    #[test]
+    fn test_report() {
+        let cmd = ["test-artifact-executor"];
+        let mut args: Vec<&str> = vec!["execute"];
+        args.extend(OK_EXECUTE_ARGS);
+        let Command::Execute(execute) = Args::from_args(&cmd, &args)
+            .expect("args without report to work")
+            .command
+        else {
+            panic!("expected execute command");
+        };
+        assert_eq!(None, execute.report);
+
+        args.extend(["--report", "./report.json"]);
+        let Command::Execute(execute) = Args::from_args(&cmd, &args)
+            .expect("args with report to work")
+            .command
+        else {
+            panic!("expected execute command");
+        };
+        assert_eq!(Some(PathBuf::from("./report.json")), execute.report);
+    }
+
+    #[test]
+    fn test_identity_scheme() {
+        let cmd = ["test-artifact-executor"];
+        let mut args: Vec<&str> = vec!["execute"];
+        args.extend(OK_EXECUTE_ARGS);
+        let Command::Execute(execute) = Args::from_args(&cmd, &args)
+            .expect("args without identity scheme to work")
+            .command
+        else {
+            panic!("expected execute command");
+        };
+        assert_eq!(IdentityScheme::ContentSha256, execute.identity_scheme);
+
+        args.extend(["--identity-scheme", "content_blake2b256"]);
+        let Command::Execute(execute) = Args::from_args(&cmd, &args)
+            .expect("args with identity scheme to work")
+            .command
+        else {
+            panic!("expected execute command");
+        };
+        assert_eq!(IdentityScheme::ContentBlake2b256, execute.identity_scheme);
+
+        let mut args: Vec<&str> = vec!["execute", "--identity-scheme", "md5"];
+        args.extend(OK_EXECUTE_ARGS);
+        assert!(Args::from_args(&cmd, &args).is_err());
+    }
+
     #[test]
     fn test_log_level() {
         let cmd = ["test-artifact-executor"];
@@ -180,4 +520,238 @@ mod tests {
         ];
         assert!(Args::from_args(&cmd, &args).is_err());
     }
+
+    #[test]
+    fn test_query() {
+        let cmd = ["test-artifact-executor"];
+        let args = Args::from_args(
+            &cmd,
+            &[
+                "query", "--label", "protoc", "--tag", "release", "--tag", "linux",
+            ],
+        )
+        .expect("query args to work");
+        assert_eq!(
+            Command::Query(Query {
+                label: Some(String::from("protoc")),
+                tag: vec![String::from("release"), String::from("linux")],
+                identity_scheme: IdentityScheme::ContentSha256,
+            }),
+            args.command
+        );
+        let Command::Query(query) =
+            Args::from_args(&cmd, &["query", "--identity-scheme", "content_blake2b256"])
+                .expect("query args with identity scheme to work")
+                .command
+        else {
+            panic!("expected query command");
+        };
+        assert_eq!(IdentityScheme::ContentBlake2b256, query.identity_scheme);
+    }
+
+    #[test]
+    fn test_run_pipeline() {
+        let cmd = ["test-artifact-executor"];
+        let args = Args::from_args(&cmd, &["run-pipeline", "pipeline.toml"])
+            .expect("run-pipeline args to work");
+        assert_eq!(
+            Command::RunPipeline(RunPipeline {
+                pipeline: PathBuf::from("pipeline.toml"),
+            }),
+            args.command
+        );
+    }
+
+    #[test]
+    fn test_package() {
+        let cmd = ["test-artifact-executor"];
+        let args = Args::from_args(&cmd, &["package", "0123abcd", "--output", "out.tar"])
+            .expect("package args to work");
+        assert_eq!(
+            Command::Package(Package {
+                inputs_identity: String::from("0123abcd"),
+                output: Some(PathBuf::from("out.tar")),
+            }),
+            args.command
+        );
+        assert!(Args::from_args(&cmd, &["package"]).is_err());
+
+        let args = Args::from_args(&cmd, &["import-package", "package.tar"])
+            .expect("import-package args to work");
+        assert_eq!(
+            Command::ImportPackage(ImportPackage {
+                package: PathBuf::from("package.tar"),
+            }),
+            args.command
+        );
+    }
+
+    #[test]
+    fn test_watch() {
+        let cmd = ["test-artifact-executor"];
+        let args = Args::from_args(
+            &cmd,
+            &["watch", "--task", "task.json", "--debounce-ms", "50"],
+        )
+        .expect("watch args to work");
+        assert_eq!(
+            Command::Watch(Watch {
+                task: PathBuf::from("task.json"),
+                poll_interval_ms: 200,
+                debounce_ms: 50,
+            }),
+            args.command
+        );
+        assert!(Args::from_args(&cmd, &["watch"]).is_err());
+    }
+
+    #[test]
+    fn test_sync() {
+        let cmd = ["test-artifact-executor"];
+        let args = Args::from_args(&cmd, &["sync", "/mnt/shared/ae-cache", "--bidirectional"])
+            .expect("sync args to work");
+        assert_eq!(
+            Command::Sync(Sync {
+                destination: PathBuf::from("/mnt/shared/ae-cache"),
+                bidirectional: true,
+                batch_size: 1000,
+            }),
+            args.command
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let cmd = ["test-artifact-executor"];
+        let args = Args::from_args(&cmd, &["verify"]).expect("verify args to work");
+        assert_eq!(Command::Verify(Verify { repair: false }), args.command);
+
+        let args = Args::from_args(&cmd, &["verify", "--repair"]).expect("verify args to work");
+        assert_eq!(Command::Verify(Verify { repair: true }), args.command);
+    }
+
+    #[test]
+    fn test_serve() {
+        let cmd = ["test-artifact-executor"];
+        let args = Args::from_args(&cmd, &["serve", "--token-file", "token", "--read-only"])
+            .expect("serve args to work");
+        assert_eq!(
+            Command::Serve(Serve {
+                address: String::from("127.0.0.1:8080"),
+                token_file: Some(PathBuf::from("token")),
+                read_only: true,
+            }),
+            args.command
+        );
+    }
+
+    #[test]
+    fn test_rekey() {
+        let cmd = ["test-artifact-executor"];
+        let args = Args::from_args(
+            &cmd,
+            &[
+                "rekey",
+                "--destination",
+                "ae-cache-blake2b",
+                "--identity-scheme",
+                "content_blake2b256",
+            ],
+        )
+        .expect("rekey args to work");
+        assert_eq!(
+            Command::Rekey(Rekey {
+                destination: PathBuf::from("ae-cache-blake2b"),
+                identity_scheme: IdentityScheme::ContentBlake2b256,
+                inputs_directory: None,
+            }),
+            args.command
+        );
+        assert!(Args::from_args(
+            &cmd,
+            &[
+                "rekey",
+                "--destination",
+                "ae-cache-blake2b",
+                "--identity-scheme",
+                "md5",
+            ],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_serve_remote_api() {
+        let cmd = ["test-artifact-executor"];
+        let args = Args::from_args(&cmd, &["serve-remote-api", "--address", "0.0.0.0:8980"])
+            .expect("serve-remote-api args to work");
+        assert_eq!(
+            Command::ServeRemoteApi(ServeRemoteApi {
+                address: String::from("0.0.0.0:8980"),
+            }),
+            args.command
+        );
+    }
+
+    #[test]
+    fn test_import_ninja() {
+        let cmd = ["test-artifact-executor"];
+        let args = Args::from_args(&cmd, &["import-ninja", "--output", "graph.toml"])
+            .expect("import-ninja args to work");
+        assert_eq!(
+            Command::ImportNinja(ImportNinja {
+                build_file: PathBuf::from("build.ninja"),
+                output: Some(PathBuf::from("graph.toml")),
+            }),
+            args.command
+        );
+    }
+
+    #[test]
+    fn test_daemon() {
+        let cmd = ["test-artifact-executor"];
+        let args = Args::from_args(&cmd, &["daemon"]).expect("daemon args to work");
+        assert_eq!(
+            Command::Daemon(Daemon {
+                socket: None,
+                metrics_address: None,
+            }),
+            args.command
+        );
+
+        let args = Args::from_args(&cmd, &["daemon", "--metrics-address", "127.0.0.1:9090"])
+            .expect("daemon args with metrics address to work");
+        assert_eq!(
+            Command::Daemon(Daemon {
+                socket: None,
+                metrics_address: Some(String::from("127.0.0.1:9090")),
+            }),
+            args.command
+        );
+
+        let args = Args::from_args(
+            &cmd,
+            &[
+                "daemon-client",
+                "--socket",
+                "/tmp/daemon.sock",
+                "--identify-file",
+                "a.txt",
+                "--identify-file",
+                "b.txt",
+            ],
+        )
+        .expect("daemon-client args to work");
+        assert_eq!(
+            Command::DaemonClient(DaemonClient {
+                socket: Some(PathBuf::from("/tmp/daemon.sock")),
+                identify_file: vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")],
+                identify_inputs: None,
+                lookup_outputs: None,
+                execute_task: None,
+                shutdown: false,
+            }),
+            args.command
+        );
+    }
 }
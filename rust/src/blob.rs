@@ -2,17 +2,37 @@
 // Use of this source code is governed by a Apache-style license that can be
 // found in the LICENSE file.
 
-use crate::error::Error as ErrorBound;
+use crate::canonical::ChunkManifest;
+use crate::canonical::FileIdentitiesManifest;
+use crate::canonical_json::to_canonical_pretty_string;
+use crate::canonical_json::to_canonical_string;
+use crate::canonical_json::to_canonical_writer;
+use crate::canonical_json::to_canonical_writer_with_layout;
+use crate::canonical_json::Layout;
+use crate::error::Error;
+use crate::error::ErrorBound;
+use crate::events;
+use crate::events::Event;
 use crate::fs::Filesystem as FilesystemApi;
 use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::limits::Limits;
+use crate::schema::read_versioned;
+use crate::schema::Versioned;
+use crate::transport::FileIdentitiesManifestDelta;
+use anyhow::Context as _;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::io::BufReader;
+use std::io::Chain;
+use std::io::Cursor;
 use std::io::Read;
 use std::io::Seek as _;
 use std::io::SeekFrom;
 use std::io::Write;
 use std::marker::PhantomData;
+use std::path::Path;
 use std::path::PathBuf;
+use std::time::SystemTime;
 
 pub struct BlobCache<
     Filesystem: FilesystemApi,
@@ -20,6 +40,8 @@ pub struct BlobCache<
     Serialization: WriteSerializer + ReadDeserializer,
 > {
     blobs: Filesystem,
+    limits: Limits,
+    compression: Compression,
     _marker: PhantomData<(IdentityScheme, Serialization)>,
 }
 
@@ -32,29 +54,92 @@ impl<
     pub fn new(blobs: Filesystem) -> Self {
         Self {
             blobs,
+            limits: Limits::default(),
+            compression: Compression::None,
             _marker: PhantomData,
         }
     }
 
+    /// Sets how blobs written from now on are stored. Blobs are read the same way however they
+    /// were stored, and identities are always computed over uncompressed content.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the limits checked when reading structured blobs, which may have been written by an
+    /// untrusted party.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
     pub fn read_blob<D: DeserializeOwned>(
         &mut self,
         identity: &IdentityScheme::Identity,
     ) -> anyhow::Result<D> {
+        self.check_blob_size(identity)?;
         read_blob::<Filesystem, IdentityScheme, D, Serialization>(&mut self.blobs, identity)
     }
 
+    /// Reads a persisted transport, upgrading it from the schema version with which it was written.
+    pub fn read_versioned_blob<D: Versioned>(
+        &mut self,
+        identity: &IdentityScheme::Identity,
+    ) -> anyhow::Result<D> {
+        self.check_blob_size(identity)?;
+        let blob_name = PathBuf::from(identity.to_string());
+        let blob_file = BlobReader::new(self.blobs.open_file_for_read(&blob_name)?)?;
+        read_versioned::<D, Serialization, _>(blob_file)
+            .map_err(|error| Error::cache_corruption(identity.to_string(), error).into())
+    }
+
     pub fn write_small_blob<D: Serialize>(
         &mut self,
         data: &D,
     ) -> anyhow::Result<IdentityScheme::Identity> {
-        write_small_blob::<Filesystem, D, IdentityScheme, Serialization>(&mut self.blobs, data)
+        write_small_blob::<Filesystem, D, IdentityScheme, Serialization>(
+            &mut self.blobs,
+            data,
+            self.compression,
+        )
+    }
+
+    /// Writes `data` in canonical JSON form, so that its identity does not depend on details of
+    /// `Serialization`. Used for blobs whose identities are used as keys, such as task inputs.
+    pub fn write_canonical_blob<D: Serialize>(
+        &mut self,
+        data: &D,
+    ) -> anyhow::Result<IdentityScheme::Identity> {
+        write_small_blob::<Filesystem, D, IdentityScheme, CanonicalJSON>(
+            &mut self.blobs,
+            data,
+            self.compression,
+        )
+    }
+
+    /// Reads a persisted transport written by `write_canonical_blob`, upgrading it from the schema
+    /// version with which it was written.
+    pub fn read_versioned_canonical_blob<D: Versioned>(
+        &mut self,
+        identity: &IdentityScheme::Identity,
+    ) -> anyhow::Result<D> {
+        self.check_blob_size(identity)?;
+        let blob_name = PathBuf::from(identity.to_string());
+        let blob_file = BlobReader::new(self.blobs.open_file_for_read(&blob_name)?)?;
+        read_versioned::<D, CanonicalJSON, _>(blob_file)
+            .map_err(|error| Error::cache_corruption(identity.to_string(), error).into())
     }
 
     pub fn write_large_blob<D: Serialize>(
         &mut self,
         data: &D,
     ) -> anyhow::Result<IdentityScheme::Identity> {
-        write_large_blob::<Filesystem, D, IdentityScheme, Serialization>(&mut self.blobs, data)
+        write_large_blob::<Filesystem, D, IdentityScheme, Serialization>(
+            &mut self.blobs,
+            data,
+            self.compression,
+        )
     }
 
     pub fn copy_blob<R: Read>(
@@ -62,7 +147,334 @@ impl<
         reader: R,
         identity: &IdentityScheme::Identity,
     ) -> anyhow::Result<()> {
-        copy_blob::<Filesystem, IdentityScheme, R>(&mut self.blobs, reader, identity)
+        copy_blob::<Filesystem, IdentityScheme, R>(
+            &mut self.blobs,
+            reader,
+            identity,
+            self.compression,
+        )
+    }
+
+    /// Opens the raw contents of a blob, for copying it elsewhere (e.g., to a remote cache).
+    pub fn open_blob(
+        &mut self,
+        identity: &IdentityScheme::Identity,
+    ) -> anyhow::Result<BlobReader<Filesystem::Read>> {
+        let blob_file = self
+            .blobs
+            .open_file_for_read(PathBuf::from(identity.to_string()))
+            .map_err(anyhow::Error::from)?;
+        Ok(BlobReader::new(blob_file)?)
+    }
+
+    /// Host path of the blob identified by `identity`, when it is stored uncompressed in a host
+    /// directory, so that its content can be linked to rather than copied.
+    pub fn uncompressed_blob_path(
+        &mut self,
+        identity: &IdentityScheme::Identity,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        let directory = match self.blobs.working_directory() {
+            Some(directory) => directory,
+            None => return Ok(None),
+        };
+        if self.open_blob(identity)?.is_compressed() {
+            return Ok(None);
+        }
+        Ok(Some(directory.join(identity.to_string())))
+    }
+
+    pub fn contains_blob(&mut self, identity: &IdentityScheme::Identity) -> bool {
+        self.blobs
+            .open_file_for_read(PathBuf::from(identity.to_string()))
+            .is_ok()
+    }
+
+    /// Lists the identities of all stored blobs, in sorted order.
+    pub fn blob_identities(&mut self) -> anyhow::Result<Vec<IdentityScheme::Identity>> {
+        crate::cache::list_identities::<Filesystem, IdentityScheme>(&mut self.blobs)
+    }
+
+    /// Gets the size, in bytes, of the stored blob identified by `identity`. The stored size of a
+    /// compressed blob is that of its compressed content.
+    pub fn blob_size(&mut self, identity: &IdentityScheme::Identity) -> anyhow::Result<u64> {
+        self.blobs
+            .metadata(PathBuf::from(identity.to_string()))
+            .map(|metadata| metadata.size)
+            .map_err(anyhow::Error::from)
+    }
+
+    pub fn remove_blob(&mut self, identity: &IdentityScheme::Identity) -> anyhow::Result<()> {
+        self.blobs
+            .remove_file(PathBuf::from(identity.to_string()))
+            .map_err(anyhow::Error::from)
+    }
+
+    /// Stores the content-defined chunks of `reader` as individual blobs, returning the manifest
+    /// from which the content can be reassembled.
+    pub fn write_chunked_blob<R: Read>(
+        &mut self,
+        reader: R,
+    ) -> anyhow::Result<ChunkManifest<IdentityScheme>> {
+        let blobs = &mut self.blobs;
+        let compression = self.compression;
+        ChunkManifest::try_from_content_with_visitor(
+            reader,
+            |chunk, identity: &IdentityScheme::Identity| {
+                let mut blob_file = BlobWriter::new(
+                    blobs.open_file_for_write(PathBuf::from(identity.to_string()))?,
+                    compression,
+                )?;
+                blob_file.write_all(chunk)?;
+                blob_file.finish()?;
+                Ok(())
+            },
+        )
+    }
+
+    /// Writes `manifest`, relative to `parent` (a manifest and the identity under which it is
+    /// stored) when one is given, so that only the differences between them are stored.
+    pub fn write_file_identities_manifest(
+        &mut self,
+        manifest: &FileIdentitiesManifest<IdentityScheme>,
+        parent: Option<(
+            &FileIdentitiesManifest<IdentityScheme>,
+            &IdentityScheme::Identity,
+        )>,
+    ) -> anyhow::Result<IdentityScheme::Identity> {
+        let delta = match parent {
+            Some((parent, parent_identity)) => manifest.delta_from(parent, parent_identity.clone()),
+            None => manifest.as_root_delta(),
+        };
+        self.write_large_blob(&delta)
+    }
+
+    /// Reads a manifest written by `write_file_identities_manifest`, applying the chain of deltas
+    /// from which it is stored.
+    pub fn read_file_identities_manifest(
+        &mut self,
+        identity: &IdentityScheme::Identity,
+    ) -> anyhow::Result<FileIdentitiesManifest<IdentityScheme>> {
+        let mut deltas = vec![];
+        let mut next_identity = Some(identity.clone());
+        while let Some(identity) = next_identity {
+            let delta: FileIdentitiesManifestDelta<IdentityScheme> = self
+                .read_versioned_blob(&identity)
+                .with_context(|| format!("reading manifest delta {}", identity.to_string()))?;
+            next_identity = delta.parent.clone();
+            deltas.push(delta);
+            self.limits
+                .check_manifest_entries("file identities manifest delta chain", deltas.len())?;
+        }
+        let manifest = deltas
+            .into_iter()
+            .rev()
+            .try_fold(FileIdentitiesManifest::empty(), |manifest, delta| {
+                FileIdentitiesManifest::apply_delta(manifest, delta)
+            })?;
+        self.limits
+            .check_manifest_entries("file identities manifest", manifest.identities().count())?;
+        Ok(manifest)
+    }
+
+    fn check_blob_size(&mut self, identity: &IdentityScheme::Identity) -> anyhow::Result<()> {
+        if let Ok(metadata) = self.blobs.metadata(PathBuf::from(identity.to_string())) {
+            self.limits
+                .check_blob_size(&format!("blob {}", identity.to_string()), metadata.size)?;
+        }
+        Ok(())
+    }
+
+    /// Reassembles content stored by `write_chunked_blob` into `writer`.
+    pub fn read_chunked_blob<W: Write>(
+        &mut self,
+        manifest: &ChunkManifest<IdentityScheme>,
+        mut writer: W,
+    ) -> anyhow::Result<()> {
+        for chunk in manifest.chunks() {
+            let mut blob_file = BlobReader::new(
+                self.blobs
+                    .open_file_for_read(PathBuf::from(chunk.identity.to_string()))
+                    .map_err(anyhow::Error::from)?,
+            )?;
+            let copied = std::io::copy(&mut blob_file, &mut writer)?;
+            if copied != chunk.length {
+                anyhow::bail!(
+                    "chunk {:?} at offset {} has length {}, expected {}",
+                    chunk.identity,
+                    chunk.offset,
+                    copied,
+                    chunk.length
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How `BlobCache` stores the content of blobs.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Zstandard compression at `level` (1 through 22; see `zstd::compression_level_range`).
+    Zstd { level: i32 },
+}
+
+impl Compression {
+    /// Zstandard compression at the level that `zstd` uses by default, which favours speed.
+    pub fn zstd() -> Self {
+        Self::Zstd {
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+        }
+    }
+}
+
+/// Prefix of every compressed blob: a Zstandard skippable frame whose content marks the blob as
+/// compressed, so that stored blobs are read correctly however they were written, and remain valid
+/// `.zst` files. Uncompressed content that starts with this prefix is always stored compressed.
+pub const COMPRESSED_BLOB_PREFIX: [u8; 16] = [
+    0x5a, 0x2a, 0x4d, 0x18, 0x08, 0x00, 0x00, 0x00, b'a', b'e', b'-', b'z', b's', b't', b'd', b'1',
+];
+
+/// Reader of the uncompressed content of a stored blob.
+pub enum BlobReader<R: Read> {
+    Uncompressed(Chain<Cursor<Vec<u8>>, R>),
+    Zstd(zstd::stream::read::Decoder<'static, BufReader<R>>),
+}
+
+impl<R: Read> BlobReader<R> {
+    pub fn new(mut reader: R) -> std::io::Result<Self> {
+        let mut prefix = vec![];
+        (&mut reader)
+            .take(COMPRESSED_BLOB_PREFIX.len() as u64)
+            .read_to_end(&mut prefix)?;
+        if prefix == COMPRESSED_BLOB_PREFIX {
+            Ok(Self::Zstd(zstd::stream::read::Decoder::new(reader)?))
+        } else {
+            Ok(Self::Uncompressed(Cursor::new(prefix).chain(reader)))
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        matches!(self, Self::Zstd(_))
+    }
+}
+
+impl<R: Read> Read for BlobReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Uncompressed(reader) => reader.read(buf),
+            Self::Zstd(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Writer that stores blob content as configured by a `Compression`. Content is only complete once
+/// `finish` returns; dropping a writer finishes it, ignoring errors.
+pub struct BlobWriter<W: Write> {
+    state: Option<BlobWriterState<W>>,
+}
+
+enum BlobWriterState<W: Write> {
+    /// Uncompressed content whose prefix is buffered until it is known not to be
+    /// `COMPRESSED_BLOB_PREFIX`.
+    Pending {
+        writer: W,
+        prefix: Vec<u8>,
+    },
+    Uncompressed(W),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> BlobWriter<W> {
+    pub fn new(writer: W, compression: Compression) -> std::io::Result<Self> {
+        let state = match compression {
+            Compression::None => BlobWriterState::Pending {
+                writer,
+                prefix: vec![],
+            },
+            Compression::Zstd { level } => Self::compressed(writer, level)?,
+        };
+        Ok(Self { state: Some(state) })
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        Self::finish_state(self.state.take())
+    }
+
+    fn compressed(mut writer: W, level: i32) -> std::io::Result<BlobWriterState<W>> {
+        writer.write_all(&COMPRESSED_BLOB_PREFIX)?;
+        Ok(BlobWriterState::Zstd(zstd::stream::write::Encoder::new(
+            writer, level,
+        )?))
+    }
+
+    /// Decides how content whose (possibly partial) prefix is `prefix` is stored.
+    fn resolve(mut writer: W, prefix: Vec<u8>) -> std::io::Result<BlobWriterState<W>> {
+        if prefix == COMPRESSED_BLOB_PREFIX {
+            let mut state = Self::compressed(writer, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+            if let BlobWriterState::Zstd(encoder) = &mut state {
+                encoder.write_all(&prefix)?;
+            }
+            Ok(state)
+        } else {
+            writer.write_all(&prefix)?;
+            Ok(BlobWriterState::Uncompressed(writer))
+        }
+    }
+
+    fn finish_state(state: Option<BlobWriterState<W>>) -> std::io::Result<()> {
+        match state {
+            None => Ok(()),
+            Some(BlobWriterState::Pending { writer, prefix }) => {
+                Self::finish_state(Some(Self::resolve(writer, prefix)?))
+            }
+            Some(BlobWriterState::Uncompressed(mut writer)) => writer.flush(),
+            Some(BlobWriterState::Zstd(encoder)) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl<W: Write> Write for BlobWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.state.take() {
+            None => Err(std::io::Error::other("blob writer already failed")),
+            Some(BlobWriterState::Pending { writer, mut prefix }) => {
+                let length = buf.len().min(COMPRESSED_BLOB_PREFIX.len() - prefix.len());
+                prefix.extend_from_slice(&buf[..length]);
+                self.state = Some(if prefix.len() == COMPRESSED_BLOB_PREFIX.len() {
+                    Self::resolve(writer, prefix)?
+                } else {
+                    BlobWriterState::Pending { writer, prefix }
+                });
+                Ok(length)
+            }
+            Some(mut state) => {
+                let result = match &mut state {
+                    BlobWriterState::Uncompressed(writer) => writer.write(buf),
+                    BlobWriterState::Zstd(encoder) => encoder.write(buf),
+                    BlobWriterState::Pending { .. } => unreachable!(),
+                };
+                self.state = Some(state);
+                result
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.state {
+            Some(BlobWriterState::Uncompressed(writer)) => writer.flush(),
+            Some(BlobWriterState::Zstd(encoder)) => encoder.flush(),
+            Some(BlobWriterState::Pending { .. }) | None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Drop for BlobWriter<W> {
+    fn drop(&mut self) {
+        if let Err(error) = Self::finish_state(self.state.take()) {
+            tracing::warn!("finishing blob: {}", error);
+        }
     }
 }
 
@@ -133,6 +545,29 @@ impl<
             destination_identity,
         )
     }
+
+    /// Lists the identities from which pointers are stored, in sorted order.
+    pub fn source_identities(&mut self) -> anyhow::Result<Vec<IdentityScheme::Identity>> {
+        crate::cache::list_identities::<Filesystem, IdentityScheme>(&mut self.blob_pointers)
+    }
+
+    pub fn remove_blob_pointer(
+        &mut self,
+        source_identity: &IdentityScheme::Identity,
+    ) -> anyhow::Result<()> {
+        self.blob_pointers
+            .remove_file(PathBuf::from(source_identity.to_string()))
+            .map_err(anyhow::Error::from)
+    }
+
+    /// Gets the time at which the pointer from `source_identity` was last written, if it exists and
+    /// the filesystem records modification times.
+    pub fn modified(&mut self, source_identity: &IdentityScheme::Identity) -> Option<SystemTime> {
+        self.blob_pointers
+            .metadata(PathBuf::from(source_identity.to_string()))
+            .ok()
+            .and_then(|metadata| metadata.modified)
+    }
 }
 
 pub struct BlobPointerFileCache<Filesystem: FilesystemApi, IdentityScheme: IdentitySchemeApi> {
@@ -183,6 +618,12 @@ pub trait StringSerializer {
     type Error: ErrorBound;
 
     fn to_string<D: Serialize>(data: &D) -> Result<String, Self::Error>;
+
+    /// Serializes `data` to the bytes stored in blobs. Defaults to the UTF-8 encoding of
+    /// `to_string`; binary formats override this.
+    fn to_bytes<D: Serialize>(data: &D) -> Result<Vec<u8>, Self::Error> {
+        Self::to_string(data).map(String::into_bytes)
+    }
 }
 
 pub trait WriteSerializer {
@@ -227,6 +668,147 @@ impl ReadDeserializer for JSON {
     }
 }
 
+/// JSON in the canonical form described in `crate::canonical_json`. Canonical JSON is valid JSON, so
+/// it is read like any other JSON.
+pub struct CanonicalJSON;
+
+impl FileFormat for CanonicalJSON {
+    const EXTENSION: &'static str = "json";
+}
+
+impl StringSerializer for CanonicalJSON {
+    type Error = serde_json::Error;
+
+    fn to_string<D: Serialize>(data: &D) -> Result<String, Self::Error> {
+        to_canonical_string(data)
+    }
+}
+
+impl WriteSerializer for CanonicalJSON {
+    type Error = serde_json::Error;
+
+    fn to_writer<W: Write, D: Serialize>(writer: W, data: &D) -> Result<(), Self::Error> {
+        to_canonical_writer(writer, data)
+    }
+}
+
+impl ReadDeserializer for CanonicalJSON {
+    type Error = serde_json::Error;
+
+    fn from_reader<R: Read, D: DeserializeOwned>(reader: R) -> Result<D, Self::Error> {
+        serde_json::from_reader(reader)
+    }
+}
+
+/// JSON in the pretty layout of canonical JSON: sorted keys and one value per line. Intended for
+/// blobs that people read or review, such as manifests checked into version control; identities are
+/// always computed from the compact canonical form.
+pub struct PrettyJSON;
+
+impl FileFormat for PrettyJSON {
+    const EXTENSION: &'static str = "json";
+}
+
+impl StringSerializer for PrettyJSON {
+    type Error = serde_json::Error;
+
+    fn to_string<D: Serialize>(data: &D) -> Result<String, Self::Error> {
+        to_canonical_pretty_string(data)
+    }
+}
+
+impl WriteSerializer for PrettyJSON {
+    type Error = serde_json::Error;
+
+    fn to_writer<W: Write, D: Serialize>(writer: W, data: &D) -> Result<(), Self::Error> {
+        to_canonical_writer_with_layout(writer, data, Layout::Pretty)
+    }
+}
+
+impl ReadDeserializer for PrettyJSON {
+    type Error = serde_json::Error;
+
+    fn from_reader<R: Read, D: DeserializeOwned>(reader: R) -> Result<D, Self::Error> {
+        serde_json::from_reader(reader)
+    }
+}
+
+/// CBOR (RFC 8949), a compact, self-describing binary format. `to_string` yields hex-encoded CBOR.
+pub struct CBOR;
+
+impl FileFormat for CBOR {
+    const EXTENSION: &'static str = "cbor";
+}
+
+impl StringSerializer for CBOR {
+    type Error = ciborium::ser::Error<std::io::Error>;
+
+    fn to_string<D: Serialize>(data: &D) -> Result<String, Self::Error> {
+        Self::to_bytes(data).map(hex::encode)
+    }
+
+    fn to_bytes<D: Serialize>(data: &D) -> Result<Vec<u8>, Self::Error> {
+        let mut bytes = vec![];
+        ciborium::ser::into_writer(data, &mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl WriteSerializer for CBOR {
+    type Error = ciborium::ser::Error<std::io::Error>;
+
+    fn to_writer<W: Write, D: Serialize>(writer: W, data: &D) -> Result<(), Self::Error> {
+        ciborium::ser::into_writer(data, writer)
+    }
+}
+
+impl ReadDeserializer for CBOR {
+    type Error = ciborium::de::Error<std::io::Error>;
+
+    fn from_reader<R: Read, D: DeserializeOwned>(reader: R) -> Result<D, Self::Error> {
+        ciborium::de::from_reader(reader)
+    }
+}
+
+/// The `bincode` binary format: the most compact and fastest to decode, but not self-describing.
+/// It therefore cannot represent types that use `#[serde(flatten)]` or
+/// `#[serde(skip_serializing_if)]`, or be read as an untyped value, which rules out task transports
+/// and any transport read through `crate::schema` migrations. `to_string` yields hex-encoded
+/// `bincode`.
+pub struct Bincode;
+
+impl FileFormat for Bincode {
+    const EXTENSION: &'static str = "bincode";
+}
+
+impl StringSerializer for Bincode {
+    type Error = bincode::Error;
+
+    fn to_string<D: Serialize>(data: &D) -> Result<String, Self::Error> {
+        Self::to_bytes(data).map(hex::encode)
+    }
+
+    fn to_bytes<D: Serialize>(data: &D) -> Result<Vec<u8>, Self::Error> {
+        bincode::serialize(data)
+    }
+}
+
+impl WriteSerializer for Bincode {
+    type Error = bincode::Error;
+
+    fn to_writer<W: Write, D: Serialize>(writer: W, data: &D) -> Result<(), Self::Error> {
+        bincode::serialize_into(writer, data)
+    }
+}
+
+impl ReadDeserializer for Bincode {
+    type Error = bincode::Error;
+
+    fn from_reader<R: Read, D: DeserializeOwned>(reader: R) -> Result<D, Self::Error> {
+        bincode::deserialize_from(reader)
+    }
+}
+
 // pub struct JSON5;
 
 // impl StringSerializer for JSON5 {
@@ -266,7 +848,7 @@ fn read_blob<
     identity: &IdentityScheme::Identity,
 ) -> Result<D, anyhow::Error> {
     let blob_name = PathBuf::from(identity.to_string());
-    let blob_file = filesystem.open_file_for_read(&blob_name)?;
+    let blob_file = BlobReader::new(filesystem.open_file_for_read(&blob_name)?)?;
     RD::from_reader(blob_file).map_err(anyhow::Error::from)
 }
 
@@ -295,12 +877,15 @@ fn write_small_blob<
 >(
     filesystem: &mut Filesystem,
     data: &D,
+    compression: Compression,
 ) -> Result<IdentityScheme::Identity, anyhow::Error> {
-    let blob_string = S::to_string(data)?;
-    let identity = IdentityScheme::identify_content(blob_string.as_bytes())?;
+    let blob_bytes = S::to_bytes(data)?;
+    let identity = IdentityScheme::identify_content(blob_bytes.as_slice())?;
     let blob_name = PathBuf::from(identity.to_string());
-    let mut blob_file = filesystem.open_file_for_write(&blob_name)?;
-    blob_file.write_all(blob_string.as_bytes())?;
+    let mut blob_file = BlobWriter::new(filesystem.open_file_for_write(&blob_name)?, compression)?;
+    blob_file.write_all(&blob_bytes)?;
+    blob_file.finish()?;
+    publish_blob_written(filesystem, &blob_name);
     Ok(identity)
 }
 
@@ -312,20 +897,28 @@ fn write_large_blob<
 >(
     filesystem: &mut Filesystem,
     data: &D,
+    compression: Compression,
 ) -> Result<IdentityScheme::Identity, anyhow::Error> {
     let random_u64: u64 = rand::random();
     let temporary_blob_name = PathBuf::from(format!("temporary_blob_{}", random_u64));
 
     {
-        let blob = filesystem.open_file_for_write(&temporary_blob_name)?;
-        S::to_writer(blob, data)?;
+        let mut blob = BlobWriter::new(
+            filesystem.open_file_for_write(&temporary_blob_name)?,
+            compression,
+        )?;
+        S::to_writer(&mut blob, data)?;
+        blob.finish()?;
     }
 
-    let identity = IdentityScheme::identify_file(filesystem, &temporary_blob_name)?;
+    let identity = IdentityScheme::identify_content(BlobReader::new(
+        filesystem.open_file_for_read(&temporary_blob_name)?,
+    )?)?;
     let blob_name = PathBuf::from(identity.to_string());
     filesystem
         .move_from_to(&temporary_blob_name, &blob_name)
         .map_err(anyhow::Error::from)?;
+    publish_blob_written(filesystem, &blob_name);
     Ok(identity)
 }
 
@@ -333,15 +926,20 @@ fn copy_blob<Filesystem: FilesystemApi, IdentityScheme: IdentitySchemeApi, R: Re
     filesystem: &mut Filesystem,
     mut blob: R,
     identity: &IdentityScheme::Identity,
+    compression: Compression,
 ) -> Result<(), anyhow::Error> {
     let blob_name = PathBuf::from(identity.to_string());
 
     {
-        let mut blob_file = filesystem.open_file_for_write(&blob_name)?;
+        let mut blob_file =
+            BlobWriter::new(filesystem.open_file_for_write(&blob_name)?, compression)?;
         std::io::copy(&mut blob, &mut blob_file)?;
+        blob_file.finish()?;
     }
 
-    let computed_identity = IdentityScheme::identify_file(filesystem, &blob_name)?;
+    let computed_identity = IdentityScheme::identify_content(BlobReader::new(
+        filesystem.open_file_for_read(&blob_name)?,
+    )?)?;
     if identity != &computed_identity {
         anyhow::bail!(
             "attempted to copy blob identified as {:?}, but computed identity is {:?}",
@@ -349,10 +947,24 @@ fn copy_blob<Filesystem: FilesystemApi, IdentityScheme: IdentitySchemeApi, R: Re
             computed_identity
         );
     }
+    publish_blob_written(filesystem, &blob_name);
 
     Ok(())
 }
 
+/// Publishes `Event::BlobWritten` for the blob stored as `blob_name` in `filesystem`.
+fn publish_blob_written<Filesystem: FilesystemApi>(filesystem: &mut Filesystem, blob_name: &Path) {
+    if !events::is_active() {
+        return;
+    }
+    if let (Some(hash), Ok(metadata)) = (blob_name.to_str(), filesystem.metadata(blob_name)) {
+        events::publish(&Event::BlobWritten {
+            hash,
+            bytes: metadata.size,
+        });
+    }
+}
+
 fn write_raw_blob_pointer<
     Filesystem: FilesystemApi,
     IdentityScheme: IdentitySchemeApi,
@@ -364,7 +976,7 @@ fn write_raw_blob_pointer<
 ) -> Result<(), anyhow::Error> {
     let blob_name = PathBuf::from(source_identity.to_string());
     let mut blob_file = filesystem.open_file_for_write(&blob_name)?;
-    blob_file.write_all(S::to_string(destination_identity)?.as_bytes())?;
+    blob_file.write_all(&S::to_bytes(destination_identity)?)?;
     Ok(())
 }
 
@@ -378,11 +990,11 @@ fn write_small_blob_pointer<
     source_data: &D,
     destination_identity: &IdentityScheme::Identity,
 ) -> Result<IdentityScheme::Identity, anyhow::Error> {
-    let blob_string = S::to_string(source_data)?;
-    let source_identity = IdentityScheme::identify_content(blob_string.as_bytes())?;
+    let blob_bytes = S::to_bytes(source_data)?;
+    let source_identity = IdentityScheme::identify_content(blob_bytes.as_slice())?;
     let blob_name = PathBuf::from(source_identity.to_string());
     let mut blob_file = filesystem.open_file_for_write(&blob_name)?;
-    blob_file.write_all(S::to_string(destination_identity)?.as_bytes())?;
+    blob_file.write_all(&S::to_bytes(destination_identity)?)?;
     Ok(source_identity)
 }
 
@@ -403,7 +1015,7 @@ fn write_large_blob_pointer<
     let source_identity = IdentityScheme::identify_content(&mut temporary_file)?;
     let blob_name = PathBuf::from(source_identity.to_string());
     let mut blob_file = filesystem.open_file_for_write(&blob_name)?;
-    blob_file.write_all(SS::to_string(destination_identity)?.as_bytes())?;
+    blob_file.write_all(&SS::to_bytes(destination_identity)?)?;
     Ok(source_identity)
 }
 
@@ -416,13 +1028,27 @@ mod tests {
     use super::write_raw_blob_pointer;
     use super::write_small_blob;
     use super::write_small_blob_pointer;
+    use super::Bincode;
+    use super::BlobCache;
+    use super::Compression;
+    use super::ReadDeserializer;
+    use super::StringSerializer;
+    use super::WriteSerializer;
+    use super::CBOR;
+    use super::COMPRESSED_BLOB_PREFIX;
     use super::JSON;
+    use crate::canonical::FileIdentitiesManifest;
     use crate::fs::Filesystem as FilesystemApi;
     use crate::fs::HostFilesystem;
+    use crate::fs::MemoryFilesystem;
     use crate::identity::IdentityScheme as _;
+    use crate::limits::Limits;
     use crate::transport::ContentSha256;
+    use crate::transport::Sha256;
     use serde::Deserialize;
     use serde::Serialize;
+    use std::io::Read as _;
+    use std::path::PathBuf;
 
     #[derive(Debug, Deserialize, PartialEq, Serialize)]
     struct A {
@@ -466,18 +1092,30 @@ mod tests {
             b: String::from("2"),
         };
 
-        let a1_identity =
-            write_large_blob::<HostFilesystem, A, ContentSha256, JSON>(&mut blob_filesystem, &a1)
-                .expect("write a1");
-        let a2_identity =
-            write_small_blob::<HostFilesystem, A, ContentSha256, JSON>(&mut blob_filesystem, &a2)
-                .expect("write a2");
-        let b1_identity =
-            write_small_blob::<HostFilesystem, B, ContentSha256, JSON>(&mut blob_filesystem, &b1)
-                .expect("write b1");
-        let b2_identity =
-            write_small_blob::<HostFilesystem, B, ContentSha256, JSON>(&mut blob_filesystem, &b2)
-                .expect("write b2");
+        let a1_identity = write_large_blob::<HostFilesystem, A, ContentSha256, JSON>(
+            &mut blob_filesystem,
+            &a1,
+            Compression::None,
+        )
+        .expect("write a1");
+        let a2_identity = write_small_blob::<HostFilesystem, A, ContentSha256, JSON>(
+            &mut blob_filesystem,
+            &a2,
+            Compression::None,
+        )
+        .expect("write a2");
+        let b1_identity = write_small_blob::<HostFilesystem, B, ContentSha256, JSON>(
+            &mut blob_filesystem,
+            &b1,
+            Compression::None,
+        )
+        .expect("write b1");
+        let b2_identity = write_small_blob::<HostFilesystem, B, ContentSha256, JSON>(
+            &mut blob_filesystem,
+            &b2,
+            Compression::None,
+        )
+        .expect("write b2");
 
         let a1_read =
             read_blob::<HostFilesystem, ContentSha256, A, JSON>(&mut blob_filesystem, &a1_identity)
@@ -563,4 +1201,258 @@ mod tests {
     }
 
     // TODO: Try incorrect identity schemes and serializer/deserializers to test error cases.
+
+    fn check_blob_format<Serialization: StringSerializer + WriteSerializer + ReadDeserializer>() {
+        let mut blob_filesystem = MemoryFilesystem::new();
+        let a = A {
+            a: String::from("a"),
+        };
+        let b = B {
+            b: String::from("b"),
+        };
+
+        let a_identity = write_small_blob::<MemoryFilesystem, A, ContentSha256, Serialization>(
+            &mut blob_filesystem,
+            &a,
+            Compression::None,
+        )
+        .expect("write small blob");
+        assert_eq!(
+            ContentSha256::identify_content(
+                Serialization::to_bytes(&a)
+                    .expect("serialize to bytes")
+                    .as_slice()
+            )
+            .expect("identify serialized bytes"),
+            a_identity
+        );
+        assert_eq!(
+            a_identity,
+            write_large_blob::<MemoryFilesystem, A, ContentSha256, Serialization>(
+                &mut blob_filesystem,
+                &a,
+                Compression::zstd()
+            )
+            .expect("write large blob")
+        );
+        assert_eq!(
+            a,
+            read_blob::<MemoryFilesystem, ContentSha256, A, Serialization>(
+                &mut blob_filesystem,
+                &a_identity
+            )
+            .expect("read blob")
+        );
+
+        let b_identity = write_small_blob_pointer::<
+            MemoryFilesystem,
+            B,
+            ContentSha256,
+            Serialization,
+        >(&mut blob_filesystem, &b, &a_identity)
+        .expect("write pointer b -> a");
+        assert_eq!(
+            a_identity,
+            read_blob_pointer::<MemoryFilesystem, ContentSha256, Serialization>(
+                &mut blob_filesystem,
+                &b_identity
+            )
+            .expect("read pointer b -> a")
+        );
+    }
+
+    #[test]
+    fn test_binary_blob_formats() {
+        check_blob_format::<CBOR>();
+        check_blob_format::<Bincode>();
+        assert_eq!(
+            "a161616161",
+            CBOR::to_string(&A {
+                a: String::from("a")
+            })
+            .expect("CBOR string")
+        );
+        assert_eq!(
+            "010000000000000061",
+            Bincode::to_string(&A {
+                a: String::from("a")
+            })
+            .expect("bincode string")
+        );
+    }
+
+    #[test]
+    fn test_compressed_blobs() {
+        let mut filesystem = MemoryFilesystem::new();
+        let mut compressed =
+            BlobCache::<MemoryFilesystem, ContentSha256, JSON>::new(filesystem.clone())
+                .with_compression(Compression::zstd());
+        let mut uncompressed =
+            BlobCache::<MemoryFilesystem, ContentSha256, JSON>::new(filesystem.clone());
+        let read_stored = |filesystem: &mut MemoryFilesystem, identity: &Sha256| {
+            let mut stored = vec![];
+            filesystem
+                .open_file_for_read(identity.to_string())
+                .expect("open stored blob")
+                .read_to_end(&mut stored)
+                .expect("read stored blob");
+            stored
+        };
+
+        let a = A {
+            a: "a".repeat(4096),
+        };
+        let identity = compressed
+            .write_small_blob(&a)
+            .expect("write compressed blob");
+        assert_eq!(
+            ContentSha256::identify_content(JSON::to_bytes(&a).expect("serialize").as_slice())
+                .expect("identify content"),
+            identity
+        );
+        let stored = read_stored(&mut filesystem, &identity);
+        assert!(stored.starts_with(&COMPRESSED_BLOB_PREFIX));
+        assert!(stored.len() < 4096);
+        assert_eq!(a, compressed.read_blob::<A>(&identity).expect("read blob"));
+        assert_eq!(
+            a,
+            uncompressed.read_blob::<A>(&identity).expect("read blob")
+        );
+        let mut content = vec![];
+        uncompressed
+            .open_blob(&identity)
+            .expect("open blob")
+            .read_to_end(&mut content)
+            .expect("read blob");
+        assert_eq!(JSON::to_bytes(&a).expect("serialize"), content);
+
+        // Uncompressed content that looks like a compressed blob is stored compressed.
+        let lookalike = [&COMPRESSED_BLOB_PREFIX[..], b"content"].concat();
+        let lookalike_identity =
+            ContentSha256::identify_content(lookalike.as_slice()).expect("identify lookalike");
+        uncompressed
+            .copy_blob(lookalike.as_slice(), &lookalike_identity)
+            .expect("copy lookalike");
+        assert_ne!(lookalike, read_stored(&mut filesystem, &lookalike_identity));
+        let mut content = vec![];
+        uncompressed
+            .open_blob(&lookalike_identity)
+            .expect("open lookalike")
+            .read_to_end(&mut content)
+            .expect("read lookalike");
+        assert_eq!(lookalike, content);
+
+        let short = ContentSha256::identify_content(&b"short"[..]).expect("identify short");
+        uncompressed
+            .copy_blob(&b"short"[..], &short)
+            .expect("copy short blob");
+        assert_eq!(b"short".to_vec(), read_stored(&mut filesystem, &short));
+    }
+
+    #[test]
+    fn test_chunked_blob() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("blob filesystem");
+        let mut blob_cache = BlobCache::<HostFilesystem, ContentSha256, JSON>::new(filesystem);
+
+        let content: Vec<u8> = (0..(1u32 << 18))
+            .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+            .collect();
+        let manifest = blob_cache
+            .write_chunked_blob(content.as_slice())
+            .expect("write chunked blob");
+        assert_eq!(content.len() as u64, manifest.length());
+        assert!(manifest.chunks().count() > 1);
+
+        let mut read_content = vec![];
+        blob_cache
+            .read_chunked_blob(&manifest, &mut read_content)
+            .expect("read chunked blob");
+        assert_eq!(content, read_content);
+
+        assert!(manifest
+            .missing_chunks(|identity| blob_cache.contains_blob(identity))
+            .is_empty());
+        let first_identity = manifest
+            .chunks()
+            .next()
+            .expect("first chunk")
+            .identity
+            .clone();
+        let missing = manifest.missing_chunks(|identity| identity != &first_identity);
+        assert_eq!(1, missing.len());
+        assert_eq!(0, missing[0].offset);
+    }
+
+    #[test]
+    fn test_file_identities_manifest_deltas() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("blob filesystem");
+        let mut blob_cache = BlobCache::<HostFilesystem, ContentSha256, JSON>::new(filesystem);
+        let identity = |content: &str| {
+            Some(ContentSha256::identify_content(content.as_bytes()).expect("identity"))
+        };
+
+        let first = FileIdentitiesManifest::<ContentSha256>::new([
+            ("a", identity("a")),
+            ("b", identity("b")),
+            ("c", identity("c")),
+        ]);
+        let second = FileIdentitiesManifest::<ContentSha256>::new([
+            ("a", identity("a")),
+            ("c", identity("changed")),
+            ("d", None),
+        ]);
+        let third = FileIdentitiesManifest::<ContentSha256>::new([("d", identity("d"))]);
+
+        let first_identity = blob_cache
+            .write_file_identities_manifest(&first, None)
+            .expect("write first manifest");
+        let second_identity = blob_cache
+            .write_file_identities_manifest(&second, Some((&first, &first_identity)))
+            .expect("write second manifest");
+        let third_identity = blob_cache
+            .write_file_identities_manifest(&third, Some((&second, &second_identity)))
+            .expect("write third manifest");
+
+        let delta = second.delta_from(&first, first_identity.clone());
+        assert_eq!(vec![(PathBuf::from("d"), None)], delta.added);
+        assert_eq!(vec![PathBuf::from("b")], delta.removed);
+        assert_eq!(
+            vec![(PathBuf::from("c"), identity("changed"))],
+            delta.changed
+        );
+
+        for (identity, manifest) in [
+            (first_identity.clone(), first),
+            (second_identity, second),
+            (third_identity, third),
+        ] {
+            assert_eq!(
+                manifest,
+                blob_cache
+                    .read_file_identities_manifest(&identity)
+                    .expect("read manifest")
+            );
+        }
+
+        let mut blob_cache = blob_cache.with_limits(Limits {
+            max_manifest_entries: 2,
+            ..Limits::default()
+        });
+        assert!(blob_cache
+            .read_file_identities_manifest(&first_identity)
+            .is_err());
+        let mut blob_cache = blob_cache.with_limits(Limits {
+            max_blob_size: 8,
+            ..Limits::default()
+        });
+        assert!(blob_cache
+            .read_versioned_blob::<crate::transport::FileIdentitiesManifestDelta<ContentSha256>>(
+                &first_identity
+            )
+            .is_err());
+    }
 }
@@ -0,0 +1,462 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! A persistent record of which files each task consumed and produced, so that changes to files
+//! can be mapped to the tasks that must re-execute.
+//!
+//! Tasks are nodes; a task depends on another when it consumed a file that the other produced.
+//! Because the graph is recorded from executions rather than declared, it also captures
+//! dependencies discovered while resolving inputs (e.g., included headers).
+
+use crate::blob::JSON;
+use crate::canonical::FileIdentitiesManifest;
+use crate::canonical_json::to_canonical_pretty_string;
+use crate::fs::Filesystem as FilesystemApi;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::schema::read_versioned;
+use crate::schema::FormatVersion;
+use crate::transport::BuildGraph as BuildGraphTransport;
+use crate::transport::BuildGraphNode as BuildGraphNodeTransport;
+use anyhow::Context as _;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::io::Write as _;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug)]
+struct BuildGraphNode<IS: IdentitySchemeApi> {
+    inputs: BTreeMap<PathBuf, Option<IS::Identity>>,
+    outputs: BTreeMap<PathBuf, Option<IS::Identity>>,
+}
+
+/// The files consumed and produced by each task, as of each task's latest execution.
+#[derive(Clone, Debug)]
+pub struct BuildGraph<IS: IdentitySchemeApi> {
+    tasks: BTreeMap<String, BuildGraphNode<IS>>,
+    /// Label of the task that produces each output file.
+    producers: BTreeMap<PathBuf, String>,
+}
+
+impl<IS: IdentitySchemeApi> Default for BuildGraph<IS> {
+    fn default() -> Self {
+        Self {
+            tasks: BTreeMap::new(),
+            producers: BTreeMap::new(),
+        }
+    }
+}
+
+impl<IS: IdentitySchemeApi> BuildGraph<IS> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = &String> {
+        self.tasks.keys()
+    }
+
+    /// Records that the task labelled `label` consumed `inputs` and produced `outputs`, replacing
+    /// any earlier record for the task. Fails if another task is recorded as producing one of
+    /// `outputs`.
+    pub fn record_task(
+        &mut self,
+        label: &str,
+        inputs: &FileIdentitiesManifest<IS>,
+        outputs: &FileIdentitiesManifest<IS>,
+    ) -> anyhow::Result<()> {
+        for (path, _) in outputs.identities() {
+            if let Some(producer) = self.producers.get(path) {
+                if producer != label {
+                    anyhow::bail!(
+                        "task, {:?}, produces {:?}, which task, {:?}, already produces",
+                        label,
+                        path,
+                        producer
+                    );
+                }
+            }
+        }
+        self.remove_task(label);
+        for (path, _) in outputs.identities() {
+            self.producers.insert(path.clone(), label.to_string());
+        }
+        self.tasks.insert(
+            label.to_string(),
+            BuildGraphNode {
+                inputs: inputs.identities().cloned().collect(),
+                outputs: outputs.identities().cloned().collect(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Forgets the task labelled `label`, returning whether it was recorded.
+    pub fn remove_task(&mut self, label: &str) -> bool {
+        match self.tasks.remove(label) {
+            Some(node) => {
+                for path in node.outputs.keys() {
+                    self.producers.remove(path);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Gets the label of the task that produces `path`, if any.
+    pub fn producer<P: AsRef<Path>>(&self, path: P) -> Option<&String> {
+        self.producers.get(path.as_ref())
+    }
+
+    /// Gets the labels of tasks that consumed `path`.
+    pub fn consumers<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = &'a String> + 'a {
+        self.tasks
+            .iter()
+            .filter(move |(_, node)| node.inputs.contains_key(path))
+            .map(|(label, _)| label)
+    }
+
+    /// Gets the labels of tasks that consumed an output of the task labelled `label`.
+    pub fn dependents(&self, label: &str) -> BTreeSet<&String> {
+        self.tasks
+            .get(label)
+            .into_iter()
+            .flat_map(|node| node.outputs.keys())
+            .flat_map(|path| self.consumers(path))
+            .filter(|dependent| dependent.as_str() != label)
+            .collect()
+    }
+
+    /// Gets the tasks that must re-execute when the files at `changed_paths` change: Those that
+    /// consumed a changed file, and, transitively, those that consumed their outputs. Tasks are
+    /// ordered so that each follows the tasks whose outputs it consumed.
+    pub fn invalidated_by<P: AsRef<Path>, I: IntoIterator<Item = P>>(
+        &self,
+        changed_paths: I,
+    ) -> Vec<String> {
+        let changed_paths: Vec<PathBuf> = changed_paths
+            .into_iter()
+            .map(|path| path.as_ref().to_path_buf())
+            .collect();
+        let directly_invalidated: BTreeSet<&String> = self
+            .tasks
+            .iter()
+            .filter(|(_, node)| {
+                changed_paths
+                    .iter()
+                    .any(|path| node.inputs.contains_key(path))
+            })
+            .map(|(label, _)| label)
+            .collect();
+        self.with_dependents_in_order(directly_invalidated)
+    }
+
+    /// Gets the tasks that must re-execute given the current identities of files, as reported by
+    /// `identify` (`None` for a missing or unidentifiable file). A task is stale when an input no
+    /// longer has the identity it had when the task executed, or when it consumed an output of a
+    /// stale task. Tasks are ordered as in `invalidated_by`.
+    pub fn stale_tasks<F: FnMut(&Path) -> anyhow::Result<Option<IS::Identity>>>(
+        &self,
+        mut identify: F,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut current_identities: BTreeMap<&PathBuf, Option<IS::Identity>> = BTreeMap::new();
+        let mut directly_stale = BTreeSet::new();
+        for (label, node) in self.tasks.iter() {
+            for (path, recorded_identity) in node.inputs.iter() {
+                let current_identity = match self.recorded_output(path) {
+                    // Compare against what the producer last produced; the producer is itself
+                    // checked against the filesystem.
+                    Some(produced_identity) => produced_identity.clone(),
+                    None => {
+                        if !current_identities.contains_key(path) {
+                            let identity = identify(path)
+                                .with_context(|| format!("identifying {:?}", path))?;
+                            current_identities.insert(path, identity);
+                        }
+                        current_identities[path].clone()
+                    }
+                };
+                if &current_identity != recorded_identity {
+                    directly_stale.insert(label);
+                    break;
+                }
+            }
+            if directly_stale.contains(label) {
+                continue;
+            }
+            for (path, produced_identity) in node.outputs.iter() {
+                if identify(path).with_context(|| format!("identifying {:?}", path))?
+                    != *produced_identity
+                {
+                    directly_stale.insert(label);
+                    break;
+                }
+            }
+        }
+        Ok(self.with_dependents_in_order(directly_stale))
+    }
+
+    fn recorded_output(&self, path: &Path) -> Option<&Option<IS::Identity>> {
+        self.producers
+            .get(path)
+            .and_then(|producer| self.tasks.get(producer))
+            .and_then(|node| node.outputs.get(path))
+    }
+
+    /// Extends `labels` with their transitive dependents, ordering the result so that each task
+    /// follows those whose outputs it consumed. Ties are broken by label.
+    fn with_dependents_in_order(&self, labels: BTreeSet<&String>) -> Vec<String> {
+        let mut invalidated = labels.clone();
+        let mut pending: Vec<&String> = labels.into_iter().collect();
+        while let Some(label) = pending.pop() {
+            for dependent in self.dependents(label) {
+                if invalidated.insert(dependent) {
+                    pending.push(dependent);
+                }
+            }
+        }
+
+        let mut remaining_dependencies: BTreeMap<&String, usize> =
+            invalidated.iter().map(|label| (*label, 0)).collect();
+        for label in invalidated.iter() {
+            for dependent in self.dependents(label) {
+                *remaining_dependencies
+                    .get_mut(dependent)
+                    .expect("dependent is invalidated") += 1;
+            }
+        }
+        let mut ready: BTreeSet<&String> = remaining_dependencies
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(label, _)| *label)
+            .collect();
+        let mut order = Vec::with_capacity(invalidated.len());
+        while let Some(label) = ready.pop_first() {
+            order.push(label.clone());
+            for dependent in self.dependents(label) {
+                let count = remaining_dependencies
+                    .get_mut(dependent)
+                    .expect("dependent is invalidated");
+                *count -= 1;
+                if *count == 0 {
+                    ready.insert(dependent);
+                }
+            }
+        }
+        // Tasks in a cycle (recorded, e.g., from tasks that update their own inputs) are never
+        // ready; append them so that they are still re-executed.
+        for label in invalidated {
+            if !order.contains(label) {
+                order.push(label.clone());
+            }
+        }
+        order
+    }
+}
+
+impl<IS: IdentitySchemeApi> TryFrom<BuildGraphTransport<IS>> for BuildGraph<IS> {
+    type Error = anyhow::Error;
+
+    fn try_from(transport: BuildGraphTransport<IS>) -> anyhow::Result<Self> {
+        if transport.identity_scheme != IS::IDENTITY_SCHEME {
+            anyhow::bail!(
+                "build graph uses identity scheme {:?}, but expected {:?}",
+                transport.identity_scheme,
+                IS::IDENTITY_SCHEME
+            );
+        }
+        let mut graph = Self::new();
+        for (label, node) in transport.tasks {
+            graph
+                .record_task(
+                    &label,
+                    &FileIdentitiesManifest::new(node.inputs),
+                    &FileIdentitiesManifest::new(node.outputs),
+                )
+                .context("loading build graph")?;
+        }
+        Ok(graph)
+    }
+}
+
+impl<IS: IdentitySchemeApi> From<&BuildGraph<IS>> for BuildGraphTransport<IS> {
+    fn from(graph: &BuildGraph<IS>) -> Self {
+        Self {
+            format_version: FormatVersion::default(),
+            identity_scheme: IS::IDENTITY_SCHEME,
+            tasks: graph
+                .tasks
+                .iter()
+                .map(|(label, node)| {
+                    (
+                        label.clone(),
+                        BuildGraphNodeTransport {
+                            inputs: node.inputs.clone().into_iter().collect(),
+                            outputs: node.outputs.clone().into_iter().collect(),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Reads the build graph stored at `path`, or returns an empty graph if there is no file at `path`
+/// (e.g., on the first invocation).
+pub fn read_build_graph_file<FS: FilesystemApi, IS: IdentitySchemeApi, P: AsRef<Path>>(
+    filesystem: &mut FS,
+    path: P,
+) -> anyhow::Result<BuildGraph<IS>> {
+    let path = path.as_ref();
+    if !filesystem.file_exists(path) {
+        return Ok(BuildGraph::new());
+    }
+    let file = filesystem
+        .open_file_for_read(path)
+        .map_err(anyhow::Error::from)
+        .with_context(|| format!("opening build graph file {:?}", path))?;
+    read_versioned::<BuildGraphTransport<IS>, JSON, _>(file)
+        .and_then(BuildGraph::try_from)
+        .with_context(|| format!("reading build graph file {:?}", path))
+}
+
+/// Writes `graph` to `path`, in canonical pretty-printed JSON so that it diffs well.
+pub fn write_build_graph_file<FS: FilesystemApi, IS: IdentitySchemeApi, P: AsRef<Path>>(
+    filesystem: &mut FS,
+    path: P,
+    graph: &BuildGraph<IS>,
+) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let contents = to_canonical_pretty_string(&BuildGraphTransport::from(graph))
+        .context("serializing build graph")?;
+    filesystem
+        .open_file_for_write(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())
+                .map_err(anyhow::Error::from)
+        })
+        .with_context(|| format!("writing build graph file {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_build_graph_file;
+    use super::write_build_graph_file;
+    use super::BuildGraph;
+    use crate::canonical::FileIdentitiesManifest;
+    use crate::fs::HostFilesystem;
+    use crate::identity::IdentityScheme as _;
+    use crate::transport::ContentSha256;
+    use crate::transport::Sha256;
+    use std::collections::BTreeMap;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    fn identify(content: &str) -> Option<Sha256> {
+        Some(ContentSha256::identify_content(content.as_bytes()).expect("identify content"))
+    }
+
+    fn manifest(files: &[(&str, &str)]) -> FileIdentitiesManifest<ContentSha256> {
+        FileIdentitiesManifest::new(
+            files
+                .iter()
+                .map(|(path, content)| (PathBuf::from(path), identify(content))),
+        )
+    }
+
+    #[test]
+    fn test_build_graph() {
+        // generate -> config.h; compile(main.c, config.h) -> main.o; compile_util(util.c) -> util.o;
+        // link(main.o, util.o) -> main.
+        let mut graph = BuildGraph::<ContentSha256>::new();
+        graph
+            .record_task("generate", &manifest(&[]), &manifest(&[("config.h", "h")]))
+            .expect("record generate");
+        graph
+            .record_task(
+                "compile",
+                &manifest(&[("config.h", "h"), ("main.c", "main")]),
+                &manifest(&[("main.o", "main object")]),
+            )
+            .expect("record compile");
+        graph
+            .record_task(
+                "compile_util",
+                &manifest(&[("util.c", "util")]),
+                &manifest(&[("util.o", "util object")]),
+            )
+            .expect("record compile_util");
+        graph
+            .record_task(
+                "link",
+                &manifest(&[("main.o", "main object"), ("util.o", "util object")]),
+                &manifest(&[("main", "binary")]),
+            )
+            .expect("record link");
+        assert!(graph
+            .record_task("other", &manifest(&[]), &manifest(&[("main.o", "other")]))
+            .is_err());
+
+        assert_eq!(Some(&String::from("compile")), graph.producer("main.o"));
+        assert_eq!(
+            vec!["compile", "link"],
+            graph.invalidated_by(["main.c"]).iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["compile", "compile_util", "link"],
+            graph
+                .invalidated_by(["config.h", "util.c"])
+                .iter()
+                .collect::<Vec<_>>()
+        );
+        assert!(graph.invalidated_by(["README.md"]).is_empty());
+
+        let mut current: BTreeMap<&Path, Option<Sha256>> = [
+            ("config.h", "h"),
+            ("main.c", "main"),
+            ("main.o", "main object"),
+            ("util.c", "util"),
+            ("util.o", "util object"),
+            ("main", "binary"),
+        ]
+        .into_iter()
+        .map(|(path, content)| (Path::new(path), identify(content)))
+        .collect();
+        let stale = |graph: &BuildGraph<ContentSha256>,
+                     current: &BTreeMap<&Path, Option<Sha256>>| {
+            graph
+                .stale_tasks(|path| Ok(current.get(path).cloned().flatten()))
+                .expect("stale tasks")
+        };
+        assert!(stale(&graph, &current).is_empty());
+
+        current.insert(Path::new("util.c"), identify("util v2"));
+        assert_eq!(vec!["compile_util", "link"], stale(&graph, &current));
+        current.insert(Path::new("util.c"), identify("util"));
+        current.insert(Path::new("main"), None);
+        assert_eq!(vec!["link"], stale(&graph, &current));
+
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let mut filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        assert!(
+            read_build_graph_file::<_, ContentSha256, _>(&mut filesystem, "graph.json")
+                .expect("read missing build graph")
+                .labels()
+                .next()
+                .is_none()
+        );
+        write_build_graph_file(&mut filesystem, "graph.json", &graph).expect("write build graph");
+        let graph = read_build_graph_file::<_, ContentSha256, _>(&mut filesystem, "graph.json")
+            .expect("read build graph");
+        assert_eq!(
+            vec!["compile", "compile_util", "generate", "link"],
+            graph.labels().collect::<Vec<_>>()
+        );
+        assert_eq!(vec!["link"], stale(&graph, &current));
+    }
+}
@@ -2,22 +2,51 @@
 // Use of this source code is governed by a Apache-style license that can be
 // found in the LICENSE file.
 
+pub mod gc;
+pub mod tiered;
+pub mod verify;
+
 use crate::blob::BlobCache;
 use crate::blob::BlobPointerCache;
+use crate::blob::BlobPointerFileCache;
+use crate::blob::BlobReader;
+use crate::blob::Compression;
 use crate::blob::ReadDeserializer as ReadDeserializerApi;
 use crate::blob::StringSerializer as StringSerializerApi;
 use crate::blob::WriteSerializer as WriteSerializerApi;
+use crate::blob::JSON;
 use crate::canonical::FileIdentitiesManifest;
 use crate::canonical::Listing;
 use crate::canonical::Metadata;
+use crate::canonical::System as CanonicalSystem;
+use crate::canonical::SystemCapture;
 use crate::canonical::TaskInputs;
+use crate::canonical::TaskLabels;
 use crate::canonical::TaskOutputs;
+use crate::execute;
+use crate::execute::identify_task_inputs;
+use crate::fs::FileType;
 use crate::fs::Filesystem as FilesystemApi;
+use crate::identity::symlink_target_content;
 use crate::identity::AsTransport;
 use crate::identity::Identity as IdentityBound;
+use crate::identity::IdentitySalt;
 use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::package;
+use crate::runner::ExecutionResult;
+use crate::schema::read_versioned;
 use crate::transport::Listing as ListingTransport;
+use crate::transport::ListingTimes;
+use crate::transport::SymlinkIdentity;
+use crate::transport::TaskSummary;
+use anyhow::Context as _;
+use serde::de::value::StrDeserializer;
+use serde::Deserialize as _;
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::io::Read;
+use std::io::Write as _;
 use std::marker::PhantomData;
 use std::path::Path;
 use std::path::PathBuf;
@@ -35,8 +64,20 @@ pub trait Index: Sized {
 
     fn put(&mut self, identity: Self::Identity) -> bool;
 
+    /// Puts `identity`, recording `timestamp_nanos` as its insertion time if it is new, and as its
+    /// last-access time either way.
+    fn put_at(&mut self, identity: Self::Identity, timestamp_nanos: i64) -> bool;
+
+    /// Records `timestamp_nanos` as the last-access time of `identity`, if it is indexed.
+    fn touch(&mut self, identity: &Self::Identity, timestamp_nanos: i64) -> bool;
+
+    fn times(&self, identity: &Self::Identity) -> Option<ListingTimes>;
+
     fn remove(&mut self, identity: &Self::Identity) -> bool;
 
+    /// Lists every indexed identity, along with its times.
+    fn entries(&self) -> Vec<(Self::Identity, ListingTimes)>;
+
     fn flush(&mut self) -> Result<(), Self::Error>;
 }
 
@@ -118,7 +159,7 @@ impl<
     fn open<P: AsRef<Path>>(mut filesystem: Filesystem, path: P) -> Result<Self, Self::Error> {
         let listing_file = filesystem.open_file_for_read(&path)?;
         let listing_transport: ListingTransport<IdentityScheme::Identity> =
-            Serialization::from_reader(listing_file)?;
+            read_versioned::<_, Serialization, _>(listing_file)?;
         let listing = Listing::<IdentityScheme::Identity>::try_from(listing_transport)?;
         Ok(Self {
             filesystem,
@@ -132,10 +173,29 @@ impl<
         self.listing.put(identity)
     }
 
+    fn put_at(&mut self, identity: Self::Identity, timestamp_nanos: i64) -> bool {
+        self.listing.put_at(identity, timestamp_nanos)
+    }
+
+    fn touch(&mut self, identity: &Self::Identity, timestamp_nanos: i64) -> bool {
+        self.listing.touch(identity, timestamp_nanos)
+    }
+
+    fn times(&self, identity: &Self::Identity) -> Option<ListingTimes> {
+        self.listing.times(identity).copied()
+    }
+
     fn remove(&mut self, identity: &Self::Identity) -> bool {
         self.listing.remove(identity)
     }
 
+    fn entries(&self) -> Vec<(Self::Identity, ListingTimes)> {
+        self.listing
+            .entries()
+            .map(|(identity, times)| (identity.clone(), *times))
+            .collect()
+    }
+
     fn flush(&mut self) -> Result<(), Self::Error> {
         let listing_transport = self.listing.as_transport();
         let mut listing_file = self.filesystem.open_file_for_write(&self.path)?;
@@ -151,10 +211,32 @@ pub struct Cache<
     Idx: Index<Filesystem = Filesystem, Identity = IdentityScheme::Identity, Error = anyhow::Error>,
 > {
     system: System,
+    system_capture: SystemCapture,
     index: Idx,
     blob_cache: BlobCache<Filesystem, IdentityScheme, Serialization>,
     metadata_pointer_cache: BlobPointerCache<Filesystem, IdentityScheme, Serialization>,
     outputs_pointer_cache: BlobPointerCache<Filesystem, IdentityScheme, Serialization>,
+    executor_pointers: Option<ExecutorPointers<Filesystem, IdentityScheme, Serialization>>,
+    salt: Option<IdentitySalt>,
+    /// Cache directory, when the cache was opened from one. Its blobs may be shared with caches in
+    /// other namespaces.
+    root: Option<Filesystem>,
+    /// Directory, relative to `root`, of this cache's index and pointers; see `namespace_directory`.
+    namespace: PathBuf,
+}
+
+/// Pointers written to a cache directory by `crate::execute::CacheDirectoryTaskExecutor`, keyed by
+/// unsalted task inputs identities. Garbage collection treats them as roots, alongside the index.
+struct ExecutorPointers<
+    Filesystem: FilesystemApi,
+    IdentityScheme: IdentitySchemeApi,
+    Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+> {
+    outputs: BlobPointerCache<Filesystem, IdentityScheme, Serialization>,
+    results: BlobPointerCache<Filesystem, IdentityScheme, Serialization>,
+    failures: BlobPointerCache<Filesystem, IdentityScheme, Serialization>,
+    stdouts: BlobPointerFileCache<Filesystem, IdentityScheme>,
+    stderrs: BlobPointerFileCache<Filesystem, IdentityScheme>,
 }
 
 impl<
@@ -168,6 +250,7 @@ impl<
     pub const DEFAULT_METADATA_POINTERS_SUBDIR: &str = "metadata";
     pub const DEFAULT_OUTPUTS_POINTERS_SUBDIR: &str = "outputs";
     pub const DEFAULT_INPUTS_LISTING_FILE: &str = "inputs.listing";
+    pub const DEFAULT_SALTED_NAMESPACES_SUBDIR: &str = "salted";
 
     pub fn new(
         system: System,
@@ -178,44 +261,209 @@ impl<
     ) -> Self {
         Self {
             system,
+            system_capture: SystemCapture::default(),
             index,
             blob_cache,
             metadata_pointer_cache,
             outputs_pointer_cache,
+            executor_pointers: None,
+            salt: None,
+            root: None,
+            namespace: PathBuf::new(),
+        }
+    }
+
+    /// Selects which properties of the host machine are recorded in the metadata of tasks put into
+    /// this cache. Host properties never contribute to cache keys.
+    pub fn with_system_capture(mut self, system_capture: SystemCapture) -> Self {
+        self.system_capture = system_capture;
+        self
+    }
+
+    /// Selects how blobs put into this cache are stored; see `crate::blob::Compression`. Blobs that
+    /// are already stored are read however they were stored.
+    pub fn with_compression(self, compression: Compression) -> Self {
+        Self {
+            blob_cache: self.blob_cache.with_compression(compression),
+            ..self
         }
     }
 
     pub fn create(filesystem: Filesystem) -> anyhow::Result<Self> {
-        let index = Idx::create(filesystem.clone(), Self::DEFAULT_INPUTS_LISTING_FILE)?;
-        Self::create_or_open_internal(filesystem, index)
+        Self::create_with_salt(filesystem, None)
     }
 
     pub fn open(filesystem: Filesystem) -> anyhow::Result<Self> {
-        let index = Idx::open(filesystem.clone(), Self::DEFAULT_INPUTS_LISTING_FILE)?;
-        Self::create_or_open_internal(filesystem, index)
+        Self::open_with_salt(filesystem, None)
+    }
+
+    /// Creates a cache whose task pointers are keyed by identities salted with `salt`. Each salt
+    /// has its own namespace in the cache directory (see `namespace_directory`), holding its index
+    /// and pointers, so that caches with different salts (or none) share the directory's blobs
+    /// without seeing or poisoning each other's tasks.
+    pub fn create_with_salt(
+        mut filesystem: Filesystem,
+        salt: Option<IdentitySalt>,
+    ) -> anyhow::Result<Self> {
+        let namespace = Self::namespace_directory(&salt)?;
+        if !namespace.as_os_str().is_empty() {
+            filesystem
+                .create_directories(&namespace)
+                .map_err(anyhow::Error::from)
+                .context("creating salted cache namespace")?;
+        }
+        let namespace_filesystem = Self::namespace_filesystem(&mut filesystem, &namespace)?;
+        let index = Idx::create(namespace_filesystem, Self::DEFAULT_INPUTS_LISTING_FILE)?;
+        Self::create_or_open_internal(filesystem, namespace, index, salt)
+    }
+
+    /// Opens the cache in the namespace of `salt`, failing if no cache has been created with it.
+    pub fn open_with_salt(
+        mut filesystem: Filesystem,
+        salt: Option<IdentitySalt>,
+    ) -> anyhow::Result<Self> {
+        let namespace = Self::namespace_directory(&salt)?;
+        let namespace_filesystem = Self::namespace_filesystem(&mut filesystem, &namespace)
+            .context("opening salted cache namespace")?;
+        let index = Idx::open(namespace_filesystem, Self::DEFAULT_INPUTS_LISTING_FILE)?;
+        Self::create_or_open_internal(filesystem, namespace, index, salt)
     }
 
     pub fn open_or_create(filesystem: Filesystem) -> anyhow::Result<Self> {
-        Self::open(filesystem.clone()).or_else(|_| Self::create(filesystem))
+        Self::open_or_create_with_salt(filesystem, None)
+    }
+
+    /// Opens the cache in the namespace of `salt`, creating it if no cache has been created with
+    /// it.
+    pub fn open_or_create_with_salt(
+        filesystem: Filesystem,
+        salt: Option<IdentitySalt>,
+    ) -> anyhow::Result<Self> {
+        Self::open_with_salt(filesystem.clone(), salt.clone())
+            .or_else(|_| Self::create_with_salt(filesystem, salt))
+    }
+
+    /// Opens an existing cache directory, including one written only by a
+    /// `crate::execute::CacheDirectoryTaskExecutor`, which has no index; an empty index is created
+    /// for such directories. Fails, rather than creating a cache, when `filesystem` contains
+    /// neither.
+    pub fn open_existing(mut filesystem: Filesystem) -> anyhow::Result<Self> {
+        if filesystem.file_exists(Self::DEFAULT_INPUTS_LISTING_FILE) {
+            return Self::open(filesystem);
+        }
+        match filesystem.metadata(Self::DEFAULT_BLOBS_SUBDIR) {
+            Ok(metadata) if metadata.file_type == FileType::Directory => Self::create(filesystem),
+            _ => anyhow::bail!("not a cache directory: no index or blobs directory"),
+        }
     }
 
-    fn create_or_open_internal(mut filesystem: Filesystem, index: Idx) -> anyhow::Result<Self> {
+    /// Identity under which pointers for the task whose inputs blob has identity
+    /// `task_inputs_identity` are stored. Equal to `task_inputs_identity` for unsalted caches.
+    pub fn task_pointer_identity(
+        &self,
+        task_inputs_identity: &IdentityScheme::Identity,
+    ) -> anyhow::Result<IdentityScheme::Identity> {
+        match &self.salt {
+            None => Ok(task_inputs_identity.clone()),
+            Some(salt) => salt
+                .identify_content::<IdentityScheme, _>(task_inputs_identity.to_string().as_bytes())
+                .context("salting task inputs identity"),
+        }
+    }
+
+    /// Directory, relative to the cache directory, that holds the index and metadata and outputs
+    /// pointers of the cache salted with `salt`: The cache directory itself for unsalted caches, and
+    /// a subdirectory of `DEFAULT_SALTED_NAMESPACES_SUBDIR` named by the salt's digest otherwise.
+    /// Blobs, and the unsalted pointers of executors, are shared by all namespaces.
+    pub fn namespace_directory(salt: &Option<IdentitySalt>) -> anyhow::Result<PathBuf> {
+        Ok(match salt {
+            None => PathBuf::new(),
+            Some(salt) => Path::new(Self::DEFAULT_SALTED_NAMESPACES_SUBDIR).join(
+                salt.digest::<IdentityScheme>()
+                    .context("computing salt digest")?
+                    .to_string(),
+            ),
+        })
+    }
+
+    fn namespace_filesystem(
+        filesystem: &mut Filesystem,
+        namespace: &Path,
+    ) -> anyhow::Result<Filesystem> {
+        if namespace.as_os_str().is_empty() {
+            Ok(filesystem.clone())
+        } else {
+            filesystem.sub_system(namespace)
+        }
+    }
+
+    fn create_or_open_internal(
+        mut filesystem: Filesystem,
+        namespace: PathBuf,
+        index: Idx,
+        salt: Option<IdentitySalt>,
+    ) -> anyhow::Result<Self> {
         let system = sysinfo::System::new();
+        let mut namespace_filesystem = Self::namespace_filesystem(&mut filesystem, &namespace)?;
+        for subdir in [
+            Self::DEFAULT_METADATA_POINTERS_SUBDIR,
+            Self::DEFAULT_OUTPUTS_POINTERS_SUBDIR,
+        ] {
+            namespace_filesystem
+                .create_directories(subdir)
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("creating cache subdirectory {:?}", subdir))?;
+        }
+        for subdir in [
+            Self::DEFAULT_BLOBS_SUBDIR,
+            execute::DEFAULT_OUTPUTS_POINTERS_DIRECTORY,
+            execute::DEFAULT_RESULTS_POINTERS_DIRECTORY,
+            execute::DEFAULT_FAILURES_POINTERS_DIRECTORY,
+            execute::DEFAULT_STDOUTS_POINTERS_DIRECTORY,
+            execute::DEFAULT_STDERRS_POINTERS_DIRECTORY,
+        ] {
+            filesystem
+                .create_directories(subdir)
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("creating cache subdirectory {:?}", subdir))?;
+        }
         let blob_filesystem = filesystem.sub_system(Self::DEFAULT_BLOBS_SUBDIR)?;
         let metadata_pointer_filesystem =
-            filesystem.sub_system(Self::DEFAULT_METADATA_POINTERS_SUBDIR)?;
+            namespace_filesystem.sub_system(Self::DEFAULT_METADATA_POINTERS_SUBDIR)?;
         let outputs_pointer_filesystem =
-            filesystem.sub_system(Self::DEFAULT_INPUTS_LISTING_FILE)?;
+            namespace_filesystem.sub_system(Self::DEFAULT_OUTPUTS_POINTERS_SUBDIR)?;
         let blob_cache = BlobCache::new(blob_filesystem);
         let metadata_pointer_cache = BlobPointerCache::new(metadata_pointer_filesystem);
         let outputs_pointer_cache = BlobPointerCache::new(outputs_pointer_filesystem);
+        let executor_pointers = ExecutorPointers {
+            outputs: BlobPointerCache::new(
+                filesystem.sub_system(execute::DEFAULT_OUTPUTS_POINTERS_DIRECTORY)?,
+            ),
+            results: BlobPointerCache::new(
+                filesystem.sub_system(execute::DEFAULT_RESULTS_POINTERS_DIRECTORY)?,
+            ),
+            failures: BlobPointerCache::new(
+                filesystem.sub_system(execute::DEFAULT_FAILURES_POINTERS_DIRECTORY)?,
+            ),
+            stdouts: BlobPointerFileCache::new(
+                filesystem.sub_system(execute::DEFAULT_STDOUTS_POINTERS_DIRECTORY)?,
+            ),
+            stderrs: BlobPointerFileCache::new(
+                filesystem.sub_system(execute::DEFAULT_STDERRS_POINTERS_DIRECTORY)?,
+            ),
+        };
 
         Ok(Self {
             system,
+            system_capture: SystemCapture::default(),
             index,
             blob_cache,
             metadata_pointer_cache,
             outputs_pointer_cache,
+            executor_pointers: Some(executor_pointers),
+            salt,
+            root: Some(filesystem),
+            namespace,
         })
     }
 
@@ -226,24 +474,191 @@ impl<
         inputs: TaskInputs<IdentityScheme>,
         outputs: TaskOutputs<IdentityScheme>,
     ) -> anyhow::Result<()> {
-        let metadata = Metadata::new(
+        self.put_task_with_execution_result(
             timestamp_nanos,
             execution_duration_nanos,
-            (&self.system).into(),
-        );
+            &ExecutionResult::default(),
+            inputs,
+            outputs,
+        )
+    }
 
-        let inputs_identity = self.blob_cache.write_small_blob(&inputs.as_transport())?;
-        self.index.put(inputs_identity.clone());
+    /// As `put_task`, additionally recording `execution_result` in the task's metadata.
+    pub fn put_task_with_execution_result(
+        &mut self,
+        timestamp_nanos: i64,
+        execution_duration_nanos: u128,
+        execution_result: &ExecutionResult,
+        inputs: TaskInputs<IdentityScheme>,
+        outputs: TaskOutputs<IdentityScheme>,
+    ) -> anyhow::Result<()> {
+        self.put_labeled_task(
+            timestamp_nanos,
+            execution_duration_nanos,
+            execution_result,
+            TaskLabels::default(),
+            inputs,
+            outputs,
+        )
+    }
+
+    /// As `put_task_with_execution_result`, additionally recording the task's label and tags in its
+    /// metadata, where `find_tasks` can query them.
+    pub fn put_labeled_task(
+        &mut self,
+        timestamp_nanos: i64,
+        execution_duration_nanos: u128,
+        execution_result: &ExecutionResult,
+        labels: TaskLabels,
+        inputs: TaskInputs<IdentityScheme>,
+        outputs: TaskOutputs<IdentityScheme>,
+    ) -> anyhow::Result<()> {
+        let mut metadata = Metadata::new(
+            timestamp_nanos,
+            execution_duration_nanos,
+            CanonicalSystem::capture(&self.system, &self.system_capture),
+        )
+        .with_execution_result(execution_result)
+        .with_labels(labels);
+        if self.system_capture.host {
+            metadata = metadata.with_host(self.system.host_name(), current_username());
+        }
+        self.put_task_with_metadata(timestamp_nanos, metadata, inputs, outputs)
+            .map(|_| ())
+    }
+
+    /// Writes the inputs, outputs, and metadata blobs of a task, and the pointers to them, indexing
+    /// the task as of `timestamp_nanos`. Returns the identity of the task's inputs.
+    fn put_task_with_metadata(
+        &mut self,
+        timestamp_nanos: i64,
+        metadata: Metadata,
+        inputs: TaskInputs<IdentityScheme>,
+        outputs: TaskOutputs<IdentityScheme>,
+    ) -> anyhow::Result<IdentityScheme::Identity> {
+        let inputs_identity = self
+            .blob_cache
+            .write_canonical_blob(&inputs.as_transport())?;
+        self.index.put_at(inputs_identity.clone(), timestamp_nanos);
+        let pointer_identity = self.task_pointer_identity(&inputs_identity)?;
 
         let outputs_identity = self.blob_cache.write_small_blob(&outputs.as_transport())?;
         self.outputs_pointer_cache
-            .write_raw_blob_pointer(&inputs_identity, &outputs_identity)?;
+            .write_raw_blob_pointer(&pointer_identity, &outputs_identity)?;
 
         let metadata_identity = self.blob_cache.write_small_blob(&metadata.as_transport())?;
         self.metadata_pointer_cache
-            .write_raw_blob_pointer(&inputs_identity, &metadata_identity)?;
+            .write_raw_blob_pointer(&pointer_identity, &metadata_identity)?;
 
-        self.index.flush()
+        self.index.flush()?;
+        Ok(inputs_identity)
+    }
+
+    /// Installs a package written by `crate::package::export_package`, so that the packaged task is
+    /// a cache hit. The content of every output file is checked against its identity in the
+    /// package's outputs manifest, and the package's name against the identity of its inputs,
+    /// before anything is written. Returns the identity of the task's inputs.
+    pub fn import_package<R: Read>(
+        &mut self,
+        reader: R,
+    ) -> anyhow::Result<IdentityScheme::Identity> {
+        let mut root = None;
+        let mut entries = HashMap::new();
+        for entry in tar::Archive::new(reader)
+            .entries()
+            .context("reading package entries")?
+        {
+            let mut entry = entry.context("reading package entry")?;
+            let entry_path = entry
+                .path()
+                .context("reading package entry path")?
+                .into_owned();
+            let mut components = entry_path.components();
+            let entry_root = components
+                .next()
+                .map(|component| component.as_os_str().to_owned());
+            let path = components.as_path().to_path_buf();
+            match (&root, entry_root) {
+                (_, None) => continue,
+                (None, Some(entry_root)) => root = Some(entry_root),
+                (Some(root), Some(entry_root)) if *root == entry_root => {}
+                (Some(root), Some(entry_root)) => anyhow::bail!(
+                    "package entries are under more than one directory: {:?} and {:?}",
+                    root,
+                    entry_root
+                ),
+            }
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let mut contents = vec![];
+            entry
+                .read_to_end(&mut contents)
+                .with_context(|| format!("reading package entry {:?}", entry_path))?;
+            entries.insert(path, contents);
+        }
+        let mut entry = |path: &str| {
+            entries
+                .remove(Path::new(path))
+                .ok_or_else(|| anyhow::anyhow!("package has no {:?}", path))
+        };
+        let inputs: TaskInputs<IdentityScheme> =
+            read_versioned::<crate::transport::TaskInputs<IdentityScheme>, JSON, _>(
+                entry(package::INPUTS_FILE)?.as_slice(),
+            )
+            .context("reading packaged task inputs")?
+            .try_into()
+            .context("verifying packaged task inputs")?;
+        let outputs: TaskOutputs<IdentityScheme> =
+            read_versioned::<crate::transport::TaskOutputs<IdentityScheme>, JSON, _>(
+                entry(package::OUTPUTS_FILE)?.as_slice(),
+            )
+            .context("reading packaged task outputs")?
+            .try_into()
+            .context("verifying packaged task outputs")?;
+        let metadata: Metadata = read_versioned::<crate::transport::Metadata, JSON, _>(
+            entry(package::METADATA_FILE)?.as_slice(),
+        )
+        .context("reading packaged task metadata")?
+        .into();
+
+        let inputs_identity = identify_task_inputs(&inputs)?;
+        let expected_root = package::package_name::<IdentityScheme>(&inputs_identity);
+        if root.as_deref() != Some(OsStr::new(&expected_root)) {
+            anyhow::bail!(
+                "package directory, {:?}, does not match the identity of its inputs; expected {:?}",
+                root,
+                expected_root
+            );
+        }
+        let mut output_blobs = vec![];
+        for (path, identity) in outputs.output_files() {
+            let identity = match identity {
+                Some(identity) => identity,
+                None => continue,
+            };
+            let entry_path = package::output_entry_path(path)?;
+            let contents = entries
+                .get(&entry_path)
+                .ok_or_else(|| anyhow::anyhow!("package has no content for output {:?}", path))?;
+            let actual_identity = IdentityScheme::identify_content(contents.as_slice())?;
+            if actual_identity != *identity {
+                anyhow::bail!(
+                    "packaged content of output {:?} has identity {}, but its manifest records {}",
+                    path,
+                    actual_identity.to_string(),
+                    identity.to_string()
+                );
+            }
+            output_blobs.push((identity.clone(), contents));
+        }
+
+        for (identity, contents) in output_blobs {
+            self.blob_cache
+                .copy_blob(contents.as_slice(), &identity)
+                .context("writing packaged output blob")?;
+        }
+        self.put_task_with_metadata(current_timestamp_nanos(), metadata, inputs, outputs)
     }
 
     pub fn put_blobs<'a>(
@@ -253,47 +668,1117 @@ impl<
     ) -> anyhow::Result<()> {
         for (path, identity) in file_identities_manifest.identities() {
             if let Some(identity) = identity {
-                let blob_reader = filesystem.open_file_for_read(path)?;
-                self.blob_cache.copy_blob(blob_reader, identity)?;
+                match file_identities_manifest.symlink_target(path) {
+                    // Dangling symbolic links are identified by their targets, which are stored in
+                    // place of their contents.
+                    Some(target) if filesystem.metadata(path).is_err() => {
+                        let content = symlink_target_content(target)?;
+                        self.blob_cache.copy_blob(content.as_slice(), identity)?;
+                    }
+                    _ => {
+                        let blob_reader = filesystem.open_file_for_read(path)?;
+                        self.blob_cache.copy_blob(blob_reader, identity)?;
+                    }
+                }
             }
         }
         Ok(())
     }
 
+    /// Opens the blob identified by `identity` (e.g., the content of a cached output file).
+    pub fn open_blob(
+        &mut self,
+        identity: &IdentityScheme::Identity,
+    ) -> anyhow::Result<BlobReader<Filesystem::Read>> {
+        self.blob_cache.open_blob(identity)
+    }
+
     pub fn get_metadata(
         &mut self,
         task_inputs_identity: &IdentityScheme::Identity,
     ) -> anyhow::Result<Option<Metadata>> {
+        let pointer_identity = self.task_pointer_identity(task_inputs_identity)?;
         match self
             .metadata_pointer_cache
-            .read_blob_pointer(task_inputs_identity)
+            .read_blob_pointer(&pointer_identity)
         {
             Err(_) => Ok(None),
             Ok(metadata_identity) => {
                 let metadata_transport = self
                     .blob_cache
-                    .read_blob::<crate::transport::Metadata>(&metadata_identity)?;
+                    .read_versioned_blob::<crate::transport::Metadata>(&metadata_identity)?;
                 Ok(Some(metadata_transport.into()))
             }
         }
     }
 
+    /// Finds cached tasks whose labels match `query` (see `TaskLabels::matches`), returning the
+    /// identity under which each task's pointers are stored, along with its metadata.
+    pub fn find_tasks(
+        &mut self,
+        query: &TaskLabels,
+    ) -> anyhow::Result<Vec<(IdentityScheme::Identity, Metadata)>> {
+        let mut tasks = vec![];
+        for pointer_identity in self.metadata_pointer_cache.source_identities()? {
+            let metadata_identity = self
+                .metadata_pointer_cache
+                .read_blob_pointer(&pointer_identity)?;
+            let metadata: Metadata = self
+                .blob_cache
+                .read_versioned_blob::<crate::transport::Metadata>(&metadata_identity)
+                .with_context(|| {
+                    format!("reading metadata for task {}", pointer_identity.to_string())
+                })?
+                .into();
+            if metadata.labels().matches(query) {
+                tasks.push((pointer_identity, metadata));
+            }
+        }
+        Ok(tasks)
+    }
+
+    /// Gets the inputs and outputs of the task whose inputs blob has identity
+    /// `task_inputs_identity`.
+    pub fn get_summary(
+        &mut self,
+        task_inputs_identity: &IdentityScheme::Identity,
+    ) -> anyhow::Result<Option<TaskSummary<IdentityScheme>>> {
+        let output = match self.get_outputs(task_inputs_identity)? {
+            None => return Ok(None),
+            Some(outputs) => outputs.as_transport(),
+        };
+        let input = self
+            .blob_cache
+            .read_versioned_canonical_blob::<crate::transport::TaskInputs<IdentityScheme>>(
+                task_inputs_identity,
+            )
+            .context("reading task inputs")?;
+        Ok(Some(TaskSummary { input, output }))
+    }
+
+    /// Gets the insertion and last-access times of the task whose inputs blob has identity
+    /// `task_inputs_identity`.
+    pub fn get_times(
+        &self,
+        task_inputs_identity: &IdentityScheme::Identity,
+    ) -> Option<ListingTimes> {
+        self.index.times(task_inputs_identity)
+    }
+
+    /// Gets the outputs of the task whose inputs blob has identity `task_inputs_identity`. A hit
+    /// is recorded as an access of the task in the index.
     pub fn get_outputs(
         &mut self,
         task_inputs_identity: &IdentityScheme::Identity,
     ) -> anyhow::Result<Option<TaskOutputs<IdentityScheme>>> {
+        let pointer_identity = self.task_pointer_identity(task_inputs_identity)?;
         match self
             .outputs_pointer_cache
-            .read_blob_pointer(task_inputs_identity)
+            .read_blob_pointer(&pointer_identity)
         {
             Err(_) => Ok(None),
             Ok(outputs_identity) => {
-                let outputs_transport = self.blob_cache.read_blob::<crate::transport::TaskOutputs<
-                    IdentityScheme,
-                >>(&outputs_identity)?;
+                self.index
+                    .touch(task_inputs_identity, current_timestamp_nanos());
+                let outputs_transport = self
+                    .blob_cache
+                    .read_versioned_blob::<crate::transport::TaskOutputs<IdentityScheme>>(
+                        &outputs_identity,
+                    )?;
                 let outputs: TaskOutputs<IdentityScheme> = outputs_transport.try_into()?;
                 Ok(Some(outputs))
             }
         }
     }
 }
+
+/// Subdirectory of a re-keyed cache that maps each identity under the previous identity scheme to
+/// the corresponding identity under the cache's identity scheme.
+pub const DEFAULT_COMPATIBILITY_POINTERS_SUBDIR: &str = "compatibility";
+
+/// Configures `rekey`.
+#[derive(Clone)]
+pub struct RekeyOptions<Filesystem: FilesystemApi> {
+    /// Salt with which the source cache was created. Only the namespace of this salt (see
+    /// `Cache::namespace_directory`) is migrated, and the destination cache is created with the
+    /// same salt.
+    pub salt: Option<IdentitySalt>,
+    /// Directory against which the paths of input files are resolved, to re-identify input files
+    /// whose content is not stored in the cache. Default: Tasks with such input files are skipped.
+    pub working_directory: Option<Filesystem>,
+}
+
+impl<Filesystem: FilesystemApi> Default for RekeyOptions<Filesystem> {
+    fn default() -> Self {
+        Self {
+            salt: None,
+            working_directory: None,
+        }
+    }
+}
+
+/// Summary of the entries migrated by `rekey`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RekeyReport {
+    pub blobs: usize,
+    pub tasks: usize,
+    /// Tasks with a file that could not be re-identified under the new identity scheme.
+    pub skipped_tasks: usize,
+    pub metadata_pointers: usize,
+    pub outputs_pointers: usize,
+    pub executor_pointers: usize,
+    pub index_entries: usize,
+}
+
+/// Migrates the cache rooted at `source` (keyed by `FromIdentityScheme`) into a new cache rooted at
+/// `destination` (keyed by `ToIdentityScheme`), leaving a compatibility pointer from each old
+/// identity to its new identity in `DEFAULT_COMPATIBILITY_POINTERS_SUBDIR`.
+///
+/// Every blob is re-hashed under the new scheme. The inputs and outputs of each task, in the index
+/// or executed by `crate::execute::CacheDirectoryTaskExecutor`, are rewritten with the new
+/// identities of their files, so that the task's new inputs identity is the one under which it is
+/// looked up after migration. Files are re-identified from their stored blobs, or else from
+/// `options.working_directory`, when the file there still has its recorded identity. Tasks with
+/// files that cannot be re-identified are skipped, and can only be re-executed.
+///
+/// The original task inputs and outputs blobs are copied along with every other blob, but nothing
+/// refers to them, so garbage collection removes them.
+pub fn rekey<
+    Filesystem: FilesystemApi,
+    FromIdentityScheme: IdentitySchemeApi,
+    ToIdentityScheme: IdentitySchemeApi,
+    Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+>(
+    mut source: Filesystem,
+    mut destination: Filesystem,
+    options: RekeyOptions<Filesystem>,
+) -> anyhow::Result<RekeyReport> {
+    type DefaultCache<FS, IS, S> = Cache<FS, IS, S, WriteOnDropIndex<FS, IS, S>>;
+    let mut source_cache = if options.salt.is_some() || source.file_exists(
+        DefaultCache::<Filesystem, FromIdentityScheme, Serialization>::DEFAULT_INPUTS_LISTING_FILE,
+    ) {
+        DefaultCache::<Filesystem, FromIdentityScheme, Serialization>::open_with_salt(
+            source.clone(),
+            options.salt.clone(),
+        )
+    } else {
+        DefaultCache::<Filesystem, FromIdentityScheme, Serialization>::open_existing(source.clone())
+    }
+    .context("opening cache to re-key")?;
+    let mut destination_cache =
+        DefaultCache::<Filesystem, ToIdentityScheme, Serialization>::create_with_salt(
+            destination.clone(),
+            options.salt.clone(),
+        )
+        .context("creating re-keyed cache")?;
+    let mut rekeyer = Rekeyer::<Filesystem, FromIdentityScheme, ToIdentityScheme> {
+        identities: HashMap::new(),
+        working_directory: options.working_directory,
+    };
+    let mut report = RekeyReport::default();
+
+    // Re-hash blobs.
+    for old_identity in source_cache.blob_cache.blob_identities()? {
+        let blob_name = old_identity.to_string();
+        let new_identity =
+            ToIdentityScheme::identify_content(source_cache.blob_cache.open_blob(&old_identity)?)
+                .with_context(|| format!("re-hashing blob {}", blob_name))?;
+        destination_cache
+            .blob_cache
+            .copy_blob(
+                source_cache.blob_cache.open_blob(&old_identity)?,
+                &new_identity,
+            )
+            .with_context(|| format!("copying re-hashed blob {}", blob_name))?;
+        rekeyer.identities.insert(old_identity, new_identity);
+        report.blobs += 1;
+    }
+
+    // Rewrite tasks in terms of new identities.
+    let index_entries: HashMap<_, _> = source_cache.index.entries().into_iter().collect();
+    let mut tasks: Vec<_> = index_entries.keys().cloned().collect();
+    tasks.extend(
+        source_cache
+            .executed_tasks()?
+            .into_iter()
+            .map(|(identity, _)| identity),
+    );
+    tasks.sort();
+    tasks.dedup();
+    for old_inputs_identity in tasks {
+        let new_inputs_identity = match rekey_task(
+            &mut source_cache,
+            &mut destination_cache,
+            &mut rekeyer,
+            &old_inputs_identity,
+            &mut report,
+        )
+        .with_context(|| format!("re-keying task {}", old_inputs_identity.to_string()))?
+        {
+            Some(new_inputs_identity) => new_inputs_identity,
+            None => {
+                report.skipped_tasks += 1;
+                continue;
+            }
+        };
+        if let Some(times) = index_entries.get(&old_inputs_identity) {
+            let index = &mut destination_cache.index;
+            index.put_at(
+                new_inputs_identity.clone(),
+                times.inserted_nanos.unwrap_or(0),
+            );
+            if let Some(last_accessed_nanos) = times.last_accessed_nanos {
+                index.touch(&new_inputs_identity, last_accessed_nanos);
+            }
+            report.index_entries += 1;
+        }
+        rekeyer
+            .identities
+            .insert(old_inputs_identity, new_inputs_identity);
+        report.tasks += 1;
+    }
+    destination_cache.index.flush()?;
+
+    // Leave compatibility pointers behind.
+    destination
+        .create_directories(DEFAULT_COMPATIBILITY_POINTERS_SUBDIR)
+        .map_err(anyhow::Error::from)
+        .context("creating compatibility pointers directory")?;
+    let mut compatibility_pointers =
+        destination.sub_system(DEFAULT_COMPATIBILITY_POINTERS_SUBDIR)?;
+    for (old_identity, new_identity) in rekeyer.identities.iter() {
+        let mut pointer_file =
+            compatibility_pointers.open_file_for_write(old_identity.to_string())?;
+        pointer_file.write_all(Serialization::to_string(new_identity)?.as_bytes())?;
+    }
+
+    Ok(report)
+}
+
+/// Re-keys the task whose inputs blob has identity `old_inputs_identity` in `source_cache` into
+/// `destination_cache`, returning its new inputs identity, or `None` if one of its files cannot be
+/// re-identified.
+fn rekey_task<
+    Filesystem: FilesystemApi,
+    FromIdentityScheme: IdentitySchemeApi,
+    ToIdentityScheme: IdentitySchemeApi,
+    Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+>(
+    source_cache: &mut Cache<
+        Filesystem,
+        FromIdentityScheme,
+        Serialization,
+        WriteOnDropIndex<Filesystem, FromIdentityScheme, Serialization>,
+    >,
+    destination_cache: &mut Cache<
+        Filesystem,
+        ToIdentityScheme,
+        Serialization,
+        WriteOnDropIndex<Filesystem, ToIdentityScheme, Serialization>,
+    >,
+    rekeyer: &mut Rekeyer<Filesystem, FromIdentityScheme, ToIdentityScheme>,
+    old_inputs_identity: &FromIdentityScheme::Identity,
+    report: &mut RekeyReport,
+) -> anyhow::Result<Option<ToIdentityScheme::Identity>> {
+    let inputs = match source_cache
+        .blob_cache
+        .read_versioned_canonical_blob::<crate::transport::TaskInputs<FromIdentityScheme>>(
+            old_inputs_identity,
+        ) {
+        Ok(inputs) => inputs,
+        Err(error) => {
+            tracing::warn!("skipping task whose inputs cannot be read: {:#}", error);
+            return Ok(None);
+        }
+    };
+    let input_files = match rekeyer.manifest(inputs.input_files) {
+        Some(input_files) => input_files,
+        None => return Ok(None),
+    };
+    let inputs = TaskInputs::<ToIdentityScheme>::try_from(crate::transport::TaskInputs {
+        format_version: inputs.format_version,
+        environment_variables: inputs.environment_variables,
+        program: inputs.program,
+        arguments: inputs.arguments,
+        input_files,
+        staged_inputs: inputs.staged_inputs,
+        stdin: inputs.stdin,
+        sandbox: inputs.sandbox,
+        resource_limits: inputs.resource_limits,
+        outputs_description: inputs.outputs_description,
+    })?;
+
+    // Pointers written by this cache are salted; those written by executors are not.
+    let old_pointer_identity = source_cache.task_pointer_identity(old_inputs_identity)?;
+    let mut outputs_pointers = vec![];
+    if let Ok(outputs_identity) = source_cache
+        .outputs_pointer_cache
+        .read_blob_pointer(&old_pointer_identity)
+    {
+        outputs_pointers.push((true, outputs_identity));
+    }
+    if let Some(executor_pointers) = source_cache.executor_pointers.as_mut() {
+        if let Ok(outputs_identity) = executor_pointers
+            .outputs
+            .read_blob_pointer(old_inputs_identity)
+        {
+            outputs_pointers.push((false, outputs_identity));
+        }
+    }
+    let mut rekeyed_outputs_pointers = vec![];
+    for (salted, old_outputs_identity) in outputs_pointers {
+        let outputs = source_cache
+            .blob_cache
+            .read_versioned_blob::<crate::transport::TaskOutputs<FromIdentityScheme>>(
+                &old_outputs_identity,
+            )
+            .context("reading task outputs")?;
+        let outputs = match (
+            rekeyer.manifest(outputs.input_files_with_program),
+            rekeyer.manifest(outputs.output_files),
+        ) {
+            (Some(input_files_with_program), Some(output_files)) => {
+                TaskOutputs::<ToIdentityScheme>::try_from(crate::transport::TaskOutputs {
+                    format_version: outputs.format_version,
+                    input_files_with_program,
+                    output_files,
+                })?
+            }
+            _ => return Ok(None),
+        };
+        let new_outputs_identity = destination_cache
+            .blob_cache
+            .write_small_blob(&outputs.as_transport())?;
+        rekeyer
+            .identities
+            .insert(old_outputs_identity, new_outputs_identity.clone());
+        rekeyed_outputs_pointers.push((salted, new_outputs_identity));
+    }
+
+    let new_inputs_identity = destination_cache
+        .blob_cache
+        .write_canonical_blob(&inputs.as_transport())?;
+    let new_pointer_identity = destination_cache.task_pointer_identity(&new_inputs_identity)?;
+    for (salted, new_outputs_identity) in rekeyed_outputs_pointers {
+        if salted {
+            destination_cache
+                .outputs_pointer_cache
+                .write_raw_blob_pointer(&new_pointer_identity, &new_outputs_identity)?;
+            report.outputs_pointers += 1;
+        } else if let Some(executor_pointers) = destination_cache.executor_pointers.as_mut() {
+            executor_pointers
+                .outputs
+                .write_raw_blob_pointer(&new_inputs_identity, &new_outputs_identity)?;
+            report.executor_pointers += 1;
+        }
+    }
+
+    // Metadata and execution results refer to no files, so only the identities of their blobs
+    // change. Without a salt, this cache and executors write metadata pointers at the same key.
+    let mut metadata_pointers = vec![(&old_pointer_identity, &new_pointer_identity)];
+    if &old_pointer_identity != old_inputs_identity {
+        metadata_pointers.push((old_inputs_identity, &new_inputs_identity));
+    }
+    for (old_source, new_source) in metadata_pointers {
+        if let Some(metadata_identity) = source_cache
+            .metadata_pointer_cache
+            .read_blob_pointer(old_source)
+            .ok()
+            .and_then(|old_identity| rekeyer.identities.get(&old_identity))
+        {
+            destination_cache
+                .metadata_pointer_cache
+                .write_raw_blob_pointer(new_source, metadata_identity)?;
+            report.metadata_pointers += 1;
+        }
+    }
+    if let (Some(source_pointers), Some(destination_pointers)) = (
+        source_cache.executor_pointers.as_mut(),
+        destination_cache.executor_pointers.as_mut(),
+    ) {
+        for (source_pointer_cache, destination_pointer_cache) in [
+            (
+                &mut source_pointers.results,
+                &mut destination_pointers.results,
+            ),
+            (
+                &mut source_pointers.failures,
+                &mut destination_pointers.failures,
+            ),
+        ] {
+            if let Some(result_identity) = source_pointer_cache
+                .read_blob_pointer(old_inputs_identity)
+                .ok()
+                .and_then(|old_identity| rekeyer.identities.get(&old_identity))
+            {
+                destination_pointer_cache
+                    .write_raw_blob_pointer(&new_inputs_identity, result_identity)?;
+                report.executor_pointers += 1;
+            }
+        }
+        for (source_stream_cache, destination_stream_cache) in [
+            (
+                &mut source_pointers.stdouts,
+                &mut destination_pointers.stdouts,
+            ),
+            (
+                &mut source_pointers.stderrs,
+                &mut destination_pointers.stderrs,
+            ),
+        ] {
+            if let Ok(mut stream) = source_stream_cache.open_file_for_read(old_inputs_identity) {
+                let mut rekeyed_stream =
+                    destination_stream_cache.open_file_for_write(&new_inputs_identity)?;
+                std::io::copy(&mut stream, &mut rekeyed_stream).context("copying task output")?;
+            }
+        }
+    }
+    Ok(Some(new_inputs_identity))
+}
+
+/// Maps file identities from `FromIdentityScheme` to `ToIdentityScheme`.
+struct Rekeyer<
+    Filesystem: FilesystemApi,
+    FromIdentityScheme: IdentitySchemeApi,
+    ToIdentityScheme: IdentitySchemeApi,
+> {
+    /// New identities of re-keyed blobs and tasks, by old identity.
+    identities: HashMap<FromIdentityScheme::Identity, ToIdentityScheme::Identity>,
+    working_directory: Option<Filesystem>,
+}
+
+impl<
+        Filesystem: FilesystemApi,
+        FromIdentityScheme: IdentitySchemeApi,
+        ToIdentityScheme: IdentitySchemeApi,
+    > Rekeyer<Filesystem, FromIdentityScheme, ToIdentityScheme>
+{
+    /// Re-identifies the files in `manifest`, or returns `None` if one cannot be re-identified.
+    fn manifest(
+        &mut self,
+        manifest: crate::transport::FileIdentitiesManifest<FromIdentityScheme>,
+    ) -> Option<crate::transport::FileIdentitiesManifest<ToIdentityScheme>> {
+        let mut identities = vec![];
+        for (path, identity) in manifest.identities {
+            let identity = match identity {
+                Some(identity) => match self.file(&path, manifest.symlinks.get(&path), &identity) {
+                    Some(identity) => Some(identity),
+                    None => {
+                        tracing::warn!(
+                            "skipping task with file {:?} that cannot be re-identified",
+                            path
+                        );
+                        return None;
+                    }
+                },
+                None => None,
+            };
+            identities.push((path, identity));
+        }
+        Some(crate::transport::FileIdentitiesManifest {
+            identity_scheme: ToIdentityScheme::IDENTITY_SCHEME,
+            identities,
+            symlinks: manifest.symlinks,
+        })
+    }
+
+    fn file(
+        &mut self,
+        path: &Path,
+        symlink_target: Option<&PathBuf>,
+        identity: &FromIdentityScheme::Identity,
+    ) -> Option<ToIdentityScheme::Identity> {
+        if let Some(new_identity) = self.identities.get(identity) {
+            return Some(new_identity.clone());
+        }
+        if let Some(target) = symlink_target {
+            if FromIdentityScheme::identify_symlink_target(target)
+                .ok()
+                .as_ref()
+                == Some(identity)
+            {
+                return ToIdentityScheme::identify_symlink_target(target).ok();
+            }
+        }
+        let working_directory = self.working_directory.as_mut()?;
+        [
+            SymlinkIdentity::ResolvedContents,
+            SymlinkIdentity::TargetPath,
+        ]
+        .into_iter()
+        .find(|symlink_identity| {
+            FromIdentityScheme::identify_path(working_directory, path, *symlink_identity)
+                .ok()
+                .as_ref()
+                == Some(identity)
+        })
+        .and_then(|symlink_identity| {
+            ToIdentityScheme::identify_path(working_directory, path, symlink_identity).ok()
+        })
+    }
+}
+
+/// Gets the current time in nanoseconds since the Unix epoch.
+pub(crate) fn current_timestamp_nanos() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| i64::try_from(duration.as_nanos()).unwrap_or(i64::MAX))
+        .unwrap_or(0)
+}
+
+/// Gets the name of the user running this process, as reported by the environment.
+pub(crate) fn current_username() -> Option<String> {
+    ["USER", "LOGNAME", "USERNAME"]
+        .into_iter()
+        .find_map(|name| std::env::var(name).ok())
+}
+
+/// Lists the identities that name files in `filesystem`, skipping files whose names are not
+/// identities under `IdentityScheme`, such as partially written temporary blobs.
+pub(crate) fn list_identities<Filesystem: FilesystemApi, IdentityScheme: IdentitySchemeApi>(
+    filesystem: &mut Filesystem,
+) -> anyhow::Result<Vec<IdentityScheme::Identity>> {
+    let mut identities = vec![];
+    for path_result in filesystem.execute_glob("*")? {
+        let path = path_result?;
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        match IdentityScheme::Identity::deserialize(
+            StrDeserializer::<serde::de::value::Error>::new(&name),
+        ) {
+            Ok(identity) => identities.push(identity),
+            Err(_) => {
+                tracing::warn!("skipping non-identity cache entry: {path:?}", path = path);
+            }
+        }
+    }
+    identities.sort();
+    Ok(identities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rekey;
+    use super::Cache;
+    use super::RekeyOptions;
+    use super::WriteOnDropIndex;
+    use super::DEFAULT_COMPATIBILITY_POINTERS_SUBDIR;
+    use crate::blob::CanonicalJSON;
+    use crate::blob::StringSerializer as _;
+    use crate::blob::CBOR;
+    use crate::blob::JSON;
+    use crate::canonical::FileIdentitiesManifest;
+    use crate::canonical::FilesManifest;
+    use crate::canonical::SystemCapture;
+    use crate::canonical::TaskInputs;
+    use crate::canonical::TaskLabels;
+    use crate::canonical::TaskOutputs;
+    use crate::execute::identify_task_inputs;
+    use crate::fs::Filesystem as FilesystemApi;
+    use crate::fs::HostFilesystem;
+    use crate::identity::AsTransport as _;
+    use crate::identity::IdentitySalt;
+    use crate::identity::IdentityScheme as IdentitySchemeApi;
+    use crate::runner::ExecutionResult;
+    use crate::transport::ContentBlake2b256;
+    use crate::transport::ContentSha256;
+    use crate::transport::IdentityScheme as IdentitySchemeEnum;
+    use std::io::Write as _;
+    use std::os::unix::process::ExitStatusExt as _;
+    use std::process::ExitStatus;
+    use std::time::Duration;
+    use sysinfo::SystemExt as _;
+
+    type TestCache<IS> =
+        Cache<HostFilesystem, IS, JSON, WriteOnDropIndex<HostFilesystem, IS, JSON>>;
+
+    #[test]
+    fn test_rekey() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let mut root = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        for directory in ["old", "new", "new_with_working_directory", "work"] {
+            root.create_directories(directory)
+                .expect("create directory");
+        }
+        let old_filesystem = root.sub_system("old").expect("old cache filesystem");
+        let mut work = root.sub_system("work").expect("working directory");
+        for (path, content) in [
+            ("stored.txt", "stored"),
+            ("unstored.txt", "unstored"),
+            ("output.txt", "output"),
+        ] {
+            work.open_file_for_write(path)
+                .expect("open file")
+                .write_all(content.as_bytes())
+                .expect("write file");
+        }
+
+        // The first task's files are all stored in the cache; the second has an input file whose
+        // content is only in the working directory.
+        let tasks: Vec<_> = [vec!["stored.txt"], vec!["stored.txt", "unstored.txt"]]
+            .into_iter()
+            .map(|input_paths| {
+                (
+                    FilesManifest::new(input_paths),
+                    FilesManifest::new(["output.txt"]),
+                )
+            })
+            .collect();
+        let new_task =
+            |work: &mut HostFilesystem,
+             (input_files, output_files): &(FilesManifest, FilesManifest)| {
+                let inputs = TaskInputs::<ContentBlake2b256>::for_test("argument")
+                    .with_input_files(input_files.clone().into_identified(work));
+                let outputs = TaskOutputs::<ContentBlake2b256>::new(
+                    FileIdentitiesManifest::empty(),
+                    output_files.clone().into_identified(work),
+                );
+                (inputs, outputs)
+            };
+        let mut old_inputs_identities = vec![];
+        {
+            let mut cache =
+                TestCache::<ContentSha256>::create(old_filesystem.clone()).expect("create cache");
+            for path in ["stored.txt", "output.txt"] {
+                let identity = ContentSha256::identify_file(&mut work, path).expect("identify");
+                cache
+                    .blob_cache
+                    .copy_blob(work.open_file_for_read(path).expect("open file"), &identity)
+                    .expect("store file");
+            }
+            for (input_files, output_files) in tasks.iter() {
+                let inputs = TaskInputs::<ContentSha256>::for_test("argument")
+                    .with_input_files(input_files.clone().into_identified(&mut work));
+                let outputs = TaskOutputs::<ContentSha256>::new(
+                    FileIdentitiesManifest::empty(),
+                    output_files.clone().into_identified(&mut work),
+                );
+                old_inputs_identities
+                    .push(identify_task_inputs(&inputs).expect("identify task inputs"));
+                cache.put_task(0, 0, inputs, outputs).expect("put task");
+            }
+        }
+
+        let new_filesystem = root.sub_system("new").expect("new cache filesystem");
+        let report = rekey::<HostFilesystem, ContentSha256, ContentBlake2b256, JSON>(
+            old_filesystem.clone(),
+            new_filesystem.clone(),
+            RekeyOptions::default(),
+        )
+        .expect("rekey cache");
+        assert_eq!((1, 1), (report.tasks, report.skipped_tasks));
+        assert_eq!(
+            (1, 1, 1),
+            (
+                report.index_entries,
+                report.outputs_pointers,
+                report.metadata_pointers
+            )
+        );
+
+        let (inputs, outputs) = new_task(&mut work, &tasks[0]);
+        let new_inputs_identity =
+            identify_task_inputs(&inputs).expect("identify re-keyed task inputs");
+        let mut new_cache =
+            TestCache::<ContentBlake2b256>::open(new_filesystem).expect("open new cache");
+        let stored_inputs = new_cache
+            .blob_cache
+            .read_versioned_canonical_blob::<crate::transport::TaskInputs<ContentBlake2b256>>(
+                &new_inputs_identity,
+            )
+            .expect("read re-keyed task inputs");
+        assert_eq!(
+            IdentitySchemeEnum::ContentBlake2b256,
+            stored_inputs.input_files.identity_scheme
+        );
+        assert_eq!(
+            Some(outputs),
+            new_cache
+                .get_outputs(&new_inputs_identity)
+                .expect("get outputs from new cache")
+        );
+        assert!(new_cache
+            .get_metadata(&new_inputs_identity)
+            .expect("get metadata from new cache")
+            .is_some());
+        let (inputs, _) = new_task(&mut work, &tasks[1]);
+        assert_eq!(
+            None,
+            new_cache
+                .get_outputs(&identify_task_inputs(&inputs).expect("identify task inputs"))
+                .expect("get outputs of skipped task")
+        );
+
+        let compatibility_pointer = std::fs::read_to_string(
+            temporary_directory
+                .path()
+                .join("new")
+                .join(DEFAULT_COMPATIBILITY_POINTERS_SUBDIR)
+                .join(old_inputs_identities[0].to_string()),
+        )
+        .expect("read compatibility pointer");
+        assert_eq!(
+            JSON::to_string(&new_inputs_identity).expect("serialize new identity"),
+            compatibility_pointer
+        );
+
+        // Files whose content is not stored are re-identified from the working directory.
+        let new_filesystem = root
+            .sub_system("new_with_working_directory")
+            .expect("new cache filesystem");
+        let report = rekey::<HostFilesystem, ContentSha256, ContentBlake2b256, JSON>(
+            old_filesystem,
+            new_filesystem.clone(),
+            RekeyOptions {
+                working_directory: Some(work.clone()),
+                ..RekeyOptions::default()
+            },
+        )
+        .expect("rekey cache with working directory");
+        assert_eq!((2, 0), (report.tasks, report.skipped_tasks));
+        let mut new_cache =
+            TestCache::<ContentBlake2b256>::open(new_filesystem).expect("open new cache");
+        for task in tasks.iter() {
+            let (inputs, outputs) = new_task(&mut work, task);
+            assert_eq!(
+                Some(outputs),
+                new_cache
+                    .get_outputs(&identify_task_inputs(&inputs).expect("identify task inputs"))
+                    .expect("get outputs from new cache")
+            );
+        }
+    }
+
+    #[test]
+    fn test_cbor_cache() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let inputs = TaskInputs::<ContentSha256>::for_test("argument");
+        let outputs = TaskOutputs::<ContentSha256>::new(
+            FileIdentitiesManifest::empty(),
+            FileIdentitiesManifest::empty(),
+        );
+        let inputs_identity =
+            identify_task_inputs::<ContentSha256>(&inputs).expect("identify task inputs");
+
+        type CborCache = Cache<
+            HostFilesystem,
+            ContentSha256,
+            CBOR,
+            WriteOnDropIndex<HostFilesystem, ContentSha256, CBOR>,
+        >;
+        {
+            let mut cache = CborCache::create(filesystem.clone()).expect("create cache");
+            cache
+                .put_task_with_execution_result(
+                    0,
+                    0,
+                    &ExecutionResult {
+                        status: Some(ExitStatus::from_raw(0)),
+                        duration: Duration::from_millis(2),
+                        peak_memory_bytes: Some(4096),
+                        interruption: None,
+                    },
+                    inputs,
+                    outputs.clone(),
+                )
+                .expect("put task");
+        }
+        let mut cache = CborCache::open(filesystem).expect("open cache");
+        assert_eq!(
+            Some(outputs),
+            cache.get_outputs(&inputs_identity).expect("get outputs")
+        );
+        let metadata = cache
+            .get_metadata(&inputs_identity)
+            .expect("get metadata")
+            .expect("metadata present");
+        assert_eq!(Some(0), metadata.exit_code());
+        assert_eq!(Some(4096), metadata.peak_memory_bytes());
+        // The host is captured by default.
+        assert_eq!(
+            sysinfo::System::new().host_name().as_deref(),
+            metadata.hostname()
+        );
+        assert_eq!(super::current_username().as_deref(), metadata.username());
+    }
+
+    #[test]
+    fn test_system_capture() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let inputs = TaskInputs::<ContentSha256>::for_test("argument");
+        let outputs = TaskOutputs::<ContentSha256>::new(
+            FileIdentitiesManifest::empty(),
+            FileIdentitiesManifest::empty(),
+        );
+        let inputs_identity =
+            identify_task_inputs::<ContentSha256>(&inputs).expect("identify task inputs");
+
+        {
+            let mut cache = TestCache::<ContentSha256>::create(filesystem.clone())
+                .expect("create cache")
+                .with_system_capture(SystemCapture::none());
+            cache
+                .put_task(0, 0, inputs, outputs.clone())
+                .expect("put task");
+        }
+        let mut cache = TestCache::<ContentSha256>::open(filesystem).expect("open cache");
+        // Capturing nothing about the host does not change the key under which outputs are found.
+        assert_eq!(
+            Some(outputs),
+            cache.get_outputs(&inputs_identity).expect("get outputs")
+        );
+        let metadata = cache
+            .get_metadata(&inputs_identity)
+            .expect("get metadata")
+            .expect("metadata present");
+        assert_eq!(None, metadata.system().kernel_version());
+        assert_eq!(None, metadata.system().total_memory());
+        assert_eq!(None, metadata.hostname());
+        assert_eq!(None, metadata.username());
+    }
+
+    #[test]
+    fn test_listing_times() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let inputs = TaskInputs::<ContentSha256>::for_test("argument");
+        let outputs = TaskOutputs::<ContentSha256>::new(
+            FileIdentitiesManifest::empty(),
+            FileIdentitiesManifest::empty(),
+        );
+        let inputs_identity =
+            identify_task_inputs::<ContentSha256>(&inputs).expect("identify task inputs");
+
+        {
+            let mut cache =
+                TestCache::<ContentSha256>::create(filesystem.clone()).expect("create cache");
+            cache.put_task(5, 0, inputs, outputs).expect("put task");
+            let times = cache.get_times(&inputs_identity).expect("times");
+            assert_eq!(Some(5), times.inserted_nanos);
+            assert_eq!(Some(5), times.last_accessed_nanos);
+        }
+
+        // Hits update the last-access time, which persists with the index.
+        {
+            let mut cache =
+                TestCache::<ContentSha256>::open(filesystem.clone()).expect("open cache");
+            cache
+                .get_outputs(&inputs_identity)
+                .expect("get outputs")
+                .expect("outputs present");
+        }
+        let cache = TestCache::<ContentSha256>::open(filesystem).expect("open cache");
+        let times = cache.get_times(&inputs_identity).expect("times");
+        assert_eq!(Some(5), times.inserted_nanos);
+        assert!(times.last_accessed_nanos.expect("last accessed") > 5);
+    }
+
+    #[test]
+    fn test_find_tasks() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let mut cache = TestCache::<ContentSha256>::create(filesystem).expect("create cache");
+        let mut identities = vec![];
+        for (argument, labels) in [
+            (
+                "a",
+                TaskLabels::new(Some(String::from("protoc")), ["release"]),
+            ),
+            (
+                "b",
+                TaskLabels::new(Some(String::from("protoc")), ["debug"]),
+            ),
+            ("c", TaskLabels::new(Some(String::from("cc")), ["release"])),
+            ("d", TaskLabels::default()),
+        ] {
+            let inputs = TaskInputs::<ContentSha256>::for_test(argument);
+            identities
+                .push(identify_task_inputs::<ContentSha256>(&inputs).expect("identify inputs"));
+            cache
+                .put_labeled_task(
+                    0,
+                    0,
+                    &ExecutionResult::default(),
+                    labels,
+                    inputs,
+                    TaskOutputs::new(
+                        FileIdentitiesManifest::empty(),
+                        FileIdentitiesManifest::empty(),
+                    ),
+                )
+                .expect("put task");
+        }
+
+        let find = |cache: &mut TestCache<ContentSha256>, label: Option<&str>, tags: &[&str]| {
+            let mut found: Vec<_> = cache
+                .find_tasks(&TaskLabels::new(
+                    label.map(String::from),
+                    tags.iter().copied(),
+                ))
+                .expect("find tasks")
+                .into_iter()
+                .map(|(identity, _)| identity)
+                .collect();
+            found.sort();
+            found
+        };
+        let expected = |indices: &[usize]| {
+            let mut expected: Vec<_> = indices.iter().map(|i| identities[*i].clone()).collect();
+            expected.sort();
+            expected
+        };
+        assert_eq!(expected(&[0, 1, 2, 3]), find(&mut cache, None, &[]));
+        assert_eq!(expected(&[0, 1]), find(&mut cache, Some("protoc"), &[]));
+        assert_eq!(
+            expected(&[0]),
+            find(&mut cache, Some("protoc"), &["release"])
+        );
+        assert_eq!(expected(&[0, 2]), find(&mut cache, None, &["release"]));
+        assert_eq!(expected(&[]), find(&mut cache, None, &["release", "debug"]));
+
+        let metadata = cache
+            .get_metadata(&identities[0])
+            .expect("get metadata")
+            .expect("metadata present");
+        assert_eq!(Some("protoc"), metadata.labels().label());
+    }
+
+    #[test]
+    fn test_find_tasks_errors() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let mut cache = TestCache::<ContentSha256>::create(filesystem).expect("create cache");
+        let query = TaskLabels::new(Some(String::from("protoc")), ["release"]);
+        assert!(cache.find_tasks(&query).expect("find tasks").is_empty());
+
+        let inputs = TaskInputs::<ContentSha256>::for_test("argument");
+        let inputs_identity =
+            identify_task_inputs::<ContentSha256>(&inputs).expect("identify inputs");
+        cache
+            .put_labeled_task(
+                0,
+                0,
+                &ExecutionResult::default(),
+                query.clone(),
+                inputs,
+                TaskOutputs::new(
+                    FileIdentitiesManifest::empty(),
+                    FileIdentitiesManifest::empty(),
+                ),
+            )
+            .expect("put task");
+
+        // A task whose metadata cannot be read fails the query, rather than silently not matching.
+        let metadata_identity = cache
+            .metadata_pointer_cache
+            .read_blob_pointer(&inputs_identity)
+            .expect("read metadata pointer");
+        std::fs::write(
+            temporary_directory
+                .path()
+                .join(TestCache::<ContentSha256>::DEFAULT_BLOBS_SUBDIR)
+                .join(metadata_identity.to_string()),
+            "not metadata",
+        )
+        .expect("corrupt metadata blob");
+        let error = cache
+            .find_tasks(&query)
+            .expect_err("find tasks with corrupt metadata");
+        assert!(format!("{:#}", error).contains(&format!(
+            "reading metadata for task {}",
+            inputs_identity.to_string()
+        )));
+    }
+
+    #[test]
+    fn test_salted_cache() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+
+        let inputs = TaskInputs::<ContentSha256>::for_test("argument");
+        let outputs = TaskOutputs::<ContentSha256>::new(
+            FileIdentitiesManifest::empty(),
+            FileIdentitiesManifest::empty(),
+        );
+        let inputs_string =
+            CanonicalJSON::to_string(&inputs.as_transport()).expect("serialize inputs");
+        let inputs_identity =
+            ContentSha256::identify_content(inputs_string.as_bytes()).expect("inputs identity");
+
+        {
+            let mut cache = TestCache::<ContentSha256>::create_with_salt(
+                filesystem.clone(),
+                Some(IdentitySalt::new("organization")),
+            )
+            .expect("create salted cache");
+            cache
+                .put_task(0, 0, inputs.clone(), outputs)
+                .expect("put task");
+            assert_ne!(
+                inputs_identity,
+                cache
+                    .task_pointer_identity(&inputs_identity)
+                    .expect("pointer identity")
+            );
+            assert!(cache
+                .get_outputs(&inputs_identity)
+                .expect("get outputs")
+                .is_some());
+        }
+
+        // Pointers are not stored under the unsalted inputs identity.
+        assert!(!temporary_directory
+            .path()
+            .join(TestCache::<ContentSha256>::DEFAULT_OUTPUTS_POINTERS_SUBDIR)
+            .join(inputs_identity.to_string())
+            .exists());
+
+        assert!(TestCache::<ContentSha256>::open_with_salt(
+            filesystem.clone(),
+            Some(IdentitySalt::new("organization"))
+        )
+        .is_ok());
+        assert!(TestCache::<ContentSha256>::open_with_salt(
+            filesystem.clone(),
+            Some(IdentitySalt::new("other organization"))
+        )
+        .is_err());
+        assert!(TestCache::<ContentSha256>::open(filesystem.clone()).is_err());
+
+        // Caches with other salts, or none, share the directory without seeing the task, and their
+        // garbage collection keeps its blobs.
+        for salt in [Some(IdentitySalt::new("other organization")), None] {
+            let mut cache =
+                TestCache::<ContentSha256>::open_or_create_with_salt(filesystem.clone(), salt)
+                    .expect("open or create cache with another salt");
+            assert!(cache
+                .get_outputs(&inputs_identity)
+                .expect("get outputs")
+                .is_none());
+            cache
+                .collect_garbage(Some(0), None)
+                .expect("collect garbage");
+        }
+        let mut cache = TestCache::<ContentSha256>::open_or_create_with_salt(
+            filesystem,
+            Some(IdentitySalt::new("organization")),
+        )
+        .expect("open salted cache");
+        assert!(cache
+            .get_outputs(&inputs_identity)
+            .expect("get outputs after garbage collection")
+            .is_some());
+        assert!(cache.blob_cache.contains_blob(&inputs_identity));
+    }
+}
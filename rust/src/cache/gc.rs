@@ -0,0 +1,651 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Garbage collection of cache directories, which otherwise grow without bound.
+//!
+//! The tasks in the inputs listing index, and those to which a
+//! `crate::execute::CacheDirectoryTaskExecutor` sharing the cache directory has written pointers,
+//! are the roots of the cache: Each task keeps alive its inputs blob, the outputs, metadata, and
+//! result blobs to which its pointers refer, and the blobs of the input and output files that those
+//! blobs name. Blobs that no task keeps alive are deleted, as are metadata and outputs pointers
+//! from task inputs that are neither indexed nor executed. Executed tasks are last used when they
+//! were last executed.
+//!
+//! Caches in other namespaces of the cache directory (see `Cache::namespace_directory`) share its
+//! blobs, so every blob to which their indexes and pointers refer is kept alive too; their tasks are
+//! only collected by garbage collection in their own namespace.
+//!
+//! Garbage collection must not run concurrently with writers to the same cache directory: A blob
+//! written before the task that refers to it is indexed is indistinguishable from garbage.
+
+use super::current_timestamp_nanos;
+use super::Cache;
+use super::Index;
+use crate::blob::BlobPointerCache;
+use crate::blob::ReadDeserializer as ReadDeserializerApi;
+use crate::blob::StringSerializer as StringSerializerApi;
+use crate::blob::WriteSerializer as WriteSerializerApi;
+use crate::canonical::TaskInputs;
+use crate::canonical::TaskOutputs;
+use crate::events;
+use crate::events::Event;
+use crate::fs::FileType;
+use crate::fs::Filesystem as FilesystemApi;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::schema::read_versioned;
+use crate::transport::Listing as ListingTransport;
+use crate::transport::TaskInputs as TaskInputsTransport;
+use crate::transport::TaskOutputs as TaskOutputsTransport;
+use anyhow::Context as _;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::UNIX_EPOCH;
+
+/// What a garbage collection removed and kept.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GarbageCollectionReport {
+    /// Tasks removed because they were last used longer ago than the maximum age.
+    pub expired_tasks: usize,
+    /// Tasks removed, least recently used first, to bring the cache under the maximum size.
+    pub evicted_tasks: usize,
+    /// Pointers removed because the task inputs from which they point are neither indexed nor
+    /// executed.
+    pub orphaned_pointers: usize,
+    pub blobs_removed: usize,
+    pub bytes_removed: u64,
+    pub blobs_retained: usize,
+    pub bytes_retained: u64,
+}
+
+impl<
+        Filesystem: FilesystemApi,
+        IdentityScheme: IdentitySchemeApi,
+        Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+        Idx: Index<Filesystem = Filesystem, Identity = IdentityScheme::Identity, Error = anyhow::Error>,
+    > Cache<Filesystem, IdentityScheme, Serialization, Idx>
+{
+    /// Removes tasks that were last used (i.e., put or hit) longer than `max_age` ago, then every
+    /// blob and pointer that no remaining task refers to. Then, while the remaining blobs total more
+    /// than `max_bytes`, removes the least recently used task, along with the blobs that only it
+    /// referred to. Tasks whose times are unknown are treated as least recently used.
+    pub fn collect_garbage(
+        &mut self,
+        max_bytes: Option<u64>,
+        max_age: Option<Duration>,
+    ) -> anyhow::Result<GarbageCollectionReport> {
+        let mut report = GarbageCollectionReport::default();
+
+        let mut tasks: Vec<(IdentityScheme::Identity, i64)> = self
+            .index
+            .entries()
+            .into_iter()
+            .map(|(identity, times)| {
+                let last_used = times
+                    .last_accessed_nanos
+                    .or(times.inserted_nanos)
+                    .unwrap_or(i64::MIN);
+                (identity, last_used)
+            })
+            .collect();
+        let indexed: HashSet<_> = tasks.iter().map(|(identity, _)| identity.clone()).collect();
+        tasks.extend(
+            self.executed_tasks()?
+                .into_iter()
+                .filter(|(identity, _)| !indexed.contains(identity)),
+        );
+
+        if let Some(max_age) = max_age {
+            let max_age_nanos = i64::try_from(max_age.as_nanos()).unwrap_or(i64::MAX);
+            let cutoff = current_timestamp_nanos().saturating_sub(max_age_nanos);
+            let (expired, retained) = tasks
+                .into_iter()
+                .partition::<Vec<_>, _>(|(_, last_used)| *last_used < cutoff);
+            for (identity, _) in expired {
+                self.remove_task(&identity)?;
+                report.expired_tasks += 1;
+            }
+            tasks = retained;
+        }
+
+        // Remove pointers that no remaining task owns.
+        let mut pointer_identities = HashSet::new();
+        for (identity, _) in tasks.iter() {
+            pointer_identities.extend(self.pointer_identities(identity)?);
+        }
+        for pointer_cache in [
+            &mut self.outputs_pointer_cache,
+            &mut self.metadata_pointer_cache,
+        ] {
+            for source_identity in pointer_cache.source_identities()? {
+                if !pointer_identities.contains(&source_identity) {
+                    pointer_cache
+                        .remove_blob_pointer(&source_identity)
+                        .context("removing orphaned pointer")?;
+                    report.orphaned_pointers += 1;
+                }
+            }
+        }
+
+        // Count references to blobs from the remaining tasks.
+        let mut task_blobs = HashMap::new();
+        let mut reference_counts: HashMap<IdentityScheme::Identity, usize> = HashMap::new();
+        for (identity, _) in tasks.iter() {
+            let blobs = self.task_blobs(identity)?;
+            for blob in blobs.iter() {
+                *reference_counts.entry(blob.clone()).or_default() += 1;
+            }
+            task_blobs.insert(identity.clone(), blobs);
+        }
+
+        let shared_blobs = self.other_namespace_blobs()?;
+        let mut sizes = HashMap::new();
+        for blob in self.blob_cache.blob_identities()? {
+            let size = self.blob_cache.blob_size(&blob)?;
+            if reference_counts.contains_key(&blob) || shared_blobs.contains(&blob) {
+                sizes.insert(blob, size);
+            } else {
+                self.collect_blob(&blob, size, &mut report)?;
+            }
+        }
+        let mut total_bytes: u64 = sizes.values().sum();
+
+        if let Some(max_bytes) = max_bytes {
+            tasks.sort_by_key(|(_, last_used)| *last_used);
+            let mut tasks = tasks.into_iter();
+            while total_bytes > max_bytes {
+                let identity = match tasks.next() {
+                    Some((identity, _)) => identity,
+                    None => break,
+                };
+                self.remove_task(&identity)?;
+                report.evicted_tasks += 1;
+                for blob in task_blobs.remove(&identity).unwrap_or_default() {
+                    let reference_count = reference_counts
+                        .get_mut(&blob)
+                        .expect("blobs of tasks are reference counted");
+                    *reference_count -= 1;
+                    if *reference_count > 0 || shared_blobs.contains(&blob) {
+                        continue;
+                    }
+                    if let Some(size) = sizes.remove(&blob) {
+                        self.collect_blob(&blob, size, &mut report)?;
+                        total_bytes -= size;
+                    }
+                }
+            }
+        }
+
+        report.blobs_retained = sizes.len();
+        report.bytes_retained = total_bytes;
+        self.index
+            .flush()
+            .context("writing index after garbage collection")?;
+        Ok(report)
+    }
+
+    /// Lists the tasks to which an executor has written pointers, each with the time at which its
+    /// pointers were last written.
+    pub(super) fn executed_tasks(
+        &mut self,
+    ) -> anyhow::Result<Vec<(IdentityScheme::Identity, i64)>> {
+        let executor_pointers = match self.executor_pointers.as_mut() {
+            Some(executor_pointers) => executor_pointers,
+            None => return Ok(vec![]),
+        };
+        let mut tasks: HashMap<IdentityScheme::Identity, i64> = HashMap::new();
+        for pointer_cache in [
+            &mut executor_pointers.outputs,
+            &mut executor_pointers.results,
+            &mut executor_pointers.failures,
+        ] {
+            for identity in pointer_cache
+                .source_identities()
+                .context("listing executor pointers")?
+            {
+                let last_used = pointer_cache
+                    .modified(&identity)
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .and_then(|since_epoch| i64::try_from(since_epoch.as_nanos()).ok())
+                    .unwrap_or(i64::MIN);
+                let entry = tasks.entry(identity).or_insert(last_used);
+                *entry = (*entry).max(last_used);
+            }
+        }
+        Ok(tasks.into_iter().collect())
+    }
+
+    /// Identities under which metadata and outputs pointers for the task whose inputs blob has
+    /// identity `identity` may be stored: Salted by this cache, or unsalted by an executor.
+    fn pointer_identities(
+        &self,
+        identity: &IdentityScheme::Identity,
+    ) -> anyhow::Result<Vec<IdentityScheme::Identity>> {
+        let pointer_identity = self.task_pointer_identity(identity)?;
+        if pointer_identity == *identity {
+            Ok(vec![pointer_identity])
+        } else {
+            Ok(vec![pointer_identity, identity.clone()])
+        }
+    }
+
+    /// Removes the task whose inputs blob has identity `identity` from the index, along with its
+    /// pointers and those an executor wrote for it, and its recorded output streams. Its blobs are
+    /// left for collection.
+    fn remove_task(&mut self, identity: &IdentityScheme::Identity) -> anyhow::Result<()> {
+        self.index.remove(identity);
+        let pointer_identities = self.pointer_identities(identity)?;
+        let mut pointer_caches = vec![
+            (&mut self.outputs_pointer_cache, pointer_identities.clone()),
+            (&mut self.metadata_pointer_cache, pointer_identities),
+        ];
+        if let Some(executor_pointers) = self.executor_pointers.as_mut() {
+            for pointer_cache in [
+                &mut executor_pointers.outputs,
+                &mut executor_pointers.results,
+                &mut executor_pointers.failures,
+            ] {
+                pointer_caches.push((pointer_cache, vec![identity.clone()]));
+            }
+            for stream_cache in [
+                &mut executor_pointers.stdouts,
+                &mut executor_pointers.stderrs,
+            ] {
+                // Tasks loaded from a remote cache have no recorded streams.
+                let _ = stream_cache.remove_file(identity);
+            }
+        }
+        for (pointer_cache, pointer_identities) in pointer_caches {
+            for pointer_identity in pointer_identities {
+                if pointer_cache.read_blob_pointer(&pointer_identity).is_ok() {
+                    pointer_cache
+                        .remove_blob_pointer(&pointer_identity)
+                        .with_context(|| {
+                            format!("removing pointer for task {}", identity.to_string())
+                        })?;
+                }
+            }
+        }
+        events::publish(&Event::TaskEvicted {
+            inputs_identity: &identity.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Gets the identities of the blobs that the task whose inputs blob has identity `identity`
+    /// keeps alive. Blobs that cannot be read are skipped, with a warning.
+    fn task_blobs(
+        &mut self,
+        identity: &IdentityScheme::Identity,
+    ) -> anyhow::Result<HashSet<IdentityScheme::Identity>> {
+        let mut blobs = self.inputs_blobs(identity);
+        let mut outputs_identities = vec![];
+        for pointer_identity in self.pointer_identities(identity)? {
+            if let Ok(metadata_identity) = self
+                .metadata_pointer_cache
+                .read_blob_pointer(&pointer_identity)
+            {
+                blobs.insert(metadata_identity);
+            }
+            if let Ok(outputs_identity) = self
+                .outputs_pointer_cache
+                .read_blob_pointer(&pointer_identity)
+            {
+                outputs_identities.push(outputs_identity);
+            }
+        }
+        if let Some(executor_pointers) = self.executor_pointers.as_mut() {
+            if let Ok(outputs_identity) = executor_pointers.outputs.read_blob_pointer(identity) {
+                outputs_identities.push(outputs_identity);
+            }
+            for pointer_cache in [
+                &mut executor_pointers.results,
+                &mut executor_pointers.failures,
+            ] {
+                if let Ok(result_identity) = pointer_cache.read_blob_pointer(identity) {
+                    blobs.insert(result_identity);
+                }
+            }
+        }
+        for outputs_identity in outputs_identities {
+            blobs.extend(self.outputs_blobs(&outputs_identity));
+        }
+        Ok(blobs)
+    }
+
+    /// Gets the identities of the inputs blob `identity` and of the input files that it names.
+    fn inputs_blobs(
+        &mut self,
+        identity: &IdentityScheme::Identity,
+    ) -> HashSet<IdentityScheme::Identity> {
+        let mut blobs = HashSet::new();
+        blobs.insert(identity.clone());
+        match self
+            .blob_cache
+            .read_versioned_canonical_blob::<TaskInputsTransport<IdentityScheme>>(identity)
+            .and_then(TaskInputs::try_from)
+        {
+            Ok(inputs) => blobs.extend(
+                inputs
+                    .input_files()
+                    .filter_map(|(_, file_identity)| file_identity.clone()),
+            ),
+            Err(error) => tracing::warn!(
+                "reading inputs of task {} for garbage collection: {:#}",
+                identity.to_string(),
+                error
+            ),
+        }
+        blobs
+    }
+
+    /// Gets the identities of the outputs blob `identity` and of the files that it names.
+    fn outputs_blobs(
+        &mut self,
+        identity: &IdentityScheme::Identity,
+    ) -> HashSet<IdentityScheme::Identity> {
+        let mut blobs = HashSet::new();
+        blobs.insert(identity.clone());
+        match self
+            .blob_cache
+            .read_versioned_blob::<TaskOutputsTransport<IdentityScheme>>(identity)
+            .and_then(TaskOutputs::try_from)
+        {
+            Ok(outputs) => blobs.extend(
+                outputs
+                    .input_files_with_program()
+                    .chain(outputs.output_files())
+                    .filter_map(|(_, file_identity)| file_identity.clone()),
+            ),
+            Err(error) => tracing::warn!(
+                "reading outputs {} for garbage collection: {:#}",
+                identity.to_string(),
+                error
+            ),
+        }
+        blobs
+    }
+
+    /// Gets the identities of the blobs to which the indexes and pointers of the other namespaces
+    /// in the cache directory refer.
+    fn other_namespace_blobs(&mut self) -> anyhow::Result<HashSet<IdentityScheme::Identity>> {
+        let mut root = match self.root.clone() {
+            Some(root) => root,
+            None => return Ok(HashSet::new()),
+        };
+        let mut namespaces = vec![PathBuf::new()];
+        if let Ok(metadata) = root.metadata(Self::DEFAULT_SALTED_NAMESPACES_SUBDIR) {
+            if metadata.file_type == FileType::Directory {
+                for (name, file_type) in root
+                    .read_directory(Self::DEFAULT_SALTED_NAMESPACES_SUBDIR)
+                    .map_err(anyhow::Error::from)
+                    .context("listing salted cache namespaces")?
+                {
+                    if file_type == FileType::Directory {
+                        namespaces
+                            .push(PathBuf::from(Self::DEFAULT_SALTED_NAMESPACES_SUBDIR).join(name));
+                    }
+                }
+            }
+        }
+
+        let mut blobs = HashSet::new();
+        for namespace in namespaces {
+            if namespace == self.namespace {
+                continue;
+            }
+            let mut namespace_filesystem = Self::namespace_filesystem(&mut root, &namespace)?;
+            if namespace_filesystem.file_exists(Self::DEFAULT_INPUTS_LISTING_FILE) {
+                let listing_file = namespace_filesystem
+                    .open_file_for_read(Self::DEFAULT_INPUTS_LISTING_FILE)
+                    .map_err(anyhow::Error::from)
+                    .with_context(|| format!("opening index of namespace {:?}", namespace))?;
+                let listing: ListingTransport<IdentityScheme::Identity> =
+                    read_versioned::<_, Serialization, _>(listing_file)
+                        .with_context(|| format!("reading index of namespace {:?}", namespace))?;
+                for identity in listing.entries.iter() {
+                    blobs.extend(self.inputs_blobs(identity));
+                }
+            }
+            for (subdirectory, outputs) in [
+                (Self::DEFAULT_METADATA_POINTERS_SUBDIR, false),
+                (Self::DEFAULT_OUTPUTS_POINTERS_SUBDIR, true),
+            ] {
+                if namespace_filesystem.metadata(subdirectory).is_err() {
+                    continue;
+                }
+                let mut pointer_cache = BlobPointerCache::<_, IdentityScheme, Serialization>::new(
+                    namespace_filesystem.sub_system(subdirectory)?,
+                );
+                for source_identity in pointer_cache.source_identities()? {
+                    let identity = match pointer_cache.read_blob_pointer(&source_identity) {
+                        Ok(identity) => identity,
+                        Err(_) => continue,
+                    };
+                    if outputs {
+                        blobs.extend(self.outputs_blobs(&identity));
+                    } else {
+                        blobs.insert(identity);
+                    }
+                }
+            }
+        }
+        Ok(blobs)
+    }
+
+    fn collect_blob(
+        &mut self,
+        identity: &IdentityScheme::Identity,
+        size: u64,
+        report: &mut GarbageCollectionReport,
+    ) -> anyhow::Result<()> {
+        self.blob_cache
+            .remove_blob(identity)
+            .with_context(|| format!("removing blob {}", identity.to_string()))?;
+        report.blobs_removed += 1;
+        report.bytes_removed += size;
+        events::publish(&Event::BlobCollected {
+            hash: &identity.to_string(),
+            bytes: size,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GarbageCollectionReport;
+    use crate::blob::JSON;
+    use crate::cache::current_timestamp_nanos;
+    use crate::cache::Cache;
+    use crate::cache::WriteOnDropIndex;
+    use crate::canonical::Arguments;
+    use crate::canonical::EnvironmentVariables;
+    use crate::canonical::FileIdentitiesManifest;
+    use crate::canonical::Outputs;
+    use crate::canonical::Program;
+    use crate::canonical::TaskInputs;
+    use crate::canonical::TaskInputsBuilder;
+    use crate::canonical::TaskOutputs;
+    use crate::execute::identify_task_inputs;
+    use crate::execute::CacheDirectoryTaskExecutor;
+    use crate::execute::Materialization;
+    use crate::execute::TaskExecutor as _;
+    use crate::fs::HostFilesystem;
+    use crate::identity::IdentityScheme as _;
+    use crate::runner::DefaultRunner;
+    use crate::transport::ContentSha256;
+    use std::time::Duration;
+
+    type TestCache = Cache<
+        HostFilesystem,
+        ContentSha256,
+        JSON,
+        WriteOnDropIndex<HostFilesystem, ContentSha256, JSON>,
+    >;
+
+    #[test]
+    fn test_collect_garbage() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let mut cache = TestCache::create(filesystem).expect("create cache");
+
+        let input_identity = ContentSha256::identify_content(&b"input"[..]).expect("identify");
+        cache
+            .blob_cache
+            .copy_blob(&b"input"[..], &input_identity)
+            .expect("store input blob");
+        let garbage_identity = ContentSha256::identify_content(&b"garbage"[..]).expect("identify");
+        cache
+            .blob_cache
+            .copy_blob(&b"garbage"[..], &garbage_identity)
+            .expect("store garbage blob");
+        cache
+            .outputs_pointer_cache
+            .write_raw_blob_pointer(&garbage_identity, &garbage_identity)
+            .expect("write orphaned pointer");
+
+        let now = current_timestamp_nanos();
+        let mut identities = vec![];
+        for (program, timestamp_nanos) in [("old", 5), ("earlier", now), ("later", now + 1)] {
+            let mut builder = TaskInputsBuilder::<ContentSha256>::new().program(program);
+            if program == "old" {
+                builder = builder.input_file("input.txt", Some(input_identity.clone()));
+            }
+            let inputs = builder.build().expect("task inputs");
+            identities.push(identify_task_inputs(&inputs).expect("identify task inputs"));
+            let outputs = TaskOutputs::<ContentSha256>::new(
+                FileIdentitiesManifest::empty(),
+                FileIdentitiesManifest::empty(),
+            );
+            cache
+                .put_task(timestamp_nanos, 0, inputs, outputs)
+                .expect("put task");
+        }
+
+        // The old task, the blobs that only it refers to, and unreferenced blobs and pointers are
+        // removed. The outputs blob, which every task shares, is kept.
+        let report = cache
+            .collect_garbage(None, Some(Duration::from_secs(24 * 60 * 60)))
+            .expect("collect garbage");
+        assert_eq!(1, report.expired_tasks);
+        assert_eq!(0, report.evicted_tasks);
+        assert_eq!(1, report.orphaned_pointers);
+        assert_eq!(4, report.blobs_removed);
+        assert_eq!(5, report.blobs_retained);
+        assert!(!cache.blob_cache.contains_blob(&input_identity));
+        assert!(!cache.blob_cache.contains_blob(&garbage_identity));
+        assert_eq!(
+            None,
+            cache.get_outputs(&identities[0]).expect("get outputs")
+        );
+        assert!(cache
+            .get_outputs(&identities[1])
+            .expect("get outputs")
+            .is_some());
+
+        // Collecting again removes nothing.
+        let bytes_retained = report.bytes_retained;
+        let report = cache
+            .collect_garbage(Some(bytes_retained), None)
+            .expect("collect garbage");
+        assert_eq!(
+            GarbageCollectionReport {
+                blobs_retained: 5,
+                bytes_retained,
+                ..GarbageCollectionReport::default()
+            },
+            report
+        );
+
+        // The least recently used task is evicted to meet the size limit; the hit above made the
+        // task put earlier the most recently used.
+        let report = cache
+            .collect_garbage(Some(bytes_retained - 1), None)
+            .expect("collect garbage");
+        assert_eq!(1, report.evicted_tasks);
+        assert_eq!(2, report.blobs_removed);
+        assert_eq!(3, report.blobs_retained);
+        assert_eq!(
+            None,
+            cache.get_outputs(&identities[2]).expect("get outputs")
+        );
+        assert!(cache
+            .get_outputs(&identities[1])
+            .expect("get outputs")
+            .is_some());
+    }
+
+    #[test]
+    fn test_collect_garbage_keeps_executed_tasks() {
+        let cache_directory = tempfile::tempdir().expect("cache directory");
+        let working_directory = tempfile::tempdir().expect("working directory");
+        let mut working_filesystem =
+            HostFilesystem::try_new(working_directory.path().to_path_buf())
+                .expect("working directory filesystem");
+        let cache_filesystem = HostFilesystem::try_new(cache_directory.path().to_path_buf())
+            .expect("cache filesystem");
+        let mut executor =
+            CacheDirectoryTaskExecutor::<HostFilesystem, ContentSha256, JSON, DefaultRunner>::new(
+                cache_filesystem.clone(),
+            )
+            .expect("task executor")
+            .with_materialization(Materialization::Copy);
+        let inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("/bin/sh"),
+            Arguments::new(["-c", "echo run >> runs.log; echo output > output.txt"]),
+            FileIdentitiesManifest::empty(),
+            Outputs::empty().with_include_globs(["output.txt"]),
+        );
+        let output_path = working_directory.path().join("output.txt");
+        let runs = || {
+            std::fs::read_to_string(working_directory.path().join("runs.log"))
+                .expect("read runs")
+                .lines()
+                .count()
+        };
+        executor
+            .load_or_execute(&mut working_filesystem, &inputs)
+            .expect("execute task");
+
+        // The executor wrote no index, but its pointers keep its blobs alive.
+        let mut cache = TestCache::open_existing(cache_filesystem).expect("open cache");
+        let report = cache.collect_garbage(None, None).expect("collect garbage");
+        assert_eq!(0, report.blobs_removed);
+        assert_eq!(0, report.orphaned_pointers);
+        std::fs::remove_file(&output_path).expect("remove output");
+        executor
+            .load_or_execute(&mut working_filesystem, &inputs)
+            .expect("load task");
+        assert_eq!(1, runs());
+        assert_eq!(
+            "output\n",
+            std::fs::read_to_string(&output_path).expect("read output")
+        );
+
+        // Evicting the executed task removes its pointers along with its blobs, so it is executed
+        // again.
+        let report = cache
+            .collect_garbage(Some(0), None)
+            .expect("collect garbage");
+        assert_eq!(1, report.evicted_tasks);
+        assert_eq!(0, report.blobs_retained);
+        executor
+            .load_or_execute(&mut working_filesystem, &inputs)
+            .expect("execute task again");
+        assert_eq!(2, runs());
+    }
+
+    #[test]
+    fn test_open_existing() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        assert!(TestCache::open_existing(filesystem).is_err());
+        assert!(!temporary_directory.path().join("blobs").exists());
+    }
+}
@@ -0,0 +1,569 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! A local cache backed by a remote cache, such as one shared by a CI fleet.
+//!
+//! Lookups check the local `Cache` first. On a miss, the task's pointers and blobs are fetched from
+//! the remote cache into the local cache, so that later lookups hit locally. Remote failures are
+//! logged and treated as misses.
+//!
+//! Tasks put into a `TieredCache` are written to the local cache, then uploaded by a pool of
+//! background threads, so that remote latency does not delay task completion. Uploads wait in a
+//! bounded queue; when it is full, the upload is dropped with a warning, and the task remains in
+//! the local cache (from which `crate::sync::sync_caches` can upload it later).
+
+use super::current_timestamp_nanos;
+use super::Cache;
+use super::Index;
+use crate::blob::ReadDeserializer as ReadDeserializerApi;
+use crate::blob::StringSerializer as StringSerializerApi;
+use crate::blob::WriteSerializer as WriteSerializerApi;
+use crate::canonical::TaskInputs;
+use crate::canonical::TaskOutputs;
+use crate::execute::identify_task_inputs;
+use crate::fs::Filesystem as FilesystemApi;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::sync::PointerKind;
+use crate::sync::SyncPeer;
+use anyhow::Context as _;
+use std::io::Read as _;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::SyncSender;
+use std::sync::mpsc::TrySendError;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+/// Default for `TieredCacheOptions::upload_threads`.
+pub const DEFAULT_UPLOAD_THREADS: usize = 4;
+
+/// Default for `TieredCacheOptions::upload_queue_capacity`.
+pub const DEFAULT_UPLOAD_QUEUE_CAPACITY: usize = 64;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TieredCacheOptions {
+    /// Number of background threads uploading tasks to the remote cache.
+    pub upload_threads: usize,
+    /// Number of tasks that may wait to be uploaded before further uploads are dropped.
+    pub upload_queue_capacity: usize,
+}
+
+impl Default for TieredCacheOptions {
+    fn default() -> Self {
+        Self {
+            upload_threads: DEFAULT_UPLOAD_THREADS,
+            upload_queue_capacity: DEFAULT_UPLOAD_QUEUE_CAPACITY,
+        }
+    }
+}
+
+/// The blobs and pointers of one task, read from the local cache to be written to the remote
+/// cache.
+struct Upload<Identity> {
+    blobs: Vec<(Identity, Vec<u8>)>,
+    pointers: Vec<(PointerKind, Identity, Identity)>,
+}
+
+/// A pool of threads that each write uploads to their own clone of the remote cache.
+struct Uploader<Identity> {
+    sender: Option<SyncSender<Upload<Identity>>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<Identity: crate::identity::Identity + Send + 'static> Uploader<Identity> {
+    fn new<IS, Remote>(remote: &Remote, options: &TieredCacheOptions) -> Self
+    where
+        IS: IdentitySchemeApi<Identity = Identity>,
+        Remote: SyncPeer<IS> + Clone + Send + 'static,
+    {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(options.upload_queue_capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..options.upload_threads.max(1))
+            .map(|_| {
+                let receiver: Arc<Mutex<Receiver<Upload<Identity>>>> = receiver.clone();
+                let mut remote = remote.clone();
+                std::thread::spawn(move || loop {
+                    // The lock is released before uploading, so that workers upload concurrently.
+                    let upload = match receiver.lock() {
+                        Ok(receiver) => receiver.recv(),
+                        Err(_) => return,
+                    };
+                    match upload {
+                        Ok(upload) => {
+                            if let Err(error) = write_upload(&mut remote, upload) {
+                                tracing::warn!("uploading task to remote cache: {:#}", error);
+                            }
+                        }
+                        // The sender is dropped once no more uploads will be queued.
+                        Err(_) => return,
+                    }
+                })
+            })
+            .collect();
+        Self {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    fn queue(&self, upload: Upload<Identity>) {
+        let sender = match self.sender.as_ref() {
+            Some(sender) => sender,
+            None => return,
+        };
+        match sender.try_send(upload) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                tracing::warn!("remote cache upload queue is full; dropping upload")
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                tracing::warn!("remote cache uploaders have stopped; dropping upload")
+            }
+        }
+    }
+}
+
+/// Waits for queued uploads to finish.
+impl<Identity> Drop for Uploader<Identity> {
+    fn drop(&mut self) {
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                tracing::warn!("remote cache uploader panicked");
+            }
+        }
+    }
+}
+
+/// Writes blobs before the pointers that refer to them, so that the remote cache never points to
+/// missing blobs.
+fn write_upload<IS: IdentitySchemeApi, Remote: SyncPeer<IS>>(
+    remote: &mut Remote,
+    upload: Upload<IS::Identity>,
+) -> anyhow::Result<()> {
+    let identities: Vec<IS::Identity> = upload
+        .blobs
+        .iter()
+        .map(|(identity, _)| identity.clone())
+        .collect();
+    let missing = remote
+        .missing_blobs(&identities)
+        .context("checking for blobs missing from remote cache")?;
+    for (identity, content) in upload.blobs.iter() {
+        if missing.contains(identity) {
+            remote
+                .write_blob(identity, content)
+                .with_context(|| format!("writing remote blob {}", identity.to_string()))?;
+        }
+    }
+    for (kind, source, destination) in upload.pointers.iter() {
+        remote
+            .write_pointer(*kind, source, destination)
+            .with_context(|| {
+                format!(
+                    "writing remote {:?} pointer from {}",
+                    kind,
+                    source.to_string()
+                )
+            })?;
+    }
+    Ok(())
+}
+
+/// A local `Cache` that falls back to, and uploads to, a remote cache.
+pub struct TieredCache<
+    Filesystem: FilesystemApi,
+    IdentityScheme: IdentitySchemeApi,
+    Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+    Idx: Index<Filesystem = Filesystem, Identity = IdentityScheme::Identity, Error = anyhow::Error>,
+    Remote: SyncPeer<IdentityScheme>,
+> {
+    local: Cache<Filesystem, IdentityScheme, Serialization, Idx>,
+    remote: Remote,
+    uploader: Uploader<IdentityScheme::Identity>,
+}
+
+impl<
+        Filesystem: FilesystemApi,
+        IdentityScheme: IdentitySchemeApi,
+        Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+        Idx: Index<Filesystem = Filesystem, Identity = IdentityScheme::Identity, Error = anyhow::Error>,
+        Remote: SyncPeer<IdentityScheme> + Clone + Send + 'static,
+    > TieredCache<Filesystem, IdentityScheme, Serialization, Idx, Remote>
+where
+    IdentityScheme::Identity: Send + 'static,
+{
+    /// Starts `options.upload_threads` threads, each uploading through a clone of `remote`.
+    pub fn new(
+        local: Cache<Filesystem, IdentityScheme, Serialization, Idx>,
+        remote: Remote,
+        options: &TieredCacheOptions,
+    ) -> Self {
+        let uploader = Uploader::new::<IdentityScheme, Remote>(&remote, options);
+        Self {
+            local,
+            remote,
+            uploader,
+        }
+    }
+
+    /// The local cache, for operations that do not involve the remote cache.
+    pub fn local(&mut self) -> &mut Cache<Filesystem, IdentityScheme, Serialization, Idx> {
+        &mut self.local
+    }
+
+    /// Waits for queued uploads to finish, and returns the local cache.
+    pub fn finish(self) -> Cache<Filesystem, IdentityScheme, Serialization, Idx> {
+        drop(self.uploader);
+        self.local
+    }
+
+    /// Gets the outputs of the task whose inputs blob has identity `task_inputs_identity` from the
+    /// local cache, or else from the remote cache, copying the task into the local cache.
+    pub fn get_outputs(
+        &mut self,
+        task_inputs_identity: &IdentityScheme::Identity,
+    ) -> anyhow::Result<Option<TaskOutputs<IdentityScheme>>> {
+        if let Some(outputs) = self.local.get_outputs(task_inputs_identity)? {
+            return Ok(Some(outputs));
+        }
+        match self.fetch_task(task_inputs_identity) {
+            Ok(true) => self.local.get_outputs(task_inputs_identity),
+            Ok(false) => Ok(None),
+            Err(error) => {
+                tracing::warn!("reading task from remote cache: {:#}", error);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Puts a task into the local cache, as by `Cache::put_task`, and queues its upload to the
+    /// remote cache. The blobs of its output files must already be in the local cache (see
+    /// `Cache::put_blobs`).
+    pub fn put_task(
+        &mut self,
+        timestamp_nanos: i64,
+        execution_duration_nanos: u128,
+        inputs: TaskInputs<IdentityScheme>,
+        outputs: TaskOutputs<IdentityScheme>,
+    ) -> anyhow::Result<()> {
+        let task_inputs_identity = identify_task_inputs(&inputs)?;
+        self.local
+            .put_task(timestamp_nanos, execution_duration_nanos, inputs, outputs)?;
+        let upload = self
+            .read_upload(&task_inputs_identity)
+            .context("reading task for upload to remote cache")?;
+        self.uploader.queue(upload);
+        Ok(())
+    }
+
+    /// Copies the task whose inputs blob has identity `task_inputs_identity` from the remote cache
+    /// into the local cache, returning whether the remote cache contains the task. The outputs
+    /// pointer is written last, so that an interrupted copy is a miss.
+    fn fetch_task(
+        &mut self,
+        task_inputs_identity: &IdentityScheme::Identity,
+    ) -> anyhow::Result<bool> {
+        let pointer_identity = self.local.task_pointer_identity(task_inputs_identity)?;
+        let outputs_identity = match self
+            .remote
+            .read_pointer(PointerKind::Outputs, &pointer_identity)?
+        {
+            Some(outputs_identity) => outputs_identity,
+            None => return Ok(false),
+        };
+        self.fetch_blob(&outputs_identity)?;
+        let outputs: TaskOutputs<IdentityScheme> = self
+            .local
+            .blob_cache
+            .read_versioned_blob::<crate::transport::TaskOutputs<IdentityScheme>>(
+                &outputs_identity,
+            )?
+            .try_into()?;
+        for (_, identity) in outputs.output_files() {
+            if let Some(identity) = identity {
+                self.fetch_blob(identity)?;
+            }
+        }
+        // The inputs blob is only needed for summaries, and the metadata only for queries.
+        if let Err(error) = self.fetch_blob(task_inputs_identity) {
+            tracing::warn!("reading task inputs from remote cache: {:#}", error);
+        }
+        if let Some(metadata_identity) = self
+            .remote
+            .read_pointer(PointerKind::Metadata, &pointer_identity)?
+        {
+            self.fetch_blob(&metadata_identity)?;
+            self.local
+                .metadata_pointer_cache
+                .write_raw_blob_pointer(&pointer_identity, &metadata_identity)?;
+        }
+        self.local
+            .outputs_pointer_cache
+            .write_raw_blob_pointer(&pointer_identity, &outputs_identity)?;
+        self.local
+            .index
+            .put_at(task_inputs_identity.clone(), current_timestamp_nanos());
+        self.local.index.flush()?;
+        Ok(true)
+    }
+
+    /// Copies the blob identified by `identity` from the remote cache, unless the local cache
+    /// already contains it.
+    fn fetch_blob(&mut self, identity: &IdentityScheme::Identity) -> anyhow::Result<()> {
+        if self.local.blob_cache.contains_blob(identity) {
+            return Ok(());
+        }
+        let content = self.remote.read_blob(identity)?;
+        self.local
+            .blob_cache
+            .copy_blob(content.as_slice(), identity)
+            .with_context(|| format!("copying blob {} from remote cache", identity.to_string()))
+    }
+
+    /// Reads the blobs and pointers of the task whose inputs blob has identity
+    /// `task_inputs_identity` from the local cache.
+    fn read_upload(
+        &mut self,
+        task_inputs_identity: &IdentityScheme::Identity,
+    ) -> anyhow::Result<Upload<IdentityScheme::Identity>> {
+        let pointer_identity = self.local.task_pointer_identity(task_inputs_identity)?;
+        let outputs_identity = self
+            .local
+            .outputs_pointer_cache
+            .read_blob_pointer(&pointer_identity)?;
+        let metadata_identity = self
+            .local
+            .metadata_pointer_cache
+            .read_blob_pointer(&pointer_identity)?;
+        let outputs = self
+            .local
+            .get_outputs(task_inputs_identity)?
+            .ok_or_else(|| anyhow::anyhow!("task missing from local cache"))?;
+
+        let mut identities = vec![
+            task_inputs_identity.clone(),
+            outputs_identity.clone(),
+            metadata_identity.clone(),
+        ];
+        identities.extend(
+            outputs
+                .output_files()
+                .filter_map(|(_, identity)| identity.clone()),
+        );
+        let mut blobs = vec![];
+        for identity in identities {
+            let mut content = vec![];
+            self.local
+                .open_blob(&identity)
+                .and_then(|mut blob| Ok(blob.read_to_end(&mut content)?))
+                .with_context(|| format!("reading blob {}", identity.to_string()))?;
+            blobs.push((identity, content));
+        }
+        Ok(Upload {
+            blobs,
+            pointers: vec![
+                (
+                    PointerKind::Metadata,
+                    pointer_identity.clone(),
+                    metadata_identity,
+                ),
+                (PointerKind::Outputs, pointer_identity, outputs_identity),
+            ],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TieredCache;
+    use super::TieredCacheOptions;
+    use crate::blob::JSON;
+    use crate::cache::Cache;
+    use crate::cache::WriteOnDropIndex;
+    use crate::canonical::FileIdentitiesManifest;
+    use crate::canonical::FilesManifest;
+    use crate::canonical::TaskInputs;
+    use crate::canonical::TaskOutputs;
+    use crate::execute::identify_task_inputs;
+    use crate::fs::HostFilesystem;
+    use crate::remote::RemoteBlobCache;
+    use crate::remote::RemoteBlobCacheOptions;
+    use crate::serve::CacheServer;
+    use crate::serve::ServeOptions;
+    use crate::sync::PointerKind;
+    use crate::transport::ContentSha256;
+    use std::io::Read as _;
+    use std::net::TcpListener;
+    use std::path::Path;
+
+    type TestCache = Cache<
+        HostFilesystem,
+        ContentSha256,
+        JSON,
+        WriteOnDropIndex<HostFilesystem, ContentSha256, JSON>,
+    >;
+
+    fn create_cache(path: &Path) -> TestCache {
+        std::fs::create_dir_all(path).expect("create cache directory");
+        TestCache::create(HostFilesystem::try_new(path.to_path_buf()).expect("cache filesystem"))
+            .expect("create cache")
+    }
+
+    /// Serves a new cache at `path`, returning a client of it.
+    fn serve_cache(path: &Path) -> RemoteBlobCache<ContentSha256> {
+        create_cache(path);
+        let server = CacheServer::<_, ContentSha256, JSON>::new(
+            HostFilesystem::try_new(path.to_path_buf()).expect("remote cache filesystem"),
+            ServeOptions::default(),
+        )
+        .expect("cache server");
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind listener");
+        let url = format!(
+            "http://{}/",
+            listener.local_addr().expect("listener address")
+        );
+        std::thread::spawn(move || server.serve(listener));
+        RemoteBlobCache::<ContentSha256>::new(&url, RemoteBlobCacheOptions::default())
+            .expect("remote cache")
+    }
+
+    #[test]
+    fn test_tiered_cache() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let remote = serve_cache(&temporary_directory.path().join("remote"));
+
+        let working_directory = temporary_directory.path().join("work");
+        std::fs::create_dir_all(&working_directory).expect("create working directory");
+        std::fs::write(working_directory.join("artifact.txt"), "artifact")
+            .expect("write artifact.txt");
+        let mut working_filesystem =
+            HostFilesystem::try_new(working_directory).expect("working directory filesystem");
+        let inputs = TaskInputs::<ContentSha256>::for_test("argument");
+        let inputs_identity = identify_task_inputs(&inputs).expect("identify task inputs");
+        let output_files = FilesManifest::new(["artifact.txt"])
+            .into_identified::<ContentSha256, _>(&mut working_filesystem);
+        let outputs = TaskOutputs::new(FileIdentitiesManifest::empty(), output_files.clone());
+
+        let mut first = TieredCache::new(
+            create_cache(&temporary_directory.path().join("first")),
+            remote.clone(),
+            &TieredCacheOptions::default(),
+        );
+        assert_eq!(None, first.get_outputs(&inputs_identity).expect("miss"));
+        first
+            .local()
+            .put_blobs(&mut working_filesystem, &output_files)
+            .expect("put blobs");
+        first
+            .put_task(0, 0, inputs, outputs.clone())
+            .expect("put task");
+        first.finish();
+        assert!(remote
+            .clone()
+            .read_blob_pointer(PointerKind::Outputs, &inputs_identity)
+            .expect("read remote pointer")
+            .is_some());
+
+        // A cache that lacks the task copies it from the remote cache.
+        let mut second = TieredCache::new(
+            create_cache(&temporary_directory.path().join("second")),
+            RemoteBlobCache::<ContentSha256>::new(
+                "http://127.0.0.1:1/",
+                RemoteBlobCacheOptions::default(),
+            )
+            .expect("unreachable remote cache"),
+            &TieredCacheOptions::default(),
+        );
+        assert_eq!(
+            None,
+            second
+                .get_outputs(&inputs_identity)
+                .expect("unreachable remote cache is a miss")
+        );
+        let mut second = TieredCache::new(second.finish(), remote, &TieredCacheOptions::default());
+        assert_eq!(
+            Some(outputs.clone()),
+            second.get_outputs(&inputs_identity).expect("remote hit")
+        );
+        let mut second = second.finish();
+        assert_eq!(
+            Some(outputs),
+            second.get_outputs(&inputs_identity).expect("local hit")
+        );
+        let (_, identity) = output_files.identities().next().expect("output file");
+        let mut contents = String::new();
+        second
+            .open_blob(identity.as_ref().expect("output identity"))
+            .expect("open output blob")
+            .read_to_string(&mut contents)
+            .expect("read output blob");
+        assert_eq!("artifact", contents);
+        assert!(second
+            .get_metadata(&inputs_identity)
+            .expect("get metadata")
+            .is_some());
+    }
+
+    #[test]
+    fn test_tiered_cache_incomplete_remote_task() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let remote_directory = temporary_directory.path().join("remote");
+        let remote = serve_cache(&remote_directory);
+
+        let working_directory = temporary_directory.path().join("work");
+        std::fs::create_dir_all(&working_directory).expect("create working directory");
+        std::fs::write(working_directory.join("artifact.txt"), "artifact")
+            .expect("write artifact.txt");
+        let mut working_filesystem =
+            HostFilesystem::try_new(working_directory).expect("working directory filesystem");
+        let inputs = TaskInputs::<ContentSha256>::for_test("argument");
+        let inputs_identity = identify_task_inputs(&inputs).expect("identify task inputs");
+        let output_files = FilesManifest::new(["artifact.txt"])
+            .into_identified::<ContentSha256, _>(&mut working_filesystem);
+        let outputs = TaskOutputs::new(FileIdentitiesManifest::empty(), output_files.clone());
+
+        let mut first = TieredCache::new(
+            create_cache(&temporary_directory.path().join("first")),
+            remote.clone(),
+            &TieredCacheOptions::default(),
+        );
+        first
+            .local()
+            .put_blobs(&mut working_filesystem, &output_files)
+            .expect("put blobs");
+        first.put_task(0, 0, inputs, outputs).expect("put task");
+        first.finish();
+
+        // The remote cache points to the task's outputs, but has lost an output file's blob.
+        let (_, identity) = output_files.identities().next().expect("output file");
+        std::fs::remove_file(
+            remote_directory
+                .join(TestCache::DEFAULT_BLOBS_SUBDIR)
+                .join(identity.as_ref().expect("output identity").to_string()),
+        )
+        .expect("remove remote output blob");
+
+        let mut second = TieredCache::new(
+            create_cache(&temporary_directory.path().join("second")),
+            remote,
+            &TieredCacheOptions::default(),
+        );
+        assert_eq!(
+            None,
+            second
+                .get_outputs(&inputs_identity)
+                .expect("incomplete remote task is a miss")
+        );
+        // The copy stopped before the outputs pointer, so the local cache does not refer to the
+        // missing blob.
+        assert_eq!(
+            None,
+            second
+                .finish()
+                .get_outputs(&inputs_identity)
+                .expect("local miss")
+        );
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Integrity checks for cache directories, which accumulate damage from interrupted writes and
+//! failing disks.
+//!
+//! Every blob is rehashed and compared with the identity under which it is stored, and every
+//! pointer is checked to refer to an intact blob. Repairing a cache deletes the damaged entries,
+//! so that later lookups miss rather than return corrupt results.
+
+use super::Cache;
+use super::Index;
+use crate::blob::ReadDeserializer as ReadDeserializerApi;
+use crate::blob::StringSerializer as StringSerializerApi;
+use crate::blob::WriteSerializer as WriteSerializerApi;
+use crate::fs::Filesystem as FilesystemApi;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use anyhow::Context as _;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// What a cache verification found, as paths relative to the cache directory.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct VerificationReport {
+    pub blobs_verified: usize,
+    /// Blobs that cannot be read, or whose contents do not have the identity under which they are
+    /// stored.
+    pub corrupt_blobs: Vec<PathBuf>,
+    pub pointers_verified: usize,
+    /// Pointers that cannot be read, or that refer to missing or corrupt blobs.
+    pub broken_pointers: Vec<PathBuf>,
+    /// Whether corrupt blobs and broken pointers were deleted.
+    pub repaired: bool,
+}
+
+impl VerificationReport {
+    pub fn is_intact(&self) -> bool {
+        self.corrupt_blobs.is_empty() && self.broken_pointers.is_empty()
+    }
+}
+
+impl<
+        Filesystem: FilesystemApi,
+        IdentityScheme: IdentitySchemeApi,
+        Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+        Idx: Index<Filesystem = Filesystem, Identity = IdentityScheme::Identity, Error = anyhow::Error>,
+    > Cache<Filesystem, IdentityScheme, Serialization, Idx>
+{
+    /// Rehashes every blob and checks that every pointer refers to an intact blob. When `repair`
+    /// is set, corrupt blobs and broken pointers are deleted.
+    pub fn verify(&mut self, repair: bool) -> anyhow::Result<VerificationReport> {
+        let mut report = VerificationReport {
+            repaired: repair,
+            ..VerificationReport::default()
+        };
+
+        let mut corrupt_blobs = HashSet::new();
+        for blob in self.blob_cache.blob_identities()? {
+            report.blobs_verified += 1;
+            match self
+                .blob_cache
+                .open_blob(&blob)
+                .and_then(IdentityScheme::identify_content)
+            {
+                Ok(identity) if identity == blob => continue,
+                Ok(identity) => tracing::warn!(
+                    "blob {} has contents with identity {}",
+                    blob.to_string(),
+                    identity.to_string()
+                ),
+                Err(error) => tracing::warn!("reading blob {}: {:#}", blob.to_string(), error),
+            }
+            report
+                .corrupt_blobs
+                .push(Path::new(Self::DEFAULT_BLOBS_SUBDIR).join(blob.to_string()));
+            if repair {
+                self.blob_cache
+                    .remove_blob(&blob)
+                    .with_context(|| format!("removing corrupt blob {}", blob.to_string()))?;
+            }
+            corrupt_blobs.insert(blob);
+        }
+
+        for (subdirectory, pointer_cache) in [
+            (
+                Self::DEFAULT_METADATA_POINTERS_SUBDIR,
+                &mut self.metadata_pointer_cache,
+            ),
+            (
+                Self::DEFAULT_OUTPUTS_POINTERS_SUBDIR,
+                &mut self.outputs_pointer_cache,
+            ),
+        ] {
+            for source_identity in pointer_cache.source_identities()? {
+                report.pointers_verified += 1;
+                match pointer_cache.read_blob_pointer(&source_identity) {
+                    Ok(destination_identity)
+                        if !corrupt_blobs.contains(&destination_identity)
+                            && self.blob_cache.contains_blob(&destination_identity) =>
+                    {
+                        continue
+                    }
+                    Ok(destination_identity) => tracing::warn!(
+                        "pointer {} refers to missing or corrupt blob {}",
+                        source_identity.to_string(),
+                        destination_identity.to_string()
+                    ),
+                    Err(error) => tracing::warn!(
+                        "reading pointer {}: {:#}",
+                        source_identity.to_string(),
+                        error
+                    ),
+                }
+                report.broken_pointers.push(
+                    self.namespace
+                        .join(subdirectory)
+                        .join(source_identity.to_string()),
+                );
+                if repair {
+                    pointer_cache
+                        .remove_blob_pointer(&source_identity)
+                        .with_context(|| {
+                            format!("removing broken pointer {}", source_identity.to_string())
+                        })?;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VerificationReport;
+    use crate::blob::JSON;
+    use crate::cache::Cache;
+    use crate::cache::WriteOnDropIndex;
+    use crate::identity::IdentityScheme as _;
+    use crate::transport::ContentSha256;
+    use std::path::Path;
+
+    #[test]
+    fn test_verify() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem =
+            crate::fs::HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+                .expect("host filesystem");
+        let mut cache =
+            Cache::<_, ContentSha256, JSON, WriteOnDropIndex<_, ContentSha256, JSON>>::create(
+                filesystem,
+            )
+            .expect("create cache");
+
+        let intact_identity = ContentSha256::identify_content(&b"intact"[..]).expect("identify");
+        cache
+            .blob_cache
+            .copy_blob(&b"intact"[..], &intact_identity)
+            .expect("store intact blob");
+        let corrupt_identity = ContentSha256::identify_content(&b"corrupt"[..]).expect("identify");
+        cache
+            .blob_cache
+            .copy_blob(&b"corrupt"[..], &corrupt_identity)
+            .expect("store corrupt blob");
+        let missing_identity = ContentSha256::identify_content(&b"missing"[..]).expect("identify");
+        for (source_identity, destination_identity) in [
+            (&intact_identity, &intact_identity),
+            (&corrupt_identity, &corrupt_identity),
+            (&missing_identity, &missing_identity),
+        ] {
+            cache
+                .outputs_pointer_cache
+                .write_raw_blob_pointer(source_identity, destination_identity)
+                .expect("write pointer");
+        }
+        std::fs::write(
+            temporary_directory
+                .path()
+                .join("blobs")
+                .join(corrupt_identity.to_string()),
+            "truncated",
+        )
+        .expect("corrupt blob");
+
+        let report = cache.verify(false).expect("verify cache");
+        let expected_report = VerificationReport {
+            blobs_verified: 2,
+            corrupt_blobs: vec![Path::new("blobs").join(corrupt_identity.to_string())],
+            pointers_verified: 3,
+            broken_pointers: {
+                let mut broken_pointers = vec![
+                    Path::new("outputs").join(corrupt_identity.to_string()),
+                    Path::new("outputs").join(missing_identity.to_string()),
+                ];
+                broken_pointers.sort();
+                broken_pointers
+            },
+            repaired: false,
+        };
+        assert_eq!(expected_report, report);
+        assert!(!report.is_intact());
+        assert!(cache.blob_cache.contains_blob(&corrupt_identity));
+
+        // Repairing deletes the damaged entries, after which the cache is intact.
+        assert_eq!(
+            VerificationReport {
+                repaired: true,
+                ..expected_report
+            },
+            cache.verify(true).expect("repair cache")
+        );
+        assert!(!cache.blob_cache.contains_blob(&corrupt_identity));
+        let report = cache.verify(false).expect("verify repaired cache");
+        assert!(report.is_intact());
+        assert_eq!(1, report.blobs_verified);
+        assert_eq!(1, report.pointers_verified);
+    }
+}
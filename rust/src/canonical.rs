@@ -2,25 +2,56 @@
 // Use of this source code is governed by a Apache-style license that can be
 // found in the LICENSE file.
 
+use crate::chunk::Chunker;
 use crate::context::diff_items_to_string;
+use crate::depfile::parse_depfile_prerequisites;
+use crate::fs::FileMetadata;
+use crate::fs::FileType;
 use crate::fs::Filesystem as FilesystemApi;
 use crate::identity::AsTransport;
+use crate::identity::FileIdentityStatus;
 use crate::identity::Identity as IdentityBound;
 use crate::identity::IdentityScheme as IdentitySchemeApi;
 use crate::identity::IntoTransport;
+use crate::include_scanner::normalize_path;
+use crate::include_scanner::scan_includes;
+use crate::include_scanner::Include;
+use crate::include_scanner::IncludeKind;
+use crate::plugin::ScannerPlugin;
+use crate::profile;
+use crate::profile::Phase;
+use crate::runner::ExecutionResult;
+use crate::schema::FormatVersion;
 use crate::transport::Arguments as ArgumentsTransport;
+use crate::transport::CIncludes as CIncludesTransport;
+use crate::transport::Chunk as ChunkTransport;
+use crate::transport::ChunkManifest as ChunkManifestTransport;
+use crate::transport::EnvironmentInheritance;
 use crate::transport::EnvironmentVariables as EnvironmentVariablesTransport;
 use crate::transport::FileIdentitiesManifest as FileIdentitiesManifestTransport;
+use crate::transport::FileIdentitiesManifestDelta as FileIdentitiesManifestDeltaTransport;
 use crate::transport::FilesManifest as FilesManifestTransport;
 use crate::transport::IdentityScheme;
 use crate::transport::Inputs as InputsTransport;
+use crate::transport::InputsGroup as InputsGroupTransport;
+use crate::transport::InterFileReferences as InterFileReferencesTransport;
 use crate::transport::Listing as ListingTransport;
+use crate::transport::ListingTimes;
 use crate::transport::Match;
+use crate::transport::MatchFlags;
 use crate::transport::MatchTransform as MatchTransformTransport;
 use crate::transport::Metadata as MetadataTransport;
 use crate::transport::Outputs as OutputsTransport;
+use crate::transport::OutputsDirectory as OutputsDirectoryTransport;
+use crate::transport::OutputsVerification;
 use crate::transport::Program as ProgramTransport;
+use crate::transport::ResourceLimits;
+use crate::transport::Sandbox;
+use crate::transport::ScannerPlugin as ScannerPluginTransport;
+use crate::transport::Stdin;
+use crate::transport::SymlinkIdentity;
 use crate::transport::System as SystemTransport;
+use crate::transport::Task as TaskTransport;
 use crate::transport::TaskInputs as TaskInputsTransport;
 use crate::transport::TaskOutputs as TaskOutputsTransport;
 use anyhow::Context as _;
@@ -28,11 +59,14 @@ use regex::Regex;
 use std::borrow::Borrow;
 use std::borrow::Cow;
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::hash::Hash;
 use std::hash::Hasher;
-use std::io::BufRead;
-use std::io::BufReader;
+use std::io::Read;
+use std::num::NonZeroUsize;
 use std::path::Path;
 use std::path::PathBuf;
 use sysinfo::SystemExt;
@@ -40,33 +74,52 @@ use sysinfo::SystemExt;
 #[derive(Clone, Debug)]
 pub struct RegularExpression {
     regular_expression_string: String,
+    flags: MatchFlags,
     regular_expression: Regex,
 }
 
 #[derive(Eq, Hash, Ord, PartialEq, PartialOrd)]
-struct RegexStr<'a>(&'a str);
+struct RegexStr<'a>(&'a str, MatchFlags);
+
+impl RegularExpression {
+    pub fn with_flags(
+        regular_expression_string: String,
+        flags: MatchFlags,
+    ) -> Result<Self, regex::Error> {
+        let regular_expression = regex::RegexBuilder::new(&regular_expression_string)
+            .case_insensitive(flags.case_insensitive)
+            .multi_line(flags.multi_line)
+            .dot_matches_new_line(flags.dot_matches_new_line)
+            .build()?;
+        Ok(Self {
+            regular_expression_string,
+            flags,
+            regular_expression,
+        })
+    }
+
+    fn as_regex_str(&self) -> RegexStr<'_> {
+        RegexStr(&self.regular_expression_string, self.flags)
+    }
+}
 
 impl TryFrom<String> for RegularExpression {
     type Error = regex::Error;
 
     fn try_from(regular_expression_string: String) -> Result<Self, Self::Error> {
-        let regular_expression = regex::Regex::new(&regular_expression_string)?;
-        Ok(Self {
-            regular_expression_string,
-            regular_expression,
-        })
+        Self::with_flags(regular_expression_string, MatchFlags::default())
     }
 }
 
 impl Hash for RegularExpression {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        RegexStr(&self.regular_expression_string).hash(state)
+        self.as_regex_str().hash(state)
     }
 }
 
 impl PartialEq for RegularExpression {
     fn eq(&self, other: &Self) -> bool {
-        RegexStr(&self.regular_expression_string) == RegexStr(&other.regular_expression_string)
+        self.as_regex_str() == other.as_regex_str()
     }
 }
 
@@ -74,14 +127,13 @@ impl Eq for RegularExpression {}
 
 impl PartialOrd<Self> for RegularExpression {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        RegexStr(&self.regular_expression_string)
-            .partial_cmp(&RegexStr(&other.regular_expression_string))
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for RegularExpression {
     fn cmp(&self, other: &Self) -> Ordering {
-        RegexStr(&self.regular_expression_string).cmp(&RegexStr(&other.regular_expression_string))
+        self.as_regex_str().cmp(&other.as_regex_str())
     }
 }
 
@@ -119,27 +171,81 @@ impl MatchTransform {
     }
 }
 
-#[cfg(test)]
 impl MatchTransform {
+    /// Creates a match transform, checking that every capture group referenced by
+    /// `match_transform_expressions` exists in `match_regular_expression`.
     pub fn new(
         match_regular_expression: RegularExpression,
         match_transform_expressions: Vec<String>,
-    ) -> Self {
-        Self {
+    ) -> anyhow::Result<Self> {
+        for match_transform_expression in match_transform_expressions.iter() {
+            validate_transform_expression(
+                &match_regular_expression.regular_expression,
+                match_transform_expression,
+            )?;
+        }
+        Ok(Self {
             match_regular_expression,
             match_transform_expressions,
+        })
+    }
+}
+
+/// Checks that each capture group referenced in `expression` exists in `regular_expression`. The
+/// `regex` crate replaces references to unknown groups with empty strings, which would turn a typo
+/// in a group name into a silently wrong path.
+fn validate_transform_expression(
+    regular_expression: &Regex,
+    expression: &str,
+) -> anyhow::Result<()> {
+    let is_name_character = |character: char| character == '_' || character.is_ascii_alphanumeric();
+    let mut remaining = expression;
+    while let Some(dollar_index) = remaining.find('$') {
+        let after_dollar = &remaining[dollar_index + 1..];
+        let (name, rest) = if let Some(rest) = after_dollar.strip_prefix('$') {
+            remaining = rest;
+            continue;
+        } else if let Some(braced) = after_dollar.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => ("", braced),
+            }
+        } else {
+            let end = after_dollar
+                .find(|character| !is_name_character(character))
+                .unwrap_or(after_dollar.len());
+            (&after_dollar[..end], &after_dollar[end..])
+        };
+        remaining = rest;
+        if name.is_empty() {
+            continue;
+        }
+        let exists = match name.parse::<usize>() {
+            Ok(index) => index < regular_expression.captures_len(),
+            Err(_) => regular_expression
+                .capture_names()
+                .any(|capture_name| capture_name == Some(name)),
+        };
+        if !exists {
+            anyhow::bail!(
+                "transform expression, {:?}, refers to capture group, {:?}, that does not exist in regular expression, {:?}",
+                expression,
+                name,
+                regular_expression.as_str()
+            );
         }
     }
+    Ok(())
 }
 
 impl TryFrom<MatchTransformTransport> for MatchTransform {
-    type Error = regex::Error;
+    type Error = anyhow::Error;
 
     fn try_from(transport: MatchTransformTransport) -> Result<Self, Self::Error> {
-        Ok(Self {
-            match_regular_expression: transport.match_regular_expression.try_into()?,
-            match_transform_expressions: transport.match_transform_expressions,
-        })
+        Self::new(
+            RegularExpression::with_flags(transport.match_regular_expression, transport.flags)?,
+            transport.match_transform_expressions,
+        )
     }
 }
 
@@ -150,43 +256,83 @@ impl IntoTransport for MatchTransform {
         Self::Transport {
             match_regular_expression: self.match_regular_expression.regular_expression_string,
             match_transform_expressions: self.match_transform_expressions,
+            flags: self.match_regular_expression.flags,
         }
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct Listing<Identity: IdentityBound> {
-    entries: HashSet<Identity>,
+    entries: HashMap<Identity, ListingTimes>,
 }
 
 impl<Identity: IdentityBound> Listing<Identity> {
     pub fn empty() -> Self {
         Self {
-            entries: HashSet::new(),
+            entries: HashMap::new(),
         }
     }
 
     pub fn put(&mut self, identity: Identity) -> bool {
-        if self.entries.contains(&identity) {
-            false
-        } else {
-            self.entries.insert(identity);
-            true
+        match self.entries.entry(identity) {
+            std::collections::hash_map::Entry::Occupied(_) => false,
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                entry.insert(ListingTimes::default());
+                true
+            }
+        }
+    }
+
+    /// Puts `identity`, recording `timestamp_nanos` as its insertion time if it is new, and as its
+    /// last-access time either way.
+    pub fn put_at(&mut self, identity: Identity, timestamp_nanos: i64) -> bool {
+        let inserted = self.put(identity.clone());
+        let times = self.entries.get_mut(&identity).expect("entry just put");
+        if inserted {
+            times.inserted_nanos = Some(timestamp_nanos);
+        }
+        times.last_accessed_nanos = Some(timestamp_nanos);
+        inserted
+    }
+
+    /// Records `timestamp_nanos` as the last-access time of `identity`, if it is listed.
+    pub fn touch(&mut self, identity: &Identity, timestamp_nanos: i64) -> bool {
+        match self.entries.get_mut(identity) {
+            Some(times) => {
+                times.last_accessed_nanos = Some(timestamp_nanos);
+                true
+            }
+            None => false,
         }
     }
 
     pub fn remove(&mut self, identity: &Identity) -> bool {
-        self.entries.remove(identity)
+        self.entries.remove(identity).is_some()
+    }
+
+    pub fn contains(&self, identity: &Identity) -> bool {
+        self.entries.contains_key(identity)
+    }
+
+    pub fn times(&self, identity: &Identity) -> Option<&ListingTimes> {
+        self.entries.get(identity)
+    }
+
+    /// Gets entries and their times, in no particular order.
+    pub fn entries(&self) -> impl Iterator<Item = (&Identity, &ListingTimes)> {
+        self.entries.iter()
     }
 }
 
-#[cfg(test)]
 impl<Identity: IdentityBound> Listing<Identity> {
     pub fn new<I: Iterator<Item = Identity>, II: IntoIterator<Item = Identity, IntoIter = I>>(
         entries: II,
     ) -> Self {
         Self {
-            entries: entries.into_iter().collect(),
+            entries: entries
+                .into_iter()
+                .map(|identity| (identity, ListingTimes::default()))
+                .collect(),
         }
     }
 }
@@ -196,15 +342,27 @@ impl<Identity: IdentityBound> IntoTransport for Listing<Identity> {
 
     fn into_transport(self) -> Self::Transport {
         let mut entries: Vec<_> = self.entries.into_iter().collect();
-        entries.sort();
-        Self::Transport { entries }
+        entries.sort_by(|(identity1, _), (identity2, _)| identity1.cmp(identity2));
+        let times = if entries
+            .iter()
+            .all(|(_, times)| *times == ListingTimes::default())
+        {
+            vec![]
+        } else {
+            entries.iter().map(|(_, times)| *times).collect()
+        };
+        Self::Transport {
+            format_version: FormatVersion::default(),
+            entries: entries.into_iter().map(|(identity, _)| identity).collect(),
+            times,
+        }
     }
 }
 
 impl<Identity: IdentityBound> Default for Listing<Identity> {
     fn default() -> Self {
         Self {
-            entries: HashSet::new(),
+            entries: HashMap::new(),
         }
     }
 }
@@ -225,14 +383,25 @@ impl<Identity: IdentityBound> TryFrom<ListingTransport<Identity>> for Listing<Id
                 )),
             );
         }
-        let deduped: HashSet<_> = sorted.clone().into_iter().collect();
-        let deduped: Vec<_> = deduped.into_iter().collect();
+        let mut deduped = sorted.clone();
+        deduped.dedup();
         if sorted != deduped {
             return Err(anyhow::anyhow!("listing contains duplicates").context(
                 diff_items_to_string("sorted vs. sorted+deduped", &sorted, &deduped),
             ));
         }
-        let entries: HashSet<_> = sorted.clone().into_iter().collect();
+        if !transport.times.is_empty() && transport.times.len() != sorted.len() {
+            anyhow::bail!(
+                "listing has {} entries, but times for {} entries",
+                sorted.len(),
+                transport.times.len()
+            );
+        }
+        let times = transport
+            .times
+            .into_iter()
+            .chain(std::iter::repeat(ListingTimes::default()));
+        let entries: HashMap<_, _> = sorted.into_iter().zip(times).collect();
         Ok(Self { entries })
     }
 }
@@ -249,21 +418,140 @@ impl<Identity: IdentityBound> TryFrom<&ListingTransport<Identity>> for Listing<I
 #[derive(Clone, Debug, PartialEq)]
 pub struct Outputs {
     include_files: HashSet<PathBuf>,
+    optional_files: HashSet<PathBuf>,
     include_match_transforms: HashSet<Vec<MatchTransform>>,
+    include_globs: HashSet<String>,
+    /// Output directories, and the exclude globs of each.
+    include_directories: BTreeMap<PathBuf, BTreeSet<String>>,
     exclude_matches: HashSet<RegularExpression>,
+    max_file_size_bytes: Option<u64>,
+    exclude_file_types: BTreeSet<FileType>,
+    stdout_file: Option<PathBuf>,
+    stderr_file: Option<PathBuf>,
+    verification: OutputsVerification,
 }
 
 impl Outputs {
     pub fn empty() -> Self {
         Self {
             include_files: HashSet::new(),
+            optional_files: HashSet::new(),
             include_match_transforms: HashSet::new(),
+            include_globs: HashSet::new(),
+            include_directories: BTreeMap::new(),
             exclude_matches: HashSet::new(),
+            max_file_size_bytes: None,
+            exclude_file_types: BTreeSet::new(),
+            stdout_file: None,
+            stderr_file: None,
+            verification: OutputsVerification::default(),
         }
     }
+
+    /// Adds `include_globs`, which are evaluated against the working directory after the task has
+    /// run.
+    pub fn with_include_globs<S: Into<String>, I: IntoIterator<Item = S>>(
+        mut self,
+        include_globs: I,
+    ) -> Self {
+        self.include_globs
+            .extend(include_globs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Adds `directory`, all of whose files, less those matching `exclude_globs`, are collected
+    /// after the task has run.
+    pub fn with_include_directory<P: AsRef<Path>, S: Into<String>, I: IntoIterator<Item = S>>(
+        mut self,
+        directory: P,
+        exclude_globs: I,
+    ) -> Self {
+        self.include_directories
+            .entry(directory.as_ref().to_path_buf())
+            .or_default()
+            .extend(exclude_globs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Skips collecting outputs larger than `max_file_size_bytes`.
+    pub fn with_max_file_size_bytes(mut self, max_file_size_bytes: u64) -> Self {
+        self.max_file_size_bytes = Some(max_file_size_bytes);
+        self
+    }
+
+    /// Skips collecting outputs whose file type is in `exclude_file_types`.
+    pub fn with_exclude_file_types<I: IntoIterator<Item = FileType>>(
+        mut self,
+        exclude_file_types: I,
+    ) -> Self {
+        self.exclude_file_types.extend(exclude_file_types);
+        self
+    }
+
+    /// Adds `optional_files`, which the task may or may not produce.
+    pub fn with_optional_files<P: AsRef<Path>, I: IntoIterator<Item = P>>(
+        mut self,
+        optional_files: I,
+    ) -> Self {
+        self.optional_files.extend(
+            optional_files
+                .into_iter()
+                .map(|path| path.as_ref().to_path_buf()),
+        );
+        self
+    }
+
+    /// Determines whether `path` is an output that the task may or may not produce.
+    pub fn is_optional(&self, path: &Path) -> bool {
+        self.optional_files.contains(path)
+    }
+
+    /// Sets the policy for checking produced outputs against this description.
+    pub fn with_verification(mut self, verification: OutputsVerification) -> Self {
+        self.verification = verification;
+        self
+    }
+
+    pub fn verification(&self) -> OutputsVerification {
+        self.verification
+    }
+
+    /// Determines whether `path` may be missing after the task has run.
+    pub fn may_be_missing(&self, path: &Path) -> bool {
+        self.verification.allows_missing_optional() && self.is_optional(path)
+    }
+
+    /// Captures the task's stdout as the output file `stdout_file`.
+    pub fn with_stdout_file<P: AsRef<Path>>(mut self, stdout_file: P) -> Self {
+        self.stdout_file = Some(stdout_file.as_ref().to_path_buf());
+        self
+    }
+
+    /// Captures the task's stderr as the output file `stderr_file`.
+    pub fn with_stderr_file<P: AsRef<Path>>(mut self, stderr_file: P) -> Self {
+        self.stderr_file = Some(stderr_file.as_ref().to_path_buf());
+        self
+    }
+
+    /// Path of the output file to which the task's stdout is written, if any.
+    pub fn stdout_file(&self) -> Option<&Path> {
+        self.stdout_file.as_deref()
+    }
+
+    /// Path of the output file to which the task's stderr is written, if any.
+    pub fn stderr_file(&self) -> Option<&Path> {
+        self.stderr_file.as_deref()
+    }
+
+    /// Determines whether an output with the given metadata should be left uncollected.
+    pub fn excludes_metadata(&self, metadata: &FileMetadata) -> bool {
+        self.exclude_file_types.contains(&metadata.file_type)
+            || self
+                .max_file_size_bytes
+                .is_some_and(|max_file_size_bytes| metadata.size > max_file_size_bytes)
+    }
 }
 
-#[cfg(test)]
 impl Outputs {
     pub fn new<
         P: AsRef<Path>,
@@ -285,11 +573,19 @@ impl Outputs {
                 .into_iter()
                 .map(|path| path.as_ref().to_path_buf())
                 .collect(),
+            optional_files: HashSet::new(),
             include_match_transforms: include_match_transforms
                 .into_iter()
                 .map(|into_iter| into_iter.into_iter().collect())
                 .collect(),
+            include_globs: HashSet::new(),
+            include_directories: BTreeMap::new(),
             exclude_matches: exclude_matches.into_iter().collect(),
+            max_file_size_bytes: None,
+            exclude_file_types: BTreeSet::new(),
+            stdout_file: None,
+            stderr_file: None,
+            verification: OutputsVerification::default(),
         }
     }
 
@@ -318,6 +614,18 @@ impl TryFrom<OutputsTransport> for Outputs {
             include_files.insert(include_file);
         }
 
+        let mut optional_files = HashSet::new();
+        for optional_file in transport.optional_files.into_iter() {
+            if include_files.contains(&optional_file) || optional_files.contains(&optional_file) {
+                anyhow::bail!(
+                    "optional path, {:?}, appears twice in output files description",
+                    optional_file
+                );
+            }
+
+            optional_files.insert(optional_file);
+        }
+
         let mut include_match_transforms = HashSet::new();
         for include_match_transform_series in transport.include_match_transforms.into_iter() {
             let match_transform_series = include_match_transform_series
@@ -334,6 +642,53 @@ impl TryFrom<OutputsTransport> for Outputs {
             include_match_transforms.insert(match_transform_series);
         }
 
+        let mut include_globs = HashSet::new();
+        for include_glob in transport.include_globs.into_iter() {
+            glob::Pattern::new(&include_glob).with_context(|| {
+                format!(
+                    "malformed include glob, {:?}, in output files description",
+                    include_glob
+                )
+            })?;
+            if include_globs.contains(&include_glob) {
+                anyhow::bail!(
+                    "include glob, {:?}, appears twice in output files description",
+                    include_glob
+                );
+            }
+
+            include_globs.insert(include_glob);
+        }
+
+        let mut include_directories = BTreeMap::new();
+        for include_directory in transport.include_directories.into_iter() {
+            if !include_directory.directory.is_relative() {
+                anyhow::bail!(
+                    "include directory, {:?}, in output files description is not a relative path",
+                    include_directory.directory
+                );
+            }
+            let mut exclude_globs = BTreeSet::new();
+            for exclude_glob in include_directory.exclude_globs.into_iter() {
+                glob::Pattern::new(&exclude_glob).with_context(|| {
+                    format!(
+                        "malformed exclude glob, {:?}, for include directory {:?} in output files description",
+                        exclude_glob, include_directory.directory
+                    )
+                })?;
+                exclude_globs.insert(exclude_glob);
+            }
+            if include_directories
+                .insert(include_directory.directory.clone(), exclude_globs)
+                .is_some()
+            {
+                anyhow::bail!(
+                    "include directory, {:?}, appears twice in output files description",
+                    include_directory.directory
+                );
+            }
+        }
+
         let mut exclude_matches = HashSet::new();
         for exclude_match in transport.exclude_matches.into_iter() {
             let exclude_match: RegularExpression = exclude_match.try_into()?;
@@ -347,10 +702,60 @@ impl TryFrom<OutputsTransport> for Outputs {
             exclude_matches.insert(exclude_match);
         }
 
+        let mut exclude_file_types = BTreeSet::new();
+        for exclude_file_type in transport.exclude_file_types.into_iter() {
+            if !exclude_file_types.insert(exclude_file_type) {
+                anyhow::bail!(
+                    "exclude file type, {:?}, appears twice in output files description",
+                    exclude_file_type
+                );
+            }
+        }
+
+        for (stream, stream_file) in [
+            ("stdout", &transport.stdout_file),
+            ("stderr", &transport.stderr_file),
+        ] {
+            if let Some(stream_file) = stream_file {
+                if !stream_file.is_relative() {
+                    anyhow::bail!(
+                        "{} file, {:?}, in output files description is not a relative path",
+                        stream,
+                        stream_file
+                    );
+                }
+                if include_files.contains(stream_file) {
+                    anyhow::bail!(
+                        "{} file, {:?}, also appears as include path in output files description",
+                        stream,
+                        stream_file
+                    );
+                }
+            }
+        }
+        if let (Some(stdout_file), Some(stderr_file)) =
+            (&transport.stdout_file, &transport.stderr_file)
+        {
+            if stdout_file == stderr_file {
+                anyhow::bail!(
+                    "stdout and stderr both written to {:?} in output files description",
+                    stdout_file
+                );
+            }
+        }
+
         Ok(Self {
             include_files,
+            optional_files,
             include_match_transforms,
+            include_globs,
+            include_directories,
             exclude_matches,
+            max_file_size_bytes: transport.max_file_size_bytes,
+            exclude_file_types,
+            stdout_file: transport.stdout_file,
+            stderr_file: transport.stderr_file,
+            verification: transport.verification,
         })
     }
 }
@@ -361,6 +766,8 @@ impl IntoTransport for Outputs {
     fn into_transport(self) -> Self::Transport {
         let mut include_files: Vec<_> = self.include_files.into_iter().collect();
         include_files.sort();
+        let mut optional_files: Vec<_> = self.optional_files.into_iter().collect();
+        optional_files.sort();
         let mut include_match_transforms: Vec<_> = self
             .include_match_transforms
             .into_iter()
@@ -372,6 +779,8 @@ impl IntoTransport for Outputs {
             })
             .collect();
         include_match_transforms.sort();
+        let mut include_globs: Vec<_> = self.include_globs.into_iter().collect();
+        include_globs.sort();
         let mut exclude_matches: Vec<_> = self
             .exclude_matches
             .into_iter()
@@ -380,8 +789,23 @@ impl IntoTransport for Outputs {
         exclude_matches.sort();
         Self::Transport {
             include_files,
+            optional_files,
             include_match_transforms,
+            include_globs,
+            include_directories: self
+                .include_directories
+                .into_iter()
+                .map(|(directory, exclude_globs)| OutputsDirectoryTransport {
+                    directory,
+                    exclude_globs: exclude_globs.into_iter().collect(),
+                })
+                .collect(),
             exclude_matches,
+            max_file_size_bytes: self.max_file_size_bytes,
+            exclude_file_types: self.exclude_file_types.into_iter().collect(),
+            stdout_file: self.stdout_file,
+            stderr_file: self.stderr_file,
+            verification: self.verification,
         }
     }
 }
@@ -399,41 +823,115 @@ impl FilesManifest {
     pub fn into_identified<IS: IdentitySchemeApi, FS: FilesystemApi>(
         self,
         filesystem: &mut FS,
+    ) -> FileIdentitiesManifest<IS> {
+        self.into_identified_with_symlink_identity(filesystem, SymlinkIdentity::default())
+    }
+
+    /// Identifies each path, recording paths that cannot be identified as having no identity.
+    /// Dangling symbolic links are only identifiable under `SymlinkIdentity::TargetPath`; under
+    /// other policies they are recorded as having no identity, and a warning is logged.
+    pub fn into_identified_with_symlink_identity<IS: IdentitySchemeApi, FS: FilesystemApi>(
+        self,
+        filesystem: &mut FS,
+        symlink_identity: SymlinkIdentity,
     ) -> FileIdentitiesManifest<IS> {
         FileIdentitiesManifest {
             identity_scheme: IS::IDENTITY_SCHEME,
             identities: self
-                .paths
+                .identify_with_status::<IS, FS>(filesystem, symlink_identity)
                 .into_iter()
-                .map(|path| match IS::identify_file(filesystem, &path) {
-                    Ok(identity) => (path, Some(identity)),
-                    Err(_) => (path, None),
+                .map(|(path, status)| match status {
+                    FileIdentityStatus::Identified(identity) => (path, Some(identity)),
+                    status => {
+                        if filesystem.is_symlink(&path) {
+                            tracing::warn!(
+                                "recording symbolic link without identity: {path:?}: {status:?}",
+                                path = path,
+                                status = status
+                            );
+                        }
+                        (path, None)
+                    }
                 })
                 .collect(),
+            symlinks: BTreeMap::new(),
+        }
+        .with_symlink_targets(filesystem)
+    }
+
+    /// Identifies each path, reporting how identification failed for each path that could not be
+    /// identified.
+    pub fn identify_with_status<IS: IdentitySchemeApi, FS: FilesystemApi>(
+        &self,
+        filesystem: &mut FS,
+        symlink_identity: SymlinkIdentity,
+    ) -> Vec<(PathBuf, FileIdentityStatus<IS::Identity>)> {
+        self.paths
+            .iter()
+            .map(|path| {
+                let status =
+                    FileIdentityStatus::identify::<IS, FS, _>(filesystem, path, symlink_identity);
+                (path.clone(), status)
+            })
+            .collect()
+    }
+
+    /// Identifies each path, recording absent files as having no identity but failing on files
+    /// that exist and cannot be identified (e.g., due to permissions).
+    pub fn try_into_identified_allowing_absent<IS: IdentitySchemeApi, FS: FilesystemApi>(
+        self,
+        filesystem: &mut FS,
+        symlink_identity: SymlinkIdentity,
+    ) -> anyhow::Result<FileIdentitiesManifest<IS>> {
+        let identities = self
+            .identify_with_status::<IS, FS>(filesystem, symlink_identity)
+            .into_iter()
+            .map(|(path, status)| {
+                let identity = status.into_optional_identity(&path)?;
+                Ok((path, identity))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(FileIdentitiesManifest {
+            identity_scheme: IS::IDENTITY_SCHEME,
+            identities,
+            symlinks: BTreeMap::new(),
         }
+        .with_symlink_targets(filesystem))
     }
 
     pub fn try_into_identified<IS: IdentitySchemeApi, FS: FilesystemApi>(
         self,
         filesystem: &mut FS,
+    ) -> anyhow::Result<FileIdentitiesManifest<IS>> {
+        self.try_into_identified_with_symlink_identity(filesystem, SymlinkIdentity::default())
+    }
+
+    pub fn try_into_identified_with_symlink_identity<IS: IdentitySchemeApi, FS: FilesystemApi>(
+        self,
+        filesystem: &mut FS,
+        symlink_identity: SymlinkIdentity,
     ) -> anyhow::Result<FileIdentitiesManifest<IS>> {
         let identities = self
             .paths
             .into_iter()
-            .map(|path| match IS::identify_file(filesystem, &path) {
-                Ok(identity) => Ok((path, Some(identity))),
-                Err(error) => Err(error),
-            })
+            .map(
+                |path| match IS::identify_path(filesystem, &path, symlink_identity) {
+                    Ok(identity) => Ok((path, Some(identity))),
+                    Err(error) => Err(error),
+                },
+            )
             .collect::<Result<_, _>>()?;
         Ok(FileIdentitiesManifest {
             identity_scheme: IS::IDENTITY_SCHEME,
             identities,
-        })
+            symlinks: BTreeMap::new(),
+        }
+        .with_symlink_targets(filesystem))
     }
 }
 
-#[cfg(test)]
 impl FilesManifest {
+    /// Creates a manifest of `paths`, sorted into canonical order.
     pub fn new<P: AsRef<Path>, I: Iterator<Item = P>, II: IntoIterator<Item = P, IntoIter = I>>(
         paths: II,
     ) -> Self {
@@ -477,7 +975,15 @@ impl<FS: FilesystemApi> TryFrom<(&mut FS, InputsTransport)> for FilesManifest {
 }
 
 fn surely_includes_none(inputs_config: &InputsTransport) -> bool {
-    if inputs_config.include_files.len() > 0 || inputs_config.include_globs.len() > 0 {
+    if inputs_config.include_files.len() > 0
+        || inputs_config.include_globs.len() > 0
+        || !inputs_config.depfiles.is_empty()
+        || inputs_config
+            .groups
+            .iter()
+            .any(|group| !group.include_files.is_empty() || !group.include_globs.is_empty())
+        || !inputs_config.include_directories.is_empty()
+    {
         return false;
     }
 
@@ -492,10 +998,179 @@ fn surely_includes_none(inputs_config: &InputsTransport) -> bool {
     true
 }
 
+/// Regular expressions compiled during a single manifest resolution, keyed by pattern string, so
+/// that each distinct pattern is compiled once regardless of how many inter-file references
+/// configurations or fixed-point iterations use it.
+#[derive(Default)]
+struct RegularExpressionCache {
+    regular_expressions: HashMap<(String, MatchFlags), RegularExpression>,
+}
+
+impl RegularExpressionCache {
+    fn get_or_compile(
+        &mut self,
+        pattern: &str,
+        flags: MatchFlags,
+    ) -> Result<RegularExpression, regex::Error> {
+        let key = (pattern.to_string(), flags);
+        if let Some(regular_expression) = self.regular_expressions.get(&key) {
+            return Ok(regular_expression.clone());
+        }
+        let regular_expression = RegularExpression::with_flags(pattern.to_string(), flags)?;
+        self.regular_expressions
+            .insert(key, regular_expression.clone());
+        Ok(regular_expression)
+    }
+
+    fn match_transforms(
+        &mut self,
+        match_transforms: &[MatchTransformTransport],
+    ) -> anyhow::Result<Vec<MatchTransform>> {
+        match_transforms
+            .iter()
+            .map(|match_transform| {
+                MatchTransform::new(
+                    self.get_or_compile(
+                        &match_transform.match_regular_expression,
+                        match_transform.flags,
+                    )?,
+                    match_transform.match_transform_expressions.clone(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Gets the set of files that match include/exclude patterns in `group`, resolved against its base
+/// directory.
+fn get_matching_group_files<FS: FilesystemApi>(
+    filesystem: &mut FS,
+    group: &InputsGroupTransport,
+) -> anyhow::Result<HashSet<PathBuf>> {
+    let base = group.relative_to.clone().unwrap_or_default();
+    let escaped_base = glob::Pattern::escape(base.to_str().ok_or_else(|| {
+        anyhow::anyhow!(
+            "inputs group base directory, {:?}, cannot be encoded as a string",
+            base
+        )
+    })?);
+    let mut glob_paths = |globs: &[String]| -> anyhow::Result<HashSet<PathBuf>> {
+        let mut paths = HashSet::new();
+        for glob in globs.iter() {
+            let glob = Path::new(&escaped_base).join(glob);
+            let glob = glob.to_str().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "inputs group glob, {:?}, cannot be encoded as a string",
+                    glob
+                )
+            })?;
+            for path_result in filesystem.execute_glob(glob)? {
+                paths.insert(
+                    path_result
+                        .map_err(anyhow::Error::from)
+                        .with_context(|| format!("executing inputs group glob, {:?}", glob))?,
+                );
+            }
+        }
+        Ok(paths)
+    };
+
+    let mut files: HashSet<PathBuf> = glob_paths(&group.include_globs)?;
+    files.extend(group.include_files.iter().map(|path| base.join(path)));
+    for path in glob_paths(&group.exclude_globs)? {
+        files.remove(&path);
+    }
+    for path in group.exclude_files.iter() {
+        files.remove(&base.join(path));
+    }
+    Ok(files)
+}
+
+/// Gets the files under `directory` that pass the filters of an `InputsDirectory` or
+/// `OutputsDirectory`. Subdirectories that match an exclude pattern are not traversed.
+fn get_matching_directory_files<FS: FilesystemApi>(
+    filesystem: &mut FS,
+    directory: &Path,
+    include_globs: &[String],
+    exclude_globs: &[String],
+) -> anyhow::Result<HashSet<PathBuf>> {
+    let compile = |globs: &[String]| {
+        globs
+            .iter()
+            .map(|glob| {
+                glob::Pattern::new(glob)
+                    .with_context(|| format!("parsing directory glob, {:?}", glob))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+    };
+    let include_patterns = compile(include_globs)?;
+    let exclude_patterns = compile(exclude_globs)?;
+    let match_options = glob::MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    };
+    let matches_any = |patterns: &[glob::Pattern], path: &Path| {
+        patterns
+            .iter()
+            .any(|pattern| pattern.matches_path_with(path, match_options))
+    };
+
+    let mut files = HashSet::new();
+    let mut pending_directories = vec![PathBuf::new()];
+    while let Some(relative_directory) = pending_directories.pop() {
+        let absolute_directory = directory.join(&relative_directory);
+        let entries = filesystem
+            .read_directory(&absolute_directory)
+            .map_err(anyhow::Error::from)
+            .with_context(|| format!("reading directory {:?}", absolute_directory))?;
+        for (name, file_type) in entries {
+            let relative_path = relative_directory.join(name);
+            if matches_any(&exclude_patterns, &relative_path) {
+                continue;
+            }
+            let path = directory.join(&relative_path);
+            match file_type {
+                FileType::Directory => pending_directories.push(relative_path),
+                FileType::File | FileType::Symlink => {
+                    if file_type == FileType::Symlink
+                        && filesystem
+                            .metadata(&path)
+                            .map(|metadata| metadata.file_type == FileType::Directory)
+                            .unwrap_or(false)
+                    {
+                        continue;
+                    }
+                    if include_patterns.is_empty() || matches_any(&include_patterns, &relative_path)
+                    {
+                        files.insert(path);
+                    }
+                }
+                _ => {
+                    tracing::debug!("skipping {:?} of type {:?} in directory", path, file_type);
+                }
+            }
+        }
+    }
+    Ok(files)
+}
+
 /// Gets the set of files that match include/exclude pattern matching in `inputs_config`.
 fn get_matching_input_files<FS: FilesystemApi>(
     filesystem: &mut FS,
     inputs_config: &InputsTransport,
+) -> anyhow::Result<HashSet<PathBuf>> {
+    get_matching_input_files_with_cache(
+        filesystem,
+        inputs_config,
+        &mut RegularExpressionCache::default(),
+    )
+}
+
+fn get_matching_input_files_with_cache<FS: FilesystemApi>(
+    filesystem: &mut FS,
+    inputs_config: &InputsTransport,
+    regular_expression_cache: &mut RegularExpressionCache,
 ) -> anyhow::Result<HashSet<PathBuf>> {
     let mut files: HashSet<PathBuf> = inputs_config
         .include_files
@@ -516,6 +1191,22 @@ fn get_matching_input_files<FS: FilesystemApi>(
             }
         }
     }
+    for include_directory in inputs_config.include_directories.iter() {
+        files.extend(
+            get_matching_directory_files(
+                filesystem,
+                &include_directory.directory,
+                &include_directory.include_globs,
+                &include_directory.exclude_globs,
+            )
+            .with_context(|| {
+                format!(
+                    "matching files in inputs directory {:?}",
+                    include_directory.directory
+                )
+            })?,
+        );
+    }
     for exclude_glob in inputs_config.exclude_globs.iter() {
         let exclude_path_results = filesystem.execute_glob(&exclude_glob)?;
         for exclude_path_result in exclude_path_results {
@@ -537,99 +1228,416 @@ fn get_matching_input_files<FS: FilesystemApi>(
             files.remove(file);
         }
     }
+    for group in inputs_config.groups.iter() {
+        files.extend(
+            get_matching_group_files(filesystem, group).with_context(|| {
+                format!("matching inputs group relative to {:?}", group.relative_to)
+            })?,
+        );
+    }
+    for depfile in inputs_config.depfiles.iter() {
+        let mut contents = String::new();
+        match filesystem.open_file_for_read(depfile) {
+            Ok(mut depfile_file) => {
+                depfile_file
+                    .read_to_string(&mut contents)
+                    .with_context(|| format!("reading depfile {:?}", depfile))?;
+            }
+            Err(_) => {
+                tracing::debug!("skipping missing depfile {:?}", depfile);
+                continue;
+            }
+        }
+        for prerequisite in parse_depfile_prerequisites(&contents)
+            .with_context(|| format!("parsing depfile {:?}", depfile))?
+        {
+            if filesystem.file_exists(&prerequisite)
+                && !is_shallowly_excluded(filesystem, inputs_config, &prerequisite)?
+            {
+                files.insert(prerequisite);
+            }
+        }
+    }
+
+    // Keep matching files until no additional files are found. Each file is scanned at most once
+    // per inter-file references configuration, and the chain of references that led to each file
+    // is recorded for diagnostics.
+    let max_reference_depth = inputs_config
+        .max_reference_depth
+        .unwrap_or(DEFAULT_MAX_REFERENCE_DEPTH);
+    let max_scan_threads = inputs_config.max_scan_threads.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(NonZeroUsize::get)
+            .unwrap_or(1)
+    });
+    let mut referrers: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut scanned: HashSet<(usize, PathBuf)> = HashSet::new();
+
+    // Prepare regular expressions and their sets of transforms once for all iterations.
+    let match_transforms_by_config = inputs_config
+        .inter_file_references
+        .iter()
+        .map(|inter_file_references_config| {
+            regular_expression_cache
+                .match_transforms(&inter_file_references_config.match_transforms)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Compile scanner plugins once for all iterations. Each plugin module is itself an input, so
+    // that changing a plugin changes the identity of the inputs it scanned.
+    let mut scanner_plugins_by_config = vec![];
+    for inter_file_references_config in inputs_config.inter_file_references.iter() {
+        let scanner_plugin = match &inter_file_references_config.scanner_plugin {
+            Some(plugin_config) => {
+                files.insert(plugin_config.module.clone());
+                Some(load_scanner_plugin(filesystem, plugin_config)?)
+            }
+            None => None,
+        };
+        scanner_plugins_by_config.push(scanner_plugin);
+    }
 
-    // Keep matching files until no additional files are found.
-    let mut prev_num_files = files.len();
-    let mut num_files = prev_num_files + 1;
-    while prev_num_files < num_files {
-        for inter_file_references_config in inputs_config.inter_file_references.iter() {
+    loop {
+        let prev_num_files = files.len();
+        for (config_index, (inter_file_references_config, match_transforms)) in inputs_config
+            .inter_file_references
+            .iter()
+            .zip(match_transforms_by_config.iter())
+            .enumerate()
+        {
             // Match against either declared set of files or else initial set of files(before inter-file
             // processing.
             let matching_files = match &inter_file_references_config.files_to_match {
-                Some(declared_matching_files) => Cow::Owned(get_matching_input_files(
+                Some(declared_matching_files) => Cow::Owned(get_matching_input_files_with_cache(
                     filesystem,
                     declared_matching_files,
+                    regular_expression_cache,
                 )?),
                 None => Cow::Borrowed(&files),
             };
 
-            // Prepare regular expressions and their sets of transforms.
-            let match_transforms = inter_file_references_config
-                .match_transforms
-                .clone()
-                .into_iter()
-                .map(MatchTransform::try_from)
-                .collect::<Result<Vec<_>, _>>()?;
+            // Read, in a deterministic order, each input whose contents should be matched to find
+            // new inputs and that has not already been scanned under this configuration.
+            let mut files_to_scan: Vec<&PathBuf> = matching_files
+                .iter()
+                .filter(|matching_file| {
+                    !scanned.contains(&(config_index, (*matching_file).clone()))
+                })
+                .collect();
+            files_to_scan.sort();
+            let mut files_and_contents = vec![];
+            for matching_file in files_to_scan {
+                scanned.insert((config_index, matching_file.clone()));
+                let mut contents = vec![];
+                filesystem
+                    .open_file_for_read(matching_file)?
+                    .read_to_end(&mut contents)
+                    .with_context(|| {
+                        format!("reading {:?} to scan for references", matching_file)
+                    })?;
+                if !inter_file_references_config.scan_binary_files && is_binary_content(&contents) {
+                    tracing::debug!("skipping binary file {:?}", matching_file);
+                    continue;
+                }
+                let contents = String::from_utf8_lossy(&contents).into_owned();
+                files_and_contents.push((matching_file.clone(), contents));
+            }
 
-            // For all inputs whose contents should be matched to find new inputs...
+            // Scan contents in parallel, then resolve references against the filesystem in the
+            // order that files were read.
+            let scanned_references = scan_references_in_parallel(
+                &files_and_contents,
+                match_transforms,
+                inter_file_references_config.c_includes.is_some(),
+                max_scan_threads,
+            );
             let mut matched_files = HashSet::new();
-            for matching_file in matching_files.iter() {
-                // Read each line.
-                let reader = BufReader::new(filesystem.open_file_for_read(matching_file)?);
-                for line_result in reader.lines() {
-                    // Give up if reading fails at any point.
-                    let line = line_result?;
-
-                    // Attempt to find-replace each bound regex/transformer pair.
-                    for MatchTransform {
-                        match_regular_expression,
-                        match_transform_expressions,
-                    } in match_transforms.iter()
+            for ((matching_file, contents), scanned_references) in
+                files_and_contents.iter().zip(scanned_references)
+            {
+                let mut transformed_paths = scanned_references.transformed_paths;
+                if let Some(scanner_plugin) = &scanner_plugins_by_config[config_index] {
+                    transformed_paths.extend(
+                        scanner_plugin
+                            .scan(matching_file, contents.as_bytes())
+                            .with_context(|| {
+                                format!("scanning {:?} with scanner plugin", matching_file)
+                            })?,
+                    );
+                }
+                let mut referenced_files = resolve_transformed_references(
+                    filesystem,
+                    inputs_config,
+                    inter_file_references_config,
+                    &transformed_paths,
+                )?;
+                if let Some(c_includes) = &inter_file_references_config.c_includes {
+                    referenced_files.extend(resolve_c_include_references(
+                        filesystem,
+                        inputs_config,
+                        inter_file_references_config,
+                        c_includes,
+                        matching_file,
+                        &scanned_references.includes,
+                    )?);
+                }
+
+                for referenced_file in referenced_files {
+                    if let Some(cycle) =
+                        find_reference_cycle(&referrers, matching_file, &referenced_file)
                     {
-                        let regular_expression = &match_regular_expression.regular_expression;
-                        for matched_text in regular_expression.find_iter(&line) {
-                            // Matched regex; store each transform bound to this regex.
-                            for transform in match_transform_expressions.iter() {
-                                let matched_file =
-                                    regular_expression.replace(matched_text.as_str(), transform);
-                                let matched_path = PathBuf::from(matched_file.into_owned());
-                                // Find actual file path that exists for pattern.
-                                match &inter_file_references_config.directories_to_search {
-                                    Some(directories) => {
-                                        for directory in directories.iter() {
-                                            let full_matched_path = directory.join(&matched_path);
-                                            if filesystem.file_exists(&full_matched_path)
-                                                && !is_shallowly_excluded(
-                                                    filesystem,
-                                                    inputs_config,
-                                                    &full_matched_path,
-                                                )?
-                                            {
-                                                matched_files.insert(full_matched_path);
-                                                break;
-                                            }
-                                        }
-                                    }
-                                    None => {
-                                        // Use matched path directly when no "directories to search"
-                                        // are provided.
-                                        if filesystem.file_exists(&matched_path)
-                                            && !is_shallowly_excluded(
-                                                filesystem,
-                                                inputs_config,
-                                                &matched_path,
-                                            )?
-                                        {
-                                            matched_files.insert(matched_path);
-                                        }
-                                    }
-                                }
-                            }
+                        let cycle = format_reference_chain(&cycle);
+                        if inputs_config.fail_on_reference_cycles {
+                            anyhow::bail!("inter-file references form a cycle: {}", cycle);
                         }
+                        tracing::warn!("inter-file references form a cycle: {}", cycle);
+                    }
+                    if files.contains(&referenced_file) || matched_files.contains(&referenced_file)
+                    {
+                        continue;
+                    }
+
+                    let mut chain = reference_chain(&referrers, matching_file);
+                    chain.push(referenced_file.clone());
+                    if chain.len() - 1 > max_reference_depth {
+                        anyhow::bail!(
+                            "inter-file references exceed maximum depth of {}: {}",
+                            max_reference_depth,
+                            format_reference_chain(&chain)
+                        );
                     }
+                    referrers.insert(referenced_file.clone(), matching_file.clone());
+                    matched_files.insert(referenced_file);
                 }
             }
 
             files.extend(matched_files.into_iter());
         }
 
-        prev_num_files = num_files;
-        num_files = files.len();
+        if files.len() == prev_num_files {
+            break;
+        }
     }
 
     Ok(files)
 }
 
+/// Default for `Inputs::max_reference_depth`.
+pub const DEFAULT_MAX_REFERENCE_DEPTH: usize = 64;
+
+/// Returns the chain of references from a file that was not discovered via references to `path`.
+fn reference_chain(referrers: &HashMap<PathBuf, PathBuf>, path: &Path) -> Vec<PathBuf> {
+    let mut chain = vec![path.to_path_buf()];
+    while let Some(referrer) = referrers.get(chain.last().expect("non-empty chain")) {
+        if chain.contains(referrer) {
+            break;
+        }
+        chain.push(referrer.clone());
+    }
+    chain.reverse();
+    chain
+}
+
+/// Returns the cycle formed by a reference from `referring_file` to `referenced_file`, if
+/// `referenced_file` is `referring_file` or one of the files through which `referring_file` was
+/// discovered.
+fn find_reference_cycle(
+    referrers: &HashMap<PathBuf, PathBuf>,
+    referring_file: &Path,
+    referenced_file: &Path,
+) -> Option<Vec<PathBuf>> {
+    let chain = reference_chain(referrers, referring_file);
+    let start = chain.iter().position(|path| path == referenced_file)?;
+    let mut cycle = chain[start..].to_vec();
+    cycle.push(referenced_file.to_path_buf());
+    Some(cycle)
+}
+
+fn format_reference_chain(chain: &[PathBuf]) -> String {
+    chain
+        .iter()
+        .map(|path| format!("{:?}", path))
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+/// Number of leading bytes inspected by `is_binary_content`.
+pub const BINARY_SNIFF_SIZE_BYTES: usize = 8 << 10;
+
+/// Treats content as binary when a NUL byte appears near its start, the same heuristic used by
+/// `git` and `grep`.
+fn is_binary_content(contents: &[u8]) -> bool {
+    contents
+        .iter()
+        .take(BINARY_SNIFF_SIZE_BYTES)
+        .any(|byte| *byte == 0)
+}
+
+/// References found in the contents of one file, before they are resolved against the filesystem.
+#[derive(Debug, Default, PartialEq)]
+struct ScannedReferences {
+    /// Paths produced by applying match transforms to matched text.
+    transformed_paths: Vec<PathBuf>,
+    /// C/C++ `#include` directives.
+    includes: Vec<Include>,
+}
+
+/// Finds references in `contents` according to `match_transforms` and, when `scan_c_includes` is
+/// set, C/C++ `#include` directives.
+fn scan_references(
+    match_transforms: &[MatchTransform],
+    scan_c_includes: bool,
+    contents: &str,
+) -> ScannedReferences {
+    let mut transformed_paths = vec![];
+    if !match_transforms.is_empty() {
+        for line in contents.lines() {
+            // Attempt to find-replace each bound regex/transformer pair.
+            for MatchTransform {
+                match_regular_expression,
+                match_transform_expressions,
+            } in match_transforms.iter()
+            {
+                let regular_expression = &match_regular_expression.regular_expression;
+                for matched_text in regular_expression.find_iter(line) {
+                    // Matched regex; store each transform bound to this regex.
+                    for transform in match_transform_expressions.iter() {
+                        let matched_file =
+                            regular_expression.replace(matched_text.as_str(), transform);
+                        transformed_paths.push(PathBuf::from(matched_file.into_owned()));
+                    }
+                }
+            }
+        }
+    }
+    let includes = if scan_c_includes {
+        scan_includes(contents)
+    } else {
+        vec![]
+    };
+    ScannedReferences {
+        transformed_paths,
+        includes,
+    }
+}
+
+/// Applies `scan_references` to each of `files_and_contents` using at most `max_threads` worker
+/// threads. Results are returned in the same order as `files_and_contents`.
+fn scan_references_in_parallel(
+    files_and_contents: &[(PathBuf, String)],
+    match_transforms: &[MatchTransform],
+    scan_c_includes: bool,
+    max_threads: usize,
+) -> Vec<ScannedReferences> {
+    let scan = |(_, contents): &(PathBuf, String)| {
+        scan_references(match_transforms, scan_c_includes, contents)
+    };
+    let num_threads = max_threads.min(files_and_contents.len());
+    if num_threads <= 1 {
+        return files_and_contents.iter().map(scan).collect();
+    }
+
+    let chunk_size = files_and_contents.len().div_ceil(num_threads);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = files_and_contents
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(scan).collect::<Vec<_>>()))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("join reference scanning thread"))
+            .collect()
+    })
+}
+
+/// Reads and compiles the scanner plugin described by `plugin_config`.
+fn load_scanner_plugin<FS: FilesystemApi>(
+    filesystem: &mut FS,
+    plugin_config: &ScannerPluginTransport,
+) -> anyhow::Result<ScannerPlugin> {
+    let mut wasm = vec![];
+    filesystem
+        .open_file_for_read(&plugin_config.module)
+        .with_context(|| format!("opening scanner plugin {:?}", plugin_config.module))?
+        .read_to_end(&mut wasm)
+        .with_context(|| format!("reading scanner plugin {:?}", plugin_config.module))?;
+    ScannerPlugin::load(plugin_config, &wasm)
+        .with_context(|| format!("loading scanner plugin {:?}", plugin_config.module))
+}
+
+/// Resolves `transformed_paths` found in a file to existing, non-excluded files.
+fn resolve_transformed_references<FS: FilesystemApi>(
+    filesystem: &mut FS,
+    inputs_config: &InputsTransport,
+    inter_file_references_config: &InterFileReferencesTransport,
+    transformed_paths: &[PathBuf],
+) -> anyhow::Result<BTreeSet<PathBuf>> {
+    let mut matched_files = BTreeSet::new();
+    for matched_path in transformed_paths.iter() {
+        // Find actual file path that exists for pattern.
+        match &inter_file_references_config.directories_to_search {
+            Some(directories) => {
+                for directory in directories.iter() {
+                    let full_matched_path = directory.join(matched_path);
+                    if filesystem.file_exists(&full_matched_path)
+                        && !is_shallowly_excluded(filesystem, inputs_config, &full_matched_path)?
+                    {
+                        matched_files.insert(full_matched_path);
+                        break;
+                    }
+                }
+            }
+            None => {
+                // Use matched path directly when no "directories to search" are provided.
+                if filesystem.file_exists(matched_path)
+                    && !is_shallowly_excluded(filesystem, inputs_config, matched_path)?
+                {
+                    matched_files.insert(matched_path.clone());
+                }
+            }
+        }
+    }
+    Ok(matched_files)
+}
+
+/// Resolves `includes` found in `matching_file` to existing, non-excluded files, as described by
+/// `c_includes`.
+fn resolve_c_include_references<FS: FilesystemApi>(
+    filesystem: &mut FS,
+    inputs_config: &InputsTransport,
+    inter_file_references_config: &InterFileReferencesTransport,
+    c_includes: &CIncludesTransport,
+    matching_file: &Path,
+    includes: &[Include],
+) -> anyhow::Result<BTreeSet<PathBuf>> {
+    let including_directory = matching_file.parent().unwrap_or_else(|| Path::new(""));
+    let directories_to_search = inter_file_references_config
+        .directories_to_search
+        .iter()
+        .flatten();
+
+    let mut matched_files = BTreeSet::new();
+    for include in includes.iter() {
+        let mut directories: Vec<&Path> = vec![];
+        if include.kind == IncludeKind::Quote {
+            directories.push(including_directory);
+            directories.extend(c_includes.quote_directories.iter().map(PathBuf::as_path));
+        }
+        directories.extend(c_includes.include_directories.iter().map(PathBuf::as_path));
+        directories.extend(directories_to_search.clone().map(PathBuf::as_path));
+        for directory in directories {
+            let included_path = normalize_path(directory.join(&include.path));
+            if filesystem.file_exists(&included_path) {
+                if !is_shallowly_excluded(filesystem, inputs_config, &included_path)? {
+                    matched_files.insert(included_path);
+                }
+                break;
+            }
+        }
+    }
+    Ok(matched_files)
+}
+
 /// Performs all non-recursive pattern matching from `inputs_config` against `path`. This function
 /// is used to ensure that files added by inspecting file contents are skipped when they should be
 /// categorically excluded.
@@ -770,6 +1778,8 @@ impl TryFrom<(&FilesManifest, &OutputsTransport)> for FilesManifest {
 pub struct FileIdentitiesManifest<IS: IdentitySchemeApi> {
     identity_scheme: IdentityScheme,
     identities: Vec<(PathBuf, Option<IS::Identity>)>,
+    /// Targets of the listed paths that are symbolic links, as stored in the links.
+    symlinks: BTreeMap<PathBuf, PathBuf>,
 }
 
 impl<IS: IdentitySchemeApi> FileIdentitiesManifest<IS> {
@@ -777,12 +1787,199 @@ impl<IS: IdentitySchemeApi> FileIdentitiesManifest<IS> {
         Self {
             identity_scheme: IS::IDENTITY_SCHEME,
             identities: vec![],
+            symlinks: BTreeMap::new(),
         }
     }
 
     pub fn identities(&self) -> impl Iterator<Item = &(PathBuf, Option<IS::Identity>)> {
         self.identities.iter()
     }
+
+    /// Listed paths that are symbolic links, and their targets, sorted by path.
+    pub fn symlinks(&self) -> impl Iterator<Item = (&PathBuf, &PathBuf)> {
+        self.symlinks.iter()
+    }
+
+    /// Target of `path`, when it was a symbolic link when identified.
+    pub fn symlink_target<P: AsRef<Path>>(&self, path: P) -> Option<&PathBuf> {
+        self.symlinks.get(path.as_ref())
+    }
+
+    /// Records the target of each listed path that is a symbolic link in `filesystem`.
+    fn with_symlink_targets<FS: FilesystemApi>(mut self, filesystem: &mut FS) -> Self {
+        for (path, _) in self.identities.iter() {
+            if !filesystem.is_symlink(path) {
+                continue;
+            }
+            match filesystem.read_link(path) {
+                Ok(target) => {
+                    self.symlinks.insert(path.clone(), target);
+                }
+                Err(error) => {
+                    tracing::warn!("reading symbolic link {:?}: {}", path, error);
+                }
+            }
+        }
+        self
+    }
+
+    /// Resolves the input files described by `inputs_config`, identifies them, and checks them
+    /// against the description's expected identities.
+    pub fn try_from_inputs<FS: FilesystemApi>(
+        filesystem: &mut FS,
+        inputs_config: &InputsTransport,
+    ) -> anyhow::Result<Self> {
+        let _span = profile::span(Phase::ManifestResolution, || {
+            String::from("identify input files")
+        });
+        let manifest = FilesManifest::try_from((&mut *filesystem, inputs_config))?
+            .into_identified::<IS, FS>(filesystem);
+        manifest.check_expected_identities(&inputs_config.expected_identities)?;
+        Ok(manifest)
+    }
+
+    /// Checks that each file in `expected_identities` is listed with the expected identity,
+    /// reporting every file that differs.
+    pub fn check_expected_identities(
+        &self,
+        expected_identities: &BTreeMap<PathBuf, String>,
+    ) -> anyhow::Result<()> {
+        let identities: HashMap<&PathBuf, &Option<IS::Identity>> = self
+            .identities
+            .iter()
+            .map(|(path, identity)| (path, identity))
+            .collect();
+        let mut differences = vec![];
+        for (path, expected_identity) in expected_identities.iter() {
+            match identities.get(path) {
+                None => differences.push(format!(
+                    "{:?}: expected {}, but file is not an input",
+                    path, expected_identity
+                )),
+                Some(None) => differences.push(format!(
+                    "{:?}: expected {}, but file could not be identified",
+                    path, expected_identity
+                )),
+                Some(Some(identity)) if identity.to_string() != *expected_identity => differences
+                    .push(format!(
+                        "{:?}: expected {}, but found {}",
+                        path,
+                        expected_identity,
+                        identity.to_string()
+                    )),
+                Some(Some(_)) => {}
+            }
+        }
+        if !differences.is_empty() {
+            anyhow::bail!(
+                "input files do not have expected identities:\n  {}",
+                differences.join("\n  ")
+            );
+        }
+        Ok(())
+    }
+}
+
+impl<IS: IdentitySchemeApi> FileIdentitiesManifest<IS> {
+    /// Computes the changes from `parent` to `self`, for storage relative to the blob identified by
+    /// `parent_identity`.
+    pub fn delta_from(
+        &self,
+        parent: &Self,
+        parent_identity: IS::Identity,
+    ) -> FileIdentitiesManifestDeltaTransport<IS> {
+        let parent_identities: HashMap<&PathBuf, &Option<IS::Identity>> = parent
+            .identities
+            .iter()
+            .map(|(path, identity)| (path, identity))
+            .collect();
+        let mut added = vec![];
+        let mut changed = vec![];
+        for (path, identity) in self.identities.iter() {
+            match parent_identities.get(path) {
+                None => added.push((path.clone(), identity.clone())),
+                Some(parent_identity) if *parent_identity != identity => {
+                    changed.push((path.clone(), identity.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        let paths: HashSet<&PathBuf> = self.identities.iter().map(|(path, _)| path).collect();
+        let removed = parent
+            .identities
+            .iter()
+            .filter(|(path, _)| !paths.contains(path))
+            .map(|(path, _)| path.clone())
+            .collect();
+        FileIdentitiesManifestDeltaTransport {
+            format_version: FormatVersion::default(),
+            identity_scheme: IS::IDENTITY_SCHEME,
+            parent: Some(parent_identity),
+            added,
+            removed,
+            changed,
+            symlinks: self.symlinks.clone(),
+        }
+    }
+
+    /// Represents `self` as a delta from the empty manifest.
+    pub fn as_root_delta(&self) -> FileIdentitiesManifestDeltaTransport<IS> {
+        FileIdentitiesManifestDeltaTransport {
+            format_version: FormatVersion::default(),
+            identity_scheme: IS::IDENTITY_SCHEME,
+            parent: None,
+            added: self.identities.clone(),
+            removed: vec![],
+            changed: vec![],
+            symlinks: self.symlinks.clone(),
+        }
+    }
+
+    /// Applies `delta` to `parent`, which must be the manifest that `delta` was computed from.
+    pub fn apply_delta(
+        parent: Self,
+        delta: FileIdentitiesManifestDeltaTransport<IS>,
+    ) -> anyhow::Result<Self> {
+        if delta.identity_scheme != IS::IDENTITY_SCHEME {
+            anyhow::bail!(
+                "manifest delta has identity scheme {:?}, but expected {:?}",
+                delta.identity_scheme,
+                IS::IDENTITY_SCHEME
+            );
+        }
+        let mut identities: BTreeMap<PathBuf, Option<IS::Identity>> =
+            parent.identities.into_iter().collect();
+        for path in delta.removed {
+            if identities.remove(&path).is_none() {
+                anyhow::bail!(
+                    "manifest delta removes path, {:?}, absent from parent",
+                    path
+                );
+            }
+        }
+        for (path, identity) in delta.changed {
+            match identities.get_mut(&path) {
+                Some(parent_identity) => *parent_identity = identity,
+                None => {
+                    anyhow::bail!(
+                        "manifest delta changes path, {:?}, absent from parent",
+                        path
+                    )
+                }
+            }
+        }
+        for (path, identity) in delta.added {
+            if identities.contains_key(&path) {
+                anyhow::bail!("manifest delta adds path, {:?}, present in parent", path);
+            }
+            identities.insert(path, identity);
+        }
+        Ok(Self {
+            identity_scheme: IS::IDENTITY_SCHEME,
+            identities: identities.into_iter().collect(),
+            symlinks: delta.symlinks,
+        })
+    }
 }
 
 impl<IS: IdentitySchemeApi> IntoTransport for FileIdentitiesManifest<IS> {
@@ -792,12 +1989,13 @@ impl<IS: IdentitySchemeApi> IntoTransport for FileIdentitiesManifest<IS> {
         Self::Transport {
             identity_scheme: self.identity_scheme,
             identities: self.identities,
+            symlinks: self.symlinks,
         }
     }
 }
 
-#[cfg(test)]
 impl<IS: IdentitySchemeApi> FileIdentitiesManifest<IS> {
+    /// Creates a manifest of `identities`, sorted by path into canonical order.
     pub fn new<
         P: AsRef<Path>,
         I: Iterator<Item = (P, Option<IS::Identity>)>,
@@ -813,6 +2011,7 @@ impl<IS: IdentitySchemeApi> FileIdentitiesManifest<IS> {
         Self {
             identity_scheme: IS::IDENTITY_SCHEME,
             identities,
+            symlinks: BTreeMap::new(),
         }
     }
 }
@@ -842,6 +2041,7 @@ impl<IS: IdentitySchemeApi> TryFrom<FileIdentitiesManifestTransport<IS>>
         Ok(FileIdentitiesManifest {
             identity_scheme: transport.identity_scheme,
             identities: transport.identities,
+            symlinks: transport.symlinks,
         })
     }
 }
@@ -857,6 +2057,123 @@ impl<IS: IdentitySchemeApi> TryFrom<&FileIdentitiesManifestTransport<IS>>
     }
 }
 
+/// Identities of the content-defined chunks of a blob (see `crate::chunk::Chunker`). Comparing
+/// chunk identities against those available elsewhere allows a sync to transfer only the chunks
+/// that the other side is missing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkManifest<IS: IdentitySchemeApi> {
+    identity_scheme: IdentityScheme,
+    chunks: Vec<ChunkTransport<IS>>,
+}
+
+impl<IS: IdentitySchemeApi> ChunkManifest<IS> {
+    /// Splits `content` into chunks and identifies each chunk, passing each chunk and its identity
+    /// to `visit_chunk` as it is identified.
+    pub fn try_from_content_with_visitor<
+        R: Read,
+        F: FnMut(&[u8], &IS::Identity) -> anyhow::Result<()>,
+    >(
+        content: R,
+        mut visit_chunk: F,
+    ) -> anyhow::Result<Self> {
+        let mut chunks = vec![];
+        let mut offset = 0;
+        for chunk in Chunker::new(content) {
+            let chunk = chunk.context("reading content to chunk")?;
+            let identity = IS::identify_content(chunk.as_slice()).context("identifying chunk")?;
+            visit_chunk(&chunk, &identity)?;
+            let length = chunk.len() as u64;
+            chunks.push(ChunkTransport {
+                offset,
+                length,
+                identity,
+            });
+            offset += length;
+        }
+        Ok(Self {
+            identity_scheme: IS::IDENTITY_SCHEME,
+            chunks,
+        })
+    }
+
+    pub fn try_from_content<R: Read>(content: R) -> anyhow::Result<Self> {
+        Self::try_from_content_with_visitor(content, |_, _| Ok(()))
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = &ChunkTransport<IS>> {
+        self.chunks.iter()
+    }
+
+    pub fn length(&self) -> u64 {
+        self.chunks
+            .last()
+            .map(|chunk| chunk.offset + chunk.length)
+            .unwrap_or(0)
+    }
+
+    /// Chunks whose identities do not satisfy `is_available`, in offset order, without duplicates.
+    pub fn missing_chunks<F: FnMut(&IS::Identity) -> bool>(
+        &self,
+        mut is_available: F,
+    ) -> Vec<&ChunkTransport<IS>> {
+        let mut seen = HashSet::new();
+        self.chunks
+            .iter()
+            .filter(|chunk| {
+                seen.insert(chunk.identity.to_string()) && !is_available(&chunk.identity)
+            })
+            .collect()
+    }
+}
+
+impl<IS: IdentitySchemeApi> IntoTransport for ChunkManifest<IS> {
+    type Transport = ChunkManifestTransport<IS>;
+
+    fn into_transport(self) -> Self::Transport {
+        Self::Transport {
+            format_version: FormatVersion::default(),
+            identity_scheme: self.identity_scheme,
+            chunks: self.chunks,
+        }
+    }
+}
+
+impl<IS: IdentitySchemeApi> TryFrom<ChunkManifestTransport<IS>> for ChunkManifest<IS> {
+    type Error = anyhow::Error;
+
+    fn try_from(transport: ChunkManifestTransport<IS>) -> Result<Self, anyhow::Error> {
+        if transport.identity_scheme != IS::IDENTITY_SCHEME {
+            anyhow::bail!(
+                "attempted to load chunk manifest with identity scheme {}, expected {}",
+                transport.identity_scheme,
+                IS::IDENTITY_SCHEME
+            );
+        }
+        let mut offset = 0;
+        for chunk in transport.chunks.iter() {
+            if chunk.offset != offset {
+                anyhow::bail!(
+                    "attempted to load chunk manifest with chunk at offset {}, expected offset {}",
+                    chunk.offset,
+                    offset
+                );
+            }
+            if chunk.length == 0 {
+                anyhow::bail!(
+                    "attempted to load chunk manifest with empty chunk at offset {}",
+                    chunk.offset
+                );
+            }
+            offset += chunk.length;
+        }
+
+        Ok(Self {
+            identity_scheme: transport.identity_scheme,
+            chunks: transport.chunks,
+        })
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct EnvironmentVariables {
     pub environment_variables: Vec<(String, String)>,
@@ -878,14 +2195,13 @@ impl EnvironmentVariables {
     pub fn try_from_config(
         mut environment_variables: EnvironmentVariablesTransport,
     ) -> Result<Self, anyhow::Error> {
+        check_inheritance_resolved(&environment_variables)?;
         environment_variables
             .environment_variables
             .sort_by(|(key1, _), (key2, _)| key1.cmp(key2));
         let environment_variables = environment_variables.environment_variables;
-        let deduped_environment_variables: HashSet<_> =
-            environment_variables.clone().into_iter().collect();
-        let deduped_environment_variables: Vec<_> =
-            deduped_environment_variables.into_iter().collect();
+        let mut deduped_environment_variables = environment_variables.clone();
+        deduped_environment_variables.dedup();
         if environment_variables != deduped_environment_variables {
             return Err(
                 anyhow::anyhow!("environment variables configuration contains duplicates").context(
@@ -914,6 +2230,7 @@ impl EnvironmentVariables {
     pub fn try_from_manifest(
         mut environment_variables: EnvironmentVariablesTransport,
     ) -> Result<Self, anyhow::Error> {
+        check_inheritance_resolved(&environment_variables)?;
         let input_environment_variables = environment_variables.environment_variables.clone();
         environment_variables
             .environment_variables
@@ -930,10 +2247,8 @@ impl EnvironmentVariables {
                 ),
             );
         }
-        let deduped_environment_variables: HashSet<_> =
-            sorted_environment_variables.clone().into_iter().collect();
-        let deduped_environment_variables: Vec<_> =
-            deduped_environment_variables.into_iter().collect();
+        let mut deduped_environment_variables = sorted_environment_variables.clone();
+        deduped_environment_variables.dedup();
         if sorted_environment_variables != deduped_environment_variables {
             return Err(
                 anyhow::anyhow!("environment variables manifest contains duplicates").context(
@@ -960,6 +2275,7 @@ impl EnvironmentVariables {
     pub fn into_manifest(self) -> EnvironmentVariablesTransport {
         EnvironmentVariablesTransport {
             environment_variables: self.environment_variables,
+            inherit: EnvironmentInheritance::None,
         }
     }
 
@@ -969,8 +2285,22 @@ impl EnvironmentVariables {
     }
 }
 
-#[cfg(test)]
+/// Canonical environment variables are fully determined, so any inheritance from the host must
+/// already have been resolved (see `TemplateContext::resolve_environment_variables`).
+fn check_inheritance_resolved(
+    environment_variables: &EnvironmentVariablesTransport,
+) -> anyhow::Result<()> {
+    if !environment_variables.inherit.is_none() {
+        anyhow::bail!(
+            "environment variable inheritance, {:?}, has not been resolved against a host environment",
+            environment_variables.inherit
+        );
+    }
+    Ok(())
+}
+
 impl EnvironmentVariables {
+    /// Creates a set of environment variables, sorted by name into canonical order.
     pub fn new<
         KeyString,
         ValueString,
@@ -982,11 +2312,13 @@ impl EnvironmentVariables {
     where
         String: From<KeyString> + From<ValueString>,
     {
+        let mut environment_variables: Vec<_> = environment_variables
+            .into_iter()
+            .map(|(key, value)| (String::from(key), String::from(value)))
+            .collect();
+        environment_variables.sort_by(|(key1, _), (key2, _)| key1.cmp(key2));
         Self {
-            environment_variables: environment_variables
-                .into_iter()
-                .map(|(key, value)| (String::from(key), String::from(value)))
-                .collect(),
+            environment_variables,
         }
     }
 }
@@ -997,6 +2329,7 @@ impl IntoTransport for EnvironmentVariables {
     fn into_transport(self) -> Self::Transport {
         Self::Transport {
             environment_variables: self.environment_variables,
+            inherit: EnvironmentInheritance::None,
         }
     }
 }
@@ -1012,7 +2345,6 @@ impl Program {
     }
 }
 
-#[cfg(test)]
 impl Program {
     pub fn new<P: AsRef<Path>>(program: P) -> Self {
         Self {
@@ -1058,19 +2390,214 @@ impl IntoTransport for Program {
 #[derive(Clone, Debug, PartialEq)]
 pub struct Arguments {
     arguments: Vec<String>,
+    argv0: Option<String>,
 }
 
 impl Arguments {
     pub fn empty() -> Self {
-        Self { arguments: vec![] }
+        Self {
+            arguments: vec![],
+            argv0: None,
+        }
     }
 
     pub fn arguments(&self) -> impl Iterator<Item = &String> {
         self.arguments.iter()
     }
+
+    /// Overrides the value the program receives as `argv[0]`, which otherwise is the program
+    /// path. Multipurpose binaries (e.g., busybox) select their behaviour based on this value.
+    pub fn with_argv0<S>(mut self, argv0: S) -> Self
+    where
+        String: From<S>,
+    {
+        self.argv0 = Some(String::from(argv0));
+        self
+    }
+
+    pub fn argv0(&self) -> Option<&String> {
+        self.argv0.as_ref()
+    }
+
+    /// Splits `command_line` into arguments according to POSIX shell quoting rules.
+    ///
+    /// Only quoting is interpreted: single quotes, double quotes, and backslash escapes. Unquoted
+    /// characters that would make a shell do more than split words (e.g., `$`, `|`, `;`, `*`) are
+    /// rejected rather than passed through literally, so that a command line copied from a shell
+    /// script either means the same thing here or fails to parse.
+    pub fn from_shell_words(command_line: &str) -> anyhow::Result<Self> {
+        Ok(Self::new(split_shell_words(command_line)?))
+    }
+
+    /// Formats these arguments as a single string that `from_shell_words` (or a POSIX shell)
+    /// splits back into the same arguments.
+    pub fn to_shell_words(&self) -> String {
+        self.arguments
+            .iter()
+            .map(|argument| shell_quote(argument))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Placeholder for input file paths; see `TaskInputs::with_expanded_argument_placeholders`.
+pub const INPUTS_ARGUMENT_PLACEHOLDER: &str = "inputs";
+
+/// Placeholder for output file paths; see `TaskInputs::with_expanded_argument_placeholders`.
+pub const OUTPUTS_ARGUMENT_PLACEHOLDER: &str = "outputs";
+
+/// Prefix of the argument placeholder modifier that sets the separator between joined paths.
+const SEPARATOR_MODIFIER_PREFIX: &str = "sep=";
+
+fn expand_argument_placeholders(
+    arguments: &[String],
+    input_paths: &[String],
+    output_paths: &[String],
+) -> anyhow::Result<Vec<String>> {
+    let placeholder_paths = |name: &str| match name {
+        INPUTS_ARGUMENT_PLACEHOLDER => Some(input_paths),
+        OUTPUTS_ARGUMENT_PLACEHOLDER => Some(output_paths),
+        _ => None,
+    };
+    let mut expanded_arguments = Vec::with_capacity(arguments.len());
+    for argument in arguments {
+        if let Some(paths) = argument
+            .strip_prefix('{')
+            .and_then(|argument| argument.strip_suffix('}'))
+            .and_then(placeholder_paths)
+        {
+            expanded_arguments.extend(paths.iter().cloned());
+            continue;
+        }
+
+        let mut expanded = String::with_capacity(argument.len());
+        let mut remaining = argument.as_str();
+        while let Some(open_index) = remaining.find('{') {
+            expanded.push_str(&remaining[..open_index]);
+            let after_open = &remaining[open_index + 1..];
+            let placeholder = match after_open.find('}') {
+                Some(close_index) => &after_open[..close_index],
+                None => "",
+            };
+            let (name, modifier) = match placeholder.split_once(':') {
+                Some((name, modifier)) => (name, Some(modifier)),
+                None => (placeholder, None),
+            };
+            let Some(paths) = placeholder_paths(name) else {
+                expanded.push('{');
+                remaining = after_open;
+                continue;
+            };
+            let separator = match modifier {
+                Some(modifier) => modifier
+                    .strip_prefix(SEPARATOR_MODIFIER_PREFIX)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "unknown modifier, {:?}, in argument placeholder, {:?}",
+                            modifier,
+                            placeholder
+                        )
+                    })?,
+                None => " ",
+            };
+            expanded.push_str(&paths.join(separator));
+            remaining = &after_open[placeholder.len() + 1..];
+        }
+        expanded.push_str(remaining);
+        expanded_arguments.push(expanded);
+    }
+    Ok(expanded_arguments)
+}
+
+/// Characters that have special meaning to a POSIX shell when unquoted, beyond word splitting.
+const SHELL_METACHARACTERS: &[char] = &['|', '&', ';', '<', '>', '(', ')', '$', '`', '*', '?'];
+
+/// Quotes `argument` so that a POSIX shell interprets it as a single, literal word.
+pub fn shell_quote(argument: &str) -> String {
+    let is_plain =
+        |character: char| character.is_ascii_alphanumeric() || "-_./=:,+@%".contains(character);
+    if !argument.is_empty() && argument.chars().all(is_plain) {
+        return String::from(argument);
+    }
+    format!("'{}'", argument.replace('\'', r"'\''"))
+}
+
+fn split_shell_words(command_line: &str) -> anyhow::Result<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut characters = command_line.chars();
+    while let Some(character) = characters.next() {
+        match character {
+            ' ' | '\t' | '\n' => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match characters.next() {
+                        Some('\'') => break,
+                        Some(character) => word.push(character),
+                        None => anyhow::bail!(
+                            "unterminated single quote in command line, {:?}",
+                            command_line
+                        ),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match characters.next() {
+                        Some('"') => break,
+                        Some('\\') => match characters.next() {
+                            Some(escaped @ ('"' | '\\' | '$' | '`')) => word.push(escaped),
+                            Some('\n') => {}
+                            Some(other) => {
+                                word.push('\\');
+                                word.push(other);
+                            }
+                            None => anyhow::bail!(
+                                "unterminated double quote in command line, {:?}",
+                                command_line
+                            ),
+                        },
+                        Some(character @ ('$' | '`')) => anyhow::bail!(
+                            "unescaped {:?} in double quotes would be expanded by a shell in command line, {:?}",
+                            character,
+                            command_line
+                        ),
+                        Some(character) => word.push(character),
+                        None => anyhow::bail!(
+                            "unterminated double quote in command line, {:?}",
+                            command_line
+                        ),
+                    }
+                }
+            }
+            '\\' => match characters.next() {
+                Some('\n') => {}
+                Some(escaped) => word.get_or_insert_with(String::new).push(escaped),
+                None => anyhow::bail!("trailing backslash in command line, {:?}", command_line),
+            },
+            '#' if word.is_none() => {
+                anyhow::bail!("unquoted comment in command line, {:?}", command_line)
+            }
+            character if SHELL_METACHARACTERS.contains(&character) => anyhow::bail!(
+                "unquoted shell metacharacter, {:?}, in command line, {:?}",
+                character,
+                command_line
+            ),
+            character => word.get_or_insert_with(String::new).push(character),
+        }
+    }
+    if let Some(word) = word {
+        words.push(word);
+    }
+    Ok(words)
 }
 
-#[cfg(test)]
 impl Arguments {
     pub fn new<S, I: Iterator<Item = S>, II: IntoIterator<Item = S, IntoIter = I>>(
         arguments: II,
@@ -1080,6 +2607,7 @@ impl Arguments {
     {
         Self {
             arguments: arguments.into_iter().map(String::from).collect(),
+            argv0: None,
         }
     }
 }
@@ -1088,6 +2616,7 @@ impl From<ArgumentsTransport> for Arguments {
     fn from(transport: ArgumentsTransport) -> Self {
         Self {
             arguments: transport.arguments,
+            argv0: transport.argv0,
         }
     }
 }
@@ -1104,6 +2633,7 @@ impl From<&Arguments> for ArgumentsTransport {
         let arguments: Arguments = arguments.clone();
         Self {
             arguments: arguments.arguments,
+            argv0: arguments.argv0,
         }
     }
 }
@@ -1114,6 +2644,7 @@ impl IntoTransport for Arguments {
     fn into_transport(self) -> Self::Transport {
         Self::Transport {
             arguments: self.arguments,
+            argv0: self.argv0,
         }
     }
 }
@@ -1123,6 +2654,11 @@ pub struct Metadata {
     timestamp_nanos: i64,
     execution_duration_nanos: u128,
     system: System,
+    exit_code: Option<i32>,
+    peak_memory_bytes: Option<u64>,
+    hostname: Option<String>,
+    username: Option<String>,
+    labels: TaskLabels,
 }
 
 impl Metadata {
@@ -1131,8 +2667,65 @@ impl Metadata {
             timestamp_nanos,
             execution_duration_nanos,
             system,
+            exit_code: None,
+            peak_memory_bytes: None,
+            hostname: None,
+            username: None,
+            labels: TaskLabels::default(),
         }
     }
+
+    /// Records the exit code and peak memory use of the task's run.
+    pub fn with_execution_result(mut self, execution_result: &ExecutionResult) -> Self {
+        self.exit_code = execution_result.exit_code();
+        self.peak_memory_bytes = execution_result.peak_memory_bytes;
+        self
+    }
+
+    /// Records the machine and user that ran the task.
+    pub fn with_host(mut self, hostname: Option<String>, username: Option<String>) -> Self {
+        self.hostname = hostname;
+        self.username = username;
+        self
+    }
+
+    pub fn timestamp_nanos(&self) -> i64 {
+        self.timestamp_nanos
+    }
+
+    pub fn execution_duration_nanos(&self) -> u128 {
+        self.execution_duration_nanos
+    }
+
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    pub fn peak_memory_bytes(&self) -> Option<u64> {
+        self.peak_memory_bytes
+    }
+
+    pub fn system(&self) -> &System {
+        &self.system
+    }
+
+    pub fn hostname(&self) -> Option<&str> {
+        self.hostname.as_deref()
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    /// Records the label and tags that the task was described with.
+    pub fn with_labels(mut self, labels: TaskLabels) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    pub fn labels(&self) -> &TaskLabels {
+        &self.labels
+    }
 }
 
 impl From<MetadataTransport> for Metadata {
@@ -1141,6 +2734,11 @@ impl From<MetadataTransport> for Metadata {
             timestamp_nanos: transport.timestamp_nanos,
             execution_duration_nanos: transport.execution_duration_nanos,
             system: transport.system.into(),
+            exit_code: transport.exit_code,
+            peak_memory_bytes: transport.peak_memory_bytes,
+            hostname: transport.hostname,
+            username: transport.username,
+            labels: TaskLabels::new(transport.label, transport.tags),
         }
     }
 }
@@ -1150,32 +2748,127 @@ impl IntoTransport for Metadata {
 
     fn into_transport(self) -> Self::Transport {
         Self::Transport {
+            format_version: FormatVersion::default(),
             timestamp_nanos: self.timestamp_nanos,
             execution_duration_nanos: self.execution_duration_nanos,
             system: self.system.into_transport(),
+            exit_code: self.exit_code,
+            peak_memory_bytes: self.peak_memory_bytes,
+            hostname: self.hostname,
+            username: self.username,
+            label: self.labels.label,
+            tags: self.labels.tags,
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct System {
-    name: Option<String>,
-    long_os_version: Option<String>,
-    kernel_version: Option<String>,
-    distribution_id: String,
-    total_memory: u64,
-    estimated_num_cpu_cores: usize,
+/// Label and tags of a task. These describe the task for humans and tools querying a cache, and
+/// are only recorded in `Metadata`; they never contribute to cache keys.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TaskLabels {
+    label: Option<String>,
+    tags: Vec<String>,
+}
+
+impl TaskLabels {
+    /// Creates labels with `tags` in sorted order, without duplicates.
+    pub fn new<S: Into<String>, I: IntoIterator<Item = S>>(label: Option<String>, tags: I) -> Self {
+        let mut tags: Vec<String> = tags.into_iter().map(Into::into).collect();
+        tags.sort();
+        tags.dedup();
+        Self { label, tags }
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    pub fn tags(&self) -> impl Iterator<Item = &String> {
+        self.tags.iter()
+    }
+
+    /// Determines whether these labels satisfy `query`: they have the query's label, if any, and
+    /// every one of its tags.
+    pub fn matches(&self, query: &TaskLabels) -> bool {
+        if query.label.is_some() && query.label != self.label {
+            return false;
+        }
+        query
+            .tags
+            .iter()
+            .all(|tag| self.tags.binary_search(tag).is_ok())
+    }
+}
+
+impl From<&TaskTransport> for TaskLabels {
+    fn from(task: &TaskTransport) -> Self {
+        Self::new(task.label.clone(), task.tags.iter().cloned())
+    }
+}
+
+/// Selects which properties of the host machine are recorded in task metadata.
+///
+/// Host properties are only ever recorded in `Metadata`. None of them contribute to the identity
+/// of a task's inputs, so changing these settings never changes cache keys or causes cache misses.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SystemCapture {
+    /// Operating system name, long version, and distribution id.
+    pub operating_system: bool,
+    pub kernel_version: bool,
+    pub total_memory: bool,
+    pub cpu_cores: bool,
+    /// Hostname and the name of the invoking user.
+    pub host: bool,
+}
+
+impl SystemCapture {
+    /// Captures every supported property of the host machine.
+    pub fn all() -> Self {
+        Self {
+            operating_system: true,
+            kernel_version: true,
+            total_memory: true,
+            cpu_cores: true,
+            host: true,
+        }
+    }
+
+    /// Captures nothing about the host machine.
+    pub fn none() -> Self {
+        Self {
+            operating_system: false,
+            kernel_version: false,
+            total_memory: false,
+            cpu_cores: false,
+            host: false,
+        }
+    }
+}
+
+impl Default for SystemCapture {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct System {
+    name: Option<String>,
+    long_os_version: Option<String>,
+    kernel_version: Option<String>,
+    distribution_id: Option<String>,
+    total_memory: Option<u64>,
+    estimated_num_cpu_cores: Option<usize>,
 }
 
-#[cfg(test)]
 impl System {
     pub fn new<NameString, LongOsVerionSring, KernelVersionString, DistributionIdString>(
         name: Option<NameString>,
         long_os_version: Option<LongOsVerionSring>,
         kernel_version: Option<KernelVersionString>,
-        distribution_id: DistributionIdString,
-        total_memory: u64,
-        estimated_num_cpu_cores: usize,
+        distribution_id: Option<DistributionIdString>,
+        total_memory: Option<u64>,
+        estimated_num_cpu_cores: Option<usize>,
     ) -> Self
     where
         String: From<NameString>
@@ -1187,25 +2880,46 @@ impl System {
             name: name.map(String::from),
             long_os_version: long_os_version.map(String::from),
             kernel_version: kernel_version.map(String::from),
-            distribution_id: String::from(distribution_id),
+            distribution_id: distribution_id.map(String::from),
             total_memory,
             estimated_num_cpu_cores,
         }
     }
+
+    /// Records the properties of `system` selected by `capture`.
+    pub fn capture(system: &sysinfo::System, capture: &SystemCapture) -> Self {
+        let operating_system = capture.operating_system;
+        System {
+            name: system.name().filter(|_| operating_system),
+            long_os_version: system.long_os_version().filter(|_| operating_system),
+            kernel_version: system.kernel_version().filter(|_| capture.kernel_version),
+            distribution_id: Some(system.distribution_id()).filter(|_| operating_system),
+            total_memory: Some(system.total_memory()).filter(|_| capture.total_memory),
+            estimated_num_cpu_cores: Some(
+                system
+                    .physical_core_count()
+                    .unwrap_or_else(|| system.cpus().len()),
+            )
+            .filter(|_| capture.cpu_cores),
+        }
+    }
+
+    pub fn kernel_version(&self) -> Option<&str> {
+        self.kernel_version.as_deref()
+    }
+
+    pub fn total_memory(&self) -> Option<u64> {
+        self.total_memory
+    }
+
+    pub fn estimated_num_cpu_cores(&self) -> Option<usize> {
+        self.estimated_num_cpu_cores
+    }
 }
 
 impl From<&sysinfo::System> for System {
     fn from(system: &sysinfo::System) -> Self {
-        System {
-            name: system.name(),
-            long_os_version: system.long_os_version(),
-            kernel_version: system.kernel_version(),
-            distribution_id: system.distribution_id(),
-            total_memory: system.total_memory(),
-            estimated_num_cpu_cores: system
-                .physical_core_count()
-                .unwrap_or_else(|| system.cpus().len()),
-        }
+        Self::capture(system, &SystemCapture::all())
     }
 }
 
@@ -1243,6 +2957,10 @@ pub struct TaskInputs<IS: IdentitySchemeApi> {
     program: Program,
     arguments: Arguments,
     input_files: FileIdentitiesManifest<IS>,
+    staged_inputs: BTreeMap<PathBuf, PathBuf>,
+    stdin: Option<Stdin>,
+    sandbox: Option<Sandbox>,
+    resource_limits: Option<ResourceLimits>,
     outputs_description: Outputs,
 }
 
@@ -1259,19 +2977,204 @@ impl<IS: IdentitySchemeApi> TaskInputs<IS> {
         self.arguments.arguments()
     }
 
+    pub fn argv0(&self) -> Option<&String> {
+        self.arguments.argv0()
+    }
+
     pub fn input_files(&self) -> impl Iterator<Item = &(PathBuf, Option<IS::Identity>)> {
         self.input_files.identities()
     }
 
+    /// Gets pairs of (input file path, path at which the task expects to find it).
+    pub fn staged_inputs(&self) -> impl Iterator<Item = (&PathBuf, &PathBuf)> {
+        self.staged_inputs.iter()
+    }
+
+    /// Gets the path at which the task expects to find the input file at `path`.
+    pub fn staged_path<'a>(&'a self, path: &'a Path) -> &'a Path {
+        self.staged_inputs
+            .get(path)
+            .map(PathBuf::as_path)
+            .unwrap_or(path)
+    }
+
+    /// Stages input files under the names the task expects. Staged inputs are recorded in the
+    /// task's identity, so relocating an input changes its cache key.
+    pub fn with_staged_inputs<
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+        I: IntoIterator<Item = (P1, P2)>,
+    >(
+        mut self,
+        staged_inputs: I,
+    ) -> anyhow::Result<Self> {
+        self.staged_inputs
+            .extend(staged_inputs.into_iter().map(|(source, destination)| {
+                (
+                    source.as_ref().to_path_buf(),
+                    destination.as_ref().to_path_buf(),
+                )
+            }));
+        validate_staged_inputs(&self.input_files, &self.staged_inputs)?;
+        Ok(self)
+    }
+
+    pub fn stdin(&self) -> Option<&Stdin> {
+        self.stdin.as_ref()
+    }
+
+    /// Sets the task's standard input. A file read as standard input must be an input file.
+    pub fn with_stdin(mut self, stdin: Stdin) -> anyhow::Result<Self> {
+        validate_stdin(&self.input_files, Some(&stdin))?;
+        self.stdin = Some(stdin);
+        Ok(self)
+    }
+
+    /// Gets the isolation that `crate::runner::IsolatedRunner` applies to the task.
+    pub fn sandbox(&self) -> Option<&Sandbox> {
+        self.sandbox.as_ref()
+    }
+
+    pub fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    /// Gets the limits that `crate::runner::LimitedRunner` applies to the task.
+    pub fn resource_limits(&self) -> Option<&ResourceLimits> {
+        self.resource_limits.as_ref()
+    }
+
+    pub fn with_resource_limits(mut self, resource_limits: ResourceLimits) -> Self {
+        self.resource_limits = Some(resource_limits);
+        self
+    }
+
+    /// Copies staged input files to the paths at which the task expects to find them in
+    /// `filesystem`.
+    pub fn stage_inputs<FS: FilesystemApi>(&self, filesystem: &mut FS) -> anyhow::Result<()> {
+        for (source, destination) in self.staged_inputs.iter() {
+            if let Some(parent) = destination.parent() {
+                if !parent.as_os_str().is_empty() {
+                    filesystem
+                        .create_directories(parent)
+                        .map_err(anyhow::Error::from)
+                        .with_context(|| {
+                            format!("creating directories for staged input {:?}", destination)
+                        })?;
+                }
+            }
+            let mut source_file = filesystem
+                .open_file_for_read(source)
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("opening input {:?} for staging", source))?;
+            let mut destination_file = filesystem
+                .open_file_for_write(destination)
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("opening staged input {:?}", destination))?;
+            std::io::copy(&mut source_file, &mut destination_file)
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("staging input {:?} as {:?}", source, destination))?;
+        }
+        Ok(())
+    }
+
     pub fn outputs_description(&self) -> &Outputs {
         &self.outputs_description
     }
 
+    /// Gets the paths of output files expected from the task, in canonical order. Outputs matched
+    /// by include globs are not known until the task has run, and are not included.
+    pub fn output_paths(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut output_paths: Vec<_> = get_matching_output_files(self)?.into_iter().collect();
+        output_paths.sort();
+        Ok(output_paths)
+    }
+
+    /// Expands manifest placeholders in the task's arguments, for use right before the task is
+    /// spawned. The task's identity is computed from the unexpanded arguments, and depends on the
+    /// expanded values through the manifests.
+    ///
+    /// `{inputs}` is replaced by the paths of the input files, at the paths where the task expects
+    /// to find them (see `staged_path`), and `{outputs}` by `output_paths`. An argument that is
+    /// exactly one of these placeholders is replaced by one argument per path; elsewhere, paths are
+    /// joined with spaces, or with the separator given as, e.g., `{inputs:sep=,}`. Braces that do
+    /// not enclose a placeholder are passed through unchanged.
+    pub fn with_expanded_argument_placeholders(self) -> anyhow::Result<Self> {
+        let path_strings = |paths: Vec<&Path>| {
+            paths
+                .into_iter()
+                .map(|path| {
+                    path.to_str().map(String::from).ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "expanding argument placeholders: path, {:?}, cannot be converted to string",
+                            path
+                        )
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+        let input_paths = path_strings(
+            self.input_files()
+                .map(|(path, _)| self.staged_path(path))
+                .collect(),
+        )?;
+        let output_paths = self.output_paths()?;
+        let output_paths = path_strings(output_paths.iter().map(PathBuf::as_path).collect())?;
+        let arguments =
+            expand_argument_placeholders(&self.arguments.arguments, &input_paths, &output_paths)?;
+        Ok(Self {
+            arguments: Arguments {
+                arguments,
+                argv0: self.arguments.argv0,
+            },
+            ..self
+        })
+    }
+
+    /// Identifies the program, and adds it to the input files. This is the set of input files
+    /// recorded in `TaskOutputs`.
+    pub fn identify_input_files_with_program<FS: FilesystemApi>(
+        &self,
+        filesystem: &mut FS,
+    ) -> anyhow::Result<FileIdentitiesManifest<IS>> {
+        let program_path = self.program().clone();
+        let program_identity =
+            IS::identify_file(filesystem, &program_path).context("identifying program")?;
+        let mut input_files_with_program = vec![(program_path.clone(), Some(program_identity))];
+        input_files_with_program.extend(
+            self.input_files()
+                .map(|path_and_identity| path_and_identity.clone()),
+        );
+        input_files_with_program.sort_by(|(path1, _), (path2, _)| path1.cmp(path2));
+        let mut symlinks = self.input_files.symlinks.clone();
+        if filesystem.is_symlink(&program_path) {
+            symlinks.insert(
+                program_path.clone(),
+                filesystem
+                    .read_link(&program_path)
+                    .map_err(anyhow::Error::from)
+                    .context("reading program symbolic link")?,
+            );
+        }
+        Ok(FileIdentitiesManifest {
+            identity_scheme: IS::IDENTITY_SCHEME,
+            identities: input_files_with_program,
+            symlinks,
+        })
+    }
+
     pub fn wrap_program<FS: FilesystemApi, P: AsRef<Path>>(
         self,
         filesystem: &mut FS,
         new_program: P,
     ) -> anyhow::Result<Self> {
+        if let Some(argv0) = self.argv0() {
+            anyhow::bail!(
+                "wrapping task program: wrapper cannot pass argv[0] override, {:?}, to wrapped program",
+                argv0
+            );
+        }
         let old_program_str = self.program().to_str().ok_or_else(|| {
             anyhow::anyhow!(
                 "wrapping task program: previous program path, {:?} cannot be converted to string",
@@ -1289,11 +3192,16 @@ impl<IS: IdentitySchemeApi> TaskInputs<IS> {
             program: Program {
                 program: new_program.as_ref().to_path_buf(),
             },
-            arguments: Arguments { arguments },
+            arguments: Arguments::new(arguments),
             input_files: FileIdentitiesManifest {
                 identity_scheme: IS::IDENTITY_SCHEME,
                 identities: input_files,
+                symlinks: self.input_files.symlinks,
             },
+            staged_inputs: self.staged_inputs,
+            stdin: self.stdin,
+            sandbox: self.sandbox,
+            resource_limits: self.resource_limits,
             outputs_description: self.outputs_description,
         })
     }
@@ -1304,14 +3212,52 @@ impl<IS: IdentitySchemeApi> TaskInputs<IS> {
         Self {
             environment_variables: self.environment_variables,
             program: self.program,
-            arguments: Arguments { arguments },
+            arguments: Arguments {
+                arguments,
+                argv0: self.arguments.argv0,
+            },
             input_files: self.input_files,
+            staged_inputs: self.staged_inputs,
+            stdin: self.stdin,
+            sandbox: self.sandbox,
+            resource_limits: self.resource_limits,
             outputs_description: self.outputs_description,
         }
     }
+
+    /// Replaces the task's arguments with a single `@response_file` argument, and adds the
+    /// response file, whose contents have identity `response_file_identity`, to the input files.
+    /// The caller is responsible for writing the arguments to the response file.
+    pub fn with_response_file<P: AsRef<Path>>(
+        self,
+        response_file: P,
+        response_file_identity: IS::Identity,
+    ) -> anyhow::Result<Self> {
+        let response_file = response_file.as_ref();
+        let argument = response_file.to_str().ok_or_else(|| {
+            anyhow::anyhow!(
+                "response file path, {:?}, cannot be converted to string",
+                response_file
+            )
+        })?;
+        let mut input_files = self.input_files().map(Clone::clone).collect::<Vec<_>>();
+        input_files.push((response_file.to_path_buf(), Some(response_file_identity)));
+        input_files.sort();
+        Ok(Self {
+            arguments: Arguments {
+                arguments: vec![format!("@{}", argument)],
+                argv0: self.arguments.argv0,
+            },
+            input_files: FileIdentitiesManifest {
+                identity_scheme: IS::IDENTITY_SCHEME,
+                identities: input_files,
+                symlinks: self.input_files.symlinks,
+            },
+            ..self
+        })
+    }
 }
 
-#[cfg(test)]
 impl<IS: IdentitySchemeApi> TaskInputs<IS> {
     pub fn new(
         environment_variables: EnvironmentVariables,
@@ -1325,22 +3271,268 @@ impl<IS: IdentitySchemeApi> TaskInputs<IS> {
             program,
             arguments,
             input_files,
+            staged_inputs: BTreeMap::new(),
+            stdin: None,
+            sandbox: None,
+            resource_limits: None,
             outputs_description,
         }
     }
 }
 
+#[cfg(test)]
+impl<IS: IdentitySchemeApi> TaskInputs<IS> {
+    /// Inputs of a task that runs `program <argument>` with no environment, input files, or
+    /// outputs, for tests that store tasks without running them. Distinct arguments make distinct
+    /// tasks.
+    pub(crate) fn for_test<S: Into<String>>(argument: S) -> Self {
+        Self::new(
+            EnvironmentVariables::empty(),
+            Program::new("program"),
+            Arguments::new([argument.into()]),
+            FileIdentitiesManifest::empty(),
+            Outputs::empty(),
+        )
+    }
+
+    /// Replaces the task's input files with `input_files`.
+    pub(crate) fn with_input_files(self, input_files: FileIdentitiesManifest<IS>) -> Self {
+        Self {
+            input_files,
+            ..self
+        }
+    }
+}
+
+/// Validating builder for `TaskInputs`, for constructing tasks programmatically rather than from
+/// task files.
+#[derive(Clone, Debug)]
+pub struct TaskInputsBuilder<IS: IdentitySchemeApi> {
+    environment_variables: Vec<(String, String)>,
+    program: Option<PathBuf>,
+    arguments: Vec<String>,
+    argv0: Option<String>,
+    input_files: Vec<(PathBuf, Option<IS::Identity>)>,
+    staged_inputs: Vec<(PathBuf, PathBuf)>,
+    stdin: Option<Stdin>,
+    sandbox: Option<Sandbox>,
+    resource_limits: Option<ResourceLimits>,
+    outputs_description: Outputs,
+}
+
+impl<IS: IdentitySchemeApi> Default for TaskInputsBuilder<IS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<IS: IdentitySchemeApi> TaskInputsBuilder<IS> {
+    pub fn new() -> Self {
+        Self {
+            environment_variables: vec![],
+            program: None,
+            arguments: vec![],
+            argv0: None,
+            input_files: vec![],
+            staged_inputs: vec![],
+            stdin: None,
+            sandbox: None,
+            resource_limits: None,
+            outputs_description: Outputs::empty(),
+        }
+    }
+
+    pub fn environment_variable<K: Into<String>, V: Into<String>>(
+        mut self,
+        name: K,
+        value: V,
+    ) -> Self {
+        self.environment_variables.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn program<P: AsRef<Path>>(mut self, program: P) -> Self {
+        self.program = Some(program.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn argument<S: Into<String>>(mut self, argument: S) -> Self {
+        self.arguments.push(argument.into());
+        self
+    }
+
+    pub fn arguments<S: Into<String>, I: IntoIterator<Item = S>>(mut self, arguments: I) -> Self {
+        self.arguments.extend(arguments.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn argv0<S: Into<String>>(mut self, argv0: S) -> Self {
+        self.argv0 = Some(argv0.into());
+        self
+    }
+
+    pub fn input_file<P: AsRef<Path>>(mut self, path: P, identity: Option<IS::Identity>) -> Self {
+        self.input_files
+            .push((path.as_ref().to_path_buf(), identity));
+        self
+    }
+
+    /// Adds each file in `input_files`, identified in `filesystem`.
+    pub fn identify_input_files<FS: FilesystemApi>(
+        mut self,
+        filesystem: &mut FS,
+        input_files: FilesManifest,
+    ) -> Self {
+        self.input_files
+            .extend(input_files.into_identified::<IS, FS>(filesystem).identities);
+        self
+    }
+
+    pub fn staged_input<P1: AsRef<Path>, P2: AsRef<Path>>(
+        mut self,
+        source: P1,
+        destination: P2,
+    ) -> Self {
+        self.staged_inputs.push((
+            source.as_ref().to_path_buf(),
+            destination.as_ref().to_path_buf(),
+        ));
+        self
+    }
+
+    pub fn stdin(mut self, stdin: Stdin) -> Self {
+        self.stdin = Some(stdin);
+        self
+    }
+
+    pub fn sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    pub fn resource_limits(mut self, resource_limits: ResourceLimits) -> Self {
+        self.resource_limits = Some(resource_limits);
+        self
+    }
+
+    pub fn outputs_description(mut self, outputs_description: Outputs) -> Self {
+        self.outputs_description = outputs_description;
+        self
+    }
+
+    /// Validates the task description and produces canonical `TaskInputs`.
+    pub fn build(self) -> anyhow::Result<TaskInputs<IS>> {
+        let program = self
+            .program
+            .ok_or_else(|| anyhow::anyhow!("task inputs builder has no program"))?;
+
+        let mut environment_variable_names = HashSet::new();
+        for (name, _) in self.environment_variables.iter() {
+            if !environment_variable_names.insert(name) {
+                anyhow::bail!("environment variable, {:?}, set twice", name);
+            }
+        }
+
+        let mut input_paths = HashSet::new();
+        for (path, _) in self.input_files.iter() {
+            if !input_paths.insert(path) {
+                anyhow::bail!("input file, {:?}, appears twice", path);
+            }
+        }
+
+        let task_inputs = TaskInputs::new(
+            EnvironmentVariables::new(self.environment_variables),
+            Program::new(program),
+            match self.argv0 {
+                Some(argv0) => Arguments::new(self.arguments).with_argv0(argv0),
+                None => Arguments::new(self.arguments),
+            },
+            FileIdentitiesManifest::new(self.input_files),
+            self.outputs_description,
+        )
+        .with_staged_inputs(self.staged_inputs)?;
+        let task_inputs = match self.sandbox {
+            Some(sandbox) => task_inputs.with_sandbox(sandbox),
+            None => task_inputs,
+        };
+        let task_inputs = match self.resource_limits {
+            Some(resource_limits) => task_inputs.with_resource_limits(resource_limits),
+            None => task_inputs,
+        };
+        match self.stdin {
+            Some(stdin) => task_inputs.with_stdin(stdin),
+            None => Ok(task_inputs),
+        }
+    }
+}
+
+fn validate_stdin<IS: IdentitySchemeApi>(
+    input_files: &FileIdentitiesManifest<IS>,
+    stdin: Option<&Stdin>,
+) -> anyhow::Result<()> {
+    if let Some(Stdin::Path(path)) = stdin {
+        if !input_files
+            .identities()
+            .any(|(input_path, _)| input_path == path)
+        {
+            anyhow::bail!("standard input file, {:?}, is not an input file", path);
+        }
+    }
+    Ok(())
+}
+
+fn validate_staged_inputs<IS: IdentitySchemeApi>(
+    input_files: &FileIdentitiesManifest<IS>,
+    staged_inputs: &BTreeMap<PathBuf, PathBuf>,
+) -> anyhow::Result<()> {
+    let input_paths: HashSet<&PathBuf> = input_files.identities().map(|(path, _)| path).collect();
+    let mut destinations = HashSet::new();
+    for (source, destination) in staged_inputs.iter() {
+        if !input_paths.contains(source) {
+            anyhow::bail!("staged input, {:?}, is not an input file", source);
+        }
+        if !destination.is_relative() {
+            anyhow::bail!(
+                "staged input, {:?}, has destination, {:?}, that is not a relative path",
+                source,
+                destination
+            );
+        }
+        if input_paths.contains(destination) {
+            anyhow::bail!(
+                "staged input, {:?}, has destination, {:?}, that is also an input file",
+                source,
+                destination
+            );
+        }
+        if !destinations.insert(destination) {
+            anyhow::bail!(
+                "destination, {:?}, appears twice in staged inputs",
+                destination
+            );
+        }
+    }
+    Ok(())
+}
+
 impl<IS: IdentitySchemeApi> TryFrom<TaskInputsTransport<IS>> for TaskInputs<IS> {
     type Error = anyhow::Error;
 
     fn try_from(transport: TaskInputsTransport<IS>) -> anyhow::Result<Self> {
+        let input_files = transport.input_files.try_into()?;
+        validate_staged_inputs(&input_files, &transport.staged_inputs)?;
+        validate_stdin(&input_files, transport.stdin.as_ref())?;
         Ok(Self {
             environment_variables: EnvironmentVariables::try_from_manifest(
                 transport.environment_variables,
             )?,
             program: transport.program.into(),
             arguments: transport.arguments.into(),
-            input_files: transport.input_files.try_into()?,
+            input_files,
+            staged_inputs: transport.staged_inputs,
+            stdin: transport.stdin,
+            sandbox: transport.sandbox,
+            resource_limits: transport.resource_limits,
             outputs_description: transport.outputs_description.try_into()?,
         })
     }
@@ -1351,10 +3543,15 @@ impl<IS: IdentitySchemeApi> IntoTransport for TaskInputs<IS> {
 
     fn into_transport(self) -> Self::Transport {
         Self::Transport {
+            format_version: FormatVersion::default(),
             environment_variables: self.environment_variables.as_manifest(),
             program: self.program.as_transport(),
             arguments: self.arguments.as_transport(),
             input_files: self.input_files.as_transport(),
+            staged_inputs: self.staged_inputs,
+            stdin: self.stdin,
+            sandbox: self.sandbox,
+            resource_limits: self.resource_limits,
             outputs_description: self.outputs_description.as_transport(),
         }
     }
@@ -1366,7 +3563,6 @@ pub struct TaskOutputs<IS: IdentitySchemeApi> {
     output_files: FileIdentitiesManifest<IS>,
 }
 
-#[cfg(test)]
 impl<IS: IdentitySchemeApi> TaskOutputs<IS> {
     pub fn new(
         input_files_with_program: FileIdentitiesManifest<IS>,
@@ -1377,6 +3573,26 @@ impl<IS: IdentitySchemeApi> TaskOutputs<IS> {
             output_files,
         }
     }
+
+    pub fn input_files_with_program(
+        &self,
+    ) -> impl Iterator<Item = &(PathBuf, Option<IS::Identity>)> {
+        self.input_files_with_program.identities()
+    }
+
+    pub fn output_files(&self) -> impl Iterator<Item = &(PathBuf, Option<IS::Identity>)> {
+        self.output_files.identities()
+    }
+
+    /// Output files that are symbolic links, and their targets.
+    pub fn output_symlinks(&self) -> impl Iterator<Item = (&PathBuf, &PathBuf)> {
+        self.output_files.symlinks()
+    }
+
+    /// Target of the output file at `path`, when it is a symbolic link.
+    pub fn output_symlink_target<P: AsRef<Path>>(&self, path: P) -> Option<&PathBuf> {
+        self.output_files.symlink_target(path)
+    }
 }
 
 impl<IS: IdentitySchemeApi> TryFrom<TaskOutputsTransport<IS>> for TaskOutputs<IS> {
@@ -1395,6 +3611,7 @@ impl<IS: IdentitySchemeApi> IntoTransport for TaskOutputs<IS> {
 
     fn into_transport(self) -> Self::Transport {
         Self::Transport {
+            format_version: FormatVersion::default(),
             input_files_with_program: self.input_files_with_program.as_transport(),
             output_files: self.output_files.as_transport(),
         }
@@ -1409,54 +3626,120 @@ impl<FS: FilesystemApi, IS: IdentitySchemeApi> TryFrom<(&mut FS, &TaskInputs<IS>
     fn try_from(filesystem_and_inputs: (&mut FS, &TaskInputs<IS>)) -> Result<Self, Self::Error> {
         let (filesystem, inputs) = filesystem_and_inputs;
 
-        let program_path = inputs.program().clone();
-        let program_identity =
-            IS::identify_file(filesystem, &program_path).context("identifying program")?;
-        let mut input_files_with_program = vec![(program_path, Some(program_identity))];
-        input_files_with_program.extend(
-            inputs
-                .input_files()
-                .map(|path_and_identity| path_and_identity.clone()),
-        );
-        input_files_with_program.sort_by(|(path1, _), (path2, _)| path1.cmp(path2));
-        let input_files_with_program = FileIdentitiesManifest {
-            identity_scheme: IS::IDENTITY_SCHEME,
-            identities: input_files_with_program,
-        };
-
-        let mut output_files: Vec<_> = get_matching_output_files(inputs)?.into_iter().collect();
-        output_files.sort();
-        let output_files = output_files
-            .into_iter()
-            .map(|path| {
-                let identity = IS::identify_file(filesystem, &path)?;
-                Ok((path, Some(identity)))
-            })
-            .collect::<Result<Vec<_>, anyhow::Error>>()
-            .context("identifying matched output files")?;
+        let input_files_with_program = inputs.identify_input_files_with_program(filesystem)?;
+
+        let mut output_paths: BTreeSet<PathBuf> = inputs.output_paths()?.into_iter().collect();
+        output_paths.extend(get_globbed_output_files(filesystem, inputs)?);
+        let outputs = inputs.outputs_description();
+        let mut output_files = vec![];
+        for path in output_paths {
+            let symlink_identity = match filesystem.metadata(&path) {
+                Ok(metadata) if outputs.excludes_metadata(&metadata) => {
+                    tracing::debug!("skipping excluded output file {:?}", path);
+                    continue;
+                }
+                Ok(_) => SymlinkIdentity::ResolvedContents,
+                // Dangling symbolic links are identified by their targets.
+                Err(_) if filesystem.is_symlink(&path) => SymlinkIdentity::TargetPath,
+                Err(_) if outputs.may_be_missing(&path) => {
+                    tracing::debug!("skipping missing optional output file {:?}", path);
+                    continue;
+                }
+                Err(error) if outputs.is_optional(&path) => {
+                    return Err(anyhow::Error::from(error)).with_context(|| {
+                        format!(
+                            "optional output file, {:?}, is missing, but outputs verification is {:?}",
+                            path,
+                            outputs.verification()
+                        )
+                    });
+                }
+                Err(error) => {
+                    return Err(anyhow::Error::from(error))
+                        .with_context(|| format!("required output file, {:?}, is missing", path));
+                }
+            };
+            let identity = IS::identify_path(filesystem, &path, symlink_identity)
+                .with_context(|| format!("identifying matched output file {:?}", path))?;
+            output_files.push((path, Some(identity)));
+        }
 
         Ok(Self {
             input_files_with_program,
             output_files: FileIdentitiesManifest {
                 identity_scheme: IS::IDENTITY_SCHEME,
                 identities: output_files,
-            },
+                symlinks: BTreeMap::new(),
+            }
+            .with_symlink_targets(filesystem),
         })
     }
 }
 
-/// Gets the set of files that matches transformations from  `inputs.input_files()` through
-/// `inputs.outputs_description()` transformations.
-fn get_matching_output_files<IS: IdentitySchemeApi>(
+/// Gets the set of files that exist after the task has run and match
+/// `inputs.outputs_description()` globs or are under its directories, less those matching its
+/// exclude matches.
+pub(crate) fn get_globbed_output_files<FS: FilesystemApi, IS: IdentitySchemeApi>(
+    filesystem: &mut FS,
     inputs: &TaskInputs<IS>,
 ) -> anyhow::Result<HashSet<PathBuf>> {
     let outputs = inputs.outputs_description();
-    let mut files: HashSet<PathBuf> = outputs.include_files.iter().map(PathBuf::clone).collect();
+    let mut globbed_paths = vec![];
+    for include_glob in outputs.include_globs.iter() {
+        for path_result in filesystem.execute_glob(include_glob)? {
+            globbed_paths.push(
+                path_result.map_err(anyhow::Error::from).with_context(|| {
+                    format!("executing output include-glob, {:?}", include_glob)
+                })?,
+            );
+        }
+    }
+    for (directory, exclude_globs) in outputs.include_directories.iter() {
+        let exclude_globs: Vec<_> = exclude_globs.iter().cloned().collect();
+        globbed_paths.extend(
+            get_matching_directory_files(filesystem, directory, &[], &exclude_globs)
+                .with_context(|| format!("collecting files in output directory {:?}", directory))?,
+        );
+    }
 
-    for match_transforms in outputs.include_match_transforms.iter() {
-        for (input_path, _) in inputs.input_files() {
-            let mut output_path = input_path
-                .to_str()
+    let mut files = HashSet::new();
+    for path in globbed_paths {
+        if !filesystem.file_exists(&path) {
+            continue;
+        }
+        let path_str = path.to_str().ok_or_else(|| {
+            anyhow::anyhow!(
+                "output file has path, {:?}, that cannot be encoded as a string",
+                path
+            )
+        })?;
+        if outputs
+            .exclude_matches
+            .iter()
+            .any(|exclude_match| exclude_match.regular_expression.is_match(path_str))
+        {
+            continue;
+        }
+        files.insert(path);
+    }
+    Ok(files)
+}
+
+/// Gets the set of files that matches transformations from  `inputs.input_files()` through
+/// `inputs.outputs_description()` transformations.
+fn get_matching_output_files<IS: IdentitySchemeApi>(
+    inputs: &TaskInputs<IS>,
+) -> anyhow::Result<HashSet<PathBuf>> {
+    let outputs = inputs.outputs_description();
+    let mut files: HashSet<PathBuf> = outputs.include_files.iter().map(PathBuf::clone).collect();
+    files.extend(outputs.optional_files.iter().cloned());
+    files.extend(outputs.stdout_file.iter().cloned());
+    files.extend(outputs.stderr_file.iter().cloned());
+
+    for match_transforms in outputs.include_match_transforms.iter() {
+        for (input_path, _) in inputs.input_files() {
+            let mut output_path = input_path
+                .to_str()
                 .ok_or_else(|| {
                     anyhow::anyhow!(
                         "input file has path, {:?}, that cannot be encoded as a string",
@@ -1501,16 +3784,45 @@ fn get_matching_output_files<IS: IdentitySchemeApi>(
 
 #[cfg(test)]
 mod tests {
+    use super::scan_references_in_parallel;
+    use super::Arguments;
+    use super::EnvironmentVariables;
+    use super::FileIdentitiesManifest;
     use super::FilesManifest;
+    use super::Listing;
+    use super::Metadata;
+    use super::Outputs;
+    use super::Program;
+    use super::RegularExpressionCache;
+    use super::System;
+    use super::TaskInputs;
+    use super::TaskInputsBuilder;
+    use super::TaskOutputs;
+    use crate::fs::FileType;
+    use crate::fs::Filesystem as _;
     use crate::fs::HostFilesystem;
+    use crate::fs::MemoryFilesystem;
+    use crate::identity::AsTransport as _;
+    use crate::identity::IdentityScheme as _;
+    use crate::identity::IntoTransport as _;
+    use crate::transport::CIncludes;
+    use crate::transport::ContentSha256;
     use crate::transport::Inputs as InputsTransport;
+    use crate::transport::InputsDirectory;
+    use crate::transport::InputsGroup;
     use crate::transport::InterFileReferences;
     use crate::transport::Match;
+    use crate::transport::MatchFlags;
     use crate::transport::MatchTransform;
+    use crate::transport::Metadata as MetadataTransport;
     use crate::transport::Outputs as OutputsTransport;
+    use crate::transport::OutputsDirectory;
+    use crate::transport::OutputsVerification;
+    use crate::transport::Stdin;
     use std::convert::TryFrom;
     use std::fs::File;
     use std::io::Write;
+    use std::path::Path;
     use std::path::PathBuf;
 
     #[test]
@@ -1564,9 +3876,13 @@ mod tests {
                     match_transforms: vec![MatchTransform {
                         match_regular_expression: String::from(r#"^INCLUDE_FILE\(([^)]+)\)$"#),
                         match_transform_expressions: vec![String::from(r#"$1"#)],
+                        flags: MatchFlags::default(),
                     }],
+                    c_includes: None,
+                    scanner_plugin: None,
                     // Search for resolved files in `__` directory.
                     directories_to_search: Some(vec![PathBuf::from("__")]),
+                    scan_binary_files: false,
                 },
                 InterFileReferences {
                     files_to_match: Some(InputsTransport {
@@ -1575,6 +3891,7 @@ mod tests {
                         include_globs: vec![String::from("__/*")],
                         exclude_globs: vec![],
                         inter_file_references: vec![],
+                        ..InputsTransport::default()
                     }),
                     // Match lines of the form `INCLUDE_FILE(file)`, resolving to path `file`.
                     match_transforms: vec![MatchTransform {
@@ -1582,11 +3899,16 @@ mod tests {
                             r#"^INCLUDE_FILE_INTERNAL\(([^)]+)\)$"#,
                         ),
                         match_transform_expressions: vec![String::from(r#"$1"#)],
+                        flags: MatchFlags::default(),
                     }],
+                    c_includes: None,
+                    scanner_plugin: None,
                     // Search for resolved files in `__` directory.
                     directories_to_search: Some(vec![PathBuf::from("a")]),
+                    scan_binary_files: false,
                 },
             ],
+            ..InputsTransport::default()
         };
         let inputs_manifest: FilesManifest =
             FilesManifest::try_from((&mut host_filesystem, inputs_config))
@@ -1603,6 +3925,340 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_inputs_manifest_depfiles() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        std::fs::create_dir_all(temporary_directory.path().join("include"))
+            .expect("manually create directories");
+        File::create(temporary_directory.path().join("main.c")).expect("manually create file");
+        File::create(temporary_directory.path().join("include/a.h")).expect("manually create file");
+        File::create(temporary_directory.path().join("include/excluded.h"))
+            .expect("manually create file");
+        std::fs::write(
+            temporary_directory.path().join("main.d"),
+            "main.o: main.c \\\n include/a.h include/excluded.h include/deleted.h\n".as_bytes(),
+        )
+        .expect("write depfile");
+
+        let mut host_filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let inputs_config = InputsTransport {
+            include_files: vec![],
+            exclude_files: vec![PathBuf::from("include/excluded.h")],
+            include_globs: vec![],
+            exclude_globs: vec![],
+            inter_file_references: vec![],
+            depfiles: vec![PathBuf::from("main.d"), PathBuf::from("missing.d")],
+            ..InputsTransport::default()
+        };
+        let inputs_manifest: FilesManifest =
+            FilesManifest::try_from((&mut host_filesystem, inputs_config))
+                .expect("create inputs manifest");
+        assert_eq!(
+            FilesManifest::new(["include/a.h", "main.c"]),
+            inputs_manifest
+        );
+    }
+
+    #[test]
+    fn test_inputs_manifest_c_includes() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        for directory in ["src", "src/detail", "include/lib", "quote", "excluded"] {
+            std::fs::create_dir_all(temporary_directory.path().join(directory))
+                .expect("manually create directories");
+        }
+        for (path, contents) in [
+            (
+                "src/main.cc",
+                "#include \"detail/local.h\"\n#include <lib/api.h>\n#include \"quoted.h\"\n#include <vector>\n#if 0\n#include \"excluded/never.h\"\n#endif\n",
+            ),
+            ("src/detail/local.h", "#include \"../../excluded/skipped.h\"\n"),
+            ("include/lib/api.h", "#include \"lib/impl.h\"\n"),
+            ("include/lib/impl.h", ""),
+            ("quote/quoted.h", ""),
+            ("excluded/never.h", ""),
+            ("excluded/skipped.h", ""),
+        ] {
+            std::fs::write(temporary_directory.path().join(path), contents.as_bytes())
+                .expect("manually create file");
+        }
+
+        let mut host_filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let inputs_config = InputsTransport {
+            include_files: vec![PathBuf::from("src/main.cc")],
+            exclude_files: vec![],
+            include_globs: vec![],
+            exclude_globs: vec![String::from("excluded/**")],
+            inter_file_references: vec![InterFileReferences {
+                files_to_match: None,
+                match_transforms: vec![],
+                c_includes: Some(CIncludes {
+                    quote_directories: vec![PathBuf::from("quote")],
+                    include_directories: vec![PathBuf::from("include")],
+                }),
+                scanner_plugin: None,
+                directories_to_search: None,
+                scan_binary_files: false,
+            }],
+            ..InputsTransport::default()
+        };
+        let inputs_manifest: FilesManifest =
+            FilesManifest::try_from((&mut host_filesystem, inputs_config))
+                .expect("create inputs manifest");
+        assert_eq!(
+            FilesManifest::new([
+                "include/lib/api.h",
+                "include/lib/impl.h",
+                "quote/quoted.h",
+                "src/detail/local.h",
+                "src/main.cc",
+            ]),
+            inputs_manifest
+        );
+    }
+
+    #[test]
+    fn test_inputs_manifest_reference_limits() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        for (path, contents) in [
+            ("a", "REF(b)\n"),
+            ("b", "REF(c)\n"),
+            ("c", "REF(d)\n"),
+            ("d", "REF(b)\n"),
+        ] {
+            std::fs::write(temporary_directory.path().join(path), contents.as_bytes())
+                .expect("manually create file");
+        }
+        let mut host_filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let inputs_config = InputsTransport {
+            include_files: vec![PathBuf::from("a")],
+            inter_file_references: vec![InterFileReferences {
+                files_to_match: None,
+                match_transforms: vec![MatchTransform {
+                    match_regular_expression: String::from(r#"^REF\(([^)]+)\)$"#),
+                    match_transform_expressions: vec![String::from(r#"$1"#)],
+                    flags: MatchFlags::default(),
+                }],
+                c_includes: None,
+                scanner_plugin: None,
+                directories_to_search: None,
+                scan_binary_files: false,
+            }],
+            ..InputsTransport::default()
+        };
+
+        // The cycle `b -> c -> d -> b` is tolerated by default.
+        let inputs_manifest: FilesManifest =
+            FilesManifest::try_from((&mut host_filesystem, inputs_config.clone()))
+                .expect("create inputs manifest");
+        assert_eq!(FilesManifest::new(["a", "b", "c", "d"]), inputs_manifest);
+
+        let error = FilesManifest::try_from((
+            &mut host_filesystem,
+            InputsTransport {
+                fail_on_reference_cycles: true,
+                ..inputs_config.clone()
+            },
+        ))
+        .expect_err("reject reference cycle");
+        assert_eq!(
+            r#"inter-file references form a cycle: "b" -> "c" -> "d" -> "b""#,
+            error.to_string()
+        );
+
+        let error = FilesManifest::try_from((
+            &mut host_filesystem,
+            InputsTransport {
+                max_reference_depth: Some(2),
+                ..inputs_config
+            },
+        ))
+        .expect_err("reject deep references");
+        assert_eq!(
+            r#"inter-file references exceed maximum depth of 2: "a" -> "b" -> "c" -> "d""#,
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_inputs_manifest_binary_files() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let mut binary_contents = b"\x7fELF\0\xff\xfe\n".to_vec();
+        binary_contents.extend_from_slice(b"REF(c)\n");
+        for (path, contents) in [
+            ("a", b"REF(b)\n".to_vec()),
+            ("b", binary_contents),
+            ("c", b"\n".to_vec()),
+        ] {
+            std::fs::write(temporary_directory.path().join(path), contents)
+                .expect("manually create file");
+        }
+        let mut host_filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let inter_file_references = InterFileReferences {
+            files_to_match: None,
+            match_transforms: vec![MatchTransform {
+                match_regular_expression: String::from(r#"^REF\(([^)]+)\)$"#),
+                match_transform_expressions: vec![String::from(r#"$1"#)],
+                flags: MatchFlags::default(),
+            }],
+            c_includes: None,
+            scanner_plugin: None,
+            directories_to_search: None,
+            scan_binary_files: false,
+        };
+
+        // Binary file `b` is included, but not scanned for references.
+        let inputs_manifest: FilesManifest = FilesManifest::try_from((
+            &mut host_filesystem,
+            InputsTransport {
+                include_files: vec![PathBuf::from("a")],
+                inter_file_references: vec![inter_file_references.clone()],
+                ..InputsTransport::default()
+            },
+        ))
+        .expect("create inputs manifest");
+        assert_eq!(FilesManifest::new(["a", "b"]), inputs_manifest);
+
+        // Opting in scans `b`, tolerating its invalid UTF-8.
+        let inputs_manifest: FilesManifest = FilesManifest::try_from((
+            &mut host_filesystem,
+            InputsTransport {
+                include_files: vec![PathBuf::from("a")],
+                inter_file_references: vec![InterFileReferences {
+                    scan_binary_files: true,
+                    ..inter_file_references
+                }],
+                ..InputsTransport::default()
+            },
+        ))
+        .expect("create inputs manifest");
+        assert_eq!(FilesManifest::new(["a", "b", "c"]), inputs_manifest);
+    }
+
+    #[test]
+    fn test_scan_references_in_parallel() {
+        let match_transforms = RegularExpressionCache::default()
+            .match_transforms(&[MatchTransform {
+                match_regular_expression: String::from(r#"^REF\(([^)]+)\)$"#),
+                match_transform_expressions: vec![String::from(r#"$1"#)],
+                flags: MatchFlags::default(),
+            }])
+            .expect("compile match transforms");
+        let files_and_contents: Vec<(PathBuf, String)> = (0..37)
+            .map(|index| {
+                (
+                    PathBuf::from(format!("{}.c", index)),
+                    format!(
+                        "REF({}.h)\n#include \"{}.inc\"\nREF({}.txt)\n",
+                        index, index, index
+                    ),
+                )
+            })
+            .collect();
+        let sequential =
+            scan_references_in_parallel(&files_and_contents, &match_transforms, true, 1);
+        assert_eq!(files_and_contents.len(), sequential.len());
+        assert_eq!(
+            vec![PathBuf::from("36.h"), PathBuf::from("36.txt")],
+            sequential[36].transformed_paths
+        );
+        assert_eq!(PathBuf::from("36.inc"), sequential[36].includes[0].path);
+        for max_threads in [2, 4, 64] {
+            assert_eq!(
+                sequential,
+                scan_references_in_parallel(
+                    &files_and_contents,
+                    &match_transforms,
+                    true,
+                    max_threads
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_regular_expression_cache() {
+        let mut regular_expression_cache = RegularExpressionCache::default();
+        let match_transforms = regular_expression_cache
+            .match_transforms(&[
+                MatchTransform {
+                    match_regular_expression: String::from("^(.*)[.]c$"),
+                    match_transform_expressions: vec![String::from("$1.h")],
+                    flags: MatchFlags::default(),
+                },
+                MatchTransform {
+                    match_regular_expression: String::from("^(.*)[.]c$"),
+                    match_transform_expressions: vec![String::from("$1.inc")],
+                    flags: MatchFlags::default(),
+                },
+            ])
+            .expect("compile match transforms");
+        assert_eq!(2, match_transforms.len());
+        assert_eq!(1, regular_expression_cache.regular_expressions.len());
+        assert!(regular_expression_cache
+            .get_or_compile("(", MatchFlags::default())
+            .is_err());
+        assert_eq!(1, regular_expression_cache.regular_expressions.len());
+    }
+
+    #[test]
+    fn test_match_transform_named_groups_and_flags() {
+        let match_transforms = RegularExpressionCache::default()
+            .match_transforms(&[MatchTransform {
+                match_regular_expression: String::from(
+                    r#"^ref\((?P<stem>[^.)]+)[.](?P<ext>\w+)\)$"#,
+                ),
+                match_transform_expressions: vec![String::from("${stem}_gen.$ext")],
+                flags: MatchFlags {
+                    case_insensitive: true,
+                    ..MatchFlags::default()
+                },
+            }])
+            .expect("compile match transforms");
+        let scanned = scan_references_in_parallel(
+            &[(PathBuf::from("a.c"), String::from("REF(a.h)\nref(b.txt)\n"))],
+            &match_transforms,
+            false,
+            1,
+        );
+        assert_eq!(
+            vec![PathBuf::from("a_gen.h"), PathBuf::from("b_gen.txt")],
+            scanned[0].transformed_paths
+        );
+
+        // References to capture groups that do not exist are rejected.
+        for match_transform_expression in ["$stme", "${stem}_$2", "$1_suffix"] {
+            assert!(
+                RegularExpressionCache::default()
+                    .match_transforms(&[MatchTransform {
+                        match_regular_expression: String::from(r#"^(?P<stem>.*)$"#),
+                        match_transform_expressions: vec![String::from(match_transform_expression)],
+                        flags: MatchFlags::default(),
+                    }])
+                    .is_err(),
+                "{}",
+                match_transform_expression
+            );
+        }
+
+        // Default flags are omitted from transports, so existing identities are unchanged.
+        assert_eq!(
+            serde_json::json!({
+                "match_regular_expression": "^(.*)$",
+                "match_transform_expressions": ["$$1", "$1"],
+            }),
+            serde_json::to_value(MatchTransform {
+                match_regular_expression: String::from("^(.*)$"),
+                match_transform_expressions: vec![String::from("$$1"), String::from("$1")],
+                flags: MatchFlags::default(),
+            })
+            .expect("match transform to JSON")
+        );
+    }
+
     #[test]
     fn test_outputs_manifest() {
         let inputs_manifest = FilesManifest::new([
@@ -1615,6 +4271,7 @@ mod tests {
         ]);
         let outputs_config = OutputsTransport {
             include_files: vec![PathBuf::from("out/log")],
+            optional_files: vec![],
             include_match_transforms: vec![
                 vec![
                     // TODO: Test multiple transforms over single path.
@@ -1624,13 +4281,17 @@ mod tests {
                             String::from("out/$1.out.1"),
                             String::from("out/$1.out.2"),
                         ],
+                        flags: MatchFlags::default(),
                     },
                 ],
                 vec![MatchTransform {
                     match_regular_expression: String::from("^(.*)[.]stu$"),
                     match_transform_expressions: vec![String::from("out/$1.out.stu")],
+                    flags: MatchFlags::default(),
                 }],
             ],
+            include_globs: vec![],
+            include_directories: vec![],
             exclude_matches: vec![
                 Match {
                     match_regular_expression: String::from("^.*/c/.*$"),
@@ -1639,6 +4300,11 @@ mod tests {
                     match_regular_expression: String::from("^.*/o[.]stu$"),
                 },
             ],
+            max_file_size_bytes: None,
+            exclude_file_types: vec![],
+            stdout_file: None,
+            stderr_file: None,
+            verification: OutputsVerification::default(),
         };
 
         let outputs_manifest: FilesManifest =
@@ -1661,4 +4327,917 @@ mod tests {
             outputs_manifest
         );
     }
+
+    #[test]
+    fn test_task_outputs_include_globs() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        std::fs::create_dir_all(temporary_directory.path().join("out/assets.d"))
+            .expect("manually create directories");
+        for (path, contents) in [
+            ("program", "program"),
+            ("out/log", "log"),
+            ("out/main.3f2a.js", "main"),
+            ("out/vendor.9c1b.js", "vendor"),
+            ("out/vendor.9c1b.js.map", "map"),
+            ("out/skip.0000.js", "skip"),
+        ] {
+            std::fs::write(temporary_directory.path().join(path), contents)
+                .expect("manually create file");
+        }
+        let mut host_filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("program"),
+            Arguments::empty(),
+            FileIdentitiesManifest::empty(),
+            Outputs::try_from(OutputsTransport {
+                include_files: vec![PathBuf::from("out/log")],
+                include_match_transforms: vec![],
+                include_globs: vec![String::from("out/*.js"), String::from("out/*.d")],
+                exclude_matches: vec![Match {
+                    match_regular_expression: String::from("^out/skip[.]"),
+                }],
+                ..OutputsTransport::empty()
+            })
+            .expect("outputs description"),
+        );
+        assert_eq!(
+            vec![PathBuf::from("out/log")],
+            inputs.output_paths().expect("output paths")
+        );
+
+        let outputs = TaskOutputs::try_from((&mut host_filesystem, &inputs)).expect("task outputs");
+        assert_eq!(
+            vec![
+                PathBuf::from("out/log"),
+                PathBuf::from("out/main.3f2a.js"),
+                PathBuf::from("out/vendor.9c1b.js"),
+            ],
+            outputs
+                .output_files()
+                .map(|(path, _)| path.clone())
+                .collect::<Vec<_>>()
+        );
+
+        assert!(Outputs::try_from(OutputsTransport {
+            include_globs: vec![String::from("out/[")],
+            ..OutputsTransport::empty()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_task_outputs_include_directories() {
+        let mut memory_filesystem = MemoryFilesystem::new();
+        for path in [
+            "program",
+            "out/docs/index.html",
+            "out/docs/api/module.html",
+            "out/docs/api/module.html.tmp",
+            "out/docs/.cache/state",
+        ] {
+            memory_filesystem
+                .write_file(path, path)
+                .expect("write file");
+        }
+        let inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("program"),
+            Arguments::empty(),
+            FileIdentitiesManifest::empty(),
+            Outputs::try_from(OutputsTransport {
+                include_directories: vec![OutputsDirectory {
+                    directory: PathBuf::from("out/docs"),
+                    exclude_globs: vec![String::from(".cache"), String::from("**/*.tmp")],
+                }],
+                ..OutputsTransport::empty()
+            })
+            .expect("outputs description"),
+        );
+        assert_eq!(
+            inputs.outputs_description().clone(),
+            Outputs::empty().with_include_directory("out/docs", ["**/*.tmp", ".cache"])
+        );
+
+        let outputs =
+            TaskOutputs::try_from((&mut memory_filesystem, &inputs)).expect("task outputs");
+        assert_eq!(
+            vec![
+                PathBuf::from("out/docs/api/module.html"),
+                PathBuf::from("out/docs/index.html"),
+            ],
+            outputs
+                .output_files()
+                .map(|(path, _)| path.clone())
+                .collect::<Vec<_>>()
+        );
+
+        // Output directories that the task does not produce are missing outputs.
+        let inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("program"),
+            Arguments::empty(),
+            FileIdentitiesManifest::empty(),
+            Outputs::empty().with_include_directory("out/missing", std::iter::empty::<String>()),
+        );
+        assert!(TaskOutputs::try_from((&mut memory_filesystem, &inputs)).is_err());
+
+        for directory in [
+            OutputsDirectory {
+                directory: PathBuf::from("/out"),
+                exclude_globs: vec![],
+            },
+            OutputsDirectory {
+                directory: PathBuf::from("out"),
+                exclude_globs: vec![String::from("[")],
+            },
+        ] {
+            assert!(Outputs::try_from(OutputsTransport {
+                include_directories: vec![directory],
+                ..OutputsTransport::empty()
+            })
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn test_task_outputs_exclusions() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        std::fs::create_dir_all(temporary_directory.path().join("out/scratch.d"))
+            .expect("manually create directories");
+        for (path, contents) in [
+            ("program", "program"),
+            ("out/small", "small"),
+            ("out/large", "large contents"),
+        ] {
+            std::fs::write(temporary_directory.path().join(path), contents)
+                .expect("manually create file");
+        }
+        let mut host_filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("program"),
+            Arguments::empty(),
+            FileIdentitiesManifest::empty(),
+            Outputs::try_from(OutputsTransport {
+                include_files: vec![
+                    PathBuf::from("out/large"),
+                    PathBuf::from("out/scratch.d"),
+                    PathBuf::from("out/small"),
+                ],
+                max_file_size_bytes: Some(8),
+                exclude_file_types: vec![FileType::Directory],
+                ..OutputsTransport::empty()
+            })
+            .expect("outputs description"),
+        );
+
+        let outputs = TaskOutputs::try_from((&mut host_filesystem, &inputs)).expect("task outputs");
+        assert_eq!(
+            vec![PathBuf::from("out/small")],
+            outputs
+                .output_files()
+                .map(|(path, _)| path.clone())
+                .collect::<Vec<_>>()
+        );
+
+        assert!(Outputs::try_from(OutputsTransport {
+            exclude_file_types: vec![FileType::Fifo, FileType::Fifo],
+            ..OutputsTransport::empty()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_inputs_manifest_directories() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        for path in [
+            "vendor/a.c",
+            "vendor/a.o",
+            "vendor/sub/b.c",
+            "vendor/sub/b.o",
+            "vendor/.git/HEAD",
+            "vendor/sub/.git/HEAD",
+            "vendor/README",
+            "other/c.c",
+        ] {
+            let path = temporary_directory.path().join(path);
+            std::fs::create_dir_all(path.parent().expect("parent directory"))
+                .expect("manually create directory");
+            File::create(path).expect("manually create file");
+        }
+        let mut host_filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+
+        let inputs_manifest = FilesManifest::try_from((
+            &mut host_filesystem,
+            InputsTransport {
+                exclude_files: vec![PathBuf::from("vendor/README")],
+                include_directories: vec![InputsDirectory {
+                    directory: PathBuf::from("vendor"),
+                    include_globs: vec![],
+                    exclude_globs: vec![String::from("**/.git"), String::from("**/*.o")],
+                }],
+                ..InputsTransport::default()
+            },
+        ))
+        .expect("create inputs manifest");
+        assert_eq!(
+            FilesManifest::new(["vendor/a.c", "vendor/sub/b.c"]),
+            inputs_manifest
+        );
+
+        let inputs_manifest = FilesManifest::try_from((
+            &mut host_filesystem,
+            InputsTransport {
+                include_directories: vec![InputsDirectory {
+                    directory: PathBuf::from("vendor"),
+                    include_globs: vec![String::from("*.c")],
+                    exclude_globs: vec![],
+                }],
+                ..InputsTransport::default()
+            },
+        ))
+        .expect("create inputs manifest");
+        assert_eq!(FilesManifest::new(["vendor/a.c"]), inputs_manifest);
+
+        assert!(FilesManifest::try_from((
+            &mut host_filesystem,
+            InputsTransport {
+                include_directories: vec![InputsDirectory {
+                    directory: PathBuf::from("missing"),
+                    ..InputsDirectory::default()
+                }],
+                ..InputsTransport::default()
+            },
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_inputs_expected_identities() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        std::fs::write(temporary_directory.path().join("compiler"), "compiler")
+            .expect("manually create file");
+        std::fs::write(temporary_directory.path().join("main.c"), "main")
+            .expect("manually create file");
+        let mut host_filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let compiler_identity = ContentSha256::identify_content("compiler".as_bytes())
+            .expect("identify content")
+            .to_string();
+        let inputs_config = |expected_identities: &[(&str, &str)]| InputsTransport {
+            include_files: vec![PathBuf::from("compiler"), PathBuf::from("main.c")],
+            expected_identities: expected_identities
+                .iter()
+                .map(|(path, identity)| (PathBuf::from(path), identity.to_string()))
+                .collect(),
+            ..InputsTransport::default()
+        };
+
+        let manifest = FileIdentitiesManifest::<ContentSha256>::try_from_inputs(
+            &mut host_filesystem,
+            &inputs_config(&[("compiler", &compiler_identity)]),
+        )
+        .expect("inputs with expected identities");
+        assert_eq!(2, manifest.identities().count());
+
+        std::fs::write(temporary_directory.path().join("compiler"), "drifted")
+            .expect("manually modify file");
+        let drifted_identity = ContentSha256::identify_content("drifted".as_bytes())
+            .expect("identify content")
+            .to_string();
+        let error = FileIdentitiesManifest::<ContentSha256>::try_from_inputs(
+            &mut host_filesystem,
+            &inputs_config(&[("compiler", &compiler_identity), ("other", "0")]),
+        )
+        .expect_err("drifted input");
+        let message = format!("{:#}", error);
+        assert!(
+            message.contains(&format!(
+                "\"compiler\": expected {}, but found {}",
+                compiler_identity, drifted_identity
+            )),
+            "{}",
+            message
+        );
+        assert!(message.contains("\"other\": expected 0, but file is not an input"));
+    }
+
+    #[test]
+    fn test_inputs_manifest_groups() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        for directory in ["src", "gen/sub", "tool[chain]/include"] {
+            std::fs::create_dir_all(temporary_directory.path().join(directory))
+                .expect("manually create directories");
+        }
+        for path in [
+            "src/main.c",
+            "src/other.c",
+            "gen/a.c",
+            "gen/b.c",
+            "gen/sub/c.c",
+            "tool[chain]/include/x.h",
+            "tool[chain]/include/y.h",
+        ] {
+            std::fs::write(temporary_directory.path().join(path), "")
+                .expect("manually create file");
+        }
+
+        let mut host_filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let inputs_config = InputsTransport {
+            include_files: vec![PathBuf::from("src/main.c")],
+            groups: vec![
+                InputsGroup {
+                    relative_to: Some(PathBuf::from("gen")),
+                    include_globs: vec![String::from("**/*.c")],
+                    exclude_files: vec![PathBuf::from("b.c")],
+                    ..InputsGroup::default()
+                },
+                InputsGroup {
+                    relative_to: Some(PathBuf::from("tool[chain]")),
+                    include_globs: vec![String::from("include/*.h")],
+                    exclude_globs: vec![String::from("include/y.*")],
+                    ..InputsGroup::default()
+                },
+            ],
+            ..InputsTransport::default()
+        };
+        let inputs_manifest: FilesManifest =
+            FilesManifest::try_from((&mut host_filesystem, inputs_config))
+                .expect("create inputs manifest");
+        assert_eq!(
+            FilesManifest::new([
+                "gen/a.c",
+                "gen/sub/c.c",
+                "src/main.c",
+                "tool[chain]/include/x.h",
+            ]),
+            inputs_manifest
+        );
+    }
+
+    #[test]
+    fn test_task_inputs_staged_inputs() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        std::fs::create_dir_all(temporary_directory.path().join("out/gen"))
+            .expect("manually create directories");
+        std::fs::write(temporary_directory.path().join("out/gen/foo.h"), "foo")
+            .expect("manually create file");
+        let mut host_filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let foo_identity =
+            ContentSha256::identify_content("foo".as_bytes()).expect("identify content");
+        let inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("program"),
+            Arguments::empty(),
+            FileIdentitiesManifest::new([(PathBuf::from("out/gen/foo.h"), Some(foo_identity))]),
+            Outputs::empty(),
+        );
+
+        assert!(inputs
+            .clone()
+            .with_staged_inputs([("out/gen/bar.h", "include/bar.h")])
+            .is_err());
+        assert!(inputs
+            .clone()
+            .with_staged_inputs([("out/gen/foo.h", "/include/foo.h")])
+            .is_err());
+        assert!(inputs
+            .clone()
+            .with_staged_inputs([("out/gen/foo.h", "out/gen/foo.h")])
+            .is_err());
+
+        let staged_inputs = inputs
+            .clone()
+            .with_staged_inputs([("out/gen/foo.h", "include/foo.h")])
+            .expect("staged inputs");
+        assert_eq!(
+            Path::new("include/foo.h"),
+            staged_inputs.staged_path(Path::new("out/gen/foo.h"))
+        );
+        assert_ne!(
+            serde_json::to_string(&inputs.as_transport()).expect("serialize inputs"),
+            serde_json::to_string(&staged_inputs.as_transport()).expect("serialize staged inputs")
+        );
+        assert_eq!(
+            staged_inputs,
+            TaskInputs::try_from(staged_inputs.as_transport()).expect("from transport")
+        );
+
+        staged_inputs
+            .stage_inputs(&mut host_filesystem)
+            .expect("stage inputs");
+        assert_eq!(
+            "foo",
+            std::fs::read_to_string(temporary_directory.path().join("include/foo.h"))
+                .expect("read staged input")
+        );
+    }
+
+    #[test]
+    fn test_task_inputs_stdin() {
+        let input_identity =
+            ContentSha256::identify_content("input".as_bytes()).expect("identify content");
+        let inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("program"),
+            Arguments::empty(),
+            FileIdentitiesManifest::new([(PathBuf::from("input.txt"), Some(input_identity))]),
+            Outputs::empty(),
+        );
+
+        assert!(inputs
+            .clone()
+            .with_stdin(Stdin::Path(PathBuf::from("other.txt")))
+            .is_err());
+        assert!(TaskInputsBuilder::<ContentSha256>::new()
+            .program("program")
+            .stdin(Stdin::Path(PathBuf::from("input.txt")))
+            .build()
+            .is_err());
+
+        let path_stdin = inputs
+            .clone()
+            .with_stdin(Stdin::Path(PathBuf::from("input.txt")))
+            .expect("stdin from input file");
+        let content_stdin = inputs
+            .clone()
+            .with_stdin(Stdin::Content(String::from("content")))
+            .expect("stdin from content");
+        assert_eq!(
+            Some(&Stdin::Content(String::from("content"))),
+            content_stdin.stdin()
+        );
+
+        // Standard input is part of the task's identity.
+        let serialized: Vec<_> = [&inputs, &path_stdin, &content_stdin]
+            .into_iter()
+            .map(|inputs| serde_json::to_string(&inputs.as_transport()).expect("serialize"))
+            .collect();
+        assert_ne!(serialized[0], serialized[1]);
+        assert_ne!(serialized[0], serialized[2]);
+        assert_ne!(serialized[1], serialized[2]);
+        assert!(!serialized[0].contains("stdin"));
+
+        for inputs in [path_stdin, content_stdin] {
+            assert_eq!(
+                inputs,
+                TaskInputs::try_from(inputs.as_transport()).expect("from transport")
+            );
+        }
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let system = System::new(
+            Some("Linux"),
+            Some("Linux 6"),
+            Some("6.1.0"),
+            Some("debian"),
+            Some(1 << 30),
+            Some(4),
+        );
+
+        // Metadata written before exit codes, peak memory, hostnames, and usernames were recorded
+        // still reads, without them.
+        let legacy = serde_json::json!({
+            "format_version": serde_json::to_value(crate::schema::FormatVersion::default())
+                .expect("serialize format version"),
+            "timestamp_nanos": 1,
+            "execution_duration_nanos": 2,
+            "system": serde_json::to_value(system.clone().into_transport())
+                .expect("serialize system"),
+        });
+        let metadata: Metadata = serde_json::from_value::<MetadataTransport>(legacy)
+            .expect("deserialize legacy metadata")
+            .into();
+        assert_eq!(Metadata::new(1, 2, system.clone()), metadata);
+        assert_eq!(None, metadata.exit_code());
+        assert_eq!(None, metadata.peak_memory_bytes());
+        assert_eq!(None, metadata.hostname());
+        assert_eq!(None, metadata.username());
+
+        // Absent properties are omitted, rather than written as nulls.
+        let serialized =
+            serde_json::to_value(metadata.into_transport()).expect("serialize metadata");
+        for field in ["exit_code", "peak_memory_bytes", "hostname", "username"] {
+            assert!(serialized.get(field).is_none(), "{} serialized", field);
+        }
+
+        let metadata = Metadata::new(1, 2, system).with_host(
+            Some(String::from("builder")),
+            Some(String::from("developer")),
+        );
+        let serialized =
+            serde_json::to_string(&metadata.clone().into_transport()).expect("serialize metadata");
+        let round_tripped: Metadata = serde_json::from_str::<MetadataTransport>(&serialized)
+            .expect("deserialize metadata")
+            .into();
+        assert_eq!(metadata, round_tripped);
+        assert_eq!(Some("builder"), round_tripped.hostname());
+        assert_eq!(Some("developer"), round_tripped.username());
+    }
+
+    #[test]
+    fn test_listing_round_trip() {
+        let identities: Vec<_> = (0..16)
+            .map(|index| {
+                ContentSha256::identify_content(format!("entry {}", index).as_bytes())
+                    .expect("identify content")
+            })
+            .collect();
+        let transport = Listing::new(identities.clone()).into_transport();
+        let listing = Listing::try_from(transport.clone()).expect("sorted listing");
+        assert_eq!(transport.entries, listing.into_transport().entries);
+
+        let mut duplicated = transport;
+        duplicated.entries.push(identities[0].clone());
+        duplicated.entries.sort();
+        let error = Listing::try_from(duplicated).expect_err("listing with duplicates");
+        assert!(format!("{:#}", error).contains("duplicates"));
+    }
+
+    #[test]
+    fn test_arguments_shell_words() {
+        assert_eq!(
+            Arguments::new(["-o", "out dir/a.o", "it's", "$HOME", "a\\b", "", "x\"y"]),
+            Arguments::from_shell_words(r#"-o 'out dir/a.o'  it\'s "\$HOME" 'a\b' "" x"\"y""#)
+                .expect("split shell words")
+        );
+        for command_line in [
+            "a | b",
+            "a; b",
+            "a > out",
+            "$HOME",
+            "\"$(whoami)\"",
+            "*.c",
+            "# comment",
+            "'unterminated",
+            "trailing\\",
+        ] {
+            assert!(
+                Arguments::from_shell_words(command_line).is_err(),
+                "{}",
+                command_line
+            );
+        }
+
+        let arguments = Arguments::new(["plain", "with space", "it's", "", "$HOME", "a;b"]);
+        assert_eq!(
+            r#"plain 'with space' 'it'\''s' '' '$HOME' 'a;b'"#,
+            arguments.to_shell_words()
+        );
+        assert_eq!(
+            arguments,
+            Arguments::from_shell_words(&arguments.to_shell_words()).expect("round trip")
+        );
+    }
+
+    #[test]
+    fn test_expand_argument_placeholders() {
+        let identity = ContentSha256::identify_content("a".as_bytes()).expect("identify content");
+        let inputs = TaskInputsBuilder::<ContentSha256>::new()
+            .program("/bin/cc")
+            .arguments([
+                "-o",
+                "{outputs}",
+                "{inputs}",
+                "--all={inputs:sep=,}",
+                "--listed={inputs} {outputs}",
+                "{not-a-placeholder}",
+                "{unterminated",
+            ])
+            .input_file("a.c", Some(identity.clone()))
+            .input_file("gen/b.c", Some(identity))
+            .staged_input("gen/b.c", "b.c")
+            .outputs_description(Outputs::new(
+                ["a.out"],
+                Outputs::empty_include_match_transforms(),
+                std::iter::empty(),
+            ))
+            .build()
+            .expect("build task inputs");
+
+        let expanded = inputs
+            .clone()
+            .with_expanded_argument_placeholders()
+            .expect("expand argument placeholders");
+        assert_eq!(
+            vec![
+                "-o",
+                "a.out",
+                "a.c",
+                "b.c",
+                "--all=a.c,b.c",
+                "--listed=a.c b.c a.out",
+                "{not-a-placeholder}",
+                "{unterminated",
+            ],
+            expanded.arguments().collect::<Vec<_>>()
+        );
+        // Only the arguments change.
+        assert_eq!(
+            inputs.input_files().collect::<Vec<_>>(),
+            expanded.input_files().collect::<Vec<_>>()
+        );
+
+        assert!(TaskInputsBuilder::<ContentSha256>::new()
+            .program("/bin/cc")
+            .argument("{inputs:join=,}")
+            .build()
+            .expect("build task inputs")
+            .with_expanded_argument_placeholders()
+            .is_err());
+    }
+
+    #[test]
+    fn test_arguments_argv0() {
+        let inputs = TaskInputsBuilder::<ContentSha256>::new()
+            .program("/bin/busybox")
+            .argv0("ls")
+            .argument("-l")
+            .build()
+            .expect("build task inputs");
+        assert_eq!(Some(&String::from("ls")), inputs.argv0());
+
+        let transport = inputs.as_transport();
+        assert_eq!(Some(String::from("ls")), transport.arguments.argv0);
+        assert_eq!(
+            inputs,
+            TaskInputs::<ContentSha256>::try_from(transport).expect("from transport")
+        );
+        assert!(inputs
+            .clone()
+            .prepend_arguments([String::from("--verbose")].into_iter())
+            .argv0()
+            .is_some());
+
+        // `argv0` is omitted from the transport unless set, so existing identities are unchanged.
+        let without_argv0 =
+            serde_json::to_value(Arguments::new(["-l"]).as_transport()).expect("arguments to JSON");
+        assert_eq!(serde_json::json!({"arguments": ["-l"]}), without_argv0);
+    }
+
+    #[test]
+    fn test_task_inputs_builder() {
+        let identity = ContentSha256::identify_content("a".as_bytes()).expect("identify content");
+        let inputs = TaskInputsBuilder::<ContentSha256>::new()
+            .environment_variable("B", "b")
+            .environment_variable("A", "a")
+            .program("program")
+            .argument("first")
+            .arguments(["second", "third"])
+            .input_file("z", None)
+            .input_file("a", Some(identity.clone()))
+            .staged_input("a", "staged/a")
+            .outputs_description(Outputs::empty().with_stdout_file("stdout"))
+            .build()
+            .expect("build task inputs");
+        assert_eq!(
+            TaskInputs::<ContentSha256>::new(
+                EnvironmentVariables::new([("A", "a"), ("B", "b")]),
+                Program::new("program"),
+                Arguments::new(["first", "second", "third"]),
+                FileIdentitiesManifest::new([
+                    (PathBuf::from("a"), Some(identity)),
+                    (PathBuf::from("z"), None)
+                ]),
+                Outputs::empty().with_stdout_file("stdout"),
+            )
+            .with_staged_inputs([("a", "staged/a")])
+            .expect("staged inputs"),
+            inputs
+        );
+
+        assert!(TaskInputsBuilder::<ContentSha256>::new().build().is_err());
+        assert!(TaskInputsBuilder::<ContentSha256>::new()
+            .program("program")
+            .environment_variable("A", "a")
+            .environment_variable("A", "b")
+            .build()
+            .is_err());
+        assert!(TaskInputsBuilder::<ContentSha256>::new()
+            .program("program")
+            .input_file("a", None)
+            .input_file("a", None)
+            .build()
+            .is_err());
+        assert!(TaskInputsBuilder::<ContentSha256>::new()
+            .program("program")
+            .staged_input("a", "b")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_outputs_verification_transport() {
+        for (verification, name) in [
+            (OutputsVerification::Strict, "strict"),
+            (OutputsVerification::AllowExtra, "allow_extra"),
+            (
+                OutputsVerification::AllowMissingOptional,
+                "allow_missing_optional",
+            ),
+        ] {
+            assert_eq!(
+                serde_json::Value::String(String::from(name)),
+                serde_json::to_value(verification).expect("serialize verification")
+            );
+        }
+        assert!(serde_json::from_str::<OutputsVerification>("\"lenient\"").is_err());
+
+        // Descriptions written before the policy existed keep the behaviour they had then.
+        let transport: OutputsTransport =
+            serde_json::from_str(r#"{"include_files": ["out.o"]}"#).expect("deserialize outputs");
+        assert_eq!(
+            OutputsVerification::AllowMissingOptional,
+            transport.verification
+        );
+        let serialized = serde_json::to_value(&transport).expect("serialize outputs");
+        assert!(serialized.get("verification").is_none());
+
+        // The policy travels with the task, so it distinguishes otherwise identical tasks.
+        let task_inputs = |verification: OutputsVerification| {
+            TaskInputs::<ContentSha256>::new(
+                EnvironmentVariables::empty(),
+                Program::new("program"),
+                Arguments::empty(),
+                FileIdentitiesManifest::empty(),
+                Outputs::new(
+                    ["out.o"],
+                    Outputs::empty_include_match_transforms(),
+                    std::iter::empty(),
+                )
+                .with_verification(verification),
+            )
+        };
+        let strict = task_inputs(OutputsVerification::Strict);
+        let serialized =
+            serde_json::to_value(strict.as_transport()).expect("serialize task inputs");
+        assert_eq!(
+            serde_json::Value::String(String::from("strict")),
+            serialized["outputs_description"]["verification"]
+        );
+        assert_ne!(
+            crate::execute::identify_task_inputs(&strict).expect("identify strict task inputs"),
+            crate::execute::identify_task_inputs(&task_inputs(OutputsVerification::default()))
+                .expect("identify default task inputs")
+        );
+    }
+
+    #[test]
+    fn test_task_outputs_optional_files() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        for path in ["program", "out.o"] {
+            std::fs::write(temporary_directory.path().join(path), path)
+                .expect("manually create file");
+        }
+        let mut host_filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let task_inputs = |outputs: Outputs| {
+            TaskInputs::<ContentSha256>::new(
+                EnvironmentVariables::empty(),
+                Program::new("program"),
+                Arguments::empty(),
+                FileIdentitiesManifest::empty(),
+                outputs,
+            )
+        };
+
+        let inputs = task_inputs(
+            Outputs::new(
+                ["out.o"],
+                Outputs::empty_include_match_transforms(),
+                std::iter::empty(),
+            )
+            .with_optional_files(["out.map"]),
+        );
+        assert_eq!(
+            vec![PathBuf::from("out.map"), PathBuf::from("out.o")],
+            inputs.output_paths().expect("output paths")
+        );
+        let outputs = TaskOutputs::try_from((&mut host_filesystem, &inputs)).expect("task outputs");
+        assert_eq!(
+            vec![PathBuf::from("out.o")],
+            outputs
+                .output_files()
+                .map(|(path, _)| path.clone())
+                .collect::<Vec<_>>()
+        );
+
+        let inputs = task_inputs(Outputs::new(
+            ["out.o", "out.map"],
+            Outputs::empty_include_match_transforms(),
+            std::iter::empty(),
+        ));
+        let error = TaskOutputs::try_from((&mut host_filesystem, &inputs))
+            .expect_err("missing required output");
+        assert!(format!("{:#}", error).contains("required output file, \"out.map\", is missing"));
+
+        // Strict verification requires optional outputs, too.
+        for verification in [OutputsVerification::Strict, OutputsVerification::AllowExtra] {
+            let inputs = task_inputs(
+                Outputs::new(
+                    ["out.o"],
+                    Outputs::empty_include_match_transforms(),
+                    std::iter::empty(),
+                )
+                .with_optional_files(["out.map"])
+                .with_verification(verification),
+            );
+            assert_eq!(
+                verification,
+                inputs.outputs_description().as_transport().verification
+            );
+            let error = TaskOutputs::try_from((&mut host_filesystem, &inputs))
+                .expect_err("missing optional output under strict verification");
+            assert!(
+                format!("{:#}", error).contains("optional output file, \"out.map\", is missing")
+            );
+        }
+
+        assert!(Outputs::try_from(OutputsTransport {
+            include_files: vec![PathBuf::from("out.o")],
+            optional_files: vec![PathBuf::from("out.o")],
+            ..OutputsTransport::empty()
+        })
+        .is_err());
+    }
+    #[test]
+    fn test_task_outputs_symlinks() {
+        let mut memory_filesystem = MemoryFilesystem::new();
+        for path in ["program", "out.o"] {
+            memory_filesystem
+                .write_file(path, path)
+                .expect("write file");
+        }
+        memory_filesystem
+            .create_symlink("out.o", "out.link")
+            .expect("create link");
+        memory_filesystem
+            .create_symlink("missing", "out.dangling")
+            .expect("create dangling link");
+        let inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("program"),
+            Arguments::empty(),
+            FileIdentitiesManifest::empty(),
+            Outputs::new(
+                ["out.dangling", "out.link", "out.o"],
+                Outputs::empty_include_match_transforms(),
+                std::iter::empty(),
+            ),
+        );
+        let outputs =
+            TaskOutputs::try_from((&mut memory_filesystem, &inputs)).expect("task outputs");
+        let out_identity = ContentSha256::identify_content("out.o".as_bytes()).expect("identity");
+        assert_eq!(
+            vec![
+                (
+                    PathBuf::from("out.dangling"),
+                    Some(ContentSha256::identify_symlink_target("missing").expect("identity"))
+                ),
+                (PathBuf::from("out.link"), Some(out_identity.clone())),
+                (PathBuf::from("out.o"), Some(out_identity)),
+            ],
+            outputs.output_files().cloned().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                (&PathBuf::from("out.dangling"), &PathBuf::from("missing")),
+                (&PathBuf::from("out.link"), &PathBuf::from("out.o")),
+            ],
+            outputs.output_symlinks().collect::<Vec<_>>()
+        );
+
+        // Link targets survive transports and deltas, and are omitted when there are none.
+        assert_eq!(
+            outputs,
+            TaskOutputs::try_from(outputs.as_transport()).expect("outputs from transport")
+        );
+        let manifest = FilesManifest::new(["out.link", "out.o"])
+            .into_identified::<ContentSha256, _>(&mut memory_filesystem);
+        assert_eq!(
+            Some(&PathBuf::from("out.o")),
+            manifest.symlink_target("out.link")
+        );
+        assert_eq!(
+            manifest,
+            FileIdentitiesManifest::apply_delta(
+                FileIdentitiesManifest::empty(),
+                manifest.as_root_delta()
+            )
+            .expect("apply delta")
+        );
+        assert!(!serde_json::to_string(
+            &FileIdentitiesManifest::<ContentSha256>::empty().as_transport()
+        )
+        .expect("serialize manifest")
+        .contains("symlinks"));
+    }
 }
@@ -0,0 +1,243 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Canonical JSON encoding for identity computation.
+//!
+//! The bytes that identify a serialized object must not depend on struct field order, map
+//! iteration order, or details of a particular `serde_json` release. The canonical form is:
+//!
+//! - Object keys sorted by byte-wise comparison of their UTF-8 encodings;
+//! - No whitespace outside of strings;
+//! - Integers written in decimal with no sign for non-negative values and no leading zeros;
+//! - Non-integral numbers written as the shortest decimal that round-trips, without an exponent;
+//! - Strings escaping only `"`, `\`, and control characters; `\b`, `\f`, `\n`, `\r`, and `\t` use
+//!   their short forms and other control characters use lowercase `\u00xx`.
+//!
+//! The pretty layout writes the same values in the same order, indenting each array element and
+//! object entry on its own line, for output read by people (e.g., task files and `show` output).
+//! Pretty output is byte-stable, but only the compact layout is used to compute identities.
+
+use serde::ser::Error as _;
+use serde::Serialize;
+use serde_json::Value;
+use std::io::Write;
+
+/// Whitespace layout of canonical JSON output.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Layout {
+    /// No whitespace outside of strings; used for blobs and identities.
+    #[default]
+    Compact,
+    /// Two-space indentation, one array element or object entry per line, and a trailing newline.
+    Pretty,
+}
+
+/// Serializes `data` into its canonical JSON form.
+pub fn to_canonical_string<D: Serialize>(data: &D) -> Result<String, serde_json::Error> {
+    to_canonical_string_with_layout(data, Layout::Compact)
+}
+
+/// Serializes `data` into its canonical JSON form, in the pretty layout.
+pub fn to_canonical_pretty_string<D: Serialize>(data: &D) -> Result<String, serde_json::Error> {
+    to_canonical_string_with_layout(data, Layout::Pretty)
+}
+
+pub fn to_canonical_string_with_layout<D: Serialize>(
+    data: &D,
+    layout: Layout,
+) -> Result<String, serde_json::Error> {
+    let mut bytes = vec![];
+    to_canonical_writer_with_layout(&mut bytes, data, layout)?;
+    Ok(String::from_utf8(bytes).expect("canonical JSON is UTF-8"))
+}
+
+/// Writes the canonical JSON form of `data` to `writer`.
+pub fn to_canonical_writer<W: Write, D: Serialize>(
+    writer: W,
+    data: &D,
+) -> Result<(), serde_json::Error> {
+    to_canonical_writer_with_layout(writer, data, Layout::Compact)
+}
+
+pub fn to_canonical_writer_with_layout<W: Write, D: Serialize>(
+    mut writer: W,
+    data: &D,
+    layout: Layout,
+) -> Result<(), serde_json::Error> {
+    let value = serde_json::to_value(data)?;
+    match layout {
+        Layout::Compact => write_value(&mut writer, &value, None),
+        Layout::Pretty => {
+            write_value(&mut writer, &value, Some(0))?;
+            writer.write_all(b"\n").map_err(serde_json::Error::io)
+        }
+    }
+}
+
+/// Writes a line break followed by indentation for `depth`, when writing the pretty layout.
+fn write_break<W: Write>(writer: &mut W, depth: Option<usize>) -> std::io::Result<()> {
+    match depth {
+        Some(depth) => write!(writer, "\n{:width$}", "", width = 2 * depth),
+        None => Ok(()),
+    }
+}
+
+/// Writes `value` at nesting `depth` in the pretty layout, or in the compact layout when `depth` is
+/// `None`.
+fn write_value<W: Write>(
+    writer: &mut W,
+    value: &Value,
+    depth: Option<usize>,
+) -> Result<(), serde_json::Error> {
+    let inner_depth = depth.map(|depth| depth + 1);
+    match value {
+        Value::Null => writer.write_all(b"null"),
+        Value::Bool(true) => writer.write_all(b"true"),
+        Value::Bool(false) => writer.write_all(b"false"),
+        Value::Number(number) => {
+            if let Some(integer) = number.as_u64() {
+                write!(writer, "{}", integer)
+            } else if let Some(integer) = number.as_i64() {
+                write!(writer, "{}", integer)
+            } else {
+                match number.as_f64() {
+                    Some(float) if float.is_finite() => write!(writer, "{}", float),
+                    _ => {
+                        return Err(serde_json::Error::custom(format!(
+                            "number, {}, has no canonical JSON form",
+                            number
+                        )))
+                    }
+                }
+            }
+        }
+        Value::String(string) => write_string(writer, string),
+        Value::Array(values) => {
+            writer.write_all(b"[").map_err(serde_json::Error::io)?;
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    writer.write_all(b",").map_err(serde_json::Error::io)?;
+                }
+                write_break(writer, inner_depth).map_err(serde_json::Error::io)?;
+                write_value(writer, value, inner_depth)?;
+            }
+            if !values.is_empty() {
+                write_break(writer, depth).map_err(serde_json::Error::io)?;
+            }
+            writer.write_all(b"]")
+        }
+        Value::Object(object) => {
+            let mut entries: Vec<_> = object.iter().collect();
+            entries.sort_by(|(key_a, _), (key_b, _)| key_a.as_bytes().cmp(key_b.as_bytes()));
+            writer.write_all(b"{").map_err(serde_json::Error::io)?;
+            for (index, (key, value)) in entries.iter().enumerate() {
+                if index > 0 {
+                    writer.write_all(b",").map_err(serde_json::Error::io)?;
+                }
+                write_break(writer, inner_depth).map_err(serde_json::Error::io)?;
+                write_string(writer, key).map_err(serde_json::Error::io)?;
+                let separator: &[u8] = if depth.is_some() { b": " } else { b":" };
+                writer.write_all(separator).map_err(serde_json::Error::io)?;
+                write_value(writer, value, inner_depth)?;
+            }
+            if !entries.is_empty() {
+                write_break(writer, depth).map_err(serde_json::Error::io)?;
+            }
+            writer.write_all(b"}")
+        }
+    }
+    .map_err(serde_json::Error::io)
+}
+
+fn write_string<W: Write>(writer: &mut W, string: &str) -> std::io::Result<()> {
+    let mut escaped = String::with_capacity(string.len() + 2);
+    escaped.push('"');
+    for character in string.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\u{08}' => escaped.push_str("\\b"),
+            '\u{0c}' => escaped.push_str("\\f"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            character if character < '\u{20}' => {
+                escaped.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => escaped.push(character),
+        }
+    }
+    escaped.push('"');
+    writer.write_all(escaped.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_canonical_pretty_string;
+    use super::to_canonical_string;
+    use serde::Serialize;
+    use std::collections::HashMap;
+
+    #[derive(Serialize)]
+    struct Unordered {
+        zeta: Vec<i64>,
+        alpha: Option<String>,
+        #[serde(rename = "Beta")]
+        beta: f32,
+        map: HashMap<String, u64>,
+    }
+
+    #[test]
+    fn test_to_canonical_string() {
+        let data = Unordered {
+            zeta: vec![-1, 0, 12],
+            alpha: Some(String::from("quote\" slash\\ tab\t bell\u{07} é")),
+            beta: 0.5,
+            map: [
+                (String::from("b"), 2),
+                (String::from("a"), u64::MAX),
+                (String::from("ab"), 1),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        assert_eq!(
+            r#"{"Beta":0.5,"alpha":"quote\" slash\\ tab\t bell\u0007 é","map":{"a":18446744073709551615,"ab":1,"b":2},"zeta":[-1,0,12]}"#,
+            to_canonical_string(&data).expect("canonical JSON")
+        );
+        assert_eq!(
+            "[0.0000001,100000000000000000000,null,true]",
+            to_canonical_string(&(1e-7f64, 1e20f64, (), true)).expect("canonical JSON")
+        );
+    }
+
+    #[test]
+    fn test_to_canonical_pretty_string() {
+        let data = Unordered {
+            zeta: vec![-1, 12],
+            alpha: None,
+            beta: 0.5,
+            map: HashMap::new(),
+        };
+        assert_eq!(
+            r#"{
+  "Beta": 0.5,
+  "alpha": null,
+  "map": {},
+  "zeta": [
+    -1,
+    12
+  ]
+}
+"#,
+            to_canonical_pretty_string(&data).expect("pretty canonical JSON")
+        );
+        let pretty = to_canonical_pretty_string(&data).expect("pretty canonical JSON");
+        let reparsed: serde_json::Value = serde_json::from_str(&pretty).expect("parse pretty");
+        assert_eq!(
+            to_canonical_string(&data).expect("canonical JSON"),
+            to_canonical_string(&reparsed).expect("canonical JSON")
+        );
+    }
+}
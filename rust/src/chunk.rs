@@ -0,0 +1,152 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+use std::io::ErrorKind;
+use std::io::Read;
+
+/// Chunks are never shorter than this, except for the final chunk of a stream.
+pub const MIN_CHUNK_SIZE_BYTES: usize = 2 << 10;
+
+/// Chunks are never longer than this.
+pub const MAX_CHUNK_SIZE_BYTES: usize = 64 << 10;
+
+/// A chunk boundary occurs wherever the rolling hash has all of these bits clear, yielding chunks
+/// of roughly 8KiB on average.
+const CHUNK_BOUNDARY_MASK: u64 = !(u64::MAX >> 13);
+
+const READ_SIZE_BYTES: usize = 16 << 10;
+
+/// Per-byte values for the gear rolling hash, generated by splitmix64 so that chunk boundaries are
+/// stable across builds and platforms.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut value = state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = value ^ (value >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Splits a stream into content-defined chunks. Boundaries depend only on nearby content, so an
+/// insertion or deletion perturbs only the chunks around it and the remaining chunks (and their
+/// identities) are shared with the original stream.
+pub struct Chunker<R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: vec![],
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for Chunker<R> {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(end) = find_chunk_boundary(&self.buffer) {
+                let remainder = self.buffer.split_off(end);
+                return Some(Ok(std::mem::replace(&mut self.buffer, remainder)));
+            }
+            if self.done {
+                if self.buffer.is_empty() {
+                    return None;
+                }
+                return Some(Ok(std::mem::take(&mut self.buffer)));
+            }
+
+            let length = self.buffer.len();
+            self.buffer.resize(length + READ_SIZE_BYTES, 0);
+            match self.reader.read(&mut self.buffer[length..]) {
+                Ok(read_length) => {
+                    self.buffer.truncate(length + read_length);
+                    self.done = read_length == 0;
+                }
+                Err(err) => {
+                    self.buffer.truncate(length);
+                    if err.kind() != ErrorKind::Interrupted {
+                        return Some(Err(err));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn find_chunk_boundary(data: &[u8]) -> Option<usize> {
+    let mut hash: u64 = 0;
+    for (i, byte) in data.iter().take(MAX_CHUNK_SIZE_BYTES).enumerate() {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        if i + 1 >= MIN_CHUNK_SIZE_BYTES && hash & CHUNK_BOUNDARY_MASK == 0 {
+            return Some(i + 1);
+        }
+    }
+    if data.len() >= MAX_CHUNK_SIZE_BYTES {
+        Some(MAX_CHUNK_SIZE_BYTES)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Chunker;
+    use super::MAX_CHUNK_SIZE_BYTES;
+    use super::MIN_CHUNK_SIZE_BYTES;
+    use rand::Rng as _;
+    use rand::SeedableRng as _;
+    use std::collections::HashSet;
+
+    fn chunks(content: &[u8]) -> Vec<Vec<u8>> {
+        Chunker::new(content)
+            .collect::<std::io::Result<Vec<_>>>()
+            .expect("chunk content")
+    }
+
+    #[test]
+    fn test_chunker() {
+        assert!(chunks(&[]).is_empty());
+        assert_eq!(vec![b"short".to_vec()], chunks(b"short"));
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut content = vec![0u8; 1 << 20];
+        rng.fill(content.as_mut_slice());
+
+        let original_chunks = chunks(&content);
+        assert_eq!(content, original_chunks.concat());
+        let (last, rest) = original_chunks.split_last().expect("at least one chunk");
+        assert!(last.len() <= MAX_CHUNK_SIZE_BYTES);
+        for chunk in rest {
+            assert!(chunk.len() >= MIN_CHUNK_SIZE_BYTES);
+            assert!(chunk.len() <= MAX_CHUNK_SIZE_BYTES);
+        }
+
+        // An insertion near the start of the content leaves most chunks intact.
+        let mut edited_content = content.clone();
+        edited_content.splice(100..100, b"inserted".iter().copied());
+        let edited_chunks = chunks(&edited_content);
+        assert_eq!(edited_content, edited_chunks.concat());
+        let original_chunks: HashSet<_> = original_chunks.into_iter().collect();
+        let shared = edited_chunks
+            .iter()
+            .filter(|chunk| original_chunks.contains(*chunk))
+            .count();
+        assert!(shared + 2 >= original_chunks.len());
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Exports of tasks recorded in a cache to formats that CI systems visualize: Chrome trace events
+//! (one span per task) and JUnit XML (one test case per task).
+
+use crate::canonical::Metadata;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::transport::ChromeTrace;
+use crate::transport::ChromeTraceEvent;
+use crate::transport::TaskSummary;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+
+/// Name of the JUnit test suite, and category of Chrome trace events, for exported tasks.
+pub const REPORT_NAME: &str = "artifact-executor";
+
+/// A task recorded in a cache, as exported to CI tools.
+#[derive(Clone, Debug)]
+pub struct TaskRecord<IS: IdentitySchemeApi> {
+    /// Identity of the task's inputs blob.
+    pub inputs_identity: IS::Identity,
+    pub metadata: Metadata,
+    /// The task's inputs and outputs, when available.
+    pub summary: Option<TaskSummary<IS>>,
+}
+
+impl<IS: IdentitySchemeApi> TaskRecord<IS> {
+    /// Name under which the task is reported: its label, its program, or, failing both, its
+    /// inputs identity.
+    pub fn name(&self) -> String {
+        if let Some(label) = self.metadata.labels().label() {
+            return label.to_string();
+        }
+        match self.summary.as_ref() {
+            Some(summary) => summary.input.program.program.display().to_string(),
+            None => self.inputs_identity.to_string(),
+        }
+    }
+
+    fn failed(&self) -> bool {
+        matches!(self.metadata.exit_code(), Some(exit_code) if exit_code != 0)
+    }
+}
+
+/// Converts `records` to a Chrome trace, with one span per task. Tasks run on the same host share a
+/// thread id, so that each host appears as a separate track.
+pub fn to_chrome_trace<IS: IdentitySchemeApi>(records: &[TaskRecord<IS>]) -> ChromeTrace {
+    let hosts: BTreeSet<&str> = records
+        .iter()
+        .map(|record| record.metadata.hostname().unwrap_or(""))
+        .collect();
+    let host_ids: BTreeMap<&str, u64> = hosts
+        .into_iter()
+        .enumerate()
+        .map(|(index, host)| (host, index as u64 + 1))
+        .collect();
+
+    let trace_events = records
+        .iter()
+        .map(|record| {
+            let metadata = &record.metadata;
+            let mut args = BTreeMap::new();
+            args.insert(
+                String::from("inputs_identity"),
+                serde_json::Value::from(record.inputs_identity.to_string()),
+            );
+            if let Some(exit_code) = metadata.exit_code() {
+                args.insert(
+                    String::from("exit_code"),
+                    serde_json::Value::from(exit_code),
+                );
+            }
+            if let Some(hostname) = metadata.hostname() {
+                args.insert(String::from("hostname"), serde_json::Value::from(hostname));
+            }
+            let tags: Vec<_> = metadata.labels().tags().cloned().collect();
+            if !tags.is_empty() {
+                args.insert(String::from("tags"), serde_json::Value::from(tags));
+            }
+            if let Some(summary) = record.summary.as_ref() {
+                args.insert(
+                    String::from("output_files"),
+                    serde_json::Value::from(summary.output.output_files.identities.len()),
+                );
+            }
+            ChromeTraceEvent {
+                name: record.name(),
+                cat: String::from(REPORT_NAME),
+                ph: String::from("X"),
+                ts: metadata.timestamp_nanos() as f64 / 1000.0,
+                dur: metadata.execution_duration_nanos() as f64 / 1000.0,
+                pid: 1,
+                tid: host_ids[metadata.hostname().unwrap_or("")],
+                args,
+            }
+        })
+        .collect();
+
+    ChromeTrace {
+        trace_events,
+        display_time_unit: Some(String::from("ms")),
+    }
+}
+
+/// Converts `records` to a JUnit XML report, with one test case per task. Tasks that exited with a
+/// non-zero exit code are reported as failures.
+pub fn to_junit_xml<IS: IdentitySchemeApi>(records: &[TaskRecord<IS>]) -> String {
+    let failures = records.iter().filter(|record| record.failed()).count();
+    let total_seconds: f64 = records
+        .iter()
+        .map(|record| seconds(record.metadata.execution_duration_nanos()))
+        .sum();
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    // Writing to a `String` cannot fail.
+    let _ = writeln!(
+        xml,
+        "<testsuites tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+        records.len(),
+        failures,
+        total_seconds
+    );
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+        REPORT_NAME,
+        records.len(),
+        failures,
+        total_seconds
+    );
+    for record in records.iter() {
+        let _ = write!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+            escape_xml(&record.name()),
+            escape_xml(&record.inputs_identity.to_string()),
+            seconds(record.metadata.execution_duration_nanos())
+        );
+        if record.failed() {
+            let _ = writeln!(
+                xml,
+                ">\n      <failure message=\"exited with code {}\"/>\n    </testcase>",
+                record.metadata.exit_code().unwrap_or_default()
+            );
+        } else {
+            xml.push_str("/>\n");
+        }
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+fn seconds(nanos: u128) -> f64 {
+    nanos as f64 / 1_000_000_000.0
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_chrome_trace;
+    use super::to_junit_xml;
+    use super::TaskRecord;
+    use crate::canonical::Metadata;
+    use crate::canonical::SystemCapture;
+    use crate::canonical::TaskLabels;
+    use crate::identity::IdentityScheme as _;
+    use crate::runner::ExecutionResult;
+    use crate::transport::ContentSha256;
+    use std::os::unix::process::ExitStatusExt as _;
+    use std::process::ExitStatus;
+    use std::time::Duration;
+    use sysinfo::SystemExt as _;
+
+    fn record(label: &str, start_nanos: i64, exit_code: i32) -> TaskRecord<ContentSha256> {
+        let system = sysinfo::System::new();
+        TaskRecord {
+            inputs_identity: ContentSha256::identify_content(label.as_bytes()).expect("identity"),
+            metadata: Metadata::new(
+                start_nanos,
+                2_000_000,
+                crate::canonical::System::capture(&system, &SystemCapture::none()),
+            )
+            .with_execution_result(&ExecutionResult {
+                // Wait statuses encode exit codes in their second byte.
+                status: Some(ExitStatus::from_raw(exit_code << 8)),
+                duration: Duration::from_millis(2),
+                peak_memory_bytes: None,
+                interruption: None,
+            })
+            .with_host(Some(String::from("builder")), None)
+            .with_labels(TaskLabels::new(Some(String::from(label)), ["release"])),
+            summary: None,
+        }
+    }
+
+    #[test]
+    fn test_chrome_trace() {
+        let trace = to_chrome_trace(&[record("protoc", 1_000_000, 0), record("cc", 3_000_000, 0)]);
+        assert_eq!(2, trace.trace_events.len());
+        let event = &trace.trace_events[0];
+        assert_eq!("protoc", event.name);
+        assert_eq!("X", event.ph);
+        assert_eq!(1000.0, event.ts);
+        assert_eq!(2000.0, event.dur);
+        assert_eq!(trace.trace_events[0].tid, trace.trace_events[1].tid);
+        assert_eq!(
+            Some(&serde_json::json!(["release"])),
+            event.args.get("tags")
+        );
+        let json = serde_json::to_value(&trace).expect("serialize trace");
+        assert!(json.get("traceEvents").is_some());
+    }
+
+    #[test]
+    fn test_junit_xml() {
+        let xml = to_junit_xml(&[record("protoc", 0, 0), record("a<b>", 0, 2)]);
+        assert!(xml.contains("<testsuites tests=\"2\" failures=\"1\" time=\"0.004\">"));
+        assert!(xml.contains("<testcase name=\"protoc\""));
+        assert!(xml.contains("<testcase name=\"a&lt;b&gt;\""));
+        assert!(xml.contains("<failure message=\"exited with code 2\"/>"));
+        assert_eq!(1, xml.matches("<failure").count());
+    }
+}
@@ -7,33 +7,51 @@ use differ::Tag;
 use std::fmt::Debug;
 use std::hash::Hash;
 
-pub fn diff_items_to_string<'a, T: Debug + Eq + Hash>(
-    description: &str,
-    a: &'a Vec<T>,
-    b: &'a Vec<T>,
-) -> String {
+/// An item of a sequence diff.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DiffItem<T> {
+    Unchanged(T),
+    Added(T),
+    Removed(T),
+}
+
+/// Computes the diff from `a` to `b` as a sequence of unchanged, added, and removed items. Replaced
+/// items are reported as removals followed by additions.
+pub fn diff_items<'a, T: Eq + Hash>(a: &'a [T], b: &'a [T]) -> Vec<DiffItem<&'a T>> {
     let differ = Differ::new(a, b);
-    let mut string = String::from(description);
+    let mut items = vec![];
 
     for span in differ.spans() {
         match span.tag {
-            Tag::Equal => string = push_diff_items(string, "  ", &a[span.a_start..span.a_end]),
-            Tag::Insert => string = push_diff_items(string, "+ ", &b[span.b_start..span.b_end]),
-            Tag::Delete => string = push_diff_items(string, "- ", &a[span.a_start..span.a_end]),
+            Tag::Equal => items.extend(a[span.a_start..span.a_end].iter().map(DiffItem::Unchanged)),
+            Tag::Insert => items.extend(b[span.b_start..span.b_end].iter().map(DiffItem::Added)),
+            Tag::Delete => items.extend(a[span.a_start..span.a_end].iter().map(DiffItem::Removed)),
             Tag::Replace => {
-                string = push_diff_items(string, "- ", &a[span.a_start..span.a_end]);
-                string = push_diff_items(string, "+ ", &b[span.b_start..span.b_end]);
+                items.extend(a[span.a_start..span.a_end].iter().map(DiffItem::Removed));
+                items.extend(b[span.b_start..span.b_end].iter().map(DiffItem::Added));
             }
         }
     }
 
-    string
+    items
 }
 
-fn push_diff_items<T: Debug + Eq + Hash>(mut string: String, prefix: &str, items: &[T]) -> String {
-    for item in items {
+pub fn diff_items_to_string<'a, T: Debug + Eq + Hash>(
+    description: &str,
+    a: &'a Vec<T>,
+    b: &'a Vec<T>,
+) -> String {
+    let mut string = String::from(description);
+
+    for item in diff_items(a, b) {
+        let (prefix, item) = match item {
+            DiffItem::Unchanged(item) => ("  ", item),
+            DiffItem::Added(item) => ("+ ", item),
+            DiffItem::Removed(item) => ("- ", item),
+        };
         string.push_str(prefix);
         string.push_str(&format!("{:?}\n", item));
     }
+
     string
 }
@@ -0,0 +1,467 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! A long-lived process that keeps a task executor (see `crate::facade::ArtifactExecutor`) and a
+//! fingerprint database (see `crate::fingerprint`) in memory, answering requests from clients over a
+//! Unix domain socket. Build systems that would otherwise start the executor for every task avoid
+//! re-opening the cache and re-hashing unchanged files on each invocation, and can have the daemon
+//! execute tasks instead of spawning the executor per task. Outputs are looked up through the same
+//! executor that records them.
+//!
+//! The protocol is line-delimited JSON: Each request is a `transport::DaemonRequest` on one line,
+//! answered by a `transport::DaemonResponse` on one line. A connection may carry any number of
+//! requests, and connections are served one at a time.
+
+use crate::blob::CanonicalJSON;
+use crate::blob::FileFormat as FileFormatApi;
+use crate::blob::ReadDeserializer as ReadDeserializerApi;
+use crate::blob::StringSerializer as StringSerializerApi;
+use crate::blob::WriteSerializer as WriteSerializerApi;
+use crate::canonical::FileIdentitiesManifest;
+use crate::canonical::FilesManifest;
+use crate::facade::ArtifactExecutor;
+use crate::fingerprint::FingerprintDatabase;
+use crate::fs::Filesystem as _;
+use crate::fs::HostFilesystem;
+use crate::identity::AsTransport as _;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::task_file::read_task_file;
+use crate::task_file::task_working_directory;
+use crate::transport::DaemonRequest;
+use crate::transport::DaemonResponse;
+use anyhow::Context as _;
+use serde::de::value::StrDeserializer;
+use serde::Deserialize as _;
+use std::convert::TryFrom as _;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::ops::ControlFlow;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Default path, relative to the cache directory, of the daemon's socket.
+pub const DEFAULT_SOCKET_NAME: &str = "daemon.sock";
+
+/// Serves `DaemonRequest`s against a cache directory and the host filesystem.
+pub struct Daemon<
+    IdentityScheme: IdentitySchemeApi,
+    Serialization: FileFormatApi + ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+> {
+    working_directory: HostFilesystem,
+    fingerprints: FingerprintDatabase<IdentityScheme>,
+    executor: ArtifactExecutor<IdentityScheme, Serialization>,
+}
+
+impl<
+        IdentityScheme: IdentitySchemeApi,
+        Serialization: FileFormatApi + ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+    > Daemon<IdentityScheme, Serialization>
+{
+    /// Serves the cache in `cache_directory`, resolving relative paths in requests against
+    /// `working_directory`.
+    pub fn new(
+        mut working_directory: HostFilesystem,
+        mut cache_directory: HostFilesystem,
+    ) -> anyhow::Result<Self> {
+        let executor = ArtifactExecutor::builder()
+            .identity_scheme::<IdentityScheme>()
+            .serialization::<Serialization>()
+            .working_directory(
+                working_directory
+                    .working_directory()
+                    .expect("host filesystem has a working directory"),
+            )
+            .cache_directory(
+                cache_directory
+                    .working_directory()
+                    .expect("host filesystem has a working directory"),
+            )
+            .build()
+            .context("creating task executor")?;
+        Ok(Self {
+            working_directory,
+            fingerprints: FingerprintDatabase::new(),
+            executor,
+        })
+    }
+
+    /// Serves connections accepted by `listener` until a client requests shutdown. Failures to
+    /// serve an individual connection are logged and do not stop the daemon.
+    pub fn serve(&mut self, listener: UnixListener) -> anyhow::Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream.context("accepting connection")?;
+            let reader = BufReader::new(
+                stream
+                    .try_clone()
+                    .context("cloning connection for reading")?,
+            );
+            match self.handle(reader, stream) {
+                Ok(ControlFlow::Break(())) => return Ok(()),
+                Ok(ControlFlow::Continue(())) => {}
+                Err(error) => tracing::warn!("failed to serve connection: {:#}", error),
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers each request read from `reader` until it is exhausted, or a shutdown is requested.
+    pub fn handle<R: BufRead, W: Write>(
+        &mut self,
+        reader: R,
+        mut writer: W,
+    ) -> anyhow::Result<ControlFlow<()>> {
+        for line in reader.lines() {
+            let line = line.context("reading request")?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (response, control_flow) = match serde_json::from_str::<DaemonRequest>(&line) {
+                Ok(DaemonRequest::Shutdown) => {
+                    (DaemonResponse::ShuttingDown, ControlFlow::Break(()))
+                }
+                Ok(request) => (
+                    self.respond(request)
+                        .unwrap_or_else(|error| DaemonResponse::Error {
+                            message: format!("{:#}", error),
+                        }),
+                    ControlFlow::Continue(()),
+                ),
+                Err(error) => (
+                    DaemonResponse::Error {
+                        message: format!("malformed request: {}", error),
+                    },
+                    ControlFlow::Continue(()),
+                ),
+            };
+            serde_json::to_writer(&mut writer, &response).context("writing response")?;
+            writer.write_all(b"\n").context("writing response")?;
+            writer.flush().context("writing response")?;
+            if control_flow.is_break() {
+                return Ok(control_flow);
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn respond(&mut self, request: DaemonRequest) -> anyhow::Result<DaemonResponse> {
+        match request {
+            DaemonRequest::Ping => Ok(DaemonResponse::Pong {
+                process_id: std::process::id(),
+            }),
+            DaemonRequest::IdentifyFiles { paths } => {
+                let identities = paths
+                    .into_iter()
+                    .map(|path| {
+                        let absolute_path = self.working_directory.get_absolute_path(&path);
+                        let identity = self
+                            .fingerprints
+                            .identify_file(&mut self.working_directory, &absolute_path)
+                            .ok()
+                            .map(|identity| identity.to_string());
+                        (path, identity)
+                    })
+                    .collect();
+                Ok(DaemonResponse::FileIdentities { identities })
+            }
+            DaemonRequest::IdentifyInputs { task_file } => self.identify_inputs(&task_file),
+            DaemonRequest::LookupOutputs { inputs_identity } => {
+                let inputs_identity =
+                    IdentityScheme::Identity::deserialize(
+                        StrDeserializer::<serde::de::value::Error>::new(&inputs_identity),
+                    )
+                    .map_err(|_| anyhow::anyhow!("malformed identity: {:?}", inputs_identity))?;
+                let identities = self
+                    .executor
+                    .cached_outputs(&inputs_identity)?
+                    .map(|outputs| {
+                        outputs
+                            .output_files()
+                            .map(|(path, identity)| {
+                                (
+                                    path.clone(),
+                                    identity.as_ref().map(|identity| identity.to_string()),
+                                )
+                            })
+                            .collect()
+                    });
+                Ok(DaemonResponse::Outputs { identities })
+            }
+            DaemonRequest::ExecuteTask { task_file } => {
+                let outputs = self.executor.run_task_file(&task_file)?;
+                Ok(DaemonResponse::TaskOutputs {
+                    identities: outputs
+                        .output_files()
+                        .map(|(path, identity)| {
+                            (
+                                path.clone(),
+                                identity.as_ref().map(|identity| identity.to_string()),
+                            )
+                        })
+                        .collect(),
+                })
+            }
+            DaemonRequest::Shutdown => Ok(DaemonResponse::ShuttingDown),
+        }
+    }
+
+    fn identify_inputs(&mut self, task_file: &Path) -> anyhow::Result<DaemonResponse> {
+        let task = read_task_file(&mut self.working_directory, task_file)?;
+        let root = self.working_directory.get_absolute_path(
+            task_working_directory(task_file, &task).unwrap_or_else(|| {
+                task_file
+                    .parent()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default()
+            }),
+        );
+        let mut task_filesystem = HostFilesystem::try_new(root.clone())?;
+        let files = FilesManifest::try_from((&mut task_filesystem, &task.inputs))
+            .context("resolving input files")?;
+        let manifest = FileIdentitiesManifest::<IdentityScheme>::new(files.paths().map(|path| {
+            let identity = self
+                .fingerprints
+                .identify_file(&mut task_filesystem, root.join(path))
+                .ok();
+            (path.clone(), identity)
+        }));
+        manifest.check_expected_identities(&task.inputs.expected_identities)?;
+
+        let manifest_contents = CanonicalJSON::to_string(&manifest.as_transport())
+            .map_err(anyhow::Error::from)
+            .context("serializing input files manifest")?;
+        let manifest_identity = IdentityScheme::identify_content(manifest_contents.as_bytes())?;
+        Ok(DaemonResponse::InputFiles {
+            manifest_identity: manifest_identity.to_string(),
+            identities: manifest
+                .identities()
+                .map(|(path, identity)| {
+                    (
+                        path.clone(),
+                        identity.as_ref().map(|identity| identity.to_string()),
+                    )
+                })
+                .collect(),
+        })
+    }
+}
+
+/// A connection to a daemon.
+pub struct DaemonClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl DaemonClient {
+    pub fn connect<P: AsRef<Path>>(socket: P) -> anyhow::Result<Self> {
+        let socket = socket.as_ref();
+        let writer = UnixStream::connect(socket)
+            .with_context(|| format!("connecting to daemon at {:?}", socket))?;
+        let reader = BufReader::new(
+            writer
+                .try_clone()
+                .context("cloning connection for reading")?,
+        );
+        Ok(Self { reader, writer })
+    }
+
+    /// Sends `request` and waits for its response. `DaemonResponse::Error` responses are returned
+    /// as errors.
+    pub fn request(&mut self, request: &DaemonRequest) -> anyhow::Result<DaemonResponse> {
+        let mut line = serde_json::to_string(request).context("serializing request")?;
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .context("sending request")?;
+        let mut line = String::new();
+        if self
+            .reader
+            .read_line(&mut line)
+            .context("receiving response")?
+            == 0
+        {
+            anyhow::bail!("daemon closed connection");
+        }
+        match serde_json::from_str(&line).context("parsing response")? {
+            DaemonResponse::Error { message } => Err(anyhow::anyhow!(message)),
+            response => Ok(response),
+        }
+    }
+}
+
+/// Resolves the daemon socket path: `socket` if given, otherwise `DEFAULT_SOCKET_NAME` in
+/// `cache_directory`.
+pub fn socket_path(cache_directory: &Path, socket: Option<&Path>) -> PathBuf {
+    socket
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| cache_directory.join(DEFAULT_SOCKET_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Daemon;
+    use super::DaemonClient;
+    use crate::blob::JSON;
+    use crate::execute::identify_task_inputs;
+    use crate::facade::ArtifactExecutor;
+    use crate::fs::HostFilesystem;
+    use crate::identity::IdentityScheme as _;
+    use crate::transport::ContentSha256;
+    use crate::transport::DaemonRequest;
+    use crate::transport::DaemonResponse;
+    use crate::transport::Task;
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_daemon() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let directory = temporary_directory.path().to_path_buf();
+        std::fs::create_dir(directory.join("cache")).expect("create cache directory");
+        std::fs::create_dir(directory.join("project")).expect("create project directory");
+        std::fs::write(directory.join("project/a.txt"), "a").expect("write a.txt");
+        std::fs::write(
+            directory.join("project/copy.json"),
+            r#"{
+                "working_directory": ".",
+                "environment_variables": [],
+                "program": "/bin/sh",
+                "arguments": ["-c", "cat a.txt a.txt > copy.txt; echo run >> runs.txt"],
+                "inputs": { "include_files": ["a.txt"] },
+                "outputs": { "include_files": ["copy.txt"] }
+            }"#,
+        )
+        .expect("write copy.json");
+        std::fs::write(
+            directory.join("project/task.json"),
+            r#"{
+                "environment_variables": [],
+                "program": "/bin/cat",
+                "arguments": ["a.txt"],
+                "inputs": { "include_globs": ["*.txt"] },
+                "outputs": {}
+            }"#,
+        )
+        .expect("write task.json");
+        let working_directory =
+            HostFilesystem::try_new(directory.clone()).expect("working directory filesystem");
+        let cache_directory =
+            HostFilesystem::try_new(directory.join("cache")).expect("cache filesystem");
+        let mut daemon = Daemon::<ContentSha256, JSON>::new(working_directory, cache_directory)
+            .expect("create daemon");
+
+        let socket = directory.join("daemon.sock");
+        let listener = UnixListener::bind(&socket).expect("bind socket");
+        let server = std::thread::spawn(move || daemon.serve(listener));
+
+        let mut client = DaemonClient::connect(&socket).expect("connect to daemon");
+        assert_eq!(
+            DaemonResponse::Pong {
+                process_id: std::process::id()
+            },
+            client.request(&DaemonRequest::Ping).expect("ping")
+        );
+        let a_identity = ContentSha256::identify_content(&b"a"[..])
+            .expect("identify content")
+            .to_string();
+        assert_eq!(
+            DaemonResponse::FileIdentities {
+                identities: vec![
+                    (PathBuf::from("project/a.txt"), Some(a_identity.clone())),
+                    (PathBuf::from("missing.txt"), None),
+                ]
+            },
+            client
+                .request(&DaemonRequest::IdentifyFiles {
+                    paths: vec![PathBuf::from("project/a.txt"), PathBuf::from("missing.txt")]
+                })
+                .expect("identify files")
+        );
+        match client
+            .request(&DaemonRequest::IdentifyInputs {
+                task_file: PathBuf::from("project/task.json"),
+            })
+            .expect("identify inputs")
+        {
+            DaemonResponse::InputFiles { identities, .. } => assert_eq!(
+                vec![(PathBuf::from("a.txt"), Some(a_identity.clone()))],
+                identities
+            ),
+            response => panic!("unexpected response: {:?}", response),
+        }
+        assert_eq!(
+            DaemonResponse::Outputs { identities: None },
+            client
+                .request(&DaemonRequest::LookupOutputs {
+                    inputs_identity: a_identity
+                })
+                .expect("lookup outputs")
+        );
+        assert!(client
+            .request(&DaemonRequest::LookupOutputs {
+                inputs_identity: String::from("not an identity")
+            })
+            .is_err());
+
+        // Executed tasks are loaded from the cache when requested again.
+        let copy_identity = ContentSha256::identify_content(&b"aa"[..])
+            .expect("identify content")
+            .to_string();
+        for _ in 0..2 {
+            assert_eq!(
+                DaemonResponse::TaskOutputs {
+                    identities: vec![(PathBuf::from("copy.txt"), Some(copy_identity.clone()))]
+                },
+                client
+                    .request(&DaemonRequest::ExecuteTask {
+                        task_file: PathBuf::from("project/copy.json"),
+                    })
+                    .expect("execute task")
+            );
+        }
+        assert_eq!(
+            "run\n",
+            std::fs::read_to_string(directory.join("project/runs.txt")).expect("read runs.txt")
+        );
+
+        // Outputs of executed tasks are found by looking them up.
+        let task: Task = serde_json::from_str(
+            &std::fs::read_to_string(directory.join("project/copy.json")).expect("read copy.json"),
+        )
+        .expect("parse copy.json");
+        let copy_inputs = ArtifactExecutor::builder()
+            .working_directory(directory.join("project"))
+            .cache_directory(directory.join("cache"))
+            .build()
+            .expect("build executor")
+            .task_inputs(&task)
+            .expect("resolve task inputs");
+        assert_eq!(
+            DaemonResponse::Outputs {
+                identities: Some(vec![(PathBuf::from("copy.txt"), Some(copy_identity))])
+            },
+            client
+                .request(&DaemonRequest::LookupOutputs {
+                    inputs_identity: identify_task_inputs(&copy_inputs)
+                        .expect("identify task inputs")
+                        .to_string()
+                })
+                .expect("lookup outputs of executed task")
+        );
+        drop(client);
+
+        // A new connection, as from a subsequent invocation, reaches the same daemon.
+        let mut client = DaemonClient::connect(&socket).expect("reconnect to daemon");
+        assert_eq!(
+            DaemonResponse::ShuttingDown,
+            client.request(&DaemonRequest::Shutdown).expect("shut down")
+        );
+        server
+            .join()
+            .expect("join daemon thread")
+            .expect("serve daemon");
+    }
+}
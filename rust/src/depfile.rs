@@ -0,0 +1,196 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+use std::path::PathBuf;
+
+/// A single rule from a Makefile-style dependency file, as emitted by `gcc -MD`, `clang -MD`, and
+/// similar tools: `targets...: prerequisites...`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DepfileRule {
+    pub targets: Vec<PathBuf>,
+    pub prerequisites: Vec<PathBuf>,
+}
+
+/// Parses the contents of a dependency file into its rules. Handles backslash-newline line
+/// continuations, `#` comments, `\ ` and `\#` escapes, `$$` escapes, and Windows drive-letter
+/// paths (a `:` is only a rule separator when followed by whitespace or the end of the line).
+pub fn parse_depfile(contents: &str) -> anyhow::Result<Vec<DepfileRule>> {
+    let mut rules = vec![];
+    for (line_index, line) in join_continuations(contents).iter().enumerate() {
+        let words = split_words(line);
+        if words.is_empty() {
+            continue;
+        }
+
+        let mut targets = vec![];
+        let mut prerequisites = vec![];
+        let mut found_separator = false;
+        for word in words {
+            match word {
+                Word::Separator => {
+                    if found_separator {
+                        anyhow::bail!(
+                            "depfile rule {} contains more than one target separator",
+                            line_index + 1
+                        );
+                    }
+                    found_separator = true;
+                }
+                Word::Path(path) => {
+                    if found_separator {
+                        prerequisites.push(PathBuf::from(path));
+                    } else {
+                        targets.push(PathBuf::from(path));
+                    }
+                }
+            }
+        }
+        if !found_separator || targets.is_empty() {
+            anyhow::bail!(
+                "depfile rule {} is not of the form `targets: prerequisites`: {:?}",
+                line_index + 1,
+                line
+            );
+        }
+        rules.push(DepfileRule {
+            targets,
+            prerequisites,
+        });
+    }
+    Ok(rules)
+}
+
+/// Parses the contents of a dependency file, returning every prerequisite of every rule in order of
+/// first appearance.
+pub fn parse_depfile_prerequisites(contents: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let mut prerequisites: Vec<PathBuf> = vec![];
+    for rule in parse_depfile(contents)? {
+        for prerequisite in rule.prerequisites {
+            if !prerequisites.contains(&prerequisite) {
+                prerequisites.push(prerequisite);
+            }
+        }
+    }
+    Ok(prerequisites)
+}
+
+/// Joins lines that end in an (unescaped) backslash, and strips comments.
+fn join_continuations(contents: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut current = String::new();
+    for line in contents.lines() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let trailing_backslashes = line.len() - line.trim_end_matches('\\').len();
+        if trailing_backslashes % 2 == 1 {
+            current.push_str(&line[..line.len() - 1]);
+            current.push(' ');
+        } else {
+            current.push_str(line);
+            lines.push(strip_comment(&current));
+            current.clear();
+        }
+    }
+    if !current.is_empty() {
+        lines.push(strip_comment(&current));
+    }
+    lines
+}
+
+fn strip_comment(line: &str) -> String {
+    let mut previous = None;
+    for (index, character) in line.char_indices() {
+        if character == '#' && previous != Some('\\') {
+            return line[..index].to_string();
+        }
+        previous = Some(character);
+    }
+    line.to_string()
+}
+
+enum Word {
+    Separator,
+    Path(String),
+}
+
+fn split_words(line: &str) -> Vec<Word> {
+    let mut words = vec![];
+    let mut current = String::new();
+    let mut characters = line.chars().peekable();
+    while let Some(character) = characters.next() {
+        match character {
+            '\\' => match characters.peek() {
+                Some(' ') | Some('#') | Some('\\') => {
+                    current.push(characters.next().expect("peeked character"));
+                }
+                _ => current.push('\\'),
+            },
+            '$' if characters.peek() == Some(&'$') => {
+                characters.next();
+                current.push('$');
+            }
+            ':' if characters.peek().is_none_or(|next| next.is_whitespace()) => {
+                if !current.is_empty() {
+                    words.push(Word::Path(std::mem::take(&mut current)));
+                }
+                words.push(Word::Separator);
+            }
+            character if character.is_whitespace() => {
+                if !current.is_empty() {
+                    words.push(Word::Path(std::mem::take(&mut current)));
+                }
+            }
+            character => current.push(character),
+        }
+    }
+    if !current.is_empty() {
+        words.push(Word::Path(current));
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_depfile;
+    use super::parse_depfile_prerequisites;
+    use super::DepfileRule;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_depfile() {
+        let contents = "out/main.o: src/main.c include/a\\ b.h \\\n  include/c.h \\\r\n  C:\\sdk\\d.h\n\n# comment\ninclude/c.h:\nout/x.o out/y.o: lib/$$x.h lib/e\\#.h # trailing\n";
+        assert_eq!(
+            vec![
+                DepfileRule {
+                    targets: vec![PathBuf::from("out/main.o")],
+                    prerequisites: vec![
+                        PathBuf::from("src/main.c"),
+                        PathBuf::from("include/a b.h"),
+                        PathBuf::from("include/c.h"),
+                        PathBuf::from("C:\\sdk\\d.h"),
+                    ],
+                },
+                DepfileRule {
+                    targets: vec![PathBuf::from("include/c.h")],
+                    prerequisites: vec![],
+                },
+                DepfileRule {
+                    targets: vec![PathBuf::from("out/x.o"), PathBuf::from("out/y.o")],
+                    prerequisites: vec![PathBuf::from("lib/$x.h"), PathBuf::from("lib/e#.h")],
+                },
+            ],
+            parse_depfile(contents).expect("parse depfile")
+        );
+        assert_eq!(
+            vec![
+                PathBuf::from("a.h"),
+                PathBuf::from("b.h"),
+                PathBuf::from("c.h"),
+            ],
+            parse_depfile_prerequisites("x.o: a.h b.h\ny.o: b.h c.h\n")
+                .expect("parse depfile prerequisites")
+        );
+        assert!(parse_depfile("no separator here\n").is_err());
+        assert!(parse_depfile("a: b: c\n").is_err());
+    }
+}
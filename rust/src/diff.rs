@@ -0,0 +1,313 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Structured differences between task inputs, for explaining why a task was not found in a cache
+//! and for programmatic consumers that would otherwise parse `diff_items_to_string` output.
+
+use crate::canonical::TaskInputs;
+use crate::context::diff_items;
+use crate::context::DiffItem;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::transport::ResourceLimits;
+use crate::transport::Sandbox;
+use crate::transport::Stdin;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::path::PathBuf;
+
+/// A change to a keyed value, such as an environment variable or an input file's identity.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ValueChange<T> {
+    Added(T),
+    Removed(T),
+    Modified { before: T, after: T },
+}
+
+/// The differences between two `TaskInputs`. Fields that did not change are empty (or `None`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputsDiff<IS: IdentitySchemeApi> {
+    /// Changes to environment variables, keyed by variable name.
+    pub environment_variables: BTreeMap<String, ValueChange<String>>,
+    pub program: Option<ValueChange<PathBuf>>,
+    /// Sequence diff of arguments; empty when arguments did not change.
+    pub arguments: Vec<DiffItem<String>>,
+    pub argv0: Option<ValueChange<String>>,
+    /// Changes to input files, keyed by path. A `None` identity denotes a file that could not be
+    /// identified.
+    pub input_files: BTreeMap<PathBuf, ValueChange<Option<IS::Identity>>>,
+    /// Changes to the paths at which staged inputs are expected, keyed by input file path.
+    pub staged_inputs: BTreeMap<PathBuf, ValueChange<PathBuf>>,
+    pub stdin: Option<ValueChange<Stdin>>,
+    pub sandbox: Option<ValueChange<Sandbox>>,
+    pub resource_limits: Option<ValueChange<ResourceLimits>>,
+    /// Whether the description of expected outputs changed.
+    pub outputs_description: bool,
+}
+
+impl<IS: IdentitySchemeApi> InputsDiff<IS> {
+    /// Computes the differences from `before` to `after`.
+    pub fn new(before: &TaskInputs<IS>, after: &TaskInputs<IS>) -> Self {
+        let arguments_before: Vec<String> = before.arguments().cloned().collect();
+        let arguments_after: Vec<String> = after.arguments().cloned().collect();
+        let arguments = if arguments_before == arguments_after {
+            vec![]
+        } else {
+            diff_items(&arguments_before, &arguments_after)
+                .into_iter()
+                .map(|item| match item {
+                    DiffItem::Unchanged(argument) => DiffItem::Unchanged(argument.clone()),
+                    DiffItem::Added(argument) => DiffItem::Added(argument.clone()),
+                    DiffItem::Removed(argument) => DiffItem::Removed(argument.clone()),
+                })
+                .collect()
+        };
+
+        Self {
+            environment_variables: diff_maps(
+                before.environment_variables().cloned().collect(),
+                after.environment_variables().cloned().collect(),
+            ),
+            program: diff_values(Some(before.program()), Some(after.program())),
+            arguments,
+            argv0: diff_values(before.argv0(), after.argv0()),
+            input_files: diff_maps(
+                before.input_files().cloned().collect(),
+                after.input_files().cloned().collect(),
+            ),
+            staged_inputs: diff_maps(
+                before
+                    .staged_inputs()
+                    .map(|(source, destination)| (source.clone(), destination.clone()))
+                    .collect(),
+                after
+                    .staged_inputs()
+                    .map(|(source, destination)| (source.clone(), destination.clone()))
+                    .collect(),
+            ),
+            stdin: diff_values(before.stdin(), after.stdin()),
+            sandbox: diff_values(before.sandbox(), after.sandbox()),
+            resource_limits: diff_values(before.resource_limits(), after.resource_limits()),
+            outputs_description: before.outputs_description() != after.outputs_description(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.environment_variables.is_empty()
+            && self.program.is_none()
+            && self.arguments.is_empty()
+            && self.argv0.is_none()
+            && self.input_files.is_empty()
+            && self.staged_inputs.is_empty()
+            && self.stdin.is_none()
+            && self.sandbox.is_none()
+            && self.resource_limits.is_none()
+            && !self.outputs_description
+    }
+}
+
+/// Renders one line per change, in the `+`/`-` style of `diff_items_to_string`.
+impl<IS: IdentitySchemeApi> Display for InputsDiff<IS> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, change) in self.environment_variables.iter() {
+            write_change(
+                f,
+                &format!("environment variable {}", name),
+                change,
+                |value| format!("{:?}", value),
+            )?;
+        }
+        if let Some(change) = self.program.as_ref() {
+            write_change(f, "program", change, |program| format!("{:?}", program))?;
+        }
+        if !self.arguments.is_empty() {
+            writeln!(f, "arguments:")?;
+            for item in self.arguments.iter() {
+                match item {
+                    DiffItem::Unchanged(argument) => writeln!(f, "    {:?}", argument)?,
+                    DiffItem::Added(argument) => writeln!(f, "  + {:?}", argument)?,
+                    DiffItem::Removed(argument) => writeln!(f, "  - {:?}", argument)?,
+                }
+            }
+        }
+        if let Some(change) = self.argv0.as_ref() {
+            write_change(f, "argv0", change, |argv0| format!("{:?}", argv0))?;
+        }
+        for (path, change) in self.input_files.iter() {
+            write_change(
+                f,
+                &format!("input file {:?}", path),
+                change,
+                |identity| match identity {
+                    Some(identity) => identity.to_string(),
+                    None => String::from("<unidentified>"),
+                },
+            )?;
+        }
+        for (path, change) in self.staged_inputs.iter() {
+            write_change(
+                f,
+                &format!("staged input {:?}", path),
+                change,
+                |destination| format!("{:?}", destination),
+            )?;
+        }
+        if let Some(change) = self.stdin.as_ref() {
+            write_change(f, "stdin", change, |stdin| format!("{:?}", stdin))?;
+        }
+        if let Some(change) = self.sandbox.as_ref() {
+            write_change(f, "sandbox", change, |sandbox| format!("{:?}", sandbox))?;
+        }
+        if let Some(change) = self.resource_limits.as_ref() {
+            write_change(f, "resource limits", change, |resource_limits| {
+                format!("{:?}", resource_limits)
+            })?;
+        }
+        if self.outputs_description {
+            writeln!(f, "outputs description changed")?;
+        }
+        Ok(())
+    }
+}
+
+fn write_change<T, F: Fn(&T) -> String>(
+    f: &mut std::fmt::Formatter<'_>,
+    description: &str,
+    change: &ValueChange<T>,
+    format_value: F,
+) -> std::fmt::Result {
+    match change {
+        ValueChange::Added(value) => writeln!(f, "+ {}: {}", description, format_value(value)),
+        ValueChange::Removed(value) => writeln!(f, "- {}: {}", description, format_value(value)),
+        ValueChange::Modified { before, after } => writeln!(
+            f,
+            "~ {}: {} -> {}",
+            description,
+            format_value(before),
+            format_value(after)
+        ),
+    }
+}
+
+fn diff_values<T: Clone + PartialEq>(
+    before: Option<&T>,
+    after: Option<&T>,
+) -> Option<ValueChange<T>> {
+    match (before, after) {
+        (Some(before), Some(after)) if before == after => None,
+        (Some(before), Some(after)) => Some(ValueChange::Modified {
+            before: before.clone(),
+            after: after.clone(),
+        }),
+        (Some(before), None) => Some(ValueChange::Removed(before.clone())),
+        (None, Some(after)) => Some(ValueChange::Added(after.clone())),
+        (None, None) => None,
+    }
+}
+
+fn diff_maps<K: Clone + Hash + Ord, V: Clone + PartialEq>(
+    before: BTreeMap<K, V>,
+    after: BTreeMap<K, V>,
+) -> BTreeMap<K, ValueChange<V>> {
+    let mut changes = BTreeMap::new();
+    for (key, before_value) in before.iter() {
+        if let Some(change) = diff_values(Some(before_value), after.get(key)) {
+            changes.insert(key.clone(), change);
+        }
+    }
+    for (key, after_value) in after.iter() {
+        if !before.contains_key(key) {
+            changes.insert(key.clone(), ValueChange::Added(after_value.clone()));
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InputsDiff;
+    use super::ValueChange;
+    use crate::canonical::TaskInputsBuilder;
+    use crate::context::DiffItem;
+    use crate::identity::IdentityScheme as _;
+    use crate::transport::ContentSha256;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_inputs_diff() {
+        let identify = |content: &str| {
+            Some(ContentSha256::identify_content(content.as_bytes()).expect("identify content"))
+        };
+        let before = TaskInputsBuilder::<ContentSha256>::new()
+            .environment_variable("LANG", "C")
+            .environment_variable("HOME", "/home/user")
+            .program("/usr/bin/cc")
+            .arguments(["-c", "main.c", "-o", "main.o"])
+            .input_file("main.c", identify("int main() {}"))
+            .input_file("main.h", identify("#pragma once"))
+            .build()
+            .expect("before inputs");
+        assert!(InputsDiff::new(&before, &before).is_empty());
+        assert_eq!("", InputsDiff::new(&before, &before).to_string());
+
+        let after = TaskInputsBuilder::<ContentSha256>::new()
+            .environment_variable("LANG", "en_US.UTF-8")
+            .environment_variable("PATH", "/usr/bin")
+            .program("/usr/bin/cc")
+            .arguments(["-c", "main.c", "-O2", "-o", "main.o"])
+            .input_file("main.c", identify("int main() { return 0; }"))
+            .input_file("util.c", None)
+            .build()
+            .expect("after inputs");
+        let diff = InputsDiff::new(&before, &after);
+        assert!(!diff.is_empty());
+
+        assert_eq!(3, diff.environment_variables.len());
+        assert_eq!(
+            Some(&ValueChange::Modified {
+                before: String::from("C"),
+                after: String::from("en_US.UTF-8")
+            }),
+            diff.environment_variables.get("LANG")
+        );
+        assert_eq!(
+            Some(&ValueChange::Removed(String::from("/home/user"))),
+            diff.environment_variables.get("HOME")
+        );
+        assert_eq!(
+            Some(&ValueChange::Added(String::from("/usr/bin"))),
+            diff.environment_variables.get("PATH")
+        );
+
+        assert_eq!(None, diff.program);
+        assert!(diff
+            .arguments
+            .contains(&DiffItem::Added(String::from("-O2"))));
+        assert!(!diff
+            .arguments
+            .contains(&DiffItem::Removed(String::from("-O2"))));
+
+        assert_eq!(
+            Some(&ValueChange::Modified {
+                before: identify("int main() {}"),
+                after: identify("int main() { return 0; }"),
+            }),
+            diff.input_files.get(&PathBuf::from("main.c"))
+        );
+        assert_eq!(
+            Some(&ValueChange::Removed(identify("#pragma once"))),
+            diff.input_files.get(&PathBuf::from("main.h"))
+        );
+        assert_eq!(
+            Some(&ValueChange::Added(None)),
+            diff.input_files.get(&PathBuf::from("util.c"))
+        );
+        assert!(!diff.outputs_description);
+
+        let rendered = diff.to_string();
+        assert!(rendered.contains("~ environment variable LANG: \"C\" -> \"en_US.UTF-8\"\n"));
+        assert!(rendered.contains("  + \"-O2\"\n"));
+        assert!(rendered.contains("+ input file \"util.c\": <unidentified>\n"));
+    }
+}
@@ -2,6 +2,228 @@
 // Use of this source code is governed by a Apache-style license that can be
 // found in the LICENSE file.
 
-pub trait Error: 'static + std::error::Error + Send + Sync {}
+//! Errors reported by the library.
+//!
+//! Internally, failures propagate as `anyhow::Error`s annotated with context. Sites that can
+//! categorize a failure raise an `Error` (e.g., `Error::child_failed`), and public entry points
+//! such as `crate::facade::ArtifactExecutor` convert the propagated error back into an `Error` of
+//! the outermost category raised, keeping the accumulated context as its source. Embedders can then
+//! `match` on the category without parsing messages.
 
-impl<T: 'static + std::error::Error + Send + Sync> Error for T {}
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+/// Bound for error types associated with filesystem and serialization traits.
+pub trait ErrorBound: 'static + std::error::Error + Send + Sync {}
+
+impl<T: 'static + std::error::Error + Send + Sync> ErrorBound for T {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Filesystem operation that failed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IoOperation {
+    Read,
+    Write,
+    Create,
+    Remove,
+    Metadata,
+    Execute,
+}
+
+impl fmt::Display for IoOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Read => "reading",
+            Self::Write => "writing",
+            Self::Create => "creating",
+            Self::Remove => "removing",
+            Self::Metadata => "reading metadata of",
+            Self::Execute => "executing",
+        })
+    }
+}
+
+/// Category of a library failure. Each variant's `source` is the failure with all the context
+/// accumulated while it propagated.
+#[derive(Debug)]
+pub enum Error {
+    /// A cached object could not be read back in the form in which it was written.
+    CacheCorruption {
+        identity: String,
+        source: anyhow::Error,
+    },
+    /// A task's input files could not be resolved, identified, or checked.
+    InputsResolution { source: anyhow::Error },
+    /// A task's program exited unsuccessfully.
+    ChildFailed {
+        program: PathBuf,
+        status: ExitStatus,
+        source: anyhow::Error,
+    },
+    /// A filesystem operation failed. The underlying error (e.g., a `std::io::Error`) can be
+    /// recovered from `source.chain()`.
+    Io {
+        path: PathBuf,
+        op: IoOperation,
+        source: anyhow::Error,
+    },
+    /// An object could not be serialized or parsed.
+    Serialization { source: anyhow::Error },
+    /// Any failure without a more specific category.
+    Other { source: anyhow::Error },
+}
+
+impl Error {
+    pub fn cache_corruption(identity: String, source: anyhow::Error) -> Self {
+        Self::CacheCorruption { identity, source }
+    }
+
+    pub fn inputs_resolution(source: anyhow::Error) -> Self {
+        Self::InputsResolution { source }
+    }
+
+    pub fn child_failed<P: AsRef<Path>>(program: P, status: ExitStatus) -> Self {
+        let program = program.as_ref().to_path_buf();
+        Self::ChildFailed {
+            source: anyhow::anyhow!(
+                "child process for {:?} returned unsuccessful exit status: {}",
+                program,
+                status
+            ),
+            program,
+            status,
+        }
+    }
+
+    pub fn io<P: AsRef<Path>, E: ErrorBound>(path: P, op: IoOperation, error: E) -> Self {
+        let path = path.as_ref().to_path_buf();
+        Self::Io {
+            source: anyhow::Error::from(error).context(format!("{} {:?}", op, path)),
+            path,
+            op,
+        }
+    }
+
+    pub fn serialization(source: anyhow::Error) -> Self {
+        Self::Serialization { source }
+    }
+
+    fn source_error(&self) -> &anyhow::Error {
+        match self {
+            Self::CacheCorruption { source, .. }
+            | Self::InputsResolution { source }
+            | Self::ChildFailed { source, .. }
+            | Self::Io { source, .. }
+            | Self::Serialization { source }
+            | Self::Other { source } => source,
+        }
+    }
+
+    /// Same category as `self`, with `source` in place of its source.
+    fn recategorize(&self, source: anyhow::Error) -> Self {
+        match self {
+            Self::CacheCorruption { identity, .. } => Self::CacheCorruption {
+                identity: identity.clone(),
+                source,
+            },
+            Self::InputsResolution { .. } => Self::InputsResolution { source },
+            Self::ChildFailed {
+                program, status, ..
+            } => Self::ChildFailed {
+                program: program.clone(),
+                status: *status,
+                source,
+            },
+            Self::Io { path, op, .. } => Self::Io {
+                path: path.clone(),
+                op: *op,
+                source,
+            },
+            Self::Serialization { .. } => Self::Serialization { source },
+            Self::Other { .. } => Self::Other { source },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.source_error(), f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source_error().source()
+    }
+}
+
+/// Categorizes `error` by the outermost `Error` in its chain, keeping all of its context.
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Self {
+        // Detach the category from `error`, so that `error` can become its source.
+        let category = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<Error>())
+            .map(|category| category.recategorize(anyhow::anyhow!("")));
+        match category {
+            Some(category) => category.recategorize(error),
+            None => Self::Other { source: error },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+    use super::IoOperation;
+    use anyhow::Context as _;
+    use std::os::unix::process::ExitStatusExt as _;
+    use std::process::ExitStatus;
+
+    #[test]
+    fn test_error_categories() {
+        let child_failed: anyhow::Result<()> =
+            Err(Error::child_failed("/bin/false", ExitStatus::from_raw(256)).into());
+        let error = Error::from(child_failed.context("executing task").unwrap_err());
+        match &error {
+            Error::ChildFailed {
+                program, status, ..
+            } => {
+                assert_eq!(std::path::Path::new("/bin/false"), program);
+                assert_eq!(Some(1), status.code());
+            }
+            error => panic!("unexpected error category: {:?}", error),
+        }
+        assert_eq!("executing task", error.to_string());
+        assert_eq!(
+            "executing task: child process for \"/bin/false\" returned unsuccessful exit status: exit status: 1",
+            format!("{:#}", anyhow::Error::from(error))
+        );
+
+        let io_error = std::io::Error::new(std::io::ErrorKind::NotFound, "not found");
+        let error = Error::from(
+            anyhow::Error::from(Error::io("task.json", IoOperation::Read, io_error))
+                .context("loading task"),
+        );
+        match &error {
+            Error::Io { path, op, source } => {
+                assert_eq!(std::path::Path::new("task.json"), path);
+                assert_eq!(IoOperation::Read, *op);
+                assert_eq!(
+                    Some(std::io::ErrorKind::NotFound),
+                    source
+                        .chain()
+                        .find_map(|cause| cause.downcast_ref::<std::io::Error>())
+                        .map(std::io::Error::kind)
+                );
+            }
+            error => panic!("unexpected error category: {:?}", error),
+        }
+
+        let error = Error::from(anyhow::anyhow!("uncategorized"));
+        assert!(matches!(error, Error::Other { .. }));
+    }
+}
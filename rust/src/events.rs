@@ -0,0 +1,281 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Crate-wide bus of progress events, the integration point for UIs, logs, and metrics.
+//!
+//! Modules publish `Event`s to the global bus with `publish`; embedders observe them by
+//! registering a `Subscriber` with `subscribe`. Publishing to a bus without subscribers is cheap,
+//! and `is_active` lets publishers skip assembling costly events altogether.
+
+use std::path::Path;
+use std::process::ExitStatus;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+
+static GLOBAL_EVENT_BUS: EventBus = EventBus::new();
+
+/// Direction of a blob transfer, relative to this process.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+/// Something that happened while identifying, transferring, or executing.
+#[derive(Clone, Copy, Debug)]
+pub enum Event<'a> {
+    /// The content of the file at `path` was identified.
+    FileHashed {
+        path: &'a Path,
+        bytes: u64,
+        duration: Duration,
+    },
+    /// A blob was transferred to or from a remote cache.
+    BlobTransferred {
+        direction: TransferDirection,
+        hash: &'a str,
+        bytes: u64,
+    },
+    /// A blob of `bytes` bytes, as stored (i.e., after compression), was written to a cache
+    /// directory.
+    BlobWritten { hash: &'a str, bytes: u64 },
+    /// A task's program was started.
+    ChildSpawned { program: &'a Path, process_id: u32 },
+    /// A task's program exited.
+    ChildExited {
+        program: &'a Path,
+        status: ExitStatus,
+        duration: Duration,
+    },
+    /// The outputs of the task identified by `inputs_identity` were looked up in a cache.
+    CacheLookup { inputs_identity: &'a str, hit: bool },
+    /// The task identified by `inputs_identity` was executed and its outputs collected.
+    TaskExecuted {
+        inputs_identity: &'a str,
+        duration: Duration,
+        succeeded: bool,
+    },
+    /// The task identified by `inputs_identity` was removed from a cache by garbage collection.
+    TaskEvicted { inputs_identity: &'a str },
+    /// A blob that no cached task refers to was deleted by garbage collection.
+    BlobCollected { hash: &'a str, bytes: u64 },
+}
+
+/// Observer of published events. Subscribers are called synchronously on the publishing thread,
+/// so they should return quickly.
+pub trait Subscriber: Send + Sync {
+    fn on_event(&self, event: &Event<'_>);
+}
+
+/// Handle with which to unsubscribe a subscriber.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SubscriptionId(u64);
+
+/// Delivers published events to every registered subscriber.
+pub struct EventBus {
+    subscribers: RwLock<Vec<(SubscriptionId, Arc<dyn Subscriber>)>>,
+    is_active: AtomicBool,
+    next_id: AtomicU64,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub const fn new() -> Self {
+        Self {
+            subscribers: RwLock::new(Vec::new()),
+            is_active: AtomicBool::new(false),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    pub fn subscribe(&self, subscriber: Arc<dyn Subscriber>) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut subscribers = self.subscribers.write().expect("event bus lock");
+        subscribers.push((id, subscriber));
+        self.is_active.store(true, Ordering::Release);
+        id
+    }
+
+    /// Removes the subscriber registered as `id`. Returns false if it was already removed.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut subscribers = self.subscribers.write().expect("event bus lock");
+        let count = subscribers.len();
+        subscribers.retain(|(subscriber_id, _)| *subscriber_id != id);
+        self.is_active
+            .store(!subscribers.is_empty(), Ordering::Release);
+        subscribers.len() != count
+    }
+
+    /// Whether any subscriber is registered.
+    pub fn is_active(&self) -> bool {
+        self.is_active.load(Ordering::Acquire)
+    }
+
+    pub fn publish(&self, event: &Event<'_>) {
+        if !self.is_active() {
+            return;
+        }
+        for (_, subscriber) in self.subscribers.read().expect("event bus lock").iter() {
+            subscriber.on_event(event);
+        }
+    }
+}
+
+/// Registers `subscriber` with the global bus.
+pub fn subscribe(subscriber: Arc<dyn Subscriber>) -> SubscriptionId {
+    GLOBAL_EVENT_BUS.subscribe(subscriber)
+}
+
+/// Removes `id` from the global bus.
+pub fn unsubscribe(id: SubscriptionId) -> bool {
+    GLOBAL_EVENT_BUS.unsubscribe(id)
+}
+
+/// Whether any subscriber is registered with the global bus.
+pub fn is_active() -> bool {
+    GLOBAL_EVENT_BUS.is_active()
+}
+
+/// Publishes `event` to the global bus.
+pub fn publish(event: &Event<'_>) {
+    GLOBAL_EVENT_BUS.publish(event)
+}
+
+/// Logs every event with `tracing` at debug level.
+#[derive(Debug, Default)]
+pub struct TracingSubscriber;
+
+impl Subscriber for TracingSubscriber {
+    fn on_event(&self, event: &Event<'_>) {
+        match event {
+            Event::FileHashed {
+                path,
+                bytes,
+                duration,
+            } => tracing::debug!("hashed {:?} ({} bytes) in {:?}", path, bytes, duration),
+            Event::BlobTransferred {
+                direction,
+                hash,
+                bytes,
+            } => tracing::debug!("{:?} blob {} ({} bytes)", direction, hash, bytes),
+            Event::BlobWritten { hash, bytes } => {
+                tracing::debug!("wrote blob {} ({} bytes)", hash, bytes)
+            }
+            Event::ChildSpawned {
+                program,
+                process_id,
+            } => tracing::debug!("spawned {:?} as process {}", program, process_id),
+            Event::ChildExited {
+                program,
+                status,
+                duration,
+            } => tracing::debug!("{:?} exited with {} after {:?}", program, status, duration),
+            Event::CacheLookup {
+                inputs_identity,
+                hit,
+            } => tracing::debug!(
+                "cache {} for task {}",
+                if *hit { "hit" } else { "miss" },
+                inputs_identity
+            ),
+            Event::TaskExecuted {
+                inputs_identity,
+                duration,
+                succeeded,
+            } => tracing::debug!(
+                "executed task {} in {:?} ({})",
+                inputs_identity,
+                duration,
+                if *succeeded { "succeeded" } else { "failed" }
+            ),
+            Event::TaskEvicted { inputs_identity } => {
+                tracing::debug!("evicted task {}", inputs_identity)
+            }
+            Event::BlobCollected { hash, bytes } => {
+                tracing::debug!("collected blob {} ({} bytes)", hash, bytes)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Event;
+    use super::EventBus;
+    use super::Subscriber;
+    use crate::fs::HostFilesystem;
+    use crate::identity::IdentityScheme as _;
+    use crate::transport::ContentSha256;
+    use std::path::Path;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct HashedPaths(Mutex<Vec<(PathBuf, u64)>>);
+
+    impl Subscriber for HashedPaths {
+        fn on_event(&self, event: &Event<'_>) {
+            if let Event::FileHashed { path, bytes, .. } = event {
+                self.0
+                    .lock()
+                    .expect("lock")
+                    .push((path.to_path_buf(), *bytes));
+            }
+        }
+    }
+
+    #[test]
+    fn test_event_bus() {
+        let bus = EventBus::new();
+        assert!(!bus.is_active());
+        let first = Arc::new(HashedPaths::default());
+        let second = Arc::new(HashedPaths::default());
+        let first_id = bus.subscribe(first.clone());
+        let second_id = bus.subscribe(second.clone());
+        assert!(bus.is_active());
+
+        let event = Event::FileHashed {
+            path: Path::new("a.txt"),
+            bytes: 1,
+            duration: Default::default(),
+        };
+        bus.publish(&event);
+        assert!(bus.unsubscribe(first_id));
+        assert!(!bus.unsubscribe(first_id));
+        bus.publish(&event);
+        assert_eq!(1, first.0.lock().expect("lock").len());
+        assert_eq!(2, second.0.lock().expect("lock").len());
+        assert!(bus.unsubscribe(second_id));
+        assert!(!bus.is_active());
+    }
+
+    #[test]
+    fn test_global_file_hashed_events() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        std::fs::write(temporary_directory.path().join("file.txt"), "content").expect("write file");
+        let mut filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let hashed_paths = Arc::new(HashedPaths::default());
+        let id = super::subscribe(hashed_paths.clone());
+        ContentSha256::identify_file(&mut filesystem, "file.txt").expect("identify file");
+        super::unsubscribe(id);
+
+        // Other tests may hash files concurrently.
+        assert!(hashed_paths
+            .0
+            .lock()
+            .expect("lock")
+            .contains(&(PathBuf::from("file.txt"), 7)));
+    }
+}
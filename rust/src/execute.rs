@@ -2,24 +2,209 @@
 // Use of this source code is governed by a Apache-style license that can be
 // found in the LICENSE file.
 
+use crate::args::Execute;
 use crate::blob::BlobCache;
 use crate::blob::BlobPointerCache;
 use crate::blob::BlobPointerFileCache;
+use crate::blob::CanonicalJSON;
+use crate::blob::Compression;
 use crate::blob::FileFormat;
 use crate::blob::ReadDeserializer;
 use crate::blob::StringSerializer;
 use crate::blob::WriteSerializer;
+use crate::blob::JSON;
+use crate::cache::current_timestamp_nanos;
+use crate::cache::current_username;
+use crate::canonical::FileIdentitiesManifest;
+use crate::canonical::FilesManifest;
+use crate::canonical::Metadata;
+use crate::canonical::Outputs;
+use crate::canonical::System as CanonicalSystem;
+use crate::canonical::SystemCapture;
 use crate::canonical::TaskInputs;
+use crate::canonical::TaskInputsBuilder;
+use crate::canonical::TaskLabels;
 use crate::canonical::TaskOutputs;
+use crate::error::Error;
+use crate::events;
+use crate::events::Event;
 use crate::fs::Filesystem as FilesystemApi;
+use crate::fs::HostFilesystem;
 use crate::identity::AsTransport;
 use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::metrics;
+use crate::profile;
+use crate::profile::Phase;
+use crate::remote::RemoteBlobCache;
+use crate::remote::RemoteBlobCacheOptions;
+use crate::repro::run_capturing_repro;
+use crate::repro::ReproOptions;
+use crate::runner::CancellationToken;
+use crate::runner::DefaultRunner;
+use crate::runner::ExecutionResult;
 use crate::runner::Runner;
-use crate::runner::SimpleRunner;
+use crate::schema::FormatVersion;
+use crate::sync::PointerKind;
+use crate::trace::apply_trace;
+use crate::trace::FileAccesses;
+use crate::trace::TraceValidation;
+use crate::transport::ExecutionReport;
+use crate::transport::ExecutionStrategy;
+use crate::transport::Inputs as InputsTransport;
+use crate::transport::OutputFileReport;
+use crate::transport::Outputs as OutputsTransport;
+use crate::transport::OutputsVerification;
+use crate::transport::System as SystemTransport;
+use crate::transport::Task;
 use crate::transport::TaskInputs as TaskInputsTransport;
 use crate::transport::TaskOutputs as TaskOutputsTransport;
+use crate::transport::TaskResult;
 use anyhow::Context as _;
-use std::io::Cursor;
+use serde::Serialize;
+use std::io::Read as _;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+use sysinfo::SystemExt as _;
+
+/// Computes the canonical identity of `inputs`: the identity of its transport in canonical JSON
+/// form (see `crate::canonical_json`), which is also the key under which executors and caches store
+/// pointers to the task's outputs. External tools may use this to pre-compute keys without
+/// re-implementing serialization details.
+pub fn identify_task_inputs<IS: IdentitySchemeApi>(
+    inputs: &TaskInputs<IS>,
+) -> anyhow::Result<IS::Identity> {
+    let inputs_contents = CanonicalJSON::to_string(&inputs.as_transport())
+        .map_err(anyhow::Error::from)
+        .context("serializing task inputs object")?;
+    IS::identify_content(inputs_contents.as_bytes())
+        .context("identifying serialized task inputs object")
+}
+
+/// Subdirectories of the cache directory of a `CacheDirectoryTaskExecutor`. Blobs are shared with
+/// `crate::cache::Cache`, whose garbage collection treats the executor's pointers as roots.
+pub const DEFAULT_BLOBS_DIRECTORY: &str = "blobs";
+pub const DEFAULT_OUTPUTS_POINTERS_DIRECTORY: &str = "inputs_to_outputs";
+pub const DEFAULT_RESULTS_POINTERS_DIRECTORY: &str = "inputs_to_results";
+pub const DEFAULT_FAILURES_POINTERS_DIRECTORY: &str = "inputs_to_failures";
+/// Shared with `crate::cache::Cache`, so that `Cache::find_tasks` finds executed tasks.
+pub const DEFAULT_METADATA_POINTERS_DIRECTORY: &str = "metadata";
+pub const DEFAULT_STDOUTS_POINTERS_DIRECTORY: &str = "inputs_to_stdouts";
+pub const DEFAULT_STDERRS_POINTERS_DIRECTORY: &str = "inputs_to_stderrs";
+
+/// Placeholder, in the arguments and output paths of a `ExecutionStrategy::ForEachInput` task,
+/// replaced by the path of the input file for which the task is executed.
+pub const FOR_EACH_INPUT_PLACEHOLDER: &str = "{input}";
+
+/// Expands `task` into the tasks its execution strategy calls for. A `Simple` task is executed as
+/// is. A `ForEachInput` task is executed once per file in `working_directory` matched by its
+/// `inputs_filter`, in path order; each execution adds the matched file to the task's inputs and
+/// substitutes it for `FOR_EACH_INPUT_PLACEHOLDER`, so that each is cached independently. Expanded
+/// tasks have the `Simple` strategy.
+pub fn expand_execution_strategy<FS: FilesystemApi>(
+    working_directory: &mut FS,
+    task: &Task,
+) -> anyhow::Result<Vec<Task>> {
+    let inputs_filter = match &task.execution_strategy {
+        ExecutionStrategy::Simple => return Ok(vec![task.clone()]),
+        ExecutionStrategy::ForEachInput { inputs_filter } => inputs_filter,
+    };
+    let inputs = FilesManifest::try_from((working_directory, inputs_filter.as_ref()))
+        .context("resolving for-each-input filter")?;
+    inputs
+        .paths()
+        .map(|input| {
+            let input_string = input.to_str().ok_or_else(|| {
+                anyhow::anyhow!("for-each-input path, {:?}, is not valid unicode", input)
+            })?;
+            let substitute =
+                |template: &str| template.replace(FOR_EACH_INPUT_PLACEHOLDER, input_string);
+            let substitute_path = |path: &PathBuf| -> anyhow::Result<PathBuf> {
+                let template = path.to_str().ok_or_else(|| {
+                    anyhow::anyhow!("output path, {:?}, is not valid unicode", path)
+                })?;
+                Ok(PathBuf::from(substitute(template)))
+            };
+
+            let mut expanded = task.clone();
+            expanded.execution_strategy = ExecutionStrategy::Simple;
+            for argument in expanded.arguments.arguments.iter_mut() {
+                *argument = substitute(argument);
+            }
+            let outputs = &mut expanded.outputs;
+            for path in outputs
+                .include_files
+                .iter_mut()
+                .chain(outputs.optional_files.iter_mut())
+                .chain(outputs.stdout_file.iter_mut())
+                .chain(outputs.stderr_file.iter_mut())
+            {
+                *path = substitute_path(path)?;
+            }
+            for glob in outputs.include_globs.iter_mut() {
+                *glob = substitute(glob);
+            }
+            if !expanded.inputs.include_files.contains(input) {
+                expanded.inputs.include_files.push(input.clone());
+            }
+            Ok(expanded)
+        })
+        .collect()
+}
+
+/// Copies the stream recorded for `inputs_identity` in `stream_pointers` to `output_file` in
+/// `working_directory`, where it is collected like any other output.
+fn copy_stream_to_output_file<FS: FilesystemApi, IS: IdentitySchemeApi>(
+    stream_pointers: &mut BlobPointerFileCache<FS, IS>,
+    inputs_identity: &IS::Identity,
+    working_directory: &mut FS,
+    output_file: &Path,
+) -> anyhow::Result<()> {
+    let mut stream = stream_pointers
+        .open_file_for_read(inputs_identity)
+        .map_err(anyhow::Error::from)
+        .context("opening recorded stream")?;
+    if let Some(parent) = output_file.parent() {
+        if !parent.as_os_str().is_empty() {
+            working_directory
+                .create_directories(parent)
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("creating directories for {:?}", output_file))?;
+        }
+    }
+    let mut output = working_directory
+        .open_file_for_write(output_file)
+        .map_err(anyhow::Error::from)
+        .with_context(|| format!("opening output file {:?}", output_file))?;
+    std::io::copy(&mut stream, &mut output)
+        .map_err(anyhow::Error::from)
+        .with_context(|| format!("copying recorded stream to {:?}", output_file))?;
+    Ok(())
+}
+
+fn read_stream<FS: FilesystemApi, IS: IdentitySchemeApi>(
+    stream_pointers: &mut BlobPointerFileCache<FS, IS>,
+    inputs_identity: &IS::Identity,
+) -> anyhow::Result<Vec<u8>> {
+    let mut contents = vec![];
+    stream_pointers
+        .open_file_for_read(inputs_identity)
+        .map_err(anyhow::Error::from)?
+        .read_to_end(&mut contents)
+        .map_err(anyhow::Error::from)?;
+    Ok(contents)
+}
+
+fn publish_cache_lookup<IS: IdentitySchemeApi>(inputs_identity: &IS::Identity, hit: bool) {
+    if events::is_active() {
+        events::publish(&Event::CacheLookup {
+            inputs_identity: &inputs_identity.to_string(),
+            hit,
+        });
+    }
+}
 
 pub trait TaskExecutor<FS: FilesystemApi, IS: IdentitySchemeApi> {
     fn load_or_execute(
@@ -45,6 +230,35 @@ pub trait TaskExecutor<FS: FilesystemApi, IS: IdentitySchemeApi> {
         working_directory: &mut FS,
         inputs_identity: &IS::Identity,
     ) -> anyhow::Result<TaskOutputs<IS>>;
+
+    /// Reports what `load_or_execute` would do with `inputs`, without executing the task or
+    /// writing to its working directory.
+    fn query(&mut self, inputs: &TaskInputs<IS>) -> anyhow::Result<QueryReport<IS>>;
+}
+
+/// What loading a task would do, according to the cache.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheStatus {
+    /// The task's outputs are cached, and would be loaded without executing it.
+    Hit,
+    /// The task failed within the failure TTL, and would fail without being executed again.
+    CachedFailure,
+    /// The task would be executed.
+    Miss,
+}
+
+/// Report returned by `TaskExecutor::query`, for planning builds without executing tasks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryReport<IS: IdentitySchemeApi> {
+    /// Identity of the task's inputs, which is its cache key.
+    pub inputs_identity: IS::Identity,
+    pub status: CacheStatus,
+    /// Paths of the task's output files: those cached, on a hit, and otherwise those expected from
+    /// its outputs description (which excludes outputs matched by globs after the task has run).
+    pub output_paths: Vec<PathBuf>,
+    /// Duration of the most recent execution of the task, if it has been run.
+    pub cached_duration: Option<Duration>,
 }
 
 pub struct CacheDirectoryTaskExecutor<
@@ -55,11 +269,45 @@ pub struct CacheDirectoryTaskExecutor<
 > {
     blobs_cache: BlobCache<FS, IS, S>,
     outputs_pointers: BlobPointerCache<FS, IS, S>,
+    results_pointers: BlobPointerCache<FS, IS, S>,
+    failures_pointers: BlobPointerCache<FS, IS, S>,
     stdouts_pointers: BlobPointerFileCache<FS, IS>,
     stderrs_pointers: BlobPointerFileCache<FS, IS>,
+    metadata_pointers: BlobPointerCache<FS, IS, S>,
+    system: sysinfo::System,
+    system_capture: SystemCapture,
+    labels: TaskLabels,
+    remote_cache: Option<RemoteBlobCache<IS>>,
+    failure_ttl: Option<Duration>,
+    timeout: Option<Duration>,
+    cancellation: CancellationToken,
+    trace_validation: Option<TraceValidation>,
+    output_replay: Option<OutputReplay>,
+    materialization: Option<Materialization>,
     runner: R,
 }
 
+/// How an executor writes cached output files into the working directory of a task whose outputs
+/// it loads from the cache.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Materialization {
+    /// Copies blobs into output files.
+    #[default]
+    Copy,
+    /// Hard-links output files to the blobs that store them, falling back to copies of blobs that
+    /// are compressed or cannot be linked (e.g., because they are on another device). Hard-linked
+    /// outputs share storage with the cache: Modifying one in place, as a task that rewrites its
+    /// outputs might, corrupts the cached blob. Only use this for outputs that are never modified.
+    PreferHardLinks,
+}
+
+/// Sinks to which an executor replays the recorded standard output and error of each task that it
+/// loads from the cache or executes.
+pub struct OutputReplay {
+    pub stdout: Box<dyn Write + Send>,
+    pub stderr: Box<dyn Write + Send>,
+}
+
 impl<
         FS: FilesystemApi,
         IS: IdentitySchemeApi,
@@ -67,44 +315,555 @@ impl<
         R: Runner,
     > CacheDirectoryTaskExecutor<FS, IS, S, R>
 {
-    const DEFAULT_BLOBS_DIRECTORY: &str = "blobs";
-    const DEFAULT_OUTPUTS_POINTERS_DIRECTORY: &str = "inputs_to_outputs";
-    const DEFAULT_STDOUTS_POINTERS_DIRECTORY: &str = "inputs_to_stdouts";
-    const DEFAULT_STDERRS_POINTERS_DIRECTORY: &str = "inputs_to_stderrs";
-
     pub fn new_with_runner(mut filesystem: FS, runner: R) -> anyhow::Result<Self> {
+        for directory in [
+            DEFAULT_BLOBS_DIRECTORY,
+            DEFAULT_OUTPUTS_POINTERS_DIRECTORY,
+            DEFAULT_RESULTS_POINTERS_DIRECTORY,
+            DEFAULT_FAILURES_POINTERS_DIRECTORY,
+            DEFAULT_STDOUTS_POINTERS_DIRECTORY,
+            DEFAULT_STDERRS_POINTERS_DIRECTORY,
+            DEFAULT_METADATA_POINTERS_DIRECTORY,
+        ] {
+            filesystem
+                .create_directories(directory)
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("creating cache directory {:?}", directory))?;
+        }
         let blobs_filesystem = filesystem
-            .sub_system(Self::DEFAULT_BLOBS_DIRECTORY)
+            .sub_system(DEFAULT_BLOBS_DIRECTORY)
             .context("creating blobs directory")?;
         let outputs_filesystem = filesystem
-            .sub_system(Self::DEFAULT_OUTPUTS_POINTERS_DIRECTORY)
+            .sub_system(DEFAULT_OUTPUTS_POINTERS_DIRECTORY)
             .context("creating inputs->outputs pointers directory")?;
+        let results_filesystem = filesystem
+            .sub_system(DEFAULT_RESULTS_POINTERS_DIRECTORY)
+            .context("creating inputs->results pointers directory")?;
+        let failures_filesystem = filesystem
+            .sub_system(DEFAULT_FAILURES_POINTERS_DIRECTORY)
+            .context("creating inputs->failures pointers directory")?;
         let stdouts_filesystem = filesystem
-            .sub_system(Self::DEFAULT_STDOUTS_POINTERS_DIRECTORY)
+            .sub_system(DEFAULT_STDOUTS_POINTERS_DIRECTORY)
             .context("creating stdouts directory")?;
         let stderrs_filesystem = filesystem
-            .sub_system(Self::DEFAULT_STDERRS_POINTERS_DIRECTORY)
+            .sub_system(DEFAULT_STDERRS_POINTERS_DIRECTORY)
             .context("creating stderrs directory")?;
+        let metadata_filesystem = filesystem
+            .sub_system(DEFAULT_METADATA_POINTERS_DIRECTORY)
+            .context("creating metadata pointers directory")?;
 
         let blobs_cache = BlobCache::new(blobs_filesystem);
         let outputs_pointers = BlobPointerCache::new(outputs_filesystem);
+        let results_pointers = BlobPointerCache::new(results_filesystem);
+        let failures_pointers = BlobPointerCache::new(failures_filesystem);
         let stdouts_pointers = BlobPointerFileCache::new(stdouts_filesystem);
         let stderrs_pointers = BlobPointerFileCache::new(stderrs_filesystem);
+        let metadata_pointers = BlobPointerCache::new(metadata_filesystem);
 
         Ok(Self {
             blobs_cache,
             outputs_pointers,
+            results_pointers,
+            failures_pointers,
             stdouts_pointers,
             stderrs_pointers,
+            metadata_pointers,
+            system: sysinfo::System::new(),
+            system_capture: SystemCapture::default(),
+            labels: TaskLabels::default(),
+            remote_cache: None,
+            failure_ttl: None,
+            timeout: None,
+            cancellation: CancellationToken::default(),
+            trace_validation: None,
+            output_replay: None,
+            materialization: None,
             runner,
         })
     }
 
+    /// Reads and writes through `remote_cache`: Outputs missing from the cache directory are
+    /// looked up in the remote cache, and the outputs of executed tasks are uploaded to it. Failures
+    /// to reach the remote cache are logged, and otherwise ignored.
+    pub fn with_remote_cache(mut self, remote_cache: RemoteBlobCache<IS>) -> Self {
+        self.remote_cache = Some(remote_cache);
+        self
+    }
+
+    /// Kills each task that this executor executes once it has run for `timeout`. Tasks that are
+    /// killed fail, and their failures are not cached.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Kills the task that this executor is executing, if any, once `cancellation` is cancelled.
+    /// Tasks that are killed fail, and their failures are not cached.
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Stores the content of the output files of each task that this executor executes, and writes
+    /// them into the working directory of each task whose outputs are loaded from the cache, as
+    /// `materialization` selects.
+    pub fn with_materialization(mut self, materialization: Materialization) -> Self {
+        self.materialization = Some(materialization);
+        self
+    }
+
+    /// Selects which properties of the host machine are recorded in the metadata of tasks that this
+    /// executor executes. Host properties never contribute to cache keys.
+    pub fn with_system_capture(mut self, system_capture: SystemCapture) -> Self {
+        self.system_capture = system_capture;
+        self
+    }
+
+    /// Selects how blobs that this executor writes are stored; see `crate::blob::Compression`.
+    pub fn with_compression(self, compression: Compression) -> Self {
+        Self {
+            blobs_cache: self.blobs_cache.with_compression(compression),
+            ..self
+        }
+    }
+
+    /// Checks the file accesses traced by the runner (which should be a
+    /// `crate::runner::TracedRunner` writing to `trace_validation.trace_path`) against each executed
+    /// task's declared inputs and outputs; see `crate::trace`.
+    pub fn with_trace_validation(mut self, trace_validation: TraceValidation) -> Self {
+        self.trace_validation = Some(trace_validation);
+        self
+    }
+
+    /// Replays each task's recorded stdout and stderr to `stdout` and `stderr`, whether its outputs
+    /// are loaded from the cache or it is executed (successfully or not). Streams are not replayed for tasks loaded from a
+    /// remote cache, which does not share them. Failures to replay are logged, and otherwise
+    /// ignored.
+    pub fn with_output_replay<Stdout: Write + Send + 'static, Stderr: Write + Send + 'static>(
+        mut self,
+        stdout: Stdout,
+        stderr: Stderr,
+    ) -> Self {
+        self.output_replay = Some(OutputReplay {
+            stdout: Box::new(stdout),
+            stderr: Box::new(stderr),
+        });
+        self
+    }
+
+    /// Caches failed executions of the tasks that this executor subsequently runs: Loading a task
+    /// that failed less than `failure_ttl` ago fails with the recorded failure (replaying its
+    /// recorded output), rather than executing the task again. `None` stops caching failures, and
+    /// ignores those already cached. Set for each task by `crate::facade::ArtifactExecutor`,
+    /// according to the task's `failure_caching`.
+    pub fn set_failure_ttl(&mut self, failure_ttl: Option<Duration>) {
+        self.failure_ttl = failure_ttl;
+    }
+
+    /// Records `labels` in the metadata of the tasks that this executor subsequently executes, where
+    /// `crate::cache::Cache::find_tasks` can query them. Set for each task by
+    /// `crate::facade::ArtifactExecutor`, according to the task's label and tags.
+    pub fn set_labels(&mut self, labels: TaskLabels) {
+        self.labels = labels;
+    }
+
+    /// Reads the standard output recorded for the task identified by `inputs_identity`, if it has
+    /// been run.
+    pub fn read_stdout(&mut self, inputs_identity: &IS::Identity) -> anyhow::Result<Vec<u8>> {
+        read_stream(&mut self.stdouts_pointers, inputs_identity).context("reading stdout")
+    }
+
+    /// Reads the standard error recorded for the task identified by `inputs_identity`, if it has
+    /// been run.
+    pub fn read_stderr(&mut self, inputs_identity: &IS::Identity) -> anyhow::Result<Vec<u8>> {
+        read_stream(&mut self.stderrs_pointers, inputs_identity).context("reading stderr")
+    }
+
+    /// Reads the result of the most recent execution of the task identified by `inputs_identity`, if
+    /// it has been run. Results are recorded for failed executions too.
+    pub fn read_task_result(
+        &mut self,
+        inputs_identity: &IS::Identity,
+    ) -> anyhow::Result<Option<TaskResult>> {
+        let result_identity = match self.results_pointers.read_blob_pointer(inputs_identity) {
+            Ok(result_identity) => result_identity,
+            Err(_) => return Ok(None),
+        };
+        self.blobs_cache
+            .read_versioned_blob::<TaskResult>(&result_identity)
+            .context("reading task result blob for task executor")
+            .map(Some)
+    }
+
+    /// Reads the outputs cached for the task identified by `inputs_identity`, in the cache directory
+    /// or else the remote cache, without materializing them.
+    pub fn read_task_outputs(
+        &mut self,
+        inputs_identity: &IS::Identity,
+    ) -> anyhow::Result<Option<TaskOutputs<IS>>> {
+        self.lookup_outputs_identity(inputs_identity)
+            .map(|outputs_identity| self.read_cached_outputs(&outputs_identity))
+            .transpose()
+    }
+
+    /// Looks up the identity of the outputs recorded for `inputs_identity`, in the cache directory or
+    /// else the remote cache. Pointers to outputs blobs that are missing from the cache directory
+    /// (e.g., because they were garbage collected) are ignored.
+    fn lookup_outputs_identity(&mut self, inputs_identity: &IS::Identity) -> Option<IS::Identity> {
+        if let Ok(outputs_identity) = self.outputs_pointers.read_blob_pointer(inputs_identity) {
+            if self.blobs_cache.contains_blob(&outputs_identity) {
+                return Some(outputs_identity);
+            }
+            tracing::warn!(
+                "outputs blob of cached task {} is missing; treating task as a cache miss",
+                inputs_identity.to_string()
+            );
+        }
+        self.read_through_outputs(inputs_identity)
+            .unwrap_or_else(|error| {
+                tracing::warn!("reading outputs from remote cache: {:#}", error);
+                None
+            })
+    }
+
+    /// Fails with the cached failure of the task identified by `inputs_identity`, if failures are
+    /// cached and the task failed within the failure TTL.
+    fn check_cached_failure(&mut self, inputs_identity: &IS::Identity) -> anyhow::Result<()> {
+        let task_result = match self.fresh_cached_failure(inputs_identity)? {
+            Some(task_result) => task_result,
+            None => return Ok(()),
+        };
+
+        let inputs: TaskInputs<IS> = self
+            .blobs_cache
+            .read_versioned_canonical_blob::<TaskInputsTransport<IS>>(inputs_identity)
+            .context("opening inputs blob of cached failure for task executor")?
+            .try_into()
+            .context("deserializing inputs blob of cached failure for task executor")?;
+        tracing::info!(
+            "{:?} failed within the failure TTL; not executing it again",
+            inputs.program()
+        );
+        self.replay_output(inputs_identity);
+        match ExecutionResult::from_task_result(&task_result).status {
+            Some(status) => {
+                Err(Error::child_failed(inputs.program(), status)).context("loading cached failure")
+            }
+            None => anyhow::bail!("{:?} failed, according to cached failure", inputs.program()),
+        }
+    }
+
+    /// Reads the cached failure of the task identified by `inputs_identity`, if failures are cached
+    /// and the task failed within the failure TTL.
+    fn fresh_cached_failure(
+        &mut self,
+        inputs_identity: &IS::Identity,
+    ) -> anyhow::Result<Option<TaskResult>> {
+        let failure_ttl = match self.failure_ttl {
+            Some(failure_ttl) => failure_ttl,
+            None => return Ok(None),
+        };
+        let result_identity = match self.failures_pointers.read_blob_pointer(inputs_identity) {
+            Ok(result_identity) => result_identity,
+            Err(_) => return Ok(None),
+        };
+        let task_result = self
+            .blobs_cache
+            .read_versioned_blob::<TaskResult>(&result_identity)
+            .context("reading cached failure for task executor")?;
+        // Failures recorded in the future (by a host whose clock is ahead) are treated as fresh.
+        let age_nanos = current_timestamp_nanos().saturating_sub(task_result.timestamp_nanos);
+        if u128::try_from(age_nanos).unwrap_or(0) >= failure_ttl.as_nanos() {
+            tracing::debug!("cached failure has expired; executing task again");
+            return Ok(None);
+        }
+        Ok(Some(task_result))
+    }
+
+    /// Loads the outputs identified by `cached_outputs_identity`, materializing them if configured.
+    /// Returns `None`, so that the task is executed again, when the content of an output file to be
+    /// materialized is missing from both the cache directory and the remote cache.
+    fn load_cached_outputs(
+        &mut self,
+        working_directory: &mut FS,
+        inputs_identity: &IS::Identity,
+        cached_outputs_identity: &IS::Identity,
+    ) -> anyhow::Result<Option<TaskOutputs<IS>>> {
+        let outputs = self.read_cached_outputs(cached_outputs_identity)?;
+        if let Some(materialization) = self.materialization {
+            for (path, identity) in outputs.output_files() {
+                let identity = match identity {
+                    Some(identity) if outputs.output_symlink_target(path).is_none() => identity,
+                    _ => continue,
+                };
+                if let Err(error) = self.read_through_blob(identity) {
+                    tracing::warn!("reading output {:?} from remote cache: {:#}", path, error);
+                }
+                if !self.blobs_cache.contains_blob(identity) {
+                    tracing::warn!(
+                        "content of cached output {:?} is missing; treating task as a cache miss",
+                        path
+                    );
+                    return Ok(None);
+                }
+            }
+            self.materialize_outputs(working_directory, &outputs, materialization)
+                .context("materializing cached outputs for task executor")?;
+        }
+        self.replay_output(inputs_identity);
+        Ok(Some(outputs))
+    }
+
+    fn read_cached_outputs(
+        &mut self,
+        cached_outputs_identity: &IS::Identity,
+    ) -> anyhow::Result<TaskOutputs<IS>> {
+        self.blobs_cache
+            .read_versioned_blob::<TaskOutputsTransport<IS>>(cached_outputs_identity)
+            .context("deserializing cached outputs description blob for task executor")?
+            .try_into()
+            .map_err(|error| Error::cache_corruption(cached_outputs_identity.to_string(), error))
+            .context("verifying cached outputs description blob for task executor")
+    }
+
+    /// Writes the cached content of each output file in `outputs` to the working directory,
+    /// replacing whatever is there. Symbolic links are recreated rather than followed.
+    fn materialize_outputs(
+        &mut self,
+        working_directory: &mut FS,
+        outputs: &TaskOutputs<IS>,
+        materialization: Materialization,
+    ) -> anyhow::Result<()> {
+        for (path, identity) in outputs.output_files() {
+            // Absent optional outputs have no content.
+            let identity = match identity {
+                Some(identity) => identity,
+                None => continue,
+            };
+            // Never write through an existing file, which may be a hard link to a blob.
+            if working_directory.is_symlink(path) || working_directory.metadata(path).is_ok() {
+                working_directory
+                    .remove_file(path)
+                    .map_err(anyhow::Error::from)
+                    .with_context(|| format!("removing stale output {:?}", path))?;
+            }
+            if let Some(parent) = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+            {
+                working_directory
+                    .create_directories(parent)
+                    .map_err(anyhow::Error::from)
+                    .with_context(|| format!("creating directory for output {:?}", path))?;
+            }
+            if let Some(target) = outputs.output_symlink_target(path) {
+                working_directory
+                    .create_symlink(target, path)
+                    .map_err(anyhow::Error::from)
+                    .with_context(|| format!("creating symbolic link output {:?}", path))?;
+                continue;
+            }
+
+            self.read_through_blob(identity)?;
+            if materialization == Materialization::PreferHardLinks {
+                if let Some(blob_path) = self.blobs_cache.uncompressed_blob_path(identity)? {
+                    match working_directory.hard_link(&blob_path, path) {
+                        Ok(()) => continue,
+                        Err(error) => tracing::debug!(
+                            "copying output {:?}, which cannot be hard-linked: {}",
+                            path,
+                            error
+                        ),
+                    }
+                }
+            }
+            let mut blob = self
+                .blobs_cache
+                .open_blob(identity)
+                .with_context(|| format!("opening blob for output {:?}", path))?;
+            let mut output_file = working_directory
+                .open_file_for_write(path)
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("creating output {:?}", path))?;
+            std::io::copy(&mut blob, &mut output_file)
+                .with_context(|| format!("copying blob to output {:?}", path))?;
+        }
+        Ok(())
+    }
+
+    /// Stores the content of each output file in `outputs` that is not a symbolic link.
+    fn put_output_blobs(
+        &mut self,
+        working_directory: &mut FS,
+        outputs: &TaskOutputs<IS>,
+    ) -> anyhow::Result<()> {
+        for (path, identity) in outputs.output_files() {
+            let identity = match identity {
+                Some(identity) if outputs.output_symlink_target(path).is_none() => identity,
+                _ => continue,
+            };
+            if self.blobs_cache.contains_blob(identity) {
+                continue;
+            }
+            let output_file = working_directory
+                .open_file_for_read(path)
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("opening output {:?}", path))?;
+            self.blobs_cache
+                .copy_blob(output_file, identity)
+                .with_context(|| format!("storing output {:?}", path))?;
+        }
+        Ok(())
+    }
+
+    fn replay_output(&mut self, inputs_identity: &IS::Identity) {
+        let output_replay = match self.output_replay.as_mut() {
+            Some(output_replay) => output_replay,
+            None => return,
+        };
+        for (stream_pointers, sink, description) in [
+            (
+                &mut self.stdouts_pointers,
+                &mut output_replay.stdout,
+                "stdout",
+            ),
+            (
+                &mut self.stderrs_pointers,
+                &mut output_replay.stderr,
+                "stderr",
+            ),
+        ] {
+            let mut stream = match stream_pointers.open_file_for_read(inputs_identity) {
+                Ok(stream) => stream,
+                Err(_) => {
+                    tracing::debug!("no recorded {} to replay", description);
+                    continue;
+                }
+            };
+            if let Err(error) = std::io::copy(&mut stream, sink).and_then(|_| sink.flush()) {
+                tracing::warn!("replaying recorded {}: {}", description, error);
+            }
+        }
+    }
+
+    /// Copies the outputs pointer from `inputs_identity`, and the outputs blob to which it points,
+    /// from the remote cache to the cache directory.
+    fn read_through_outputs(
+        &mut self,
+        inputs_identity: &IS::Identity,
+    ) -> anyhow::Result<Option<IS::Identity>> {
+        let outputs_identity = match self.remote_cache.as_mut() {
+            Some(remote_cache) => {
+                match remote_cache.read_blob_pointer(PointerKind::Outputs, inputs_identity)? {
+                    Some(outputs_identity) => outputs_identity,
+                    None => return Ok(None),
+                }
+            }
+            None => return Ok(None),
+        };
+        self.read_through_blob(&outputs_identity)?;
+        self.outputs_pointers
+            .write_raw_blob_pointer(inputs_identity, &outputs_identity)
+            .context("writing inputs->outputs pointer from remote cache")?;
+        Ok(Some(outputs_identity))
+    }
+
+    /// Copies the blob identified by `identity` from the remote cache, unless the cache directory
+    /// already contains it.
+    fn read_through_blob(&mut self, identity: &IS::Identity) -> anyhow::Result<()> {
+        let remote_cache = match self.remote_cache.as_mut() {
+            Some(remote_cache) if !self.blobs_cache.contains_blob(identity) => remote_cache,
+            _ => return Ok(()),
+        };
+        let content = remote_cache
+            .read_blob(identity)?
+            .ok_or_else(|| anyhow::anyhow!("remote cache does not contain blob {:?}", identity))?;
+        self.blobs_cache
+            .copy_blob(content.as_slice(), identity)
+            .context("copying blob from remote cache")
+    }
+
+    /// Uploads the content of each output file in `outputs`, the inputs and outputs blobs of a task,
+    /// and then the pointer between them, to the remote cache, so that the pointer is never
+    /// published before the blobs that a hit needs. Output files that are not stored in the cache
+    /// directory are read from `working_directory`.
+    fn write_through(
+        &mut self,
+        working_directory: &mut FS,
+        inputs_identity: &IS::Identity,
+        outputs_identity: &IS::Identity,
+        outputs: &TaskOutputs<IS>,
+    ) -> anyhow::Result<()> {
+        let remote_cache = match self.remote_cache.as_mut() {
+            Some(remote_cache) if !remote_cache.is_read_only() => remote_cache,
+            _ => return Ok(()),
+        };
+        for (path, identity) in outputs.output_files() {
+            let identity = match identity {
+                Some(identity) if outputs.output_symlink_target(path).is_none() => identity,
+                _ => continue,
+            };
+            if remote_cache.contains_blob(identity)? {
+                continue;
+            }
+            let mut content = vec![];
+            if self.blobs_cache.contains_blob(identity) {
+                self.blobs_cache
+                    .open_blob(identity)?
+                    .read_to_end(&mut content)
+            } else {
+                working_directory
+                    .open_file_for_read(path)
+                    .map_err(anyhow::Error::from)
+                    .with_context(|| format!("opening output {:?}", path))?
+                    .read_to_end(&mut content)
+            }
+            .with_context(|| format!("reading output {:?} for remote cache", path))?;
+            remote_cache.write_blob(identity, &content)?;
+        }
+        for identity in [inputs_identity, outputs_identity] {
+            if remote_cache.contains_blob(identity)? {
+                continue;
+            }
+            let mut content = vec![];
+            self.blobs_cache
+                .open_blob(identity)?
+                .read_to_end(&mut content)
+                .context("reading blob for remote cache")?;
+            remote_cache.write_blob(identity, &content)?;
+        }
+        remote_cache.write_blob_pointer(PointerKind::Outputs, inputs_identity, outputs_identity)
+    }
+
     fn do_force_execute(
         &mut self,
         working_directory: &mut FS,
         inputs: &TaskInputs<IS>,
         inputs_identity: &IS::Identity,
+    ) -> anyhow::Result<TaskOutputs<IS>> {
+        let inputs_identity_string = inputs_identity.to_string();
+        let span = metrics::task_span(&inputs_identity_string);
+        let _entered = span.enter();
+        let start = Instant::now();
+        let result = self.run_and_collect_outputs(working_directory, inputs, inputs_identity);
+        events::publish(&Event::TaskExecuted {
+            inputs_identity: &inputs_identity_string,
+            duration: start.elapsed(),
+            succeeded: result.is_ok(),
+        });
+        span.record(
+            "otel.status_code",
+            if result.is_ok() { "OK" } else { "ERROR" },
+        );
+        self.replay_output(inputs_identity);
+        result
+    }
+
+    fn run_and_collect_outputs(
+        &mut self,
+        working_directory: &mut FS,
+        inputs: &TaskInputs<IS>,
+        inputs_identity: &IS::Identity,
     ) -> anyhow::Result<TaskOutputs<IS>> {
         let stdout_file = self
             .stdouts_pointers
@@ -114,13 +873,159 @@ impl<
             .stderrs_pointers
             .open_file_for_write(inputs_identity)
             .context("opening stderr file for task executor")?;
-        self.runner
-            .run_task(working_directory, inputs, stdout_file, stderr_file)
+        let trace_path = match &self.trace_validation {
+            Some(trace_validation) => {
+                let trace_path = match working_directory.working_directory() {
+                    Some(directory) => directory.join(&trace_validation.trace_path),
+                    None if trace_validation.trace_path.is_absolute() => {
+                        trace_validation.trace_path.clone()
+                    }
+                    None => anyhow::bail!(
+                        "relative trace path, {:?}, in filesystem that has no working directory",
+                        trace_validation.trace_path
+                    ),
+                };
+                // Never mistake the trace of an earlier task for this one's.
+                match std::fs::remove_file(&trace_path) {
+                    Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                        return Err(anyhow::Error::from(error)).with_context(|| {
+                            format!("removing stale trace file {:?}", trace_path)
+                        });
+                    }
+                    _ => {}
+                }
+                Some(trace_path)
+            }
+            None => None,
+        };
+        let expanded_inputs = inputs
+            .clone()
+            .with_expanded_argument_placeholders()
+            .context("preparing task arguments")?;
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let execution_result = self
+            .runner
+            .run_task(
+                working_directory,
+                &expanded_inputs,
+                stdout_file,
+                stderr_file,
+                deadline,
+                &self.cancellation,
+            )
             .context("executing task")?;
+        let result_identity = self
+            .blobs_cache
+            .write_small_blob(&execution_result.to_task_result(current_timestamp_nanos()))
+            .context("writing task result blob for task executor")?;
+        self.results_pointers
+            .write_raw_blob_pointer(inputs_identity, &result_identity)
+            .context("writing inputs->results pointer for task executor")?;
+        // Interrupted tasks might have succeeded given more time, so their failures are not cached.
+        if let Some(interruption) = execution_result.interruption {
+            anyhow::bail!(
+                "task {:?} {} after {:?}",
+                inputs.program(),
+                interruption,
+                execution_result.duration
+            );
+        }
+        if let Some(status) = execution_result.status.filter(|status| !status.success()) {
+            if self.failure_ttl.is_some() {
+                self.blobs_cache
+                    .write_canonical_blob(&inputs.as_transport())
+                    .context("writing inputs description blob for task executor")?;
+                self.failures_pointers
+                    .write_raw_blob_pointer(inputs_identity, &result_identity)
+                    .context("writing inputs->failures pointer for task executor")?;
+            }
+            return Err(Error::child_failed(inputs.program(), status)).context("executing task");
+        }
+
+        let collection_span =
+            profile::span(Phase::OutputCollection, || inputs_identity.to_string());
+        let outputs = inputs.outputs_description();
+        if let Some(stdout_output_file) = outputs.stdout_file() {
+            copy_stream_to_output_file(
+                &mut self.stdouts_pointers,
+                inputs_identity,
+                working_directory,
+                stdout_output_file,
+            )
+            .context("capturing stdout as output file for task executor")?;
+        }
+        if let Some(stderr_output_file) = outputs.stderr_file() {
+            copy_stream_to_output_file(
+                &mut self.stderrs_pointers,
+                inputs_identity,
+                working_directory,
+                stderr_output_file,
+            )
+            .context("capturing stderr as output file for task executor")?;
+        }
 
-        (working_directory, inputs)
+        let mut outputs: TaskOutputs<IS> = (&mut *working_directory, inputs)
             .try_into()
-            .context("computing concrete outputs for task executor")
+            .context("computing concrete outputs for task executor")?;
+        if let (Some(trace_validation), Some(trace_path)) = (&self.trace_validation, trace_path) {
+            let mut accesses = FileAccesses::read_trace_file(trace_path)?;
+            if let Some(directory) = working_directory.working_directory() {
+                accesses = accesses.relative_to(directory);
+            }
+            outputs = apply_trace(
+                working_directory,
+                trace_validation.mode,
+                &accesses,
+                inputs,
+                outputs,
+            )
+            .context("validating traced file accesses")?;
+        }
+        drop(collection_span);
+
+        let _cache_span = profile::span(Phase::CacheIo, || {
+            format!("write {}", inputs_identity.to_string())
+        });
+        if self.materialization.is_some() {
+            self.put_output_blobs(working_directory, &outputs)
+                .context("writing output blobs for task executor")?;
+        }
+        self.blobs_cache
+            .write_canonical_blob(&inputs.as_transport())
+            .context("writing inputs description blob for task executor")?;
+        let outputs_identity = self
+            .blobs_cache
+            .write_small_blob(&outputs.as_transport())
+            .context("writing outputs description blob for task executor")?;
+        self.outputs_pointers
+            .write_raw_blob_pointer(inputs_identity, &outputs_identity)
+            .context("writing inputs->outputs pointer for task executor")?;
+        let mut metadata = Metadata::new(
+            current_timestamp_nanos(),
+            execution_result.duration.as_nanos(),
+            CanonicalSystem::capture(&self.system, &self.system_capture),
+        )
+        .with_execution_result(&execution_result)
+        .with_labels(self.labels.clone());
+        if self.system_capture.host {
+            metadata = metadata.with_host(self.system.host_name(), current_username());
+        }
+        let metadata_identity = self
+            .blobs_cache
+            .write_small_blob(&metadata.as_transport())
+            .context("writing metadata blob for task executor")?;
+        self.metadata_pointers
+            .write_raw_blob_pointer(inputs_identity, &metadata_identity)
+            .context("writing inputs->metadata pointer for task executor")?;
+        if let Err(error) = self.write_through(
+            working_directory,
+            inputs_identity,
+            &outputs_identity,
+            &outputs,
+        ) {
+            tracing::warn!("writing outputs to remote cache: {:#}", error);
+        }
+        Ok(outputs)
     }
 }
 
@@ -128,10 +1033,10 @@ impl<
         FS: FilesystemApi,
         IS: IdentitySchemeApi,
         S: FileFormat + ReadDeserializer + StringSerializer + WriteSerializer,
-    > CacheDirectoryTaskExecutor<FS, IS, S, SimpleRunner>
+    > CacheDirectoryTaskExecutor<FS, IS, S, DefaultRunner>
 {
     pub fn new(filesystem: FS) -> anyhow::Result<Self> {
-        Self::new_with_runner(filesystem, SimpleRunner)
+        Self::new_with_runner(filesystem, DefaultRunner::default())
     }
 }
 
@@ -147,23 +1052,25 @@ impl<
         working_directory: &mut FS,
         inputs: &TaskInputs<IS>,
     ) -> anyhow::Result<TaskOutputs<IS>> {
-        let mut inputs_contents = vec![];
-        let imports_transport = inputs.as_transport();
-        S::to_writer(&mut inputs_contents, &imports_transport)
-            .context("serializing inputs object for task executor")?;
-        let inputs_identity = IS::identify_content(Cursor::new(inputs_contents))
-            .context("identifying serialized inputs object for task executor")?;
-        if let Ok(cached_outputs_identity) =
-            self.outputs_pointers.read_blob_pointer(&inputs_identity)
-        {
-            self.blobs_cache
-                .read_blob::<TaskOutputsTransport<IS>>(&cached_outputs_identity)
-                .context("deserializing cached outputs description blob for task executor")?
-                .try_into()
-                .context("verifiying cached outputs description blob for task executor")
-        } else {
-            self.force_execute(working_directory, inputs)
+        let inputs_identity = identify_task_inputs::<IS>(inputs)
+            .context("identifying inputs object for task executor")?;
+        let cache_span = profile::span(Phase::CacheIo, || {
+            format!("read {}", inputs_identity.to_string())
+        });
+        if let Some(cached_outputs_identity) = self.lookup_outputs_identity(&inputs_identity) {
+            if let Some(outputs) = self.load_cached_outputs(
+                working_directory,
+                &inputs_identity,
+                &cached_outputs_identity,
+            )? {
+                publish_cache_lookup::<IS>(&inputs_identity, true);
+                return Ok(outputs);
+            }
         }
+        publish_cache_lookup::<IS>(&inputs_identity, false);
+        self.check_cached_failure(&inputs_identity)?;
+        drop(cache_span);
+        self.force_execute(working_directory, inputs)
     }
 
     fn load_or_execute_identity(
@@ -171,17 +1078,23 @@ impl<
         working_directory: &mut FS,
         inputs_identity: &IS::Identity,
     ) -> anyhow::Result<TaskOutputs<IS>> {
-        if let Ok(cached_outputs_identity) =
-            self.outputs_pointers.read_blob_pointer(inputs_identity)
-        {
-            self.blobs_cache
-                .read_blob::<TaskOutputsTransport<IS>>(&cached_outputs_identity)
-                .context("deserializing cached outputs description blob for task executor")?
-                .try_into()
-                .context("verifying cached outputs description blob for task executor")
-        } else {
-            self.force_execute_identity(working_directory, inputs_identity)
+        let cache_span = profile::span(Phase::CacheIo, || {
+            format!("read {}", inputs_identity.to_string())
+        });
+        if let Some(cached_outputs_identity) = self.lookup_outputs_identity(inputs_identity) {
+            if let Some(outputs) = self.load_cached_outputs(
+                working_directory,
+                inputs_identity,
+                &cached_outputs_identity,
+            )? {
+                publish_cache_lookup::<IS>(inputs_identity, true);
+                return Ok(outputs);
+            }
         }
+        publish_cache_lookup::<IS>(inputs_identity, false);
+        self.check_cached_failure(inputs_identity)?;
+        drop(cache_span);
+        self.force_execute_identity(working_directory, inputs_identity)
     }
 
     fn force_execute(
@@ -189,11 +1102,8 @@ impl<
         working_directory: &mut FS,
         inputs: &TaskInputs<IS>,
     ) -> anyhow::Result<TaskOutputs<IS>> {
-        let mut inputs_contents = vec![];
-        S::to_writer(&mut inputs_contents, &inputs.as_transport())
-            .context("serializing inputs object for task executor")?;
-        let inputs_identity = IS::identify_content(Cursor::new(inputs_contents))
-            .context("identifying serialized inputs object for task executor")?;
+        let inputs_identity = identify_task_inputs::<IS>(inputs)
+            .context("identifying inputs object for task executor")?;
         self.do_force_execute(working_directory, inputs, &inputs_identity)
     }
 
@@ -202,12 +1112,1061 @@ impl<
         working_directory: &mut FS,
         inputs_identity: &IS::Identity,
     ) -> anyhow::Result<TaskOutputs<IS>> {
+        if let Err(error) = self.read_through_blob(inputs_identity) {
+            tracing::warn!("reading inputs from remote cache: {:#}", error);
+        }
         let inputs: TaskInputs<IS> = self
             .blobs_cache
-            .read_blob::<TaskInputsTransport<IS>>(&inputs_identity)
+            .read_versioned_canonical_blob::<TaskInputsTransport<IS>>(&inputs_identity)
             .context("opening inputs blob for task executor")?
             .try_into()
             .context("deserializing inputs blob for task executor")?;
         self.do_force_execute(working_directory, &inputs, inputs_identity)
     }
+
+    fn query(&mut self, inputs: &TaskInputs<IS>) -> anyhow::Result<QueryReport<IS>> {
+        let inputs_identity = identify_task_inputs::<IS>(inputs)
+            .context("identifying inputs object for task executor")?;
+        let (status, output_paths) = match self.lookup_outputs_identity(&inputs_identity) {
+            Some(cached_outputs_identity) => {
+                let outputs = self.read_cached_outputs(&cached_outputs_identity)?;
+                (
+                    CacheStatus::Hit,
+                    outputs
+                        .output_files()
+                        .map(|(path, _)| path.clone())
+                        .collect(),
+                )
+            }
+            None => {
+                let status = if self.fresh_cached_failure(&inputs_identity)?.is_some() {
+                    CacheStatus::CachedFailure
+                } else {
+                    CacheStatus::Miss
+                };
+                (status, inputs.output_paths()?)
+            }
+        };
+        let cached_duration = self.read_task_result(&inputs_identity)?.map(|task_result| {
+            Duration::from_nanos(u64::try_from(task_result.duration_nanos).unwrap_or(u64::MAX))
+        });
+        Ok(QueryReport {
+            inputs_identity,
+            status,
+            output_paths,
+            cached_duration,
+        })
+    }
+}
+
+/// Task described by the files passed to the `execute` subcommand:
+///
+/// - `--program`: The program on the first line, followed by its arguments, one per line;
+/// - `--environment`: Environment variables, as one `key=value` pair per line;
+/// - `--inputs`: Input files, one path per line;
+/// - `--outputs`: Output files, one path per line.
+///
+/// Paths are relative to the working directory, and blank lines are ignored (except in arguments).
+pub struct ExecuteQuery<IS: IdentitySchemeApi> {
+    working_directory: PathBuf,
+    cache_directory: PathBuf,
+    inputs: TaskInputs<IS>,
+    repro: Option<ReproOptions>,
+    remote_cache: Option<RemoteBlobCache<IS>>,
+    replay_output: bool,
+    compression: Compression,
+    materialization: Materialization,
+    report: Option<PathBuf>,
+    timeout: Option<Duration>,
+}
+
+impl<IS: IdentitySchemeApi> ExecuteQuery<IS> {
+    /// Loads the files named by `command` and identifies the task's input files.
+    pub fn from_command(
+        working_directory: PathBuf,
+        cache_directory: PathBuf,
+        command: Execute,
+    ) -> anyhow::Result<Self> {
+        let mut program_lines = read_lines(&working_directory.join(&command.program))
+            .context("reading program file")?
+            .into_iter();
+        let program = program_lines
+            .by_ref()
+            .find(|line| !line.trim().is_empty())
+            .ok_or_else(|| anyhow::anyhow!("program file {:?} is empty", command.program))?;
+        let mut builder = TaskInputsBuilder::<IS>::new()
+            .program(program.trim())
+            .arguments(program_lines);
+
+        for line in read_lines(&working_directory.join(&command.environment))
+            .context("reading environment file")?
+        {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (name, value) = line.split_once('=').ok_or_else(|| {
+                anyhow::anyhow!("environment variable {:?} is not a `key=value` pair", line)
+            })?;
+            if name.is_empty() {
+                anyhow::bail!("environment variable {:?} has an empty key", line);
+            }
+            builder = builder.environment_variable(name, value);
+        }
+
+        let inputs_config = InputsTransport {
+            include_files: read_paths(&working_directory.join(&command.inputs))
+                .context("reading inputs file")?,
+            ..InputsTransport::default()
+        };
+        let mut filesystem = HostFilesystem::try_new(working_directory.clone())?;
+        let input_files =
+            FileIdentitiesManifest::<IS>::try_from_inputs(&mut filesystem, &inputs_config)
+                .map_err(Error::inputs_resolution)
+                .context("identifying input files")?;
+        for (path, identity) in input_files.identities() {
+            builder = builder.input_file(path, identity.clone());
+        }
+
+        let outputs_config = OutputsTransport {
+            include_files: read_paths(&working_directory.join(&command.outputs))
+                .context("reading outputs file")?,
+            optional_files: vec![],
+            include_match_transforms: vec![],
+            include_globs: vec![],
+            include_directories: vec![],
+            exclude_matches: vec![],
+            max_file_size_bytes: None,
+            exclude_file_types: vec![],
+            stdout_file: None,
+            stderr_file: None,
+            verification: OutputsVerification::default(),
+        };
+        let inputs = builder
+            .outputs_description(Outputs::try_from(outputs_config)?)
+            .build()?;
+
+        let repro = command.capture_repro.map(|directory| ReproOptions {
+            directory: working_directory.join(directory),
+            include_inputs: command.capture_repro_inputs,
+        });
+
+        let remote_cache = match command.remote_cache.as_ref() {
+            Some(url) => {
+                let token = match command.remote_cache_token_file.as_ref() {
+                    Some(token_file) => Some(
+                        std::fs::read_to_string(working_directory.join(token_file))
+                            .context("reading remote cache token file")?
+                            .trim()
+                            .to_string(),
+                    ),
+                    None => None,
+                };
+                Some(RemoteBlobCache::new(
+                    url,
+                    RemoteBlobCacheOptions {
+                        token,
+                        ..RemoteBlobCacheOptions::default()
+                    },
+                )?)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            cache_directory: working_directory.join(cache_directory),
+            report: command.report.map(|report| working_directory.join(report)),
+            timeout: command.timeout_secs.map(Duration::from_secs),
+            working_directory,
+            inputs,
+            repro,
+            remote_cache,
+            replay_output: !command.no_replay,
+            compression: if command.compress_blobs {
+                Compression::zstd()
+            } else {
+                Compression::None
+            },
+            materialization: if command.hard_link_outputs {
+                Materialization::PreferHardLinks
+            } else {
+                Materialization::Copy
+            },
+        })
+    }
+
+    pub fn inputs(&self) -> &TaskInputs<IS> {
+        &self.inputs
+    }
+
+    /// Reports whether the task's outputs are cached, without executing it; see
+    /// `TaskExecutor::query`.
+    pub fn query(self) -> anyhow::Result<QueryReport<IS>> {
+        let mut executor =
+            CacheDirectoryTaskExecutor::<HostFilesystem, IS, JSON, DefaultRunner>::new(
+                HostFilesystem::try_new(self.cache_directory.clone())?,
+            )
+            .context("opening cache directory")?;
+        if let Some(remote_cache) = self.remote_cache {
+            executor = executor.with_remote_cache(remote_cache);
+        }
+        executor.query(&self.inputs)
+    }
+
+    /// Loads the task's outputs from the cache directory, executing the task if they are not
+    /// cached yet.
+    pub fn run(self) -> anyhow::Result<TaskOutputs<IS>> {
+        let mut working_directory = HostFilesystem::try_new(self.working_directory.clone())?;
+        let mut executor =
+            CacheDirectoryTaskExecutor::<HostFilesystem, IS, JSON, DefaultRunner>::new(
+                HostFilesystem::try_new(self.cache_directory.clone())?,
+            )
+            .context("opening cache directory")?
+            .with_compression(self.compression)
+            .with_materialization(self.materialization);
+        if let Some(remote_cache) = self.remote_cache {
+            executor = executor.with_remote_cache(remote_cache);
+        }
+        if let Some(timeout) = self.timeout {
+            executor = executor.with_timeout(timeout);
+        }
+        if self.replay_output {
+            executor = executor.with_output_replay(std::io::stdout(), std::io::stderr());
+        }
+        let query = match self.report {
+            Some(_) => Some(
+                executor
+                    .query(&self.inputs)
+                    .context("querying cache for execution report")?,
+            ),
+            None => None,
+        };
+        let start = Instant::now();
+        let outputs = match self.repro.as_ref() {
+            Some(repro) => run_capturing_repro(
+                &mut executor,
+                &mut working_directory,
+                &self.inputs,
+                None,
+                false,
+                repro,
+            ),
+            None => executor.load_or_execute(&mut working_directory, &self.inputs),
+        }?;
+        let wall_duration = start.elapsed();
+
+        if let (Some(report_path), Some(query)) = (self.report.as_ref(), query) {
+            let report = execution_report(
+                &mut executor,
+                &mut working_directory,
+                query,
+                &outputs,
+                wall_duration,
+            )?;
+            let report_file = std::fs::File::create(report_path)
+                .with_context(|| format!("creating execution report {:?}", report_path))?;
+            serde_json::to_writer_pretty(report_file, &report)
+                .with_context(|| format!("writing execution report {:?}", report_path))?;
+        }
+        Ok(outputs)
+    }
+}
+
+/// Describes the execution of a task whose cache status before loading or executing it was
+/// `query`, and whose outputs, now in `working_directory`, are `outputs`.
+fn execution_report<FS, IS, S, R>(
+    executor: &mut CacheDirectoryTaskExecutor<FS, IS, S, R>,
+    working_directory: &mut FS,
+    query: QueryReport<IS>,
+    outputs: &TaskOutputs<IS>,
+    wall_duration: Duration,
+) -> anyhow::Result<ExecutionReport<IS>>
+where
+    FS: FilesystemApi,
+    IS: IdentitySchemeApi,
+    S: FileFormat + ReadDeserializer + StringSerializer + WriteSerializer,
+    R: Runner,
+{
+    let execution_duration_nanos = executor
+        .read_task_result(&query.inputs_identity)?
+        .map(|task_result| task_result.duration_nanos);
+    let output_files = outputs
+        .output_files()
+        .map(|(path, identity)| OutputFileReport {
+            path: path.clone(),
+            identity: identity.clone(),
+            size_bytes: identity
+                .as_ref()
+                .and_then(|_| working_directory.metadata(path).ok())
+                .map(|metadata| metadata.size),
+        })
+        .collect();
+    let mut system = sysinfo::System::new();
+    system.refresh_memory();
+    system.refresh_cpu();
+    Ok(ExecutionReport {
+        format_version: FormatVersion::default(),
+        identity_scheme: IS::IDENTITY_SCHEME,
+        inputs_identity: query.inputs_identity,
+        cache_hit: query.status == CacheStatus::Hit,
+        wall_duration_nanos: wall_duration.as_nanos(),
+        execution_duration_nanos,
+        output_files,
+        system: SystemTransport::from(system),
+    })
+}
+
+fn read_lines(path: &Path) -> anyhow::Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?;
+    Ok(contents.lines().map(String::from).collect())
+}
+
+fn read_paths(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    Ok(read_lines(path)?
+        .into_iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expand_execution_strategy;
+    use super::identify_task_inputs;
+    use super::CacheDirectoryTaskExecutor;
+    use super::CacheStatus;
+    use super::ExecuteQuery;
+    use super::Materialization;
+    use super::TaskExecutor as _;
+    use crate::args::Execute;
+    use crate::blob::CanonicalJSON;
+    use crate::blob::StringSerializer as _;
+    use crate::blob::CBOR;
+    use crate::blob::JSON;
+    use crate::cache::Cache;
+    use crate::cache::WriteOnDropIndex;
+    use crate::canonical::Arguments;
+    use crate::canonical::EnvironmentVariables;
+    use crate::canonical::FileIdentitiesManifest;
+    use crate::canonical::Outputs;
+    use crate::canonical::Program;
+    use crate::canonical::TaskInputs;
+    use crate::canonical::TaskOutputs;
+    use crate::error::Error;
+    use crate::facade::ArtifactExecutor;
+    use crate::fs::HostFilesystem;
+    use crate::identity::AsTransport as _;
+    use crate::identity::IdentityScheme as _;
+    use crate::runner::DefaultRunner;
+    use crate::transport::ContentSha256;
+    use crate::transport::ExecutionReport;
+    use crate::transport::ExecutionStrategy;
+    use crate::transport::IdentityScheme as IdentitySchemeEnum;
+    use crate::transport::Task;
+    use std::io::Write;
+    use std::os::unix::fs::MetadataExt as _;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[test]
+    fn test_identify_task_inputs() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let inputs = TaskInputs::<ContentSha256>::for_test("argument");
+        let outputs = TaskOutputs::<ContentSha256>::new(
+            FileIdentitiesManifest::empty(),
+            FileIdentitiesManifest::empty(),
+        );
+
+        let inputs_identity =
+            identify_task_inputs::<ContentSha256>(&inputs).expect("identify task inputs");
+        let canonical_inputs = r#"{"arguments":["argument"],"environment_variables":[],"format_version":1,"input_files":{"identities":[],"identity_scheme":"content_sha256"},"outputs_description":{},"program":"program"}"#;
+        assert_eq!(
+            canonical_inputs,
+            CanonicalJSON::to_string(&inputs.as_transport()).expect("canonical inputs")
+        );
+        assert_eq!(
+            ContentSha256::identify_content(canonical_inputs.as_bytes())
+                .expect("identify canonical inputs"),
+            inputs_identity
+        );
+        assert_eq!(
+            inputs_identity,
+            identify_task_inputs::<ContentSha256>(&inputs.clone())
+                .expect("identify task inputs again")
+        );
+
+        let mut cache = Cache::<
+            HostFilesystem,
+            ContentSha256,
+            JSON,
+            WriteOnDropIndex<HostFilesystem, ContentSha256, JSON>,
+        >::create(filesystem)
+        .expect("create cache");
+        cache.put_task(0, 0, inputs, outputs).expect("put task");
+        assert!(cache
+            .get_outputs(&inputs_identity)
+            .expect("get outputs")
+            .is_some());
+    }
+
+    #[test]
+    fn test_stdout_stderr_output_files() {
+        let cache_directory = tempfile::tempdir().expect("cache directory");
+        for sub_directory in [
+            "blobs",
+            "inputs_to_outputs",
+            "inputs_to_stdouts",
+            "inputs_to_stderrs",
+        ] {
+            std::fs::create_dir(cache_directory.path().join(sub_directory))
+                .expect("manually create cache directory");
+        }
+        let working_directory = tempfile::tempdir().expect("working directory");
+        let mut working_filesystem =
+            HostFilesystem::try_new(working_directory.path().to_path_buf())
+                .expect("working directory filesystem");
+        let mut executor =
+            CacheDirectoryTaskExecutor::<HostFilesystem, ContentSha256, JSON, DefaultRunner>::new(
+                HostFilesystem::try_new(cache_directory.path().to_path_buf())
+                    .expect("cache filesystem"),
+            )
+            .expect("task executor");
+        let inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("/bin/sh"),
+            Arguments::new(["-c", "echo out; echo err 1>&2"]),
+            FileIdentitiesManifest::empty(),
+            Outputs::empty()
+                .with_stdout_file("logs/stdout.txt")
+                .with_stderr_file("logs/stderr.txt"),
+        );
+
+        let outputs = executor
+            .force_execute(&mut working_filesystem, &inputs)
+            .expect("execute task");
+        assert_eq!(
+            vec![
+                (
+                    PathBuf::from("logs/stderr.txt"),
+                    Some(ContentSha256::identify_content("err\n".as_bytes()).expect("identity"))
+                ),
+                (
+                    PathBuf::from("logs/stdout.txt"),
+                    Some(ContentSha256::identify_content("out\n".as_bytes()).expect("identity"))
+                ),
+            ],
+            outputs.output_files().cloned().collect::<Vec<_>>()
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().expect("lock buffer").write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_output_replay() {
+        let cache_directory = tempfile::tempdir().expect("cache directory");
+        let working_directory = tempfile::tempdir().expect("working directory");
+        let mut working_filesystem =
+            HostFilesystem::try_new(working_directory.path().to_path_buf())
+                .expect("working directory filesystem");
+        let stdout = SharedBuffer::default();
+        let stderr = SharedBuffer::default();
+        let mut executor =
+            CacheDirectoryTaskExecutor::<HostFilesystem, ContentSha256, JSON, DefaultRunner>::new(
+                HostFilesystem::try_new(cache_directory.path().to_path_buf())
+                    .expect("cache filesystem"),
+            )
+            .expect("task executor")
+            .with_output_replay(stdout.clone(), stderr.clone());
+        let inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("/bin/sh"),
+            Arguments::new(["-c", "echo out; echo err 1>&2; echo run >> runs.txt"]),
+            FileIdentitiesManifest::empty(),
+            Outputs::empty(),
+        );
+
+        for _ in 0..2 {
+            executor
+                .load_or_execute(&mut working_filesystem, &inputs)
+                .expect("load or execute task");
+        }
+        // The second run was a cache hit, which replayed the streams recorded by the first.
+        assert_eq!(
+            "run\n",
+            std::fs::read_to_string(working_directory.path().join("runs.txt"))
+                .expect("read runs.txt")
+        );
+        assert_eq!(
+            b"out\nout\n".to_vec(),
+            *stdout.0.lock().expect("lock stdout")
+        );
+        assert_eq!(
+            b"err\nerr\n".to_vec(),
+            *stderr.0.lock().expect("lock stderr")
+        );
+    }
+
+    #[test]
+    fn test_materialize_outputs() {
+        let cache_directory = tempfile::tempdir().expect("cache directory");
+        let working_directory = tempfile::tempdir().expect("working directory");
+        let mut working_filesystem =
+            HostFilesystem::try_new(working_directory.path().to_path_buf())
+                .expect("working directory filesystem");
+        let executor = |materialization| {
+            CacheDirectoryTaskExecutor::<HostFilesystem, ContentSha256, JSON, DefaultRunner>::new(
+                HostFilesystem::try_new(cache_directory.path().to_path_buf())
+                    .expect("cache filesystem"),
+            )
+            .expect("task executor")
+            .with_materialization(materialization)
+        };
+        let inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("/bin/sh"),
+            Arguments::new([
+                "-c",
+                "mkdir -p out; echo output > out/output.txt; ln -s output.txt out/link.txt",
+            ]),
+            FileIdentitiesManifest::empty(),
+            Outputs::empty().with_include_globs(["out/*.txt"]),
+        );
+        let output_path = working_directory.path().join("out/output.txt");
+        let link_path = working_directory.path().join("out/link.txt");
+
+        let outputs = executor(Materialization::PreferHardLinks)
+            .load_or_execute(&mut working_filesystem, &inputs)
+            .expect("execute task");
+        std::fs::remove_dir_all(working_directory.path().join("out")).expect("remove outputs");
+
+        // The cache hit links the output to its blob, and recreates the symbolic link.
+        assert_eq!(
+            outputs,
+            executor(Materialization::PreferHardLinks)
+                .load_or_execute(&mut working_filesystem, &inputs)
+                .expect("load task")
+        );
+        assert_eq!(
+            "output\n",
+            std::fs::read_to_string(&output_path).expect("read output")
+        );
+        assert_eq!(
+            2,
+            std::fs::metadata(&output_path)
+                .expect("output metadata")
+                .nlink()
+        );
+        assert_eq!(
+            PathBuf::from("output.txt"),
+            std::fs::read_link(&link_path).expect("read link")
+        );
+
+        // By default, copies replace the existing output rather than writing through the hard link.
+        executor(Materialization::default())
+            .load_or_execute(&mut working_filesystem, &inputs)
+            .expect("load task");
+        assert_eq!(
+            1,
+            std::fs::metadata(&output_path)
+                .expect("output metadata")
+                .nlink()
+        );
+        assert_eq!(
+            "output\n",
+            std::fs::read_to_string(&output_path).expect("read output")
+        );
+    }
+
+    #[test]
+    fn test_missing_blobs_are_cache_misses() {
+        let cache_directory = tempfile::tempdir().expect("cache directory");
+        let working_directory = tempfile::tempdir().expect("working directory");
+        let mut working_filesystem =
+            HostFilesystem::try_new(working_directory.path().to_path_buf())
+                .expect("working directory filesystem");
+        let mut executor =
+            CacheDirectoryTaskExecutor::<HostFilesystem, ContentSha256, JSON, DefaultRunner>::new(
+                HostFilesystem::try_new(cache_directory.path().to_path_buf())
+                    .expect("cache filesystem"),
+            )
+            .expect("task executor")
+            .with_materialization(Materialization::Copy);
+        let inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("/bin/sh"),
+            Arguments::new(["-c", "echo run >> runs.log; echo output > output.txt"]),
+            FileIdentitiesManifest::empty(),
+            Outputs::empty().with_include_globs(["output.txt"]),
+        );
+        let runs = || {
+            std::fs::read_to_string(working_directory.path().join("runs.log"))
+                .expect("read runs")
+                .lines()
+                .count()
+        };
+
+        executor
+            .load_or_execute(&mut working_filesystem, &inputs)
+            .expect("execute task");
+        executor
+            .load_or_execute(&mut working_filesystem, &inputs)
+            .expect("load task");
+        assert_eq!(1, runs());
+
+        // Removing the blobs that the pointers refer to (e.g., by an unrelated garbage collection)
+        // leaves dangling pointers, which are cache misses rather than failures.
+        let blobs_directory = cache_directory.path().join("blobs");
+        std::fs::remove_dir_all(&blobs_directory).expect("remove blobs");
+        std::fs::create_dir(&blobs_directory).expect("create blobs directory");
+        std::fs::remove_file(working_directory.path().join("output.txt")).expect("remove output");
+        executor
+            .load_or_execute(&mut working_filesystem, &inputs)
+            .expect("execute task again");
+        assert_eq!(2, runs());
+        assert_eq!(
+            "output\n",
+            std::fs::read_to_string(working_directory.path().join("output.txt"))
+                .expect("read output")
+        );
+    }
+
+    #[test]
+    fn test_task_result() {
+        let cache_directory = tempfile::tempdir().expect("cache directory");
+        let working_directory = tempfile::tempdir().expect("working directory");
+        let mut working_filesystem =
+            HostFilesystem::try_new(working_directory.path().to_path_buf())
+                .expect("working directory filesystem");
+        let mut executor =
+            CacheDirectoryTaskExecutor::<HostFilesystem, ContentSha256, JSON, DefaultRunner>::new(
+                HostFilesystem::try_new(cache_directory.path().to_path_buf())
+                    .expect("cache filesystem"),
+            )
+            .expect("task executor");
+        let task_inputs = |script: &str| {
+            TaskInputs::<ContentSha256>::new(
+                EnvironmentVariables::empty(),
+                Program::new("/bin/sh"),
+                Arguments::new(["-c", script]),
+                FileIdentitiesManifest::empty(),
+                Outputs::empty(),
+            )
+        };
+
+        let succeeds = task_inputs("exit 0");
+        executor
+            .load_or_execute(&mut working_filesystem, &succeeds)
+            .expect("execute succeeding task");
+        let result = executor
+            .read_task_result(&identify_task_inputs(&succeeds).expect("identify inputs"))
+            .expect("read task result")
+            .expect("task result");
+        assert!(result.success());
+        assert_eq!(Some(0), result.exit_code);
+
+        let fails = task_inputs("exit 3");
+        let fails_identity = identify_task_inputs(&fails).expect("identify inputs");
+        assert_eq!(
+            None,
+            executor
+                .read_task_result(&fails_identity)
+                .expect("read task result")
+        );
+        let error = executor
+            .load_or_execute(&mut working_filesystem, &fails)
+            .expect_err("execute failing task");
+        assert!(matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::ChildFailed { .. })
+        ));
+        let result = executor
+            .read_task_result(&fails_identity)
+            .expect("read task result")
+            .expect("task result");
+        assert!(!result.success());
+        assert_eq!(Some(3), result.exit_code);
+        assert_eq!(None, result.signal);
+
+        let killed = task_inputs("kill -KILL $$");
+        executor
+            .load_or_execute(&mut working_filesystem, &killed)
+            .expect_err("execute killed task");
+        let result = executor
+            .read_task_result(&identify_task_inputs(&killed).expect("identify inputs"))
+            .expect("read task result")
+            .expect("task result");
+        assert_eq!(None, result.exit_code);
+        assert_eq!(Some(9), result.signal);
+    }
+
+    #[test]
+    fn test_failure_caching() {
+        let cache_directory = tempfile::tempdir().expect("cache directory");
+        let working_directory = tempfile::tempdir().expect("working directory");
+        let mut working_filesystem =
+            HostFilesystem::try_new(working_directory.path().to_path_buf())
+                .expect("working directory filesystem");
+        let mut executor =
+            CacheDirectoryTaskExecutor::<HostFilesystem, ContentSha256, JSON, DefaultRunner>::new(
+                HostFilesystem::try_new(cache_directory.path().to_path_buf())
+                    .expect("cache filesystem"),
+            )
+            .expect("task executor");
+        let inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("/bin/sh"),
+            Arguments::new(["-c", "echo run >> runs.txt; exit 2"]),
+            FileIdentitiesManifest::empty(),
+            Outputs::empty(),
+        );
+        let runs = || {
+            std::fs::read_to_string(working_directory.path().join("runs.txt"))
+                .expect("read runs.txt")
+                .lines()
+                .count()
+        };
+        let mut expect_child_failed = |executor: &mut CacheDirectoryTaskExecutor<_, _, _, _>| {
+            let error = executor
+                .load_or_execute(&mut working_filesystem, &inputs)
+                .expect_err("load or execute failing task");
+            match error.downcast_ref::<Error>() {
+                Some(Error::ChildFailed { status, .. }) => assert_eq!(Some(2), status.code()),
+                _ => panic!("expected child failure, got {:?}", error),
+            }
+        };
+
+        // Failures are not cached by default.
+        expect_child_failed(&mut executor);
+        expect_child_failed(&mut executor);
+        assert_eq!(2, runs());
+
+        executor.set_failure_ttl(Some(Duration::from_secs(3600)));
+        expect_child_failed(&mut executor);
+        expect_child_failed(&mut executor);
+        assert_eq!(3, runs());
+
+        // Expired failures are executed again.
+        executor.set_failure_ttl(Some(Duration::ZERO));
+        expect_child_failed(&mut executor);
+        assert_eq!(4, runs());
+    }
+
+    #[test]
+    fn test_cbor_executor() {
+        let cache_directory = tempfile::tempdir().expect("cache directory");
+        let working_directory = tempfile::tempdir().expect("working directory");
+        let mut working_filesystem =
+            HostFilesystem::try_new(working_directory.path().to_path_buf())
+                .expect("working directory filesystem");
+        let mut executor =
+            CacheDirectoryTaskExecutor::<HostFilesystem, ContentSha256, CBOR, DefaultRunner>::new(
+                HostFilesystem::try_new(cache_directory.path().to_path_buf())
+                    .expect("cache filesystem"),
+            )
+            .expect("task executor");
+
+        // Inputs blobs are canonical JSON whatever the executor's format, and are read back as such.
+        let inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("/bin/sh"),
+            Arguments::new(["-c", "echo output > output.txt"]),
+            FileIdentitiesManifest::empty(),
+            Outputs::empty().with_include_globs(["output.txt"]),
+        );
+        let inputs_identity = identify_task_inputs(&inputs).expect("identify task inputs");
+        let outputs = executor
+            .load_or_execute(&mut working_filesystem, &inputs)
+            .expect("execute task");
+        assert_eq!(
+            outputs,
+            executor
+                .force_execute_identity(&mut working_filesystem, &inputs_identity)
+                .expect("force execute task by identity")
+        );
+
+        let failing_inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("/bin/sh"),
+            Arguments::new(["-c", "echo run >> runs.txt; exit 2"]),
+            FileIdentitiesManifest::empty(),
+            Outputs::empty(),
+        );
+        executor.set_failure_ttl(Some(Duration::from_secs(3600)));
+        for _ in 0..2 {
+            let error = executor
+                .load_or_execute(&mut working_filesystem, &failing_inputs)
+                .expect_err("load or execute failing task");
+            match error.downcast_ref::<Error>() {
+                Some(Error::ChildFailed { status, .. }) => assert_eq!(Some(2), status.code()),
+                _ => panic!("expected child failure, got {:?}", error),
+            }
+        }
+        assert_eq!(
+            1,
+            std::fs::read_to_string(working_directory.path().join("runs.txt"))
+                .expect("read runs.txt")
+                .lines()
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_query() {
+        let cache_directory = tempfile::tempdir().expect("cache directory");
+        let working_directory = tempfile::tempdir().expect("working directory");
+        let mut working_filesystem =
+            HostFilesystem::try_new(working_directory.path().to_path_buf())
+                .expect("working directory filesystem");
+        let mut executor =
+            CacheDirectoryTaskExecutor::<HostFilesystem, ContentSha256, JSON, DefaultRunner>::new(
+                HostFilesystem::try_new(cache_directory.path().to_path_buf())
+                    .expect("cache filesystem"),
+            )
+            .expect("task executor");
+        let inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("/bin/sh"),
+            Arguments::new(["-c", "echo output > output.txt"]),
+            FileIdentitiesManifest::empty(),
+            Outputs::empty().with_optional_files(["output.txt"]),
+        );
+        let inputs_identity = identify_task_inputs(&inputs).expect("identify task inputs");
+
+        // Queries do not execute the task.
+        let report = executor.query(&inputs).expect("query uncached task");
+        assert_eq!(inputs_identity, report.inputs_identity);
+        assert_eq!(CacheStatus::Miss, report.status);
+        assert_eq!(vec![PathBuf::from("output.txt")], report.output_paths);
+        assert_eq!(None, report.cached_duration);
+        assert!(!working_directory.path().join("output.txt").exists());
+
+        executor
+            .load_or_execute(&mut working_filesystem, &inputs)
+            .expect("execute task");
+        let report = executor.query(&inputs).expect("query cached task");
+        assert_eq!(CacheStatus::Hit, report.status);
+        assert_eq!(vec![PathBuf::from("output.txt")], report.output_paths);
+        assert!(report.cached_duration.is_some());
+
+        let failing_inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new("/bin/sh"),
+            Arguments::new(["-c", "exit 2"]),
+            FileIdentitiesManifest::empty(),
+            Outputs::empty(),
+        );
+        executor.set_failure_ttl(Some(Duration::from_secs(3600)));
+        executor
+            .load_or_execute(&mut working_filesystem, &failing_inputs)
+            .expect_err("execute failing task");
+        assert_eq!(
+            CacheStatus::CachedFailure,
+            executor
+                .query(&failing_inputs)
+                .expect("query failed task")
+                .status
+        );
+    }
+
+    #[test]
+    fn test_execute_query() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let working_directory = temporary_directory.path().to_path_buf();
+        for (path, contents) in [
+            (
+                "program",
+                "/bin/sh\n-c\ncat input.txt > output.txt; echo \"$GREETING\" >> output.txt\n",
+            ),
+            ("environment", "GREETING=hello=world\n\n"),
+            ("inputs", "input.txt\n"),
+            ("outputs", "output.txt\n"),
+            ("input.txt", "input\n"),
+        ] {
+            std::fs::write(working_directory.join(path), contents).expect("write file");
+        }
+        let command = || Execute {
+            program: PathBuf::from("program"),
+            environment: PathBuf::from("environment"),
+            inputs: PathBuf::from("inputs"),
+            outputs: PathBuf::from("outputs"),
+            capture_repro: None,
+            capture_repro_inputs: false,
+            remote_cache: None,
+            remote_cache_token_file: None,
+            no_replay: true,
+            compress_blobs: true,
+            hard_link_outputs: false,
+            dry_run: false,
+            report: Some(PathBuf::from("report.json")),
+            timeout_secs: None,
+            identity_scheme: IdentitySchemeEnum::ContentSha256,
+        };
+
+        let execute = ExecuteQuery::<ContentSha256>::from_command(
+            working_directory.clone(),
+            PathBuf::from("cache"),
+            command(),
+        )
+        .expect("execute query");
+        assert_eq!(&PathBuf::from("/bin/sh"), execute.inputs().program());
+        assert_eq!(
+            vec![(String::from("GREETING"), String::from("hello=world"))],
+            execute
+                .inputs()
+                .environment_variables()
+                .cloned()
+                .collect::<Vec<_>>()
+        );
+        let inputs_identity = identify_task_inputs(execute.inputs()).expect("identify task inputs");
+        let outputs = execute.run().expect("run task");
+
+        assert_eq!(
+            vec![(
+                PathBuf::from("output.txt"),
+                Some(
+                    ContentSha256::identify_content("input\nhello=world\n".as_bytes())
+                        .expect("identity")
+                )
+            )],
+            outputs.output_files().cloned().collect::<Vec<_>>()
+        );
+        assert!(working_directory.join("cache/inputs_to_outputs").is_dir());
+
+        let read_report = || -> ExecutionReport<ContentSha256> {
+            serde_json::from_str(
+                &std::fs::read_to_string(working_directory.join("report.json"))
+                    .expect("read report"),
+            )
+            .expect("parse report")
+        };
+        let report = read_report();
+        assert_eq!(IdentitySchemeEnum::ContentSha256, report.identity_scheme);
+        assert_eq!(inputs_identity, report.inputs_identity);
+        assert!(!report.cache_hit);
+        assert_eq!(1, report.output_files.len());
+        assert_eq!(PathBuf::from("output.txt"), report.output_files[0].path);
+        assert_eq!(
+            outputs
+                .output_files()
+                .next()
+                .and_then(|(_, identity)| identity.clone()),
+            report.output_files[0].identity
+        );
+        assert_eq!(Some(18), report.output_files[0].size_bytes);
+        assert!(report.execution_duration_nanos.is_some());
+
+        ExecuteQuery::<ContentSha256>::from_command(
+            working_directory.clone(),
+            PathBuf::from("cache"),
+            command(),
+        )
+        .expect("execute query")
+        .run()
+        .expect("load task");
+        let cached_report = read_report();
+        assert!(cached_report.cache_hit);
+        assert_eq!(inputs_identity, cached_report.inputs_identity);
+        assert_eq!(
+            report.execution_duration_nanos,
+            cached_report.execution_duration_nanos
+        );
+
+        // Failing to write the report fails the command, even though the task's outputs loaded.
+        let error = ExecuteQuery::<ContentSha256>::from_command(
+            working_directory.clone(),
+            PathBuf::from("cache"),
+            Execute {
+                report: Some(PathBuf::from("missing/report.json")),
+                ..command()
+            },
+        )
+        .expect("execute query")
+        .run()
+        .expect_err("write report to missing directory");
+        assert!(format!("{:#}", error).contains("creating execution report"));
+        assert!(working_directory.join("output.txt").is_file());
+    }
+
+    #[test]
+    fn test_expand_execution_strategy() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let working_directory = temporary_directory.path().to_path_buf();
+        for (name, contents) in [("a.c", "a"), ("b.c", "b"), ("c.h", "c")] {
+            std::fs::write(working_directory.join(name), contents).expect("write input");
+        }
+        let task: Task = serde_json::from_str(
+            r#"{
+                "execution_strategy": {"for_each_input": {"inputs_filter": {"include_globs": ["*.c"]}}},
+                "environment_variables": [],
+                "program": "/bin/sh",
+                "arguments": ["-c", "cat {input} c.h > {input}.o; echo {input} >> runs.txt"],
+                "inputs": {"include_files": ["/bin/sh", "c.h"]},
+                "outputs": {"include_files": ["{input}.o"]}
+            }"#,
+        )
+        .expect("parse task");
+
+        let mut filesystem =
+            HostFilesystem::try_new(working_directory.clone()).expect("filesystem");
+        let expanded = expand_execution_strategy(&mut filesystem, &task).expect("expand task");
+        assert_eq!(2, expanded.len());
+        assert!(expanded
+            .iter()
+            .all(|task| matches!(task.execution_strategy, ExecutionStrategy::Simple)));
+        assert_eq!(
+            vec![
+                String::from("-c"),
+                String::from("cat a.c c.h > a.c.o; echo a.c >> runs.txt")
+            ],
+            expanded[0].arguments.arguments
+        );
+        assert_eq!(
+            vec![PathBuf::from("b.c.o")],
+            expanded[1].outputs.include_files
+        );
+        assert_eq!(
+            vec![
+                PathBuf::from("/bin/sh"),
+                PathBuf::from("c.h"),
+                PathBuf::from("b.c")
+            ],
+            expanded[1].inputs.include_files
+        );
+
+        let mut executor = ArtifactExecutor::builder()
+            .working_directory(&working_directory)
+            .build()
+            .expect("build executor");
+        assert!(executor.run_task(&task).is_err());
+        let outputs = executor.run_task_executions(&task).expect("run task");
+        assert_eq!(2, outputs.len());
+        assert_eq!(
+            "ac",
+            std::fs::read_to_string(working_directory.join("a.c.o")).expect("read a.c.o")
+        );
+
+        // Only the execution whose input changed runs again.
+        std::fs::write(working_directory.join("b.c"), "changed").expect("change b.c");
+        executor
+            .run_task_executions(&task)
+            .expect("run changed task");
+        assert_eq!(
+            "a.c\nb.c\nb.c\n",
+            std::fs::read_to_string(working_directory.join("runs.txt")).expect("read runs.txt")
+        );
+    }
 }
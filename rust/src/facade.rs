@@ -0,0 +1,922 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! A high-level entry point for embedding the executor in other programs.
+//!
+//! `ArtifactExecutor::builder()` wires a host filesystem, a cache directory, an identity scheme, a
+//! serialization format, and a runner together, defaulting to SHA256 content identities, JSON
+//! serialization, and `DefaultRunner`. Output files of tasks whose outputs are loaded from the
+//! cache are restored into the working directory, as `ArtifactExecutorBuilder::materialization`
+//! configures:
+//!
+//! ```no_run
+//! use artifact_executor::facade::ArtifactExecutor;
+//!
+//! let mut executor = ArtifactExecutor::builder()
+//!     .cache_directory("/tmp/ae-cache")
+//!     .build()?;
+//! let outputs = executor.run_task_file("task.json")?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::blob::FileFormat;
+use crate::blob::ReadDeserializer;
+use crate::blob::StringSerializer;
+use crate::blob::WriteSerializer;
+use crate::blob::JSON;
+use crate::canonical::FileIdentitiesManifest;
+use crate::canonical::Outputs;
+use crate::canonical::TaskInputs;
+use crate::canonical::TaskInputsBuilder;
+use crate::canonical::TaskLabels;
+use crate::canonical::TaskOutputs;
+use crate::error::Error;
+use crate::error::IoOperation;
+use crate::error::Result;
+use crate::execute::expand_execution_strategy;
+use crate::execute::CacheDirectoryTaskExecutor;
+use crate::execute::Materialization;
+use crate::execute::TaskExecutor as _;
+use crate::fs::Filesystem as _;
+use crate::fs::HostFilesystem;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::repro::run_capturing_repro;
+use crate::repro::ReproOptions;
+use crate::runner::DefaultRunner;
+use crate::runner::Runner;
+use crate::task_file::load_task_file;
+use crate::task_file::task_filesystem;
+use crate::task_graph::read_task_graph_file;
+use crate::task_graph::TaskGraph;
+use crate::template::TemplateContext;
+use crate::trace::TraceValidation;
+use crate::transport::ContentSha256;
+use crate::transport::ExecutionStrategy;
+use crate::transport::Task;
+use anyhow::Context as _;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Cache directory used when none is configured, relative to the working directory. Matches the
+/// command line's default.
+pub const DEFAULT_CACHE_DIRECTORY: &str = "ae-cache";
+
+/// Configures an `ArtifactExecutor`. Created by `ArtifactExecutor::builder()`.
+pub struct ArtifactExecutorBuilder<IS, S, R> {
+    working_directory: Option<PathBuf>,
+    cache_directory: Option<PathBuf>,
+    repro: Option<ReproOptions>,
+    trace_validation: Option<TraceValidation>,
+    allowed_environment_variables: Option<Vec<String>>,
+    materialization: Materialization,
+    runner: R,
+    phantom: PhantomData<(IS, S)>,
+}
+
+impl Default for ArtifactExecutorBuilder<ContentSha256, JSON, DefaultRunner> {
+    fn default() -> Self {
+        Self {
+            working_directory: None,
+            cache_directory: None,
+            repro: None,
+            trace_validation: None,
+            allowed_environment_variables: None,
+            materialization: Materialization::default(),
+            runner: DefaultRunner::default(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<IS, S, R> ArtifactExecutorBuilder<IS, S, R> {
+    /// Sets the directory in which tasks run, and against which relative task paths and cache
+    /// directories are resolved. Default: the current directory.
+    pub fn working_directory<P: AsRef<Path>>(mut self, working_directory: P) -> Self {
+        self.working_directory = Some(working_directory.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the directory in which task outputs and metadata are cached. Default:
+    /// `DEFAULT_CACHE_DIRECTORY` in the working directory.
+    pub fn cache_directory<P: AsRef<Path>>(mut self, cache_directory: P) -> Self {
+        self.cache_directory = Some(cache_directory.as_ref().to_path_buf());
+        self
+    }
+
+    /// Writes a reproduction bundle (see `crate::repro`) to `options.directory`, relative to the
+    /// working directory, whenever a task fails. Default: no bundles are written.
+    pub fn capture_repro(mut self, options: ReproOptions) -> Self {
+        self.repro = Some(options);
+        self
+    }
+
+    /// Checks the file accesses traced by the runner against each executed task's declarations;
+    /// see `crate::trace`. Requires a `crate::runner::TracedRunner`. Default: Traces are not
+    /// checked.
+    pub fn validate_trace(mut self, trace_validation: TraceValidation) -> Self {
+        self.trace_validation = Some(trace_validation);
+        self
+    }
+
+    /// Allows task templates and environment inheritance to read only the host environment
+    /// variables in `names`; see `TemplateContext::with_allowed_environment_variables`. Default: all
+    /// host environment variables are allowed.
+    pub fn allow_host_environment_variables<I: IntoIterator<Item = N>, N: Into<String>>(
+        mut self,
+        names: I,
+    ) -> Self {
+        self.allowed_environment_variables = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Sets how the output files of tasks whose outputs are loaded from the cache are written into
+    /// their working directories. Default: `Materialization::default()`.
+    pub fn materialization(mut self, materialization: Materialization) -> Self {
+        self.materialization = materialization;
+        self
+    }
+
+    /// Identifies files and tasks with `IS2` instead of SHA256 content hashes.
+    pub fn identity_scheme<IS2>(self) -> ArtifactExecutorBuilder<IS2, S, R> {
+        ArtifactExecutorBuilder {
+            working_directory: self.working_directory,
+            cache_directory: self.cache_directory,
+            repro: self.repro,
+            trace_validation: self.trace_validation,
+            allowed_environment_variables: self.allowed_environment_variables,
+            materialization: self.materialization,
+            runner: self.runner,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Serializes cached objects with `S2` instead of JSON.
+    pub fn serialization<S2>(self) -> ArtifactExecutorBuilder<IS, S2, R> {
+        ArtifactExecutorBuilder {
+            working_directory: self.working_directory,
+            cache_directory: self.cache_directory,
+            repro: self.repro,
+            trace_validation: self.trace_validation,
+            allowed_environment_variables: self.allowed_environment_variables,
+            materialization: self.materialization,
+            runner: self.runner,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Runs task programs with `runner` instead of `DefaultRunner`, which enforces the sandboxes
+    /// and resource limits that tasks request.
+    pub fn runner<R2>(self, runner: R2) -> ArtifactExecutorBuilder<IS, S, R2> {
+        ArtifactExecutorBuilder {
+            working_directory: self.working_directory,
+            cache_directory: self.cache_directory,
+            repro: self.repro,
+            trace_validation: self.trace_validation,
+            allowed_environment_variables: self.allowed_environment_variables,
+            materialization: self.materialization,
+            runner,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<
+        IS: IdentitySchemeApi,
+        S: FileFormat + ReadDeserializer + StringSerializer + WriteSerializer,
+        R: Runner,
+    > ArtifactExecutorBuilder<IS, S, R>
+{
+    /// Resolves the configured directories, creating the cache directory if necessary.
+    pub fn build(self) -> Result<ArtifactExecutor<IS, S, R>> {
+        let current_directory = std::env::current_dir().context("getting current directory")?;
+        let working_directory = match self.working_directory {
+            Some(working_directory) => current_directory.join(working_directory),
+            None => current_directory,
+        };
+        let cache_directory = working_directory.join(
+            self.cache_directory
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_CACHE_DIRECTORY)),
+        );
+        std::fs::create_dir_all(&cache_directory)
+            .map_err(|error| Error::io(&cache_directory, IoOperation::Create, error))?;
+
+        let repro = self.repro.map(|options| ReproOptions {
+            directory: working_directory.join(options.directory),
+            ..options
+        });
+
+        let mut executor = CacheDirectoryTaskExecutor::new_with_runner(
+            HostFilesystem::try_new(cache_directory)?,
+            self.runner,
+        )
+        .context("creating task executor")?
+        .with_materialization(self.materialization);
+        if let Some(trace_validation) = self.trace_validation {
+            executor = executor.with_trace_validation(trace_validation);
+        }
+        let mut template_context = TemplateContext::from_host(&working_directory);
+        if let Some(names) = self.allowed_environment_variables {
+            template_context = template_context.with_allowed_environment_variables(names);
+        }
+        Ok(ArtifactExecutor {
+            working_directory: HostFilesystem::try_new(working_directory.clone())?,
+            template_context,
+            executor,
+            repro,
+        })
+    }
+}
+
+/// Runs tasks on the host, loading their outputs from a cache directory when their inputs are
+/// unchanged.
+pub struct ArtifactExecutor<IS = ContentSha256, S = JSON, R = DefaultRunner>
+where
+    IS: IdentitySchemeApi,
+    S: FileFormat + ReadDeserializer + StringSerializer + WriteSerializer,
+    R: Runner,
+{
+    working_directory: HostFilesystem,
+    template_context: TemplateContext,
+    executor: CacheDirectoryTaskExecutor<HostFilesystem, IS, S, R>,
+    repro: Option<ReproOptions>,
+}
+
+impl ArtifactExecutor {
+    /// Creates a builder with the default configuration.
+    pub fn builder() -> ArtifactExecutorBuilder<ContentSha256, JSON, DefaultRunner> {
+        ArtifactExecutorBuilder::default()
+    }
+}
+
+impl<
+        IS: IdentitySchemeApi,
+        S: FileFormat + ReadDeserializer + StringSerializer + WriteSerializer,
+        R: Runner,
+    > ArtifactExecutor<IS, S, R>
+{
+    /// Gets the directory in which tasks run.
+    pub fn working_directory(&self) -> PathBuf {
+        self.working_directory
+            .clone()
+            .working_directory()
+            .expect("host filesystem has a working directory")
+    }
+
+    /// Loads the outputs of the task described by `inputs` from the cache, or runs it in the
+    /// working directory if they are not cached.
+    pub fn run(&mut self, inputs: &TaskInputs<IS>) -> Result<TaskOutputs<IS>> {
+        Ok(Self::execute(
+            &mut self.executor,
+            &mut self.working_directory,
+            inputs,
+            None,
+            false,
+            self.repro.as_ref(),
+        )?)
+    }
+
+    /// Runs the task described by `inputs` in the working directory, regardless of whether its
+    /// outputs are cached.
+    pub fn force_run(&mut self, inputs: &TaskInputs<IS>) -> Result<TaskOutputs<IS>> {
+        Ok(Self::execute(
+            &mut self.executor,
+            &mut self.working_directory,
+            inputs,
+            None,
+            true,
+            self.repro.as_ref(),
+        )?)
+    }
+
+    /// Reads the outputs cached for the task whose inputs have identity `inputs_identity` (see
+    /// `crate::execute::identify_task_inputs`), without running it or writing its output files.
+    pub fn cached_outputs(
+        &mut self,
+        inputs_identity: &IS::Identity,
+    ) -> Result<Option<TaskOutputs<IS>>> {
+        Ok(self.executor.read_task_outputs(inputs_identity)?)
+    }
+
+    /// Resolves `task` into canonical inputs: expands its templates against the host environment,
+    /// and identifies its input files in the task's working directory.
+    pub fn task_inputs(&mut self, task: &Task) -> Result<TaskInputs<IS>> {
+        let mut task_working_directory = self.task_working_directory(task)?;
+        Ok(Self::resolve_task_inputs(
+            &mut task_working_directory,
+            &self.template_context,
+            task,
+        )?)
+    }
+
+    /// Like `run`, with inputs resolved from `task` by `task_inputs`, in the task's own working
+    /// directory, if it declares one. Fails for tasks that are executed more than once; see
+    /// `run_task_executions`.
+    pub fn run_task(&mut self, task: &Task) -> Result<TaskOutputs<IS>> {
+        Self::check_simple_execution_strategy(task)?;
+        let inputs = self.task_inputs(task)?;
+        let mut task_working_directory = self.task_working_directory(task)?;
+        Ok(Self::execute(
+            &mut self.executor,
+            &mut task_working_directory,
+            &inputs,
+            Some(task),
+            false,
+            self.repro.as_ref(),
+        )?)
+    }
+
+    /// Runs each execution of `task` called for by its execution strategy (see
+    /// `crate::execute::expand_execution_strategy`), returning their outputs in order.
+    pub fn run_task_executions(&mut self, task: &Task) -> Result<Vec<TaskOutputs<IS>>> {
+        let mut task_working_directory = self.task_working_directory(task)?;
+        expand_execution_strategy(&mut task_working_directory, task)?
+            .iter()
+            .map(|task| self.run_task(task))
+            .collect()
+    }
+
+    /// Loads the task file at `path`, relative to the working directory, and runs it in the task's
+    /// own working directory, if it declares one.
+    pub fn run_task_file<P: AsRef<Path>>(&mut self, path: P) -> Result<TaskOutputs<IS>> {
+        let path = path.as_ref();
+        let task = load_task_file(&mut self.working_directory, path, &self.template_context)?;
+        Self::check_simple_execution_strategy(&task)?;
+        let mut task_working_directory = task_filesystem(&mut self.working_directory, path, &task)?;
+        let inputs =
+            Self::resolve_task_inputs(&mut task_working_directory, &self.template_context, &task)
+                .with_context(|| format!("resolving inputs of task file {:?}", path))?;
+        Ok(Self::execute(
+            &mut self.executor,
+            &mut task_working_directory,
+            &inputs,
+            Some(&task),
+            false,
+            self.repro.as_ref(),
+        )?)
+    }
+
+    /// Runs every task in `graph`, each after its dependencies and in its own working directory
+    /// (see `run_task`), returning the outputs of each task by label. Wired outputs are resolved
+    /// against the working directory of the task that produces them, and copied to the consuming
+    /// task's input path, in its working directory, unless that is the same file. Stops at the
+    /// first task that fails.
+    pub fn run_task_graph(
+        &mut self,
+        graph: &TaskGraph,
+    ) -> Result<BTreeMap<String, TaskOutputs<IS>>> {
+        let mut outputs = BTreeMap::new();
+        for label in graph.topological_order() {
+            let task = graph
+                .task(label)
+                .expect("topologically ordered label is in task graph");
+            for wiring in graph.wired_inputs(label) {
+                let producer = graph
+                    .task(&wiring.from_task)
+                    .expect("wired task is in task graph");
+                let input = wiring.input.as_ref().unwrap_or(&wiring.output);
+                if producer.working_directory == task.working_directory && *input == wiring.output {
+                    continue;
+                }
+                let mut producer_working_directory = self.task_working_directory(producer)?;
+                let mut consumer_working_directory = self.task_working_directory(task)?;
+                Self::copy_wired_output(
+                    &mut producer_working_directory,
+                    &wiring.output,
+                    &mut consumer_working_directory,
+                    input,
+                )
+                .with_context(|| {
+                    format!(
+                        "wiring output {:?} of task {:?} to input {:?} of task {:?}",
+                        wiring.output, wiring.from_task, input, label
+                    )
+                })?;
+            }
+            let task_outputs = self
+                .run_task(task)
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("running task {:?}", label))?;
+            outputs.insert(label.clone(), task_outputs);
+        }
+        Ok(outputs)
+    }
+
+    /// Loads the task graph file at `path`, relative to the working directory (see
+    /// `crate::task_graph::read_task_graph_file`), and runs it with `run_task_graph`.
+    pub fn run_task_graph_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<BTreeMap<String, TaskOutputs<IS>>> {
+        let graph = read_task_graph_file(&mut self.working_directory, path)?;
+        self.run_task_graph(&graph)
+    }
+
+    /// Creates the filesystem in which `task` runs, against which its relative paths are resolved:
+    /// a sub-system of the working directory rooted at the task's own working directory, or the
+    /// working directory itself when the task does not declare one.
+    fn task_working_directory(&mut self, task: &Task) -> anyhow::Result<HostFilesystem> {
+        match task.working_directory.as_ref() {
+            Some(directory) => self
+                .working_directory
+                .sub_system(directory)
+                .with_context(|| {
+                    format!("creating filesystem for working directory {:?}", directory)
+                }),
+            None => Ok(self.working_directory.clone()),
+        }
+    }
+
+    fn copy_wired_output(
+        producer_working_directory: &mut HostFilesystem,
+        output: &Path,
+        consumer_working_directory: &mut HostFilesystem,
+        input: &Path,
+    ) -> anyhow::Result<()> {
+        let mut reader = producer_working_directory.open_file_for_read(output)?;
+        if let Some(parent) = input.parent() {
+            consumer_working_directory.create_directories(parent)?;
+        }
+        let mut writer = consumer_working_directory.open_file_for_write(input)?;
+        std::io::copy(&mut reader, &mut writer)?;
+        Ok(())
+    }
+
+    fn execute(
+        executor: &mut CacheDirectoryTaskExecutor<HostFilesystem, IS, S, R>,
+        working_directory: &mut HostFilesystem,
+        inputs: &TaskInputs<IS>,
+        task: Option<&Task>,
+        force: bool,
+        repro: Option<&ReproOptions>,
+    ) -> anyhow::Result<TaskOutputs<IS>> {
+        executor.set_failure_ttl(
+            task.and_then(|task| task.failure_caching.as_ref())
+                .map(|failure_caching| Duration::from_secs(failure_caching.ttl_seconds)),
+        );
+        executor.set_labels(task.map(TaskLabels::from).unwrap_or_default());
+        match repro {
+            Some(options) => {
+                run_capturing_repro(executor, working_directory, inputs, task, force, options)
+            }
+            None if force => executor.force_execute(working_directory, inputs),
+            None => executor.load_or_execute(working_directory, inputs),
+        }
+    }
+
+    fn check_simple_execution_strategy(task: &Task) -> anyhow::Result<()> {
+        if !matches!(task.execution_strategy, ExecutionStrategy::Simple) {
+            anyhow::bail!("task executed more than once must be run with run_task_executions");
+        }
+        Ok(())
+    }
+
+    fn resolve_task_inputs(
+        working_directory: &mut HostFilesystem,
+        template_context: &TemplateContext,
+        task: &Task,
+    ) -> anyhow::Result<TaskInputs<IS>> {
+        let task = template_context.expand_task(task.clone())?;
+        let input_files =
+            FileIdentitiesManifest::<IS>::try_from_inputs(working_directory, &task.inputs)
+                .map_err(Error::inputs_resolution)
+                .context("identifying input files")?;
+
+        let mut builder = TaskInputsBuilder::<IS>::new()
+            .program(&task.program.program)
+            .arguments(task.arguments.arguments)
+            .outputs_description(Outputs::try_from(task.outputs)?);
+        for (name, value) in task.environment_variables.environment_variables {
+            builder = builder.environment_variable(name, value);
+        }
+        if let Some(argv0) = task.arguments.argv0 {
+            builder = builder.argv0(argv0);
+        }
+        for (path, identity) in input_files.identities() {
+            builder = builder.input_file(path, identity.clone());
+        }
+        if let Some(stdin) = task.stdin {
+            builder = builder.stdin(stdin);
+        }
+        if let Some(sandbox) = task.sandbox {
+            builder = builder.sandbox(sandbox);
+        }
+        if let Some(resource_limits) = task.resource_limits {
+            builder = builder.resource_limits(resource_limits);
+        }
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ArtifactExecutor;
+    use crate::blob::JSON;
+    use crate::cache::Cache;
+    use crate::cache::WriteOnDropIndex;
+    use crate::canonical::TaskLabels;
+    use crate::error::Error;
+    use crate::execute::identify_task_inputs;
+    use crate::execute::Materialization;
+    use crate::fs::HostFilesystem;
+    use crate::identity::IdentityScheme as _;
+    use crate::repro::ReproOptions;
+    use crate::transport::ContentSha256;
+    use crate::transport::Task;
+    use std::path::PathBuf;
+
+    const PIPELINE: &str = r#"{
+        "tasks": {
+            "generate": {
+                "environment_variables": [],
+                "program": "/bin/sh",
+                "arguments": ["-c", "mkdir -p gen; cp seed.txt gen/value.txt"],
+                "inputs": {"include_files": ["seed.txt"]},
+                "outputs": {"include_files": ["gen/value.txt"]}
+            },
+            "consume": {
+                "environment_variables": [],
+                "program": "/bin/sh",
+                "arguments": ["-c", "cat value.txt > result.txt"],
+                "inputs": {"include_files": ["value.txt"]},
+                "outputs": {"include_files": ["result.txt"]},
+                "wired_inputs": [
+                    {"from_task": "generate", "output": "gen/value.txt", "input": "value.txt"}
+                ]
+            },
+            "report": {
+                "depends_on": ["consume"],
+                "environment_variables": [],
+                "program": "/bin/sh",
+                "arguments": ["-c", "cp result.txt report.txt"],
+                "inputs": {"include_files": ["result.txt"]},
+                "outputs": {"include_files": ["report.txt"]}
+            }
+        }
+    }"#;
+
+    #[test]
+    fn test_artifact_executor() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let working_directory = temporary_directory.path().to_path_buf();
+        std::fs::write(working_directory.join("input.txt"), "input").expect("write input.txt");
+        let mut executor = ArtifactExecutor::builder()
+            .working_directory(&working_directory)
+            .build()
+            .expect("build executor");
+        assert!(working_directory.join("ae-cache").is_dir());
+
+        let task: Task = serde_json::from_str(
+            r#"{
+                "environment_variables": [],
+                "program": "/bin/sh",
+                "arguments": ["-c", "cat input.txt > output.txt; echo run >> runs.txt"],
+                "inputs": {"include_files": ["input.txt"]},
+                "outputs": {"include_files": ["output.txt"]}
+            }"#,
+        )
+        .expect("parse task");
+        let expected_outputs = vec![(
+            PathBuf::from("output.txt"),
+            Some(ContentSha256::identify_content("input".as_bytes()).expect("identity")),
+        )];
+        for _ in 0..2 {
+            let outputs = executor.run_task(&task).expect("run task");
+            assert_eq!(
+                expected_outputs,
+                outputs.output_files().cloned().collect::<Vec<_>>()
+            );
+        }
+        // The second run loaded outputs from the cache.
+        assert_eq!(
+            "run\n",
+            std::fs::read_to_string(working_directory.join("runs.txt")).expect("read runs.txt")
+        );
+
+        let failing_task: Task = serde_json::from_str(
+            r#"{
+                "environment_variables": [],
+                "program": "/bin/sh",
+                "arguments": ["-c", "exit 3"],
+                "inputs": {
+                    "include_files": ["input.txt"],
+                    "expected_identities": {"input.txt": "unexpected"}
+                },
+                "outputs": {}
+            }"#,
+        )
+        .expect("parse failing task");
+        assert!(matches!(
+            executor.run_task(&failing_task),
+            Err(Error::InputsResolution { .. })
+        ));
+        let mut failing_task = failing_task;
+        failing_task.inputs.expected_identities.clear();
+        match executor.run_task(&failing_task) {
+            Err(Error::ChildFailed { status, .. }) => assert_eq!(Some(3), status.code()),
+            result => panic!("unexpected result: {:?}", result.map(|_| ())),
+        }
+        let mut capturing_executor = ArtifactExecutor::builder()
+            .working_directory(&working_directory)
+            .capture_repro(ReproOptions {
+                directory: PathBuf::from("repro"),
+                include_inputs: false,
+            })
+            .build()
+            .expect("build capturing executor");
+        match capturing_executor.run_task(&failing_task) {
+            Err(Error::ChildFailed { source, .. }) => {
+                assert!(source
+                    .to_string()
+                    .starts_with("reproduction bundle written to"))
+            }
+            result => panic!("unexpected result: {:?}", result.map(|_| ())),
+        }
+        assert_eq!(
+            1,
+            std::fs::read_dir(working_directory.join("repro"))
+                .expect("read reproduction directory")
+                .count()
+        );
+
+        std::fs::write(working_directory.join("input.txt"), "changed").expect("change input.txt");
+        executor.run_task(&task).expect("run changed task");
+        assert_eq!(
+            "changed",
+            std::fs::read_to_string(working_directory.join("output.txt")).expect("read output.txt")
+        );
+    }
+
+    #[test]
+    fn test_cache_hit_restores_outputs() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let working_directory = temporary_directory.path().to_path_buf();
+        std::fs::write(working_directory.join("name.txt"), "world").expect("write name.txt");
+        let task: Task = serde_json::from_str(
+            r#"{
+                "environment_variables": [],
+                "program": "/bin/sh",
+                "arguments": ["-c", "cat name.txt > greeting.txt; echo ran >> runs.log"],
+                "inputs": {"include_files": ["name.txt"]},
+                "outputs": {"include_files": ["greeting.txt"]}
+            }"#,
+        )
+        .expect("parse task");
+
+        for materialization in [Materialization::PreferHardLinks, Materialization::Copy] {
+            let mut executor = ArtifactExecutor::builder()
+                .working_directory(&working_directory)
+                .materialization(materialization)
+                .build()
+                .expect("build executor");
+            executor.run_task(&task).expect("run task");
+            std::fs::remove_file(working_directory.join("greeting.txt"))
+                .expect("remove greeting.txt");
+            executor.run_task(&task).expect("load task outputs");
+            assert_eq!(
+                "world",
+                std::fs::read_to_string(working_directory.join("greeting.txt"))
+                    .expect("read restored greeting.txt")
+            );
+        }
+        assert_eq!(
+            "ran\n",
+            std::fs::read_to_string(working_directory.join("runs.log")).expect("read runs.log")
+        );
+    }
+
+    #[test]
+    fn test_query_executed_tasks() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let working_directory = temporary_directory.path().to_path_buf();
+        std::fs::write(working_directory.join("name.txt"), "world").expect("write name.txt");
+        let mut executor = ArtifactExecutor::builder()
+            .working_directory(&working_directory)
+            .build()
+            .expect("build executor");
+        let task: Task = serde_json::from_str(
+            r#"{
+                "label": "greet",
+                "tags": ["release", "docs"],
+                "environment_variables": [],
+                "program": "/bin/sh",
+                "arguments": ["-c", "cat name.txt > greeting.txt"],
+                "inputs": {"include_files": ["name.txt"]},
+                "outputs": {"include_files": ["greeting.txt"]}
+            }"#,
+        )
+        .expect("parse task");
+        let inputs = executor.task_inputs(&task).expect("task inputs");
+        let inputs_identity = identify_task_inputs(&inputs).expect("identify task inputs");
+        executor.run_task(&task).expect("run task");
+
+        let mut cache = Cache::<
+            HostFilesystem,
+            ContentSha256,
+            JSON,
+            WriteOnDropIndex<HostFilesystem, ContentSha256, JSON>,
+        >::open_existing(
+            HostFilesystem::try_new(working_directory.join("ae-cache")).expect("cache filesystem"),
+        )
+        .expect("open cache");
+        let tasks = cache
+            .find_tasks(&TaskLabels::new(None, ["release"]))
+            .expect("find tasks");
+        assert_eq!(1, tasks.len());
+        let (identity, metadata) = &tasks[0];
+        assert_eq!(inputs_identity, *identity);
+        assert_eq!(Some("greet"), metadata.labels().label());
+        assert_eq!(Some(0), metadata.exit_code());
+        assert!(cache
+            .find_tasks(&TaskLabels::new(
+                Some(String::from("other")),
+                Vec::<String>::new()
+            ))
+            .expect("find tasks")
+            .is_empty());
+    }
+
+    #[test]
+    fn test_run_task_graph_file() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let working_directory = temporary_directory.path().to_path_buf();
+        std::fs::write(working_directory.join("pipeline.json"), PIPELINE)
+            .expect("write pipeline.json");
+        std::fs::write(working_directory.join("seed.txt"), "generated").expect("write seed.txt");
+        let mut executor = ArtifactExecutor::builder()
+            .working_directory(&working_directory)
+            .build()
+            .expect("build executor");
+
+        let outputs = executor
+            .run_task_graph_file("pipeline.json")
+            .expect("run pipeline");
+        assert_eq!(
+            vec!["consume", "generate", "report"],
+            outputs.keys().map(String::as_str).collect::<Vec<_>>()
+        );
+        let expected_identity =
+            ContentSha256::identify_content("generated".as_bytes()).expect("identity");
+        assert_eq!(
+            Some(&(PathBuf::from("report.txt"), Some(expected_identity))),
+            outputs["report"].output_files().next()
+        );
+        assert_eq!(
+            "generated",
+            std::fs::read_to_string(working_directory.join("report.txt")).expect("read report.txt")
+        );
+
+        // Failures name the failing task.
+        std::fs::write(
+            working_directory.join("pipeline.json"),
+            PIPELINE.replace("cp result.txt", "exit 3; cp result.txt"),
+        )
+        .expect("rewrite pipeline.json");
+        let error = executor
+            .run_task_graph_file("pipeline.json")
+            .expect_err("failing pipeline");
+        assert!(matches!(error, Error::ChildFailed { .. }));
+        assert_eq!("running task \"report\"", error.to_string());
+    }
+
+    #[test]
+    fn test_run_task_graph_in_task_working_directories() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let working_directory = temporary_directory.path().to_path_buf();
+        for directory in ["lib", "app"] {
+            std::fs::create_dir(working_directory.join(directory)).expect("create directory");
+        }
+        std::fs::write(working_directory.join("lib/source.txt"), "library")
+            .expect("write lib/source.txt");
+        std::fs::write(
+            working_directory.join("pipeline.json"),
+            r#"{
+                "tasks": {
+                    "library": {
+                        "working_directory": "lib",
+                        "environment_variables": [],
+                        "program": "/bin/sh",
+                        "arguments": ["-c", "cp source.txt library.txt"],
+                        "inputs": {"include_files": ["source.txt"]},
+                        "outputs": {"include_files": ["library.txt"]}
+                    },
+                    "application": {
+                        "working_directory": "app",
+                        "environment_variables": [],
+                        "program": "/bin/sh",
+                        "arguments": ["-c", "cat deps/library.txt > application.txt"],
+                        "inputs": {"include_files": ["deps/library.txt"]},
+                        "outputs": {"include_files": ["application.txt"]},
+                        "wired_inputs": [
+                            {
+                                "from_task": "library",
+                                "output": "library.txt",
+                                "input": "deps/library.txt"
+                            }
+                        ]
+                    }
+                }
+            }"#,
+        )
+        .expect("write pipeline.json");
+        let mut executor = ArtifactExecutor::builder()
+            .working_directory(&working_directory)
+            .build()
+            .expect("build executor");
+
+        let outputs = executor
+            .run_task_graph_file("pipeline.json")
+            .expect("run pipeline");
+        // Output paths are relative to each task's working directory.
+        let expected_identity =
+            ContentSha256::identify_content("library".as_bytes()).expect("identity");
+        assert_eq!(
+            Some(&(PathBuf::from("application.txt"), Some(expected_identity))),
+            outputs["application"].output_files().next()
+        );
+        assert_eq!(
+            "library",
+            std::fs::read_to_string(working_directory.join("app/application.txt"))
+                .expect("read app/application.txt")
+        );
+        assert!(!working_directory.join("library.txt").exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sandboxed_task_requires_bubblewrap() {
+        use crate::runner::PolicyRunner;
+        use crate::runner::SimpleRunner;
+
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let working_directory = temporary_directory.path().to_path_buf();
+        std::fs::write(working_directory.join("input.txt"), "input").expect("write input.txt");
+        let mut executor = ArtifactExecutor::builder()
+            .working_directory(&working_directory)
+            .runner(
+                PolicyRunner::new(SimpleRunner)
+                    .with_bubblewrap_path(working_directory.join("missing/bwrap")),
+            )
+            .build()
+            .expect("build executor");
+        let task = |sandbox: &str| -> Task {
+            serde_json::from_str(&format!(
+                r#"{{
+                    "environment_variables": [],
+                    "program": "/bin/sh",
+                    "arguments": ["-c", "echo run >> runs.txt"],
+                    {}
+                    "inputs": {{"include_files": ["input.txt"]}},
+                    "outputs": {{}}
+                }}"#,
+                sandbox
+            ))
+            .expect("parse task")
+        };
+
+        // A sandboxed task fails, rather than running unconfined, where it cannot be isolated.
+        let error = executor
+            .run_task(&task(r#""sandbox": {},"#))
+            .expect_err("sandboxed task without bubblewrap");
+        assert!(format!("{:#}", anyhow::Error::from(error))
+            .contains("task requests a sandbox, but bubblewrap is not installed"));
+        assert!(!working_directory.join("runs.txt").exists());
+
+        executor.run_task(&task("")).expect("run task");
+        assert!(working_directory.join("runs.txt").exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_resource_limits_are_enforced() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let working_directory = temporary_directory.path().to_path_buf();
+        std::fs::write(working_directory.join("input.txt"), "input").expect("write input.txt");
+        let mut executor = ArtifactExecutor::builder()
+            .working_directory(&working_directory)
+            .build()
+            .expect("build executor");
+        let task: Task = serde_json::from_str(
+            r#"{
+                "environment_variables": [],
+                "program": "/bin/sh",
+                "arguments": ["-c", "ulimit -n > limit.txt"],
+                "resource_limits": {"max_open_files": 17},
+                "inputs": {"include_files": ["input.txt"]},
+                "outputs": {"include_files": ["limit.txt"]}
+            }"#,
+        )
+        .expect("parse task");
+
+        executor.run_task(&task).expect("run limited task");
+        assert_eq!(
+            "17",
+            std::fs::read_to_string(working_directory.join("limit.txt"))
+                .expect("read limit.txt")
+                .trim()
+        );
+    }
+}
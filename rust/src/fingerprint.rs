@@ -0,0 +1,180 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Remembers file identities by size and modification time, so that long-lived processes (see
+//! `crate::daemon`) re-hash only files that changed since they were last identified.
+
+use crate::fs::Filesystem as FilesystemApi;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use anyhow::Context as _;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// Files modified more recently than this before being identified are not remembered: a write
+/// within the same modification time granule could change their content without changing their
+/// fingerprint.
+pub const RACY_MODIFICATION_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug)]
+struct Fingerprint<IS: IdentitySchemeApi> {
+    size: u64,
+    modified: SystemTime,
+    identity: IS::Identity,
+}
+
+/// File identities keyed by path, valid while a file's size and modification time are unchanged.
+/// A database shared between filesystems should be given absolute paths.
+#[derive(Clone, Debug)]
+pub struct FingerprintDatabase<IS: IdentitySchemeApi> {
+    fingerprints: HashMap<PathBuf, Fingerprint<IS>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl<IS: IdentitySchemeApi> Default for FingerprintDatabase<IS> {
+    fn default() -> Self {
+        Self {
+            fingerprints: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+}
+
+impl<IS: IdentitySchemeApi> FingerprintDatabase<IS> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Identifies the file at `path` in `filesystem`, hashing it only if its fingerprint changed
+    /// since it was last identified.
+    pub fn identify_file<FS: FilesystemApi, P: AsRef<Path>>(
+        &mut self,
+        filesystem: &mut FS,
+        path: P,
+    ) -> anyhow::Result<IS::Identity> {
+        let path = path.as_ref();
+        let metadata = filesystem
+            .metadata(path)
+            .map_err(anyhow::Error::from)
+            .with_context(|| format!("reading metadata of {:?}", path))?;
+        if let (Some(fingerprint), Some(modified)) =
+            (self.fingerprints.get(path), metadata.modified)
+        {
+            if fingerprint.size == metadata.size && fingerprint.modified == modified {
+                self.hits += 1;
+                return Ok(fingerprint.identity.clone());
+            }
+        }
+
+        self.misses += 1;
+        let identity = IS::identify_file(filesystem, path)?;
+        match metadata.modified {
+            Some(modified)
+                if SystemTime::now()
+                    .duration_since(modified)
+                    .is_ok_and(|age| age >= RACY_MODIFICATION_WINDOW) =>
+            {
+                self.fingerprints.insert(
+                    path.to_path_buf(),
+                    Fingerprint {
+                        size: metadata.size,
+                        modified,
+                        identity: identity.clone(),
+                    },
+                );
+            }
+            _ => {
+                self.fingerprints.remove(path);
+            }
+        }
+        Ok(identity)
+    }
+
+    /// Forgets the identity of the file at `path`.
+    pub fn invalidate<P: AsRef<Path>>(&mut self, path: P) {
+        self.fingerprints.remove(path.as_ref());
+    }
+
+    /// Number of remembered files.
+    pub fn len(&self) -> usize {
+        self.fingerprints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fingerprints.is_empty()
+    }
+
+    /// Numbers of identifications answered from, and not from, remembered fingerprints.
+    pub fn hits_and_misses(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FingerprintDatabase;
+    use crate::fs::HostFilesystem;
+    use crate::identity::IdentityScheme as _;
+    use crate::transport::ContentSha256;
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_fingerprint_database() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let directory = temporary_directory.path().to_path_buf();
+        let set_modified = |name: &str, age: Duration| {
+            std::fs::File::options()
+                .write(true)
+                .open(directory.join(name))
+                .expect("open file")
+                .set_modified(SystemTime::now() - age)
+                .expect("set modification time");
+        };
+        std::fs::write(directory.join("old.txt"), "old").expect("write old.txt");
+        set_modified("old.txt", Duration::from_secs(60));
+        std::fs::write(directory.join("new.txt"), "new").expect("write new.txt");
+        let mut filesystem = HostFilesystem::try_new(directory.clone()).expect("host filesystem");
+        let mut database = FingerprintDatabase::<ContentSha256>::new();
+
+        let identify = |content: &str| {
+            ContentSha256::identify_content(content.as_bytes()).expect("identify content")
+        };
+        for _ in 0..2 {
+            assert_eq!(
+                identify("old"),
+                database
+                    .identify_file(&mut filesystem, "old.txt")
+                    .expect("identify old.txt")
+            );
+            assert_eq!(
+                identify("new"),
+                database
+                    .identify_file(&mut filesystem, "new.txt")
+                    .expect("identify new.txt")
+            );
+        }
+        // Recently modified files are re-hashed each time.
+        assert_eq!(1, database.len());
+        assert_eq!((1, 3), database.hits_and_misses());
+
+        std::fs::write(directory.join("old.txt"), "changed").expect("change old.txt");
+        set_modified("old.txt", Duration::from_secs(30));
+        assert_eq!(
+            identify("changed"),
+            database
+                .identify_file(&mut filesystem, "old.txt")
+                .expect("identify changed old.txt")
+        );
+        database.invalidate("old.txt");
+        assert!(database.is_empty());
+        assert!(database
+            .identify_file(&mut filesystem, "missing.txt")
+            .is_err());
+    }
+}
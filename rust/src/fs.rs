@@ -2,15 +2,26 @@
 // Use of this source code is governed by a Apache-style license that can be
 // found in the LICENSE file.
 
-use crate::error::Error as ErrorBound;
+use crate::error::ErrorBound;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::convert::Infallible;
 use std::fs::File;
+use std::io::Cursor;
 use std::io::Read;
 use std::io::Write;
+use std::os::unix::fs::FileTypeExt as _;
 use std::os::unix::fs::PermissionsExt as _;
 use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::SystemTime;
 
 pub trait Filesystem: Clone + Sized {
     type Read: Read;
@@ -25,8 +36,33 @@ pub trait Filesystem: Clone + Sized {
 
     fn file_exists<P: AsRef<Path>>(&mut self, path: P) -> bool;
 
+    /// Returns true when `path` is a symbolic link, regardless of whether its target exists.
+    fn is_symlink<P: AsRef<Path>>(&mut self, path: P) -> bool;
+
+    /// Reads the target stored in the symbolic link at `path` without resolving it.
+    fn read_link<P: AsRef<Path>>(&mut self, path: P) -> Result<PathBuf, Self::IoError>;
+
+    /// Gets metadata for the entry at `path`, following symbolic links.
+    fn metadata<P: AsRef<Path>>(&mut self, path: P) -> Result<FileMetadata, Self::IoError>;
+
+    /// Lists the entries of the directory at `path`, sorted by name. Entry types are reported
+    /// without following symbolic links.
+    fn read_directory<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<Vec<(PathBuf, FileType)>, Self::IoError>;
+
     fn open_file_for_read<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::Read, Self::IoError>;
 
+    /// Opens the file at `path` to be read once, front to back, as when computing its identity.
+    /// Filesystems may hint the operating system to read ahead.
+    fn open_file_for_sequential_read<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<Self::Read, Self::IoError> {
+        self.open_file_for_read(path)
+    }
+
     fn open_file_for_write<P: AsRef<Path>>(
         &mut self,
         path: P,
@@ -44,6 +80,22 @@ pub trait Filesystem: Clone + Sized {
 
     fn mark_as_executable<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Self::IoError>;
 
+    /// Creates a symbolic link at `path` that stores `target` as given. The target need not exist.
+    fn create_symlink<Target: AsRef<Path>, P: AsRef<Path>>(
+        &mut self,
+        target: Target,
+        path: P,
+    ) -> Result<(), Self::IoError>;
+
+    /// Creates a hard link at `link` to the file at `original`, which may be an absolute path
+    /// outside this filesystem. Fails when the filesystem does not support hard links, or cannot
+    /// link to `original` (e.g., because it is on another device).
+    fn hard_link<Original: AsRef<Path>, Link: AsRef<Path>>(
+        &mut self,
+        original: Original,
+        link: Link,
+    ) -> Result<(), Self::IoError>;
+
     fn execute_glob<'a>(
         &'a mut self,
         glob_pattern_str: &str,
@@ -56,6 +108,49 @@ pub trait Filesystem: Clone + Sized {
     ) -> Result<bool, Self::PatternError>;
 }
 
+/// Kinds of filesystem entries.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+    Fifo,
+    Socket,
+    BlockDevice,
+    CharDevice,
+}
+
+impl From<std::fs::FileType> for FileType {
+    fn from(file_type: std::fs::FileType) -> Self {
+        if file_type.is_dir() {
+            Self::Directory
+        } else if file_type.is_symlink() {
+            Self::Symlink
+        } else if file_type.is_fifo() {
+            Self::Fifo
+        } else if file_type.is_socket() {
+            Self::Socket
+        } else if file_type.is_block_device() {
+            Self::BlockDevice
+        } else if file_type.is_char_device() {
+            Self::CharDevice
+        } else {
+            Self::File
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FileMetadata {
+    pub file_type: FileType,
+    pub size: u64,
+    /// Last modification time, where the filesystem records one.
+    pub modified: Option<SystemTime>,
+    /// Whether the file may be executed (e.g., by having any execute permission bit set).
+    pub executable: bool,
+}
+
 #[derive(Clone, Debug)]
 pub struct HostFilesystem {
     working_directory: PathBuf,
@@ -129,11 +224,65 @@ impl Filesystem for HostFilesystem {
         }
     }
 
+    fn is_symlink<P: AsRef<Path>>(&mut self, path: P) -> bool {
+        let path = self.get_absolute_path(path);
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) => metadata.file_type().is_symlink(),
+            Err(_) => false,
+        }
+    }
+
+    fn read_link<P: AsRef<Path>>(&mut self, path: P) -> Result<PathBuf, Self::IoError> {
+        let path = self.get_absolute_path(path);
+        std::fs::read_link(path)
+    }
+
+    fn metadata<P: AsRef<Path>>(&mut self, path: P) -> Result<FileMetadata, Self::IoError> {
+        let metadata = std::fs::metadata(self.get_absolute_path(path))?;
+        Ok(FileMetadata {
+            file_type: metadata.file_type().into(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            executable: metadata.permissions().mode() & 0o111 != 0,
+        })
+    }
+
+    fn read_directory<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<Vec<(PathBuf, FileType)>, Self::IoError> {
+        let mut entries = std::fs::read_dir(self.get_absolute_path(path))?
+            .map(|entry| {
+                let entry = entry?;
+                Ok((PathBuf::from(entry.file_name()), entry.file_type()?.into()))
+            })
+            .collect::<Result<Vec<_>, Self::IoError>>()?;
+        entries.sort();
+        Ok(entries)
+    }
+
     fn open_file_for_read<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::Read, Self::IoError> {
         let path = self.get_absolute_path(path);
         File::open(path)
     }
 
+    fn open_file_for_sequential_read<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<Self::Read, Self::IoError> {
+        let file = self.open_file_for_read(path)?;
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            use std::os::unix::io::AsRawFd as _;
+
+            // The hint only affects performance, so failing to apply it is not an error.
+            // Safety: The descriptor is owned by `file`, which outlives the call.
+            let _ =
+                unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_SEQUENTIAL) };
+        }
+        Ok(file)
+    }
+
     fn open_file_for_write<P: AsRef<Path>>(
         &mut self,
         path: P,
@@ -169,6 +318,25 @@ impl Filesystem for HostFilesystem {
         std::fs::set_permissions(path, permissions)
     }
 
+    fn create_symlink<Target: AsRef<Path>, P: AsRef<Path>>(
+        &mut self,
+        target: Target,
+        path: P,
+    ) -> Result<(), Self::IoError> {
+        let path = self.get_absolute_path(path);
+        std::os::unix::fs::symlink(target, path)
+    }
+
+    fn hard_link<Original: AsRef<Path>, Link: AsRef<Path>>(
+        &mut self,
+        original: Original,
+        link: Link,
+    ) -> Result<(), Self::IoError> {
+        let original = self.get_absolute_path(original);
+        let link = self.get_absolute_path(link);
+        std::fs::hard_link(original, link)
+    }
+
     fn execute_glob<'a>(
         &'a mut self,
         glob_pattern_str: &str,
@@ -229,6 +397,723 @@ impl Filesystem for HostFilesystem {
     }
 }
 
+/// Entry of a `MemoryFilesystem`.
+#[derive(Clone, Debug)]
+enum MemoryEntry {
+    File {
+        contents: Vec<u8>,
+        executable: bool,
+        modified: SystemTime,
+    },
+    Directory,
+    Symlink {
+        target: PathBuf,
+    },
+}
+
+impl MemoryEntry {
+    fn file_type(&self) -> FileType {
+        match self {
+            Self::File { .. } => FileType::File,
+            Self::Directory => FileType::Directory,
+            Self::Symlink { .. } => FileType::Symlink,
+        }
+    }
+}
+
+type MemoryEntries = Arc<Mutex<BTreeMap<PathBuf, MemoryEntry>>>;
+
+/// Number of symbolic links that `MemoryFilesystem` follows before giving up on resolving a path.
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+/// Filesystem whose files are byte buffers held in memory, for tests and for hosts without a
+/// filesystem (e.g., WebAssembly). Clones and sub-systems share the same entries. Paths are
+/// resolved against an absolute working directory within the filesystem, initially its root.
+/// Symbolic links are followed only when they are the last component of a path.
+///
+/// Child processes cannot write to memory: a `MemoryFile` passed as a child's `Stdio` discards
+/// whatever the child writes.
+#[derive(Clone, Debug)]
+pub struct MemoryFilesystem {
+    entries: MemoryEntries,
+    working_directory: PathBuf,
+}
+
+impl Default for MemoryFilesystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryFilesystem {
+    /// Creates an empty filesystem, which contains only its root directory.
+    pub fn new() -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(BTreeMap::new())),
+            working_directory: PathBuf::from(std::path::MAIN_SEPARATOR_STR),
+        }
+    }
+
+    /// Writes `contents` to the file at `path`, creating its parent directories as needed.
+    pub fn write_file<P: AsRef<Path>, C: AsRef<[u8]>>(
+        &mut self,
+        path: P,
+        contents: C,
+    ) -> Result<(), std::io::Error> {
+        let path = self.resolve(path);
+        if let Some(parent) = path.parent() {
+            self.create_directories(parent)?;
+        }
+        self.open_file_for_write(path)?.write_all(contents.as_ref())
+    }
+
+    /// Absolute, normalized form of `path`. `..` never ascends above the root.
+    fn resolve<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        Self::normalize(self.working_directory.join(path))
+    }
+
+    fn normalize<P: AsRef<Path>>(path: P) -> PathBuf {
+        let mut resolved = PathBuf::from(std::path::MAIN_SEPARATOR_STR);
+        for component in path.as_ref().components() {
+            match component {
+                Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                Component::Normal(name) => resolved.push(name),
+            }
+        }
+        resolved
+    }
+
+    /// Follows symbolic links at `path`, an absolute, normalized path, to the path of the entry
+    /// that they resolve to, which may not exist.
+    fn follow(entries: &BTreeMap<PathBuf, MemoryEntry>, mut path: PathBuf) -> PathBuf {
+        for _ in 0..MAX_SYMLINK_DEPTH {
+            match entries.get(&path) {
+                Some(MemoryEntry::Symlink { target }) => {
+                    let parent = path.parent().unwrap_or(&path).to_path_buf();
+                    path = Self::normalize(parent.join(target));
+                }
+                _ => break,
+            }
+        }
+        path
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<PathBuf, MemoryEntry>> {
+        self.entries.lock().expect("memory filesystem lock")
+    }
+
+    fn is_directory(entries: &BTreeMap<PathBuf, MemoryEntry>, path: &Path) -> bool {
+        path.parent().is_none() || matches!(entries.get(path), Some(MemoryEntry::Directory))
+    }
+
+    /// Paths of all entries, in order, excluding the root directory.
+    fn paths(&self) -> Vec<PathBuf> {
+        self.lock().keys().cloned().collect()
+    }
+
+    fn pattern(&self, glob_pattern_str: &str) -> Result<glob::Pattern, glob::PatternError> {
+        let pattern = self.working_directory.join(glob_pattern_str);
+        glob::Pattern::new(&pattern.to_string_lossy())
+    }
+}
+
+fn not_found(path: &Path) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("no entry at {:?} in memory filesystem", path),
+    )
+}
+
+impl Filesystem for MemoryFilesystem {
+    type Read = Cursor<Vec<u8>>;
+    type Write = MemoryFile;
+    type IoError = std::io::Error;
+    type PatternError = glob::PatternError;
+    type GlobError = Infallible;
+
+    /// Memory filesystems have no directory on the host.
+    fn working_directory(&mut self) -> Option<PathBuf> {
+        None
+    }
+
+    fn sub_system<P: AsRef<Path>>(&mut self, sub_directory: P) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            entries: self.entries.clone(),
+            working_directory: self.resolve(sub_directory),
+        })
+    }
+
+    fn file_exists<P: AsRef<Path>>(&mut self, path: P) -> bool {
+        let path = self.resolve(path);
+        let entries = self.lock();
+        matches!(
+            entries.get(&Self::follow(&entries, path)),
+            Some(MemoryEntry::File { .. })
+        )
+    }
+
+    fn is_symlink<P: AsRef<Path>>(&mut self, path: P) -> bool {
+        let path = self.resolve(path);
+        matches!(self.lock().get(&path), Some(MemoryEntry::Symlink { .. }))
+    }
+
+    fn read_link<P: AsRef<Path>>(&mut self, path: P) -> Result<PathBuf, Self::IoError> {
+        let path = self.resolve(path);
+        match self.lock().get(&path) {
+            Some(MemoryEntry::Symlink { target }) => Ok(target.clone()),
+            Some(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("memory filesystem entry {:?} is not a symbolic link", path),
+            )),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn metadata<P: AsRef<Path>>(&mut self, path: P) -> Result<FileMetadata, Self::IoError> {
+        let path = self.resolve(path);
+        let entries = self.lock();
+        let path = Self::follow(&entries, path);
+        match entries.get(&path) {
+            Some(MemoryEntry::File {
+                contents,
+                executable,
+                modified,
+            }) => Ok(FileMetadata {
+                file_type: FileType::File,
+                size: contents.len() as u64,
+                modified: Some(*modified),
+                executable: *executable,
+            }),
+            Some(MemoryEntry::Directory) => Ok(FileMetadata {
+                file_type: FileType::Directory,
+                size: 0,
+                modified: None,
+                executable: false,
+            }),
+            None if path.parent().is_none() => Ok(FileMetadata {
+                file_type: FileType::Directory,
+                size: 0,
+                modified: None,
+                executable: false,
+            }),
+            _ => Err(not_found(&path)),
+        }
+    }
+
+    fn read_directory<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<Vec<(PathBuf, FileType)>, Self::IoError> {
+        let path = self.resolve(path);
+        let entries = self.lock();
+        if !Self::is_directory(&entries, &path) {
+            return Err(not_found(&path));
+        }
+        Ok(entries
+            .iter()
+            .filter(|(entry_path, _)| entry_path.parent() == Some(path.as_path()))
+            .filter_map(|(entry_path, entry)| {
+                entry_path
+                    .file_name()
+                    .map(|name| (PathBuf::from(name), entry.file_type()))
+            })
+            .collect())
+    }
+
+    fn open_file_for_read<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::Read, Self::IoError> {
+        let path = self.resolve(path);
+        let entries = self.lock();
+        match entries.get(&Self::follow(&entries, path.clone())) {
+            Some(MemoryEntry::File { contents, .. }) => Ok(Cursor::new(contents.clone())),
+            _ => Err(not_found(&path)),
+        }
+    }
+
+    fn open_file_for_write<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<Self::Write, Self::IoError> {
+        let path = self.resolve(path);
+        let mut entries = self.lock();
+        let path = Self::follow(&entries, path);
+        match path.parent() {
+            Some(parent) if Self::is_directory(&entries, parent) => {}
+            _ => return Err(not_found(&path)),
+        }
+        if let Some(MemoryEntry::Directory | MemoryEntry::Symlink { .. }) = entries.get(&path) {
+            return Err(std::io::Error::other(format!(
+                "memory filesystem entry {:?} is a directory",
+                path
+            )));
+        }
+        let executable = matches!(
+            entries.get(&path),
+            Some(MemoryEntry::File {
+                executable: true,
+                ..
+            })
+        );
+        entries.insert(
+            path.clone(),
+            MemoryEntry::File {
+                contents: vec![],
+                executable,
+                modified: SystemTime::now(),
+            },
+        );
+        Ok(MemoryFile {
+            entries: self.entries.clone(),
+            path,
+        })
+    }
+
+    fn remove_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Self::IoError> {
+        let path = self.resolve(path);
+        let mut entries = self.lock();
+        match entries.get(&path) {
+            Some(MemoryEntry::File { .. } | MemoryEntry::Symlink { .. }) => {
+                entries.remove(&path);
+                Ok(())
+            }
+            _ => Err(not_found(&path)),
+        }
+    }
+
+    fn move_from_to<FromPath: AsRef<Path>, ToPath: AsRef<Path>>(
+        &mut self,
+        from_path: FromPath,
+        to_path: ToPath,
+    ) -> Result<(), Self::IoError> {
+        let from_path = self.resolve(from_path);
+        let to_path = self.resolve(to_path);
+        let mut entries = self.lock();
+        if !entries.contains_key(&from_path) {
+            return Err(not_found(&from_path));
+        }
+        match to_path.parent() {
+            Some(parent) if Self::is_directory(&entries, parent) => {}
+            _ => return Err(not_found(&to_path)),
+        }
+        // Moving a directory moves everything under it.
+        let moved_paths: Vec<PathBuf> = entries
+            .keys()
+            .filter(|path| path.starts_with(&from_path))
+            .cloned()
+            .collect();
+        for path in moved_paths {
+            let entry = entries.remove(&path).expect("moved entry");
+            let moved_path = match path.strip_prefix(&from_path).expect("moved entry prefix") {
+                suffix if suffix.as_os_str().is_empty() => to_path.clone(),
+                suffix => to_path.join(suffix),
+            };
+            entries.insert(moved_path, entry);
+        }
+        Ok(())
+    }
+
+    fn create_directories<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Self::IoError> {
+        let path = self.resolve(path);
+        let mut entries = self.lock();
+        for directory in path
+            .ancestors()
+            .filter(|directory| directory.parent().is_some())
+        {
+            match entries.get(directory) {
+                Some(MemoryEntry::File { .. } | MemoryEntry::Symlink { .. }) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        format!("memory filesystem entry {:?} is not a directory", directory),
+                    ))
+                }
+                Some(MemoryEntry::Directory) => {}
+                None => {
+                    entries.insert(directory.to_path_buf(), MemoryEntry::Directory);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn mark_as_executable<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Self::IoError> {
+        let path = self.resolve(path);
+        let mut entries = self.lock();
+        let path = Self::follow(&entries, path);
+        match entries.get_mut(&path) {
+            Some(MemoryEntry::File { executable, .. }) => {
+                *executable = true;
+                Ok(())
+            }
+            _ => Err(not_found(&path)),
+        }
+    }
+
+    fn create_symlink<Target: AsRef<Path>, P: AsRef<Path>>(
+        &mut self,
+        target: Target,
+        path: P,
+    ) -> Result<(), Self::IoError> {
+        let path = self.resolve(path);
+        let mut entries = self.lock();
+        match path.parent() {
+            Some(parent) if Self::is_directory(&entries, parent) => {}
+            _ => return Err(not_found(&path)),
+        }
+        if entries.contains_key(&path) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("memory filesystem entry {:?} already exists", path),
+            ));
+        }
+        entries.insert(
+            path,
+            MemoryEntry::Symlink {
+                target: target.as_ref().to_path_buf(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Memory filesystems have no hard links: Entries never share content.
+    fn hard_link<Original: AsRef<Path>, Link: AsRef<Path>>(
+        &mut self,
+        _original: Original,
+        link: Link,
+    ) -> Result<(), Self::IoError> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!(
+                "memory filesystem cannot hard-link {:?}",
+                self.resolve(link)
+            ),
+        ))
+    }
+
+    fn execute_glob<'a>(
+        &'a mut self,
+        glob_pattern_str: &str,
+    ) -> Result<Box<dyn Iterator<Item = Result<PathBuf, Self::GlobError>> + 'a>, Self::PatternError>
+    {
+        let pattern = self.pattern(glob_pattern_str)?;
+        // Match as `glob::glob` does when walking directories: wildcards other than `**` do not
+        // cross directory boundaries.
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..glob::MatchOptions::new()
+        };
+        let working_directory = self.working_directory.clone();
+        Ok(Box::new(
+            self.paths()
+                .into_iter()
+                .filter(move |path| pattern.matches_path_with(path, options))
+                .map(move |path| Ok(relativize_path(&working_directory, path))),
+        ))
+    }
+
+    fn glob_matches<P: AsRef<Path>>(
+        &mut self,
+        glob_pattern_str: &str,
+        path: P,
+    ) -> Result<bool, Self::PatternError> {
+        let pattern = self.pattern(glob_pattern_str)?;
+        Ok(pattern.matches_path(&self.resolve(path)))
+    }
+}
+
+/// File of a `MemoryFilesystem`, opened for writing. Writes are visible to readers that open the
+/// file afterwards.
+#[derive(Debug)]
+pub struct MemoryFile {
+    entries: MemoryEntries,
+    path: PathBuf,
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self
+            .entries
+            .lock()
+            .expect("memory filesystem lock")
+            .get_mut(&self.path)
+        {
+            Some(MemoryEntry::File {
+                contents, modified, ..
+            }) => {
+                contents.extend_from_slice(buf);
+                *modified = SystemTime::now();
+                Ok(buf.len())
+            }
+            _ => Err(not_found(&self.path)),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl From<MemoryFile> for Stdio {
+    fn from(_file: MemoryFile) -> Self {
+        Stdio::null()
+    }
+}
+
+/// Filesystem that layers a writable filesystem over read-only base filesystems. Reads resolve
+/// through the layers from the top down; writes always go to the top layer, creating parent
+/// directories there as needed, so the base layers are never modified.
+///
+/// Entries that exist only in a base layer cannot be removed, moved, or marked as executable.
+/// `working_directory` is the top layer's, so child processes only observe files in base layers
+/// when they are staged through this filesystem.
+#[derive(Clone, Debug)]
+pub struct OverlayFilesystem<FS: Filesystem<IoError = std::io::Error> = HostFilesystem> {
+    upper: FS,
+    lowers: Vec<FS>,
+}
+
+impl<FS: Filesystem<IoError = std::io::Error>> OverlayFilesystem<FS> {
+    /// Creates a filesystem that writes to `upper` and reads through `upper`, then each of
+    /// `lowers` in order.
+    pub fn new(upper: FS, lowers: Vec<FS>) -> Self {
+        Self { upper, lowers }
+    }
+
+    fn layers(&mut self) -> impl Iterator<Item = &mut FS> {
+        std::iter::once(&mut self.upper).chain(self.lowers.iter_mut())
+    }
+
+    fn entry_exists<P: AsRef<Path>>(layer: &mut FS, path: P) -> bool {
+        layer.is_symlink(path.as_ref()) || layer.metadata(path.as_ref()).is_ok()
+    }
+
+    /// Topmost layer that contains an entry at `path`.
+    fn layer_for<P: AsRef<Path>>(&mut self, path: P) -> Option<&mut FS> {
+        self.layers()
+            .find_map(|layer| Self::entry_exists(layer, path.as_ref()).then_some(layer))
+    }
+
+    /// Fails when the entry at `path` exists, but only in a base layer.
+    fn check_writable<P: AsRef<Path>>(&mut self, path: P) -> Result<(), std::io::Error> {
+        let path = path.as_ref();
+        if Self::entry_exists(&mut self.upper, path) {
+            return Ok(());
+        }
+        if self
+            .lowers
+            .iter_mut()
+            .any(|layer| Self::entry_exists(layer, path))
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!(
+                    "{:?} is in a read-only layer of an overlay filesystem",
+                    path
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Creates the parent directory of `path` in the top layer when it exists in a base layer.
+    fn copy_up_parent<P: AsRef<Path>>(&mut self, path: P) -> Result<(), std::io::Error> {
+        let parent = match path.as_ref().parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => return Ok(()),
+        };
+        if Self::entry_exists(&mut self.upper, parent) {
+            return Ok(());
+        }
+        if self
+            .lowers
+            .iter_mut()
+            .any(|layer| Self::entry_exists(layer, parent))
+        {
+            self.upper.create_directories(parent)?;
+        }
+        Ok(())
+    }
+}
+
+impl<FS: Filesystem<IoError = std::io::Error>> Filesystem for OverlayFilesystem<FS> {
+    type Read = FS::Read;
+    type Write = FS::Write;
+    type IoError = std::io::Error;
+    type PatternError = FS::PatternError;
+    type GlobError = FS::GlobError;
+
+    fn working_directory(&mut self) -> Option<PathBuf> {
+        self.upper.working_directory()
+    }
+
+    fn sub_system<P: AsRef<Path>>(&mut self, sub_directory: P) -> Result<Self, anyhow::Error> {
+        let sub_directory = sub_directory.as_ref();
+        Ok(Self {
+            upper: self.upper.sub_system(sub_directory)?,
+            lowers: self
+                .lowers
+                .iter_mut()
+                .map(|layer| layer.sub_system(sub_directory))
+                .collect::<Result<Vec<_>, _>>()?,
+        })
+    }
+
+    fn file_exists<P: AsRef<Path>>(&mut self, path: P) -> bool {
+        self.layer_for(path.as_ref())
+            .map(|layer| layer.file_exists(path.as_ref()))
+            .unwrap_or(false)
+    }
+
+    fn is_symlink<P: AsRef<Path>>(&mut self, path: P) -> bool {
+        self.layer_for(path.as_ref())
+            .map(|layer| layer.is_symlink(path.as_ref()))
+            .unwrap_or(false)
+    }
+
+    fn read_link<P: AsRef<Path>>(&mut self, path: P) -> Result<PathBuf, Self::IoError> {
+        match self.layer_for(path.as_ref()) {
+            Some(layer) => layer.read_link(path),
+            None => self.upper.read_link(path),
+        }
+    }
+
+    fn metadata<P: AsRef<Path>>(&mut self, path: P) -> Result<FileMetadata, Self::IoError> {
+        match self.layer_for(path.as_ref()) {
+            Some(layer) => layer.metadata(path),
+            None => self.upper.metadata(path),
+        }
+    }
+
+    fn read_directory<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<Vec<(PathBuf, FileType)>, Self::IoError> {
+        let path = path.as_ref();
+        let mut entries = BTreeMap::new();
+        let mut error = None;
+        let mut found = false;
+        for layer in self.layers() {
+            match layer.read_directory(path) {
+                Ok(layer_entries) => {
+                    found = true;
+                    for (name, file_type) in layer_entries {
+                        // Upper layers shadow lower layers' entries of the same name.
+                        entries.entry(name).or_insert(file_type);
+                    }
+                }
+                Err(err) => {
+                    error.get_or_insert(err);
+                }
+            }
+        }
+        match (found, error) {
+            (false, Some(error)) => Err(error),
+            _ => Ok(entries.into_iter().collect()),
+        }
+    }
+
+    fn open_file_for_read<P: AsRef<Path>>(&mut self, path: P) -> Result<Self::Read, Self::IoError> {
+        match self.layer_for(path.as_ref()) {
+            Some(layer) => layer.open_file_for_read(path),
+            None => self.upper.open_file_for_read(path),
+        }
+    }
+
+    fn open_file_for_sequential_read<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<Self::Read, Self::IoError> {
+        match self.layer_for(path.as_ref()) {
+            Some(layer) => layer.open_file_for_sequential_read(path),
+            None => self.upper.open_file_for_sequential_read(path),
+        }
+    }
+
+    fn open_file_for_write<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<Self::Write, Self::IoError> {
+        self.copy_up_parent(path.as_ref())?;
+        self.upper.open_file_for_write(path)
+    }
+
+    fn remove_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Self::IoError> {
+        self.check_writable(path.as_ref())?;
+        self.upper.remove_file(path)
+    }
+
+    fn move_from_to<FromPath: AsRef<Path>, ToPath: AsRef<Path>>(
+        &mut self,
+        from_path: FromPath,
+        to_path: ToPath,
+    ) -> Result<(), Self::IoError> {
+        self.check_writable(from_path.as_ref())?;
+        self.copy_up_parent(to_path.as_ref())?;
+        self.upper.move_from_to(from_path, to_path)
+    }
+
+    fn create_directories<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Self::IoError> {
+        self.upper.create_directories(path)
+    }
+
+    fn mark_as_executable<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Self::IoError> {
+        self.check_writable(path.as_ref())?;
+        self.upper.mark_as_executable(path)
+    }
+
+    fn create_symlink<Target: AsRef<Path>, P: AsRef<Path>>(
+        &mut self,
+        target: Target,
+        path: P,
+    ) -> Result<(), Self::IoError> {
+        self.check_writable(path.as_ref())?;
+        self.copy_up_parent(path.as_ref())?;
+        self.upper.create_symlink(target, path)
+    }
+
+    /// Links in the upper layer, so `original` must be in the upper layer, or an absolute path.
+    fn hard_link<Original: AsRef<Path>, Link: AsRef<Path>>(
+        &mut self,
+        original: Original,
+        link: Link,
+    ) -> Result<(), Self::IoError> {
+        self.check_writable(link.as_ref())?;
+        self.copy_up_parent(link.as_ref())?;
+        self.upper.hard_link(original, link)
+    }
+
+    fn execute_glob<'a>(
+        &'a mut self,
+        glob_pattern_str: &str,
+    ) -> Result<Box<dyn Iterator<Item = Result<PathBuf, Self::GlobError>> + 'a>, Self::PatternError>
+    {
+        let mut matched = BTreeSet::new();
+        let mut results = vec![];
+        for layer in self.layers() {
+            for result in layer.execute_glob(glob_pattern_str)? {
+                match result {
+                    Ok(path) => {
+                        if matched.insert(path.clone()) {
+                            results.push(Ok(path));
+                        }
+                    }
+                    Err(err) => results.push(Err(err)),
+                }
+            }
+        }
+        Ok(Box::new(results.into_iter()))
+    }
+
+    fn glob_matches<P: AsRef<Path>>(
+        &mut self,
+        glob_pattern_str: &str,
+        path: P,
+    ) -> Result<bool, Self::PatternError> {
+        self.upper.glob_matches(glob_pattern_str, path)
+    }
+}
+
 fn relativize_path<BasePath: AsRef<Path>, MainPath: AsRef<Path>>(
     base_path: BasePath,
     main_path: MainPath,
@@ -337,10 +1222,15 @@ pub fn copy_file_to<
 #[cfg(test)]
 mod tests {
     use super::relativize_path;
+    use super::FileType;
     use super::Filesystem as _;
     use super::HostFilesystem;
+    use super::MemoryFilesystem;
+    use super::OverlayFilesystem;
     use std::fs::File;
+    use std::io::Read as _;
     use std::io::Write as _;
+    use std::os::unix::fs::MetadataExt as _;
     use std::path::PathBuf;
 
     fn invalid_path_buf() -> PathBuf {
@@ -404,6 +1294,39 @@ mod tests {
         host_filesystem
             .open_file_for_read("newly_created_file.txt")
             .expect("host filesystem reopen for read");
+        host_filesystem
+            .create_symlink("newly_created_file.txt", "link")
+            .expect("host filesystem create symlink");
+        assert!(host_filesystem.is_symlink("link"));
+        assert_eq!(
+            PathBuf::from("newly_created_file.txt"),
+            host_filesystem
+                .read_link("link")
+                .expect("host filesystem read link")
+        );
+        host_filesystem
+            .remove_file("link")
+            .expect("host filesystem remove symlink");
+        host_filesystem
+            .hard_link(
+                temporary_directory.path().join("newly_created_file.txt"),
+                "hard_link",
+            )
+            .expect("host filesystem create hard link");
+        assert_eq!(
+            std::fs::metadata(temporary_directory.path().join("newly_created_file.txt"))
+                .expect("original metadata")
+                .ino(),
+            std::fs::metadata(temporary_directory.path().join("hard_link"))
+                .expect("hard link metadata")
+                .ino()
+        );
+        assert!(host_filesystem
+            .hard_link("newly_created_file.txt", "hard_link")
+            .is_err());
+        host_filesystem
+            .remove_file("hard_link")
+            .expect("host filesystem remove hard link");
 
         {
             host_filesystem
@@ -466,6 +1389,207 @@ mod tests {
         assert_eq!(maplit::hashset! {}, matches);
     }
 
+    #[test]
+    fn test_memory_filesystem() {
+        let mut memory_filesystem = MemoryFilesystem::new();
+        assert_eq!(None, memory_filesystem.working_directory());
+        memory_filesystem
+            .write_file("pre-existing_file", "\n")
+            .expect("memory filesystem write file");
+        assert!(memory_filesystem
+            .hard_link("pre-existing_file", "hard_link")
+            .is_err());
+        memory_filesystem
+            .open_file_for_write("missing/file.txt")
+            .expect_err("memory filesystem file in missing directory");
+        memory_filesystem
+            .create_directories("sub/directory")
+            .expect("memory filesystem-created directories");
+        memory_filesystem
+            .open_file_for_write("sub/directory/file_in_directory.txt")
+            .expect("memory filesystem file-in-sub-directory")
+            .write_all("directory".as_bytes())
+            .expect("memory filesystem write to file-in-sub-directory");
+        memory_filesystem
+            .write_file("sub/file_in_sub.txt", "sub")
+            .expect("memory filesystem file-in-directory");
+        memory_filesystem
+            .write_file("sub/directory/file_in_sub.abc", "\n")
+            .expect("memory filesystem unmatched file");
+
+        let mut contents = String::new();
+        memory_filesystem
+            .open_file_for_read("sub/directory/../file_in_sub.txt")
+            .expect("memory filesystem reopen for read")
+            .read_to_string(&mut contents)
+            .expect("memory filesystem read");
+        assert_eq!("sub", contents);
+        assert_eq!(
+            3,
+            memory_filesystem
+                .metadata("sub/file_in_sub.txt")
+                .expect("file metadata")
+                .size
+        );
+        assert_eq!(
+            FileType::Directory,
+            memory_filesystem
+                .metadata("sub")
+                .expect("directory metadata")
+                .file_type
+        );
+        assert_eq!(
+            vec![
+                (PathBuf::from("directory"), FileType::Directory),
+                (PathBuf::from("file_in_sub.txt"), FileType::File),
+            ],
+            memory_filesystem
+                .read_directory("sub")
+                .expect("read directory")
+        );
+
+        memory_filesystem
+            .remove_file("pre-existing_file")
+            .expect("memory filesystem delete file");
+        memory_filesystem
+            .open_file_for_read("pre-existing_file")
+            .expect_err("memory filesystem reopen deleted file");
+
+        // Sub-systems share entries, and resolve paths relative to their directory.
+        let mut sub_filesystem = memory_filesystem.sub_system("sub").expect("sub-system");
+        assert!(sub_filesystem.file_exists("directory/file_in_directory.txt"));
+        sub_filesystem
+            .move_from_to("directory", "moved")
+            .expect("memory filesystem move directory");
+        assert!(memory_filesystem.file_exists("sub/moved/file_in_directory.txt"));
+        assert!(!memory_filesystem.file_exists("sub/directory/file_in_directory.txt"));
+        assert_eq!(
+            maplit::hashset! {
+                PathBuf::from("file_in_sub.txt"),
+                PathBuf::from("moved/file_in_directory.txt"),
+            },
+            sub_filesystem
+                .execute_glob("**/*.txt")
+                .expect("memory filesystem executed glob")
+                .map(|path| path.expect("pattern path ok"))
+                .collect()
+        );
+        assert_eq!(
+            maplit::hashset! {
+                PathBuf::from("sub/file_in_sub.txt"),
+                PathBuf::from("sub/moved"),
+            },
+            memory_filesystem
+                .execute_glob("sub/*")
+                .expect("memory filesystem executed glob")
+                .map(|path| path.expect("pattern path ok"))
+                .collect()
+        );
+        assert!(sub_filesystem
+            .glob_matches("**/*.txt", "moved/file_in_directory.txt")
+            .expect("glob matches"));
+        assert!(!sub_filesystem
+            .glob_matches("**/*.txt", "moved/file_in_sub.abc")
+            .expect("glob matches"));
+    }
+
+    #[test]
+    fn test_overlay_filesystem() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let upper_directory = temporary_directory.path().join("upper");
+        let lower_directory = temporary_directory.path().join("lower");
+        std::fs::create_dir_all(lower_directory.join("src")).expect("create lower directories");
+        std::fs::create_dir_all(&upper_directory).expect("create upper directory");
+        std::fs::write(lower_directory.join("src/main.c"), "lower").expect("write main.c");
+        std::fs::write(lower_directory.join("src/shadowed.c"), "lower")
+            .expect("write lower shadowed.c");
+        let mut overlay_filesystem = OverlayFilesystem::new(
+            HostFilesystem::try_new(upper_directory.clone()).expect("upper filesystem"),
+            vec![HostFilesystem::try_new(lower_directory.clone()).expect("lower filesystem")],
+        );
+        assert_eq!(
+            Some(upper_directory.clone()),
+            overlay_filesystem.working_directory()
+        );
+
+        // Writes go to the upper layer, including into directories that only exist below.
+        overlay_filesystem
+            .open_file_for_write("src/shadowed.c")
+            .expect("overlay filesystem write shadowing file")
+            .write_all("upper".as_bytes())
+            .expect("overlay filesystem write to shadowing file");
+        overlay_filesystem
+            .open_file_for_write("out.o")
+            .expect("overlay filesystem write new file")
+            .write_all("object".as_bytes())
+            .expect("overlay filesystem write to new file");
+        assert_eq!(
+            "upper",
+            std::fs::read_to_string(upper_directory.join("src/shadowed.c"))
+                .expect("read upper shadowed.c")
+        );
+        assert_eq!(
+            "lower",
+            std::fs::read_to_string(lower_directory.join("src/shadowed.c"))
+                .expect("read lower shadowed.c")
+        );
+        assert!(!lower_directory.join("out.o").exists());
+
+        // Reads resolve through the layers from the top down.
+        for (path, expected) in [("src/main.c", "lower"), ("src/shadowed.c", "upper")] {
+            let mut contents = String::new();
+            overlay_filesystem
+                .open_file_for_read(path)
+                .expect("overlay filesystem open for read")
+                .read_to_string(&mut contents)
+                .expect("overlay filesystem read");
+            assert_eq!(expected, contents);
+        }
+        assert!(overlay_filesystem.file_exists("src/main.c"));
+        assert!(!overlay_filesystem.file_exists("src/missing.c"));
+        overlay_filesystem
+            .open_file_for_read("src/missing.c")
+            .expect_err("overlay filesystem open missing file");
+        assert_eq!(
+            vec![
+                (PathBuf::from("main.c"), FileType::File),
+                (PathBuf::from("shadowed.c"), FileType::File),
+            ],
+            overlay_filesystem
+                .read_directory("src")
+                .expect("overlay filesystem read directory")
+        );
+        assert_eq!(
+            maplit::hashset! {
+                PathBuf::from("src/main.c"),
+                PathBuf::from("src/shadowed.c"),
+            },
+            overlay_filesystem
+                .execute_glob("src/*.c")
+                .expect("overlay filesystem executed glob")
+                .map(|path| path.expect("pattern path ok"))
+                .collect()
+        );
+
+        // Base layers are read-only.
+        overlay_filesystem
+            .remove_file("src/main.c")
+            .expect_err("overlay filesystem remove lower file");
+        overlay_filesystem
+            .move_from_to("src/main.c", "main.c")
+            .expect_err("overlay filesystem move lower file");
+        overlay_filesystem
+            .move_from_to("out.o", "src/out.o")
+            .expect("overlay filesystem move upper file");
+        overlay_filesystem
+            .remove_file("src/out.o")
+            .expect("overlay filesystem remove upper file");
+        assert!(lower_directory.join("src/main.c").exists());
+
+        let mut sub_filesystem = overlay_filesystem.sub_system("src").expect("sub-system");
+        assert!(sub_filesystem.file_exists("main.c"));
+    }
+
     #[test]
     fn test_relativize_path() {
         assert_eq!("", relativize_path("/a/b", "/a/b").to_str().unwrap());
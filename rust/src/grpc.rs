@@ -0,0 +1,457 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! gRPC over cleartext HTTP/2 (`grpc://` endpoints) for the services in `crate::remote_api`.
+//!
+//! `GrpcServer` serves a `GrpcChannel`, such as a `RemoteApiServer`, to clients such as Bazel's
+//! `--remote_cache=grpc://host:port`, and `GrpcClient` is a `GrpcChannel` to a remote server, for
+//! use with `RemoteCacheClient`.
+//!
+//! Both are blocking; their HTTP/2 connections run on an internal tokio runtime. Messages are
+//! buffered: all of a call's request messages are received before the call is dispatched, and all
+//! of its response messages are produced before they are sent. `GrpcServer` dispatches calls to
+//! its channel one at a time.
+
+use crate::reapi::Status;
+use crate::remote_api::code;
+use crate::remote_api::decode_grpc_frame;
+use crate::remote_api::encode_grpc_frame;
+use crate::remote_api::GrpcChannel;
+use crate::remote_api::BYTESTREAM_READ_METHOD;
+use crate::remote_api::BYTESTREAM_WRITE_METHOD;
+use crate::remote_api::EXECUTE_METHOD;
+use anyhow::Context as _;
+use bytes::Bytes;
+use h2::RecvStream;
+use http::header::CONTENT_TYPE;
+use http::HeaderMap;
+use http::HeaderValue;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// Default address on which `serve-remote-api` listens.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8980";
+
+const GRPC_CONTENT_TYPE: &str = "application/grpc";
+const GRPC_STATUS_HEADER: &str = "grpc-status";
+const GRPC_MESSAGE_HEADER: &str = "grpc-message";
+
+/// Serves a `GrpcChannel` over HTTP/2.
+pub struct GrpcServer<Channel: GrpcChannel + Send + 'static> {
+    channel: Arc<Mutex<Channel>>,
+}
+
+impl<Channel: GrpcChannel + Send + 'static> GrpcServer<Channel> {
+    pub fn new(channel: Channel) -> Self {
+        Self {
+            channel: Arc::new(Mutex::new(channel)),
+        }
+    }
+
+    /// Serves connections accepted by `listener` until accepting fails. Failures to serve a
+    /// connection are logged, and do not stop the server.
+    pub fn serve(&self, listener: TcpListener) -> anyhow::Result<()> {
+        listener
+            .set_nonblocking(true)
+            .context("configuring listener")?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_io()
+            .build()
+            .context("starting gRPC runtime")?;
+        runtime.block_on(async {
+            let listener =
+                tokio::net::TcpListener::from_std(listener).context("registering listener")?;
+            loop {
+                let (stream, peer) = listener.accept().await.context("accepting connection")?;
+                let channel = self.channel.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = serve_connection(stream, channel).await {
+                        tracing::warn!(%peer, %error, "failed to serve gRPC connection");
+                    }
+                });
+            }
+        })
+    }
+}
+
+async fn serve_connection<Channel: GrpcChannel + Send + 'static>(
+    stream: tokio::net::TcpStream,
+    channel: Arc<Mutex<Channel>>,
+) -> Result<(), h2::Error> {
+    let mut connection = h2::server::handshake(stream).await?;
+    while let Some(call) = connection.accept().await {
+        let (request, respond) = call?;
+        let channel = channel.clone();
+        tokio::spawn(async move {
+            if let Err(error) = serve_call(request, respond, channel).await {
+                tracing::warn!(%error, "failed to respond to gRPC call");
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn serve_call<Channel: GrpcChannel + Send + 'static>(
+    request: http::Request<RecvStream>,
+    mut respond: h2::server::SendResponse<Bytes>,
+    channel: Arc<Mutex<Channel>>,
+) -> Result<(), h2::Error> {
+    let method = request.uri().path().to_string();
+    let mut body = request.into_body();
+    let result = match read_messages(&mut body, code::INVALID_ARGUMENT).await {
+        Ok(requests) => tokio::task::spawn_blocking(move || {
+            let mut channel = channel
+                .lock()
+                .map_err(|_| Status::new(code::INTERNAL, "server failed while handling a call"))?;
+            dispatch(&mut *channel, &method, requests)
+        })
+        .await
+        .unwrap_or_else(|error| Err(Status::new(code::INTERNAL, error.to_string()))),
+        Err(status) => Err(status),
+    };
+
+    let response = http::Response::builder()
+        .header(CONTENT_TYPE, GRPC_CONTENT_TYPE)
+        .body(())
+        .expect("gRPC response headers are valid");
+    let mut send = respond.send_response(response, false)?;
+    let status = match result {
+        Ok(responses) => {
+            for response in responses.iter() {
+                send.send_data(Bytes::from(encode_grpc_frame(response)), false)?;
+            }
+            Status::ok()
+        }
+        Err(status) => status,
+    };
+    send.send_trailers(status_trailers(&status))
+}
+
+/// Calls the method of `channel` that matches the kind of the gRPC method `method`.
+fn dispatch<Channel: GrpcChannel>(
+    channel: &mut Channel,
+    method: &str,
+    requests: Vec<Vec<u8>>,
+) -> Result<Vec<Vec<u8>>, Status> {
+    match method {
+        BYTESTREAM_WRITE_METHOD => channel
+            .client_streaming(method, &requests)
+            .map(|response| vec![response]),
+        BYTESTREAM_READ_METHOD | EXECUTE_METHOD => {
+            channel.server_streaming(method, &single(requests, code::INVALID_ARGUMENT)?)
+        }
+        _ => channel
+            .unary(method, &single(requests, code::INVALID_ARGUMENT)?)
+            .map(|response| vec![response]),
+    }
+}
+
+/// A blocking gRPC client connection.
+pub struct GrpcClient {
+    runtime: tokio::runtime::Runtime,
+    sender: h2::client::SendRequest<Bytes>,
+    authority: String,
+}
+
+impl GrpcClient {
+    /// Connects to the gRPC server at `address` (`host:port`).
+    pub fn connect(address: &str) -> anyhow::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .context("starting gRPC runtime")?;
+        let sender = runtime.block_on(async {
+            let stream = tokio::net::TcpStream::connect(address)
+                .await
+                .with_context(|| format!("connecting to {}", address))?;
+            let (sender, connection) = h2::client::handshake(stream)
+                .await
+                .with_context(|| format!("starting HTTP/2 connection to {}", address))?;
+            tokio::spawn(async move {
+                if let Err(error) = connection.await {
+                    tracing::warn!(%error, "gRPC connection failed");
+                }
+            });
+            anyhow::Ok(sender)
+        })?;
+        Ok(Self {
+            runtime,
+            sender,
+            authority: String::from(address),
+        })
+    }
+
+    /// Sends `body`, the framed request messages, to `method`, returning the response messages.
+    fn call(&mut self, method: &str, body: Vec<u8>) -> Result<Vec<Vec<u8>>, Status> {
+        let request = http::Request::post(format!("http://{}{}", self.authority, method))
+            .header(CONTENT_TYPE, GRPC_CONTENT_TYPE)
+            .header("te", "trailers")
+            .body(())
+            .map_err(|error| Status::new(code::INVALID_ARGUMENT, error.to_string()))?;
+        let sender = self.sender.clone();
+        self.runtime.block_on(async move {
+            let mut sender = sender.ready().await.map_err(unavailable)?;
+            let (response, mut send) = sender.send_request(request, false).map_err(unavailable)?;
+            send.send_data(Bytes::from(body), true)
+                .map_err(unavailable)?;
+            let response = response.await.map_err(unavailable)?;
+            if response.status() != http::StatusCode::OK {
+                return Err(Status::new(
+                    code::UNAVAILABLE,
+                    format!("HTTP status {}", response.status()),
+                ));
+            }
+            // A response without messages may carry its status in its headers.
+            let header_status = grpc_status(response.headers());
+            let mut body = response.into_body();
+            let responses = read_messages(&mut body, code::INTERNAL).await?;
+            let trailers = body.trailers().await.map_err(unavailable)?;
+            let status = trailers
+                .as_ref()
+                .and_then(grpc_status)
+                .or(header_status)
+                .unwrap_or_else(|| Status::new(code::INTERNAL, "response without gRPC status"));
+            if status.code == code::OK {
+                Ok(responses)
+            } else {
+                Err(status)
+            }
+        })
+    }
+}
+
+impl GrpcChannel for GrpcClient {
+    fn unary(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>, Status> {
+        single(
+            self.call(method, encode_grpc_frame(request))?,
+            code::INTERNAL,
+        )
+    }
+
+    fn server_streaming(&mut self, method: &str, request: &[u8]) -> Result<Vec<Vec<u8>>, Status> {
+        self.call(method, encode_grpc_frame(request))
+    }
+
+    fn client_streaming(&mut self, method: &str, requests: &[Vec<u8>]) -> Result<Vec<u8>, Status> {
+        let body = requests
+            .iter()
+            .flat_map(|request| encode_grpc_frame(request))
+            .collect();
+        single(self.call(method, body)?, code::INTERNAL)
+    }
+}
+
+/// Reads the gRPC messages in `body`, failing with `malformed_code` if they are malformed.
+async fn read_messages(body: &mut RecvStream, malformed_code: i32) -> Result<Vec<Vec<u8>>, Status> {
+    let mut frames = vec![];
+    while let Some(data) = body.data().await {
+        let data = data.map_err(unavailable)?;
+        body.flow_control()
+            .release_capacity(data.len())
+            .map_err(unavailable)?;
+        frames.extend_from_slice(&data);
+    }
+    let mut messages = vec![];
+    let mut rest = frames.as_slice();
+    while !rest.is_empty() {
+        let (message, next) = decode_grpc_frame(rest)
+            .map_err(|error| Status::new(malformed_code, format!("{:#}", error)))?;
+        messages.push(message.to_vec());
+        rest = next;
+    }
+    Ok(messages)
+}
+
+/// Returns the only message in `messages`, failing with `count_code` if there is not exactly one.
+fn single(mut messages: Vec<Vec<u8>>, count_code: i32) -> Result<Vec<u8>, Status> {
+    if messages.len() != 1 {
+        return Err(Status::new(
+            count_code,
+            format!("expected one message, got {}", messages.len()),
+        ));
+    }
+    Ok(messages.remove(0))
+}
+
+fn unavailable(error: h2::Error) -> Status {
+    Status::new(code::UNAVAILABLE, error.to_string())
+}
+
+fn status_trailers(status: &Status) -> HeaderMap {
+    let mut trailers = HeaderMap::new();
+    trailers.insert(GRPC_STATUS_HEADER, HeaderValue::from(status.code));
+    if !status.message.is_empty() {
+        if let Ok(message) = HeaderValue::from_str(&percent_encode(&status.message)) {
+            trailers.insert(GRPC_MESSAGE_HEADER, message);
+        }
+    }
+    trailers
+}
+
+fn grpc_status(headers: &HeaderMap) -> Option<Status> {
+    let status_code = headers
+        .get(GRPC_STATUS_HEADER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()?;
+    let message = headers
+        .get(GRPC_MESSAGE_HEADER)
+        .and_then(|message| message.to_str().ok())
+        .map(percent_decode)
+        .unwrap_or_default();
+    Some(Status::new(status_code, message))
+}
+
+/// Percent-encodes `message` as a `grpc-message` header value.
+fn percent_encode(message: &str) -> String {
+    message
+        .bytes()
+        .map(|byte| match byte {
+            b' '..=b'~' if byte != b'%' => char::from(byte).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn percent_decode(message: &str) -> String {
+    let bytes = message.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::percent_decode;
+    use super::percent_encode;
+    use super::GrpcClient;
+    use super::GrpcServer;
+    use crate::blob::JSON;
+    use crate::fs::HostFilesystem;
+    use crate::reapi::digest_content;
+    use crate::reapi::ActionResult;
+    use crate::remote_api::code;
+    use crate::remote_api::GrpcChannel;
+    use crate::remote_api::RemoteApiServer;
+    use crate::remote_api::BYTESTREAM_WRITE_METHOD;
+    use crate::remote_client::RemoteCacheClient;
+    use crate::remote_client::RemoteCacheOptions;
+    use std::net::TcpListener;
+
+    fn serve_temporary_cache() -> (tempfile::TempDir, String) {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let server = GrpcServer::new(
+            RemoteApiServer::<_, JSON>::new(filesystem).expect("remote API server"),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener");
+        let address = listener.local_addr().expect("local address").to_string();
+        std::thread::spawn(move || server.serve(listener));
+        (temporary_directory, address)
+    }
+
+    #[test]
+    fn test_grpc_remote_cache() {
+        let (_temporary_directory, address) = serve_temporary_cache();
+        let mut client = RemoteCacheClient::new(
+            GrpcClient::connect(&address).expect("connect"),
+            RemoteCacheOptions::default(),
+        );
+
+        // Larger than the HTTP/2 flow control window and the batch limit, so that it is streamed.
+        let large: Vec<u8> = (0..(4 << 20)).map(|index| (index % 251) as u8).collect();
+        let small = b"small".to_vec();
+        let uploaded = client
+            .upload_blobs(&[large.clone(), small.clone()])
+            .expect("upload blobs");
+        assert_eq!(2, uploaded.len());
+        assert!(client
+            .find_missing_blobs(&uploaded)
+            .expect("find missing blobs")
+            .is_empty());
+        for blob in [large, small] {
+            assert_eq!(
+                Some(blob.clone()),
+                client
+                    .download_blob(&digest_content(&blob).expect("digest"))
+                    .expect("download blob")
+            );
+        }
+        assert_eq!(
+            None,
+            client
+                .download_blob(&digest_content(b"absent").expect("digest"))
+                .expect("download absent blob")
+        );
+
+        let action_digest = digest_content(b"action").expect("digest");
+        let action_result = ActionResult {
+            exit_code: 3,
+            ..ActionResult::default()
+        };
+        client
+            .update_action_result(&action_digest, &action_result)
+            .expect("update action result");
+        assert_eq!(
+            Some(action_result),
+            client
+                .get_action_result(&action_digest)
+                .expect("get action result")
+        );
+    }
+
+    #[test]
+    fn test_grpc_status() {
+        let (_temporary_directory, address) = serve_temporary_cache();
+        let mut client = GrpcClient::connect(&address).expect("connect");
+
+        let status = client
+            .unary("/test.Unknown/Method", b"")
+            .expect_err("unknown method");
+        assert_eq!(code::UNIMPLEMENTED, status.code);
+        assert!(status.message.contains("/test.Unknown/Method"));
+
+        let status = client
+            .client_streaming(BYTESTREAM_WRITE_METHOD, &[])
+            .expect_err("empty write");
+        assert_eq!(code::INVALID_ARGUMENT, status.code);
+    }
+
+    #[test]
+    fn test_grpc_client_connect_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener");
+        let address = listener.local_addr().expect("local address").to_string();
+        drop(listener);
+        assert!(GrpcClient::connect(&address).is_err());
+    }
+
+    #[test]
+    fn test_percent_encoding() {
+        let message = "100% done: naïve\nline";
+        let encoded = percent_encode(message);
+        assert_eq!("100%25 done: na%C3%AFve%0Aline", encoded);
+        assert_eq!(message, percent_decode(&encoded));
+        assert_eq!("50%", percent_decode("50%"));
+    }
+}
@@ -2,18 +2,32 @@
 // Use of this source code is governed by a Apache-style license that can be
 // found in the LICENSE file.
 
+use crate::events;
+use crate::events::Event;
 use crate::fs::Filesystem;
+use crate::profile;
+use crate::profile::Phase;
+use crate::transport::Blake2b256;
+use crate::transport::ContentBlake2b256;
 use crate::transport::ContentSha256;
 use crate::transport::IdentityScheme as IdentitySchemeEnum;
 use crate::transport::Sha256;
+use crate::transport::SymlinkIdentity;
 use anyhow::Context as _;
+use blake2::digest::consts::U32;
+use blake2::Blake2b;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use sha2::Digest as _;
+use sha2::Digest;
 use sha2::Sha256 as Sha256Hasher;
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::marker::PhantomData;
 use std::path::Path;
+use std::time::Instant;
 
 pub trait Identity: Clone + Debug + DeserializeOwned + Hash + Ord + Serialize + ToString {}
 
@@ -24,6 +38,9 @@ pub trait IdentityScheme: Clone + DeserializeOwned + Serialize {
 
     const IDENTITY_SCHEME: IdentitySchemeEnum;
 
+    /// Identifies the contents of the file at `path`, following symbolic links. Dangling symbolic
+    /// links have no contents, and fail to be identified; `identify_path` identifies them by their
+    /// target paths under `SymlinkIdentity::TargetPath`.
     fn identify_file<FS: Filesystem, P: AsRef<Path>>(
         filesystem: &mut FS,
         path: P,
@@ -36,6 +53,178 @@ pub trait IdentityScheme: Clone + DeserializeOwned + Serialize {
     ) -> Result<Self::Identity, anyhow::Error>;
 
     fn identify_content<R: std::io::Read>(content: R) -> Result<Self::Identity, anyhow::Error>;
+
+    /// Identifies the target path stored in a symbolic link. The path is prefixed with
+    /// `SYMLINK_IDENTITY_PREFIX` so that a link never shares an identity with a regular file whose
+    /// contents happen to be the link's target path.
+    fn identify_symlink_target<P: AsRef<Path>>(target: P) -> Result<Self::Identity, anyhow::Error> {
+        Self::identify_content(symlink_target_content(target)?.as_slice())
+    }
+
+    /// Identifies the file at `path`, treating symbolic links according to `symlink_identity`.
+    /// Paths that are not symbolic links are identified by `identify_file`.
+    fn identify_path<FS: Filesystem, P: AsRef<Path>>(
+        filesystem: &mut FS,
+        path: P,
+        symlink_identity: SymlinkIdentity,
+    ) -> Result<Self::Identity, anyhow::Error> {
+        let path = path.as_ref();
+        if !filesystem.is_symlink(path) {
+            return Self::identify_file(filesystem, path);
+        }
+
+        match symlink_identity {
+            SymlinkIdentity::TargetPath => {
+                let target = filesystem
+                    .read_link(path)
+                    .map_err(anyhow::Error::from)
+                    .with_context(|| format!("reading symbolic link {:?}", path))?;
+                Self::identify_symlink_target(target)
+            }
+            SymlinkIdentity::ResolvedContents => {
+                if !filesystem.file_exists(path) {
+                    let target = filesystem.read_link(path).ok();
+                    anyhow::bail!(
+                        "symbolic link, {:?}, is dangling (target: {:?}) and cannot be identified by its resolved contents",
+                        path,
+                        target
+                    );
+                }
+                Self::identify_file(filesystem, path)
+            }
+        }
+    }
+}
+
+/// Outcome of identifying a single file. Distinguishes files that are absent (and may be optional)
+/// from files that exist but could not be read, so callers can tolerate the former while failing
+/// fast on the latter.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileIdentityStatus<Identity> {
+    Identified(Identity),
+    Missing,
+    DanglingSymlink,
+    Directory,
+    PermissionDenied(String),
+    Failed(String),
+}
+
+impl<Identity> FileIdentityStatus<Identity> {
+    /// Identifies `path` and classifies any failure.
+    pub fn identify<IS: IdentityScheme<Identity = Identity>, FS: Filesystem, P: AsRef<Path>>(
+        filesystem: &mut FS,
+        path: P,
+        symlink_identity: SymlinkIdentity,
+    ) -> Self {
+        let path = path.as_ref();
+        match IS::identify_path(filesystem, path, symlink_identity) {
+            Ok(identity) => Self::Identified(identity),
+            Err(error) => {
+                if filesystem.is_symlink(path) && !filesystem.file_exists(path) {
+                    return Self::DanglingSymlink;
+                }
+                let io_error_kind = error
+                    .chain()
+                    .find_map(|cause| cause.downcast_ref::<std::io::Error>())
+                    .map(std::io::Error::kind);
+                match io_error_kind {
+                    Some(ErrorKind::NotFound) => Self::Missing,
+                    Some(ErrorKind::IsADirectory) => Self::Directory,
+                    Some(ErrorKind::PermissionDenied) => {
+                        Self::PermissionDenied(format!("{:#}", error))
+                    }
+                    _ => Self::Failed(format!("{:#}", error)),
+                }
+            }
+        }
+    }
+
+    pub fn identity(&self) -> Option<&Identity> {
+        match self {
+            Self::Identified(identity) => Some(identity),
+            _ => None,
+        }
+    }
+
+    /// True for files that do not exist (including dangling symbolic links), which callers may
+    /// treat as optional. All other failures indicate a file that exists but cannot be identified.
+    pub fn is_absent(&self) -> bool {
+        matches!(self, Self::Missing | Self::DanglingSymlink)
+    }
+
+    /// Converts to an optional identity, tolerating absent files and failing on all other errors.
+    pub fn into_optional_identity<P: AsRef<Path>>(
+        self,
+        path: P,
+    ) -> anyhow::Result<Option<Identity>> {
+        let path = path.as_ref();
+        match self {
+            Self::Identified(identity) => Ok(Some(identity)),
+            Self::Missing | Self::DanglingSymlink => Ok(None),
+            Self::Directory => anyhow::bail!("attempted to identify directory {:?}", path),
+            Self::PermissionDenied(message) => {
+                anyhow::bail!("permission denied identifying {:?}: {}", path, message)
+            }
+            Self::Failed(message) => anyhow::bail!("failed to identify {:?}: {}", path, message),
+        }
+    }
+}
+
+type Blake2b256Hasher = Blake2b<U32>;
+
+/// Domain-separation prefix for identities computed by `IdentitySalt::identify_content`.
+pub const SALT_IDENTITY_PREFIX: &[u8] = b"salt\0";
+
+/// Size of the buffer used when computing identities from readers.
+pub const READ_BUFFER_SIZE_BYTES: usize = 1 << 16;
+
+/// Domain-separation prefix for identities computed by `IdentityScheme::identify_symlink_target`.
+pub const SYMLINK_IDENTITY_PREFIX: &[u8] = b"symlink\0";
+
+/// SHA-256 implementation that `ContentSha256` uses on this host. `sha2` does not report the
+/// implementation it dispatches to, so this mirrors the selection in `sha2` 0.10's `sha256` module;
+/// keep the two in step when upgrading `sha2`.
+pub fn sha256_backend() -> &'static str {
+    select_sha256_backend(
+        std::env::consts::ARCH,
+        cfg!(feature = "asm"),
+        sha256_instructions_detected(),
+    )
+}
+
+/// Selects the `sha2` 0.10 SHA-256 implementation for `target_arch`:
+///
+/// - On x86, SHA-NI when the CPU supports it, otherwise `sha2-asm` when the `asm` feature is
+///   enabled, otherwise the portable implementation.
+/// - On ARMv8, the SHA2 extension only when both the `asm` feature is enabled and the CPU supports
+///   it; `sha2-asm` is never used.
+/// - Elsewhere, the portable implementation.
+fn select_sha256_backend(target_arch: &str, asm: bool, instructions: bool) -> &'static str {
+    match target_arch {
+        "x86" | "x86_64" if instructions => "sha-ni",
+        "x86" | "x86_64" if asm => "asm",
+        "aarch64" if asm && instructions => "armv8-sha2",
+        _ => "software",
+    }
+}
+
+/// Whether the CPU supports the SHA-256 instructions that `sha2` checks for at runtime.
+fn sha256_instructions_detected() -> bool {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        std::is_x86_feature_detected!("sha")
+            && std::is_x86_feature_detected!("sse2")
+            && std::is_x86_feature_detected!("ssse3")
+            && std::is_x86_feature_detected!("sse4.1")
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        std::arch::is_aarch64_feature_detected!("sha2")
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        false
+    }
 }
 
 impl IdentityScheme for ContentSha256 {
@@ -47,17 +236,8 @@ impl IdentityScheme for ContentSha256 {
         filesystem: &mut FS,
         path: P,
     ) -> Result<Self::Identity, anyhow::Error> {
-        let mut hasher = Sha256Hasher::new();
-        let mut file = filesystem
-            .open_file_for_read(path.as_ref())
-            .with_context(|| format!("identifying {:?}", path.as_ref()))?;
-        std::io::copy(&mut file, &mut hasher)?;
-        let hash: [u8; 32] = hasher
-            .finalize()
-            .as_slice()
-            .try_into()
-            .expect("sha256 hash contains 32 bytes");
-        Ok(Sha256::new(hash))
+        reject_dangling_symlink(filesystem, path.as_ref())?;
+        digest_file::<Sha256Hasher, FS>(filesystem, path.as_ref()).map(Sha256::new)
     }
 
     fn identify_file_content<FS: Filesystem, P: AsRef<Path>>(
@@ -65,34 +245,270 @@ impl IdentityScheme for ContentSha256 {
         _path: P,
         content: &[u8],
     ) -> Result<Self::Identity, anyhow::Error> {
-        let mut hasher = Sha256Hasher::new();
-        hasher.update(content);
-        let hash: [u8; 32] = hasher
-            .finalize()
-            .as_slice()
-            .try_into()
-            .expect("sha256 hash contains 32 bytes");
-        Ok(Sha256::new(hash))
+        Ok(Sha256::new(digest_bytes::<Sha256Hasher>(content)))
     }
 
-    fn identify_content<R: std::io::Read>(mut content: R) -> Result<Self::Identity, anyhow::Error> {
-        let mut hasher = Sha256Hasher::new();
-        let mut buffer = [0; 1024];
+    fn identify_content<R: std::io::Read>(content: R) -> Result<Self::Identity, anyhow::Error> {
+        digest_content::<Sha256Hasher, R>(content).map(Sha256::new)
+    }
+}
 
-        loop {
-            let count = content.read(&mut buffer)?;
-            if count == 0 {
-                break;
-            }
-            hasher.update(&buffer[..count]);
+impl IdentityScheme for ContentBlake2b256 {
+    type Identity = Blake2b256;
+
+    const IDENTITY_SCHEME: IdentitySchemeEnum = IdentitySchemeEnum::ContentBlake2b256;
+
+    fn identify_file<FS: Filesystem, P: AsRef<Path>>(
+        filesystem: &mut FS,
+        path: P,
+    ) -> Result<Self::Identity, anyhow::Error> {
+        reject_dangling_symlink(filesystem, path.as_ref())?;
+        digest_file::<Blake2b256Hasher, FS>(filesystem, path.as_ref()).map(Blake2b256::new)
+    }
+
+    fn identify_file_content<FS: Filesystem, P: AsRef<Path>>(
+        _filesystem: &mut FS,
+        _path: P,
+        content: &[u8],
+    ) -> Result<Self::Identity, anyhow::Error> {
+        Ok(Blake2b256::new(digest_bytes::<Blake2b256Hasher>(content)))
+    }
+
+    fn identify_content<R: std::io::Read>(content: R) -> Result<Self::Identity, anyhow::Error> {
+        digest_content::<Blake2b256Hasher, R>(content).map(Blake2b256::new)
+    }
+}
+
+fn digest_bytes<Hasher: Digest>(content: &[u8]) -> [u8; 32] {
+    Hasher::digest(content)
+        .as_slice()
+        .try_into()
+        .expect("256-bit digest contains 32 bytes")
+}
+
+/// Content identified by `IdentityScheme::identify_symlink_target`, which is stored as the blob of a
+/// dangling symbolic link.
+pub fn symlink_target_content<P: AsRef<Path>>(target: P) -> anyhow::Result<Vec<u8>> {
+    let target_str = target.as_ref().to_str().ok_or_else(|| {
+        anyhow::anyhow!(
+            "symbolic link target, {:?}, cannot be encoded as a string",
+            target.as_ref()
+        )
+    })?;
+    let mut content = SYMLINK_IDENTITY_PREFIX.to_vec();
+    content.extend_from_slice(target_str.as_bytes());
+    Ok(content)
+}
+
+/// Fails when `path` is a symbolic link that does not resolve, and so has no contents to identify.
+fn reject_dangling_symlink<FS: Filesystem>(filesystem: &mut FS, path: &Path) -> anyhow::Result<()> {
+    if filesystem.is_symlink(path) && filesystem.metadata(path).is_err() {
+        anyhow::bail!(
+            "symbolic link, {:?}, is dangling (target: {:?}) and has no contents to identify",
+            path,
+            filesystem.read_link(path).ok()
+        );
+    }
+    Ok(())
+}
+
+fn digest_file<Hasher: Digest, FS: Filesystem>(
+    filesystem: &mut FS,
+    path: &Path,
+) -> Result<[u8; 32], anyhow::Error> {
+    let file = filesystem
+        .open_file_for_sequential_read(path)
+        .with_context(|| format!("identifying {:?}", path))?;
+    let _span = profile::span(Phase::Hashing, || format!("{}", path.display()));
+    let start = Instant::now();
+    let mut counted_file = CountingReader {
+        inner: file,
+        count: 0,
+    };
+    let digest = digest_content::<Hasher, _>(&mut counted_file)?;
+    events::publish(&Event::FileHashed {
+        path,
+        bytes: counted_file.count as u64,
+        duration: start.elapsed(),
+    });
+    Ok(digest)
+}
+
+/// Counts the bytes read through it, for recording hash throughput.
+struct CountingReader<R: Read> {
+    inner: R,
+    count: usize,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.inner.read(buffer)?;
+        self.count += count;
+        Ok(count)
+    }
+}
+
+fn digest_content<Hasher: Digest, R: Read>(mut content: R) -> Result<[u8; 32], anyhow::Error> {
+    let mut hasher = Hasher::new();
+    let mut buffer = vec![0; READ_BUFFER_SIZE_BYTES];
+
+    loop {
+        let count = content.read(&mut buffer)?;
+        if count == 0 {
+            break;
         }
+        hasher.update(&buffer[..count]);
+    }
 
-        let hash: [u8; 32] = hasher
-            .finalize()
-            .as_slice()
-            .try_into()
-            .expect("sha256 hash contains 32 bytes");
-        Ok(Sha256::new(hash))
+    Ok(hasher
+        .finalize()
+        .as_slice()
+        .try_into()
+        .expect("256-bit digest contains 32 bytes"))
+}
+
+/// Object-safe counterpart to `IdentityScheme` for selecting a scheme at runtime. Identities are
+/// surfaced in their string encoding, which is the same encoding used to name blobs.
+pub trait DynIdentityScheme {
+    fn identity_scheme(&self) -> IdentitySchemeEnum;
+
+    fn identify_content(&self, content: &mut dyn Read) -> Result<String, anyhow::Error>;
+}
+
+/// Adapter that exposes a statically typed `IdentityScheme` as a `DynIdentityScheme`.
+pub struct DynIdentitySchemeAdapter<IS: IdentityScheme> {
+    _marker: PhantomData<IS>,
+}
+
+impl<IS: IdentityScheme> DynIdentitySchemeAdapter<IS> {
+    pub fn new() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<IS: IdentityScheme> Default for DynIdentitySchemeAdapter<IS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<IS: IdentityScheme> DynIdentityScheme for DynIdentitySchemeAdapter<IS> {
+    fn identity_scheme(&self) -> IdentitySchemeEnum {
+        IS::IDENTITY_SCHEME
+    }
+
+    fn identify_content(&self, content: &mut dyn Read) -> Result<String, anyhow::Error> {
+        IS::identify_content(content).map(|identity| identity.to_string())
+    }
+}
+
+/// Registry of boxed identity schemes keyed by `IdentitySchemeEnum`, so that configuration or
+/// command-line flags can choose a scheme without adding generic instantiations at call sites.
+pub struct IdentitySchemeRegistry {
+    schemes: HashMap<IdentitySchemeEnum, Box<dyn DynIdentityScheme>>,
+}
+
+impl IdentitySchemeRegistry {
+    pub fn empty() -> Self {
+        Self {
+            schemes: HashMap::new(),
+        }
+    }
+
+    /// Registers `IS`, returning the previously registered scheme with the same enum value, if any.
+    pub fn register<IS: 'static + IdentityScheme>(&mut self) -> Option<Box<dyn DynIdentityScheme>> {
+        self.register_boxed(Box::new(DynIdentitySchemeAdapter::<IS>::new()))
+    }
+
+    pub fn register_boxed(
+        &mut self,
+        scheme: Box<dyn DynIdentityScheme>,
+    ) -> Option<Box<dyn DynIdentityScheme>> {
+        self.schemes.insert(scheme.identity_scheme(), scheme)
+    }
+
+    pub fn get(&self, identity_scheme: IdentitySchemeEnum) -> Option<&dyn DynIdentityScheme> {
+        self.schemes
+            .get(&identity_scheme)
+            .map(|scheme| scheme.as_ref())
+    }
+
+    pub fn try_get(
+        &self,
+        identity_scheme: IdentitySchemeEnum,
+    ) -> Result<&dyn DynIdentityScheme, anyhow::Error> {
+        self.get(identity_scheme).ok_or_else(|| {
+            anyhow::anyhow!("identity scheme, {}, is not registered", identity_scheme)
+        })
+    }
+
+    pub fn identity_schemes(&self) -> impl Iterator<Item = &IdentitySchemeEnum> {
+        self.schemes.keys()
+    }
+
+    pub fn identify_content<R: Read>(
+        &self,
+        identity_scheme: IdentitySchemeEnum,
+        mut content: R,
+    ) -> Result<String, anyhow::Error> {
+        self.try_get(identity_scheme)?
+            .identify_content(&mut content)
+    }
+
+    pub fn identify_file<FS: Filesystem, P: AsRef<Path>>(
+        &self,
+        identity_scheme: IdentitySchemeEnum,
+        filesystem: &mut FS,
+        path: P,
+    ) -> Result<String, anyhow::Error> {
+        let scheme = self.try_get(identity_scheme)?;
+        let mut file = filesystem
+            .open_file_for_read(path.as_ref())
+            .with_context(|| format!("identifying {:?}", path.as_ref()))?;
+        scheme.identify_content(&mut file)
+    }
+}
+
+impl Default for IdentitySchemeRegistry {
+    /// Creates a registry containing every identity scheme implemented by this crate.
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register::<ContentSha256>();
+        registry.register::<ContentBlake2b256>();
+        registry
+    }
+}
+
+/// Salt mixed into the identities of task inputs, so that caches belonging to distinct
+/// organizations can share storage without their pointers colliding or poisoning each other.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IdentitySalt {
+    salt: Vec<u8>,
+}
+
+impl IdentitySalt {
+    pub fn new<B: Into<Vec<u8>>>(salt: B) -> Self {
+        Self { salt: salt.into() }
+    }
+
+    /// Identifies `content` with the salt mixed in. The salt is length-prefixed so that no
+    /// salt/content pair can produce the same input to `IS` as a different pair.
+    pub fn identify_content<IS: IdentityScheme, R: Read>(
+        &self,
+        content: R,
+    ) -> Result<IS::Identity, anyhow::Error> {
+        let mut prefix = SALT_IDENTITY_PREFIX.to_vec();
+        prefix.extend_from_slice(&(self.salt.len() as u64).to_le_bytes());
+        prefix.extend_from_slice(&self.salt);
+        IS::identify_content(prefix.as_slice().chain(content))
+    }
+
+    /// Digest of the salt itself, suitable for recording alongside salted data without revealing
+    /// the salt.
+    pub fn digest<IS: IdentityScheme>(&self) -> Result<IS::Identity, anyhow::Error> {
+        IS::identify_content(self.salt.as_slice())
     }
 }
 
@@ -123,12 +539,23 @@ mod tests {
     use crate::canonical::FilesManifest;
     use crate::fs::Filesystem;
     use crate::fs::HostFilesystem;
+    use crate::identity::FileIdentityStatus;
+    use crate::identity::IdentitySalt;
     use crate::identity::IdentityScheme;
+    use crate::identity::IdentitySchemeRegistry;
+    use crate::identity::READ_BUFFER_SIZE_BYTES;
+    use crate::multihash::Multibase;
+    use crate::multihash::MultihashIdentity as _;
+    use crate::transport::Blake2b256;
+    use crate::transport::ContentBlake2b256;
     use crate::transport::ContentSha256;
     use crate::transport::FileIdentitiesManifest as FileIdentitiesManifestTransport;
+    use crate::transport::IdentityScheme as IdentitySchemeEnum;
     use crate::transport::Sha256;
+    use crate::transport::SymlinkIdentity;
     use sha2::Digest as _;
     use sha2::Sha256 as Sha256Hasher;
+    use std::collections::BTreeMap;
     use std::path::PathBuf;
 
     fn identify_files<FS, Id, IS>(
@@ -145,6 +572,7 @@ mod tests {
                 .paths()
                 .map(|path| (path.clone(), IS::identify_file(filesystem, path).ok()))
                 .collect(),
+            symlinks: BTreeMap::new(),
         }
         .try_into()
     }
@@ -204,4 +632,302 @@ mod tests {
 
         assert_eq!(expected_manifest, actual_manifest);
     }
+
+    #[test]
+    fn test_identify_large_file() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let contents = "0123456789abcdef".repeat((READ_BUFFER_SIZE_BYTES / 16) * 4 + 1);
+        std::fs::write(
+            temporary_directory.path().join("large"),
+            contents.as_bytes(),
+        )
+        .expect("write large file");
+        let mut filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+
+        assert_eq!(
+            get_sha256_from_str(&contents),
+            ContentSha256::identify_file(&mut filesystem, "large").expect("identify large file")
+        );
+    }
+
+    #[test]
+    fn test_identity_scheme_registry() {
+        let registry = IdentitySchemeRegistry::default();
+        let identity_scheme: IdentitySchemeEnum =
+            "content_sha256".parse().expect("parse identity scheme");
+        assert_eq!(IdentitySchemeEnum::ContentSha256, identity_scheme);
+        assert!("content_md5".parse::<IdentitySchemeEnum>().is_err());
+
+        let expected = get_sha256_from_str("registry").to_string();
+        let actual = registry
+            .identify_content(identity_scheme, "registry".as_bytes())
+            .expect("identify content via registry");
+        assert_eq!(expected, actual);
+
+        let empty = IdentitySchemeRegistry::empty();
+        assert!(empty
+            .identify_content(identity_scheme, "registry".as_bytes())
+            .is_err());
+    }
+
+    #[test]
+    fn test_identify_symlinks() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        std::fs::write(
+            temporary_directory.path().join("file"),
+            "contents".as_bytes(),
+        )
+        .expect("write file");
+        std::os::unix::fs::symlink("file", temporary_directory.path().join("link"))
+            .expect("create link");
+        std::os::unix::fs::symlink("missing", temporary_directory.path().join("dangling"))
+            .expect("create dangling link");
+        let mut filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+
+        let file_identity =
+            ContentSha256::identify_file(&mut filesystem, "file").expect("identify regular file");
+        assert_eq!(get_sha256_from_str("contents"), file_identity);
+        assert_eq!(
+            file_identity,
+            ContentSha256::identify_path(
+                &mut filesystem,
+                "link",
+                SymlinkIdentity::ResolvedContents
+            )
+            .expect("identify link by resolved contents")
+        );
+        assert!(ContentSha256::identify_path(
+            &mut filesystem,
+            "dangling",
+            SymlinkIdentity::ResolvedContents
+        )
+        .is_err());
+
+        let link_identity =
+            ContentSha256::identify_path(&mut filesystem, "link", SymlinkIdentity::TargetPath)
+                .expect("identify link by target path");
+        assert_eq!(
+            ContentSha256::identify_symlink_target("file").expect("identify target path"),
+            link_identity
+        );
+        assert_ne!(get_sha256_from_str("file"), link_identity);
+        assert_ne!(file_identity, link_identity);
+        assert_eq!(
+            ContentSha256::identify_symlink_target("missing").expect("identify target path"),
+            ContentSha256::identify_path(&mut filesystem, "dangling", SymlinkIdentity::TargetPath)
+                .expect("identify dangling link by target path")
+        );
+        assert!(format!(
+            "{:#}",
+            ContentSha256::identify_file(&mut filesystem, "dangling")
+                .expect_err("identify dangling link as file")
+        )
+        .contains("is dangling"));
+        assert_eq!(
+            file_identity,
+            ContentSha256::identify_file(&mut filesystem, "link").expect("identify link as file")
+        );
+    }
+
+    #[test]
+    fn test_identity_salt() {
+        let unsalted = ContentSha256::identify_content("content".as_bytes()).expect("unsalted");
+        let salt_a = IdentitySalt::new("a");
+        let salt_b = IdentitySalt::new("b");
+        let salted_a = salt_a
+            .identify_content::<ContentSha256, _>("content".as_bytes())
+            .expect("salted a");
+        let salted_b = salt_b
+            .identify_content::<ContentSha256, _>("content".as_bytes())
+            .expect("salted b");
+        assert_ne!(unsalted, salted_a);
+        assert_ne!(salted_a, salted_b);
+        assert_eq!(
+            salted_a,
+            IdentitySalt::new("a")
+                .identify_content::<ContentSha256, _>("content".as_bytes())
+                .expect("salted a again")
+        );
+        assert_eq!(
+            get_sha256_from_str("a"),
+            salt_a.digest::<ContentSha256>().expect("salt digest")
+        );
+    }
+
+    #[test]
+    fn test_file_identity_status() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        std::fs::write(
+            temporary_directory.path().join("file"),
+            "contents".as_bytes(),
+        )
+        .expect("write file");
+        std::fs::create_dir(temporary_directory.path().join("directory"))
+            .expect("create directory");
+        std::os::unix::fs::symlink("missing", temporary_directory.path().join("dangling"))
+            .expect("create dangling link");
+        let mut filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+
+        let manifest = FilesManifest::new(["dangling", "directory", "file", "missing"]);
+        let statuses = manifest.identify_with_status::<ContentSha256, HostFilesystem>(
+            &mut filesystem,
+            SymlinkIdentity::ResolvedContents,
+        );
+        assert_eq!(
+            vec![
+                (
+                    PathBuf::from("dangling"),
+                    FileIdentityStatus::DanglingSymlink
+                ),
+                (PathBuf::from("directory"), FileIdentityStatus::Directory),
+                (
+                    PathBuf::from("file"),
+                    FileIdentityStatus::Identified(get_sha256_from_str("contents"))
+                ),
+                (PathBuf::from("missing"), FileIdentityStatus::Missing),
+            ],
+            statuses
+        );
+
+        let identified = FilesManifest::new(["dangling", "file", "missing"])
+            .try_into_identified_allowing_absent::<ContentSha256, HostFilesystem>(
+                &mut filesystem,
+                SymlinkIdentity::ResolvedContents,
+            )
+            .expect("tolerate absent files");
+        assert_eq!(
+            vec![
+                (PathBuf::from("dangling"), None),
+                (PathBuf::from("file"), Some(get_sha256_from_str("contents"))),
+                (PathBuf::from("missing"), None),
+            ],
+            identified.identities().cloned().collect::<Vec<_>>()
+        );
+        assert!(manifest
+            .try_into_identified_allowing_absent::<ContentSha256, HostFilesystem>(
+                &mut filesystem,
+                SymlinkIdentity::ResolvedContents,
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_select_sha256_backend() {
+        for target_arch in ["x86", "x86_64"] {
+            assert_eq!(
+                "sha-ni",
+                super::select_sha256_backend(target_arch, false, true)
+            );
+            assert_eq!(
+                "sha-ni",
+                super::select_sha256_backend(target_arch, true, true)
+            );
+            assert_eq!(
+                "asm",
+                super::select_sha256_backend(target_arch, true, false)
+            );
+            assert_eq!(
+                "software",
+                super::select_sha256_backend(target_arch, false, false)
+            );
+        }
+
+        assert_eq!(
+            "armv8-sha2",
+            super::select_sha256_backend("aarch64", true, true)
+        );
+        assert_eq!(
+            "software",
+            super::select_sha256_backend("aarch64", false, true)
+        );
+        assert_eq!(
+            "software",
+            super::select_sha256_backend("aarch64", true, false)
+        );
+
+        assert_eq!(
+            "software",
+            super::select_sha256_backend("riscv64", true, true)
+        );
+
+        assert_eq!(
+            super::select_sha256_backend(
+                std::env::consts::ARCH,
+                cfg!(feature = "asm"),
+                super::sha256_instructions_detected()
+            ),
+            super::sha256_backend()
+        );
+    }
+
+    #[test]
+    fn test_content_sha256_test_vectors() {
+        // FIPS 180-2 test vectors, hashed by whichever backend `sha256_backend` reports.
+        assert_eq!(
+            Sha256::try_from("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+                .expect("parse sha256 hex"),
+            ContentSha256::identify_content("abc".as_bytes()).expect("identify content")
+        );
+
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        std::fs::write(
+            temporary_directory.path().join("file"),
+            vec![b'a'; 1_000_000],
+        )
+        .expect("write file");
+        let mut filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        assert_eq!(
+            Sha256::try_from("cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0")
+                .expect("parse sha256 hex"),
+            ContentSha256::identify_file(&mut filesystem, "file").expect("identify file")
+        );
+    }
+
+    #[test]
+    fn test_content_blake2b256() {
+        let expected = Blake2b256::try_from(
+            "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319",
+        )
+        .expect("parse blake2b-256 hex");
+        assert_eq!(
+            expected,
+            ContentBlake2b256::identify_content("abc".as_bytes()).expect("identify content")
+        );
+
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        std::fs::write(temporary_directory.path().join("file"), "abc".as_bytes())
+            .expect("write file");
+        let mut filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        assert_eq!(
+            expected,
+            ContentBlake2b256::identify_file(&mut filesystem, "file").expect("identify file")
+        );
+
+        let serialized = serde_json::to_string(&expected).expect("serialize identity");
+        assert_eq!(
+            expected,
+            serde_json::from_str::<Blake2b256>(&serialized).expect("deserialize identity")
+        );
+        let multihash_string = expected.to_multihash().encode(Multibase::Base32);
+        assert_eq!(
+            expected,
+            serde_json::from_value::<Blake2b256>(serde_json::Value::String(multihash_string))
+                .expect("deserialize multihash identity")
+        );
+
+        let identity_scheme: IdentitySchemeEnum =
+            "content_blake2b256".parse().expect("parse identity scheme");
+        assert_eq!(IdentitySchemeEnum::ContentBlake2b256, identity_scheme);
+        assert_eq!(
+            expected.to_string(),
+            IdentitySchemeRegistry::default()
+                .identify_content(identity_scheme, "abc".as_bytes())
+                .expect("identify content via registry")
+        );
+    }
 }
@@ -0,0 +1,214 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Delimiters of an `#include` directive, which determine where the included file is searched for.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum IncludeKind {
+    /// `#include "path"`: searched for relative to the including file, then in quote and include
+    /// directories.
+    Quote,
+    /// `#include <path>`: searched for in include directories only.
+    Angle,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Include {
+    pub kind: IncludeKind,
+    pub path: PathBuf,
+}
+
+/// Finds the `#include`, `#include_next`, and `#import` directives in C/C++ source `contents`.
+///
+/// Scanning is conservative: directives in both branches of conditional-compilation blocks are
+/// reported, except for blocks that can never be compiled (`#if 0`). Comments are stripped, and
+/// computed includes (`#include MACRO`) are ignored.
+pub fn scan_includes(contents: &str) -> Vec<Include> {
+    let mut includes = vec![];
+    let mut in_block_comment = false;
+    // One entry per enclosing conditional block: whether the current branch is compiled out.
+    let mut disabled_stack: Vec<bool> = vec![];
+    for line in contents.lines() {
+        let line = strip_comments(line, &mut in_block_comment);
+        let directive = match line.trim_start().strip_prefix('#') {
+            Some(directive) => directive.trim_start(),
+            None => continue,
+        };
+        let (keyword, rest) = split_keyword(directive);
+        let disabled = disabled_stack.iter().any(|disabled| *disabled);
+        match keyword {
+            "if" => disabled_stack.push(rest.trim() == "0"),
+            "ifdef" | "ifndef" => disabled_stack.push(false),
+            "elif" | "else" => {
+                // The branch after `#if 0` may be compiled; other branches are scanned anyway.
+                if let Some(last) = disabled_stack.last_mut() {
+                    *last = false;
+                }
+            }
+            "endif" => {
+                disabled_stack.pop();
+            }
+            "include" | "include_next" | "import" if !disabled => {
+                if let Some(include) = parse_include_operand(rest) {
+                    includes.push(include);
+                }
+            }
+            _ => {}
+        }
+    }
+    includes
+}
+
+fn split_keyword(directive: &str) -> (&str, &str) {
+    let end = directive
+        .find(|character: char| !(character.is_ascii_alphanumeric() || character == '_'))
+        .unwrap_or(directive.len());
+    (&directive[..end], &directive[end..])
+}
+
+fn parse_include_operand(operand: &str) -> Option<Include> {
+    let operand = operand.trim();
+    let (kind, closing) = match operand.chars().next()? {
+        '"' => (IncludeKind::Quote, '"'),
+        '<' => (IncludeKind::Angle, '>'),
+        _ => return None,
+    };
+    let end = operand[1..].find(closing)?;
+    let path = &operand[1..1 + end];
+    if path.is_empty() {
+        return None;
+    }
+    Some(Include {
+        kind,
+        path: PathBuf::from(path),
+    })
+}
+
+/// Lexically removes `.` components and resolves `..` components in `path`, so that a header
+/// reached through different include directories is recorded under one path. Leading `..`
+/// components are preserved.
+pub fn normalize_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut components: Vec<Component> = vec![];
+    for component in path.as_ref().components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match components.last() {
+                Some(Component::Normal(_)) => {
+                    components.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => components.push(component),
+            },
+            component => components.push(component),
+        }
+    }
+    components.into_iter().collect()
+}
+
+/// Removes `//` and `/* */` comments from `line`, tracking block comments that span lines.
+fn strip_comments(line: &str, in_block_comment: &mut bool) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut characters = line.chars().peekable();
+    let mut in_string = None;
+    while let Some(character) = characters.next() {
+        if *in_block_comment {
+            if character == '*' && characters.peek() == Some(&'/') {
+                characters.next();
+                *in_block_comment = false;
+            }
+            continue;
+        }
+        if let Some(quote) = in_string {
+            stripped.push(character);
+            if character == quote {
+                in_string = None;
+            }
+            continue;
+        }
+        match character {
+            '/' if characters.peek() == Some(&'/') => break,
+            '/' if characters.peek() == Some(&'*') => {
+                characters.next();
+                *in_block_comment = true;
+            }
+            '"' => {
+                in_string = Some('"');
+                stripped.push(character);
+            }
+            '<' if stripped.trim_start().starts_with('#') => {
+                in_string = Some('>');
+                stripped.push(character);
+            }
+            _ => stripped.push(character),
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_path;
+    use super::scan_includes;
+    use super::Include;
+    use super::IncludeKind;
+    use std::path::PathBuf;
+
+    fn include(kind: IncludeKind, path: &str) -> Include {
+        Include {
+            kind,
+            path: PathBuf::from(path),
+        }
+    }
+
+    #[test]
+    fn test_scan_includes() {
+        let contents = r#"
+#include "local.h"
+  #  include <system/header.h> // trailing comment
+#include_next <next.h>
+#import "objc.h"
+#include MACRO_HEADER
+// #include "commented.h"
+/* #include "block_commented.h"
+#include "still_commented.h" */
+#include "path//with/slashes.h"
+#if 0
+#include "never.h"
+#if FEATURE
+#include "nested_never.h"
+#endif
+#else
+#include "else_branch.h"
+#endif
+#ifdef FEATURE
+#include "feature.h"
+#else
+#include "no_feature.h"
+#endif
+"#;
+        assert_eq!(
+            vec![
+                include(IncludeKind::Quote, "local.h"),
+                include(IncludeKind::Angle, "system/header.h"),
+                include(IncludeKind::Angle, "next.h"),
+                include(IncludeKind::Quote, "objc.h"),
+                include(IncludeKind::Quote, "path//with/slashes.h"),
+                include(IncludeKind::Quote, "else_branch.h"),
+                include(IncludeKind::Quote, "feature.h"),
+                include(IncludeKind::Quote, "no_feature.h"),
+            ],
+            scan_includes(contents)
+        );
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(PathBuf::from("a/c.h"), normalize_path("a/./b/../c.h"));
+        assert_eq!(PathBuf::from("../c.h"), normalize_path("a/../../c.h"));
+        assert_eq!(PathBuf::from("/c.h"), normalize_path("/../c.h"));
+    }
+}
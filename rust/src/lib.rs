@@ -4,12 +4,52 @@
 
 pub mod args;
 pub mod blob;
+pub mod build_graph;
 pub mod cache;
 pub mod canonical;
+pub mod canonical_json;
+pub mod chunk;
+pub mod ci;
 pub mod context;
+pub mod daemon;
+pub mod depfile;
+pub mod diff;
 pub mod error;
+pub mod events;
 pub mod execute;
+pub mod facade;
+pub mod fingerprint;
 pub mod fs;
+pub mod grpc;
 pub mod identity;
+pub mod include_scanner;
+pub mod limits;
+pub mod metrics;
+pub mod multihash;
+pub mod ndjson;
+pub mod ninja;
+pub mod package;
+pub mod plugin;
+pub mod profile;
+pub mod reapi;
+pub mod remote;
+pub mod remote_api;
+pub mod remote_client;
+pub mod repro;
 pub mod runner;
+pub mod scheduler;
+pub mod schema;
+pub mod serve;
+pub mod stream;
+pub mod sync;
+pub mod task_file;
+pub mod task_graph;
+pub mod template;
+pub mod trace;
 pub mod transport;
+pub mod watch;
+
+pub use canonical::Outputs;
+pub use canonical::TaskInputs;
+pub use canonical::TaskInputsBuilder;
+pub use facade::ArtifactExecutor;
@@ -0,0 +1,298 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Limits on the size of descriptions and stored data that the executor accepts.
+//!
+//! Task files and cache contents may come from untrusted sources. Limits are checked after
+//! deserialization and before any description is acted upon, so that a malformed or malicious
+//! description fails with an error rather than exhausting memory or time.
+
+use crate::transport::ExecutionStrategy;
+use crate::transport::Inputs;
+use crate::transport::Outputs;
+use crate::transport::Task;
+
+/// Default for `Limits::max_manifest_entries`.
+pub const DEFAULT_MAX_MANIFEST_ENTRIES: usize = 1 << 20;
+
+/// Default for `Limits::max_regular_expression_length`.
+pub const DEFAULT_MAX_REGULAR_EXPRESSION_LENGTH: usize = 4096;
+
+/// Default for `Limits::max_reference_depth`.
+pub const DEFAULT_MAX_REFERENCE_DEPTH_LIMIT: usize = 1024;
+
+/// Default for `Limits::max_nesting_depth`.
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 16;
+
+/// Default for `Limits::max_blob_size`.
+pub const DEFAULT_MAX_BLOB_SIZE: u64 = 1 << 30;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Limits {
+    /// Maximum number of entries in a manifest, such as the files, globs, or patterns of an inputs
+    /// or outputs description, or the identities of a file identities manifest.
+    pub max_manifest_entries: usize,
+    /// Maximum length, in bytes, of a regular expression.
+    pub max_regular_expression_length: usize,
+    /// Maximum value of `Inputs::max_reference_depth`.
+    pub max_reference_depth: usize,
+    /// Maximum depth of inputs descriptions nested in inter-file references or execution
+    /// strategies.
+    pub max_nesting_depth: usize,
+    /// Maximum size, in bytes, of a task file or of a structured blob read from a cache.
+    pub max_blob_size: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_manifest_entries: DEFAULT_MAX_MANIFEST_ENTRIES,
+            max_regular_expression_length: DEFAULT_MAX_REGULAR_EXPRESSION_LENGTH,
+            max_reference_depth: DEFAULT_MAX_REFERENCE_DEPTH_LIMIT,
+            max_nesting_depth: DEFAULT_MAX_NESTING_DEPTH,
+            max_blob_size: DEFAULT_MAX_BLOB_SIZE,
+        }
+    }
+}
+
+impl Limits {
+    /// Checks every limit that applies to `task`.
+    pub fn check_task(&self, task: &Task) -> anyhow::Result<()> {
+        self.check_manifest_entries(
+            "environment variables",
+            task.environment_variables.environment_variables.len(),
+        )?;
+        self.check_manifest_entries("arguments", task.arguments.arguments.len())?;
+        self.check_manifest_entries("tags", task.tags.len())?;
+        if let ExecutionStrategy::ForEachInput { inputs_filter } = &task.execution_strategy {
+            self.check_inputs(inputs_filter, 1)?;
+        }
+        self.check_inputs(&task.inputs, 0)?;
+        self.check_outputs(&task.outputs)
+    }
+
+    pub fn check_manifest_entries(&self, description: &str, entries: usize) -> anyhow::Result<()> {
+        if entries > self.max_manifest_entries {
+            anyhow::bail!(
+                "{} has {} entries, exceeding limit of {}",
+                description,
+                entries,
+                self.max_manifest_entries
+            );
+        }
+        Ok(())
+    }
+
+    pub fn check_regular_expression(&self, regular_expression: &str) -> anyhow::Result<()> {
+        if regular_expression.len() > self.max_regular_expression_length {
+            anyhow::bail!(
+                "regular expression of length {} exceeds limit of {}: {:?}...",
+                regular_expression.len(),
+                self.max_regular_expression_length,
+                regular_expression.chars().take(32).collect::<String>()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn check_blob_size(&self, description: &str, size: u64) -> anyhow::Result<()> {
+        if size > self.max_blob_size {
+            anyhow::bail!(
+                "{} of size {} bytes exceeds limit of {} bytes",
+                description,
+                size,
+                self.max_blob_size
+            );
+        }
+        Ok(())
+    }
+
+    fn check_inputs(&self, inputs: &Inputs, depth: usize) -> anyhow::Result<()> {
+        if depth > self.max_nesting_depth {
+            anyhow::bail!(
+                "inputs descriptions nested more than {} deep",
+                self.max_nesting_depth
+            );
+        }
+        self.check_manifest_entries(
+            "inputs",
+            inputs.include_files.len()
+                + inputs.exclude_files.len()
+                + inputs.include_globs.len()
+                + inputs.exclude_globs.len()
+                + inputs.depfiles.len()
+                + inputs.groups.len()
+                + inputs.include_directories.len()
+                + inputs.inter_file_references.len()
+                + inputs.expected_identities.len(),
+        )?;
+        if let Some(max_reference_depth) = inputs.max_reference_depth {
+            if max_reference_depth > self.max_reference_depth {
+                anyhow::bail!(
+                    "inputs max_reference_depth of {} exceeds limit of {}",
+                    max_reference_depth,
+                    self.max_reference_depth
+                );
+            }
+        }
+        for group in inputs.groups.iter() {
+            self.check_manifest_entries(
+                "inputs group",
+                group.include_files.len()
+                    + group.exclude_files.len()
+                    + group.include_globs.len()
+                    + group.exclude_globs.len(),
+            )?;
+        }
+        for directory in inputs.include_directories.iter() {
+            self.check_manifest_entries(
+                "inputs directory",
+                directory.include_globs.len() + directory.exclude_globs.len(),
+            )?;
+        }
+        for references in inputs.inter_file_references.iter() {
+            self.check_manifest_entries(
+                "inter-file references match transforms",
+                references.match_transforms.len(),
+            )?;
+            for match_transform in references.match_transforms.iter() {
+                self.check_regular_expression(&match_transform.match_regular_expression)?;
+                self.check_manifest_entries(
+                    "match transform expressions",
+                    match_transform.match_transform_expressions.len(),
+                )?;
+            }
+            if let Some(files_to_match) = references.files_to_match.as_ref() {
+                self.check_inputs(files_to_match, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_outputs(&self, outputs: &Outputs) -> anyhow::Result<()> {
+        self.check_manifest_entries(
+            "outputs",
+            outputs.include_files.len()
+                + outputs.optional_files.len()
+                + outputs.include_match_transforms.len()
+                + outputs.include_globs.len()
+                + outputs.include_directories.len()
+                + outputs.exclude_matches.len(),
+        )?;
+        for directory in outputs.include_directories.iter() {
+            self.check_manifest_entries("outputs directory", directory.exclude_globs.len())?;
+        }
+        for match_transforms in outputs.include_match_transforms.iter() {
+            self.check_manifest_entries("outputs match transforms", match_transforms.len())?;
+            for match_transform in match_transforms.iter() {
+                self.check_regular_expression(&match_transform.match_regular_expression)?;
+            }
+        }
+        for exclude_match in outputs.exclude_matches.iter() {
+            self.check_regular_expression(&exclude_match.match_regular_expression)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Limits;
+    use crate::task_file::TaskFileFormat;
+
+    const TASK: &str = r#"{
+  "environment_variables": [],
+  "program": "/usr/bin/cc",
+  "arguments": ["-c", "main.c"],
+  "inputs": {
+    "include_files": ["main.c"],
+    "exclude_files": [],
+    "include_globs": [],
+    "exclude_globs": [],
+    "inter_file_references": [
+      {
+        "match_transforms": [
+          {
+            "match_regular_expression": "^#include \"(.*)\"$",
+            "match_transform_expressions": ["$1"]
+          }
+        ]
+      }
+    ]
+  },
+  "outputs": {
+    "include_files": ["main.o"],
+    "include_match_transforms": [],
+    "exclude_matches": []
+  }
+}"#;
+
+    #[test]
+    fn test_limits() {
+        let format = TaskFileFormat::Json;
+        format
+            .parse_task_with_limits(TASK, &Limits::default())
+            .expect("task within default limits");
+
+        for (limits, expected_message) in [
+            (
+                Limits {
+                    max_manifest_entries: 1,
+                    ..Limits::default()
+                },
+                "entries",
+            ),
+            (
+                Limits {
+                    max_regular_expression_length: 4,
+                    ..Limits::default()
+                },
+                "regular expression",
+            ),
+            (
+                Limits {
+                    max_blob_size: 16,
+                    ..Limits::default()
+                },
+                "bytes",
+            ),
+        ] {
+            let error = format
+                .parse_task_with_limits(TASK, &limits)
+                .expect_err("task exceeding limits");
+            assert!(
+                format!("{:#}", error).contains(expected_message),
+                "{:#}",
+                error
+            );
+        }
+
+        let deep_task = TASK.replace(
+            r#""inputs": {"#,
+            r#""inputs": {"max_reference_depth": 1000000,"#,
+        );
+        assert!(format
+            .parse_task_with_limits(&deep_task, &Limits::default())
+            .is_err());
+
+        let inputs = |inter_file_references: &str| {
+            format!(
+                r#"{{"include_files": [], "exclude_files": [], "include_globs": [], "exclude_globs": [], "inter_file_references": [{}]}}"#,
+                inter_file_references
+            )
+        };
+        let mut nested_inputs = inputs("");
+        for _ in 0..20 {
+            nested_inputs = inputs(&format!(r#"{{"files_to_match": {}}}"#, nested_inputs));
+        }
+        let nested_task = format!(
+            r#"{{"environment_variables": [], "program": "p", "arguments": [], "inputs": {}, "outputs": {{"include_files": [], "include_match_transforms": [], "exclude_matches": []}}}}"#,
+            nested_inputs
+        );
+        let error = format
+            .parse_task_with_limits(&nested_task, &Limits::default())
+            .expect_err("deeply nested task");
+        assert!(format!("{:#}", error).contains("nested"), "{:#}", error);
+    }
+}
@@ -2,9 +2,70 @@
 // Use of this source code is governed by a Apache-style license that can be
 // found in the LICENSE file.
 
+use artifact_executor::args::Command;
+use artifact_executor::args::Execute;
+use artifact_executor::args::Query;
+use artifact_executor::blob::JSON;
+use artifact_executor::cache::rekey;
+use artifact_executor::cache::Cache;
+use artifact_executor::cache::RekeyOptions;
+use artifact_executor::cache::WriteOnDropIndex;
+use artifact_executor::canonical::TaskLabels;
+use artifact_executor::daemon::socket_path;
+use artifact_executor::daemon::Daemon;
+use artifact_executor::daemon::DaemonClient;
+use artifact_executor::events;
+use artifact_executor::events::TracingSubscriber;
+use artifact_executor::execute::ExecuteQuery;
+use artifact_executor::facade::ArtifactExecutor;
+use artifact_executor::fs::HostFilesystem;
+use artifact_executor::grpc::GrpcServer;
+use artifact_executor::identity::AsTransport as _;
+use artifact_executor::identity::IdentityScheme as IdentitySchemeApi;
+use artifact_executor::identity::IdentitySchemeRegistry;
+use artifact_executor::metrics;
+use artifact_executor::metrics::Metrics;
+use artifact_executor::ninja::import_ninja_file;
+use artifact_executor::package::export_package;
+use artifact_executor::package::package_name;
+use artifact_executor::profile::TraceWriter;
+use artifact_executor::remote_api::RemoteApiServer;
+use artifact_executor::serve::CacheServer;
+use artifact_executor::serve::ServeOptions;
+use artifact_executor::sync::sync_caches;
+use artifact_executor::sync::sync_caches_bidirectional;
+use artifact_executor::sync::CacheDirectory;
+use artifact_executor::sync::SyncOptions;
+use artifact_executor::sync::SyncReport;
+use artifact_executor::task_file::read_task_file;
+use artifact_executor::task_file::task_filesystem;
+use artifact_executor::task_file::TaskFileFormat;
+use artifact_executor::transport::ContentBlake2b256;
+use artifact_executor::transport::ContentSha256;
+use artifact_executor::transport::DaemonRequest;
+use artifact_executor::transport::IdentityScheme;
+use artifact_executor::transport::Sha256;
+use artifact_executor::watch::WatchOptions;
+use artifact_executor::watch::Watcher;
+use serde::de::value::StrDeserializer;
+use serde::Deserialize as _;
+use std::collections::BTreeMap;
+use std::net::TcpListener;
+use std::ops::ControlFlow;
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 use tracing::info;
 
+type DefaultCache = Cache<
+    HostFilesystem,
+    ContentSha256,
+    JSON,
+    WriteOnDropIndex<HostFilesystem, ContentSha256, JSON>,
+>;
+
 fn main() -> anyhow::Result<()> {
     let args: artifact_executor::args::Args = argh::from_env();
 
@@ -22,6 +83,9 @@ fn main() -> anyhow::Result<()> {
     tracing::subscriber::set_global_default(subscriber)
         .map_err(anyhow::Error::from)
         .map_err(|err| err.context("unable to set tracing subscriber global default"))?;
+    if trace_level >= tracing::Level::DEBUG {
+        events::subscribe(Arc::new(TracingSubscriber));
+    }
 
     info!("Arguments parsed and logging initialized");
 
@@ -29,15 +93,394 @@ fn main() -> anyhow::Result<()> {
         .map_err(anyhow::Error::from)
         .map_err(|err| err.context("failed to determine current working directory"))?;
     info!("Working directory: {:?}", working_directory);
+    info!(
+        "SHA-256 backend: {}",
+        artifact_executor::identity::sha256_backend()
+    );
+
+    // Writes the profile when `main` returns, including when a command fails.
+    let _trace_writer = args
+        .profile
+        .as_ref()
+        .map(|path| TraceWriter::enable(working_directory.join(path)))
+        .transpose()?;
+
+    match args.command {
+        Command::Daemon(daemon_args) => {
+            let cache_directory = working_directory.join(&args.cache_directory);
+            let socket = working_directory
+                .join(socket_path(&cache_directory, daemon_args.socket.as_deref()));
+            let mut daemon = Daemon::<ContentSha256, JSON>::new(
+                HostFilesystem::try_new(working_directory.clone())?,
+                HostFilesystem::try_new(cache_directory)?,
+            )?;
+            if let Some(metrics_address) = daemon_args.metrics_address.as_ref() {
+                metrics::set_global(Metrics::new())?;
+                let metrics_listener = TcpListener::bind(metrics_address)
+                    .map_err(anyhow::Error::from)
+                    .map_err(|err| {
+                        err.context(format!("failed to listen on {}", metrics_address))
+                    })?;
+                info!("Serving metrics on {}", metrics_address);
+                metrics::spawn_exporter(
+                    metrics_listener,
+                    metrics::global().expect("metrics registry installed"),
+                );
+            }
+            let listener = UnixListener::bind(&socket)
+                .map_err(anyhow::Error::from)
+                .map_err(|err| err.context(format!("failed to listen on {:?}", socket)))?;
+            info!("Daemon listening on {:?}", socket);
+            let result = daemon.serve(listener);
+            std::fs::remove_file(&socket)
+                .map_err(anyhow::Error::from)
+                .map_err(|err| err.context(format!("failed to remove socket {:?}", socket)))?;
+            result?;
+        }
+        Command::DaemonClient(client) => {
+            let socket = working_directory.join(socket_path(
+                &working_directory.join(&args.cache_directory),
+                client.socket.as_deref(),
+            ));
+            let request = if client.shutdown {
+                DaemonRequest::Shutdown
+            } else if let Some(task_file) = client.identify_inputs {
+                DaemonRequest::IdentifyInputs { task_file }
+            } else if let Some(inputs_identity) = client.lookup_outputs {
+                DaemonRequest::LookupOutputs { inputs_identity }
+            } else if let Some(task_file) = client.execute_task {
+                DaemonRequest::ExecuteTask { task_file }
+            } else if !client.identify_file.is_empty() {
+                DaemonRequest::IdentifyFiles {
+                    paths: client.identify_file,
+                }
+            } else {
+                DaemonRequest::Ping
+            };
+            let response = DaemonClient::connect(&socket)?.request(&request)?;
+            println!("{}", serde_json::to_string(&response)?);
+        }
+        Command::Execute(command) => {
+            let cache_directory = args.cache_directory;
+            match registered_identity_scheme(command.identity_scheme)? {
+                IdentityScheme::ContentSha256 => {
+                    execute::<ContentSha256>(working_directory, cache_directory, command)?
+                }
+                IdentityScheme::ContentBlake2b256 => {
+                    execute::<ContentBlake2b256>(working_directory, cache_directory, command)?
+                }
+            }
+        }
+        Command::Gc(gc) => {
+            let filesystem =
+                HostFilesystem::try_new(working_directory.join(&args.cache_directory))?;
+            let mut cache = DefaultCache::open_existing(filesystem)
+                .map_err(|err| err.context("failed to open cache directory"))?;
+            let report =
+                cache.collect_garbage(gc.max_bytes, gc.max_age_secs.map(Duration::from_secs))?;
+            info!("Garbage collection report: {:?}", report);
+            println!(
+                "removed {} tasks and {} blobs ({} bytes); retained {} blobs ({} bytes)",
+                report.expired_tasks + report.evicted_tasks,
+                report.blobs_removed,
+                report.bytes_removed,
+                report.blobs_retained,
+                report.bytes_retained
+            );
+        }
+        Command::ImportNinja(import_ninja) => {
+            let mut filesystem = HostFilesystem::try_new(working_directory.clone())?;
+            let graph = import_ninja_file(&mut filesystem, &import_ninja.build_file)?;
+            match import_ninja.output.as_ref() {
+                Some(output) => {
+                    let contents = TaskFileFormat::from_path(output)?.format(&graph)?;
+                    std::fs::write(working_directory.join(output), contents)
+                        .map_err(anyhow::Error::from)
+                        .map_err(|err| err.context(format!("failed to write {:?}", output)))?;
+                }
+                None => print!("{}", TaskFileFormat::Json.format(&graph)?),
+            }
+        }
+        Command::ImportPackage(import_package) => {
+            let filesystem =
+                HostFilesystem::try_new(working_directory.join(&args.cache_directory))?;
+            let mut cache = DefaultCache::open_or_create(filesystem)
+                .map_err(|err| err.context("failed to open cache directory"))?;
+            let path = working_directory.join(&import_package.package);
+            let file = std::fs::File::open(&path)
+                .map_err(anyhow::Error::from)
+                .map_err(|err| err.context(format!("failed to open {:?}", path)))?;
+            let inputs_identity = cache
+                .import_package(file)
+                .map_err(|err| err.context(format!("failed to import {:?}", path)))?;
+            println!("{}", inputs_identity.to_string());
+        }
+        Command::Package(package) => {
+            let filesystem =
+                HostFilesystem::try_new(working_directory.join(&args.cache_directory))?;
+            let mut cache = DefaultCache::open_existing(filesystem)
+                .map_err(|err| err.context("failed to open cache directory"))?;
+            let inputs_identity = Sha256::deserialize(
+                StrDeserializer::<serde::de::value::Error>::new(&package.inputs_identity),
+            )
+            .map_err(|_| anyhow::anyhow!("malformed identity: {:?}", package.inputs_identity))?;
+            let output = working_directory.join(package.output.unwrap_or_else(|| {
+                PathBuf::from(format!(
+                    "{}.tar",
+                    package_name::<ContentSha256>(&inputs_identity)
+                ))
+            }));
+            let file = std::fs::File::create(&output)
+                .map_err(anyhow::Error::from)
+                .map_err(|err| err.context(format!("failed to create {:?}", output)))?;
+            export_package(&mut cache, &inputs_identity, file)?;
+            println!("{}", output.display());
+        }
+        Command::Query(query) => {
+            let filesystem =
+                HostFilesystem::try_new(working_directory.join(&args.cache_directory))?;
+            match registered_identity_scheme(query.identity_scheme)? {
+                IdentityScheme::ContentSha256 => query_tasks::<ContentSha256>(filesystem, query)?,
+                IdentityScheme::ContentBlake2b256 => {
+                    query_tasks::<ContentBlake2b256>(filesystem, query)?
+                }
+            }
+        }
+        Command::Rekey(rekey_args) => {
+            let source = HostFilesystem::try_new(working_directory.join(&args.cache_directory))?;
+            let destination_directory = working_directory.join(&rekey_args.destination);
+            std::fs::create_dir_all(&destination_directory)
+                .map_err(anyhow::Error::from)
+                .map_err(|err| {
+                    err.context(format!("failed to create {:?}", destination_directory))
+                })?;
+            let destination = HostFilesystem::try_new(destination_directory)?;
+            let options = RekeyOptions {
+                salt: None,
+                working_directory: rekey_args
+                    .inputs_directory
+                    .map(|inputs_directory| {
+                        HostFilesystem::try_new(working_directory.join(inputs_directory))
+                    })
+                    .transpose()?,
+            };
+            let report = match rekey_args.identity_scheme {
+                IdentityScheme::ContentSha256 => {
+                    anyhow::bail!("cache directory is already keyed by content_sha256")
+                }
+                IdentityScheme::ContentBlake2b256 => {
+                    rekey::<HostFilesystem, ContentSha256, ContentBlake2b256, JSON>(
+                        source,
+                        destination,
+                        options,
+                    )?
+                }
+            };
+            info!("Re-keying report: {:?}", report);
+            println!(
+                "re-keyed {} blobs and {} tasks; skipped {} tasks with unidentifiable files",
+                report.blobs, report.tasks, report.skipped_tasks
+            );
+        }
+        Command::RunPipeline(run_pipeline) => {
+            let mut executor = ArtifactExecutor::builder()
+                .working_directory(&working_directory)
+                .cache_directory(&args.cache_directory)
+                .build()?;
+            let outputs = executor.run_task_graph_file(&run_pipeline.pipeline)?;
+            let outputs: BTreeMap<_, _> = outputs
+                .iter()
+                .map(|(label, outputs)| (label, outputs.as_transport()))
+                .collect();
+            println!("{}", serde_json::to_string(&outputs)?);
+        }
+        Command::Serve(serve) => {
+            let token = match serve.token_file.as_ref() {
+                Some(token_file) => Some(
+                    std::fs::read_to_string(working_directory.join(token_file))
+                        .map_err(anyhow::Error::from)
+                        .map_err(|err| err.context("failed to read token file"))?
+                        .trim()
+                        .to_string(),
+                ),
+                None => None,
+            };
+            metrics::set_global(Metrics::new())?;
+            let filesystem =
+                HostFilesystem::try_new(working_directory.join(&args.cache_directory))?;
+            let server = CacheServer::<_, ContentSha256, JSON>::new(
+                filesystem,
+                ServeOptions {
+                    token,
+                    read_only: serve.read_only,
+                    ..ServeOptions::default()
+                },
+            )?;
+            let listener = TcpListener::bind(&serve.address)
+                .map_err(anyhow::Error::from)
+                .map_err(|err| err.context(format!("failed to listen on {}", serve.address)))?;
+            info!("Serving {:?} on {}", args.cache_directory, serve.address);
+            server.serve(listener)?;
+        }
+        Command::ServeRemoteApi(serve_remote_api) => {
+            let filesystem =
+                HostFilesystem::try_new(working_directory.join(&args.cache_directory))?;
+            let server = GrpcServer::new(RemoteApiServer::<_, JSON>::new(filesystem)?);
+            let listener = TcpListener::bind(&serve_remote_api.address)
+                .map_err(anyhow::Error::from)
+                .map_err(|err| {
+                    err.context(format!("failed to listen on {}", serve_remote_api.address))
+                })?;
+            info!(
+                "Serving {:?} over gRPC on {}",
+                args.cache_directory, serve_remote_api.address
+            );
+            server.serve(listener)?;
+        }
+        Command::Sync(sync) => {
+            let mut source = CacheDirectory::<_, ContentSha256, JSON>::open(
+                HostFilesystem::try_new(working_directory.join(&args.cache_directory))?,
+            )?;
+            let mut destination = CacheDirectory::<_, ContentSha256, JSON>::open(
+                HostFilesystem::try_new(working_directory.join(&sync.destination))?,
+            )?;
+            let options = SyncOptions {
+                batch_size: sync.batch_size,
+            };
+            let print_report = |direction: &str, report: &SyncReport| {
+                println!(
+                    "{}: {} blobs ({} bytes) and {} pointers copied; {} blobs and {} pointers already present; {} conflicting and {} dangling pointers skipped",
+                    direction,
+                    report.blobs_transferred,
+                    report.bytes_transferred,
+                    report.pointers_transferred,
+                    report.blobs_present,
+                    report.pointers_present,
+                    report.pointer_conflicts,
+                    report.dangling_pointers
+                );
+            };
+            if sync.bidirectional {
+                let (to_destination, from_destination) =
+                    sync_caches_bidirectional(&mut source, &mut destination, &options)?;
+                print_report("to destination", &to_destination);
+                print_report("from destination", &from_destination);
+            } else {
+                print_report(
+                    "to destination",
+                    &sync_caches(&mut source, &mut destination, &options)?,
+                );
+            }
+        }
+        Command::Verify(verify) => {
+            let filesystem =
+                HostFilesystem::try_new(working_directory.join(&args.cache_directory))?;
+            let mut cache = DefaultCache::open_existing(filesystem)
+                .map_err(|err| err.context("failed to open cache directory"))?;
+            let report = cache.verify(verify.repair)?;
+            info!("Verification report: {:?}", report);
+            for path in report
+                .corrupt_blobs
+                .iter()
+                .chain(report.broken_pointers.iter())
+            {
+                println!("{}", path.display());
+            }
+            println!(
+                "verified {} blobs and {} pointers; {} corrupt blobs and {} broken pointers{}",
+                report.blobs_verified,
+                report.pointers_verified,
+                report.corrupt_blobs.len(),
+                report.broken_pointers.len(),
+                if report.repaired { " removed" } else { "" }
+            );
+            if !report.is_intact() && !report.repaired {
+                anyhow::bail!(
+                    "cache directory is damaged; rerun with --repair to remove damaged entries"
+                );
+            }
+        }
+        Command::Watch(watch) => {
+            let mut filesystem = HostFilesystem::try_new(working_directory.clone())?;
+            let task = read_task_file(&mut filesystem, &watch.task)?;
+            let task_filesystem = task_filesystem(&mut filesystem, &watch.task, &task)?;
+            let options = WatchOptions {
+                poll_interval: Duration::from_millis(watch.poll_interval_ms),
+                debounce: Duration::from_millis(watch.debounce_ms),
+            };
+            let mut executor = ArtifactExecutor::builder()
+                .working_directory(&working_directory)
+                .cache_directory(&args.cache_directory)
+                .build()?;
+            Watcher::<_, ContentSha256>::new(task_filesystem, task.inputs, options).run(
+                |event| {
+                    info!("Inputs changed: {} files", event.changed_paths.len());
+                    for path in event.changed_paths.iter() {
+                        info!("Changed input: {}", path.display());
+                    }
+                    match executor.run_task_file(&watch.task) {
+                        Ok(outputs) => {
+                            println!("{}", serde_json::to_string(&outputs.as_transport())?)
+                        }
+                        // Keep watching, so that fixing the inputs runs the task again.
+                        Err(error) => eprintln!("task failed: {:#}", anyhow::Error::from(error)),
+                    }
+                    Ok(ControlFlow::Continue(()))
+                },
+            )?;
+        }
+    };
+
+    Ok(())
+}
+
+/// Looks `identity_scheme` up among the identity schemes that this binary implements, so that
+/// commands name the scheme that they cannot use.
+fn registered_identity_scheme(identity_scheme: IdentityScheme) -> anyhow::Result<IdentityScheme> {
+    let registry = IdentitySchemeRegistry::default();
+    let identity_scheme = registry.try_get(identity_scheme)?.identity_scheme();
+    info!("Identity scheme: {}", identity_scheme);
+    Ok(identity_scheme)
+}
 
-    // match args.command {
-    //     artifact_executor::args::Command::Execute(command) => {
-    //         let _execute = artifact_executor::execute::ExecuteQuery::from_command(
-    //             args.cache_directory,
-    //             command,
-    //         )?;
-    //     }
-    // };
+fn execute<IS: IdentitySchemeApi>(
+    working_directory: PathBuf,
+    cache_directory: PathBuf,
+    command: Execute,
+) -> anyhow::Result<()> {
+    let dry_run = command.dry_run;
+    let execute = ExecuteQuery::<IS>::from_command(working_directory, cache_directory, command)?;
+    if dry_run {
+        let report = execute.query()?;
+        let report = serde_json::json!({
+            "inputs_identity": report.inputs_identity.to_string(),
+            "status": report.status,
+            "output_paths": report.output_paths,
+            "cached_duration_nanos": report.cached_duration.map(|duration| duration.as_nanos()),
+        });
+        println!("{}", report);
+        return Ok(());
+    }
+    let outputs = execute.run()?;
+    println!("{}", serde_json::to_string(&outputs.as_transport())?);
+    Ok(())
+}
 
+fn query_tasks<IS: IdentitySchemeApi>(
+    filesystem: HostFilesystem,
+    query: Query,
+) -> anyhow::Result<()> {
+    let mut cache = Cache::<_, IS, JSON, WriteOnDropIndex<_, IS, JSON>>::open_existing(filesystem)
+        .map_err(|err| err.context("failed to open cache directory"))?;
+    let labels = TaskLabels::new(query.label, query.tag);
+    for (identity, metadata) in cache.find_tasks(&labels)? {
+        let labels = metadata.labels();
+        println!(
+            "{}\t{}\t{}",
+            identity.to_string(),
+            labels.label().unwrap_or(""),
+            labels.tags().cloned().collect::<Vec<_>>().join(",")
+        );
+    }
     Ok(())
 }
@@ -0,0 +1,425 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Optional metrics and tracing for monitoring shared caches and executors.
+//!
+//! Metrics are recorded from `crate::events` only once a `Metrics` registry is installed with
+//! `set_global`, which subscribes it to the global event bus. Installed metrics are rendered in the Prometheus text
+//! exposition format by `Metrics::render_prometheus`, and served at `/metrics` by `crate::serve`,
+//! or, for long-running modes without an HTTP server of their own (e.g., `crate::daemon`), by an
+//! exporter started with `spawn_exporter`.
+//!
+//! Each executed task is also wrapped in a `tracing` span (see `task_span`) whose fields follow
+//! OpenTelemetry conventions (`otel.name`, `otel.kind`, `otel.status_code`), so that a subscriber
+//! bridging `tracing` to OpenTelemetry exports one span per task.
+
+use crate::events;
+use crate::events::Event;
+use crate::events::Subscriber;
+use crate::serve::refuse_connection;
+use crate::serve::set_timeouts;
+use crate::serve::DEFAULT_MAX_CONNECTIONS;
+use crate::serve::DEFAULT_TIMEOUT;
+use anyhow::Context as _;
+use std::fmt::Write as _;
+use std::io::BufReader;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Prefix of every exported metric name.
+pub const METRIC_NAME_PREFIX: &str = "artifact_executor";
+
+/// Upper bounds, in seconds, of the buckets of duration histograms.
+pub const DEFAULT_DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 30.0, 120.0,
+];
+
+static GLOBAL_METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Installs `metrics` as the registry to which published events are recorded. Fails if a registry
+/// is already installed.
+pub fn set_global(metrics: Metrics) -> anyhow::Result<()> {
+    GLOBAL_METRICS
+        .set(metrics)
+        .map_err(|_| anyhow::anyhow!("global metrics registry already installed"))?;
+    events::subscribe(Arc::new(GlobalMetricsSubscriber));
+    Ok(())
+}
+
+/// Records events to the installed registry.
+struct GlobalMetricsSubscriber;
+
+impl Subscriber for GlobalMetricsSubscriber {
+    fn on_event(&self, event: &Event<'_>) {
+        if let Some(metrics) = global() {
+            metrics.record_event(event);
+        }
+    }
+}
+
+/// Gets the installed registry, if any.
+pub fn global() -> Option<&'static Metrics> {
+    GLOBAL_METRICS.get()
+}
+
+/// Serves `metrics` at `GET /metrics` to connections accepted by `listener`, on a background thread
+/// that runs until accepting fails. As in `crate::serve`, each connection is served on its own
+/// thread, and is closed when it waits longer than `crate::serve::DEFAULT_TIMEOUT`; connections
+/// beyond `crate::serve::DEFAULT_MAX_CONNECTIONS` are refused. Failures to serve an individual
+/// connection are logged.
+pub fn spawn_exporter(listener: TcpListener, metrics: &'static Metrics) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let connections = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    tracing::warn!("metrics exporter stopped accepting connections: {}", error);
+                    return;
+                }
+            };
+            if connections.load(Ordering::SeqCst) >= DEFAULT_MAX_CONNECTIONS {
+                if let Err(error) = refuse_connection(stream, DEFAULT_TIMEOUT) {
+                    tracing::warn!("failed to refuse metrics connection: {:#}", error);
+                }
+                continue;
+            }
+            let connections = connections.clone();
+            connections.fetch_add(1, Ordering::SeqCst);
+            std::thread::spawn(move || {
+                if let Err(error) = serve_metrics_connection(stream, metrics) {
+                    tracing::warn!("failed to serve metrics: {:#}", error);
+                }
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    })
+}
+
+fn serve_metrics_connection(stream: TcpStream, metrics: &'static Metrics) -> anyhow::Result<()> {
+    set_timeouts(&stream, DEFAULT_TIMEOUT)?;
+    let reader = BufReader::new(
+        stream
+            .try_clone()
+            .context("cloning connection for reading")?,
+    );
+    crate::serve::handle_metrics_request(Some(metrics), reader, stream)
+}
+
+/// Creates the span that wraps executing the task identified by `inputs_identity`. Callers record
+/// `otel.status_code` when the task finishes.
+pub fn task_span(inputs_identity: &str) -> tracing::Span {
+    tracing::info_span!(
+        "task",
+        otel.name = "execute task",
+        otel.kind = "internal",
+        otel.status_code = tracing::field::Empty,
+        task.inputs_identity = inputs_identity,
+    )
+}
+
+/// A monotonically increasing count.
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+struct HistogramState {
+    /// Count of observations in each bucket (not cumulative); the last bucket is `+Inf`.
+    bucket_counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// A distribution of observations over fixed buckets.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+    state: Mutex<HistogramState>,
+}
+
+impl Histogram {
+    /// Creates a histogram whose buckets have the upper bounds `bounds`, which must be ascending.
+    /// A final `+Inf` bucket is implied.
+    pub fn new(bounds: &[f64]) -> Self {
+        Self {
+            bounds: bounds.to_vec(),
+            state: Mutex::new(HistogramState {
+                bucket_counts: vec![0; bounds.len() + 1],
+                sum: 0.0,
+                count: 0,
+            }),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        let mut state = self.state.lock().expect("histogram lock");
+        state.bucket_counts[bucket] += 1;
+        state.sum += value;
+        state.count += 1;
+    }
+
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_secs_f64());
+    }
+
+    /// Number of observations.
+    pub fn count(&self) -> u64 {
+        self.state.lock().expect("histogram lock").count
+    }
+
+    /// Sum of observations.
+    pub fn sum(&self) -> f64 {
+        self.state.lock().expect("histogram lock").sum
+    }
+
+    fn render(&self, name: &str, output: &mut String) {
+        let state = self.state.lock().expect("histogram lock");
+        let mut cumulative_count = 0;
+        for (bound, count) in self.bounds.iter().zip(state.bucket_counts.iter()) {
+            cumulative_count += count;
+            writeln!(
+                output,
+                "{}_bucket{{le=\"{}\"}} {}",
+                name, bound, cumulative_count
+            )
+            .expect("write to string");
+        }
+        writeln!(output, "{}_bucket{{le=\"+Inf\"}} {}", name, state.count)
+            .expect("write to string");
+        writeln!(output, "{}_sum {}", name, state.sum).expect("write to string");
+        writeln!(output, "{}_count {}", name, state.count).expect("write to string");
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new(&DEFAULT_DURATION_BUCKETS)
+    }
+}
+
+/// Registry of the metrics recorded by the crate.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Tasks whose outputs were loaded from a cache.
+    pub cache_hits: Counter,
+    /// Tasks that were executed because their outputs were not cached.
+    pub cache_misses: Counter,
+    /// Bytes of file content identified.
+    pub hashed_bytes: Counter,
+    /// Bytes of blobs written to cache directories, as stored.
+    pub blob_bytes_written: Counter,
+    /// Time spent identifying file content, in seconds.
+    pub hash_duration: Histogram,
+    /// Time spent executing tasks, in seconds.
+    pub execution_duration: Histogram,
+}
+
+/// Embedders that keep their own registry can subscribe it to `crate::events` directly.
+impl Subscriber for Metrics {
+    fn on_event(&self, event: &Event<'_>) {
+        self.record_event(event);
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records identifying `bytes` bytes of file content in `duration`.
+    pub fn record_hash(&self, bytes: u64, duration: Duration) {
+        self.hashed_bytes.add(bytes);
+        self.hash_duration.observe_duration(duration);
+    }
+
+    /// Records the metrics that `event` contributes to.
+    pub fn record_event(&self, event: &Event<'_>) {
+        match event {
+            Event::FileHashed {
+                bytes, duration, ..
+            } => self.record_hash(*bytes, *duration),
+            Event::BlobWritten { bytes, .. } => self.blob_bytes_written.add(*bytes),
+            Event::CacheLookup { hit: true, .. } => self.cache_hits.increment(),
+            Event::CacheLookup { hit: false, .. } => self.cache_misses.increment(),
+            Event::TaskExecuted { duration, .. } => {
+                self.execution_duration.observe_duration(*duration)
+            }
+            _ => {}
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render_prometheus(&self) -> String {
+        let mut output = String::new();
+        let counters = [
+            (
+                "cache_hits_total",
+                "Tasks whose outputs were loaded from a cache.",
+                &self.cache_hits,
+            ),
+            (
+                "cache_misses_total",
+                "Tasks executed because their outputs were not cached.",
+                &self.cache_misses,
+            ),
+            (
+                "hashed_bytes_total",
+                "Bytes of file content identified.",
+                &self.hashed_bytes,
+            ),
+            (
+                "blob_bytes_written_total",
+                "Bytes of blobs written to cache directories, as stored.",
+                &self.blob_bytes_written,
+            ),
+        ];
+        for (name, help, counter) in counters {
+            let name = format!("{}_{}", METRIC_NAME_PREFIX, name);
+            writeln!(output, "# HELP {} {}", name, help).expect("write to string");
+            writeln!(output, "# TYPE {} counter", name).expect("write to string");
+            writeln!(output, "{} {}", name, counter.get()).expect("write to string");
+        }
+        let histograms = [
+            (
+                "hash_duration_seconds",
+                "Time spent identifying file content.",
+                &self.hash_duration,
+            ),
+            (
+                "execution_duration_seconds",
+                "Time spent executing tasks.",
+                &self.execution_duration,
+            ),
+        ];
+        for (name, help, histogram) in histograms {
+            let name = format!("{}_{}", METRIC_NAME_PREFIX, name);
+            writeln!(output, "# HELP {} {}", name, help).expect("write to string");
+            writeln!(output, "# TYPE {} histogram", name).expect("write to string");
+            histogram.render(&name, &mut output);
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::spawn_exporter;
+    use super::Histogram;
+    use super::Metrics;
+    use crate::events::Event;
+    use std::io::Read as _;
+    use std::io::Write as _;
+    use std::net::TcpListener;
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    #[test]
+    fn test_render_prometheus() {
+        let metrics = Metrics::new();
+        metrics.cache_hits.increment();
+        metrics.cache_hits.increment();
+        metrics.cache_misses.increment();
+        metrics.record_hash(1024, Duration::from_millis(2));
+        metrics
+            .execution_duration
+            .observe_duration(Duration::from_millis(300));
+        metrics
+            .execution_duration
+            .observe_duration(Duration::from_secs(600));
+
+        let rendered = metrics.render_prometheus();
+        assert!(rendered.contains(
+            "# TYPE artifact_executor_cache_hits_total counter\n\
+             artifact_executor_cache_hits_total 2\n"
+        ));
+        assert!(rendered.contains("artifact_executor_cache_misses_total 1\n"));
+        assert!(rendered.contains("artifact_executor_hashed_bytes_total 1024\n"));
+        assert!(rendered.contains("artifact_executor_blob_bytes_written_total 0\n"));
+        assert!(
+            rendered.contains("artifact_executor_hash_duration_seconds_bucket{le=\"0.001\"} 0\n")
+        );
+        assert!(
+            rendered.contains("artifact_executor_hash_duration_seconds_bucket{le=\"0.005\"} 1\n")
+        );
+        assert!(rendered.contains(
+            "artifact_executor_execution_duration_seconds_bucket{le=\"0.25\"} 0\n\
+             artifact_executor_execution_duration_seconds_bucket{le=\"0.5\"} 1\n"
+        ));
+        assert!(rendered.contains(
+            "artifact_executor_execution_duration_seconds_bucket{le=\"120\"} 1\n\
+             artifact_executor_execution_duration_seconds_bucket{le=\"+Inf\"} 2\n\
+             artifact_executor_execution_duration_seconds_sum 600.3\n\
+             artifact_executor_execution_duration_seconds_count 2\n"
+        ));
+    }
+
+    #[test]
+    fn test_exporter() {
+        let metrics: &'static Metrics = Box::leak(Box::new(Metrics::new()));
+        metrics.record_event(&Event::BlobWritten {
+            hash: "blob",
+            bytes: 42,
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind exporter");
+        let address = listener.local_addr().expect("exporter address");
+        spawn_exporter(listener, metrics);
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(address).expect("connect to exporter");
+            write!(stream, "GET {} HTTP/1.1\r\n\r\n", path).expect("write request");
+            let mut response = String::new();
+            stream.read_to_string(&mut response).expect("read response");
+            response
+        };
+        // A client that connects without sending a request does not hold up others.
+        let _idle = TcpStream::connect(address).expect("connect idle client to exporter");
+        let response = get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("artifact_executor_blob_bytes_written_total 42\n"));
+        assert!(get("/blobs").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::new(&[1.0, 2.0]);
+        histogram.observe(1.0);
+        histogram.observe(1.5);
+        histogram.observe(3.0);
+        assert_eq!(3, histogram.count());
+        assert_eq!(5.5, histogram.sum());
+        let mut rendered = String::new();
+        histogram.render("h", &mut rendered);
+        assert_eq!(
+            "h_bucket{le=\"1\"} 1\nh_bucket{le=\"2\"} 2\nh_bucket{le=\"+Inf\"} 3\nh_sum 5.5\nh_count 3\n",
+            rendered
+        );
+    }
+}
@@ -0,0 +1,398 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+use anyhow::Context as _;
+use serde::de::Deserializer;
+use serde::de::Visitor;
+use serde::ser::Serializer;
+use serde::Deserialize;
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+/// Multihash code for sha2-256 digests, from the multicodec table.
+pub const SHA2_256: u64 = 0x12;
+
+/// Multihash code for blake2b-256 digests, from the multicodec table.
+pub const BLAKE2B_256: u64 = 0xb220;
+
+/// Multibase encodings that identities may be emitted or parsed in.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Multibase {
+    /// Lowercase hexadecimal; prefix `f`.
+    Base16,
+    /// Lowercase RFC 4648 base32 without padding; prefix `b`.
+    Base32,
+    /// Bitcoin-alphabet base58; prefix `z`.
+    Base58Btc,
+}
+
+impl Multibase {
+    pub fn prefix(&self) -> char {
+        match self {
+            Self::Base16 => 'f',
+            Self::Base32 => 'b',
+            Self::Base58Btc => 'z',
+        }
+    }
+
+    pub fn from_prefix(prefix: char) -> anyhow::Result<Self> {
+        match prefix {
+            'f' => Ok(Self::Base16),
+            'b' => Ok(Self::Base32),
+            'z' => Ok(Self::Base58Btc),
+            _ => anyhow::bail!("unsupported multibase prefix, {:?}", prefix),
+        }
+    }
+
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        let mut encoded = String::from(self.prefix());
+        match self {
+            Self::Base16 => encoded.push_str(&hex::encode(bytes)),
+            Self::Base32 => encoded.push_str(&encode_base32(bytes)),
+            Self::Base58Btc => encoded.push_str(&encode_base58(bytes)),
+        }
+        encoded
+    }
+
+    /// Decodes a multibase string, returning the base it was encoded in along with its bytes.
+    pub fn decode(encoded: &str) -> anyhow::Result<(Self, Vec<u8>)> {
+        let mut chars = encoded.chars();
+        let prefix = chars
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("attempted to decode empty multibase string"))?;
+        let base = Self::from_prefix(prefix)?;
+        let data = chars.as_str();
+        let bytes = match base {
+            Self::Base16 => hex::decode(data).map_err(anyhow::Error::from),
+            Self::Base32 => decode_base32(data),
+            Self::Base58Btc => decode_base58(data),
+        }
+        .with_context(|| format!("decoding multibase string, {:?}", encoded))?;
+        Ok((base, bytes))
+    }
+}
+
+impl Default for Multibase {
+    /// Base32 is the default multibase for self-describing content identifiers.
+    fn default() -> Self {
+        Self::Base32
+    }
+}
+
+/// A self-describing digest: the multihash code of the hash function, followed by the digest.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Multihash {
+    code: u64,
+    digest: Vec<u8>,
+}
+
+impl Multihash {
+    pub fn new(code: u64, digest: Vec<u8>) -> Self {
+        Self { code, digest }
+    }
+
+    pub fn code(&self) -> u64 {
+        self.code
+    }
+
+    pub fn digest(&self) -> &[u8] {
+        &self.digest
+    }
+
+    /// Binary multihash encoding: `varint(code) || varint(digest length) || digest`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        write_varint(&mut bytes, self.code);
+        write_varint(&mut bytes, self.digest.len() as u64);
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let (code, rest) = read_varint(bytes).context("reading multihash code")?;
+        let (length, digest) = read_varint(rest).context("reading multihash digest length")?;
+        if digest.len() as u64 != length {
+            anyhow::bail!(
+                "multihash declares digest of {} bytes, but contains {} bytes",
+                length,
+                digest.len()
+            );
+        }
+        Ok(Self {
+            code,
+            digest: digest.to_vec(),
+        })
+    }
+
+    pub fn encode(&self, base: Multibase) -> String {
+        base.encode(&self.to_bytes())
+    }
+}
+
+impl FromStr for Multihash {
+    type Err = anyhow::Error;
+
+    fn from_str(encoded: &str) -> Result<Self, Self::Err> {
+        let (_, bytes) = Multibase::decode(encoded)?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// An identity whose digest can be described as a multihash.
+pub trait MultihashIdentity: Sized {
+    const MULTIHASH_CODE: u64;
+
+    fn digest(&self) -> &[u8];
+
+    fn try_from_digest(digest: &[u8]) -> anyhow::Result<Self>;
+
+    fn to_multihash(&self) -> Multihash {
+        Multihash::new(Self::MULTIHASH_CODE, self.digest().to_vec())
+    }
+
+    fn try_from_multihash(multihash: &Multihash) -> anyhow::Result<Self> {
+        if multihash.code() != Self::MULTIHASH_CODE {
+            anyhow::bail!(
+                "expected multihash code {:#x}, but got {:#x}",
+                Self::MULTIHASH_CODE,
+                multihash.code()
+            );
+        }
+        Self::try_from_digest(multihash.digest())
+    }
+}
+
+/// Transport wrapper that serializes an identity as a multibase-encoded multihash string, for
+/// interoperating with content stores that key on multihashes.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct MultihashEncoded<Identity: MultihashIdentity>(pub Identity);
+
+impl<Identity: MultihashIdentity> Serialize for MultihashEncoded<Identity> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.0.to_multihash().encode(Multibase::default()))
+    }
+}
+
+struct MultihashEncodedVisitor<Identity>(PhantomData<Identity>);
+
+impl<'de, Identity: MultihashIdentity> Visitor<'de> for MultihashEncodedVisitor<Identity> {
+    type Value = MultihashEncoded<Identity>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a multibase string containing a multihash")
+    }
+
+    fn visit_str<E>(self, encoded: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Multihash::from_str(encoded)
+            .and_then(|multihash| Identity::try_from_multihash(&multihash))
+            .map(MultihashEncoded)
+            .map_err(|err| E::custom(format!("{:?}", err)))
+    }
+}
+
+impl<'de, Identity: MultihashIdentity> Deserialize<'de> for MultihashEncoded<Identity> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(MultihashEncodedVisitor(PhantomData))
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn read_varint(bytes: &[u8]) -> anyhow::Result<(u64, &[u8])> {
+    let mut value: u64 = 0;
+    for (index, byte) in bytes.iter().enumerate() {
+        // Unsigned varints in multiformats are limited to 9 bytes (63 bits).
+        if index >= 9 {
+            anyhow::bail!("varint exceeds maximum length of 9 bytes");
+        }
+        value |= ((byte & 0x7f) as u64) << (7 * index);
+        if byte & 0x80 == 0 {
+            return Ok((value, &bytes[index + 1..]));
+        }
+    }
+    anyhow::bail!("unterminated varint")
+}
+
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+fn encode_base32(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | (*byte as u32);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+fn decode_base32(encoded: &str) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for character in encoded.chars() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|alphabet_byte| *alphabet_byte as char == character)
+            .ok_or_else(|| anyhow::anyhow!("invalid base32 character, {:?}", character))?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push(((buffer >> bits) & 0xff) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+fn encode_base58(bytes: &[u8]) -> String {
+    let leading_zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    // Little-endian base-58 digits.
+    let mut digits: Vec<u8> = vec![];
+    for byte in bytes {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let mut encoded = String::new();
+    for _ in 0..leading_zeros {
+        encoded.push(BASE58_ALPHABET[0] as char);
+    }
+    for digit in digits.iter().rev() {
+        encoded.push(BASE58_ALPHABET[*digit as usize] as char);
+    }
+    encoded
+}
+
+fn decode_base58(encoded: &str) -> anyhow::Result<Vec<u8>> {
+    let leading_zeros = encoded
+        .chars()
+        .take_while(|character| *character == BASE58_ALPHABET[0] as char)
+        .count();
+    // Little-endian bytes.
+    let mut bytes: Vec<u8> = vec![];
+    for character in encoded.chars() {
+        let mut carry = BASE58_ALPHABET
+            .iter()
+            .position(|alphabet_byte| *alphabet_byte as char == character)
+            .ok_or_else(|| anyhow::anyhow!("invalid base58 character, {:?}", character))?
+            as u32;
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+    let mut decoded = vec![0; leading_zeros];
+    decoded.extend(bytes.iter().rev());
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Multibase;
+    use super::Multihash;
+    use super::MultihashEncoded;
+    use super::MultihashIdentity as _;
+    use crate::identity::IdentityScheme as _;
+    use crate::transport::ContentSha256;
+    use crate::transport::Sha256;
+
+    // Multihash of the sha2-256 digest of empty content.
+    const EMPTY_SHA256_MULTIHASH_HEX: &str =
+        "1220e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn test_multihash_encodings() {
+        let identity = ContentSha256::identify_content("".as_bytes()).expect("identify empty");
+        let multihash = identity.to_multihash();
+        assert_eq!(
+            EMPTY_SHA256_MULTIHASH_HEX,
+            hex::encode(multihash.to_bytes())
+        );
+        assert_eq!(
+            format!("f{}", EMPTY_SHA256_MULTIHASH_HEX),
+            multihash.encode(Multibase::Base16)
+        );
+        assert_eq!(
+            "bciqohmgeikmpyhautl57jsezn64sij5oihsgjg4tjssjlgi3pbjlqvi",
+            multihash.encode(Multibase::Base32)
+        );
+        assert_eq!(
+            "zQmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n",
+            multihash.encode(Multibase::Base58Btc)
+        );
+
+        for base in [Multibase::Base16, Multibase::Base32, Multibase::Base58Btc] {
+            let parsed: Multihash = multihash.encode(base).parse().expect("parse multihash");
+            assert_eq!(multihash, parsed);
+            assert_eq!(
+                identity,
+                Sha256::try_from_multihash(&parsed).expect("sha256 from multihash")
+            );
+        }
+
+        assert!("x1220".parse::<Multihash>().is_err());
+        assert!("f1220e3b0".parse::<Multihash>().is_err());
+        assert!(Sha256::try_from_multihash(&Multihash::new(0x13, vec![0; 32])).is_err());
+    }
+
+    #[test]
+    fn test_multihash_encoded_serde() {
+        let identity = ContentSha256::identify_content("".as_bytes()).expect("identify empty");
+        let json = serde_json::to_string(&MultihashEncoded(identity.clone()))
+            .expect("serialize multihash-encoded identity");
+        assert_eq!(
+            "\"bciqohmgeikmpyhautl57jsezn64sij5oihsgjg4tjssjlgi3pbjlqvi\"",
+            json
+        );
+        let parsed: MultihashEncoded<Sha256> =
+            serde_json::from_str(&json).expect("deserialize multihash-encoded identity");
+        assert_eq!(identity, parsed.0);
+
+        // Plain identities also accept multihash encodings.
+        let parsed: Sha256 = serde_json::from_str(&json).expect("deserialize sha256");
+        assert_eq!(identity, parsed);
+    }
+}
@@ -0,0 +1,297 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Line-delimited JSON ("NDJSON") representations of listings and file identities manifests.
+//!
+//! A stream consists of a header object on its first line, followed by one JSON value per entry
+//! per line. Readers and writers process one entry at a time, so memory use does not grow with the
+//! number of entries, and a reader may stop after any prefix of the entries.
+
+use crate::identity::Identity as IdentityBound;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::schema::migrate;
+use crate::schema::FormatVersion;
+use crate::schema::Versioned;
+use crate::transport::FileIdentitiesManifestHeader;
+use crate::transport::ListingHeader;
+use anyhow::Context as _;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::io::BufRead;
+use std::io::Lines;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+
+/// Writes a header followed by entries of type `E`, one per line.
+pub struct NdjsonWriter<W: Write, E: Serialize> {
+    writer: W,
+    _marker: PhantomData<E>,
+}
+
+impl<W: Write, E: Serialize> NdjsonWriter<W, E> {
+    pub fn new<H: Serialize>(mut writer: W, header: &H) -> anyhow::Result<Self> {
+        write_line(&mut writer, header).context("writing line-delimited header")?;
+        Ok(Self {
+            writer,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn write_entry(&mut self, entry: &E) -> anyhow::Result<()> {
+        write_line(&mut self.writer, entry).context("writing line-delimited entry")
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn into_inner(mut self) -> anyhow::Result<W> {
+        self.writer
+            .flush()
+            .map_err(anyhow::Error::from)
+            .context("flushing line-delimited writer")?;
+        Ok(self.writer)
+    }
+}
+
+fn write_line<W: Write, T: Serialize>(writer: &mut W, value: &T) -> anyhow::Result<()> {
+    serde_json::to_writer(&mut *writer, value).map_err(anyhow::Error::from)?;
+    writer.write_all(b"\n").map_err(anyhow::Error::from)
+}
+
+/// Reads a header, then yields entries of type `E`, one per line. Blank lines are ignored.
+pub struct NdjsonReader<R: BufRead, E: DeserializeOwned> {
+    lines: Lines<R>,
+    line_number: usize,
+    _marker: PhantomData<E>,
+}
+
+impl<R: BufRead, E: DeserializeOwned> NdjsonReader<R, E> {
+    /// Reads the header from `reader`, upgrading it to the current schema.
+    pub fn new<H: Versioned>(reader: R) -> anyhow::Result<(H, Self)> {
+        let mut lines = reader.lines();
+        let header_line = lines
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("line-delimited stream has no header"))?
+            .map_err(anyhow::Error::from)
+            .context("reading line-delimited header")?;
+        let header_value: Value = serde_json::from_str(&header_line)
+            .map_err(anyhow::Error::from)
+            .context("parsing line-delimited header")?;
+        let header = migrate::<H>(header_value).context("loading line-delimited header")?;
+        Ok((
+            header,
+            Self {
+                lines,
+                line_number: 1,
+                _marker: PhantomData,
+            },
+        ))
+    }
+}
+
+impl<R: BufRead, E: DeserializeOwned> Iterator for NdjsonReader<R, E> {
+    type Item = anyhow::Result<E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = self.lines.next()?;
+            self.line_number += 1;
+            let line_number = self.line_number;
+            let line = match line {
+                Ok(line) => line,
+                Err(error) => {
+                    return Some(
+                        Err(anyhow::Error::from(error))
+                            .with_context(|| format!("reading line {}", line_number)),
+                    )
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(
+                serde_json::from_str(&line)
+                    .map_err(anyhow::Error::from)
+                    .with_context(|| format!("parsing entry on line {}", line_number)),
+            );
+        }
+    }
+}
+
+/// Creates a writer for a line-delimited listing of identities.
+pub fn listing_writer<W: Write, Identity: IdentityBound>(
+    writer: W,
+) -> anyhow::Result<NdjsonWriter<W, Identity>> {
+    NdjsonWriter::new(writer, &ListingHeader::default())
+}
+
+/// Creates a reader for a line-delimited listing of identities.
+pub fn listing_reader<R: BufRead, Identity: IdentityBound>(
+    reader: R,
+) -> anyhow::Result<NdjsonReader<R, Identity>> {
+    let (_, entries) = NdjsonReader::new::<ListingHeader>(reader)?;
+    Ok(entries)
+}
+
+/// Writes a line-delimited file identities manifest, checking that paths are written in the sorted
+/// order required of canonical manifests.
+pub struct FileIdentitiesWriter<W: Write, IS: IdentitySchemeApi> {
+    writer: NdjsonWriter<W, (PathBuf, Option<IS::Identity>)>,
+    last_path: Option<PathBuf>,
+}
+
+impl<W: Write, IS: IdentitySchemeApi> FileIdentitiesWriter<W, IS> {
+    pub fn new(writer: W) -> anyhow::Result<Self> {
+        Ok(Self {
+            writer: NdjsonWriter::new(
+                writer,
+                &FileIdentitiesManifestHeader {
+                    format_version: FormatVersion::default(),
+                    identity_scheme: IS::IDENTITY_SCHEME,
+                },
+            )?,
+            last_path: None,
+        })
+    }
+
+    pub fn write_entry(&mut self, entry: &(PathBuf, Option<IS::Identity>)) -> anyhow::Result<()> {
+        check_sorted(&mut self.last_path, &entry.0)?;
+        self.writer.write_entry(entry)
+    }
+
+    pub fn into_inner(self) -> anyhow::Result<W> {
+        self.writer.into_inner()
+    }
+}
+
+/// Reads a line-delimited file identities manifest, checking its identity scheme and that its
+/// paths are sorted.
+pub struct FileIdentitiesReader<R: BufRead, IS: IdentitySchemeApi> {
+    entries: NdjsonReader<R, (PathBuf, Option<IS::Identity>)>,
+    last_path: Option<PathBuf>,
+}
+
+impl<R: BufRead, IS: IdentitySchemeApi> FileIdentitiesReader<R, IS> {
+    pub fn new(reader: R) -> anyhow::Result<Self> {
+        let (header, entries) = NdjsonReader::new::<FileIdentitiesManifestHeader>(reader)?;
+        if header.identity_scheme != IS::IDENTITY_SCHEME {
+            anyhow::bail!(
+                "line-delimited manifest has identity scheme {:?}, but expected {:?}",
+                header.identity_scheme,
+                IS::IDENTITY_SCHEME
+            );
+        }
+        Ok(Self {
+            entries,
+            last_path: None,
+        })
+    }
+}
+
+impl<R: BufRead, IS: IdentitySchemeApi> Iterator for FileIdentitiesReader<R, IS> {
+    type Item = anyhow::Result<(PathBuf, Option<IS::Identity>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.next()?;
+        Some(entry.and_then(|entry| {
+            check_sorted(&mut self.last_path, &entry.0)?;
+            Ok(entry)
+        }))
+    }
+}
+
+fn check_sorted(last_path: &mut Option<PathBuf>, path: &PathBuf) -> anyhow::Result<()> {
+    if let Some(last_path) = last_path.as_ref() {
+        if path <= last_path {
+            anyhow::bail!(
+                "line-delimited manifest path, {:?}, does not sort after previous path, {:?}",
+                path,
+                last_path
+            );
+        }
+    }
+    *last_path = Some(path.clone());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::listing_reader;
+    use super::listing_writer;
+    use super::FileIdentitiesReader;
+    use super::FileIdentitiesWriter;
+    use crate::identity::IdentityScheme as _;
+    use crate::transport::ContentSha256;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_listing() {
+        let identities: Vec<_> = ["a", "b", "c"]
+            .into_iter()
+            .map(|content| ContentSha256::identify_content(content.as_bytes()).expect("identity"))
+            .collect();
+        let mut writer = listing_writer(vec![]).expect("listing writer");
+        for identity in identities.iter() {
+            writer.write_entry(identity).expect("write entry");
+        }
+        let contents = writer.into_inner().expect("finish listing");
+        assert_eq!(4, contents.split(|byte| *byte == b'\n').count() - 1);
+
+        let read_identities = listing_reader(contents.as_slice())
+            .expect("listing reader")
+            .collect::<anyhow::Result<Vec<_>>>()
+            .expect("read entries");
+        assert_eq!(identities, read_identities);
+
+        // Partial reads stop after the requested entries.
+        let first = listing_reader(contents.as_slice())
+            .expect("listing reader")
+            .next()
+            .expect("first entry")
+            .expect("read first entry");
+        assert_eq!(identities[0], first);
+    }
+
+    #[test]
+    fn test_file_identities() {
+        let identity = ContentSha256::identify_content("a".as_bytes()).expect("identity");
+        let entries = vec![
+            (PathBuf::from("a"), Some(identity)),
+            (PathBuf::from("b"), None),
+        ];
+        let mut writer = FileIdentitiesWriter::<_, ContentSha256>::new(vec![]).expect("writer");
+        for entry in entries.iter() {
+            writer.write_entry(entry).expect("write entry");
+        }
+        assert!(writer.write_entry(&entries[0]).is_err());
+        let contents = writer.into_inner().expect("finish manifest");
+
+        let read_entries = FileIdentitiesReader::<_, ContentSha256>::new(contents.as_slice())
+            .expect("reader")
+            .collect::<anyhow::Result<Vec<_>>>()
+            .expect("read entries");
+        assert_eq!(entries, read_entries);
+
+        let unsorted = concat!(
+            r#"{"format_version":1,"identity_scheme":"content_sha256"}"#,
+            "\n",
+            r#"["b",null]"#,
+            "\n",
+            r#"["a",null]"#,
+            "\n",
+        );
+        assert!(
+            FileIdentitiesReader::<_, ContentSha256>::new(unsorted.as_bytes())
+                .expect("reader")
+                .collect::<anyhow::Result<Vec<_>>>()
+                .is_err()
+        );
+        assert!(
+            FileIdentitiesReader::<_, crate::transport::ContentBlake2b256>::new(
+                unsorted.as_bytes()
+            )
+            .is_err()
+        );
+    }
+}
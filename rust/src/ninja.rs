@@ -0,0 +1,825 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Imports Ninja build files (`build.ninja`) into the task graph format, so that projects whose
+//! build is generated by, e.g., CMake or GN can be executed with content-addressed caching.
+//!
+//! Each build statement becomes one task, labelled by its first output, that runs the statement's
+//! command with `/bin/sh -c`, as Ninja does. Explicit and implicit inputs become input files,
+//! `depfile`s become input depfiles, and explicit and implicit outputs become output files. A task
+//! depends on the tasks that produce any of its inputs, including order-only inputs; `phony`
+//! statements are resolved to the tasks behind them rather than imported. Response files
+//! (`rspfile`) are written by the task's command before the statement's command runs.
+//!
+//! Not supported: `dyndep`, `deps = msvc` (the depfile is ignored), and pools (tasks are not
+//! throttled).
+
+use crate::fs::Filesystem as FilesystemApi;
+use crate::schema::FormatVersion;
+use crate::transport::Arguments;
+use crate::transport::EnvironmentInheritance;
+use crate::transport::EnvironmentVariables;
+use crate::transport::Inputs;
+use crate::transport::Outputs;
+use crate::transport::OutputsVerification;
+use crate::transport::Program;
+use crate::transport::Task;
+use crate::transport::TaskGraph as TaskGraphTransport;
+use crate::transport::TaskGraphNode;
+use anyhow::Context as _;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::io::Read as _;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Shell with which build statement commands are run.
+pub const NINJA_SHELL: &str = "/bin/sh";
+
+/// Name of the built-in rule that aliases its inputs.
+const PHONY_RULE: &str = "phony";
+
+/// A build statement, with its variables expanded.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct NinjaBuild {
+    pub rule: String,
+    pub outputs: Vec<String>,
+    pub implicit_outputs: Vec<String>,
+    pub inputs: Vec<String>,
+    pub implicit_inputs: Vec<String>,
+    pub order_only_inputs: Vec<String>,
+    pub command: String,
+    pub description: Option<String>,
+    pub depfile: Option<String>,
+    pub deps: Option<String>,
+    pub rspfile: Option<String>,
+    pub rspfile_content: String,
+    /// Whether the rule regenerates the build file itself (e.g., CMake's re-run rule).
+    pub generator: bool,
+}
+
+/// The build statements of a Ninja build file and the files it includes.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct NinjaFile {
+    pub builds: Vec<NinjaBuild>,
+    /// Targets named by `default` statements.
+    pub defaults: Vec<String>,
+}
+
+/// A string containing variable references, as written in a build file.
+#[derive(Clone, Debug, Default)]
+struct EvalString(Vec<EvalToken>);
+
+#[derive(Clone, Debug)]
+enum EvalToken {
+    Literal(String),
+    Variable(String),
+}
+
+impl EvalString {
+    fn parse(raw: &str) -> anyhow::Result<Self> {
+        let mut tokens = vec![];
+        let mut literal = String::new();
+        let mut chars = raw.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '$' {
+                literal.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some(escaped @ ('$' | ' ' | ':')) => literal.push(escaped),
+                Some('{') => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => anyhow::bail!("unterminated variable reference in {:?}", raw),
+                        }
+                    }
+                    tokens.push(EvalToken::Literal(std::mem::take(&mut literal)));
+                    tokens.push(EvalToken::Variable(name));
+                }
+                Some(c) if is_simple_variable_char(c) => {
+                    let mut name = String::from(c);
+                    while let Some(c) = chars
+                        .peek()
+                        .copied()
+                        .filter(|c| is_simple_variable_char(*c))
+                    {
+                        name.push(c);
+                        chars.next();
+                    }
+                    tokens.push(EvalToken::Literal(std::mem::take(&mut literal)));
+                    tokens.push(EvalToken::Variable(name));
+                }
+                Some(c) => anyhow::bail!("bad escape, \"${}\", in {:?}", c, raw),
+                None => anyhow::bail!("trailing \"$\" in {:?}", raw),
+            }
+        }
+        tokens.push(EvalToken::Literal(literal));
+        Ok(Self(tokens))
+    }
+
+    fn evaluate<F: FnMut(&str) -> String>(&self, mut lookup: F) -> String {
+        self.0
+            .iter()
+            .map(|token| match token {
+                EvalToken::Literal(literal) => literal.clone(),
+                EvalToken::Variable(name) => lookup(name),
+            })
+            .collect()
+    }
+}
+
+fn is_simple_variable_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+#[derive(Clone, Debug, Default)]
+struct Rule {
+    bindings: HashMap<String, EvalString>,
+}
+
+/// Variables and rules visible at a point in a build file. `subninja` files get a copy, so their
+/// definitions do not leak into the including file.
+#[derive(Clone, Debug)]
+struct Scope {
+    variables: HashMap<String, String>,
+    rules: HashMap<String, Rule>,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self {
+            variables: HashMap::new(),
+            rules: [(String::from(PHONY_RULE), Rule::default())]
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+/// Parses the Ninja build file `contents`. Files named by `include` and `subninja` statements are
+/// read with `read_file`.
+pub fn parse_ninja<F: FnMut(&str) -> anyhow::Result<String>>(
+    contents: &str,
+    mut read_file: F,
+) -> anyhow::Result<NinjaFile> {
+    let mut ninja_file = NinjaFile::default();
+    parse_into(
+        contents,
+        &mut Scope::default(),
+        &mut read_file,
+        &mut ninja_file,
+    )?;
+    Ok(ninja_file)
+}
+
+/// Joins lines continued with a trailing `$`, and drops comments and blank lines.
+fn logical_lines(contents: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut current: Option<String> = None;
+    for line in contents.lines() {
+        let line = line.trim_end_matches('\r');
+        let line = match current.take() {
+            Some(mut continued) => {
+                continued.push_str(line.trim_start());
+                continued
+            }
+            None => line.to_string(),
+        };
+        let trailing_dollars = line.len() - line.trim_end_matches('$').len();
+        if trailing_dollars % 2 == 1 {
+            current = Some(line[..line.len() - 1].to_string());
+            continue;
+        }
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        lines.push(line);
+    }
+    lines.extend(current);
+    lines
+}
+
+/// Splits a binding line, `name = value`, into its name and unevaluated value.
+fn parse_binding(line: &str) -> anyhow::Result<(String, EvalString)> {
+    let (name, value) = line
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("expected \"name = value\", found {:?}", line))?;
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| is_simple_variable_char(c) || c == '.') {
+        anyhow::bail!("bad variable name, {:?}", name);
+    }
+    Ok((name.to_string(), EvalString::parse(value.trim_start())?))
+}
+
+/// Splits the paths of a `build` or `default` line on unescaped spaces, emitting the separators
+/// `:`, `|`, `||`, and `|@` as separate words.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => {
+                word.push(c);
+                if let Some(escaped) = chars.next() {
+                    word.push(escaped);
+                }
+            }
+            ' ' | '\t' => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            ':' | '|' => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                let mut separator = String::from(c);
+                if c == '|' {
+                    if let Some(next @ ('|' | '@')) = chars.peek().copied() {
+                        separator.push(next);
+                        chars.next();
+                    }
+                }
+                words.push(separator);
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn parse_into<F: FnMut(&str) -> anyhow::Result<String>>(
+    contents: &str,
+    scope: &mut Scope,
+    read_file: &mut F,
+    ninja_file: &mut NinjaFile,
+) -> anyhow::Result<()> {
+    let lines = logical_lines(contents);
+    let mut index = 0;
+    while index < lines.len() {
+        let line = &lines[index];
+        index += 1;
+        if line.starts_with([' ', '\t']) {
+            anyhow::bail!("unexpected indented line, {:?}", line.trim());
+        }
+        // Bindings indented under the statement on `line`.
+        let mut indented = vec![];
+        while index < lines.len() && lines[index].starts_with([' ', '\t']) {
+            indented.push(parse_binding(lines[index].trim_start())?);
+            index += 1;
+        }
+
+        let (keyword, rest) = line.split_once([' ', '\t']).unwrap_or((line.as_str(), ""));
+        match keyword {
+            "rule" => {
+                let name = rest.trim();
+                if scope.rules.contains_key(name) {
+                    anyhow::bail!("duplicate rule, {:?}", name);
+                }
+                scope.rules.insert(
+                    name.to_string(),
+                    Rule {
+                        bindings: indented.into_iter().collect(),
+                    },
+                );
+            }
+            "build" => {
+                let build = parse_build(rest, indented, scope)
+                    .with_context(|| format!("parsing build statement {:?}", line))?;
+                ninja_file.builds.push(build);
+            }
+            "default" => {
+                for word in split_words(rest) {
+                    ninja_file
+                        .defaults
+                        .push(EvalString::parse(&word)?.evaluate(|name| scope.lookup(name)));
+                }
+            }
+            "pool" => {}
+            "include" | "subninja" => {
+                let path = EvalString::parse(rest.trim())?.evaluate(|name| scope.lookup(name));
+                let included = read_file(&path).with_context(|| format!("reading {:?}", path))?;
+                if keyword == "include" {
+                    parse_into(&included, scope, read_file, ninja_file)
+                } else {
+                    parse_into(&included, &mut scope.clone(), read_file, ninja_file)
+                }
+                .with_context(|| format!("parsing {:?}", path))?;
+            }
+            _ => {
+                let (name, value) = parse_binding(line)?;
+                let value = value.evaluate(|name| scope.lookup(name));
+                scope.variables.insert(name, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Scope {
+    fn lookup(&self, name: &str) -> String {
+        self.variables.get(name).cloned().unwrap_or_default()
+    }
+}
+
+fn parse_build(
+    line: &str,
+    bindings: Vec<(String, EvalString)>,
+    scope: &Scope,
+) -> anyhow::Result<NinjaBuild> {
+    // Build-level bindings are evaluated in the enclosing scope; paths and rule bindings may refer
+    // to them.
+    let build_variables: HashMap<String, String> = bindings
+        .into_iter()
+        .map(|(name, value)| {
+            let value = value.evaluate(|name| scope.lookup(name));
+            (name, value)
+        })
+        .collect();
+    let lookup = |name: &str| -> String {
+        build_variables
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| scope.lookup(name))
+    };
+
+    let mut sections: Vec<(String, Vec<String>)> = vec![(String::new(), vec![])];
+    let mut rule = None;
+    for word in split_words(line) {
+        match word.as_str() {
+            ":" if rule.is_none() => {
+                rule = Some(String::new());
+                sections.push((word, vec![]));
+            }
+            "|" | "||" | "|@" => sections.push((word, vec![])),
+            ":" => anyhow::bail!("unexpected \":\""),
+            _ if rule.as_deref() == Some("") => rule = Some(word),
+            _ => {
+                let path = EvalString::parse(&word)?.evaluate(lookup);
+                sections.last_mut().expect("current section").1.push(path);
+            }
+        }
+    }
+    let rule_name = match rule {
+        Some(rule) if !rule.is_empty() => rule,
+        _ => anyhow::bail!("expected \"outputs: rule inputs\""),
+    };
+    let rule = scope
+        .rules
+        .get(&rule_name)
+        .ok_or_else(|| anyhow::anyhow!("unknown rule, {:?}", rule_name))?;
+
+    let mut build = NinjaBuild {
+        rule: rule_name.clone(),
+        ..NinjaBuild::default()
+    };
+    let mut after_colon = false;
+    for (separator, paths) in sections {
+        match (separator.as_str(), after_colon) {
+            ("", _) => build.outputs = paths,
+            ("|", false) => build.implicit_outputs = paths,
+            (":", _) => {
+                after_colon = true;
+                build.inputs = paths;
+            }
+            ("|", true) => build.implicit_inputs = paths,
+            ("||", true) => build.order_only_inputs = paths,
+            // Validations do not affect what a task reads or produces.
+            ("|@", true) => {}
+            _ => anyhow::bail!("unexpected {:?}", separator),
+        }
+    }
+    if build.outputs.is_empty() && build.implicit_outputs.is_empty() {
+        anyhow::bail!("build statement has no outputs");
+    }
+
+    let in_value = join_for_shell(&build.inputs, " ");
+    let in_newline_value = build.inputs.join("\n");
+    let out_value = join_for_shell(&build.outputs, " ");
+    let evaluate_rule_binding = |name: &str| -> Option<String> {
+        let mut stack = vec![];
+        evaluate_rule_variable(
+            name,
+            rule,
+            &build_variables,
+            scope,
+            &in_value,
+            &in_newline_value,
+            &out_value,
+            &mut stack,
+        )
+        .filter(|value| !value.is_empty())
+    };
+    build.command = evaluate_rule_binding("command").unwrap_or_default();
+    build.description = evaluate_rule_binding("description");
+    build.depfile = evaluate_rule_binding("depfile");
+    build.deps = evaluate_rule_binding("deps");
+    build.rspfile = evaluate_rule_binding("rspfile");
+    build.rspfile_content = evaluate_rule_binding("rspfile_content").unwrap_or_default();
+    build.generator = evaluate_rule_binding("generator").is_some();
+    if rule_name != PHONY_RULE && build.command.is_empty() {
+        anyhow::bail!("rule, {:?}, has no command", rule_name);
+    }
+    Ok(build)
+}
+
+/// Evaluates the variable `name` as seen by a rule's bindings: `in`, `in_newline`, and `out`, then
+/// the build statement's bindings, then the rule's own bindings, then the enclosing scope.
+/// `stack` guards against bindings that refer to themselves.
+#[allow(clippy::too_many_arguments)]
+fn evaluate_rule_variable(
+    name: &str,
+    rule: &Rule,
+    build_variables: &HashMap<String, String>,
+    scope: &Scope,
+    in_value: &str,
+    in_newline_value: &str,
+    out_value: &str,
+    stack: &mut Vec<String>,
+) -> Option<String> {
+    match name {
+        "in" => return Some(in_value.to_string()),
+        "in_newline" => return Some(in_newline_value.to_string()),
+        "out" => return Some(out_value.to_string()),
+        _ => {}
+    }
+    if let Some(value) = build_variables.get(name) {
+        return Some(value.clone());
+    }
+    if let Some(value) = rule.bindings.get(name) {
+        if stack.iter().any(|entry| entry == name) {
+            tracing::warn!("rule binding, {:?}, refers to itself", name);
+            return Some(String::new());
+        }
+        stack.push(name.to_string());
+        let value = value.evaluate(|name| {
+            evaluate_rule_variable(
+                name,
+                rule,
+                build_variables,
+                scope,
+                in_value,
+                in_newline_value,
+                out_value,
+                stack,
+            )
+            .unwrap_or_default()
+        });
+        stack.pop();
+        return Some(value);
+    }
+    scope.variables.get(name).cloned()
+}
+
+/// Joins `paths` as Ninja does for `$in` and `$out`, quoting paths that contain characters
+/// special to the shell.
+fn join_for_shell(paths: &[String], separator: &str) -> String {
+    paths
+        .iter()
+        .map(|path| shell_quote(path))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+fn shell_quote(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_+-./=,@%".contains(c))
+    {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// Converts the build statements of `ninja_file` to a task graph.
+pub fn ninja_to_task_graph(ninja_file: &NinjaFile) -> anyhow::Result<TaskGraphTransport> {
+    let label = |build: &NinjaBuild| {
+        build
+            .outputs
+            .first()
+            .or_else(|| build.implicit_outputs.first())
+            .expect("build statement has outputs")
+            .clone()
+    };
+    let mut producers: HashMap<&str, &NinjaBuild> = HashMap::new();
+    for build in ninja_file.builds.iter() {
+        for output in build.outputs.iter().chain(build.implicit_outputs.iter()) {
+            if producers.insert(output, build).is_some() {
+                anyhow::bail!("multiple build statements produce {:?}", output);
+            }
+        }
+    }
+
+    // Phony targets are not files, unless a phony statement with no inputs names a file (e.g., to
+    // tolerate the file's deletion).
+    let is_phony_alias = |path: &str| {
+        matches!(
+            producers.get(path),
+            Some(producer) if producer.rule == PHONY_RULE && !producer.inputs.is_empty()
+        )
+    };
+
+    let mut tasks = BTreeMap::new();
+    for build in ninja_file.builds.iter() {
+        if build.rule == PHONY_RULE {
+            continue;
+        }
+        if build.generator {
+            tracing::debug!("skipping generator build statement for {:?}", label(build));
+            continue;
+        }
+        if build.deps.as_deref() == Some("msvc") {
+            tracing::warn!(
+                "ignoring \"deps = msvc\" of build statement for {:?}",
+                label(build)
+            );
+        }
+
+        let mut dependencies = BTreeSet::new();
+        let mut visited = BTreeSet::new();
+        let mut pending: Vec<&String> = build
+            .inputs
+            .iter()
+            .chain(build.implicit_inputs.iter())
+            .chain(build.order_only_inputs.iter())
+            .collect();
+        while let Some(input) = pending.pop() {
+            if !visited.insert(input) {
+                continue;
+            }
+            match producers.get(input.as_str()) {
+                Some(producer) if producer.rule == PHONY_RULE => pending.extend(
+                    producer
+                        .inputs
+                        .iter()
+                        .chain(producer.implicit_inputs.iter())
+                        .chain(producer.order_only_inputs.iter()),
+                ),
+                Some(producer) if !producer.generator => {
+                    dependencies.insert(label(producer));
+                }
+                _ => {}
+            }
+        }
+
+        let mut script = String::new();
+        if let Some(rspfile) = build.rspfile.as_ref() {
+            script.push_str(&format!(
+                "printf '%s' {} > {} && ",
+                shell_quote(&build.rspfile_content),
+                shell_quote(rspfile)
+            ));
+        }
+        script.push_str(&build.command);
+
+        let task = Task {
+            execution_strategy: Default::default(),
+            environment_variables: EnvironmentVariables {
+                environment_variables: vec![],
+                inherit: EnvironmentInheritance::Names(vec![String::from("PATH")]),
+            },
+            program: Program::from(PathBuf::from(NINJA_SHELL)),
+            arguments: Arguments::from_iter([String::from("-c"), script]),
+            stdin: None,
+            sandbox: None,
+            resource_limits: None,
+            working_directory: None,
+            failure_caching: None,
+            label: Some(build.rule.clone()),
+            tags: vec![],
+            inputs: Inputs {
+                include_files: build
+                    .inputs
+                    .iter()
+                    .chain(build.implicit_inputs.iter())
+                    .filter(|input| !is_phony_alias(input))
+                    .map(PathBuf::from)
+                    .collect(),
+                depfiles: match (build.depfile.as_ref(), build.deps.as_deref()) {
+                    (Some(_), Some("msvc")) | (None, _) => vec![],
+                    (Some(depfile), _) => vec![PathBuf::from(depfile)],
+                },
+                ..Inputs::default()
+            },
+            outputs: Outputs {
+                include_files: build
+                    .outputs
+                    .iter()
+                    .chain(build.implicit_outputs.iter())
+                    .map(PathBuf::from)
+                    .collect(),
+                optional_files: vec![],
+                include_match_transforms: vec![],
+                include_globs: vec![],
+                include_directories: vec![],
+                exclude_matches: vec![],
+                max_file_size_bytes: None,
+                exclude_file_types: vec![],
+                stdout_file: None,
+                stderr_file: None,
+                verification: OutputsVerification::default(),
+            },
+        };
+        tasks.insert(
+            label(build),
+            TaskGraphNode {
+                task,
+                dependencies: dependencies.into_iter().collect(),
+                wired_inputs: vec![],
+            },
+        );
+    }
+    Ok(TaskGraphTransport {
+        format_version: FormatVersion::default(),
+        tasks,
+    })
+}
+
+/// Reads the Ninja build file at `path`, and any files it includes, and converts it to a task
+/// graph. Included paths are resolved against the directory containing `path`, which is also the
+/// directory from which the graph's tasks should run.
+pub fn import_ninja_file<FS: FilesystemApi, P: AsRef<Path>>(
+    filesystem: &mut FS,
+    path: P,
+) -> anyhow::Result<TaskGraphTransport> {
+    let path = path.as_ref();
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut read_file = |path: &Path| -> anyhow::Result<String> {
+        let mut contents = String::new();
+        filesystem
+            .open_file_for_read(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|mut file| {
+                file.read_to_string(&mut contents)
+                    .map_err(anyhow::Error::from)
+            })
+            .with_context(|| format!("reading Ninja file {:?}", path))?;
+        Ok(contents)
+    };
+    let contents = read_file(path)?;
+    let ninja_file = parse_ninja(&contents, |included| read_file(&directory.join(included)))
+        .with_context(|| format!("parsing Ninja file {:?}", path))?;
+    ninja_to_task_graph(&ninja_file)
+        .with_context(|| format!("converting Ninja file {:?} to a task graph", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ninja_to_task_graph;
+    use super::parse_ninja;
+    use super::split_words;
+    use crate::task_graph::TaskGraph;
+    use std::convert::TryFrom;
+    use std::path::PathBuf;
+
+    const BUILD_NINJA: &str = r#"
+# Generated by hand.
+cflags = -O2
+builddir = out
+
+rule cc
+  command = cc $cflags -MD -MF $out.d -c $in -o $out
+  depfile = $out.d
+  deps = gcc
+  description = CC $out
+
+rule link
+  command = cc @$out.rsp -o $out
+  rspfile = $out.rsp
+  rspfile_content = $in
+
+rule regenerate
+  command = cmake .
+  generator = 1
+
+build $builddir/main.o: cc src/main.c | gen/config.h
+  cflags = -O0 $
+      -g
+build $builddir/my$ file.o: cc src/my$ file.c || headers
+build gen/config.h: cc gen/config.in
+build headers: phony gen/config.h
+build $builddir/app | $builddir/app.map: link $builddir/main.o $builddir/my$ file.o
+build build.ninja: regenerate CMakeLists.txt
+build all: phony $builddir/app
+
+default all
+include rules.ninja
+"#;
+
+    #[test]
+    fn test_parse_ninja() {
+        assert_eq!(
+            vec!["a$ b", "|", "c", ":", "cc", "d", "||", "e", "|@", "f"],
+            split_words("a$ b | c: cc d || e |@ f")
+        );
+
+        let mut included = vec![];
+        let ninja_file = parse_ninja(BUILD_NINJA, |path| {
+            included.push(path.to_string());
+            Ok(String::from(
+                "build out/extra.o: cc extra.c\n  description = \n",
+            ))
+        })
+        .expect("parse build.ninja");
+        assert_eq!(vec!["rules.ninja"], included);
+        assert_eq!(vec!["all"], ninja_file.defaults);
+        assert_eq!(8, ninja_file.builds.len());
+
+        let main = &ninja_file.builds[0];
+        assert_eq!(vec!["out/main.o"], main.outputs);
+        assert_eq!(vec!["src/main.c"], main.inputs);
+        assert_eq!(vec!["gen/config.h"], main.implicit_inputs);
+        assert_eq!(
+            "cc -O0 -g -MD -MF out/main.o.d -c src/main.c -o out/main.o",
+            main.command
+        );
+        assert_eq!(Some(String::from("out/main.o.d")), main.depfile);
+        assert_eq!(Some(String::from("CC out/main.o")), main.description);
+
+        let spaced = &ninja_file.builds[1];
+        assert_eq!(vec!["out/my file.o"], spaced.outputs);
+        assert_eq!(vec!["headers"], spaced.order_only_inputs);
+        assert_eq!(
+            "cc -O2 -MD -MF 'out/my file.o'.d -c 'src/my file.c' -o 'out/my file.o'",
+            spaced.command
+        );
+
+        let app = &ninja_file.builds[4];
+        assert_eq!(vec!["out/app.map"], app.implicit_outputs);
+        assert_eq!(Some(String::from("out/app.rsp")), app.rspfile);
+        assert_eq!("out/main.o 'out/my file.o'", app.rspfile_content);
+        assert!(ninja_file.builds[5].generator);
+        assert_eq!(None, ninja_file.builds[7].description);
+
+        assert!(parse_ninja("build a: unknown b\n", |_| Ok(String::new())).is_err());
+        assert!(parse_ninja("build: phony b\n", |_| Ok(String::new())).is_err());
+        assert!(parse_ninja("rule r\n  depfile = d\nbuild a: r\n", |_| Ok(String::new())).is_err());
+    }
+
+    #[test]
+    fn test_ninja_to_task_graph() {
+        let ninja_file = parse_ninja(BUILD_NINJA, |_| Ok(String::new())).expect("parse");
+        let transport = ninja_to_task_graph(&ninja_file).expect("convert to task graph");
+        assert_eq!(
+            vec!["gen/config.h", "out/app", "out/main.o", "out/my file.o"],
+            transport.tasks.keys().collect::<Vec<_>>()
+        );
+
+        let main = &transport.tasks["out/main.o"];
+        assert_eq!(vec!["gen/config.h"], main.dependencies);
+        assert_eq!(PathBuf::from("/bin/sh"), main.task.program.program);
+        assert_eq!("-c", main.task.arguments.arguments[0]);
+        assert_eq!(
+            vec![PathBuf::from("src/main.c"), PathBuf::from("gen/config.h")],
+            main.task.inputs.include_files
+        );
+        assert_eq!(
+            vec![PathBuf::from("out/main.o.d")],
+            main.task.inputs.depfiles
+        );
+
+        // Order-only dependencies through a phony target, which is not an input file.
+        let spaced = &transport.tasks["out/my file.o"];
+        assert_eq!(vec!["gen/config.h"], spaced.dependencies);
+        assert_eq!(
+            vec![PathBuf::from("src/my file.c")],
+            spaced.task.inputs.include_files
+        );
+
+        let app = &transport.tasks["out/app"];
+        assert_eq!(vec!["out/main.o", "out/my file.o"], app.dependencies);
+        assert_eq!(
+            vec![PathBuf::from("out/app"), PathBuf::from("out/app.map")],
+            app.task.outputs.include_files
+        );
+        assert_eq!(
+            "printf '%s' 'out/main.o '\\''out/my file.o'\\''' > out/app.rsp && cc @out/app.rsp -o out/app",
+            app.task.arguments.arguments[1]
+        );
+
+        let graph = TaskGraph::try_from(transport).expect("valid task graph");
+        assert_eq!(
+            vec!["gen/config.h", "out/main.o", "out/my file.o", "out/app"],
+            graph.topological_order()
+        );
+
+        let duplicate = parse_ninja("rule r\n  command = c\nbuild a: r\nbuild a: r\n", |_| {
+            Ok(String::new())
+        })
+        .expect("parse");
+        assert!(ninja_to_task_graph(&duplicate).is_err());
+    }
+}
@@ -0,0 +1,306 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Packages of cached task results, for distributing artifacts to hosts without the cache.
+//!
+//! `export_package` writes a tarball whose entries are all under a `package-<inputs identity>/`
+//! directory:
+//!
+//! - `task_inputs.json`: the canonical task inputs, whose identity is the task's cache key;
+//! - `task_outputs.json`: the identities of the task's input and output files;
+//! - `metadata.json`: the metadata recorded when the task was cached;
+//! - `outputs/`: the content of each output file, at its path relative to the task's working
+//!   directory.
+//!
+//! `crate::cache::Cache::import_package` installs a package in another cache, so that the packaged
+//! task is a cache hit there.
+
+use crate::blob::ReadDeserializer as ReadDeserializerApi;
+use crate::blob::StringSerializer as StringSerializerApi;
+use crate::blob::WriteSerializer as WriteSerializerApi;
+use crate::cache::Cache;
+use crate::cache::Index;
+use crate::fs::Filesystem as FilesystemApi;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::identity::IntoTransport as _;
+use anyhow::Context as _;
+use std::io::Read as _;
+use std::io::Write;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+pub const INPUTS_FILE: &str = "task_inputs.json";
+pub const OUTPUTS_FILE: &str = "task_outputs.json";
+pub const METADATA_FILE: &str = "metadata.json";
+pub const OUTPUTS_DIRECTORY: &str = "outputs";
+
+/// Name of the package of the task whose inputs have identity `inputs_identity`, which is also the
+/// directory under which its entries are written.
+pub fn package_name<IS: IdentitySchemeApi>(inputs_identity: &IS::Identity) -> String {
+    format!("package-{}", inputs_identity.to_string())
+}
+
+/// Writes the package of the task cached in `cache` whose inputs have identity `inputs_identity`
+/// to `writer`, returning `writer`.
+pub fn export_package<FS, IS, S, Idx, W>(
+    cache: &mut Cache<FS, IS, S, Idx>,
+    inputs_identity: &IS::Identity,
+    writer: W,
+) -> anyhow::Result<W>
+where
+    FS: FilesystemApi,
+    IS: IdentitySchemeApi,
+    S: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+    Idx: Index<Filesystem = FS, Identity = IS::Identity, Error = anyhow::Error>,
+    W: Write,
+{
+    let summary = cache.get_summary(inputs_identity)?.ok_or_else(|| {
+        anyhow::anyhow!("no task cached for inputs {}", inputs_identity.to_string())
+    })?;
+    let metadata = cache.get_metadata(inputs_identity)?.ok_or_else(|| {
+        anyhow::anyhow!(
+            "no metadata cached for inputs {}",
+            inputs_identity.to_string()
+        )
+    })?;
+
+    let mut package = TarPackage::new(writer, package_name::<IS>(inputs_identity));
+    package.append(
+        INPUTS_FILE,
+        serde_json::to_string_pretty(&summary.input)?.as_bytes(),
+    )?;
+    package.append(
+        OUTPUTS_FILE,
+        serde_json::to_string_pretty(&summary.output)?.as_bytes(),
+    )?;
+    package.append(
+        METADATA_FILE,
+        serde_json::to_string_pretty(&metadata.into_transport())?.as_bytes(),
+    )?;
+    for (path, identity) in summary.output.output_files.identities.iter() {
+        // Absent optional outputs have no content.
+        let identity = match identity {
+            Some(identity) => identity,
+            None => continue,
+        };
+        let mut contents = vec![];
+        cache
+            .open_blob(identity)
+            .and_then(|mut blob| Ok(blob.read_to_end(&mut contents)?))
+            .with_context(|| format!("reading output {:?} for package", path))?;
+        package.append(output_entry_path(path)?, &contents)?;
+    }
+    package.finish()
+}
+
+/// Path of the entry that holds the content of the output file at `path`, relative to the
+/// package's directory.
+pub fn output_entry_path<P: AsRef<Path>>(path: P) -> anyhow::Result<PathBuf> {
+    let path = path.as_ref();
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        anyhow::bail!(
+            "output path, {:?}, is not a relative path within the working directory",
+            path
+        );
+    }
+    Ok(Path::new(OUTPUTS_DIRECTORY).join(path))
+}
+
+/// Tarball whose entries are all under one directory.
+pub struct TarPackage<W: Write> {
+    builder: tar::Builder<W>,
+    root: PathBuf,
+}
+
+impl<W: Write> TarPackage<W> {
+    pub fn new<P: AsRef<Path>>(writer: W, root: P) -> Self {
+        Self {
+            builder: tar::Builder::new(writer),
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn append<P: AsRef<Path>>(&mut self, path: P, contents: &[u8]) -> anyhow::Result<()> {
+        let path = self.root.join(path);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        // Fixed timestamps keep packages of the same task byte-for-byte identical.
+        header.set_mtime(0);
+        header.set_cksum();
+        self.builder
+            .append_data(&mut header, &path, contents)
+            .with_context(|| format!("adding {:?} to package", path))
+    }
+
+    pub fn finish(self) -> anyhow::Result<W> {
+        self.builder.into_inner().context("finishing package")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::export_package;
+    use super::output_entry_path;
+    use super::package_name;
+    use super::TarPackage;
+    use crate::blob::JSON;
+    use crate::cache::Cache;
+    use crate::cache::WriteOnDropIndex;
+    use crate::canonical::FileIdentitiesManifest;
+    use crate::canonical::FilesManifest;
+    use crate::canonical::TaskInputs;
+    use crate::canonical::TaskOutputs;
+    use crate::execute::identify_task_inputs;
+    use crate::fs::HostFilesystem;
+    use crate::identity::IdentityScheme as _;
+    use crate::transport::ContentSha256;
+    use crate::transport::Sha256;
+    use std::collections::BTreeMap;
+    use std::io::Read as _;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    type TestCache = Cache<
+        HostFilesystem,
+        ContentSha256,
+        JSON,
+        WriteOnDropIndex<HostFilesystem, ContentSha256, JSON>,
+    >;
+
+    fn create_cache(path: &Path) -> TestCache {
+        std::fs::create_dir_all(path).expect("create cache directory");
+        TestCache::create(HostFilesystem::try_new(path.to_path_buf()).expect("cache filesystem"))
+            .expect("create cache")
+    }
+
+    /// Caches a task whose output is `out/artifact.txt`, returning the identity of its inputs.
+    fn put_task(cache: &mut TestCache, working_directory: &Path) -> Sha256 {
+        std::fs::create_dir_all(working_directory.join("out")).expect("create out directory");
+        std::fs::write(working_directory.join("out/artifact.txt"), "artifact")
+            .expect("write artifact.txt");
+        let mut working_filesystem = HostFilesystem::try_new(working_directory.to_path_buf())
+            .expect("working directory filesystem");
+        let inputs = TaskInputs::<ContentSha256>::for_test("argument");
+        let output_files = FilesManifest::new(["out/artifact.txt"])
+            .into_identified::<ContentSha256, _>(&mut working_filesystem);
+        cache
+            .put_blobs(&mut working_filesystem, &output_files)
+            .expect("put blobs");
+        cache
+            .put_task(
+                0,
+                0,
+                inputs.clone(),
+                TaskOutputs::new(FileIdentitiesManifest::empty(), output_files),
+            )
+            .expect("put task");
+        identify_task_inputs(&inputs).expect("identify task inputs")
+    }
+
+    #[test]
+    fn test_export_package() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let mut cache = create_cache(&temporary_directory.path().join("cache"));
+        let inputs_identity = put_task(&mut cache, &temporary_directory.path().join("work"));
+
+        let package = export_package(&mut cache, &inputs_identity, vec![]).expect("export package");
+        let mut archive = tar::Archive::new(package.as_slice());
+        let entries: BTreeMap<PathBuf, String> = archive
+            .entries()
+            .expect("package entries")
+            .map(|entry| {
+                let mut entry = entry.expect("package entry");
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents).expect("read entry");
+                (entry.path().expect("entry path").into_owned(), contents)
+            })
+            .collect();
+        let root = PathBuf::from(format!("package-{}", inputs_identity.to_string()));
+        assert_eq!(
+            vec![
+                root.join("metadata.json"),
+                root.join("outputs/out/artifact.txt"),
+                root.join("task_inputs.json"),
+                root.join("task_outputs.json"),
+            ],
+            entries.keys().cloned().collect::<Vec<_>>()
+        );
+        assert_eq!("artifact", entries[&root.join("outputs/out/artifact.txt")]);
+        assert!(entries[&root.join("task_inputs.json")].contains("argument"));
+
+        let uncached_identity =
+            ContentSha256::identify_content("uncached".as_bytes()).expect("identity");
+        assert!(export_package(&mut cache, &uncached_identity, vec![]).is_err());
+        assert!(output_entry_path("../escape.txt").is_err());
+        assert!(output_entry_path("/absolute.txt").is_err());
+    }
+
+    #[test]
+    fn test_import_package() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let mut source = create_cache(&temporary_directory.path().join("source"));
+        let inputs_identity = put_task(&mut source, &temporary_directory.path().join("work"));
+        let package =
+            export_package(&mut source, &inputs_identity, vec![]).expect("export package");
+
+        let mut destination = create_cache(&temporary_directory.path().join("destination"));
+        assert_eq!(
+            inputs_identity,
+            destination
+                .import_package(package.as_slice())
+                .expect("import package")
+        );
+        let outputs = destination
+            .get_outputs(&inputs_identity)
+            .expect("get outputs")
+            .expect("imported outputs");
+        let (path, identity) = outputs.output_files().next().expect("output file");
+        assert_eq!(&PathBuf::from("out/artifact.txt"), path);
+        let mut contents = String::new();
+        destination
+            .open_blob(identity.as_ref().expect("output identity"))
+            .expect("open output blob")
+            .read_to_string(&mut contents)
+            .expect("read output blob");
+        assert_eq!("artifact", contents);
+        assert!(destination
+            .get_metadata(&inputs_identity)
+            .expect("get metadata")
+            .is_some());
+
+        // Tampered outputs are rejected, and nothing is installed.
+        let mut tampered = TarPackage::new(vec![], package_name::<ContentSha256>(&inputs_identity));
+        for entry in tar::Archive::new(package.as_slice())
+            .entries()
+            .expect("package entries")
+        {
+            let mut entry = entry.expect("package entry");
+            let path = entry.path().expect("entry path").into_owned();
+            let mut contents = vec![];
+            entry.read_to_end(&mut contents).expect("read entry");
+            if path.ends_with("artifact.txt") {
+                contents = b"tampered".to_vec();
+            }
+            tampered
+                .append(
+                    path.strip_prefix(path.iter().next().expect("root"))
+                        .expect("relative path"),
+                    &contents,
+                )
+                .expect("append entry");
+        }
+        let tampered = tampered.finish().expect("finish package");
+        let mut other = create_cache(&temporary_directory.path().join("other"));
+        assert!(other.import_package(tampered.as_slice()).is_err());
+        assert_eq!(
+            None,
+            other.get_outputs(&inputs_identity).expect("get outputs")
+        );
+    }
+}
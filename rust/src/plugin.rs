@@ -0,0 +1,315 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Sandboxed WebAssembly plugins that find the files referenced by a file, for languages whose
+//! references neither match transforms nor the C/C++ `#include` scanner describe precisely.
+//!
+//! A scanner plugin is a core WebAssembly module that imports nothing and exports:
+//!
+//! - `memory`: its linear memory;
+//! - `scanner_alloc(len: i32) -> i32`: allocates `len` bytes, returning their address;
+//! - `scanner_scan(path: i32, path_len: i32, contents: i32, contents_len: i32) -> i64`: scans the
+//!   file at UTF-8 `path` whose contents are `contents`. Returns the address of its result in the
+//!   upper 32 bits and the result's length in the lower 32 bits, or a negative value on failure.
+//!   The result is a UTF-8, newline-separated list of referenced paths, which are resolved like
+//!   the paths produced by match transforms.
+//!
+//! Each file is scanned by a fresh instance of the plugin, so that results do not depend on the
+//! order in which files are scanned. Instances are limited in the fuel (roughly, instructions) they
+//! consume and the memory they allocate. Running plugins requires the `wasm-plugins` feature.
+
+use crate::transport::ScannerPlugin as ScannerPluginTransport;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Default limit on the fuel consumed scanning a single file.
+pub const DEFAULT_MAX_FUEL: u64 = 1_000_000_000;
+
+/// Default limit on the memory allocated scanning a single file.
+pub const DEFAULT_MAX_MEMORY_BYTES: u64 = 64 * 1024 * 1024;
+
+#[cfg(feature = "wasm-plugins")]
+const MEMORY_EXPORT: &str = "memory";
+#[cfg(feature = "wasm-plugins")]
+const ALLOC_EXPORT: &str = "scanner_alloc";
+#[cfg(feature = "wasm-plugins")]
+const SCAN_EXPORT: &str = "scanner_scan";
+
+/// A compiled scanner plugin, and the limits under which it runs.
+pub struct ScannerPlugin {
+    #[cfg(feature = "wasm-plugins")]
+    engine: wasmi::Engine,
+    #[cfg(feature = "wasm-plugins")]
+    module: wasmi::Module,
+    #[cfg(feature = "wasm-plugins")]
+    max_fuel: u64,
+    #[cfg(feature = "wasm-plugins")]
+    max_memory_bytes: u64,
+}
+
+impl ScannerPlugin {
+    /// Compiles the plugin module `wasm` under the limits described by `plugin_config`.
+    #[cfg(feature = "wasm-plugins")]
+    pub fn load(plugin_config: &ScannerPluginTransport, wasm: &[u8]) -> anyhow::Result<Self> {
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        let engine = wasmi::Engine::new(&config);
+        let module = wasmi::Module::new(&engine, wasm)
+            .map_err(|error| anyhow::anyhow!("compiling scanner plugin: {}", error))?;
+        if let Some(import) = module.imports().next() {
+            anyhow::bail!(
+                "scanner plugins may not import anything, but plugin imports {}::{}",
+                import.module(),
+                import.name()
+            );
+        }
+        Ok(Self {
+            engine,
+            module,
+            max_fuel: plugin_config.max_fuel.unwrap_or(DEFAULT_MAX_FUEL),
+            max_memory_bytes: plugin_config
+                .max_memory_bytes
+                .unwrap_or(DEFAULT_MAX_MEMORY_BYTES),
+        })
+    }
+
+    #[cfg(not(feature = "wasm-plugins"))]
+    pub fn load(_plugin_config: &ScannerPluginTransport, _wasm: &[u8]) -> anyhow::Result<Self> {
+        anyhow::bail!("scanner plugins require building with the `wasm-plugins` feature")
+    }
+
+    /// Scans the file at `path` whose contents are `contents`, returning the paths it references.
+    #[cfg(feature = "wasm-plugins")]
+    pub fn scan(&self, path: &Path, contents: &[u8]) -> anyhow::Result<Vec<PathBuf>> {
+        use anyhow::Context as _;
+
+        let path_string = path
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("path is not valid UTF-8: {:?}", path))?;
+        let limits = wasmi::StoreLimitsBuilder::new()
+            .memory_size(usize::try_from(self.max_memory_bytes).unwrap_or(usize::MAX))
+            .instances(1)
+            .build();
+        let mut store = wasmi::Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store
+            .add_fuel(self.max_fuel)
+            .map_err(|error| anyhow::anyhow!("fueling scanner plugin: {}", error))?;
+
+        let instance = wasmi::Linker::<wasmi::StoreLimits>::new(&self.engine)
+            .instantiate(&mut store, &self.module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|error| anyhow::anyhow!("instantiating scanner plugin: {}", error))?;
+        let memory = instance
+            .get_memory(&store, MEMORY_EXPORT)
+            .ok_or_else(|| anyhow::anyhow!("scanner plugin does not export {:?}", MEMORY_EXPORT))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, ALLOC_EXPORT)
+            .map_err(|error| anyhow::anyhow!("finding {:?}: {}", ALLOC_EXPORT, error))?;
+        let scan = instance
+            .get_typed_func::<(i32, i32, i32, i32), i64>(&store, SCAN_EXPORT)
+            .map_err(|error| anyhow::anyhow!("finding {:?}: {}", SCAN_EXPORT, error))?;
+
+        let mut write_argument = |bytes: &[u8]| -> anyhow::Result<(i32, i32)> {
+            let len = i32::try_from(bytes.len()).context("scanner plugin argument is too long")?;
+            let address = alloc
+                .call(&mut store, len)
+                .map_err(|error| anyhow::anyhow!("calling {:?}: {}", ALLOC_EXPORT, error))?;
+            memory
+                .write(&mut store, address as u32 as usize, bytes)
+                .map_err(|error| anyhow::anyhow!("writing scanner plugin argument: {}", error))?;
+            Ok((address, len))
+        };
+        let (path_address, path_len) = write_argument(path_string.as_bytes())?;
+        let (contents_address, contents_len) = write_argument(contents)?;
+        let result = scan
+            .call(
+                &mut store,
+                (path_address, path_len, contents_address, contents_len),
+            )
+            .map_err(|error| anyhow::anyhow!("calling {:?}: {}", SCAN_EXPORT, error))?;
+        if result < 0 {
+            anyhow::bail!("scanner plugin failed with status {}", result);
+        }
+
+        let address = (result as u64 >> 32) as usize;
+        let len = (result as u64 & u64::from(u32::MAX)) as usize;
+        let mut output = vec![0; len];
+        memory
+            .read(&store, address, &mut output)
+            .map_err(|error| anyhow::anyhow!("reading scanner plugin result: {}", error))?;
+        let output = String::from_utf8(output).context("scanner plugin result is not UTF-8")?;
+        Ok(output
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect())
+    }
+
+    #[cfg(not(feature = "wasm-plugins"))]
+    pub fn scan(&self, path: &Path, _contents: &[u8]) -> anyhow::Result<Vec<PathBuf>> {
+        anyhow::bail!(
+            "scanning {:?} requires building with the `wasm-plugins` feature",
+            path
+        )
+    }
+}
+
+#[cfg(all(test, feature = "wasm-plugins"))]
+mod tests {
+    use super::ScannerPlugin;
+    use crate::canonical::FilesManifest;
+    use crate::fs::HostFilesystem;
+    use crate::transport::Inputs as InputsTransport;
+    use crate::transport::InterFileReferences;
+    use crate::transport::ScannerPlugin as ScannerPluginTransport;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    /// Reports the contents of each line of the form `use <path>`, with a bump allocator.
+    const USE_SCANNER: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (func $alloc (export "scanner_alloc") (param $len i32) (result i32)
+            (local $address i32)
+            (local.set $address (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (if (i32.gt_u (global.get $next) (i32.mul (memory.size) (i32.const 65536)))
+              (then (drop (memory.grow (i32.const 1)))))
+            (local.get $address))
+          (func (export "scanner_scan")
+            (param $path i32) (param $path_len i32) (param $contents i32) (param $len i32)
+            (result i64)
+            (local $i i32) (local $end i32) (local $output i32) (local $output_len i32)
+            (local $at_line_start i32) (local $copying i32) (local $byte i32)
+            (local.set $output (call $alloc (local.get $len)))
+            (local.set $end (i32.add (local.get $contents) (local.get $len)))
+            (local.set $i (local.get $contents))
+            (local.set $at_line_start (i32.const 1))
+            (block $done
+              (loop $next_byte
+                (br_if $done (i32.ge_u (local.get $i) (local.get $end)))
+                (local.set $byte (i32.load8_u (local.get $i)))
+                (if (local.get $copying)
+                  (then
+                    (i32.store8
+                      (i32.add (local.get $output) (local.get $output_len))
+                      (local.get $byte))
+                    (local.set $output_len (i32.add (local.get $output_len) (i32.const 1)))))
+                (if (i32.eq (local.get $byte) (i32.const 10))
+                  (then
+                    (local.set $copying (i32.const 0))
+                    (local.set $at_line_start (i32.const 1))
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (br $next_byte)))
+                ;; Start copying after a line-initial "use ".
+                (if (i32.and
+                      (local.get $at_line_start)
+                      (i32.and
+                        (i32.le_u (i32.add (local.get $i) (i32.const 4)) (local.get $end))
+                        (i32.eq (i32.load (local.get $i)) (i32.const 0x20657375))))
+                  (then
+                    (local.set $copying (i32.const 1))
+                    (local.set $i (i32.add (local.get $i) (i32.const 3)))))
+                (local.set $at_line_start (i32.const 0))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $next_byte)))
+            (i64.or
+              (i64.shl (i64.extend_i32_u (local.get $output)) (i64.const 32))
+              (i64.extend_i32_u (local.get $output_len)))))
+    "#;
+
+    const LOOPING_SCANNER: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "scanner_alloc") (param i32) (result i32) (i32.const 0))
+          (func (export "scanner_scan") (param i32 i32 i32 i32) (result i64)
+            (loop $forever (br $forever))
+            (i64.const 0)))
+    "#;
+
+    fn plugin_config() -> ScannerPluginTransport {
+        ScannerPluginTransport {
+            module: PathBuf::from("scanner.wasm"),
+            max_fuel: None,
+            max_memory_bytes: None,
+        }
+    }
+
+    #[test]
+    fn test_scanner_plugin() {
+        let wasm = wat::parse_str(USE_SCANNER).expect("assemble plugin");
+        let plugin = ScannerPlugin::load(&plugin_config(), &wasm).expect("load plugin");
+        assert_eq!(
+            vec![PathBuf::from("a.mod"), PathBuf::from("lib/b.mod")],
+            plugin
+                .scan(
+                    Path::new("main.mod"),
+                    b"use a.mod\n# use c.mod\nuse lib/b.mod\n"
+                )
+                .expect("scan")
+        );
+        // Each scan starts from a fresh instance.
+        assert_eq!(
+            vec![PathBuf::from("a.mod")],
+            plugin
+                .scan(Path::new("main.mod"), b"use a.mod")
+                .expect("scan")
+        );
+
+        let wasm = wat::parse_str(LOOPING_SCANNER).expect("assemble plugin");
+        let plugin = ScannerPlugin::load(
+            &ScannerPluginTransport {
+                max_fuel: Some(10_000),
+                ..plugin_config()
+            },
+            &wasm,
+        )
+        .expect("load plugin");
+        assert!(plugin.scan(Path::new("main.mod"), b"").is_err());
+
+        let wasm = wat::parse_str(r#"(module (import "env" "f" (func)))"#).expect("assemble");
+        assert!(ScannerPlugin::load(&plugin_config(), &wasm).is_err());
+    }
+
+    #[test]
+    fn test_scanner_plugin_inputs() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let wasm = wat::parse_str(USE_SCANNER).expect("assemble plugin");
+        std::fs::write(temporary_directory.path().join("scanner.wasm"), wasm).expect("write");
+        for (path, contents) in [
+            ("main.mod", "use a.mod\nuse missing.mod\n"),
+            ("a.mod", "use b.mod\n"),
+            ("b.mod", ""),
+        ] {
+            std::fs::write(temporary_directory.path().join(path), contents).expect("write");
+        }
+        let mut host_filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let inputs_manifest = FilesManifest::try_from((
+            &mut host_filesystem,
+            InputsTransport {
+                include_files: vec![PathBuf::from("main.mod")],
+                inter_file_references: vec![InterFileReferences {
+                    files_to_match: Some(InputsTransport {
+                        include_globs: vec![String::from("*.mod")],
+                        ..InputsTransport::default()
+                    }),
+                    match_transforms: vec![],
+                    c_includes: None,
+                    scanner_plugin: Some(plugin_config()),
+                    directories_to_search: None,
+                    scan_binary_files: false,
+                }],
+                ..InputsTransport::default()
+            },
+        ))
+        .expect("create inputs manifest");
+        assert_eq!(
+            FilesManifest::new(["a.mod", "b.mod", "main.mod", "scanner.wasm"]),
+            inputs_manifest
+        );
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Profiling of where an invocation spends its time, in the Chrome trace event format loaded by
+//! `chrome://tracing` and Perfetto.
+//!
+//! Once profiling is enabled with `enable`, instrumented code paths record one span per `Phase`
+//! they pass through (e.g., hashing a file, or waiting for a child process), on a track per thread.
+//! Until then, `span` returns a guard that records nothing.
+
+use crate::transport::ChromeTrace;
+use crate::transport::ChromeTraceEvent;
+use anyhow::Context as _;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+static GLOBAL_PROFILER: OnceLock<Profiler> = OnceLock::new();
+
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static THREAD_ID: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Small, stable identifier of the current thread, used as its trace track.
+fn thread_id() -> u64 {
+    THREAD_ID.with(|id| {
+        if id.get() == 0 {
+            id.set(NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed));
+        }
+        id.get()
+    })
+}
+
+/// Phases of resolving, executing, and caching tasks.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Phase {
+    /// Resolving the files described by a task's inputs.
+    ManifestResolution,
+    /// Identifying file content.
+    Hashing,
+    /// Staging inputs and starting a task's program.
+    Spawn,
+    /// Waiting for a task's program to exit.
+    Wait,
+    /// Identifying and recording a task's outputs.
+    OutputCollection,
+    /// Reading and writing cached objects.
+    CacheIo,
+}
+
+impl Phase {
+    /// Category of the phase's trace events.
+    pub fn category(&self) -> &'static str {
+        match self {
+            Self::ManifestResolution => "manifest_resolution",
+            Self::Hashing => "hashing",
+            Self::Spawn => "spawn",
+            Self::Wait => "wait",
+            Self::OutputCollection => "output_collection",
+            Self::CacheIo => "cache_io",
+        }
+    }
+}
+
+/// Records spans relative to the time at which it was created.
+#[derive(Debug)]
+pub struct Profiler {
+    start: Instant,
+    events: Mutex<Vec<ChromeTraceEvent>>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            events: Mutex::new(vec![]),
+        }
+    }
+
+    fn record(&self, phase: Phase, name: String, start: Instant, end: Instant) {
+        let event = ChromeTraceEvent {
+            name,
+            cat: String::from(phase.category()),
+            ph: String::from("X"),
+            ts: start.duration_since(self.start).as_nanos() as f64 / 1000.0,
+            dur: end.duration_since(start).as_nanos() as f64 / 1000.0,
+            pid: std::process::id() as u64,
+            tid: thread_id(),
+            args: BTreeMap::new(),
+        };
+        self.events.lock().expect("profiler lock").push(event);
+    }
+
+    /// Gets the spans recorded so far, ordered by start time.
+    pub fn trace(&self) -> ChromeTrace {
+        let mut trace_events = self.events.lock().expect("profiler lock").clone();
+        trace_events.sort_by(|a, b| a.ts.total_cmp(&b.ts));
+        ChromeTrace {
+            trace_events,
+            display_time_unit: Some(String::from("ms")),
+        }
+    }
+}
+
+/// Starts recording spans for the rest of the process. Fails if profiling is already enabled.
+pub fn enable() -> anyhow::Result<()> {
+    GLOBAL_PROFILER
+        .set(Profiler::new())
+        .map_err(|_| anyhow::anyhow!("profiling already enabled"))
+}
+
+/// Gets the global profiler, if profiling is enabled.
+pub fn global() -> Option<&'static Profiler> {
+    GLOBAL_PROFILER.get()
+}
+
+/// Starts a span of `phase`, named by `name` (which is only called when profiling is enabled), that
+/// ends when the returned guard is dropped.
+pub fn span<F: FnOnce() -> String>(phase: Phase, name: F) -> Span {
+    Span {
+        started: global().map(|profiler| (profiler, phase, name(), Instant::now())),
+    }
+}
+
+/// Guard that records a span when dropped.
+#[must_use = "the span ends when the guard is dropped"]
+pub struct Span {
+    started: Option<(&'static Profiler, Phase, String, Instant)>,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some((profiler, phase, name, start)) = self.started.take() {
+            profiler.record(phase, name, start, Instant::now());
+        }
+    }
+}
+
+/// Enables profiling, and writes the recorded trace as JSON to a file when dropped (e.g., at the
+/// end of `main`, whether or not the invocation succeeded).
+pub struct TraceWriter {
+    path: PathBuf,
+}
+
+impl TraceWriter {
+    pub fn enable<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        enable()?;
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+        })
+    }
+
+    fn write(&self) -> anyhow::Result<()> {
+        let trace = global().map(Profiler::trace).unwrap_or_default();
+        let contents = serde_json::to_string(&trace).context("serializing profile")?;
+        std::fs::write(&self.path, contents)
+            .with_context(|| format!("writing profile to {:?}", self.path))
+    }
+}
+
+impl Drop for TraceWriter {
+    fn drop(&mut self) {
+        if let Err(error) = self.write() {
+            tracing::error!("{:#}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Phase;
+    use super::Profiler;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    #[test]
+    fn test_profiler() {
+        let profiler = Profiler::new();
+        let start = Instant::now();
+        profiler.record(
+            Phase::Wait,
+            String::from("/bin/sh"),
+            start + Duration::from_millis(2),
+            start + Duration::from_millis(5),
+        );
+        profiler.record(
+            Phase::Hashing,
+            String::from("input.txt"),
+            start,
+            start + Duration::from_millis(1),
+        );
+        let trace = profiler.trace();
+
+        let events: Vec<_> = trace
+            .trace_events
+            .iter()
+            .map(|event| (event.name.as_str(), event.cat.as_str(), event.ph.as_str()))
+            .collect();
+        assert_eq!(
+            vec![("input.txt", "hashing", "X"), ("/bin/sh", "wait", "X")],
+            events
+        );
+        let wait = &trace.trace_events[1];
+        assert!((wait.dur - 3000.0).abs() < 1.0);
+        assert_eq!(trace.trace_events[0].tid, wait.tid);
+        assert!(trace.trace_events[0].ts < wait.ts);
+    }
+}
@@ -0,0 +1,846 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Conversions between tasks and messages of the Bazel Remote Execution API (REAPI) v2, so that
+//! artifact-executor caches can front or consume standard remote-execution services.
+//!
+//! Messages are declared with `prost` derives rather than generated from `.proto` files. Only the
+//! subset of `build/bazel/remote/execution/v2/remote_execution.proto` needed to describe tasks is
+//! declared; field tags match the upstream definitions, so encoded messages are wire-compatible,
+//! and unknown fields in decoded messages are ignored. REAPI digests are SHA-256 digests, so only
+//! `ContentSha256` tasks are supported.
+
+use crate::canonical::FileIdentitiesManifest;
+use crate::canonical::TaskInputs;
+use crate::canonical::TaskOutputs;
+use crate::fs::Filesystem as FilesystemApi;
+use crate::identity::IdentityScheme as _;
+use crate::transport::ContentSha256;
+use crate::transport::Sha256;
+use anyhow::Context as _;
+use prost::Message;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// `build.bazel.remote.execution.v2.Digest`.
+#[derive(Clone, Eq, Hash, Message, PartialEq)]
+pub struct Digest {
+    /// Lowercase hex-encoded SHA-256 hash of the content.
+    #[prost(string, tag = "1")]
+    pub hash: String,
+    #[prost(int64, tag = "2")]
+    pub size_bytes: i64,
+}
+
+/// `build.bazel.remote.execution.v2.Command.EnvironmentVariable`.
+#[derive(Clone, Message, PartialEq)]
+pub struct EnvironmentVariable {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+/// `build.bazel.remote.execution.v2.Command`.
+#[derive(Clone, Message, PartialEq)]
+pub struct Command {
+    /// The program followed by its arguments.
+    #[prost(string, repeated, tag = "1")]
+    pub arguments: Vec<String>,
+    /// Sorted by name.
+    #[prost(message, repeated, tag = "2")]
+    pub environment_variables: Vec<EnvironmentVariable>,
+    #[prost(string, tag = "6")]
+    pub working_directory: String,
+    /// Sorted output paths, relative to the working directory.
+    #[prost(string, repeated, tag = "7")]
+    pub output_paths: Vec<String>,
+}
+
+/// `build.bazel.remote.execution.v2.Action`.
+#[derive(Clone, Message, PartialEq)]
+pub struct Action {
+    #[prost(message, optional, tag = "1")]
+    pub command_digest: Option<Digest>,
+    #[prost(message, optional, tag = "2")]
+    pub input_root_digest: Option<Digest>,
+    #[prost(bool, tag = "7")]
+    pub do_not_cache: bool,
+    #[prost(bytes = "vec", tag = "9")]
+    pub salt: Vec<u8>,
+}
+
+/// `build.bazel.remote.execution.v2.FileNode`.
+#[derive(Clone, Message, PartialEq)]
+pub struct FileNode {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(message, optional, tag = "2")]
+    pub digest: Option<Digest>,
+    #[prost(bool, tag = "4")]
+    pub is_executable: bool,
+}
+
+/// `build.bazel.remote.execution.v2.DirectoryNode`.
+#[derive(Clone, Message, PartialEq)]
+pub struct DirectoryNode {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(message, optional, tag = "2")]
+    pub digest: Option<Digest>,
+}
+
+/// `build.bazel.remote.execution.v2.Directory`. Files and directories are sorted by name.
+#[derive(Clone, Message, PartialEq)]
+pub struct Directory {
+    #[prost(message, repeated, tag = "1")]
+    pub files: Vec<FileNode>,
+    #[prost(message, repeated, tag = "2")]
+    pub directories: Vec<DirectoryNode>,
+}
+
+/// `build.bazel.remote.execution.v2.OutputFile`.
+#[derive(Clone, Message, PartialEq)]
+pub struct OutputFile {
+    #[prost(string, tag = "1")]
+    pub path: String,
+    #[prost(message, optional, tag = "2")]
+    pub digest: Option<Digest>,
+    #[prost(bool, tag = "4")]
+    pub is_executable: bool,
+}
+
+/// `build.bazel.remote.execution.v2.ActionResult`.
+#[derive(Clone, Message, PartialEq)]
+pub struct ActionResult {
+    #[prost(message, repeated, tag = "2")]
+    pub output_files: Vec<OutputFile>,
+    #[prost(int32, tag = "4")]
+    pub exit_code: i32,
+    #[prost(message, optional, tag = "6")]
+    pub stdout_digest: Option<Digest>,
+    #[prost(message, optional, tag = "8")]
+    pub stderr_digest: Option<Digest>,
+}
+
+/// `google.rpc.Status`.
+#[derive(Clone, Message, PartialEq)]
+pub struct Status {
+    /// A `google.rpc.Code`.
+    #[prost(int32, tag = "1")]
+    pub code: i32,
+    #[prost(string, tag = "2")]
+    pub message: String,
+}
+
+/// `google.protobuf.Any`.
+#[derive(Clone, Message, PartialEq)]
+pub struct Any {
+    #[prost(string, tag = "1")]
+    pub type_url: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub value: Vec<u8>,
+}
+
+/// `google.longrunning.Operation`. At most one of `error` and `response` is set.
+#[derive(Clone, Message, PartialEq)]
+pub struct Operation {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(bool, tag = "3")]
+    pub done: bool,
+    #[prost(message, optional, tag = "4")]
+    pub error: Option<Status>,
+    #[prost(message, optional, tag = "5")]
+    pub response: Option<Any>,
+}
+
+/// `build.bazel.remote.execution.v2.FindMissingBlobsRequest`.
+#[derive(Clone, Message, PartialEq)]
+pub struct FindMissingBlobsRequest {
+    #[prost(string, tag = "1")]
+    pub instance_name: String,
+    #[prost(message, repeated, tag = "2")]
+    pub blob_digests: Vec<Digest>,
+}
+
+/// `build.bazel.remote.execution.v2.FindMissingBlobsResponse`.
+#[derive(Clone, Message, PartialEq)]
+pub struct FindMissingBlobsResponse {
+    #[prost(message, repeated, tag = "2")]
+    pub missing_blob_digests: Vec<Digest>,
+}
+
+/// `build.bazel.remote.execution.v2.BatchUpdateBlobsRequest.Request`.
+#[derive(Clone, Message, PartialEq)]
+pub struct BatchUpdateBlobsRequestEntry {
+    #[prost(message, optional, tag = "1")]
+    pub digest: Option<Digest>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub data: Vec<u8>,
+}
+
+/// `build.bazel.remote.execution.v2.BatchUpdateBlobsRequest`.
+#[derive(Clone, Message, PartialEq)]
+pub struct BatchUpdateBlobsRequest {
+    #[prost(string, tag = "1")]
+    pub instance_name: String,
+    #[prost(message, repeated, tag = "2")]
+    pub requests: Vec<BatchUpdateBlobsRequestEntry>,
+}
+
+/// `build.bazel.remote.execution.v2.BatchUpdateBlobsResponse.Response`.
+#[derive(Clone, Message, PartialEq)]
+pub struct BatchUpdateBlobsResponseEntry {
+    #[prost(message, optional, tag = "1")]
+    pub digest: Option<Digest>,
+    #[prost(message, optional, tag = "2")]
+    pub status: Option<Status>,
+}
+
+/// `build.bazel.remote.execution.v2.BatchUpdateBlobsResponse`.
+#[derive(Clone, Message, PartialEq)]
+pub struct BatchUpdateBlobsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub responses: Vec<BatchUpdateBlobsResponseEntry>,
+}
+
+/// `build.bazel.remote.execution.v2.BatchReadBlobsRequest`.
+#[derive(Clone, Message, PartialEq)]
+pub struct BatchReadBlobsRequest {
+    #[prost(string, tag = "1")]
+    pub instance_name: String,
+    #[prost(message, repeated, tag = "2")]
+    pub digests: Vec<Digest>,
+}
+
+/// `build.bazel.remote.execution.v2.BatchReadBlobsResponse.Response`.
+#[derive(Clone, Message, PartialEq)]
+pub struct BatchReadBlobsResponseEntry {
+    #[prost(message, optional, tag = "1")]
+    pub digest: Option<Digest>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub data: Vec<u8>,
+    #[prost(message, optional, tag = "3")]
+    pub status: Option<Status>,
+}
+
+/// `build.bazel.remote.execution.v2.BatchReadBlobsResponse`.
+#[derive(Clone, Message, PartialEq)]
+pub struct BatchReadBlobsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub responses: Vec<BatchReadBlobsResponseEntry>,
+}
+
+/// `build.bazel.remote.execution.v2.GetActionResultRequest`.
+#[derive(Clone, Message, PartialEq)]
+pub struct GetActionResultRequest {
+    #[prost(string, tag = "1")]
+    pub instance_name: String,
+    #[prost(message, optional, tag = "2")]
+    pub action_digest: Option<Digest>,
+}
+
+/// `build.bazel.remote.execution.v2.UpdateActionResultRequest`.
+#[derive(Clone, Message, PartialEq)]
+pub struct UpdateActionResultRequest {
+    #[prost(string, tag = "1")]
+    pub instance_name: String,
+    #[prost(message, optional, tag = "2")]
+    pub action_digest: Option<Digest>,
+    #[prost(message, optional, tag = "3")]
+    pub action_result: Option<ActionResult>,
+}
+
+/// `build.bazel.remote.execution.v2.ExecuteRequest`.
+#[derive(Clone, Message, PartialEq)]
+pub struct ExecuteRequest {
+    #[prost(string, tag = "1")]
+    pub instance_name: String,
+    #[prost(bool, tag = "3")]
+    pub skip_cache_lookup: bool,
+    #[prost(message, optional, tag = "6")]
+    pub action_digest: Option<Digest>,
+}
+
+/// `build.bazel.remote.execution.v2.ExecuteResponse`.
+#[derive(Clone, Message, PartialEq)]
+pub struct ExecuteResponse {
+    #[prost(message, optional, tag = "1")]
+    pub result: Option<ActionResult>,
+    #[prost(bool, tag = "2")]
+    pub cached_result: bool,
+    #[prost(message, optional, tag = "3")]
+    pub status: Option<Status>,
+}
+
+/// `build.bazel.remote.execution.v2.GetCapabilitiesRequest`.
+#[derive(Clone, Message, PartialEq)]
+pub struct GetCapabilitiesRequest {
+    #[prost(string, tag = "1")]
+    pub instance_name: String,
+}
+
+/// `build.bazel.remote.execution.v2.DigestFunction.Value` values.
+pub mod digest_function {
+    pub const SHA256: i32 = 1;
+}
+
+/// `build.bazel.remote.execution.v2.SymlinkAbsolutePathStrategy.Value` values.
+pub mod symlink_absolute_path_strategy {
+    pub const DISALLOWED: i32 = 1;
+}
+
+/// `build.bazel.semver.SemVer`.
+#[derive(Clone, Message, PartialEq)]
+pub struct SemVer {
+    #[prost(int32, tag = "1")]
+    pub major: i32,
+    #[prost(int32, tag = "2")]
+    pub minor: i32,
+    #[prost(int32, tag = "3")]
+    pub patch: i32,
+}
+
+/// `build.bazel.remote.execution.v2.ActionCacheUpdateCapabilities`.
+#[derive(Clone, Message, PartialEq)]
+pub struct ActionCacheUpdateCapabilities {
+    #[prost(bool, tag = "1")]
+    pub update_enabled: bool,
+}
+
+/// `build.bazel.remote.execution.v2.CacheCapabilities`.
+#[derive(Clone, Message, PartialEq)]
+pub struct CacheCapabilities {
+    /// `digest_function` values.
+    #[prost(int32, repeated, tag = "1")]
+    pub digest_functions: Vec<i32>,
+    #[prost(message, optional, tag = "2")]
+    pub action_cache_update_capabilities: Option<ActionCacheUpdateCapabilities>,
+    /// Largest total size of the blobs in a batch request; zero means no limit.
+    #[prost(int64, tag = "4")]
+    pub max_batch_total_size_bytes: i64,
+    /// A `symlink_absolute_path_strategy` value.
+    #[prost(int32, tag = "5")]
+    pub symlink_absolute_path_strategy: i32,
+}
+
+/// `build.bazel.remote.execution.v2.ExecutionCapabilities`.
+#[derive(Clone, Message, PartialEq)]
+pub struct ExecutionCapabilities {
+    /// A `digest_function` value.
+    #[prost(int32, tag = "1")]
+    pub digest_function: i32,
+    #[prost(bool, tag = "2")]
+    pub exec_enabled: bool,
+    /// `digest_function` values.
+    #[prost(int32, repeated, tag = "5")]
+    pub digest_functions: Vec<i32>,
+}
+
+/// `build.bazel.remote.execution.v2.ServerCapabilities`.
+#[derive(Clone, Message, PartialEq)]
+pub struct ServerCapabilities {
+    #[prost(message, optional, tag = "1")]
+    pub cache_capabilities: Option<CacheCapabilities>,
+    #[prost(message, optional, tag = "2")]
+    pub execution_capabilities: Option<ExecutionCapabilities>,
+    #[prost(message, optional, tag = "4")]
+    pub low_api_version: Option<SemVer>,
+    #[prost(message, optional, tag = "5")]
+    pub high_api_version: Option<SemVer>,
+}
+
+/// `google.bytestream.ReadRequest`.
+#[derive(Clone, Message, PartialEq)]
+pub struct ReadRequest {
+    #[prost(string, tag = "1")]
+    pub resource_name: String,
+    #[prost(int64, tag = "2")]
+    pub read_offset: i64,
+    /// Maximum number of bytes to read; zero means no limit.
+    #[prost(int64, tag = "3")]
+    pub read_limit: i64,
+}
+
+/// `google.bytestream.ReadResponse`.
+#[derive(Clone, Message, PartialEq)]
+pub struct ReadResponse {
+    #[prost(bytes = "vec", tag = "10")]
+    pub data: Vec<u8>,
+}
+
+/// `google.bytestream.WriteRequest`.
+#[derive(Clone, Message, PartialEq)]
+pub struct WriteRequest {
+    /// Required only in the first request of a write.
+    #[prost(string, tag = "1")]
+    pub resource_name: String,
+    #[prost(int64, tag = "2")]
+    pub write_offset: i64,
+    #[prost(bool, tag = "3")]
+    pub finish_write: bool,
+    #[prost(bytes = "vec", tag = "10")]
+    pub data: Vec<u8>,
+}
+
+/// `google.bytestream.WriteResponse`.
+#[derive(Clone, Message, PartialEq)]
+pub struct WriteResponse {
+    #[prost(int64, tag = "1")]
+    pub committed_size: i64,
+}
+
+/// An `Action` together with the messages it refers to by digest, all of which must be present in
+/// a remote content-addressable store before the action is executed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RemoteAction {
+    pub action: Action,
+    pub command: Command,
+    /// The input root directory, followed by all of its descendant directories.
+    pub input_directories: Vec<Directory>,
+}
+
+impl RemoteAction {
+    pub fn action_digest(&self) -> anyhow::Result<Digest> {
+        digest_message(&self.action)
+    }
+}
+
+/// Computes the digest of the encoded form of `message`.
+pub fn digest_message<M: Message>(message: &M) -> anyhow::Result<Digest> {
+    digest_content(&message.encode_to_vec())
+}
+
+/// Computes the digest of `content`.
+pub fn digest_content(content: &[u8]) -> anyhow::Result<Digest> {
+    Ok(Digest {
+        hash: ContentSha256::identify_content(content)?.to_string(),
+        size_bytes: i64::try_from(content.len())?,
+    })
+}
+
+fn digest_file<FS: FilesystemApi>(
+    filesystem: &mut FS,
+    path: &Path,
+    identity: &Sha256,
+) -> anyhow::Result<Digest> {
+    let mut file = filesystem
+        .open_file_for_read(path)
+        .map_err(anyhow::Error::from)?;
+    let size_bytes = std::io::copy(&mut file, &mut std::io::sink())?;
+    Ok(Digest {
+        hash: identity.to_string(),
+        size_bytes: i64::try_from(size_bytes)?,
+    })
+}
+
+fn is_executable<FS: FilesystemApi>(filesystem: &mut FS, path: &Path) -> anyhow::Result<bool> {
+    Ok(filesystem
+        .metadata(path)
+        .map_err(anyhow::Error::from)?
+        .executable)
+}
+
+fn path_to_string(path: &Path) -> anyhow::Result<String> {
+    path.to_str()
+        .map(String::from)
+        .ok_or_else(|| anyhow::anyhow!("path, {:?}, cannot be encoded as a string", path))
+}
+
+/// Input files arranged into directories, from which the Merkle tree of `Directory` messages is
+/// built.
+#[derive(Default)]
+struct DirectoryTree {
+    /// Maps file names to their digests and whether they are executable.
+    files: BTreeMap<String, (Digest, bool)>,
+    directories: BTreeMap<String, DirectoryTree>,
+}
+
+impl DirectoryTree {
+    fn insert(&mut self, path: &Path, digest: Digest, is_executable: bool) -> anyhow::Result<()> {
+        let mut names = vec![];
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => names.push(path_to_string(Path::new(name))?),
+                Component::CurDir => {}
+                _ => anyhow::bail!(
+                    "input file path, {:?}, is not a normalized relative path",
+                    path
+                ),
+            }
+        }
+        let file_name = names
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("input file path, {:?}, is empty", path))?;
+        let mut directory = self;
+        for name in names {
+            directory = directory.directories.entry(name).or_default();
+        }
+        directory.files.insert(file_name, (digest, is_executable));
+        Ok(())
+    }
+
+    /// Appends `Directory` messages for this directory's descendants, then this directory, to
+    /// `directories`, and returns this directory's digest.
+    fn build(self, directories: &mut Vec<Directory>) -> anyhow::Result<Digest> {
+        let mut directory = Directory {
+            files: self
+                .files
+                .into_iter()
+                .map(|(name, (digest, is_executable))| FileNode {
+                    name,
+                    digest: Some(digest),
+                    is_executable,
+                })
+                .collect(),
+            directories: vec![],
+        };
+        for (name, subdirectory) in self.directories {
+            let digest = subdirectory.build(directories)?;
+            directory.directories.push(DirectoryNode {
+                name,
+                digest: Some(digest),
+            });
+        }
+        let digest = digest_message(&directory)?;
+        directories.push(directory);
+        Ok(digest)
+    }
+}
+
+/// Describes `inputs` as a remote `Action`. Input files that are recorded as absent are omitted
+/// from the input root, and the program is only included when it is also an input file. File sizes
+/// are read from `filesystem`, and input files are marked executable when they are executable in
+/// `filesystem`.
+pub fn remote_action_from_task_inputs<FS: FilesystemApi>(
+    filesystem: &mut FS,
+    inputs: &TaskInputs<ContentSha256>,
+) -> anyhow::Result<RemoteAction> {
+    if let Some(argv0) = inputs.argv0() {
+        anyhow::bail!(
+            "remote actions cannot override argv[0] (to {:?}); the program path is always argv[0]",
+            argv0
+        );
+    }
+    if let Some(stdin) = inputs.stdin() {
+        anyhow::bail!(
+            "remote actions cannot supply standard input, {:?}, to the program",
+            stdin
+        );
+    }
+    let mut arguments = vec![path_to_string(inputs.program())?];
+    arguments.extend(inputs.arguments().cloned());
+    let mut environment_variables: Vec<_> = inputs
+        .environment_variables()
+        .map(|(name, value)| EnvironmentVariable {
+            name: name.clone(),
+            value: value.clone(),
+        })
+        .collect();
+    environment_variables.sort_by(|variable1, variable2| variable1.name.cmp(&variable2.name));
+    let output_paths = inputs
+        .output_paths()?
+        .iter()
+        .map(|path| path_to_string(path))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let command = Command {
+        arguments,
+        environment_variables,
+        working_directory: String::new(),
+        output_paths,
+    };
+
+    let mut input_root = DirectoryTree::default();
+    for (path, identity) in inputs.input_files() {
+        if let Some(identity) = identity {
+            let digest = digest_file(filesystem, path, identity)
+                .with_context(|| format!("computing digest of input file {:?}", path))?;
+            let is_executable = is_executable(filesystem, path)
+                .with_context(|| format!("reading permissions of input file {:?}", path))?;
+            input_root.insert(inputs.staged_path(path), digest, is_executable)?;
+        }
+    }
+    let mut input_directories = vec![];
+    let input_root_digest = input_root.build(&mut input_directories)?;
+    input_directories.reverse();
+
+    Ok(RemoteAction {
+        action: Action {
+            command_digest: Some(digest_message(&command)?),
+            input_root_digest: Some(input_root_digest),
+            do_not_cache: false,
+            salt: vec![],
+        },
+        command,
+        input_directories,
+    })
+}
+
+/// Describes `outputs` as the successful `ActionResult` of a remote action. File sizes and
+/// permissions are read from `filesystem`. Output files that were not produced are omitted.
+pub fn action_result_from_task_outputs<FS: FilesystemApi>(
+    filesystem: &mut FS,
+    outputs: &TaskOutputs<ContentSha256>,
+) -> anyhow::Result<ActionResult> {
+    let mut output_files = vec![];
+    for (path, identity) in outputs.output_files() {
+        if let Some(identity) = identity {
+            output_files.push(OutputFile {
+                path: path_to_string(path)?,
+                digest: Some(
+                    digest_file(filesystem, path, identity)
+                        .with_context(|| format!("computing digest of output file {:?}", path))?,
+                ),
+                is_executable: is_executable(filesystem, path)
+                    .with_context(|| format!("reading permissions of output file {:?}", path))?,
+            });
+        }
+    }
+    Ok(ActionResult {
+        output_files,
+        exit_code: 0,
+        stdout_digest: None,
+        stderr_digest: None,
+    })
+}
+
+/// Interprets the `ActionResult` of remotely executing `inputs` as the task's outputs. Fails if the
+/// action did not succeed, or if it reports outputs that are not described and the description's
+/// verification policy does not allow extra outputs. Expected outputs that are missing from
+/// `action_result` are recorded as absent, unless they are optional and the policy allows missing
+/// optional outputs, in which case they are omitted.
+pub fn task_outputs_from_action_result<FS: FilesystemApi>(
+    filesystem: &mut FS,
+    inputs: &TaskInputs<ContentSha256>,
+    action_result: &ActionResult,
+) -> anyhow::Result<TaskOutputs<ContentSha256>> {
+    if action_result.exit_code != 0 {
+        anyhow::bail!(
+            "remote action failed with exit code {}",
+            action_result.exit_code
+        );
+    }
+    let outputs_description = inputs.outputs_description();
+    let expected_paths: BTreeSet<PathBuf> = inputs.output_paths()?.into_iter().collect();
+    let mut output_files: BTreeMap<PathBuf, Option<Sha256>> = expected_paths
+        .iter()
+        .filter(|path| !outputs_description.may_be_missing(path))
+        .map(|path| (path.clone(), None))
+        .collect();
+    for output_file in action_result.output_files.iter() {
+        if !outputs_description.verification().allows_extra()
+            && !expected_paths.contains(Path::new(&output_file.path))
+        {
+            anyhow::bail!(
+                "remote action reported output file, {:?}, that is not in the outputs description",
+                output_file.path
+            );
+        }
+        let digest = output_file.digest.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("remote output file, {:?}, has no digest", output_file.path)
+        })?;
+        let identity = Sha256::try_from(digest.hash.as_str())
+            .with_context(|| format!("parsing digest of remote output {:?}", output_file.path))?;
+        output_files.insert(PathBuf::from(&output_file.path), Some(identity));
+    }
+    Ok(TaskOutputs::new(
+        inputs.identify_input_files_with_program(filesystem)?,
+        FileIdentitiesManifest::new(output_files),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::action_result_from_task_outputs;
+    use super::digest_message;
+    use super::remote_action_from_task_inputs;
+    use super::task_outputs_from_action_result;
+    use super::Action;
+    use super::Command;
+    use super::Digest;
+    use crate::canonical::Arguments;
+    use crate::canonical::EnvironmentVariables;
+    use crate::canonical::FileIdentitiesManifest;
+    use crate::canonical::Outputs;
+    use crate::canonical::Program;
+    use crate::canonical::TaskInputs;
+    use crate::canonical::TaskOutputs;
+    use crate::fs::Filesystem as _;
+    use crate::fs::HostFilesystem;
+    use crate::identity::IdentityScheme as _;
+    use crate::transport::ContentSha256;
+    use crate::transport::OutputsVerification;
+    use prost::Message as _;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_remote_action() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        std::fs::create_dir_all(temporary_directory.path().join("src/lib"))
+            .expect("manually create directories");
+        for (path, contents) in [
+            ("program", "#!/bin/sh\n"),
+            ("src/main.c", "main\n"),
+            ("src/lib/a.h", "a\n"),
+            ("out.o", "object\n"),
+        ] {
+            std::fs::write(temporary_directory.path().join(path), contents)
+                .expect("manually create file");
+        }
+        let mut filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let identify = |contents: &str| {
+            Some(ContentSha256::identify_content(contents.as_bytes()).expect("identify content"))
+        };
+        let task_inputs = |verification: OutputsVerification| {
+            TaskInputs::<ContentSha256>::new(
+                EnvironmentVariables::new([("Z", "z"), ("A", "a")]),
+                Program::new("program"),
+                Arguments::new(["-c", "src/main.c"]),
+                FileIdentitiesManifest::new([
+                    ("src/main.c", identify("main\n")),
+                    ("src/lib/a.h", identify("a\n")),
+                    ("src/missing.h", None),
+                ]),
+                Outputs::new(
+                    ["out.o", "out.d"],
+                    Outputs::empty_include_match_transforms(),
+                    [],
+                )
+                .with_verification(verification),
+            )
+        };
+        let inputs = task_inputs(OutputsVerification::default());
+
+        let remote_action =
+            remote_action_from_task_inputs(&mut filesystem, &inputs).expect("remote action");
+        assert_eq!(
+            vec!["program", "-c", "src/main.c"],
+            remote_action.command.arguments
+        );
+        assert_eq!(
+            vec!["A", "Z"],
+            remote_action
+                .command
+                .environment_variables
+                .iter()
+                .map(|variable| variable.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec!["out.d", "out.o"], remote_action.command.output_paths);
+        assert_eq!(
+            Some(digest_message(&remote_action.command).expect("command digest")),
+            remote_action.action.command_digest
+        );
+
+        // Input root contains `src/`, which contains `main.c` and `lib/`, which contains `a.h`.
+        assert_eq!(3, remote_action.input_directories.len());
+        let root = &remote_action.input_directories[0];
+        assert_eq!(
+            Some(digest_message(root).expect("root digest")),
+            remote_action.action.input_root_digest
+        );
+        assert!(root.files.is_empty());
+        assert_eq!("src", root.directories[0].name);
+        let src = remote_action
+            .input_directories
+            .iter()
+            .find(|directory| {
+                Some(digest_message(*directory).expect("directory digest"))
+                    == root.directories[0].digest
+            })
+            .expect("src directory");
+        assert_eq!("main.c", src.files[0].name);
+        assert_eq!(
+            Some(Digest {
+                hash: identify("main\n").expect("identity").to_string(),
+                size_bytes: 5,
+            }),
+            src.files[0].digest
+        );
+        assert!(!src.files[0].is_executable);
+        assert_eq!("lib", src.directories[0].name);
+
+        // Executable input files are marked executable in the input root.
+        filesystem
+            .mark_as_executable("src/main.c")
+            .expect("mark input as executable");
+        let executable_action =
+            remote_action_from_task_inputs(&mut filesystem, &inputs).expect("remote action");
+        let executable_src = executable_action
+            .input_directories
+            .iter()
+            .find(|directory| directory.files.iter().any(|file| file.name == "main.c"))
+            .expect("src directory");
+        assert!(executable_src.files[0].is_executable);
+        assert_ne!(
+            executable_action.action.input_root_digest,
+            remote_action.action.input_root_digest
+        );
+
+        // Messages round-trip through their wire encoding.
+        let action_bytes = remote_action.action.encode_to_vec();
+        assert_eq!(
+            remote_action.action,
+            Action::decode(action_bytes.as_slice()).expect("decode action")
+        );
+        assert_eq!(
+            remote_action.command,
+            Command::decode(remote_action.command.encode_to_vec().as_slice())
+                .expect("decode command")
+        );
+
+        // Outputs round-trip through `ActionResult`; `out.d` was not produced.
+        let outputs = TaskOutputs::<ContentSha256>::new(
+            inputs
+                .identify_input_files_with_program(&mut filesystem)
+                .expect("input files with program"),
+            FileIdentitiesManifest::new([
+                (PathBuf::from("out.d"), None),
+                (PathBuf::from("out.o"), identify("object\n")),
+            ]),
+        );
+        let action_result =
+            action_result_from_task_outputs(&mut filesystem, &outputs).expect("action result");
+        assert_eq!(1, action_result.output_files.len());
+        assert!(!action_result.output_files[0].is_executable);
+        filesystem
+            .mark_as_executable("out.o")
+            .expect("mark output as executable");
+        assert!(
+            action_result_from_task_outputs(&mut filesystem, &outputs)
+                .expect("action result")
+                .output_files[0]
+                .is_executable
+        );
+        assert_eq!(
+            outputs,
+            task_outputs_from_action_result(&mut filesystem, &inputs, &action_result)
+                .expect("task outputs")
+        );
+
+        // Undescribed outputs are rejected unless the verification policy allows extra outputs.
+        let mut extra_result = action_result.clone();
+        let mut extra_file = extra_result.output_files[0].clone();
+        extra_file.path = String::from("extra.o");
+        extra_result.output_files.push(extra_file);
+        assert!(task_outputs_from_action_result(&mut filesystem, &inputs, &extra_result).is_err());
+        assert!(task_outputs_from_action_result(
+            &mut filesystem,
+            &task_inputs(OutputsVerification::AllowExtra),
+            &extra_result
+        )
+        .is_ok());
+
+        let failed_result = super::ActionResult {
+            exit_code: 1,
+            ..action_result
+        };
+        assert!(task_outputs_from_action_result(&mut filesystem, &inputs, &failed_result).is_err());
+    }
+}
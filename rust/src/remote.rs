@@ -0,0 +1,581 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Client for the HTTP cache protocol served by `crate::serve`, so that machines (e.g., a CI fleet)
+//! can share execution results through a cache server.
+//!
+//! Blobs are read and written by identity, and every blob that is read is verified against its
+//! identity before it is returned. Only plain `http://` URLs are supported; use a TLS-terminating
+//! proxy to reach a server over HTTPS.
+
+use crate::events;
+use crate::events::Event;
+use crate::events::TransferDirection;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::serve::parse_identity;
+use crate::sync::PointerKind;
+use crate::sync::SyncPeer;
+use anyhow::Context as _;
+use std::io::BufRead as _;
+use std::io::BufReader;
+use std::io::Read as _;
+use std::io::Write as _;
+use std::marker::PhantomData;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs as _;
+use std::time::Duration;
+
+/// Default for `RemoteBlobCacheOptions::timeout`.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RemoteBlobCacheOptions {
+    /// Bearer token presented with each request. Default: Requests are not authenticated.
+    pub token: Option<String>,
+    /// Timeout for connecting, and for each read from or write to a connection.
+    pub timeout: Duration,
+    /// Never write to the remote cache; only read through it.
+    pub read_only: bool,
+}
+
+impl Default for RemoteBlobCacheOptions {
+    fn default() -> Self {
+        Self {
+            token: None,
+            timeout: DEFAULT_TIMEOUT,
+            read_only: false,
+        }
+    }
+}
+
+/// Blobs and task pointers stored by a cache server.
+#[derive(Clone)]
+pub struct RemoteBlobCache<IdentityScheme: IdentitySchemeApi> {
+    /// Host and port, as sent in the `Host` header.
+    authority: String,
+    /// Path under which the cache is served, without a trailing `/`.
+    path_prefix: String,
+    options: RemoteBlobCacheOptions,
+    _marker: PhantomData<IdentityScheme>,
+}
+
+struct Response {
+    status: u16,
+    body: Vec<u8>,
+}
+
+impl<IdentityScheme: IdentitySchemeApi> RemoteBlobCache<IdentityScheme> {
+    /// Connects to the cache served at `url` (e.g., `http://cache.example.com:8080/`) for each
+    /// request.
+    pub fn new(url: &str, options: RemoteBlobCacheOptions) -> anyhow::Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| anyhow::anyhow!("remote cache URL {:?} is not an http:// URL", url))?;
+        let (authority, path_prefix) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            anyhow::bail!("remote cache URL {:?} has no host", url);
+        }
+        let authority = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+        Ok(Self {
+            authority,
+            path_prefix: path_prefix.trim_end_matches('/').to_string(),
+            options,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.options.read_only
+    }
+
+    pub fn contains_blob(&mut self, identity: &IdentityScheme::Identity) -> anyhow::Result<bool> {
+        let response = self.request("HEAD", "blobs", identity, &[])?;
+        match response.status {
+            200 => Ok(true),
+            404 => Ok(false),
+            _ => Err(response.error("checking for blob")),
+        }
+    }
+
+    /// Reads the blob identified by `identity`, or `None` if the remote cache does not contain it.
+    pub fn read_blob(
+        &mut self,
+        identity: &IdentityScheme::Identity,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let response = self.request("GET", "blobs", identity, &[])?;
+        match response.status {
+            200 => {}
+            404 => return Ok(None),
+            _ => return Err(response.error("reading blob")),
+        }
+        let computed_identity = IdentityScheme::identify_content(response.body.as_slice())?;
+        if &computed_identity != identity {
+            anyhow::bail!(
+                "remote blob identified as {:?} has computed identity {:?}",
+                identity,
+                computed_identity
+            );
+        }
+        events::publish(&Event::BlobTransferred {
+            direction: TransferDirection::Download,
+            hash: &identity.to_string(),
+            bytes: response.body.len() as u64,
+        });
+        Ok(Some(response.body))
+    }
+
+    /// Stores `content`, which must have identity `identity`.
+    pub fn write_blob(
+        &mut self,
+        identity: &IdentityScheme::Identity,
+        content: &[u8],
+    ) -> anyhow::Result<()> {
+        self.check_writable()?;
+        let response = self.request("PUT", "blobs", identity, content)?;
+        match response.status {
+            // The server already contained the blob.
+            200 => Ok(()),
+            201 => {
+                events::publish(&Event::BlobTransferred {
+                    direction: TransferDirection::Upload,
+                    hash: &identity.to_string(),
+                    bytes: content.len() as u64,
+                });
+                Ok(())
+            }
+            _ => Err(response.error("writing blob")),
+        }
+    }
+
+    /// Reads the pointer of `kind` from `source`, or `None` if there is none.
+    pub fn read_blob_pointer(
+        &mut self,
+        kind: PointerKind,
+        source: &IdentityScheme::Identity,
+    ) -> anyhow::Result<Option<IdentityScheme::Identity>> {
+        let response = self.request("GET", collection(kind), source, &[])?;
+        match response.status {
+            200 => {}
+            404 => return Ok(None),
+            _ => return Err(response.error("reading pointer")),
+        }
+        let destination = std::str::from_utf8(&response.body)
+            .ok()
+            .and_then(|body| parse_identity::<IdentityScheme>(body.trim()))
+            .ok_or_else(|| anyhow::anyhow!("malformed remote pointer from {:?}", source))?;
+        Ok(Some(destination))
+    }
+
+    /// Records a pointer of `kind` from `source` to `destination`, which must already be stored.
+    /// The server keeps any existing pointer from `source`.
+    pub fn write_blob_pointer(
+        &mut self,
+        kind: PointerKind,
+        source: &IdentityScheme::Identity,
+        destination: &IdentityScheme::Identity,
+    ) -> anyhow::Result<()> {
+        self.check_writable()?;
+        let response = self.request(
+            "PUT",
+            collection(kind),
+            source,
+            destination.to_string().as_bytes(),
+        )?;
+        match response.status {
+            200 | 201 => Ok(()),
+            _ => Err(response.error("writing pointer")),
+        }
+    }
+
+    fn check_writable(&self) -> anyhow::Result<()> {
+        if self.options.read_only {
+            anyhow::bail!("remote cache at {} is read-only", self.authority);
+        }
+        Ok(())
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        collection: &str,
+        identity: &IdentityScheme::Identity,
+        body: &[u8],
+    ) -> anyhow::Result<Response> {
+        let path = format!(
+            "{}/{}/{}",
+            self.path_prefix,
+            collection,
+            identity.to_string()
+        );
+        self.send(method, &path, body)
+            .with_context(|| format!("{} http://{}{}", method, self.authority, path))
+    }
+
+    fn send(&self, method: &str, path: &str, body: &[u8]) -> anyhow::Result<Response> {
+        let address = self
+            .authority
+            .to_socket_addrs()
+            .context("resolving remote cache address")?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("remote cache address resolved to nothing"))?;
+        let mut stream = TcpStream::connect_timeout(&address, self.options.timeout)
+            .context("connecting to remote cache")?;
+        stream.set_read_timeout(Some(self.options.timeout))?;
+        stream.set_write_timeout(Some(self.options.timeout))?;
+
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            method,
+            path,
+            self.authority,
+            body.len()
+        );
+        if let Some(token) = self.options.token.as_ref() {
+            request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(body)?;
+        stream.flush().context("writing request")?;
+
+        let mut reader = BufReader::new(stream);
+        let mut status_line = String::new();
+        reader
+            .read_line(&mut status_line)
+            .context("reading status line")?;
+        let status = status_line
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or_else(|| anyhow::anyhow!("malformed status line {:?}", status_line))?;
+
+        let mut content_length = None;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).context("reading header")?;
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    content_length = Some(
+                        value
+                            .trim()
+                            .parse::<u64>()
+                            .context("malformed content-length")?,
+                    );
+                }
+            }
+        }
+
+        let mut body = vec![];
+        if method != "HEAD" {
+            match content_length {
+                Some(content_length) => {
+                    reader.take(content_length).read_to_end(&mut body)?;
+                    if body.len() as u64 != content_length {
+                        anyhow::bail!("response body shorter than content-length");
+                    }
+                }
+                None => {
+                    reader.read_to_end(&mut body)?;
+                }
+            }
+        }
+        Ok(Response { status, body })
+    }
+}
+
+impl Response {
+    fn error(&self, action: &str) -> anyhow::Error {
+        anyhow::anyhow!(
+            "{}: remote cache responded {}: {}",
+            action,
+            self.status,
+            String::from_utf8_lossy(&self.body).trim()
+        )
+    }
+}
+
+fn collection(kind: PointerKind) -> &'static str {
+    match kind {
+        PointerKind::Outputs => "outputs",
+        PointerKind::Metadata => "metadata",
+    }
+}
+
+/// Remote caches take part in synchronization as destinations, and as sources of the particular
+/// pointers and blobs that a destination asks for; the protocol cannot list a cache's contents.
+impl<IdentityScheme: IdentitySchemeApi> SyncPeer<IdentityScheme>
+    for RemoteBlobCache<IdentityScheme>
+{
+    fn blob_identities(&mut self) -> anyhow::Result<Vec<IdentityScheme::Identity>> {
+        anyhow::bail!("remote caches cannot list their blobs")
+    }
+
+    fn missing_blobs(
+        &mut self,
+        identities: &[IdentityScheme::Identity],
+    ) -> anyhow::Result<Vec<IdentityScheme::Identity>> {
+        let mut missing = vec![];
+        for identity in identities {
+            if !self.contains_blob(identity)? {
+                missing.push(identity.clone());
+            }
+        }
+        Ok(missing)
+    }
+
+    fn read_blob(&mut self, identity: &IdentityScheme::Identity) -> anyhow::Result<Vec<u8>> {
+        RemoteBlobCache::read_blob(self, identity)?
+            .ok_or_else(|| anyhow::anyhow!("remote cache does not contain blob {:?}", identity))
+    }
+
+    fn write_blob(
+        &mut self,
+        identity: &IdentityScheme::Identity,
+        content: &[u8],
+    ) -> anyhow::Result<()> {
+        RemoteBlobCache::write_blob(self, identity, content)
+    }
+
+    fn pointers(
+        &mut self,
+        _kind: PointerKind,
+    ) -> anyhow::Result<Vec<(IdentityScheme::Identity, IdentityScheme::Identity)>> {
+        anyhow::bail!("remote caches cannot list their pointers")
+    }
+
+    fn read_pointer(
+        &mut self,
+        kind: PointerKind,
+        source: &IdentityScheme::Identity,
+    ) -> anyhow::Result<Option<IdentityScheme::Identity>> {
+        self.read_blob_pointer(kind, source)
+    }
+
+    fn write_pointer(
+        &mut self,
+        kind: PointerKind,
+        source: &IdentityScheme::Identity,
+        destination: &IdentityScheme::Identity,
+    ) -> anyhow::Result<()> {
+        self.write_blob_pointer(kind, source, destination)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RemoteBlobCache;
+    use super::RemoteBlobCacheOptions;
+    use crate::blob::JSON;
+    use crate::canonical::Outputs;
+    use crate::canonical::TaskInputsBuilder;
+    use crate::execute::CacheDirectoryTaskExecutor;
+    use crate::execute::Materialization;
+    use crate::execute::TaskExecutor as _;
+    use crate::fs::HostFilesystem;
+    use crate::identity::IdentityScheme as _;
+    use crate::runner::DefaultRunner;
+    use crate::serve::CacheServer;
+    use crate::serve::ServeOptions;
+    use crate::sync::PointerKind;
+    use crate::transport::ContentSha256;
+    use std::net::TcpListener;
+
+    /// Serves a new cache directory on a local port until the test process exits.
+    fn serve_temporary_cache(token: Option<&str>) -> (tempfile::TempDir, String) {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let server = CacheServer::<_, ContentSha256, JSON>::new(
+            HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+                .expect("host filesystem"),
+            ServeOptions {
+                token: token.map(String::from),
+                ..ServeOptions::default()
+            },
+        )
+        .expect("cache server");
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind listener");
+        let url = format!(
+            "http://{}/",
+            listener.local_addr().expect("listener address")
+        );
+        std::thread::spawn(move || server.serve(listener));
+        (temporary_directory, url)
+    }
+
+    #[test]
+    fn test_remote_blob_cache() {
+        let (_server_directory, url) = serve_temporary_cache(Some("secret"));
+        let mut remote = RemoteBlobCache::<ContentSha256>::new(
+            &url,
+            RemoteBlobCacheOptions {
+                token: Some(String::from("secret")),
+                ..RemoteBlobCacheOptions::default()
+            },
+        )
+        .expect("remote cache");
+
+        let content = b"outputs".to_vec();
+        let identity = ContentSha256::identify_content(content.as_slice()).expect("identify");
+        assert!(!remote.contains_blob(&identity).expect("check blob"));
+        assert_eq!(
+            None,
+            remote.read_blob(&identity).expect("read missing blob")
+        );
+        remote.write_blob(&identity, &content).expect("write blob");
+        remote
+            .write_blob(&identity, &content)
+            .expect("rewrite blob");
+        assert!(remote.contains_blob(&identity).expect("check blob"));
+        assert_eq!(
+            Some(content),
+            remote.read_blob(&identity).expect("read blob")
+        );
+        assert!(remote.write_blob(&identity, b"tampered").is_err());
+
+        let source = ContentSha256::identify_content(&b"inputs"[..]).expect("identify");
+        assert_eq!(
+            None,
+            remote
+                .read_blob_pointer(PointerKind::Outputs, &source)
+                .expect("read missing pointer")
+        );
+        remote
+            .write_blob_pointer(PointerKind::Outputs, &source, &identity)
+            .expect("write pointer");
+        assert_eq!(
+            Some(identity.clone()),
+            remote
+                .read_blob_pointer(PointerKind::Outputs, &source)
+                .expect("read pointer")
+        );
+
+        let mut unauthorized =
+            RemoteBlobCache::<ContentSha256>::new(&url, RemoteBlobCacheOptions::default())
+                .expect("remote cache");
+        assert!(unauthorized.contains_blob(&identity).is_err());
+        assert!(RemoteBlobCache::<ContentSha256>::new(
+            "https://cache.example.com",
+            RemoteBlobCacheOptions::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_read_write_through() {
+        let (_server_directory, url) = serve_temporary_cache(None);
+        let working_directory = tempfile::tempdir().expect("working directory");
+        let mut working_filesystem =
+            HostFilesystem::try_new(working_directory.path().to_path_buf())
+                .expect("working directory filesystem");
+        let inputs = TaskInputsBuilder::<ContentSha256>::new()
+            .program("/bin/sh")
+            .arguments(["-c", "echo output > output.txt; echo run >> runs.txt"])
+            .build()
+            .expect("task inputs");
+
+        let mut run_with_new_cache_directory = || {
+            let cache_directory = tempfile::tempdir().expect("cache directory");
+            let mut executor = CacheDirectoryTaskExecutor::<
+                HostFilesystem,
+                ContentSha256,
+                JSON,
+                DefaultRunner,
+            >::new(
+                HostFilesystem::try_new(cache_directory.path().to_path_buf())
+                    .expect("cache filesystem"),
+            )
+            .expect("task executor")
+            .with_remote_cache(
+                RemoteBlobCache::new(&url, RemoteBlobCacheOptions::default())
+                    .expect("remote cache"),
+            );
+            executor
+                .load_or_execute(&mut working_filesystem, &inputs)
+                .expect("load or execute task")
+        };
+
+        let executed_outputs = run_with_new_cache_directory();
+        let loaded_outputs = run_with_new_cache_directory();
+        assert_eq!(executed_outputs, loaded_outputs);
+        assert_eq!(
+            "run\n",
+            std::fs::read_to_string(working_directory.path().join("runs.txt")).expect("read runs")
+        );
+    }
+
+    #[test]
+    fn test_shared_output_files() {
+        let (server_directory, url) = serve_temporary_cache(None);
+        let runs_directory = tempfile::tempdir().expect("runs directory");
+        let runs_path = runs_directory.path().join("runs.txt");
+        let inputs = TaskInputsBuilder::<ContentSha256>::new()
+            .program("/bin/sh")
+            .arguments([
+                "-c",
+                &format!(
+                    "echo output > output.txt; echo run >> {}",
+                    runs_path.display()
+                ),
+            ])
+            .outputs_description(Outputs::empty().with_include_globs(["output.txt"]))
+            .build()
+            .expect("task inputs");
+        // Each run is on a separate machine, with its own working and cache directories.
+        let run_on_new_machine = || {
+            let working_directory = tempfile::tempdir().expect("working directory");
+            let cache_directory = tempfile::tempdir().expect("cache directory");
+            CacheDirectoryTaskExecutor::<HostFilesystem, ContentSha256, JSON, DefaultRunner>::new(
+                HostFilesystem::try_new(cache_directory.path().to_path_buf())
+                    .expect("cache filesystem"),
+            )
+            .expect("task executor")
+            .with_materialization(Materialization::Copy)
+            .with_remote_cache(
+                RemoteBlobCache::new(&url, RemoteBlobCacheOptions::default())
+                    .expect("remote cache"),
+            )
+            .load_or_execute(
+                &mut HostFilesystem::try_new(working_directory.path().to_path_buf())
+                    .expect("working directory filesystem"),
+                &inputs,
+            )
+            .expect("load or execute task");
+            std::fs::read_to_string(working_directory.path().join("output.txt"))
+                .expect("read output")
+        };
+        let runs = || {
+            std::fs::read_to_string(&runs_path)
+                .expect("read runs")
+                .lines()
+                .count()
+        };
+
+        // The second machine materializes the output that the first uploaded.
+        assert_eq!("output\n", run_on_new_machine());
+        assert_eq!("output\n", run_on_new_machine());
+        assert_eq!(1, runs());
+
+        // A remote cache that lost the content of the output is a miss.
+        let output_identity = ContentSha256::identify_content(&b"output\n"[..]).expect("identify");
+        std::fs::remove_file(
+            server_directory
+                .path()
+                .join("blobs")
+                .join(output_identity.to_string()),
+        )
+        .expect("remove output blob");
+        assert_eq!("output\n", run_on_new_machine());
+        assert_eq!(2, runs());
+    }
+}
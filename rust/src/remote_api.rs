@@ -0,0 +1,1288 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Remote Execution API (REAPI) v2 services over a cache directory, so that Bazel, Buck2, and
+//! reclient can use an artifact-executor cache as their content-addressable store (CAS), action
+//! cache, and execution node.
+//!
+//! `GetCapabilities` advertises SHA-256 digests, action cache updates, and execution, as clients
+//! query before using the other services.
+//!
+//! CAS blobs are the cache's own blobs: a REAPI digest's hash is the `ContentSha256` identity of
+//! the blob. Action results are stored as encoded `ActionResult` blobs, pointed to from the action
+//! digest in the `DEFAULT_ACTION_CACHE_SUBDIR` subdirectory.
+//!
+//! `RemoteApiServer::handle_unary` dispatches encoded requests by gRPC method name and returns
+//! encoded responses, and `encode_grpc_frame`/`decode_grpc_frame` implement gRPC message framing.
+//! `crate::grpc::GrpcServer` serves them over HTTP/2 (the `serve-remote-api` subcommand).
+//! `Execute`, a server-streaming call, responds with a single, completed `Operation`.
+
+use crate::blob::BlobCache;
+use crate::blob::BlobPointerCache;
+use crate::blob::ReadDeserializer as ReadDeserializerApi;
+use crate::blob::StringSerializer as StringSerializerApi;
+use crate::blob::WriteSerializer as WriteSerializerApi;
+use crate::cache::Cache;
+use crate::cache::WriteOnDropIndex;
+use crate::fs::Filesystem as FilesystemApi;
+use crate::reapi::digest_content;
+use crate::reapi::digest_function;
+use crate::reapi::symlink_absolute_path_strategy;
+use crate::reapi::Action;
+use crate::reapi::ActionCacheUpdateCapabilities;
+use crate::reapi::ActionResult;
+use crate::reapi::Any;
+use crate::reapi::BatchReadBlobsRequest;
+use crate::reapi::BatchReadBlobsResponse;
+use crate::reapi::BatchReadBlobsResponseEntry;
+use crate::reapi::BatchUpdateBlobsRequest;
+use crate::reapi::BatchUpdateBlobsResponse;
+use crate::reapi::BatchUpdateBlobsResponseEntry;
+use crate::reapi::CacheCapabilities;
+use crate::reapi::Command;
+use crate::reapi::Digest;
+use crate::reapi::Directory;
+use crate::reapi::ExecuteRequest;
+use crate::reapi::ExecuteResponse;
+use crate::reapi::ExecutionCapabilities;
+use crate::reapi::FindMissingBlobsRequest;
+use crate::reapi::FindMissingBlobsResponse;
+use crate::reapi::GetActionResultRequest;
+use crate::reapi::GetCapabilitiesRequest;
+use crate::reapi::Operation;
+use crate::reapi::OutputFile;
+use crate::reapi::ReadRequest;
+use crate::reapi::ReadResponse;
+use crate::reapi::SemVer;
+use crate::reapi::ServerCapabilities;
+use crate::reapi::Status;
+use crate::reapi::UpdateActionResultRequest;
+use crate::reapi::WriteRequest;
+use crate::reapi::WriteResponse;
+use crate::transport::ContentSha256;
+use crate::transport::Sha256;
+use anyhow::Context as _;
+use prost::Message;
+use std::fs::File;
+use std::io::Read as _;
+use std::os::unix::fs::PermissionsExt as _;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Subdirectory of a cache directory in which action cache entries are stored.
+pub const DEFAULT_ACTION_CACHE_SUBDIR: &str = "action_cache";
+
+pub const FIND_MISSING_BLOBS_METHOD: &str =
+    "/build.bazel.remote.execution.v2.ContentAddressableStorage/FindMissingBlobs";
+pub const BATCH_UPDATE_BLOBS_METHOD: &str =
+    "/build.bazel.remote.execution.v2.ContentAddressableStorage/BatchUpdateBlobs";
+pub const BATCH_READ_BLOBS_METHOD: &str =
+    "/build.bazel.remote.execution.v2.ContentAddressableStorage/BatchReadBlobs";
+pub const GET_ACTION_RESULT_METHOD: &str =
+    "/build.bazel.remote.execution.v2.ActionCache/GetActionResult";
+pub const UPDATE_ACTION_RESULT_METHOD: &str =
+    "/build.bazel.remote.execution.v2.ActionCache/UpdateActionResult";
+pub const EXECUTE_METHOD: &str = "/build.bazel.remote.execution.v2.Execution/Execute";
+pub const GET_CAPABILITIES_METHOD: &str =
+    "/build.bazel.remote.execution.v2.Capabilities/GetCapabilities";
+pub const BYTESTREAM_READ_METHOD: &str = "/google.bytestream.ByteStream/Read";
+pub const BYTESTREAM_WRITE_METHOD: &str = "/google.bytestream.ByteStream/Write";
+
+/// Maximum size of the data in each `ReadResponse` sent by the ByteStream service.
+pub const BYTESTREAM_CHUNK_SIZE: usize = 1 << 20;
+
+/// Largest total size of the blobs in a batch request that clients are told to send: the default
+/// maximum gRPC message size. Larger blobs are transferred with the ByteStream service.
+pub const MAX_BATCH_TOTAL_SIZE_BYTES: i64 = 4 << 20;
+
+const EXECUTE_RESPONSE_TYPE_URL: &str =
+    "type.googleapis.com/build.bazel.remote.execution.v2.ExecuteResponse";
+
+/// `google.rpc.Code` values used by the services.
+pub mod code {
+    pub const OK: i32 = 0;
+    pub const INVALID_ARGUMENT: i32 = 3;
+    pub const NOT_FOUND: i32 = 5;
+    pub const FAILED_PRECONDITION: i32 = 9;
+    pub const UNIMPLEMENTED: i32 = 12;
+    pub const INTERNAL: i32 = 13;
+    pub const UNAVAILABLE: i32 = 14;
+}
+
+impl Status {
+    pub fn new<S: Into<String>>(code: i32, message: S) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn ok() -> Self {
+        Self::new(code::OK, "")
+    }
+
+    fn internal(error: anyhow::Error) -> Self {
+        Self::new(code::INTERNAL, format!("{:#}", error))
+    }
+}
+
+/// Prefixes an encoded message with the gRPC length-prefixed message header (uncompressed).
+pub fn encode_grpc_frame(message: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(message.len() + 5);
+    frame.push(0);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(message);
+    frame
+}
+
+/// Splits the first gRPC length-prefixed message from `frames`, returning the message and the
+/// remaining bytes. Compressed messages are not supported.
+pub fn decode_grpc_frame(frames: &[u8]) -> anyhow::Result<(&[u8], &[u8])> {
+    if frames.len() < 5 {
+        anyhow::bail!("gRPC message header truncated");
+    }
+    if frames[0] != 0 {
+        anyhow::bail!("compressed gRPC messages are not supported");
+    }
+    let length = u32::from_be_bytes([frames[1], frames[2], frames[3], frames[4]]) as usize;
+    let rest = &frames[5..];
+    if rest.len() < length {
+        anyhow::bail!(
+            "gRPC message of {} bytes truncated to {} bytes",
+            length,
+            rest.len()
+        );
+    }
+    Ok(rest.split_at(length))
+}
+
+/// A connection to gRPC services, exchanging encoded messages. Implemented by
+/// `crate::grpc::GrpcClient` over HTTP/2, and by `RemoteApiServer` for in-process use.
+pub trait GrpcChannel {
+    fn unary(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>, Status>;
+
+    fn server_streaming(&mut self, method: &str, request: &[u8]) -> Result<Vec<Vec<u8>>, Status>;
+
+    fn client_streaming(&mut self, method: &str, requests: &[Vec<u8>]) -> Result<Vec<u8>, Status>;
+}
+
+/// Parses the digest from a ByteStream resource name: `[{instance_name}/]blobs/{hash}/{size}` for
+/// reads, or `[{instance_name}/]uploads/{uuid}/blobs/{hash}/{size}[/{metadata}]` for writes.
+pub fn parse_resource_name(resource_name: &str) -> Result<Digest, Status> {
+    let segments: Vec<_> = resource_name.split('/').collect();
+    let blobs_index = segments.iter().position(|segment| *segment == "blobs");
+    let digest = blobs_index.and_then(|index| {
+        let size_bytes = segments.get(index + 2)?.parse().ok()?;
+        Some(Digest {
+            hash: String::from(*segments.get(index + 1)?),
+            size_bytes,
+        })
+    });
+    digest.ok_or_else(|| {
+        Status::new(
+            code::INVALID_ARGUMENT,
+            format!("malformed resource name {:?}", resource_name),
+        )
+    })
+}
+
+type DefaultCache<FS, IS, S> = Cache<FS, IS, S, WriteOnDropIndex<FS, IS, S>>;
+
+/// REAPI ContentAddressableStorage, ActionCache, and Execution services over a cache directory.
+pub struct RemoteApiServer<
+    Filesystem: FilesystemApi,
+    Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+> {
+    blob_cache: BlobCache<Filesystem, ContentSha256, Serialization>,
+    action_cache: BlobPointerCache<Filesystem, ContentSha256, Serialization>,
+}
+
+impl<
+        Filesystem: FilesystemApi,
+        Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+    > RemoteApiServer<Filesystem, Serialization>
+{
+    /// Serves the cache whose root directory is `filesystem`.
+    pub fn new(mut filesystem: Filesystem) -> anyhow::Result<Self> {
+        let blobs_subdir =
+            DefaultCache::<Filesystem, ContentSha256, Serialization>::DEFAULT_BLOBS_SUBDIR;
+        for sub_directory in [blobs_subdir, DEFAULT_ACTION_CACHE_SUBDIR] {
+            filesystem
+                .create_directories(sub_directory)
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("creating cache subdirectory {:?}", sub_directory))?;
+        }
+        Ok(Self {
+            blob_cache: BlobCache::new(
+                filesystem
+                    .sub_system(blobs_subdir)
+                    .context("opening blobs directory")?,
+            ),
+            action_cache: BlobPointerCache::new(
+                filesystem
+                    .sub_system(DEFAULT_ACTION_CACHE_SUBDIR)
+                    .context("opening action cache directory")?,
+            ),
+        })
+    }
+
+    /// Handles an encoded request to the gRPC method `method` (e.g., `FIND_MISSING_BLOBS_METHOD`),
+    /// returning the encoded response.
+    pub fn handle_unary(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>, Status> {
+        fn decode<M: Message + Default>(request: &[u8]) -> Result<M, Status> {
+            M::decode(request)
+                .map_err(|error| Status::new(code::INVALID_ARGUMENT, error.to_string()))
+        }
+        match method {
+            FIND_MISSING_BLOBS_METHOD => self
+                .find_missing_blobs(decode(request)?)
+                .map(|response| response.encode_to_vec()),
+            BATCH_UPDATE_BLOBS_METHOD => self
+                .batch_update_blobs(decode(request)?)
+                .map(|response| response.encode_to_vec()),
+            BATCH_READ_BLOBS_METHOD => self
+                .batch_read_blobs(decode(request)?)
+                .map(|response| response.encode_to_vec()),
+            GET_ACTION_RESULT_METHOD => self
+                .get_action_result(decode(request)?)
+                .map(|response| response.encode_to_vec()),
+            UPDATE_ACTION_RESULT_METHOD => self
+                .update_action_result(decode(request)?)
+                .map(|response| response.encode_to_vec()),
+            EXECUTE_METHOD => self
+                .execute(decode(request)?)
+                .map(|response| response.encode_to_vec()),
+            GET_CAPABILITIES_METHOD => self
+                .get_capabilities(decode(request)?)
+                .map(|response| response.encode_to_vec()),
+            _ => Err(Status::new(
+                code::UNIMPLEMENTED,
+                format!("unknown method {}", method),
+            )),
+        }
+    }
+
+    /// Describes the supported subset of REAPI v2.
+    pub fn get_capabilities(
+        &mut self,
+        _request: GetCapabilitiesRequest,
+    ) -> Result<ServerCapabilities, Status> {
+        let api_version = SemVer {
+            major: 2,
+            minor: 0,
+            patch: 0,
+        };
+        Ok(ServerCapabilities {
+            cache_capabilities: Some(CacheCapabilities {
+                digest_functions: vec![digest_function::SHA256],
+                action_cache_update_capabilities: Some(ActionCacheUpdateCapabilities {
+                    update_enabled: true,
+                }),
+                max_batch_total_size_bytes: MAX_BATCH_TOTAL_SIZE_BYTES,
+                symlink_absolute_path_strategy: symlink_absolute_path_strategy::DISALLOWED,
+            }),
+            execution_capabilities: Some(ExecutionCapabilities {
+                digest_function: digest_function::SHA256,
+                exec_enabled: true,
+                digest_functions: vec![digest_function::SHA256],
+            }),
+            low_api_version: Some(api_version.clone()),
+            high_api_version: Some(api_version),
+        })
+    }
+
+    pub fn find_missing_blobs(
+        &mut self,
+        request: FindMissingBlobsRequest,
+    ) -> Result<FindMissingBlobsResponse, Status> {
+        let mut missing_blob_digests = vec![];
+        for digest in request.blob_digests {
+            if !self.blob_cache.contains_blob(&identity(&digest)?) {
+                missing_blob_digests.push(digest);
+            }
+        }
+        Ok(FindMissingBlobsResponse {
+            missing_blob_digests,
+        })
+    }
+
+    pub fn batch_update_blobs(
+        &mut self,
+        request: BatchUpdateBlobsRequest,
+    ) -> Result<BatchUpdateBlobsResponse, Status> {
+        let responses = request
+            .requests
+            .into_iter()
+            .map(|entry| {
+                let status = match entry.digest.as_ref() {
+                    Some(digest) => match self.write_blob(digest, &entry.data) {
+                        Ok(()) => Status::ok(),
+                        Err(status) => status,
+                    },
+                    None => Status::new(code::INVALID_ARGUMENT, "missing digest"),
+                };
+                BatchUpdateBlobsResponseEntry {
+                    digest: entry.digest,
+                    status: Some(status),
+                }
+            })
+            .collect();
+        Ok(BatchUpdateBlobsResponse { responses })
+    }
+
+    pub fn batch_read_blobs(
+        &mut self,
+        request: BatchReadBlobsRequest,
+    ) -> Result<BatchReadBlobsResponse, Status> {
+        let responses = request
+            .digests
+            .into_iter()
+            .map(|digest| match self.read_blob(&digest) {
+                Ok(data) => BatchReadBlobsResponseEntry {
+                    digest: Some(digest),
+                    data,
+                    status: Some(Status::ok()),
+                },
+                Err(status) => BatchReadBlobsResponseEntry {
+                    digest: Some(digest),
+                    data: vec![],
+                    status: Some(status),
+                },
+            })
+            .collect();
+        Ok(BatchReadBlobsResponse { responses })
+    }
+
+    pub fn get_action_result(
+        &mut self,
+        request: GetActionResultRequest,
+    ) -> Result<ActionResult, Status> {
+        let action_digest = required(request.action_digest, "action_digest")?;
+        self.lookup_action_result(&action_digest)?
+            .ok_or_else(|| Status::new(code::NOT_FOUND, "action result not found"))
+    }
+
+    pub fn update_action_result(
+        &mut self,
+        request: UpdateActionResultRequest,
+    ) -> Result<ActionResult, Status> {
+        let action_digest = required(request.action_digest, "action_digest")?;
+        let action_result = required(request.action_result, "action_result")?;
+        self.store_action_result(&action_digest, &action_result)?;
+        Ok(action_result)
+    }
+
+    /// Executes the requested action, unless its result is cached, and returns the completed
+    /// operation. Failed actions are reported in the `ExecuteResponse`, and are not cached.
+    pub fn execute(&mut self, request: ExecuteRequest) -> Result<Operation, Status> {
+        let action_digest = required(request.action_digest, "action_digest")?;
+        let cached_result = if request.skip_cache_lookup {
+            None
+        } else {
+            self.lookup_action_result(&action_digest)?
+        };
+        let response = match cached_result {
+            Some(result) => ExecuteResponse {
+                result: Some(result),
+                cached_result: true,
+                status: Some(Status::ok()),
+            },
+            None => {
+                let action: Action = self.read_message(&action_digest)?;
+                let result = self.execute_action(&action)?;
+                if !action.do_not_cache && result.exit_code == 0 {
+                    self.store_action_result(&action_digest, &result)?;
+                }
+                ExecuteResponse {
+                    result: Some(result),
+                    cached_result: false,
+                    status: Some(Status::ok()),
+                }
+            }
+        };
+        Ok(Operation {
+            name: action_digest.hash,
+            done: true,
+            error: None,
+            response: Some(Any {
+                type_url: String::from(EXECUTE_RESPONSE_TYPE_URL),
+                value: response.encode_to_vec(),
+            }),
+        })
+    }
+
+    /// Handles a server-streaming call, returning the encoded responses.
+    pub fn handle_server_streaming(
+        &mut self,
+        method: &str,
+        request: &[u8],
+    ) -> Result<Vec<Vec<u8>>, Status> {
+        match method {
+            BYTESTREAM_READ_METHOD => {
+                let request = ReadRequest::decode(request)
+                    .map_err(|error| Status::new(code::INVALID_ARGUMENT, error.to_string()))?;
+                Ok(self
+                    .read(request)?
+                    .iter()
+                    .map(Message::encode_to_vec)
+                    .collect())
+            }
+            EXECUTE_METHOD => Ok(vec![self.handle_unary(method, request)?]),
+            _ => Err(Status::new(
+                code::UNIMPLEMENTED,
+                format!("unknown server-streaming method {}", method),
+            )),
+        }
+    }
+
+    /// Handles a client-streaming call, returning the encoded response.
+    pub fn handle_client_streaming(
+        &mut self,
+        method: &str,
+        requests: &[Vec<u8>],
+    ) -> Result<Vec<u8>, Status> {
+        match method {
+            BYTESTREAM_WRITE_METHOD => {
+                let requests = requests
+                    .iter()
+                    .map(|request| WriteRequest::decode(request.as_slice()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|error| Status::new(code::INVALID_ARGUMENT, error.to_string()))?;
+                Ok(self.write(requests)?.encode_to_vec())
+            }
+            _ => Err(Status::new(
+                code::UNIMPLEMENTED,
+                format!("unknown client-streaming method {}", method),
+            )),
+        }
+    }
+
+    /// ByteStream `Read` of a CAS blob, in chunks of at most `BYTESTREAM_CHUNK_SIZE` bytes.
+    pub fn read(&mut self, request: ReadRequest) -> Result<Vec<ReadResponse>, Status> {
+        let digest = parse_resource_name(&request.resource_name)?;
+        let content = self.read_blob(&digest)?;
+        let start = usize::try_from(request.read_offset)
+            .ok()
+            .filter(|start| *start <= content.len())
+            .ok_or_else(|| Status::new(code::INVALID_ARGUMENT, "read offset out of range"))?;
+        let end = match usize::try_from(request.read_limit) {
+            Ok(0) => content.len(),
+            Ok(limit) => content.len().min(start.saturating_add(limit)),
+            Err(_) => return Err(Status::new(code::INVALID_ARGUMENT, "negative read limit")),
+        };
+        let chunks: Vec<_> = content[start..end]
+            .chunks(BYTESTREAM_CHUNK_SIZE)
+            .map(|chunk| ReadResponse {
+                data: chunk.to_vec(),
+            })
+            .collect();
+        // An empty blob is read as a single, empty response.
+        if chunks.is_empty() {
+            return Ok(vec![ReadResponse { data: vec![] }]);
+        }
+        Ok(chunks)
+    }
+
+    /// ByteStream `Write` of a CAS blob. Requests must be contiguous and the last must finish the
+    /// write.
+    pub fn write(&mut self, requests: Vec<WriteRequest>) -> Result<WriteResponse, Status> {
+        let first = requests
+            .first()
+            .ok_or_else(|| Status::new(code::INVALID_ARGUMENT, "empty write"))?;
+        let digest = parse_resource_name(&first.resource_name)?;
+        let mut content = vec![];
+        let mut finished = false;
+        for request in requests.iter() {
+            if finished {
+                return Err(Status::new(
+                    code::INVALID_ARGUMENT,
+                    "write request after finish_write",
+                ));
+            }
+            if request.write_offset != content.len() as i64 {
+                return Err(Status::new(
+                    code::INVALID_ARGUMENT,
+                    format!(
+                        "write offset {} does not follow {} bytes written",
+                        request.write_offset,
+                        content.len()
+                    ),
+                ));
+            }
+            content.extend_from_slice(&request.data);
+            finished = request.finish_write;
+        }
+        if !finished {
+            return Err(Status::new(
+                code::INVALID_ARGUMENT,
+                "write did not finish_write",
+            ));
+        }
+        self.write_blob(&digest, &content)?;
+        Ok(WriteResponse {
+            committed_size: content.len() as i64,
+        })
+    }
+
+    fn execute_action(&mut self, action: &Action) -> Result<ActionResult, Status> {
+        let command: Command =
+            self.read_message(&required(action.command_digest.clone(), "command_digest")?)?;
+        let program = command
+            .arguments
+            .first()
+            .ok_or_else(|| Status::new(code::INVALID_ARGUMENT, "command has no arguments"))?;
+        let input_root_digest = required(action.input_root_digest.clone(), "input_root_digest")?;
+
+        let execution_directory = tempfile::tempdir()
+            .context("creating execution directory")
+            .map_err(Status::internal)?;
+        let input_root = execution_directory.path().join("root");
+        self.materialize_directory(&input_root_digest, &input_root)?;
+        let working_directory = input_root.join(relative_path(&command.working_directory)?);
+        let stdout_path = execution_directory.path().join("stdout");
+        let stderr_path = execution_directory.path().join("stderr");
+
+        let program_path = Path::new(program);
+        let program_path = if program_path.components().count() > 1 {
+            working_directory.join(program_path)
+        } else {
+            program_path.to_path_buf()
+        };
+        let status = std::process::Command::new(&program_path)
+            .current_dir(&working_directory)
+            .env_clear()
+            .envs(
+                command
+                    .environment_variables
+                    .iter()
+                    .map(|variable| (&variable.name, &variable.value)),
+            )
+            .args(&command.arguments[1..])
+            .stdin(std::process::Stdio::null())
+            .stdout(File::create(&stdout_path).map_err(|error| Status::internal(error.into()))?)
+            .stderr(File::create(&stderr_path).map_err(|error| Status::internal(error.into()))?)
+            .status()
+            .map_err(|error| {
+                Status::new(
+                    code::FAILED_PRECONDITION,
+                    format!("spawning {:?}: {}", program_path, error),
+                )
+            })?;
+
+        let mut output_files = vec![];
+        for output_path in command.output_paths.iter() {
+            let path = working_directory.join(relative_path(output_path)?);
+            if path.is_file() {
+                output_files.push(OutputFile {
+                    path: output_path.clone(),
+                    digest: Some(self.upload_file(&path)?),
+                    is_executable: is_executable(&path)?,
+                });
+            }
+        }
+        Ok(ActionResult {
+            output_files,
+            exit_code: status.code().unwrap_or(-1),
+            stdout_digest: Some(self.upload_file(&stdout_path)?),
+            stderr_digest: Some(self.upload_file(&stderr_path)?),
+        })
+    }
+
+    /// Writes the directory identified by `digest`, and its descendants, to `path`.
+    fn materialize_directory(&mut self, digest: &Digest, path: &Path) -> Result<(), Status> {
+        let directory: Directory = self.read_message(digest)?;
+        std::fs::create_dir_all(path).map_err(|error| Status::internal(error.into()))?;
+        for file in directory.files.iter() {
+            let file_digest = required(file.digest.clone(), "file digest")?;
+            let file_path = path.join(node_name(&file.name)?);
+            std::fs::write(&file_path, self.read_blob(&file_digest)?)
+                .map_err(|error| Status::internal(error.into()))?;
+            if file.is_executable {
+                std::fs::set_permissions(&file_path, std::fs::Permissions::from_mode(0o755))
+                    .map_err(|error| Status::internal(error.into()))?;
+            }
+        }
+        for subdirectory in directory.directories.iter() {
+            let subdirectory_digest = required(subdirectory.digest.clone(), "directory digest")?;
+            self.materialize_directory(
+                &subdirectory_digest,
+                &path.join(node_name(&subdirectory.name)?),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn upload_file(&mut self, path: &Path) -> Result<Digest, Status> {
+        let content = std::fs::read(path).map_err(|error| Status::internal(error.into()))?;
+        let digest = digest_content(&content).map_err(Status::internal)?;
+        self.write_blob(&digest, &content)?;
+        Ok(digest)
+    }
+
+    fn lookup_action_result(
+        &mut self,
+        action_digest: &Digest,
+    ) -> Result<Option<ActionResult>, Status> {
+        let result_identity = match self
+            .action_cache
+            .read_blob_pointer(&identity(action_digest)?)
+        {
+            Ok(result_identity) => result_identity,
+            Err(_) => return Ok(None),
+        };
+        let result_digest = Digest {
+            hash: result_identity.to_string(),
+            size_bytes: 0,
+        };
+        match self.read_message(&result_digest) {
+            Ok(result) => Ok(Some(result)),
+            Err(status) if status.code == code::NOT_FOUND => Ok(None),
+            Err(status) => Err(status),
+        }
+    }
+
+    fn store_action_result(
+        &mut self,
+        action_digest: &Digest,
+        action_result: &ActionResult,
+    ) -> Result<(), Status> {
+        let encoded = action_result.encode_to_vec();
+        let result_digest = digest_content(&encoded).map_err(Status::internal)?;
+        self.write_blob(&result_digest, &encoded)?;
+        self.action_cache
+            .write_raw_blob_pointer(&identity(action_digest)?, &identity(&result_digest)?)
+            .map_err(Status::internal)
+    }
+
+    fn read_message<M: Message + Default>(&mut self, digest: &Digest) -> Result<M, Status> {
+        let content = self.read_blob(digest)?;
+        M::decode(content.as_slice()).map_err(|error| {
+            Status::new(
+                code::INVALID_ARGUMENT,
+                format!("decoding blob {}: {}", digest.hash, error),
+            )
+        })
+    }
+
+    fn read_blob(&mut self, digest: &Digest) -> Result<Vec<u8>, Status> {
+        let mut blob = self
+            .blob_cache
+            .open_blob(&identity(digest)?)
+            .map_err(|_| Status::new(code::NOT_FOUND, format!("blob {} not found", digest.hash)))?;
+        let mut content = vec![];
+        blob.read_to_end(&mut content)
+            .map_err(|error| Status::internal(error.into()))?;
+        Ok(content)
+    }
+
+    fn write_blob(&mut self, digest: &Digest, content: &[u8]) -> Result<(), Status> {
+        let computed_digest = digest_content(content).map_err(Status::internal)?;
+        if computed_digest != *digest {
+            return Err(Status::new(
+                code::INVALID_ARGUMENT,
+                format!(
+                    "content has digest {}/{}, not {}/{}",
+                    computed_digest.hash,
+                    computed_digest.size_bytes,
+                    digest.hash,
+                    digest.size_bytes
+                ),
+            ));
+        }
+        let identity = identity(digest)?;
+        if self.blob_cache.contains_blob(&identity) {
+            return Ok(());
+        }
+        self.blob_cache
+            .copy_blob(content, &identity)
+            .map_err(Status::internal)
+    }
+}
+
+impl<
+        Filesystem: FilesystemApi,
+        Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+    > GrpcChannel for RemoteApiServer<Filesystem, Serialization>
+{
+    fn unary(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>, Status> {
+        self.handle_unary(method, request)
+    }
+
+    fn server_streaming(&mut self, method: &str, request: &[u8]) -> Result<Vec<Vec<u8>>, Status> {
+        self.handle_server_streaming(method, request)
+    }
+
+    fn client_streaming(&mut self, method: &str, requests: &[Vec<u8>]) -> Result<Vec<u8>, Status> {
+        self.handle_client_streaming(method, requests)
+    }
+}
+
+fn identity(digest: &Digest) -> Result<Sha256, Status> {
+    Sha256::try_from(digest.hash.as_str()).map_err(|error| {
+        Status::new(
+            code::INVALID_ARGUMENT,
+            format!("malformed digest hash {:?}: {:#}", digest.hash, error),
+        )
+    })
+}
+
+fn required<T>(field: Option<T>, name: &str) -> Result<T, Status> {
+    field.ok_or_else(|| Status::new(code::INVALID_ARGUMENT, format!("missing {}", name)))
+}
+
+/// Checks that `name`, the name of a file or directory node, is a single path component.
+fn node_name(name: &str) -> Result<&str, Status> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(name),
+        _ => Err(Status::new(
+            code::INVALID_ARGUMENT,
+            format!("invalid directory entry name {:?}", name),
+        )),
+    }
+}
+
+/// Reports whether the file at `path` has any execute permission bit set.
+fn is_executable(path: &Path) -> Result<bool, Status> {
+    let metadata = std::fs::metadata(path).map_err(|error| Status::internal(error.into()))?;
+    Ok(metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Checks that `path` is relative and stays within the input root.
+fn relative_path(path: &str) -> Result<PathBuf, Status> {
+    let path = PathBuf::from(path);
+    if path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        Ok(path)
+    } else {
+        Err(Status::new(
+            code::INVALID_ARGUMENT,
+            format!("path {:?} escapes the input root", path),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::code;
+    use super::decode_grpc_frame;
+    use super::encode_grpc_frame;
+    use super::parse_resource_name;
+    use super::RemoteApiServer;
+    use super::BYTESTREAM_READ_METHOD;
+    use super::BYTESTREAM_WRITE_METHOD;
+    use super::EXECUTE_METHOD;
+    use super::FIND_MISSING_BLOBS_METHOD;
+    use super::GET_ACTION_RESULT_METHOD;
+    use super::GET_CAPABILITIES_METHOD;
+    use crate::blob::JSON;
+    use crate::fs::HostFilesystem;
+    use crate::reapi::digest_content;
+    use crate::reapi::digest_function;
+    use crate::reapi::digest_message;
+    use crate::reapi::Action;
+    use crate::reapi::BatchReadBlobsRequest;
+    use crate::reapi::BatchUpdateBlobsRequest;
+    use crate::reapi::BatchUpdateBlobsRequestEntry;
+    use crate::reapi::Command;
+    use crate::reapi::Digest;
+    use crate::reapi::Directory;
+    use crate::reapi::ExecuteRequest;
+    use crate::reapi::ExecuteResponse;
+    use crate::reapi::FileNode;
+    use crate::reapi::FindMissingBlobsRequest;
+    use crate::reapi::FindMissingBlobsResponse;
+    use crate::reapi::GetActionResultRequest;
+    use crate::reapi::GetCapabilitiesRequest;
+    use crate::reapi::Operation;
+    use crate::reapi::ReadRequest;
+    use crate::reapi::ReadResponse;
+    use crate::reapi::ServerCapabilities;
+    use crate::reapi::WriteRequest;
+    use crate::reapi::WriteResponse;
+    use prost::Message;
+
+    #[test]
+    fn test_grpc_frames() {
+        let frames = [encode_grpc_frame(b"first"), encode_grpc_frame(b"")].concat();
+        let (first, rest) = decode_grpc_frame(&frames).expect("first frame");
+        assert_eq!(b"first", first);
+        let (second, rest) = decode_grpc_frame(rest).expect("second frame");
+        assert!(second.is_empty());
+        assert!(rest.is_empty());
+        assert!(decode_grpc_frame(&frames[..7]).is_err());
+    }
+
+    #[test]
+    fn test_parse_resource_name() {
+        let digest = Digest {
+            hash: String::from("abc"),
+            size_bytes: 3,
+        };
+        for resource_name in [
+            "blobs/abc/3",
+            "instance/blobs/abc/3",
+            "uploads/uuid/blobs/abc/3",
+            "instance/uploads/uuid/blobs/abc/3/metadata",
+        ] {
+            assert_eq!(
+                digest,
+                parse_resource_name(resource_name).expect("parse resource name")
+            );
+        }
+        for resource_name in ["", "blobs/abc", "blobs/abc/three", "uploads/uuid/abc/3"] {
+            assert_eq!(
+                code::INVALID_ARGUMENT,
+                parse_resource_name(resource_name)
+                    .expect_err("malformed resource name")
+                    .code
+            );
+        }
+    }
+
+    #[test]
+    fn test_remote_api_server_errors() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let mut server = RemoteApiServer::<_, JSON>::new(filesystem).expect("remote API server");
+
+        assert_eq!(
+            code::UNIMPLEMENTED,
+            server
+                .handle_unary("/unknown.Service/Method", &[])
+                .expect_err("unknown unary method")
+                .code
+        );
+        assert_eq!(
+            code::UNIMPLEMENTED,
+            server
+                .handle_server_streaming("/unknown.Service/Method", &[])
+                .expect_err("unknown server-streaming method")
+                .code
+        );
+        assert_eq!(
+            code::UNIMPLEMENTED,
+            server
+                .handle_client_streaming("/unknown.Service/Method", &[])
+                .expect_err("unknown client-streaming method")
+                .code
+        );
+        assert_eq!(
+            code::INVALID_ARGUMENT,
+            server
+                .handle_unary(FIND_MISSING_BLOBS_METHOD, &[0xff])
+                .expect_err("undecodable request")
+                .code
+        );
+
+        let content = b"hello, world".to_vec();
+        let digest = digest_content(&content).expect("digest");
+        let resource_name = format!("uploads/uuid/blobs/{}/{}", digest.hash, digest.size_bytes);
+        let write_request = |write_offset: usize, end: usize, finish_write: bool| WriteRequest {
+            resource_name: resource_name.clone(),
+            write_offset: write_offset as i64,
+            finish_write,
+            data: content[write_offset..end].to_vec(),
+        };
+        let read = |server: &mut RemoteApiServer<HostFilesystem, JSON>,
+                    read_offset: i64,
+                    read_limit: i64| {
+            server.read(ReadRequest {
+                resource_name: format!("blobs/{}/{}", digest.hash, digest.size_bytes),
+                read_offset,
+                read_limit,
+            })
+        };
+
+        assert_eq!(
+            code::NOT_FOUND,
+            read(&mut server, 0, 0).expect_err("read missing blob").code
+        );
+        for (requests, error) in [
+            (vec![], "empty write"),
+            (
+                vec![write_request(0, 5, false), write_request(6, 12, true)],
+                "non-contiguous write",
+            ),
+            (vec![write_request(0, 5, false)], "unfinished write"),
+            (
+                vec![write_request(0, 5, true), write_request(5, 12, true)],
+                "write after finish_write",
+            ),
+            (vec![write_request(0, 5, true)], "write of partial content"),
+        ] {
+            assert_eq!(
+                code::INVALID_ARGUMENT,
+                server.write(requests).expect_err(error).code
+            );
+        }
+        assert_eq!(
+            code::NOT_FOUND,
+            read(&mut server, 0, 0)
+                .expect_err("read blob after failed writes")
+                .code
+        );
+
+        let response = WriteResponse::decode(
+            server
+                .handle_client_streaming(
+                    BYTESTREAM_WRITE_METHOD,
+                    &[
+                        write_request(0, 5, false).encode_to_vec(),
+                        write_request(5, 12, true).encode_to_vec(),
+                    ],
+                )
+                .expect("write blob")
+                .as_slice(),
+        )
+        .expect("decode write response");
+        assert_eq!(content.len() as i64, response.committed_size);
+
+        let responses = server
+            .handle_server_streaming(
+                BYTESTREAM_READ_METHOD,
+                &ReadRequest {
+                    resource_name: format!("blobs/{}/{}", digest.hash, digest.size_bytes),
+                    read_offset: 7,
+                    read_limit: 0,
+                }
+                .encode_to_vec(),
+            )
+            .expect("read blob");
+        assert_eq!(
+            vec![b"world".to_vec()],
+            responses
+                .iter()
+                .map(|response| ReadResponse::decode(response.as_slice())
+                    .expect("decode read response")
+                    .data)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            b"hello".to_vec(),
+            read(&mut server, 0, 5).expect("read prefix")[0].data
+        );
+        assert_eq!(
+            code::INVALID_ARGUMENT,
+            read(&mut server, 13, 0)
+                .expect_err("read past end of blob")
+                .code
+        );
+        assert_eq!(
+            code::INVALID_ARGUMENT,
+            read(&mut server, 0, -1)
+                .expect_err("read with negative limit")
+                .code
+        );
+
+        // Actions must be in the CAS before they are executed.
+        let missing_action = ExecuteRequest {
+            instance_name: String::new(),
+            skip_cache_lookup: false,
+            action_digest: Some(digest_content(b"action").expect("digest")),
+        };
+        assert_eq!(
+            code::NOT_FOUND,
+            server
+                .execute(missing_action)
+                .expect_err("execute missing action")
+                .code
+        );
+        assert_eq!(
+            code::INVALID_ARGUMENT,
+            server
+                .execute(ExecuteRequest {
+                    instance_name: String::new(),
+                    skip_cache_lookup: false,
+                    action_digest: None,
+                })
+                .expect_err("execute without action digest")
+                .code
+        );
+        assert_eq!(
+            code::INVALID_ARGUMENT,
+            server
+                .get_action_result(GetActionResultRequest {
+                    instance_name: String::new(),
+                    action_digest: Some(Digest {
+                        hash: String::from("not-a-hash"),
+                        size_bytes: 0,
+                    }),
+                })
+                .expect_err("malformed action digest")
+                .code
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_remote_api_server() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let mut server = RemoteApiServer::<_, JSON>::new(filesystem).expect("remote API server");
+
+        let capabilities = ServerCapabilities::decode(
+            server
+                .handle_unary(
+                    GET_CAPABILITIES_METHOD,
+                    &GetCapabilitiesRequest::default().encode_to_vec(),
+                )
+                .expect("get capabilities")
+                .as_slice(),
+        )
+        .expect("decode capabilities");
+        let cache_capabilities = capabilities.cache_capabilities.expect("cache capabilities");
+        assert_eq!(
+            vec![digest_function::SHA256],
+            cache_capabilities.digest_functions
+        );
+        assert!(
+            cache_capabilities
+                .action_cache_update_capabilities
+                .expect("action cache update capabilities")
+                .update_enabled
+        );
+        assert!(
+            capabilities
+                .execution_capabilities
+                .expect("execution capabilities")
+                .exec_enabled
+        );
+        assert_eq!(2, capabilities.high_api_version.expect("API version").major);
+
+        let input = b"hello".to_vec();
+        let input_root = Directory {
+            files: vec![FileNode {
+                name: String::from("input.txt"),
+                digest: Some(digest_content(&input).expect("digest")),
+                is_executable: false,
+            }],
+            directories: vec![],
+        };
+        let command = Command {
+            arguments: vec![
+                String::from("/bin/sh"),
+                String::from("-c"),
+                String::from("cat input.txt input.txt > output.txt; echo done"),
+            ],
+            environment_variables: vec![],
+            working_directory: String::new(),
+            output_paths: vec![String::from("output.txt")],
+        };
+        let action = Action {
+            command_digest: Some(digest_message(&command).expect("digest")),
+            input_root_digest: Some(digest_message(&input_root).expect("digest")),
+            do_not_cache: false,
+            salt: vec![],
+        };
+        let action_digest = digest_message(&action).expect("digest");
+        let blobs = [
+            input,
+            input_root.encode_to_vec(),
+            command.encode_to_vec(),
+            action.encode_to_vec(),
+        ];
+
+        let find_missing = FindMissingBlobsRequest {
+            instance_name: String::new(),
+            blob_digests: blobs
+                .iter()
+                .map(|blob| digest_content(blob).expect("digest"))
+                .collect(),
+        };
+        let response = FindMissingBlobsResponse::decode(
+            server
+                .handle_unary(FIND_MISSING_BLOBS_METHOD, &find_missing.encode_to_vec())
+                .expect("find missing blobs")
+                .as_slice(),
+        )
+        .expect("decode response");
+        assert_eq!(4, response.missing_blob_digests.len());
+
+        let mut requests: Vec<_> = blobs
+            .iter()
+            .map(|blob| BatchUpdateBlobsRequestEntry {
+                digest: Some(digest_content(blob).expect("digest")),
+                data: blob.clone(),
+            })
+            .collect();
+        requests.push(BatchUpdateBlobsRequestEntry {
+            digest: Some(digest_content(b"other").expect("digest")),
+            data: b"tampered".to_vec(),
+        });
+        let response = server
+            .batch_update_blobs(BatchUpdateBlobsRequest {
+                instance_name: String::new(),
+                requests,
+            })
+            .expect("batch update blobs");
+        let codes: Vec<_> = response
+            .responses
+            .iter()
+            .map(|response| response.status.as_ref().expect("status").code)
+            .collect();
+        assert_eq!(
+            vec![
+                code::OK,
+                code::OK,
+                code::OK,
+                code::OK,
+                code::INVALID_ARGUMENT
+            ],
+            codes
+        );
+        assert!(server
+            .find_missing_blobs(find_missing.clone())
+            .expect("find missing blobs")
+            .missing_blob_digests
+            .is_empty());
+
+        let get_action_result = GetActionResultRequest {
+            instance_name: String::new(),
+            action_digest: Some(action_digest.clone()),
+        }
+        .encode_to_vec();
+        assert_eq!(
+            code::NOT_FOUND,
+            server
+                .handle_unary(GET_ACTION_RESULT_METHOD, &get_action_result)
+                .expect_err("uncached action")
+                .code
+        );
+
+        let execute = ExecuteRequest {
+            instance_name: String::new(),
+            skip_cache_lookup: false,
+            action_digest: Some(action_digest.clone()),
+        }
+        .encode_to_vec();
+        let execute_response = |server: &mut RemoteApiServer<HostFilesystem, JSON>| {
+            let operation = Operation::decode(
+                server
+                    .handle_unary(EXECUTE_METHOD, &execute)
+                    .expect("execute")
+                    .as_slice(),
+            )
+            .expect("decode operation");
+            assert!(operation.done);
+            ExecuteResponse::decode(operation.response.expect("response").value.as_slice())
+                .expect("decode execute response")
+        };
+        let response = execute_response(&mut server);
+        assert!(!response.cached_result);
+        let result = response.result.expect("action result");
+        assert_eq!(0, result.exit_code);
+        assert_eq!(1, result.output_files.len());
+        let output_digest = result.output_files[0].digest.clone().expect("digest");
+        assert_eq!(
+            digest_content(b"hellohello").expect("digest"),
+            output_digest
+        );
+        let read = server
+            .batch_read_blobs(BatchReadBlobsRequest {
+                instance_name: String::new(),
+                digests: vec![result.stdout_digest.clone().expect("stdout digest")],
+            })
+            .expect("batch read blobs");
+        assert_eq!(b"done\n".to_vec(), read.responses[0].data);
+
+        let response = execute_response(&mut server);
+        assert!(response.cached_result);
+        assert_eq!(Some(result.clone()), response.result);
+        assert!(server
+            .handle_unary(GET_ACTION_RESULT_METHOD, &get_action_result)
+            .is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_remote_api_server_executable_files() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let mut server = RemoteApiServer::<_, JSON>::new(filesystem).expect("remote API server");
+
+        let script =
+            b"#!/bin/sh\necho data > output.txt\ncp output.txt tool.sh\nchmod +x tool.sh\n"
+                .to_vec();
+        let input_root = Directory {
+            files: vec![FileNode {
+                name: String::from("script.sh"),
+                digest: Some(digest_content(&script).expect("digest")),
+                is_executable: true,
+            }],
+            directories: vec![],
+        };
+        let command = Command {
+            arguments: vec![String::from("./script.sh")],
+            environment_variables: vec![],
+            working_directory: String::new(),
+            output_paths: vec![String::from("output.txt"), String::from("tool.sh")],
+        };
+        let action = Action {
+            command_digest: Some(digest_message(&command).expect("digest")),
+            input_root_digest: Some(digest_message(&input_root).expect("digest")),
+            do_not_cache: false,
+            salt: vec![],
+        };
+        let action_digest = digest_message(&action).expect("digest");
+        server
+            .batch_update_blobs(BatchUpdateBlobsRequest {
+                instance_name: String::new(),
+                requests: [
+                    script,
+                    input_root.encode_to_vec(),
+                    command.encode_to_vec(),
+                    action.encode_to_vec(),
+                ]
+                .into_iter()
+                .map(|blob| BatchUpdateBlobsRequestEntry {
+                    digest: Some(digest_content(&blob).expect("digest")),
+                    data: blob,
+                })
+                .collect(),
+            })
+            .expect("batch update blobs");
+
+        let operation = Operation::decode(
+            server
+                .handle_unary(
+                    EXECUTE_METHOD,
+                    &ExecuteRequest {
+                        instance_name: String::new(),
+                        skip_cache_lookup: false,
+                        action_digest: Some(action_digest),
+                    }
+                    .encode_to_vec(),
+                )
+                .expect("execute")
+                .as_slice(),
+        )
+        .expect("decode operation");
+        let result =
+            ExecuteResponse::decode(operation.response.expect("response").value.as_slice())
+                .expect("decode execute response")
+                .result
+                .expect("action result");
+
+        // The executable input ran, and only the output it marked as executable is reported as such.
+        assert_eq!(0, result.exit_code);
+        assert_eq!(
+            vec![
+                (String::from("output.txt"), false),
+                (String::from("tool.sh"), true)
+            ],
+            result
+                .output_files
+                .iter()
+                .map(|file| (file.path.clone(), file.is_executable))
+                .collect::<Vec<_>>()
+        );
+    }
+}
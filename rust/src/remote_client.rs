@@ -0,0 +1,492 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Client for REAPI ContentAddressableStorage and ActionCache services (see `crate::remote_api`),
+//! for use as the remote tier of a cache. `crate::grpc::GrpcClient` connects it to a remote server.
+//!
+//! Small blobs are transferred in batches; blobs larger than `RemoteCacheOptions::max_batch_bytes`
+//! are streamed with the ByteStream service, in chunks of `RemoteCacheOptions::chunk_size` bytes.
+
+use crate::events;
+use crate::events::Event;
+use crate::events::TransferDirection;
+use crate::reapi::digest_content;
+use crate::reapi::ActionResult;
+use crate::reapi::BatchReadBlobsRequest;
+use crate::reapi::BatchReadBlobsResponse;
+use crate::reapi::BatchUpdateBlobsRequest;
+use crate::reapi::BatchUpdateBlobsRequestEntry;
+use crate::reapi::BatchUpdateBlobsResponse;
+use crate::reapi::Digest;
+use crate::reapi::FindMissingBlobsRequest;
+use crate::reapi::FindMissingBlobsResponse;
+use crate::reapi::GetActionResultRequest;
+use crate::reapi::ReadRequest;
+use crate::reapi::ReadResponse;
+use crate::reapi::Status;
+use crate::reapi::UpdateActionResultRequest;
+use crate::reapi::WriteRequest;
+use crate::reapi::WriteResponse;
+use crate::remote_api::code;
+use crate::remote_api::GrpcChannel;
+use crate::remote_api::BATCH_READ_BLOBS_METHOD;
+use crate::remote_api::BATCH_UPDATE_BLOBS_METHOD;
+use crate::remote_api::BYTESTREAM_READ_METHOD;
+use crate::remote_api::BYTESTREAM_WRITE_METHOD;
+use crate::remote_api::FIND_MISSING_BLOBS_METHOD;
+use crate::remote_api::GET_ACTION_RESULT_METHOD;
+use crate::remote_api::UPDATE_ACTION_RESULT_METHOD;
+use prost::Message;
+use rand::Rng as _;
+
+/// Default for `RemoteCacheOptions::max_batch_bytes`: Comfortably below the 4 MiB default maximum
+/// gRPC message size.
+pub const DEFAULT_MAX_BATCH_BYTES: usize = 3 << 20;
+
+/// Default for `RemoteCacheOptions::max_batch_digests`.
+pub const DEFAULT_MAX_BATCH_DIGESTS: usize = 10_000;
+
+/// Default for `RemoteCacheOptions::chunk_size`.
+pub const DEFAULT_CHUNK_SIZE: usize = 1 << 20;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RemoteCacheOptions {
+    /// REAPI instance name sent with each request.
+    pub instance_name: String,
+    /// Maximum total size of the blobs in one batch request. Larger blobs are streamed.
+    pub max_batch_bytes: usize,
+    /// Maximum number of digests in one `FindMissingBlobs` request.
+    pub max_batch_digests: usize,
+    /// Size of the chunks in which large blobs are streamed.
+    pub chunk_size: usize,
+}
+
+impl Default for RemoteCacheOptions {
+    fn default() -> Self {
+        Self {
+            instance_name: String::new(),
+            max_batch_bytes: DEFAULT_MAX_BATCH_BYTES,
+            max_batch_digests: DEFAULT_MAX_BATCH_DIGESTS,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+/// A REAPI remote cache, reached through `Channel`.
+pub struct RemoteCacheClient<Channel: GrpcChannel> {
+    channel: Channel,
+    options: RemoteCacheOptions,
+}
+
+impl<Channel: GrpcChannel> RemoteCacheClient<Channel> {
+    pub fn new(channel: Channel, options: RemoteCacheOptions) -> Self {
+        Self { channel, options }
+    }
+
+    /// Returns the digests in `digests` that the remote cache does not contain.
+    pub fn find_missing_blobs(&mut self, digests: &[Digest]) -> anyhow::Result<Vec<Digest>> {
+        let mut missing_blob_digests = vec![];
+        for batch in digests.chunks(self.options.max_batch_digests.max(1)) {
+            let response: FindMissingBlobsResponse = self.unary(
+                FIND_MISSING_BLOBS_METHOD,
+                &FindMissingBlobsRequest {
+                    instance_name: self.options.instance_name.clone(),
+                    blob_digests: batch.to_vec(),
+                },
+            )?;
+            missing_blob_digests.extend(response.missing_blob_digests);
+        }
+        Ok(missing_blob_digests)
+    }
+
+    /// Uploads those of `blobs` that the remote cache does not already contain, returning the
+    /// digests of the uploaded blobs.
+    pub fn upload_blobs(&mut self, blobs: &[Vec<u8>]) -> anyhow::Result<Vec<Digest>> {
+        let digests = blobs
+            .iter()
+            .map(|blob| digest_content(blob))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let missing = self.find_missing_blobs(&digests)?;
+
+        let mut batch: Vec<BatchUpdateBlobsRequestEntry> = vec![];
+        let mut batch_bytes = 0;
+        let mut uploaded = vec![];
+        for (digest, blob) in digests.iter().zip(blobs.iter()) {
+            if !missing.contains(digest) || uploaded.contains(digest) {
+                continue;
+            }
+            uploaded.push(digest.clone());
+            if blob.len() > self.options.max_batch_bytes {
+                self.write_stream(digest, blob)?;
+                continue;
+            }
+            if batch_bytes + blob.len() > self.options.max_batch_bytes {
+                self.batch_update_blobs(std::mem::take(&mut batch))?;
+                batch_bytes = 0;
+            }
+            batch_bytes += blob.len();
+            batch.push(BatchUpdateBlobsRequestEntry {
+                digest: Some(digest.clone()),
+                data: blob.clone(),
+            });
+        }
+        if !batch.is_empty() {
+            self.batch_update_blobs(batch)?;
+        }
+        for digest in uploaded.iter() {
+            events::publish(&Event::BlobTransferred {
+                direction: TransferDirection::Upload,
+                hash: &digest.hash,
+                bytes: digest.size_bytes as u64,
+            });
+        }
+        Ok(uploaded)
+    }
+
+    /// Downloads the blob identified by `digest`, or `None` if the remote cache does not contain
+    /// it.
+    pub fn download_blob(&mut self, digest: &Digest) -> anyhow::Result<Option<Vec<u8>>> {
+        let content = if digest.size_bytes as usize > self.options.max_batch_bytes {
+            match self.read_stream(digest) {
+                Ok(content) => content,
+                Err(status) if status.code == code::NOT_FOUND => return Ok(None),
+                Err(status) => return Err(status_error(BYTESTREAM_READ_METHOD, status)),
+            }
+        } else {
+            let response: BatchReadBlobsResponse = self.unary(
+                BATCH_READ_BLOBS_METHOD,
+                &BatchReadBlobsRequest {
+                    instance_name: self.options.instance_name.clone(),
+                    digests: vec![digest.clone()],
+                },
+            )?;
+            let entry =
+                response.responses.into_iter().next().ok_or_else(|| {
+                    anyhow::anyhow!("remote cache returned no blob for {:?}", digest)
+                })?;
+            match entry.status {
+                Some(status) if status.code == code::NOT_FOUND => return Ok(None),
+                Some(status) if status.code != code::OK => {
+                    return Err(status_error(BATCH_READ_BLOBS_METHOD, status))
+                }
+                _ => entry.data,
+            }
+        };
+        let computed_digest = digest_content(&content)?;
+        if &computed_digest != digest {
+            anyhow::bail!(
+                "remote cache returned content with digest {:?} for {:?}",
+                computed_digest,
+                digest
+            );
+        }
+        events::publish(&Event::BlobTransferred {
+            direction: TransferDirection::Download,
+            hash: &digest.hash,
+            bytes: content.len() as u64,
+        });
+        Ok(Some(content))
+    }
+
+    /// Looks up the result of the action identified by `action_digest`.
+    pub fn get_action_result(
+        &mut self,
+        action_digest: &Digest,
+    ) -> anyhow::Result<Option<ActionResult>> {
+        let request = GetActionResultRequest {
+            instance_name: self.options.instance_name.clone(),
+            action_digest: Some(action_digest.clone()),
+        };
+        match self
+            .channel
+            .unary(GET_ACTION_RESULT_METHOD, &request.encode_to_vec())
+        {
+            Ok(response) => Ok(Some(ActionResult::decode(response.as_slice())?)),
+            Err(status) if status.code == code::NOT_FOUND => Ok(None),
+            Err(status) => Err(status_error(GET_ACTION_RESULT_METHOD, status)),
+        }
+    }
+
+    /// Records `action_result` as the result of the action identified by `action_digest`. The
+    /// blobs that `action_result` refers to should be uploaded first.
+    pub fn update_action_result(
+        &mut self,
+        action_digest: &Digest,
+        action_result: &ActionResult,
+    ) -> anyhow::Result<()> {
+        let _: ActionResult = self.unary(
+            UPDATE_ACTION_RESULT_METHOD,
+            &UpdateActionResultRequest {
+                instance_name: self.options.instance_name.clone(),
+                action_digest: Some(action_digest.clone()),
+                action_result: Some(action_result.clone()),
+            },
+        )?;
+        Ok(())
+    }
+
+    fn batch_update_blobs(
+        &mut self,
+        requests: Vec<BatchUpdateBlobsRequestEntry>,
+    ) -> anyhow::Result<()> {
+        let response: BatchUpdateBlobsResponse = self.unary(
+            BATCH_UPDATE_BLOBS_METHOD,
+            &BatchUpdateBlobsRequest {
+                instance_name: self.options.instance_name.clone(),
+                requests,
+            },
+        )?;
+        for entry in response.responses {
+            if let Some(status) = entry.status {
+                if status.code != code::OK {
+                    return Err(status_error(BATCH_UPDATE_BLOBS_METHOD, status)
+                        .context(format!("uploading {:?}", entry.digest)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_stream(&mut self, digest: &Digest, blob: &[u8]) -> anyhow::Result<()> {
+        let upload_id: u128 = rand::thread_rng().gen();
+        let resource_name = format!(
+            "{}uploads/{:032x}/blobs/{}/{}",
+            self.instance_prefix(),
+            upload_id,
+            digest.hash,
+            digest.size_bytes
+        );
+        let chunks: Vec<&[u8]> = if blob.is_empty() {
+            vec![blob]
+        } else {
+            blob.chunks(self.options.chunk_size.max(1)).collect()
+        };
+        let mut write_offset = 0;
+        let requests: Vec<Vec<u8>> = chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let request = WriteRequest {
+                    resource_name: if index == 0 {
+                        resource_name.clone()
+                    } else {
+                        String::new()
+                    },
+                    write_offset,
+                    finish_write: index + 1 == chunks.len(),
+                    data: chunk.to_vec(),
+                };
+                write_offset += chunk.len() as i64;
+                request.encode_to_vec()
+            })
+            .collect();
+        let response = self
+            .channel
+            .client_streaming(BYTESTREAM_WRITE_METHOD, &requests)
+            .map_err(|status| status_error(BYTESTREAM_WRITE_METHOD, status))?;
+        let response = WriteResponse::decode(response.as_slice())?;
+        if response.committed_size != digest.size_bytes {
+            anyhow::bail!(
+                "remote cache committed {} of {} bytes of {}",
+                response.committed_size,
+                digest.size_bytes,
+                digest.hash
+            );
+        }
+        Ok(())
+    }
+
+    fn read_stream(&mut self, digest: &Digest) -> Result<Vec<u8>, Status> {
+        let request = ReadRequest {
+            resource_name: format!(
+                "{}blobs/{}/{}",
+                self.instance_prefix(),
+                digest.hash,
+                digest.size_bytes
+            ),
+            read_offset: 0,
+            read_limit: 0,
+        };
+        let mut content = vec![];
+        for response in self
+            .channel
+            .server_streaming(BYTESTREAM_READ_METHOD, &request.encode_to_vec())?
+        {
+            let response = ReadResponse::decode(response.as_slice())
+                .map_err(|error| Status::new(code::INTERNAL, error.to_string()))?;
+            content.extend(response.data);
+        }
+        Ok(content)
+    }
+
+    fn instance_prefix(&self) -> String {
+        if self.options.instance_name.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", self.options.instance_name)
+        }
+    }
+
+    fn unary<Request: Message, Response: Message + Default>(
+        &mut self,
+        method: &str,
+        request: &Request,
+    ) -> anyhow::Result<Response> {
+        let response = self
+            .channel
+            .unary(method, &request.encode_to_vec())
+            .map_err(|status| status_error(method, status))?;
+        Ok(Response::decode(response.as_slice())?)
+    }
+}
+
+fn status_error(method: &str, status: Status) -> anyhow::Error {
+    anyhow::anyhow!(
+        "{} failed with code {}: {}",
+        method,
+        status.code,
+        status.message
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RemoteCacheClient;
+    use super::RemoteCacheOptions;
+    use crate::blob::JSON;
+    use crate::fs::HostFilesystem;
+    use crate::reapi::digest_content;
+    use crate::reapi::ActionResult;
+    use crate::reapi::Status;
+    use crate::remote_api::GrpcChannel;
+    use crate::remote_api::RemoteApiServer;
+
+    /// Counts calls by method, to check batching and streaming.
+    struct CountingChannel {
+        server: RemoteApiServer<HostFilesystem, JSON>,
+        methods: Vec<String>,
+    }
+
+    impl GrpcChannel for CountingChannel {
+        fn unary(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>, Status> {
+            self.methods
+                .push(method.rsplit('/').next().unwrap().to_string());
+            self.server.unary(method, request)
+        }
+
+        fn server_streaming(
+            &mut self,
+            method: &str,
+            request: &[u8],
+        ) -> Result<Vec<Vec<u8>>, Status> {
+            self.methods
+                .push(method.rsplit('/').next().unwrap().to_string());
+            self.server.server_streaming(method, request)
+        }
+
+        fn client_streaming(
+            &mut self,
+            method: &str,
+            requests: &[Vec<u8>],
+        ) -> Result<Vec<u8>, Status> {
+            self.methods.push(format!(
+                "{}x{}",
+                method.rsplit('/').next().unwrap(),
+                requests.len()
+            ));
+            self.server.client_streaming(method, requests)
+        }
+    }
+
+    #[test]
+    fn test_remote_cache_client() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let server = RemoteApiServer::<_, JSON>::new(filesystem).expect("remote API server");
+        let mut client = RemoteCacheClient::new(
+            CountingChannel {
+                server,
+                methods: vec![],
+            },
+            RemoteCacheOptions {
+                instance_name: String::from("main"),
+                max_batch_bytes: 8,
+                max_batch_digests: 2,
+                chunk_size: 4,
+            },
+        );
+
+        let blobs = vec![
+            b"small".to_vec(),
+            b"tiny".to_vec(),
+            b"a large blob".to_vec(),
+            b"small".to_vec(),
+        ];
+        let digests: Vec<_> = blobs
+            .iter()
+            .map(|blob| digest_content(blob).expect("digest"))
+            .collect();
+        let uploaded = client.upload_blobs(&blobs).expect("upload blobs");
+        assert_eq!(digests[..3].to_vec(), uploaded);
+        assert_eq!(
+            vec![
+                "FindMissingBlobs",
+                "FindMissingBlobs",
+                "BatchUpdateBlobs",
+                "Writex3",
+                "BatchUpdateBlobs"
+            ],
+            client.channel.methods
+        );
+        assert!(client
+            .find_missing_blobs(&digests)
+            .expect("find missing blobs")
+            .is_empty());
+        assert!(client
+            .upload_blobs(&blobs)
+            .expect("upload blobs")
+            .is_empty());
+
+        client.channel.methods.clear();
+        for (digest, blob) in digests.iter().zip(blobs.iter()) {
+            assert_eq!(
+                Some(blob),
+                client
+                    .download_blob(digest)
+                    .expect("download blob")
+                    .as_ref()
+            );
+        }
+        assert_eq!(
+            vec!["BatchReadBlobs", "BatchReadBlobs", "Read", "BatchReadBlobs"],
+            client.channel.methods
+        );
+        let absent = digest_content(b"absent").expect("digest");
+        assert_eq!(None, client.download_blob(&absent).expect("download blob"));
+
+        let action_digest = digest_content(b"action").expect("digest");
+        assert_eq!(
+            None,
+            client
+                .get_action_result(&action_digest)
+                .expect("get action result")
+        );
+        let action_result = ActionResult {
+            output_files: vec![],
+            exit_code: 0,
+            stdout_digest: Some(digests[0].clone()),
+            stderr_digest: None,
+        };
+        client
+            .update_action_result(&action_digest, &action_result)
+            .expect("update action result");
+        assert_eq!(
+            Some(action_result),
+            client
+                .get_action_result(&action_digest)
+                .expect("get action result")
+        );
+    }
+}
@@ -0,0 +1,405 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Reproduction bundles for failed tasks.
+//!
+//! When a task run under `run_capturing_repro` fails, everything needed to rerun it elsewhere is
+//! packaged into a single tarball, `repro-<inputs identity>.tar`, whose entries are all under a
+//! `repro-<inputs identity>/` directory:
+//!
+//! - `task_inputs.json`: the canonical task inputs (program, arguments, environment, input file
+//!   identities, and outputs description) from which the task's cache key is computed;
+//! - `task.json`: the task description that the inputs were resolved from, when known;
+//! - `host.json`: the platform and working directory of the failed run;
+//! - `error.txt`: the failure;
+//! - `stdout` and `stderr`: the task's standard streams;
+//! - `trace.log`: the events published while the task ran (see `crate::events`);
+//! - `inputs/`: the content of each relative input file, when `ReproOptions::include_inputs` is
+//!   set;
+//! - `replay.sh`: a script that reruns the program with exactly the recorded arguments and
+//!   environment, in the directory given as its argument (restoring bundled inputs there) or else
+//!   in the current directory.
+
+use crate::blob::FileFormat;
+use crate::blob::ReadDeserializer;
+use crate::blob::StringSerializer;
+use crate::blob::WriteSerializer;
+use crate::canonical::TaskInputs;
+use crate::canonical::TaskOutputs;
+use crate::events;
+use crate::events::Event;
+use crate::events::Subscriber;
+use crate::execute::identify_task_inputs;
+use crate::execute::CacheDirectoryTaskExecutor;
+use crate::execute::TaskExecutor as _;
+use crate::fs::Filesystem as FilesystemApi;
+use crate::identity::AsTransport as _;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::runner::Runner;
+use crate::transport::Stdin;
+use crate::transport::Task;
+use anyhow::Context as _;
+use std::io::Read as _;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+#[derive(Clone, Debug, Default)]
+pub struct ReproOptions {
+    /// Directory in which bundles are written.
+    pub directory: PathBuf,
+    /// Whether to bundle the content of input files, so that the task can be replayed on a host
+    /// that lacks them.
+    pub include_inputs: bool,
+}
+
+/// Records the events published while a task runs, one `Debug`-formatted event per line.
+#[derive(Debug, Default)]
+pub struct EventLog(Mutex<Vec<String>>);
+
+impl EventLog {
+    pub fn lines(&self) -> Vec<String> {
+        self.0.lock().expect("event log lock").clone()
+    }
+}
+
+impl Subscriber for EventLog {
+    fn on_event(&self, event: &Event<'_>) {
+        self.0
+            .lock()
+            .expect("event log lock")
+            .push(format!("{:?}", event));
+    }
+}
+
+/// Loads or executes (when `force` is set, executes) the task described by `inputs`, writing a
+/// reproduction bundle to `options.directory` if it fails. `task`, when known, is the description
+/// that `inputs` were resolved from.
+pub fn run_capturing_repro<FS, IS, S, R>(
+    executor: &mut CacheDirectoryTaskExecutor<FS, IS, S, R>,
+    working_directory: &mut FS,
+    inputs: &TaskInputs<IS>,
+    task: Option<&Task>,
+    force: bool,
+    options: &ReproOptions,
+) -> anyhow::Result<TaskOutputs<IS>>
+where
+    FS: FilesystemApi,
+    IS: IdentitySchemeApi,
+    S: FileFormat + ReadDeserializer + StringSerializer + WriteSerializer,
+    R: Runner,
+{
+    let event_log = Arc::new(EventLog::default());
+    let subscription = events::subscribe(event_log.clone());
+    let result = if force {
+        executor.force_execute(working_directory, inputs)
+    } else {
+        executor.load_or_execute(working_directory, inputs)
+    };
+    events::unsubscribe(subscription);
+
+    result.or_else(|error| {
+        let inputs_identity = identify_task_inputs::<IS>(inputs)?;
+        let bundle = ReproBundle {
+            inputs,
+            task,
+            error: format!("{:#}", error),
+            // The task may have failed before its streams were recorded.
+            stdout: executor.read_stdout(&inputs_identity).unwrap_or_default(),
+            stderr: executor.read_stderr(&inputs_identity).unwrap_or_default(),
+            trace: event_log.lines(),
+        };
+        match write_repro_bundle(working_directory, &bundle, options) {
+            Ok(path) => Err(error.context(format!("reproduction bundle written to {:?}", path))),
+            Err(bundle_error) => {
+                tracing::error!("writing reproduction bundle: {:#}", bundle_error);
+                Err(error)
+            }
+        }
+    })
+}
+
+/// Everything recorded about a failed task.
+pub struct ReproBundle<'a, IS: IdentitySchemeApi> {
+    pub inputs: &'a TaskInputs<IS>,
+    pub task: Option<&'a Task>,
+    pub error: String,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub trace: Vec<String>,
+}
+
+/// Writes `bundle` as a tarball in `options.directory`, returning the tarball's path. Input files
+/// are read from `working_directory`.
+pub fn write_repro_bundle<IS: IdentitySchemeApi, FS: FilesystemApi>(
+    working_directory: &mut FS,
+    bundle: &ReproBundle<'_, IS>,
+    options: &ReproOptions,
+) -> anyhow::Result<PathBuf> {
+    let inputs_identity = identify_task_inputs::<IS>(bundle.inputs)?.to_string();
+    let name = format!("repro-{}", inputs_identity);
+    std::fs::create_dir_all(&options.directory)
+        .with_context(|| format!("creating reproduction directory {:?}", options.directory))?;
+    let path = options.directory.join(format!("{}.tar", name));
+    let file = std::fs::File::create(&path)
+        .with_context(|| format!("creating reproduction bundle {:?}", path))?;
+    let mut tarball = Tarball {
+        builder: tar::Builder::new(file),
+        root: PathBuf::from(&name),
+    };
+
+    tarball.append(
+        "task_inputs.json",
+        serde_json::to_string_pretty(&bundle.inputs.as_transport())?.as_bytes(),
+        0o644,
+    )?;
+    if let Some(task) = bundle.task {
+        tarball.append(
+            "task.json",
+            serde_json::to_string_pretty(task)?.as_bytes(),
+            0o644,
+        )?;
+    }
+    let host = serde_json::json!({
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "working_directory": working_directory.working_directory(),
+        "inputs_identity": inputs_identity,
+    });
+    tarball.append(
+        "host.json",
+        serde_json::to_string_pretty(&host)?.as_bytes(),
+        0o644,
+    )?;
+    tarball.append("error.txt", bundle.error.as_bytes(), 0o644)?;
+    tarball.append("stdout", &bundle.stdout, 0o644)?;
+    tarball.append("stderr", &bundle.stderr, 0o644)?;
+    tarball.append("trace.log", bundle.trace.join("\n").as_bytes(), 0o644)?;
+    if let Some(Stdin::Content(content)) = bundle.inputs.stdin() {
+        tarball.append("stdin", content.as_bytes(), 0o644)?;
+    }
+
+    if options.include_inputs {
+        for (input_path, identity) in bundle.inputs.input_files() {
+            // Absolute inputs (e.g., toolchains) are expected to be installed on the replaying
+            // host, and absent inputs have no content.
+            if input_path.is_absolute() || identity.is_none() {
+                continue;
+            }
+            let mut contents = vec![];
+            working_directory
+                .open_file_for_read(input_path)
+                .map_err(anyhow::Error::from)
+                .and_then(|mut file| Ok(file.read_to_end(&mut contents)?))
+                .with_context(|| format!("reading input {:?} for reproduction", input_path))?;
+            tarball.append(Path::new("inputs").join(input_path), &contents, 0o644)?;
+        }
+    }
+    tarball.append("replay.sh", replay_script(bundle.inputs).as_bytes(), 0o755)?;
+
+    tarball
+        .builder
+        .into_inner()
+        .with_context(|| format!("finishing reproduction bundle {:?}", path))?;
+    Ok(path)
+}
+
+struct Tarball {
+    builder: tar::Builder<std::fs::File>,
+    root: PathBuf,
+}
+
+impl Tarball {
+    fn append<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        contents: &[u8],
+        mode: u32,
+    ) -> anyhow::Result<()> {
+        let path = self.root.join(path);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(mode);
+        // Fixed timestamps keep bundles of the same failure byte-for-byte identical.
+        header.set_mtime(0);
+        header.set_cksum();
+        self.builder
+            .append_data(&mut header, &path, contents)
+            .with_context(|| format!("adding {:?} to reproduction bundle", path))
+    }
+}
+
+/// Quotes `value` as a single shell word.
+fn shell_quote<S: AsRef<str>>(value: S) -> String {
+    format!("'{}'", value.as_ref().replace('\'', r#"'\''"#))
+}
+
+fn replay_script<IS: IdentitySchemeApi>(inputs: &TaskInputs<IS>) -> String {
+    let mut script = String::from(
+        r#"#!/bin/sh
+# Reruns a failed artifact-executor task with its recorded arguments and environment. Run from the
+# directory in which the task ran, or pass a directory in which to restore the bundled inputs.
+set -e
+bundle_directory=$(cd "$(dirname "$0")" && pwd)
+if [ $# -gt 0 ]; then
+  mkdir -p "$1"
+  cd "$1"
+  if [ -d "$bundle_directory/inputs" ]; then
+    cp -R "$bundle_directory/inputs/." .
+  fi
+fi
+"#,
+    );
+    for (source, destination) in inputs.staged_inputs() {
+        if let Some(parent) = destination.parent() {
+            if !parent.as_os_str().is_empty() {
+                script.push_str(&format!(
+                    "mkdir -p {}\n",
+                    shell_quote(parent.to_string_lossy())
+                ));
+            }
+        }
+        script.push_str(&format!(
+            "cp {} {}\n",
+            shell_quote(source.to_string_lossy()),
+            shell_quote(destination.to_string_lossy())
+        ));
+    }
+
+    let mut command = vec![String::from("exec env -i")];
+    command.extend(
+        inputs
+            .environment_variables()
+            .map(|(name, value)| shell_quote(format!("{}={}", name, value))),
+    );
+    let program = inputs.program();
+    let program = if program.is_relative() {
+        Path::new(".").join(program)
+    } else {
+        program.clone()
+    };
+    if let Some(argv0) = inputs.argv0() {
+        command.push(String::from(r#"bash -c 'exec -a "$0" "$@"'"#));
+        command.push(shell_quote(argv0));
+    }
+    command.push(shell_quote(program.to_string_lossy()));
+    command.extend(inputs.arguments().map(shell_quote));
+    match inputs.stdin() {
+        None => command.push(String::from("< /dev/null")),
+        Some(Stdin::Path(path)) => {
+            command.push(format!("< {}", shell_quote(path.to_string_lossy())))
+        }
+        Some(Stdin::Content(_)) => command.push(String::from(r#"< "$bundle_directory/stdin""#)),
+    }
+    script.push_str(&command.join(" \\\n  "));
+    script.push('\n');
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run_capturing_repro;
+    use super::ReproOptions;
+    use crate::blob::JSON;
+    use crate::canonical::Outputs;
+    use crate::canonical::TaskInputsBuilder;
+    use crate::execute::CacheDirectoryTaskExecutor;
+    use crate::fs::HostFilesystem;
+    use crate::identity::IdentityScheme as _;
+    use crate::transport::ContentSha256;
+    use crate::transport::Stdin;
+    use std::collections::BTreeMap;
+    use std::io::Read as _;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_repro_bundle() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let working_directory = temporary_directory.path().join("work");
+        std::fs::create_dir_all(&working_directory).expect("create working directory");
+        std::fs::write(working_directory.join("input.txt"), "input").expect("write input");
+        let mut working_filesystem =
+            HostFilesystem::try_new(working_directory.clone()).expect("host filesystem");
+        let mut executor = CacheDirectoryTaskExecutor::<_, ContentSha256, JSON, _>::new(
+            HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+                .expect("host filesystem"),
+        )
+        .expect("executor");
+
+        let input_identity =
+            ContentSha256::identify_content("input".as_bytes()).expect("identify input");
+        let inputs = TaskInputsBuilder::<ContentSha256>::new()
+            .environment_variable("GREETING", "it's me")
+            .program("/bin/sh")
+            .arguments(["-c", "cat; echo \"$GREETING\"; echo oops >&2; exit 3"])
+            .input_file("input.txt", Some(input_identity))
+            .stdin(Stdin::Content(String::from("from stdin\n")))
+            .outputs_description(Outputs::empty())
+            .build()
+            .expect("task inputs");
+        let options = ReproOptions {
+            directory: temporary_directory.path().join("repro"),
+            include_inputs: true,
+        };
+        let error = run_capturing_repro(
+            &mut executor,
+            &mut working_filesystem,
+            &inputs,
+            None,
+            false,
+            &options,
+        )
+        .expect_err("task fails");
+        assert!(error
+            .to_string()
+            .starts_with("reproduction bundle written to"));
+
+        let bundle = std::fs::read_dir(&options.directory)
+            .expect("read reproduction directory")
+            .next()
+            .expect("bundle")
+            .expect("bundle entry")
+            .path();
+        let mut archive = tar::Archive::new(std::fs::File::open(&bundle).expect("open bundle"));
+        let mut entries = BTreeMap::new();
+        for entry in archive.entries().expect("bundle entries") {
+            let mut entry = entry.expect("bundle entry");
+            let path = entry.path().expect("entry path").into_owned();
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).expect("read entry");
+            let path: PathBuf = path.components().skip(1).collect();
+            entries.insert(path, contents);
+        }
+        assert_eq!("from stdin\nit's me\n", entries[&PathBuf::from("stdout")]);
+        assert_eq!("oops\n", entries[&PathBuf::from("stderr")]);
+        assert_eq!("input", entries[&PathBuf::from("inputs/input.txt")]);
+        assert!(entries[&PathBuf::from("trace.log")].contains("ChildExited"));
+        assert!(entries[&PathBuf::from("error.txt")].contains("exit status: 3"));
+
+        // Replaying in a fresh directory reproduces the task's output from the bundle alone.
+        let extracted = temporary_directory.path().join("extracted");
+        tar::Archive::new(std::fs::File::open(&bundle).expect("open bundle"))
+            .unpack(&extracted)
+            .expect("unpack bundle");
+        let replay_script = std::fs::read_dir(&extracted)
+            .expect("read extracted bundle")
+            .next()
+            .expect("bundle directory")
+            .expect("bundle directory entry")
+            .path()
+            .join("replay.sh");
+        let replay = std::process::Command::new(&replay_script)
+            .arg(temporary_directory.path().join("replay"))
+            .output()
+            .expect("run replay script");
+        assert_eq!(Some(3), replay.status.code());
+        assert_eq!("from stdin\nit's me\n".as_bytes(), &replay.stdout[..]);
+        assert_eq!(
+            "input",
+            std::fs::read_to_string(temporary_directory.path().join("replay/input.txt"))
+                .expect("read restored input")
+        );
+    }
+}
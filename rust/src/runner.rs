@@ -2,14 +2,148 @@
 // Use of this source code is governed by a Apache-style license that can be
 // found in the LICENSE file.
 
+use crate::canonical::get_globbed_output_files;
+use crate::canonical::shell_quote;
 use crate::canonical::TaskInputs;
+use crate::error::Error;
+use crate::error::IoOperation;
+use crate::events;
+use crate::events::Event;
 use crate::fs::Filesystem as FilesystemApi;
+use crate::fs::HostFilesystem;
 use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::profile;
+use crate::profile::Phase;
+use crate::schema::FormatVersion;
+use crate::transport::Stdin;
+use crate::transport::TaskResult;
 use anyhow::Context;
+use std::fs::File;
+use std::io::Write as _;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
 use std::process::Command;
+use std::process::ExitStatus;
 use std::process::Stdio;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Shortest and longest intervals at which a runner polls a task process that may need to be
+/// killed.
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Result of a task run that ran to completion, successfully or not. Recorded in the task's
+/// metadata, and by executors as a `transport::TaskResult`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExecutionResult {
+    /// Exit status of the task process. `None` when the runner did not spawn a process.
+    pub status: Option<ExitStatus>,
+    /// Wall-clock time from spawning the task process until it exited.
+    pub duration: Duration,
+    /// Peak resident set size of the task process, if the platform reports it.
+    pub peak_memory_bytes: Option<u64>,
+    /// Why the runner killed the task process, if it did.
+    pub interruption: Option<Interruption>,
+}
+
+/// Reason a runner killed a task process before it exited.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Interruption {
+    /// The task ran past its deadline.
+    TimedOut,
+    /// The task's cancellation token was cancelled.
+    Cancelled,
+}
+
+impl std::fmt::Display for Interruption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::TimedOut => "timed out",
+            Self::Cancelled => "was cancelled",
+        })
+    }
+}
+
+/// Shared flag that asks runners to kill the task processes that they are waiting for. Clones
+/// share the flag, so that another thread (e.g., a signal handler) can cancel running tasks.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl ExecutionResult {
+    /// Whether the task succeeded: It exited with status zero, or no process was spawned.
+    pub fn success(&self) -> bool {
+        self.interruption.is_none() && self.status.map(|status| status.success()).unwrap_or(true)
+    }
+
+    /// Whether the runner killed the task process because it ran past its deadline.
+    pub fn timed_out(&self) -> bool {
+        self.interruption == Some(Interruption::TimedOut)
+    }
+
+    /// Exit code of the task process, if it exited normally.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.status.and_then(|status| status.code())
+    }
+
+    /// Signal that terminated the task process, if any.
+    #[cfg(unix)]
+    pub fn signal(&self) -> Option<i32> {
+        use std::os::unix::process::ExitStatusExt as _;
+
+        self.status.and_then(|status| status.signal())
+    }
+
+    #[cfg(not(unix))]
+    pub fn signal(&self) -> Option<i32> {
+        None
+    }
+
+    /// Recovers a result recorded by `to_task_result`. Exit statuses are only recovered on unix.
+    pub fn from_task_result(task_result: &TaskResult) -> Self {
+        Self {
+            status: exit_status(task_result.exit_code, task_result.signal),
+            duration: Duration::from_nanos(
+                u64::try_from(task_result.duration_nanos).unwrap_or(u64::MAX),
+            ),
+            peak_memory_bytes: task_result.peak_memory_bytes,
+            interruption: None,
+        }
+    }
+
+    /// Records this result of an execution that completed at `timestamp_nanos`.
+    pub fn to_task_result(&self, timestamp_nanos: i64) -> TaskResult {
+        TaskResult {
+            format_version: FormatVersion::default(),
+            timestamp_nanos,
+            exit_code: self.exit_code(),
+            signal: self.signal(),
+            duration_nanos: self.duration.as_nanos(),
+            peak_memory_bytes: self.peak_memory_bytes,
+        }
+    }
+}
 
 pub trait Runner {
+    /// Runs the task described by `inputs` in `filesystem`. Fails only if the task could not be run
+    /// to completion; a task that exits unsuccessfully produces an `ExecutionResult` that is not a
+    /// `success()`. A task still running at `deadline`, or once `cancellation` is cancelled, is
+    /// killed, and its result records the `Interruption`.
     fn run_task<
         Filesystem: FilesystemApi,
         IdentityScheme: IdentitySchemeApi,
@@ -21,9 +155,32 @@ pub trait Runner {
         inputs: &TaskInputs<IdentityScheme>,
         stdout: Stdout,
         stderr: Stderr,
-    ) -> anyhow::Result<()>;
+        deadline: Option<Instant>,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<ExecutionResult>;
 }
 
+impl<R: Runner> Runner for &mut R {
+    fn run_task<
+        Filesystem: FilesystemApi,
+        IdentityScheme: IdentitySchemeApi,
+        Stdout: Into<Stdio>,
+        Stderr: Into<Stdio>,
+    >(
+        &mut self,
+        filesystem: &mut Filesystem,
+        inputs: &TaskInputs<IdentityScheme>,
+        stdout: Stdout,
+        stderr: Stderr,
+        deadline: Option<Instant>,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<ExecutionResult> {
+        (**self).run_task(filesystem, inputs, stdout, stderr, deadline, cancellation)
+    }
+}
+
+/// Runs tasks as child processes. On unix, each task runs in its own process group, so that
+/// interrupting a task also kills the processes that it spawned.
 pub struct SimpleRunner;
 
 impl Runner for SimpleRunner {
@@ -38,49 +195,655 @@ impl Runner for SimpleRunner {
         inputs: &TaskInputs<IdentityScheme>,
         stdout: Stdout,
         stderr: Stderr,
-    ) -> anyhow::Result<()> {
+        deadline: Option<Instant>,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<ExecutionResult> {
         let working_directory = filesystem.working_directory();
         if working_directory.is_none() && inputs.program().is_relative() {
             anyhow::bail!("attempted to run task filesystem that has no working directory, but relative program with relative path, {:?}", inputs.program());
         }
         let working_directory = working_directory.unwrap();
 
+        let spawn_span = profile::span(Phase::Spawn, || format!("{}", inputs.program().display()));
+        inputs
+            .stage_inputs(filesystem)
+            .context("staging inputs for task")?;
+
         let program = if inputs.program().is_absolute() {
             std::borrow::Cow::Borrowed(inputs.program())
         } else {
             std::borrow::Cow::Owned(working_directory.join(inputs.program()))
         };
 
+        let stdin = match inputs.stdin() {
+            None => Stdio::null(),
+            Some(Stdin::Path(path)) => File::open(working_directory.join(path))
+                .map_err(|error| Error::io(path, IoOperation::Read, error))
+                .with_context(|| format!("opening standard input file, {:?}", path))?
+                .into(),
+            Some(Stdin::Content(_)) => Stdio::piped(),
+        };
+
         let mut command = Command::new(program.as_path());
         command
             .current_dir(working_directory)
             .env_clear()
             .envs(inputs.environment_variables().map(|v| v.clone()))
             .args(inputs.arguments())
-            .stdin(Stdio::null())
+            .stdin(stdin)
             .stdout(stdout)
             .stderr(stderr);
+        if let Some(argv0) = inputs.argv0() {
+            set_argv0(&mut command, argv0)?;
+        }
+        set_process_group(&mut command);
         let mut child = command
             .spawn()
-            .map_err(anyhow::Error::from)
+            .map_err(|error| Error::io(program.as_path(), IoOperation::Execute, error))
             .with_context(|| {
                 format!("spawning child process for binary, {:?}", program.as_path())
             })?;
-        let status = child
-            .wait()
+        let start = Instant::now();
+        events::publish(&Event::ChildSpawned {
+            program: program.as_path(),
+            process_id: child.id(),
+        });
+        drop(spawn_span);
+
+        // Write inline standard input on another thread, so that a child that fills its output
+        // pipes before draining its input cannot deadlock the runner.
+        let stdin_writer = match (inputs.stdin(), child.stdin.take()) {
+            (Some(Stdin::Content(content)), Some(mut child_stdin)) => {
+                let content = content.clone();
+                Some(std::thread::spawn(move || {
+                    match child_stdin.write_all(content.as_bytes()) {
+                        // The child need not read all of its input.
+                        Err(error) if error.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+                        result => result,
+                    }
+                }))
+            }
+            _ => None,
+        };
+        let wait_span = profile::span(Phase::Wait, || format!("{}", program.display()));
+        let (status, peak_memory_bytes, interruption) =
+            wait_for_child(child, deadline, cancellation)
+                .context("waiting for child proces to complete")?;
+        let duration = start.elapsed();
+        drop(wait_span);
+        events::publish(&Event::ChildExited {
+            program: program.as_path(),
+            status,
+            duration,
+        });
+        if let Some(stdin_writer) = stdin_writer {
+            stdin_writer
+                .join()
+                .map_err(|_| anyhow::anyhow!("standard input writer panicked"))?
+                .map_err(anyhow::Error::from)
+                .context("writing standard input")?;
+        }
+
+        if let Some(interruption) = interruption {
+            tracing::warn!(
+                "task {} {} after {:?}",
+                program.display(),
+                interruption,
+                duration
+            );
+        }
+
+        Ok(ExecutionResult {
+            status: Some(status),
+            duration,
+            peak_memory_bytes,
+            interruption,
+        })
+    }
+}
+
+/// Runs tasks in a fresh temporary directory that contains only their declared input files, and
+/// then moves their declared outputs into the working directory. A task that depends on an
+/// undeclared file in the working directory fails to find it, rather than producing outputs that
+/// are cached under a key that does not reflect all of their inputs.
+///
+/// Inputs are hard-linked into the sandbox where possible, so tasks must not modify their inputs in
+/// place unless inputs are copied (see `with_copied_inputs`). Files outside the working directory
+/// (e.g., absolute program paths) are not sandboxed.
+pub struct SandboxRunner<R: Runner> {
+    parent_directory: Option<PathBuf>,
+    copy_inputs: bool,
+    delegate: R,
+}
+
+impl<R: Runner> SandboxRunner<R> {
+    pub fn new(delegate: R) -> Self {
+        Self {
+            parent_directory: None,
+            copy_inputs: false,
+            delegate,
+        }
+    }
+
+    /// Creates sandboxes in `parent_directory`, rather than the system's temporary directory.
+    /// Hard links only succeed when the sandbox is on the same device as the working directory.
+    pub fn with_parent_directory<P: AsRef<Path>>(mut self, parent_directory: P) -> Self {
+        self.parent_directory = Some(parent_directory.as_ref().to_path_buf());
+        self
+    }
+
+    /// Copies inputs into the sandbox, rather than hard-linking them.
+    pub fn with_copied_inputs(mut self) -> Self {
+        self.copy_inputs = true;
+        self
+    }
+
+    fn link_or_copy(&self, source: &Path, destination: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|error| Error::io(parent, IoOperation::Create, error))?;
+        }
+        if !self.copy_inputs && std::fs::hard_link(source, destination).is_ok() {
+            return Ok(());
+        }
+        std::fs::copy(source, destination)
+            .map_err(|error| Error::io(source, IoOperation::Read, error))
+            .with_context(|| format!("copying {:?} into sandbox", source))?;
+        Ok(())
+    }
+}
+
+impl<R: Runner> Runner for SandboxRunner<R> {
+    fn run_task<
+        Filesystem: FilesystemApi,
+        IdentityScheme: IdentitySchemeApi,
+        Stdout: Into<Stdio>,
+        Stderr: Into<Stdio>,
+    >(
+        &mut self,
+        filesystem: &mut Filesystem,
+        inputs: &TaskInputs<IdentityScheme>,
+        stdout: Stdout,
+        stderr: Stderr,
+        deadline: Option<Instant>,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<ExecutionResult> {
+        let working_directory = filesystem.working_directory().ok_or_else(|| {
+            anyhow::anyhow!("attempted to sandbox task filesystem that has no working directory")
+        })?;
+        let parent_directory = self
+            .parent_directory
+            .clone()
+            .unwrap_or_else(std::env::temp_dir);
+        let sandbox = tempfile::Builder::new()
+            .prefix("ae-sandbox-")
+            .tempdir_in(&parent_directory)
+            .map_err(|error| Error::io(&parent_directory, IoOperation::Create, error))
+            .context("creating sandbox directory")?;
+
+        let stdin_path = match inputs.stdin() {
+            Some(Stdin::Path(path)) => Some(path),
+            _ => None,
+        };
+        let sandboxed_inputs = inputs
+            .input_files()
+            .map(|(path, _)| path)
+            .chain(std::iter::once(inputs.program()))
+            .chain(stdin_path)
+            .filter(|path| path.is_relative());
+        for path in sandboxed_inputs {
+            if path
+                .components()
+                .any(|component| component == Component::ParentDir)
+            {
+                anyhow::bail!("input, {:?}, is outside of the working directory", path);
+            }
+            self.link_or_copy(&working_directory.join(path), &sandbox.path().join(path))
+                .with_context(|| format!("sandboxing input {:?}", path))?;
+        }
+
+        // Tasks may expect the directories that contain their outputs to exist.
+        let mut output_paths = inputs.output_paths()?;
+        for parent in output_paths.iter().filter_map(|path| path.parent()) {
+            let parent = sandbox.path().join(parent);
+            std::fs::create_dir_all(&parent)
+                .map_err(|error| Error::io(&parent, IoOperation::Create, error))?;
+        }
+
+        let mut sandbox_filesystem = HostFilesystem::try_new(sandbox.path().to_path_buf())?;
+        let execution_result = self.delegate.run_task(
+            &mut sandbox_filesystem,
+            inputs,
+            stdout,
+            stderr,
+            deadline,
+            cancellation,
+        )?;
+        if !execution_result.success() {
+            // Outputs of failed tasks are not collected.
+            return Ok(execution_result);
+        }
+
+        output_paths.extend(get_globbed_output_files(&mut sandbox_filesystem, inputs)?);
+        for path in output_paths {
+            let source = sandbox.path().join(&path);
+            if !source.exists() {
+                // Missing outputs are reported when outputs are collected.
+                continue;
+            }
+            let destination = working_directory.join(&path);
+            if let Some(parent) = destination.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|error| Error::io(parent, IoOperation::Create, error))?;
+            }
+            if std::fs::rename(&source, &destination).is_err() {
+                // The sandbox may be on another device.
+                std::fs::copy(&source, &destination)
+                    .map_err(|error| Error::io(&destination, IoOperation::Write, error))
+                    .with_context(|| format!("moving output {:?} out of sandbox", path))?;
+            }
+        }
+        Ok(execution_result)
+    }
+}
+
+/// Total length, in bytes, of argument lists above which `ResponseFileRunner` passes arguments
+/// in a response file by default. Well below the limit that Linux places on a single argument.
+pub const DEFAULT_RESPONSE_FILE_THRESHOLD_BYTES: usize = 128 * 1024;
+
+/// Prefix of the names of response files generated by `ResponseFileRunner`.
+const RESPONSE_FILE_PREFIX: &str = "ae-args-";
+
+/// Passes argument lists that are too long for the operating system to the program as a single
+/// `@response_file` argument, as understood by compilers and linkers. Response files contain one
+/// shell-quoted argument per line.
+///
+/// Response files are written to the working directory, named by the identity of their contents,
+/// and are removed when the task exits. Each is added to the input files of the task passed to the
+/// delegate, so that, e.g., a `SandboxRunner` delegate makes it available to the task.
+pub struct ResponseFileRunner<R: Runner> {
+    threshold_bytes: usize,
+    delegate: R,
+}
+
+impl<R: Runner> ResponseFileRunner<R> {
+    pub fn new(delegate: R) -> Self {
+        Self {
+            threshold_bytes: DEFAULT_RESPONSE_FILE_THRESHOLD_BYTES,
+            delegate,
+        }
+    }
+
+    /// Uses response files for argument lists longer than `threshold_bytes`, rather than
+    /// `DEFAULT_RESPONSE_FILE_THRESHOLD_BYTES`.
+    pub fn with_threshold_bytes(mut self, threshold_bytes: usize) -> Self {
+        self.threshold_bytes = threshold_bytes;
+        self
+    }
+}
+
+impl<R: Runner> Runner for ResponseFileRunner<R> {
+    fn run_task<
+        Filesystem: FilesystemApi,
+        IdentityScheme: IdentitySchemeApi,
+        Stdout: Into<Stdio>,
+        Stderr: Into<Stdio>,
+    >(
+        &mut self,
+        filesystem: &mut Filesystem,
+        inputs: &TaskInputs<IdentityScheme>,
+        stdout: Stdout,
+        stderr: Stderr,
+        deadline: Option<Instant>,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<ExecutionResult> {
+        // Each argument is terminated by a NUL byte in the argument list.
+        let arguments_length: usize = inputs.arguments().map(|argument| argument.len() + 1).sum();
+        if arguments_length <= self.threshold_bytes {
+            return self.delegate.run_task(
+                filesystem,
+                inputs,
+                stdout,
+                stderr,
+                deadline,
+                cancellation,
+            );
+        }
+
+        let mut contents = String::with_capacity(arguments_length);
+        for argument in inputs.arguments() {
+            contents.push_str(&shell_quote(argument));
+            contents.push('\n');
+        }
+        let identity = IdentityScheme::identify_content(contents.as_bytes())
+            .context("identifying response file")?;
+        let response_file = PathBuf::from(format!(
+            "{}{}.rsp",
+            RESPONSE_FILE_PREFIX,
+            identity.to_string()
+        ));
+        let inputs = inputs
+            .clone()
+            .with_response_file(&response_file, identity)?;
+        filesystem
+            .open_file_for_write(&response_file)
             .map_err(anyhow::Error::from)
-            .context("waiting for child proces to complete")?;
+            .context("creating response file")?
+            .write_all(contents.as_bytes())
+            .map_err(|error| Error::io(&response_file, IoOperation::Write, error))
+            .context("writing response file")?;
+
+        let execution_result =
+            self.delegate
+                .run_task(filesystem, &inputs, stdout, stderr, deadline, cancellation);
+        if let Err(error) = filesystem.remove_file(&response_file) {
+            tracing::warn!(
+                "removing response file {:?}: {}",
+                response_file,
+                anyhow::Error::from(error)
+            );
+        }
+        execution_result
+    }
+}
+
+/// Runner with which executors run tasks unless they are given another.
+pub type DefaultRunner = PolicyRunner<SimpleRunner>;
+
+/// Runs each task with the isolation and limits that it requests: a task whose inputs set a
+/// `transport::Sandbox` runs under an `IsolatedRunner`, one whose inputs set
+/// `transport::ResourceLimits` runs under a `LimitedRunner`, and other tasks run under the delegate
+/// directly. A task whose requests cannot be enforced on this host (e.g., because bubblewrap is
+/// not installed) fails, rather than running unconfined.
+pub struct PolicyRunner<R: Runner> {
+    #[cfg(target_os = "linux")]
+    bubblewrap_path: PathBuf,
+    #[cfg(target_os = "linux")]
+    prlimit_path: PathBuf,
+    delegate: R,
+}
+
+impl<R: Runner> PolicyRunner<R> {
+    pub fn new(delegate: R) -> Self {
+        Self {
+            #[cfg(target_os = "linux")]
+            bubblewrap_path: PathBuf::from(DEFAULT_BUBBLEWRAP_PATH),
+            #[cfg(target_os = "linux")]
+            prlimit_path: PathBuf::from(DEFAULT_PRLIMIT_PATH),
+            delegate,
+        }
+    }
+
+    /// Isolates tasks with the bubblewrap utility at `bubblewrap_path`, rather than
+    /// `DEFAULT_BUBBLEWRAP_PATH`.
+    #[cfg(target_os = "linux")]
+    pub fn with_bubblewrap_path<P: AsRef<Path>>(mut self, bubblewrap_path: P) -> Self {
+        self.bubblewrap_path = bubblewrap_path.as_ref().to_path_buf();
+        self
+    }
+
+    /// Limits tasks with the `prlimit` utility at `prlimit_path`, rather than
+    /// `DEFAULT_PRLIMIT_PATH`.
+    #[cfg(target_os = "linux")]
+    pub fn with_prlimit_path<P: AsRef<Path>>(mut self, prlimit_path: P) -> Self {
+        self.prlimit_path = prlimit_path.as_ref().to_path_buf();
+        self
+    }
+}
+
+impl Default for PolicyRunner<SimpleRunner> {
+    fn default() -> Self {
+        Self::new(SimpleRunner)
+    }
+}
+
+impl<R: Runner> Runner for PolicyRunner<R> {
+    fn run_task<
+        Filesystem: FilesystemApi,
+        IdentityScheme: IdentitySchemeApi,
+        Stdout: Into<Stdio>,
+        Stderr: Into<Stdio>,
+    >(
+        &mut self,
+        filesystem: &mut Filesystem,
+        inputs: &TaskInputs<IdentityScheme>,
+        stdout: Stdout,
+        stderr: Stderr,
+        deadline: Option<Instant>,
+        cancellation: &CancellationToken,
+    ) -> anyhow::Result<ExecutionResult> {
+        let sandboxed = inputs.sandbox().is_some();
+        let limited = inputs.resource_limits().is_some();
+        if !sandboxed && !limited {
+            return self.delegate.run_task(
+                filesystem,
+                inputs,
+                stdout,
+                stderr,
+                deadline,
+                cancellation,
+            );
+        }
 
-        if !status.success() {
-            anyhow::bail!("child returned unsuccessful exit status: {}", status);
+        #[cfg(target_os = "linux")]
+        {
+            if sandboxed {
+                require_tool("a sandbox", "bubblewrap", &self.bubblewrap_path)?;
+            }
+            if limited {
+                require_tool("resource limits", "prlimit", &self.prlimit_path)?;
+            }
+            match (sandboxed, limited) {
+                (true, false) => {
+                    IsolatedRunner::try_new(&self.bubblewrap_path, &mut self.delegate)?.run_task(
+                        filesystem,
+                        inputs,
+                        stdout,
+                        stderr,
+                        deadline,
+                        cancellation,
+                    )
+                }
+                (false, true) => LimitedRunner::try_new(&self.prlimit_path, &mut self.delegate)?
+                    .run_task(filesystem, inputs, stdout, stderr, deadline, cancellation),
+                // Limits apply to bubblewrap, and thereby to the isolated task that it spawns.
+                _ => IsolatedRunner::try_new(
+                    &self.bubblewrap_path,
+                    LimitedRunner::try_new(&self.prlimit_path, &mut self.delegate)?,
+                )?
+                .run_task(
+                    filesystem,
+                    inputs,
+                    stdout,
+                    stderr,
+                    deadline,
+                    cancellation,
+                ),
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (filesystem, stdout, stderr, deadline, cancellation);
+            anyhow::bail!(
+                "task requests {}, which cannot be enforced on this platform",
+                match (sandboxed, limited) {
+                    (true, false) => "a sandbox",
+                    (false, true) => "resource limits",
+                    _ => "a sandbox and resource limits",
+                }
+            )
         }
+    }
+}
 
+/// Fails unless `tool_path`, the utility that enforces what a task `requests`, is installed.
+#[cfg(target_os = "linux")]
+fn require_tool(requests: &str, tool: &str, tool_path: &Path) -> anyhow::Result<()> {
+    if tool_path.is_file() {
         Ok(())
+    } else {
+        anyhow::bail!(
+            "task requests {}, but {} is not installed at {:?}",
+            requests,
+            tool,
+            tool_path
+        )
+    }
+}
+
+#[cfg(unix)]
+fn set_argv0(command: &mut Command, argv0: &str) -> anyhow::Result<()> {
+    use std::os::unix::process::CommandExt as _;
+
+    command.arg0(argv0);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_argv0(_command: &mut Command, argv0: &str) -> anyhow::Result<()> {
+    anyhow::bail!(
+        "overriding argv[0] (to {:?}) is not supported on this platform",
+        argv0
+    )
+}
+
+/// Puts the child process in a new process group, whose id is the child's process id.
+#[cfg(unix)]
+fn set_process_group(command: &mut Command) {
+    use std::os::unix::process::CommandExt as _;
+
+    command.process_group(0);
+}
+
+#[cfg(not(unix))]
+fn set_process_group(_command: &mut Command) {}
+
+/// The interruption, if any, for which a task process that is still running should be killed.
+fn interruption(
+    deadline: Option<Instant>,
+    cancellation: &CancellationToken,
+) -> Option<Interruption> {
+    if cancellation.is_cancelled() {
+        Some(Interruption::Cancelled)
+    } else if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        Some(Interruption::TimedOut)
+    } else {
+        None
+    }
+}
+
+/// Sleeps for `poll_interval`, or until `deadline` if that is sooner, returning the interval for
+/// the next poll.
+fn sleep_until_next_poll(poll_interval: Duration, deadline: Option<Instant>) -> Duration {
+    let sleep_duration = match deadline {
+        Some(deadline) => poll_interval.min(deadline.saturating_duration_since(Instant::now())),
+        None => poll_interval,
+    };
+    std::thread::sleep(sleep_duration);
+    (poll_interval * 2).min(MAX_POLL_INTERVAL)
+}
+
+/// Waits for `child`, which leads its own process group, to exit, reporting its exit status and
+/// peak resident set size. The child's process group is killed at `deadline`, or once
+/// `cancellation` is cancelled.
+#[cfg(unix)]
+fn wait_for_child(
+    child: Child,
+    deadline: Option<Instant>,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<(ExitStatus, Option<u64>, Option<Interruption>)> {
+    use std::os::unix::process::ExitStatusExt as _;
+
+    let child_pid = child.id() as libc::pid_t;
+    let mut status: libc::c_int = 0;
+    // SAFETY: `rusage` is plain data that `wait4` fully initializes on success.
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    let mut interrupted = None;
+    let mut poll_interval = MIN_POLL_INTERVAL;
+    loop {
+        // Once the child has been killed, block until it has exited.
+        let options = if interrupted.is_some() {
+            0
+        } else {
+            libc::WNOHANG
+        };
+        // SAFETY: `child` has not yet been waited for, so its pid still refers to it, and both
+        // out-parameters point to valid, writable memory.
+        let pid = unsafe { libc::wait4(child_pid, &mut status, options, &mut rusage) };
+        if pid < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(anyhow::Error::from(error));
+        }
+        if pid == child_pid {
+            break;
+        }
+        interrupted = interruption(deadline, cancellation);
+        match interrupted {
+            // SAFETY: The child leads a process group whose id is its pid, which remains reserved
+            // until the child is waited for.
+            Some(_) => unsafe {
+                libc::kill(-child_pid, libc::SIGKILL);
+            },
+            None => poll_interval = sleep_until_next_poll(poll_interval, deadline),
+        }
+    }
+
+    // `ru_maxrss` is reported in bytes on macOS, and in kilobytes elsewhere.
+    let max_rss = u64::try_from(rusage.ru_maxrss).ok();
+    #[cfg(target_os = "macos")]
+    let peak_memory_bytes = max_rss;
+    #[cfg(not(target_os = "macos"))]
+    let peak_memory_bytes = max_rss.map(|kilobytes| kilobytes * 1024);
+
+    Ok((ExitStatus::from_raw(status), peak_memory_bytes, interrupted))
+}
+
+#[cfg(not(unix))]
+fn wait_for_child(
+    mut child: Child,
+    deadline: Option<Instant>,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<(ExitStatus, Option<u64>, Option<Interruption>)> {
+    let mut poll_interval = MIN_POLL_INTERVAL;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((status, None, None));
+        }
+        if let Some(interrupted) = interruption(deadline, cancellation) {
+            child.kill()?;
+            return Ok((child.wait()?, None, Some(interrupted)));
+        }
+        poll_interval = sleep_until_next_poll(poll_interval, deadline);
     }
 }
 
+/// Reconstructs the exit status of a process that exited with `exit_code` or was terminated by
+/// `signal`.
+#[cfg(unix)]
+fn exit_status(exit_code: Option<i32>, signal: Option<i32>) -> Option<ExitStatus> {
+    use std::os::unix::process::ExitStatusExt as _;
+
+    // Wait statuses hold a terminating signal in their low seven bits, and an exit code in their
+    // second byte.
+    match (exit_code, signal) {
+        (_, Some(signal)) => Some(ExitStatus::from_raw(signal & 0x7f)),
+        (Some(exit_code), None) => Some(ExitStatus::from_raw((exit_code & 0xff) << 8)),
+        (None, None) => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn exit_status(_exit_code: Option<i32>, _signal: Option<i32>) -> Option<ExitStatus> {
+    None
+}
+
 #[cfg(unix)]
 mod unix {
+    use super::CancellationToken;
+    use super::ExecutionResult;
     use super::Runner;
     use crate::blob::JSON;
     use crate::canonical::TaskInputs;
@@ -88,6 +851,7 @@ mod unix {
     use crate::identity::IdentityScheme as IdentitySchemeApi;
     use std::path::{Path, PathBuf};
     use std::process::Stdio;
+    use std::time::Instant;
 
     pub type TimedRunDeserializer = JSON;
 
@@ -135,7 +899,9 @@ mod unix {
             inputs: &TaskInputs<IdentityScheme>,
             stdout: Stdout,
             stderr: Stderr,
-        ) -> anyhow::Result<()> {
+            deadline: Option<Instant>,
+            cancellation: &CancellationToken,
+        ) -> anyhow::Result<ExecutionResult> {
             let inputs = inputs
                 .clone()
                 .wrap_program(filesystem, &self.time_program_path)?
@@ -153,7 +919,8 @@ mod unix {
                     .into_iter(),
                 );
 
-            self.delegate.run_task(filesystem, &inputs, stdout, stderr)
+            self.delegate
+                .run_task(filesystem, &inputs, stdout, stderr, deadline, cancellation)
         }
     }
 }
@@ -169,12 +936,23 @@ pub type TimedRunner<R> = unix::TimedRunner<R>;
 
 #[cfg(target_os = "linux")]
 mod linux {
+    use super::CancellationToken;
+    use super::ExecutionResult;
     use super::Runner;
     use crate::canonical::TaskInputs;
+    use crate::error::Error;
+    use crate::error::IoOperation;
     use crate::fs::Filesystem as FilesystemApi;
     use crate::identity::IdentityScheme as IdentitySchemeApi;
+    use crate::transport::ResourceLimits;
+    use crate::transport::Sandbox;
+    use crate::transport::Stdin;
+    use anyhow::Context as _;
     use std::path::{Path, PathBuf};
     use std::process::Stdio;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::atomic::Ordering;
+    use std::time::Instant;
 
     pub struct TracedRunner<R: Runner> {
         fsatrace_path: PathBuf,
@@ -221,7 +999,9 @@ mod linux {
             inputs: &TaskInputs<IdentityScheme>,
             stdout: Stdout,
             stderr: Stderr,
-        ) -> anyhow::Result<()> {
+            deadline: Option<Instant>,
+            cancellation: &CancellationToken,
+        ) -> anyhow::Result<ExecutionResult> {
             let inputs = inputs
                 .clone()
                 .wrap_program(filesystem, &self.fsatrace_path)?
@@ -238,18 +1018,340 @@ mod linux {
                     .into_iter(),
                 );
 
-            self.delegate.run_task(filesystem, &inputs, stdout, stderr)
+            self.delegate
+                .run_task(filesystem, &inputs, stdout, stderr, deadline, cancellation)
+        }
+    }
+
+    /// Default location of the bubblewrap (`bwrap`) utility.
+    pub const DEFAULT_BUBBLEWRAP_PATH: &str = "/usr/bin/bwrap";
+
+    /// System directories that isolated tasks may read, where they exist.
+    pub const DEFAULT_SYSTEM_PATHS: &[&str] =
+        &["/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/etc"];
+
+    /// Runs tasks under bubblewrap, in new namespaces. Tasks have no network access unless their
+    /// `transport::Sandbox` allows it, and see only their working directory, their absolute input
+    /// files, system directories, and their sandbox's read-only paths. Wrap a
+    /// `crate::runner::SandboxRunner` around this runner to also hide undeclared files in the
+    /// working directory.
+    pub struct IsolatedRunner<R: Runner> {
+        bubblewrap_path: PathBuf,
+        system_paths: Vec<PathBuf>,
+        delegate: R,
+    }
+
+    impl<R: Runner> IsolatedRunner<R> {
+        pub fn try_new<ToolPath: AsRef<Path>>(
+            bubblewrap_path: ToolPath,
+            delegate: R,
+        ) -> anyhow::Result<Self> {
+            path_argument(bubblewrap_path.as_ref())?;
+            Ok(Self {
+                bubblewrap_path: bubblewrap_path.as_ref().to_path_buf(),
+                system_paths: DEFAULT_SYSTEM_PATHS.iter().map(PathBuf::from).collect(),
+                delegate,
+            })
+        }
+
+        /// Replaces `DEFAULT_SYSTEM_PATHS` as the directories that every task may read.
+        pub fn with_system_paths<P: AsRef<Path>, I: IntoIterator<Item = P>>(
+            mut self,
+            system_paths: I,
+        ) -> Self {
+            self.system_paths = system_paths
+                .into_iter()
+                .map(|path| path.as_ref().to_path_buf())
+                .collect();
+            self
         }
+
+        /// Gets the arguments that make bubblewrap isolate the task described by `inputs`, which
+        /// runs in `working_directory`.
+        pub(super) fn bubblewrap_arguments<IdentityScheme: IdentitySchemeApi>(
+            &self,
+            working_directory: &Path,
+            inputs: &TaskInputs<IdentityScheme>,
+        ) -> anyhow::Result<Vec<String>> {
+            let default_sandbox = Sandbox::default();
+            let sandbox = inputs.sandbox().unwrap_or(&default_sandbox);
+
+            let mut arguments = vec![
+                String::from("--die-with-parent"),
+                String::from("--unshare-all"),
+            ];
+            if sandbox.network {
+                arguments.push(String::from("--share-net"));
+            }
+            for path in self.system_paths.iter() {
+                let path = path_argument(path)?;
+                arguments.extend([String::from("--ro-bind-try"), path.clone(), path]);
+            }
+            // Mount the scratch filesystems before binding paths, which may be beneath them.
+            arguments
+                .extend(["--proc", "/proc", "--dev", "/dev", "--tmpfs", "/tmp"].map(String::from));
+            let stdin_path = match inputs.stdin() {
+                Some(Stdin::Path(path)) => Some(path),
+                _ => None,
+            };
+            let absolute_inputs = inputs
+                .input_files()
+                .map(|(path, _)| path)
+                .chain(std::iter::once(inputs.program()))
+                .chain(stdin_path)
+                .filter(|path| path.is_absolute());
+            for path in sandbox.read_only_paths.iter().chain(absolute_inputs) {
+                let path = path_argument(path)?;
+                arguments.extend([String::from("--ro-bind"), path.clone(), path]);
+            }
+            let working_directory = path_argument(working_directory)?;
+            arguments.extend([
+                String::from("--bind"),
+                working_directory.clone(),
+                working_directory.clone(),
+                String::from("--chdir"),
+                working_directory,
+                String::from("--"),
+            ]);
+            Ok(arguments)
+        }
+    }
+
+    impl<R: Runner> Runner for IsolatedRunner<R> {
+        fn run_task<
+            Filesystem: FilesystemApi,
+            IdentityScheme: IdentitySchemeApi,
+            Stdout: Into<Stdio>,
+            Stderr: Into<Stdio>,
+        >(
+            &mut self,
+            filesystem: &mut Filesystem,
+            inputs: &TaskInputs<IdentityScheme>,
+            stdout: Stdout,
+            stderr: Stderr,
+            deadline: Option<Instant>,
+            cancellation: &CancellationToken,
+        ) -> anyhow::Result<ExecutionResult> {
+            let working_directory = filesystem.working_directory().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "attempted to isolate task filesystem that has no working directory"
+                )
+            })?;
+            let arguments = self.bubblewrap_arguments(&working_directory, inputs)?;
+            let inputs = inputs
+                .clone()
+                .wrap_program(filesystem, &self.bubblewrap_path)?
+                .prepend_arguments(arguments.into_iter());
+
+            self.delegate
+                .run_task(filesystem, &inputs, stdout, stderr, deadline, cancellation)
+        }
+    }
+
+    /// Default location of the util-linux `prlimit` utility.
+    pub const DEFAULT_PRLIMIT_PATH: &str = "/usr/bin/prlimit";
+
+    /// Shell that moves tasks into their cgroups before running them.
+    const CGROUP_SHELL_PATH: &str = "/bin/sh";
+
+    /// Script, run by `CGROUP_SHELL_PATH` with a cgroup directory as `$0` and the task's command
+    /// line as its arguments, that moves the shell into the cgroup and then executes the task.
+    const JOIN_CGROUP_SCRIPT: &str = r#"echo 0 > "$0/cgroup.procs" && exec "$@""#;
+
+    /// Distinguishes the cgroups of the tasks run by this process.
+    static NEXT_CGROUP_ID: AtomicU64 = AtomicU64::new(0);
+
+    /// Runs tasks under `prlimit`, which sets each task's `transport::ResourceLimits` as resource
+    /// limits of its process (see `setrlimit(2)`) before executing it. Limits that a task does not
+    /// set are taken from the runner's default limits.
+    ///
+    /// Resource limits apply to each process separately. With a cgroup parent, each task with a
+    /// memory limit also runs in a cgroup v2 of its own, whose `memory.max` bounds the memory of all
+    /// of the task's processes together.
+    pub struct LimitedRunner<R: Runner> {
+        prlimit_path: PathBuf,
+        default_limits: ResourceLimits,
+        cgroup_parent: Option<PathBuf>,
+        delegate: R,
+    }
+
+    impl<R: Runner> LimitedRunner<R> {
+        pub fn try_new<ToolPath: AsRef<Path>>(
+            prlimit_path: ToolPath,
+            delegate: R,
+        ) -> anyhow::Result<Self> {
+            path_argument(prlimit_path.as_ref())?;
+            Ok(Self {
+                prlimit_path: prlimit_path.as_ref().to_path_buf(),
+                default_limits: ResourceLimits::default(),
+                cgroup_parent: None,
+                delegate,
+            })
+        }
+
+        /// Applies `default_limits` to tasks that do not set their own.
+        pub fn with_default_limits(mut self, default_limits: ResourceLimits) -> Self {
+            self.default_limits = default_limits;
+            self
+        }
+
+        /// Creates the cgroups of tasks in `cgroup_parent`, a cgroup v2 directory (e.g., under
+        /// `/sys/fs/cgroup`) that is writable by this process and has the memory controller enabled
+        /// for its children.
+        pub fn with_cgroup_parent<P: AsRef<Path>>(mut self, cgroup_parent: P) -> Self {
+            self.cgroup_parent = Some(cgroup_parent.as_ref().to_path_buf());
+            self
+        }
+
+        /// Gets the limits that apply to the task described by `inputs`.
+        pub(super) fn limits<IdentityScheme: IdentitySchemeApi>(
+            &self,
+            inputs: &TaskInputs<IdentityScheme>,
+        ) -> ResourceLimits {
+            let task_limits = inputs.resource_limits().cloned().unwrap_or_default();
+            ResourceLimits {
+                max_memory_bytes: task_limits
+                    .max_memory_bytes
+                    .or(self.default_limits.max_memory_bytes),
+                max_cpu_seconds: task_limits
+                    .max_cpu_seconds
+                    .or(self.default_limits.max_cpu_seconds),
+                max_open_files: task_limits
+                    .max_open_files
+                    .or(self.default_limits.max_open_files),
+            }
+        }
+
+        /// Creates a cgroup in which the memory of a task's processes totals at most
+        /// `max_memory_bytes`.
+        fn create_cgroup(
+            &self,
+            cgroup_parent: &Path,
+            max_memory_bytes: u64,
+        ) -> anyhow::Result<PathBuf> {
+            let cgroup = cgroup_parent.join(format!(
+                "ae-task-{}-{}",
+                std::process::id(),
+                NEXT_CGROUP_ID.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::create_dir(&cgroup)
+                .map_err(|error| Error::io(&cgroup, IoOperation::Create, error))
+                .context("creating task cgroup")?;
+            let memory_max = cgroup.join("memory.max");
+            std::fs::write(&memory_max, max_memory_bytes.to_string())
+                .map_err(|error| Error::io(&memory_max, IoOperation::Write, error))
+                .context("limiting task cgroup memory")?;
+            Ok(cgroup)
+        }
+    }
+
+    /// Gets the arguments that make `prlimit` apply `limits`.
+    pub(super) fn prlimit_arguments(limits: &ResourceLimits) -> Vec<String> {
+        let mut arguments = vec![];
+        if let Some(max_memory_bytes) = limits.max_memory_bytes {
+            arguments.push(format!("--as={}", max_memory_bytes));
+        }
+        if let Some(max_cpu_seconds) = limits.max_cpu_seconds {
+            arguments.push(format!("--cpu={}", max_cpu_seconds));
+        }
+        if let Some(max_open_files) = limits.max_open_files {
+            arguments.push(format!("--nofile={}", max_open_files));
+        }
+        if !arguments.is_empty() {
+            arguments.push(String::from("--"));
+        }
+        arguments
+    }
+
+    impl<R: Runner> Runner for LimitedRunner<R> {
+        fn run_task<
+            Filesystem: FilesystemApi,
+            IdentityScheme: IdentitySchemeApi,
+            Stdout: Into<Stdio>,
+            Stderr: Into<Stdio>,
+        >(
+            &mut self,
+            filesystem: &mut Filesystem,
+            inputs: &TaskInputs<IdentityScheme>,
+            stdout: Stdout,
+            stderr: Stderr,
+            deadline: Option<Instant>,
+            cancellation: &CancellationToken,
+        ) -> anyhow::Result<ExecutionResult> {
+            let limits = self.limits(inputs);
+            let mut inputs = inputs.clone();
+            let arguments = prlimit_arguments(&limits);
+            if !arguments.is_empty() {
+                inputs = inputs
+                    .wrap_program(filesystem, &self.prlimit_path)?
+                    .prepend_arguments(arguments.into_iter());
+            }
+            let cgroup = match (self.cgroup_parent.as_ref(), limits.max_memory_bytes) {
+                (Some(cgroup_parent), Some(max_memory_bytes)) => {
+                    Some(self.create_cgroup(cgroup_parent, max_memory_bytes)?)
+                }
+                _ => None,
+            };
+            if let Some(cgroup) = cgroup.as_ref() {
+                inputs = inputs
+                    .wrap_program(filesystem, CGROUP_SHELL_PATH)?
+                    .prepend_arguments(
+                        [
+                            String::from("-c"),
+                            String::from(JOIN_CGROUP_SCRIPT),
+                            path_argument(cgroup)?,
+                        ]
+                        .into_iter(),
+                    );
+            }
+
+            let execution_result =
+                self.delegate
+                    .run_task(filesystem, &inputs, stdout, stderr, deadline, cancellation);
+            if let Some(cgroup) = cgroup {
+                // Removal fails while processes that the task left behind remain in the cgroup.
+                if let Err(error) = std::fs::remove_dir(&cgroup) {
+                    tracing::warn!("removing task cgroup {:?}: {}", cgroup, error);
+                }
+            }
+            execution_result
+        }
+    }
+
+    fn path_argument(path: &Path) -> anyhow::Result<String> {
+        path.to_str()
+            .map(String::from)
+            .ok_or_else(|| anyhow::anyhow!("path, {:?}, cannot be formatted as string", path))
     }
 }
 
 #[cfg(target_os = "linux")]
 pub type TracedRunner<R> = linux::TracedRunner<R>;
 
+#[cfg(target_os = "linux")]
+pub const DEFAULT_BUBBLEWRAP_PATH: &str = linux::DEFAULT_BUBBLEWRAP_PATH;
+
+#[cfg(target_os = "linux")]
+pub const DEFAULT_SYSTEM_PATHS: &[&str] = linux::DEFAULT_SYSTEM_PATHS;
+
+#[cfg(target_os = "linux")]
+pub type IsolatedRunner<R> = linux::IsolatedRunner<R>;
+
+#[cfg(target_os = "linux")]
+pub const DEFAULT_PRLIMIT_PATH: &str = linux::DEFAULT_PRLIMIT_PATH;
+
+#[cfg(target_os = "linux")]
+pub type LimitedRunner<R> = linux::LimitedRunner<R>;
+
 #[cfg(unix)]
 #[cfg(test)]
 mod tests {
+    use super::CancellationToken;
+    use super::ExecutionResult;
+    use super::Interruption;
+    use super::ResponseFileRunner;
     use super::Runner;
+    use super::SandboxRunner;
     use super::SimpleRunner;
     use super::TimedRunDeserializer;
     use super::TimedRunner;
@@ -257,13 +1359,16 @@ mod tests {
     use crate::canonical::Arguments;
     use crate::canonical::EnvironmentVariables;
     use crate::canonical::FileIdentitiesManifest;
+    use crate::canonical::FilesManifest;
     use crate::canonical::Outputs;
     use crate::canonical::Program;
     use crate::canonical::TaskInputs;
+    use crate::canonical::TaskInputsBuilder;
     use crate::fs::Filesystem as FilesystemApi;
     use crate::fs::HostFilesystem;
     use crate::identity::IdentityScheme as IdentitySchemeApi;
     use crate::transport::ContentSha256;
+    use crate::transport::Stdin;
     use crate::transport::TaskRunTime;
     use std::fs::File;
     use std::io::Write;
@@ -271,6 +1376,8 @@ mod tests {
     use std::path::Path;
     use std::path::PathBuf;
     use std::process::Stdio;
+    use std::time::Duration;
+    use std::time::Instant;
 
     struct AssertInputFileRunner {
         input_file_path: PathBuf,
@@ -294,10 +1401,12 @@ mod tests {
             inputs: &crate::canonical::TaskInputs<IdentityScheme>,
             _stdout: Stdout,
             _stderr: Stderr,
-        ) -> anyhow::Result<()> {
+            _deadline: Option<Instant>,
+            _cancellation: &CancellationToken,
+        ) -> anyhow::Result<ExecutionResult> {
             for (input_file_path, _) in inputs.input_files() {
                 if input_file_path == &self.input_file_path {
-                    return Ok(());
+                    return Ok(ExecutionResult::default());
                 }
             }
             anyhow::bail!("missing expected input: {:?}", self.input_file_path);
@@ -359,7 +1468,7 @@ printf "{}" >&2
                 .expect("filesystem for temporary directory");
 
             let mut runner = SimpleRunner;
-            runner
+            let execution_result = runner
                 .run_task::<HostFilesystem, ContentSha256, File, File>(
                     &mut filesystem,
                     &TaskInputs::<ContentSha256>::new(
@@ -371,8 +1480,14 @@ printf "{}" >&2
                     ),
                     stdout_file,
                     stderr_file,
+                    None,
+                    &CancellationToken::default(),
                 )
                 .expect("run program");
+            assert_eq!(Some(0), execution_result.exit_code());
+            assert!(execution_result.success());
+            #[cfg(unix)]
+            assert!(execution_result.peak_memory_bytes.unwrap_or(0) > 0);
         }
 
         let actual_stdout = std::fs::read_to_string(&stdout_path).expect("read stdout");
@@ -422,6 +1537,8 @@ printf "Hello, World!" >&1
                     ),
                     stdout_file,
                     stderr_file,
+                    None,
+                    &CancellationToken::default(),
                 )
                 .expect_err("run program");
         }
@@ -456,7 +1573,7 @@ exit 1
                 .expect("filesystem for temporary directory");
 
             let mut runner = SimpleRunner;
-            runner
+            let execution_result = runner
                 .run_task::<HostFilesystem, ContentSha256, File, File>(
                     &mut filesystem,
                     &TaskInputs::<ContentSha256>::new(
@@ -468,11 +1585,254 @@ exit 1
                     ),
                     stdout_file,
                     stderr_file,
+                    None,
+                    &CancellationToken::default(),
                 )
-                .expect_err("run program");
+                .expect("run program");
+            assert!(!execution_result.success());
+            assert_eq!(Some(1), execution_result.exit_code());
+            assert_eq!(None, execution_result.signal());
+        }
+    }
+
+    #[test]
+    fn test_interrupted_program() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let mut filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("filesystem for temporary directory");
+        // The background `sleep` is in the task's process group, so it is killed too.
+        let inputs = TaskInputsBuilder::<ContentSha256>::new()
+            .program("/bin/sh")
+            .arguments(["-c", "sleep 30 & sleep 30"])
+            .build()
+            .expect("task inputs");
+        let run = |deadline: Option<Instant>, cancellation: &CancellationToken| {
+            let mut filesystem = filesystem.clone();
+            SimpleRunner
+                .run_task::<HostFilesystem, ContentSha256, Stdio, Stdio>(
+                    &mut filesystem,
+                    &inputs,
+                    Stdio::null(),
+                    Stdio::null(),
+                    deadline,
+                    cancellation,
+                )
+                .expect("run program")
+        };
+
+        let start = Instant::now();
+        let execution_result = run(
+            Some(Instant::now() + Duration::from_millis(100)),
+            &CancellationToken::default(),
+        );
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(Some(Interruption::TimedOut), execution_result.interruption);
+        assert!(execution_result.timed_out());
+        assert!(!execution_result.success());
+        assert_eq!(Some(9), execution_result.signal());
+
+        let cancellation = CancellationToken::default();
+        let canceller = {
+            let cancellation = cancellation.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(100));
+                cancellation.cancel();
+            })
+        };
+        let execution_result = run(None, &cancellation);
+        canceller.join().expect("join canceller");
+        assert_eq!(Some(Interruption::Cancelled), execution_result.interruption);
+        assert!(!execution_result.timed_out());
+
+        // Tasks that finish before their deadline are not interrupted.
+        let inputs = TaskInputsBuilder::<ContentSha256>::new()
+            .program("/bin/sh")
+            .arguments(["-c", "true"])
+            .build()
+            .expect("task inputs");
+        let execution_result = SimpleRunner
+            .run_task::<HostFilesystem, ContentSha256, Stdio, Stdio>(
+                &mut filesystem,
+                &inputs,
+                Stdio::null(),
+                Stdio::null(),
+                Some(Instant::now() + Duration::from_secs(30)),
+                &CancellationToken::default(),
+            )
+            .expect("run program");
+        assert!(execution_result.success());
+        assert_eq!(None, execution_result.interruption);
+    }
+
+    #[test]
+    fn test_stdin() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let dir_path = temporary_directory.path();
+        let bin_path = dir_path.join("bin");
+        let stdout_path = dir_path.join("stdout");
+
+        create_and_set_permissions(
+            "program",
+            &bin_path,
+            r#"#!/usr/bin/env bash
+
+cat
+"#
+            .as_bytes(),
+            0o744,
+        );
+        std::fs::write(dir_path.join("input.txt"), "from file\n").expect("write input file");
+
+        let task_inputs = TaskInputs::<ContentSha256>::new(
+            EnvironmentVariables::empty(),
+            Program::new(bin_path),
+            Arguments::empty(),
+            FileIdentitiesManifest::<ContentSha256>::new([(PathBuf::from("input.txt"), None)]),
+            Outputs::empty(),
+        );
+        for (stdin, expected_stdout) in [
+            (Stdin::Path(PathBuf::from("input.txt")), "from file\n"),
+            (Stdin::Content(String::from("inline\n")), "inline\n"),
+        ] {
+            {
+                let stdout_file = File::create(&stdout_path).expect("stdout file");
+                let mut filesystem = HostFilesystem::try_new(dir_path.to_path_buf())
+                    .expect("filesystem for temporary directory");
+
+                let mut runner = SimpleRunner;
+                runner
+                    .run_task::<HostFilesystem, ContentSha256, File, Stdio>(
+                        &mut filesystem,
+                        &task_inputs.clone().with_stdin(stdin).expect("stdin"),
+                        stdout_file,
+                        Stdio::null(),
+                        None,
+                        &CancellationToken::default(),
+                    )
+                    .expect("run program");
+            }
+
+            assert_eq!(
+                expected_stdout,
+                std::fs::read_to_string(&stdout_path).expect("read stdout")
+            );
         }
     }
 
+    #[test]
+    fn test_response_file_runner() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let dir_path = temporary_directory.path();
+        // Copies the response file named by its only argument, which must exist.
+        let script_path = dir_path.join("print.sh");
+        std::fs::write(
+            &script_path,
+            "#!/bin/sh\ntest $# -eq 1 && cp \"${1#@}\" arguments.txt\n",
+        )
+        .expect("write print.sh");
+        std::fs::set_permissions(&script_path, std::fs::Permissions::from_mode(0o755))
+            .expect("make print.sh executable");
+
+        let mut filesystem = HostFilesystem::try_new(dir_path.to_path_buf())
+            .expect("filesystem for temporary directory");
+        let mut run = |arguments: &[&str]| {
+            let inputs = TaskInputsBuilder::<ContentSha256>::new()
+                .program("print.sh")
+                .arguments(arguments.iter().copied())
+                .outputs_description(Outputs::new(
+                    ["arguments.txt"],
+                    Outputs::empty_include_match_transforms(),
+                    [],
+                ))
+                .build()
+                .expect("task inputs");
+            ResponseFileRunner::new(SandboxRunner::new(SimpleRunner))
+                .with_threshold_bytes(16)
+                .run_task::<HostFilesystem, ContentSha256, Stdio, Stdio>(
+                    &mut filesystem,
+                    &inputs,
+                    Stdio::null(),
+                    Stdio::null(),
+                    None,
+                    &CancellationToken::default(),
+                )
+                .expect("run task")
+        };
+
+        // Short argument lists are passed directly.
+        assert!(!run(&["short"]).success());
+        // Long argument lists are passed in a response file, which is an input of the task, and so
+        // is made available in the sandbox.
+        assert!(run(&["it's", "long-argument"]).success());
+        assert_eq!(
+            "'it'\\''s'\nlong-argument\n",
+            std::fs::read_to_string(dir_path.join("arguments.txt")).expect("read arguments.txt")
+        );
+        // The response file is removed afterwards.
+        let mut entries = std::fs::read_dir(dir_path)
+            .expect("read working directory")
+            .map(|entry| entry.expect("directory entry").file_name())
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(vec!["arguments.txt", "print.sh"], entries);
+    }
+
+    #[test]
+    fn test_sandbox_runner() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let dir_path = temporary_directory.path();
+        let sandboxes_directory = tempfile::tempdir().expect("sandboxes directory");
+        std::fs::write(dir_path.join("declared.txt"), "declared\n").expect("write declared.txt");
+        std::fs::write(dir_path.join("undeclared.txt"), "undeclared\n")
+            .expect("write undeclared.txt");
+
+        let mut filesystem = HostFilesystem::try_new(dir_path.to_path_buf())
+            .expect("filesystem for temporary directory");
+        let mut run_script = |script: &str| {
+            let inputs = TaskInputsBuilder::<ContentSha256>::new()
+                .program("/bin/sh")
+                .arguments(["-c", script])
+                .identify_input_files(&mut filesystem, FilesManifest::new(["declared.txt"]))
+                .outputs_description(Outputs::new(
+                    ["out/copy.txt"],
+                    Outputs::empty_include_match_transforms(),
+                    [],
+                ))
+                .build()
+                .expect("task inputs");
+            SandboxRunner::new(SimpleRunner)
+                .with_parent_directory(sandboxes_directory.path())
+                .run_task::<HostFilesystem, ContentSha256, Stdio, Stdio>(
+                    &mut filesystem,
+                    &inputs,
+                    Stdio::null(),
+                    Stdio::null(),
+                    None,
+                    &CancellationToken::default(),
+                )
+        };
+
+        run_script("cat declared.txt > out/copy.txt && touch extra.txt")
+            .expect("run sandboxed task");
+        assert_eq!(
+            "declared\n",
+            std::fs::read_to_string(dir_path.join("out/copy.txt")).expect("read copy.txt")
+        );
+        // Undeclared outputs are left in the sandbox, which is removed.
+        assert!(!dir_path.join("extra.txt").exists());
+        assert_eq!(
+            0,
+            std::fs::read_dir(sandboxes_directory.path())
+                .expect("read sandboxes directory")
+                .count()
+        );
+
+        // Undeclared inputs are not in the sandbox.
+        assert!(!run_script("cat undeclared.txt")
+            .expect("run sandboxed task")
+            .success());
+    }
+
     #[test]
     fn test_time_forwards_input() {
         let temporary_directory = tempfile::tempdir().expect("temporary directory");
@@ -515,6 +1875,8 @@ exit 1
                     ),
                     stdout_file,
                     stderr_file,
+                    None,
+                    &CancellationToken::default(),
                 )
                 .expect("run program");
         }
@@ -560,6 +1922,8 @@ exit 1
                     ),
                     stdout_file,
                     stderr_file,
+                    None,
+                    &CancellationToken::default(),
                 )
                 .map_err(|err| {
                     if let (Ok(stdout_string), Ok(stderr_string)) = (
@@ -596,18 +1960,130 @@ exit 1
         use crate::canonical::Outputs;
         use crate::canonical::Program;
         use crate::canonical::TaskInputs;
+        use crate::canonical::TaskInputsBuilder;
         use crate::fs::HostFilesystem;
+        use crate::runner::linux::prlimit_arguments;
+        use crate::runner::CancellationToken;
+        use crate::runner::IsolatedRunner;
+        use crate::runner::LimitedRunner;
         use crate::runner::Runner;
         use crate::runner::SimpleRunner;
         use crate::runner::TracedRunner;
+        use crate::runner::DEFAULT_BUBBLEWRAP_PATH;
+        use crate::runner::DEFAULT_PRLIMIT_PATH;
         use crate::transport::ContentSha256;
+        use crate::transport::ResourceLimits;
+        use crate::transport::Sandbox;
         use std::collections::HashSet;
         use std::fs::File;
         use std::path::Path;
+        use std::path::PathBuf;
 
         const FSATRACE_BINARY_PATH: &str = "../fsatrace/fsatrace";
         const FSATRACE_LIBRARY_PATH: &str = "../fsatrace/fsatrace.so";
 
+        #[test]
+        fn test_isolated_runner_arguments() {
+            let runner = IsolatedRunner::try_new(DEFAULT_BUBBLEWRAP_PATH, SimpleRunner)
+                .expect("isolated runner")
+                .with_system_paths(["/usr"]);
+            let inputs = TaskInputsBuilder::<ContentSha256>::new()
+                .program("/bin/sh")
+                .arguments(["-c", "true"])
+                .input_file("/etc/hosts", None)
+                .input_file("relative.txt", None)
+                .build()
+                .expect("task inputs");
+
+            let arguments = runner
+                .bubblewrap_arguments(Path::new("/work"), &inputs)
+                .expect("bubblewrap arguments");
+            assert_eq!(
+                vec![
+                    "--die-with-parent",
+                    "--unshare-all",
+                    "--ro-bind-try",
+                    "/usr",
+                    "/usr",
+                    "--proc",
+                    "/proc",
+                    "--dev",
+                    "/dev",
+                    "--tmpfs",
+                    "/tmp",
+                    "--ro-bind",
+                    "/etc/hosts",
+                    "/etc/hosts",
+                    "--ro-bind",
+                    "/bin/sh",
+                    "/bin/sh",
+                    "--bind",
+                    "/work",
+                    "/work",
+                    "--chdir",
+                    "/work",
+                    "--",
+                ],
+                arguments
+            );
+
+            let inputs = inputs.with_sandbox(Sandbox {
+                network: true,
+                read_only_paths: vec![PathBuf::from("/opt/toolchain")],
+            });
+            let arguments = runner
+                .bubblewrap_arguments(Path::new("/work"), &inputs)
+                .expect("bubblewrap arguments");
+            assert_eq!("--share-net", arguments[2]);
+            assert!(arguments
+                .windows(2)
+                .any(|window| window == ["--ro-bind", "/opt/toolchain"]));
+        }
+
+        #[test]
+        fn test_limited_runner() {
+            let temporary_directory = tempfile::tempdir().expect("temporary directory");
+            let mut filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+                .expect("filesystem for temporary directory");
+            let mut runner = LimitedRunner::try_new(DEFAULT_PRLIMIT_PATH, SimpleRunner)
+                .expect("limited runner")
+                .with_default_limits(ResourceLimits {
+                    max_cpu_seconds: Some(60),
+                    max_open_files: Some(64),
+                    ..ResourceLimits::default()
+                });
+            let inputs = TaskInputsBuilder::<ContentSha256>::new()
+                .program("/bin/sh")
+                .arguments(["-c", "ulimit -t; ulimit -n; ulimit -v"])
+                .resource_limits(ResourceLimits {
+                    max_open_files: Some(16),
+                    ..ResourceLimits::default()
+                })
+                .build()
+                .expect("task inputs");
+            assert_eq!(
+                vec!["--cpu=60", "--nofile=16", "--"],
+                prlimit_arguments(&runner.limits(&inputs))
+            );
+
+            let stdout_path = temporary_directory.path().join("stdout");
+            let execution_result = runner
+                .run_task::<HostFilesystem, ContentSha256, File, File>(
+                    &mut filesystem,
+                    &inputs,
+                    File::create(&stdout_path).expect("stdout file"),
+                    File::create(temporary_directory.path().join("stderr")).expect("stderr file"),
+                    None,
+                    &CancellationToken::default(),
+                )
+                .expect("run limited task");
+            assert!(execution_result.success());
+            assert_eq!(
+                "60\n16\nunlimited\n",
+                std::fs::read_to_string(&stdout_path).expect("read stdout")
+            );
+        }
+
         #[test]
         fn test_traced_runner() {
             // let temporary_directory = tempfile::tempdir().expect("temporary directory");
@@ -685,6 +2161,8 @@ printf "{}" > "$3"
                         ),
                         stdout_file,
                         stderr_file,
+                        None,
+                        &CancellationToken::default(),
                     )
                     .expect("run program");
             }
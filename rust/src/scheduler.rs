@@ -0,0 +1,569 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Concurrent execution of sets of tasks, each after the tasks that produce its inputs.
+//!
+//! Dependencies are derived from the tasks themselves: A task depends on every other task that
+//! declares, as an output, a file that the task names as an input (including its program), or that
+//! matches one of its input globs. Likewise, a task depends on every task whose output globs match
+//! one of its input files. Paths are compared after resolving them against each task's working
+//! directory.
+
+use crate::canonical::System as CanonicalSystem;
+use crate::canonical::SystemCapture;
+use crate::transport::Task;
+use anyhow::Context as _;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Condvar;
+use std::sync::Mutex;
+
+/// Estimates the number of CPU cores of the host, as recorded in task metadata (see
+/// `crate::canonical::System::estimated_num_cpu_cores`). At least 1.
+pub fn estimated_num_cpu_cores() -> usize {
+    use sysinfo::SystemExt as _;
+
+    let mut system = sysinfo::System::new();
+    system.refresh_cpu();
+    CanonicalSystem::capture(
+        &system,
+        &SystemCapture {
+            cpu_cores: true,
+            ..SystemCapture::none()
+        },
+    )
+    .estimated_num_cpu_cores()
+    .filter(|cores| *cores > 0)
+    .or_else(|| std::thread::available_parallelism().ok().map(usize::from))
+    .unwrap_or(1)
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SchedulerOptions {
+    /// Maximum number of tasks executed at a time. Default: `estimated_num_cpu_cores()`.
+    pub max_parallelism: usize,
+    /// Continue executing tasks that do not depend on a failed task. Default: No new tasks are
+    /// started once one fails.
+    pub keep_going: bool,
+}
+
+impl Default for SchedulerOptions {
+    fn default() -> Self {
+        Self {
+            max_parallelism: estimated_num_cpu_cores(),
+            keep_going: false,
+        }
+    }
+}
+
+/// What became of a scheduled task.
+#[derive(Debug)]
+pub enum TaskOutcome<T> {
+    Succeeded(T),
+    Failed(anyhow::Error),
+    /// The task was not started, because a task on which it depends failed, or because another
+    /// task failed and `SchedulerOptions::keep_going` is not set.
+    Skipped,
+}
+
+impl<T> TaskOutcome<T> {
+    pub fn is_success(&self) -> bool {
+        matches!(self, Self::Succeeded(_))
+    }
+}
+
+/// Tasks keyed by label, with the dependencies between them derived from their inputs and outputs.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    tasks: BTreeMap<String, Task>,
+    dependencies: BTreeMap<String, BTreeSet<String>>,
+    dependents: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Schedule {
+    /// Derives the dependencies between `tasks`. Fails if two tasks declare the same output file,
+    /// or if dependencies form a cycle.
+    pub fn new<I: IntoIterator<Item = (String, Task)>>(tasks: I) -> anyhow::Result<Self> {
+        let tasks: BTreeMap<String, Task> = tasks.into_iter().collect();
+
+        let mut producers: BTreeMap<PathBuf, &String> = BTreeMap::new();
+        let mut output_globs = vec![];
+        for (label, task) in tasks.iter() {
+            let outputs = &task.outputs;
+            for path in outputs
+                .include_files
+                .iter()
+                .chain(outputs.optional_files.iter())
+                .chain(outputs.stdout_file.iter())
+                .chain(outputs.stderr_file.iter())
+            {
+                let path = resolve(task, path);
+                if let Some(producer) = producers.insert(path.clone(), label) {
+                    if producer != label {
+                        anyhow::bail!(
+                            "tasks {:?} and {:?} both declare output {:?}",
+                            producer,
+                            label,
+                            path
+                        );
+                    }
+                }
+            }
+            for glob in outputs.include_globs.iter() {
+                output_globs.push((label, compile_glob(task, glob)?));
+            }
+        }
+
+        let mut dependencies: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        let mut dependents: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for (label, task) in tasks.iter() {
+            let mut task_dependencies = BTreeSet::new();
+            let inputs = &task.inputs;
+            for path in inputs
+                .include_files
+                .iter()
+                .chain(inputs.depfiles.iter())
+                .chain(std::iter::once(&task.program.program))
+            {
+                let path = resolve(task, path);
+                task_dependencies.extend(producers.get(&path).copied());
+                task_dependencies.extend(
+                    output_globs
+                        .iter()
+                        .filter(|(_, glob)| glob.matches_path(&path))
+                        .map(|(producer, _)| *producer),
+                );
+            }
+            for glob in inputs.include_globs.iter() {
+                let glob = compile_glob(task, glob)?;
+                task_dependencies.extend(
+                    producers
+                        .iter()
+                        .filter(|(path, _)| glob.matches_path(path))
+                        .map(|(_, producer)| *producer),
+                );
+            }
+            task_dependencies.remove(label);
+            for dependency in task_dependencies.iter() {
+                dependents
+                    .entry((*dependency).clone())
+                    .or_default()
+                    .insert(label.clone());
+            }
+            dependencies.insert(
+                label.clone(),
+                task_dependencies.into_iter().cloned().collect(),
+            );
+        }
+
+        let schedule = Self {
+            tasks,
+            dependencies,
+            dependents,
+        };
+        schedule.check_acyclic()?;
+        Ok(schedule)
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = &String> {
+        self.tasks.keys()
+    }
+
+    pub fn task(&self, label: &str) -> Option<&Task> {
+        self.tasks.get(label)
+    }
+
+    /// Gets the labels of tasks that must complete before the task labelled `label` runs.
+    pub fn dependencies(&self, label: &str) -> impl Iterator<Item = &String> {
+        self.dependencies
+            .get(label)
+            .into_iter()
+            .flat_map(|dependencies| dependencies.iter())
+    }
+
+    fn dependents(&self, label: &str) -> impl Iterator<Item = &String> {
+        self.dependents
+            .get(label)
+            .into_iter()
+            .flat_map(|dependents| dependents.iter())
+    }
+
+    fn check_acyclic(&self) -> anyhow::Result<()> {
+        let mut remaining_dependencies: BTreeMap<&String, usize> = self
+            .dependencies
+            .iter()
+            .map(|(label, dependencies)| (label, dependencies.len()))
+            .collect();
+        let mut ready: Vec<&String> = remaining_dependencies
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(label, _)| *label)
+            .collect();
+        let mut visited = 0;
+        while let Some(label) = ready.pop() {
+            visited += 1;
+            for dependent in self.dependents(label) {
+                let count = remaining_dependencies
+                    .get_mut(dependent)
+                    .expect("dependents are scheduled");
+                *count -= 1;
+                if *count == 0 {
+                    ready.push(dependent);
+                }
+            }
+        }
+        if visited != self.tasks.len() {
+            let cycle: Vec<&String> = remaining_dependencies
+                .into_iter()
+                .filter(|(_, count)| *count > 0)
+                .map(|(label, _)| label)
+                .collect();
+            anyhow::bail!("task dependencies form a cycle among {:?}", cycle);
+        }
+        Ok(())
+    }
+
+    /// Executes every task, each after its dependencies succeed, on up to
+    /// `options.max_parallelism` threads. Each thread creates its own worker with `make_worker`
+    /// (e.g., a `crate::facade::ArtifactExecutor`), with which it executes tasks by calling
+    /// `execute` with the task's label.
+    pub fn run<W, T, M, E>(
+        &self,
+        options: &SchedulerOptions,
+        make_worker: M,
+        execute: E,
+    ) -> anyhow::Result<BTreeMap<String, TaskOutcome<T>>>
+    where
+        T: Send,
+        M: Fn() -> anyhow::Result<W> + Sync,
+        E: Fn(&mut W, &str, &Task) -> anyhow::Result<T> + Sync,
+    {
+        let state = Mutex::new(RunState {
+            remaining_dependencies: self
+                .dependencies
+                .iter()
+                .map(|(label, dependencies)| (label.clone(), dependencies.len()))
+                .collect(),
+            ready: self
+                .dependencies
+                .iter()
+                .filter(|(_, dependencies)| dependencies.is_empty())
+                .map(|(label, _)| label.clone())
+                .collect(),
+            outcomes: BTreeMap::new(),
+            failed: false,
+        });
+        let changed = Condvar::new();
+        let num_workers = options.max_parallelism.max(1).min(self.tasks.len().max(1));
+
+        let worker_results: Vec<anyhow::Result<()>> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..num_workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut worker = make_worker().context("creating scheduler worker")?;
+                        while let Some(label) = self.next_task(&state, &changed, options) {
+                            let task = &self.tasks[&label];
+                            let outcome = match execute(&mut worker, &label, task) {
+                                Ok(result) => TaskOutcome::Succeeded(result),
+                                Err(error) => TaskOutcome::Failed(
+                                    error.context(format!("executing task {:?}", label)),
+                                ),
+                            };
+                            self.complete_task(&state, &changed, label, outcome);
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("scheduler worker panicked"))
+                .collect()
+        });
+
+        let state = state.into_inner().expect("scheduler lock");
+        for result in worker_results {
+            if let Err(error) = result {
+                if state.outcomes.len() < self.tasks.len() {
+                    return Err(error);
+                }
+            }
+        }
+        Ok(state.outcomes)
+    }
+
+    /// Waits for a task to become ready, and claims it. Returns `None` when no task remains to be
+    /// started.
+    fn next_task<T>(
+        &self,
+        state: &Mutex<RunState<T>>,
+        changed: &Condvar,
+        options: &SchedulerOptions,
+    ) -> Option<String> {
+        let mut state = state.lock().expect("scheduler lock");
+        loop {
+            if state.failed && !options.keep_going && !state.remaining_dependencies.is_empty() {
+                let unstarted = std::mem::take(&mut state.remaining_dependencies);
+                state.ready.clear();
+                for label in unstarted.into_keys() {
+                    state.outcomes.insert(label, TaskOutcome::Skipped);
+                }
+                changed.notify_all();
+            }
+            if let Some(label) = state.ready.pop_first() {
+                state.remaining_dependencies.remove(&label);
+                return Some(label);
+            }
+            if state.remaining_dependencies.is_empty() {
+                return None;
+            }
+            state = changed.wait(state).expect("scheduler lock");
+        }
+    }
+
+    fn complete_task<T>(
+        &self,
+        state: &Mutex<RunState<T>>,
+        changed: &Condvar,
+        label: String,
+        outcome: TaskOutcome<T>,
+    ) {
+        let mut state = state.lock().expect("scheduler lock");
+        if outcome.is_success() {
+            for dependent in self.dependents(&label) {
+                if let Some(count) = state.remaining_dependencies.get_mut(dependent) {
+                    *count -= 1;
+                    if *count == 0 {
+                        state.ready.insert(dependent.clone());
+                    }
+                }
+            }
+        } else {
+            state.failed = true;
+            let mut skipped: Vec<&String> = self.dependents(&label).collect();
+            while let Some(dependent) = skipped.pop() {
+                if state.remaining_dependencies.remove(dependent).is_some() {
+                    state.ready.remove(dependent);
+                    state
+                        .outcomes
+                        .insert(dependent.clone(), TaskOutcome::Skipped);
+                    skipped.extend(self.dependents(dependent));
+                }
+            }
+        }
+        state.outcomes.insert(label, outcome);
+        changed.notify_all();
+    }
+}
+
+/// Progress of `Schedule::run`. Tasks leave `remaining_dependencies` when they are started or
+/// skipped.
+struct RunState<T> {
+    remaining_dependencies: BTreeMap<String, usize>,
+    ready: BTreeSet<String>,
+    outcomes: BTreeMap<String, TaskOutcome<T>>,
+    failed: bool,
+}
+
+/// Resolves `path` against the working directory of `task`, without consulting the filesystem.
+fn resolve(task: &Task, path: &Path) -> PathBuf {
+    let path = match task.working_directory.as_ref() {
+        Some(working_directory) => working_directory.join(path),
+        None => path.to_path_buf(),
+    };
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .collect()
+}
+
+fn compile_glob(task: &Task, glob: &str) -> anyhow::Result<glob::Pattern> {
+    let glob = resolve(task, Path::new(glob));
+    glob::Pattern::new(&glob.to_string_lossy()).with_context(|| format!("parsing glob {:?}", glob))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Schedule;
+    use super::SchedulerOptions;
+    use super::TaskOutcome;
+    use crate::facade::ArtifactExecutor;
+    use crate::transport::Task;
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    fn task(json: &str) -> Task {
+        serde_json::from_str(json).expect("parse task")
+    }
+
+    #[test]
+    fn test_schedule_dependencies() {
+        let schedule = Schedule::new([
+            (
+                String::from("compile"),
+                task(
+                    r#"{
+                        "environment_variables": [],
+                        "program": "/bin/cc",
+                        "arguments": [],
+                        "inputs": {"include_files": ["src/main.c"]},
+                        "outputs": {"include_files": ["out/main.o"]}
+                    }"#,
+                ),
+            ),
+            (
+                String::from("link"),
+                task(
+                    r#"{
+                        "environment_variables": [],
+                        "program": "/bin/ld",
+                        "arguments": [],
+                        "inputs": {"include_globs": ["out/*.o"]},
+                        "outputs": {"include_files": ["out/main"]}
+                    }"#,
+                ),
+            ),
+            (
+                String::from("test"),
+                task(
+                    r#"{
+                        "environment_variables": [],
+                        "program": "./main",
+                        "arguments": [],
+                        "working_directory": "out",
+                        "inputs": {},
+                        "outputs": {}
+                    }"#,
+                ),
+            ),
+        ])
+        .expect("schedule");
+        let dependencies = |label: &str| schedule.dependencies(label).cloned().collect::<Vec<_>>();
+        assert!(dependencies("compile").is_empty());
+        assert_eq!(vec![String::from("compile")], dependencies("link"));
+        assert_eq!(vec![String::from("link")], dependencies("test"));
+
+        let cycle = Schedule::new([
+            (
+                String::from("a"),
+                task(
+                    r#"{
+                        "environment_variables": [],
+                        "program": "/bin/sh",
+                        "arguments": [],
+                        "inputs": {"include_files": ["b.txt"]},
+                        "outputs": {"include_files": ["a.txt"]}
+                    }"#,
+                ),
+            ),
+            (
+                String::from("b"),
+                task(
+                    r#"{
+                        "environment_variables": [],
+                        "program": "/bin/sh",
+                        "arguments": [],
+                        "inputs": {"include_files": ["a.txt"]},
+                        "outputs": {"include_files": ["b.txt"]}
+                    }"#,
+                ),
+            ),
+        ]);
+        assert!(cycle.is_err());
+    }
+
+    #[test]
+    fn test_schedule_run() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let working_directory = temporary_directory.path().to_path_buf();
+        let shell_task = |script: &str, inputs: &[&str], outputs: &[&str]| {
+            let mut task = task(
+                r#"{
+                    "environment_variables": [],
+                    "program": "/bin/sh",
+                    "arguments": [],
+                    "inputs": {},
+                    "outputs": {}
+                }"#,
+            );
+            task.arguments.arguments = vec![String::from("-c"), String::from(script)];
+            task.inputs.include_files = inputs.iter().map(Into::into).collect();
+            task.outputs.include_files = outputs.iter().map(Into::into).collect();
+            task
+        };
+        let schedule = Schedule::new([
+            (
+                String::from("a"),
+                shell_task("echo a > a.txt", &["/bin/sh"], &["a.txt"]),
+            ),
+            (
+                String::from("b"),
+                shell_task("echo b > b.txt", &["/bin/sh"], &["b.txt"]),
+            ),
+            (
+                String::from("ab"),
+                shell_task("cat a.txt b.txt > ab.txt", &["a.txt", "b.txt"], &["ab.txt"]),
+            ),
+            (
+                String::from("fail"),
+                shell_task("cat ab.txt; exit 1", &["ab.txt"], &["fail.txt"]),
+            ),
+            (
+                String::from("after_fail"),
+                shell_task("true", &["fail.txt"], &[]),
+            ),
+        ])
+        .expect("schedule");
+
+        let started = Mutex::new(vec![]);
+        let outcomes = schedule
+            .run(
+                &SchedulerOptions {
+                    max_parallelism: 2,
+                    keep_going: true,
+                },
+                || {
+                    Ok(ArtifactExecutor::builder()
+                        .working_directory(&working_directory)
+                        .build()?)
+                },
+                |executor, label, task| {
+                    started.lock().expect("lock").push(String::from(label));
+                    Ok(executor.run_task(task)?)
+                },
+            )
+            .expect("run schedule");
+
+        let summary: BTreeMap<&str, &str> = outcomes
+            .iter()
+            .map(|(label, outcome)| {
+                let outcome = match outcome {
+                    TaskOutcome::Succeeded(_) => "succeeded",
+                    TaskOutcome::Failed(_) => "failed",
+                    TaskOutcome::Skipped => "skipped",
+                };
+                (label.as_str(), outcome)
+            })
+            .collect();
+        assert_eq!(
+            BTreeMap::from([
+                ("a", "succeeded"),
+                ("ab", "succeeded"),
+                ("after_fail", "skipped"),
+                ("b", "succeeded"),
+                ("fail", "failed"),
+            ]),
+            summary
+        );
+        assert_eq!(
+            "a\nb\n",
+            std::fs::read_to_string(working_directory.join("ab.txt")).expect("read ab.txt")
+        );
+        let started = started.into_inner().expect("lock");
+        assert_eq!(4, started.len());
+        assert_eq!(Some(&String::from("fail")), started.last());
+    }
+}
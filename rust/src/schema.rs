@@ -0,0 +1,191 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+use crate::blob::ReadDeserializer;
+use crate::identity::Identity as IdentityBound;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::transport::BuildGraph;
+use crate::transport::ChunkManifest;
+use crate::transport::FileIdentitiesManifestDelta;
+use crate::transport::FileIdentitiesManifestHeader;
+use crate::transport::Listing;
+use crate::transport::ListingHeader;
+use crate::transport::Metadata;
+use crate::transport::TaskGraph;
+use crate::transport::TaskInputs;
+use crate::transport::TaskOutputs;
+use crate::transport::TaskResult;
+use anyhow::Context as _;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Map;
+use serde_json::Value;
+use std::io::Read;
+
+/// Version of the schema of persisted transports written by this build. Increment this, and add a
+/// step to `Versioned::migrate` for each affected transport, whenever a persisted transport changes
+/// in a way that older data cannot be deserialized as-is.
+///
+/// Version history:
+///
+/// - 0: Transports written before `format_version` was recorded.
+/// - 1: Adds `format_version`.
+pub const FORMAT_VERSION: u32 = 1;
+
+const FORMAT_VERSION_FIELD: &str = "format_version";
+
+/// Schema version recorded in persisted transports. Transports created by this build are always
+/// stamped with `FORMAT_VERSION`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(transparent)]
+pub struct FormatVersion(u32);
+
+impl FormatVersion {
+    pub fn version(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Default for FormatVersion {
+    fn default() -> Self {
+        Self(FORMAT_VERSION)
+    }
+}
+
+/// A transport that is persisted in caches, and may therefore be read by builds newer than the one
+/// that wrote it.
+pub trait Versioned: DeserializeOwned {
+    /// Upgrades `object`, a transport written with schema `from_version`, to schema
+    /// `from_version + 1`. `format_version` is maintained by the caller.
+    fn migrate(from_version: u32, object: &mut Map<String, Value>) -> anyhow::Result<()> {
+        let _ = (from_version, object);
+        Ok(())
+    }
+}
+
+impl<IS: IdentitySchemeApi> Versioned for TaskInputs<IS> {}
+
+impl<IS: IdentitySchemeApi> Versioned for TaskOutputs<IS> {}
+
+impl Versioned for Metadata {}
+
+impl Versioned for TaskResult {}
+
+impl<Identity: IdentityBound> Versioned for Listing<Identity> {}
+
+impl<IS: IdentitySchemeApi> Versioned for ChunkManifest<IS> {}
+
+impl<IS: IdentitySchemeApi> Versioned for BuildGraph<IS> {}
+
+impl Versioned for TaskGraph {}
+
+impl Versioned for ListingHeader {}
+
+impl<IS: IdentitySchemeApi> Versioned for FileIdentitiesManifestDelta<IS> {}
+
+impl Versioned for FileIdentitiesManifestHeader {}
+
+/// Upgrades `value`, a transport of type `D` written with any schema version up to
+/// `FORMAT_VERSION`, to the current schema, then deserializes it.
+pub fn migrate<D: Versioned>(mut value: Value) -> anyhow::Result<D> {
+    let object = value
+        .as_object_mut()
+        .ok_or_else(|| anyhow::anyhow!("versioned transport is not an object"))?;
+    let from_version = match object.get(FORMAT_VERSION_FIELD) {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| anyhow::anyhow!("malformed format version: {}", version))?,
+    };
+    if from_version > FORMAT_VERSION {
+        anyhow::bail!(
+            "transport has format version {}, but this build supports versions up to {}",
+            from_version,
+            FORMAT_VERSION
+        );
+    }
+    for version in from_version..FORMAT_VERSION {
+        D::migrate(version, object).with_context(|| {
+            format!(
+                "migrating transport from format version {} to {}",
+                version,
+                version + 1
+            )
+        })?;
+    }
+    object.insert(
+        FORMAT_VERSION_FIELD.to_string(),
+        Value::from(FORMAT_VERSION),
+    );
+    serde_json::from_value(value).map_err(anyhow::Error::from)
+}
+
+/// Reads a transport of type `D` from `reader`, upgrading it to the current schema.
+pub fn read_versioned<D: Versioned, RD: ReadDeserializer, R: Read>(reader: R) -> anyhow::Result<D> {
+    let value: Value = RD::from_reader(reader).map_err(anyhow::Error::from)?;
+    migrate(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::migrate;
+    use super::FormatVersion;
+    use super::Versioned;
+    use super::FORMAT_VERSION;
+    use crate::transport::ContentSha256;
+    use crate::transport::Listing;
+    use serde::Deserialize;
+    use serde_json::json;
+    use serde_json::Map;
+    use serde_json::Value;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Renamed {
+        format_version: FormatVersion,
+        new_name: String,
+    }
+
+    impl Versioned for Renamed {
+        fn migrate(from_version: u32, object: &mut Map<String, Value>) -> anyhow::Result<()> {
+            if from_version == 0 {
+                let value = object
+                    .remove("old_name")
+                    .ok_or_else(|| anyhow::anyhow!("missing old_name"))?;
+                object.insert(String::from("new_name"), value);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_migrate() {
+        let listing: Listing<<ContentSha256 as crate::identity::IdentityScheme>::Identity> =
+            migrate(json!({ "entries": [] })).expect("migrate unversioned listing");
+        assert_eq!(FormatVersion::default(), listing.format_version);
+        assert_eq!(FORMAT_VERSION, listing.format_version.version());
+
+        assert_eq!(
+            Renamed {
+                format_version: FormatVersion::default(),
+                new_name: String::from("value"),
+            },
+            migrate::<Renamed>(json!({ "old_name": "value" })).expect("migrate renamed field")
+        );
+        assert_eq!(
+            Renamed {
+                format_version: FormatVersion::default(),
+                new_name: String::from("value"),
+            },
+            migrate::<Renamed>(json!({ "format_version": FORMAT_VERSION, "new_name": "value" }))
+                .expect("read current version")
+        );
+        assert!(migrate::<Renamed>(json!({ "new_name": "value" })).is_err());
+        assert!(migrate::<Renamed>(
+            json!({ "format_version": FORMAT_VERSION + 1, "new_name": "value" })
+        )
+        .is_err());
+    }
+}
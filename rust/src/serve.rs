@@ -0,0 +1,866 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! A minimal HTTP/1.1 server that shares a cache directory with other machines.
+//!
+//! Endpoints:
+//!
+//! - `GET`/`HEAD /blobs/{identity}`: The contents of a blob.
+//! - `PUT /blobs/{identity}`: Stores a blob. The body must have the given identity.
+//! - `GET`/`HEAD /outputs/{identity}` and `/metadata/{identity}`: The identity of the outputs or
+//!   metadata blob recorded for the task inputs identified by `identity`, as text.
+//! - `PUT /outputs/{identity}` and `/metadata/{identity}`: Records the pointer to a blob, whose
+//!   identity is the body, as text. The blob must already be stored, and an existing pointer is
+//!   kept.
+//! - `GET /metrics`: Metrics in the Prometheus text format, when a registry is installed (see
+//!   `crate::metrics`).
+//!
+//! When a token is configured, every request must carry an `Authorization: Bearer {token}` header.
+//! In read-only mode, `PUT` requests are refused. Each connection is served on its own thread, one
+//! request at a time, and closed after its response. Blobs are streamed from the cache directory.
+
+use crate::blob::BlobCache;
+use crate::blob::BlobPointerCache;
+use crate::blob::ReadDeserializer as ReadDeserializerApi;
+use crate::blob::StringSerializer as StringSerializerApi;
+use crate::blob::WriteSerializer as WriteSerializerApi;
+use crate::cache::Cache;
+use crate::cache::WriteOnDropIndex;
+use crate::fs::Filesystem as FilesystemApi;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::limits::Limits;
+use crate::metrics;
+use crate::metrics::Metrics;
+use anyhow::Context as _;
+use serde::de::value::StrDeserializer;
+use serde::Deserialize as _;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Default address on which the `serve` subcommand listens.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+
+/// Default for `ServeOptions::timeout`.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default for `ServeOptions::max_connections`.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 256;
+
+/// Maximum size, in bytes, of a request line or header line.
+const MAX_LINE_LENGTH: u64 = 8192;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServeOptions {
+    /// Bearer token that requests must present. Default: Requests are not authenticated.
+    pub token: Option<String>,
+    /// Refuse requests that would modify the cache.
+    pub read_only: bool,
+    /// Limits applied to uploaded blobs.
+    pub limits: Limits,
+    /// Longest that a connection may wait to read or write, after which it is closed.
+    pub timeout: Duration,
+    /// Maximum number of connections served at once. Further connections are refused with
+    /// `503 Service Unavailable`.
+    pub max_connections: usize,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        Self {
+            token: None,
+            read_only: false,
+            limits: Limits::default(),
+            timeout: DEFAULT_TIMEOUT,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+        }
+    }
+}
+
+type DefaultCache<FS, IS, S> = Cache<FS, IS, S, WriteOnDropIndex<FS, IS, S>>;
+
+/// Serves the blobs and task pointers of a cache directory over HTTP.
+pub struct CacheServer<
+    Filesystem: FilesystemApi,
+    IdentityScheme: IdentitySchemeApi,
+    Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+> {
+    filesystem: Filesystem,
+    blob_cache: BlobCache<Filesystem, IdentityScheme, Serialization>,
+    metadata_pointer_cache: BlobPointerCache<Filesystem, IdentityScheme, Serialization>,
+    outputs_pointer_cache: BlobPointerCache<Filesystem, IdentityScheme, Serialization>,
+    options: ServeOptions,
+}
+
+impl<
+        Filesystem: FilesystemApi,
+        IdentityScheme: IdentitySchemeApi,
+        Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+    > CacheServer<Filesystem, IdentityScheme, Serialization>
+{
+    /// Serves the cache whose root directory is `filesystem`, creating its subdirectories if
+    /// necessary.
+    pub fn new(mut filesystem: Filesystem, options: ServeOptions) -> anyhow::Result<Self> {
+        for sub_directory in Self::sub_directories() {
+            filesystem
+                .create_directories(sub_directory)
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("creating cache subdirectory {:?}", sub_directory))?;
+        }
+        Self::open(filesystem, options)
+    }
+
+    fn sub_directories() -> [&'static str; 3] {
+        [
+            DefaultCache::<Filesystem, IdentityScheme, Serialization>::DEFAULT_BLOBS_SUBDIR,
+            DefaultCache::<Filesystem, IdentityScheme, Serialization>::DEFAULT_METADATA_POINTERS_SUBDIR,
+            DefaultCache::<Filesystem, IdentityScheme, Serialization>::DEFAULT_OUTPUTS_POINTERS_SUBDIR,
+        ]
+    }
+
+    fn open(mut filesystem: Filesystem, options: ServeOptions) -> anyhow::Result<Self> {
+        let [blobs, metadata_pointers, outputs_pointers] =
+            Self::sub_directories().map(|sub_directory| {
+                filesystem
+                    .sub_system(sub_directory)
+                    .with_context(|| format!("opening cache subdirectory {:?}", sub_directory))
+            });
+        Ok(Self {
+            blob_cache: BlobCache::new(blobs?).with_limits(options.limits),
+            metadata_pointer_cache: BlobPointerCache::new(metadata_pointers?),
+            outputs_pointer_cache: BlobPointerCache::new(outputs_pointers?),
+            filesystem,
+            options,
+        })
+    }
+
+    /// Reads one request from `reader` and writes its response to `writer`.
+    pub fn handle<R: BufRead, W: Write>(
+        &mut self,
+        mut reader: R,
+        mut writer: W,
+    ) -> anyhow::Result<()> {
+        let response = match read_request(&mut reader, &self.options.limits) {
+            Ok(request) => {
+                let response = self.respond(&request);
+                tracing::info!(
+                    "{method} {path}: {status}",
+                    method = request.method,
+                    path = request.path,
+                    status = response.status
+                );
+                response
+            }
+            Err(response) => response,
+        };
+        response.write(&mut writer)
+    }
+
+    fn respond(&mut self, request: &Request) -> Response<'_> {
+        if let Some(token) = self.options.token.as_ref() {
+            let expected = format!("Bearer {}", token);
+            let authorization = request.header("authorization").unwrap_or_default();
+            if !constant_time_eq(authorization.as_bytes(), expected.as_bytes()) {
+                return Response::text(401, "missing or incorrect bearer token");
+            }
+        }
+
+        if request.path == "/metrics" {
+            return metrics_response(request, metrics::global());
+        }
+
+        let (collection, identity) = match request.path.trim_start_matches('/').split_once('/') {
+            Some((collection, identity)) => (collection, identity),
+            None => return Response::text(404, "not found"),
+        };
+        let identity = match parse_identity::<IdentityScheme>(identity) {
+            Some(identity) => identity,
+            None => return Response::text(400, "malformed identity"),
+        };
+
+        let head_only = request.method == "HEAD";
+        let response = match (request.method.as_str(), collection) {
+            ("GET" | "HEAD", "blobs") => self.get_blob(&identity),
+            ("PUT", "blobs") => self.put_blob(&identity, &request.body),
+            ("GET" | "HEAD", "outputs") => {
+                lookup_pointer(&mut self.outputs_pointer_cache, &identity)
+            }
+            ("GET" | "HEAD", "metadata") => {
+                lookup_pointer(&mut self.metadata_pointer_cache, &identity)
+            }
+            ("PUT", "outputs" | "metadata") => {
+                self.put_pointer(collection, &identity, &request.body)
+            }
+            (_, "blobs" | "outputs" | "metadata") => Response::text(405, "method not allowed"),
+            _ => Response::text(404, "not found"),
+        };
+        Response {
+            head_only,
+            ..response
+        }
+    }
+
+    fn get_blob(&mut self, identity: &IdentityScheme::Identity) -> Response<'_> {
+        let blob = match self.blob_cache.open_blob(identity) {
+            Ok(blob) => blob,
+            Err(_) => return Response::text(404, "blob not found"),
+        };
+        // The uncompressed size of a compressed blob is not known until it is read.
+        let length = match blob.is_compressed() {
+            true => None,
+            false => match self.blob_cache.blob_size(identity) {
+                Ok(length) => Some(length),
+                Err(error) => return Response::text(500, &format!("reading blob: {:#}", error)),
+            },
+        };
+        Response::stream(200, "application/octet-stream", Box::new(blob), length)
+    }
+
+    fn put_blob(&mut self, identity: &IdentityScheme::Identity, body: &[u8]) -> Response<'_> {
+        if self.options.read_only {
+            return Response::text(403, "server is read-only");
+        }
+        match IdentityScheme::identify_content(body) {
+            Ok(computed_identity) if &computed_identity == identity => {}
+            Ok(computed_identity) => {
+                return Response::text(
+                    400,
+                    &format!(
+                        "content identified as {}, not {}",
+                        computed_identity.to_string(),
+                        identity.to_string()
+                    ),
+                )
+            }
+            Err(error) => return Response::text(500, &format!("identifying content: {}", error)),
+        }
+        if self.blob_cache.contains_blob(identity) {
+            return Response::text(200, "blob exists");
+        }
+        match self.blob_cache.copy_blob(body, identity) {
+            Ok(()) => Response::text(201, "blob stored"),
+            Err(error) => Response::text(500, &format!("storing blob: {:#}", error)),
+        }
+    }
+
+    fn put_pointer(
+        &mut self,
+        collection: &str,
+        source_identity: &IdentityScheme::Identity,
+        body: &[u8],
+    ) -> Response<'_> {
+        if self.options.read_only {
+            return Response::text(403, "server is read-only");
+        }
+        let destination_identity = match std::str::from_utf8(body)
+            .ok()
+            .and_then(|body| parse_identity::<IdentityScheme>(body.trim()))
+        {
+            Some(destination_identity) => destination_identity,
+            None => return Response::text(400, "malformed destination identity"),
+        };
+        if !self.blob_cache.contains_blob(&destination_identity) {
+            return Response::text(400, "destination blob not found");
+        }
+        let pointer_cache = match collection {
+            "outputs" => &mut self.outputs_pointer_cache,
+            _ => &mut self.metadata_pointer_cache,
+        };
+        if pointer_cache.read_blob_pointer(source_identity).is_ok() {
+            return Response::text(200, "pointer exists");
+        }
+        match pointer_cache.write_raw_blob_pointer(source_identity, &destination_identity) {
+            Ok(()) => Response::text(201, "pointer stored"),
+            Err(error) => Response::text(500, &format!("storing pointer: {:#}", error)),
+        }
+    }
+}
+
+impl<
+        Filesystem: FilesystemApi + Send + 'static,
+        IdentityScheme: IdentitySchemeApi + Send + 'static,
+        Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi + Send + 'static,
+    > CacheServer<Filesystem, IdentityScheme, Serialization>
+{
+    /// Serves connections accepted by `listener`, each on its own thread, until accepting fails.
+    /// Failures to serve an individual connection are logged and do not stop the server.
+    pub fn serve(&self, listener: TcpListener) -> anyhow::Result<()> {
+        let connections = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            let stream = stream.context("accepting connection")?;
+            let peer = stream.peer_addr().ok();
+            if connections.load(Ordering::SeqCst) >= self.options.max_connections {
+                if let Err(error) = refuse_connection(stream, self.options.timeout) {
+                    tracing::warn!(
+                        "failed to refuse {peer:?}: {error:#}",
+                        peer = peer,
+                        error = error
+                    );
+                }
+                continue;
+            }
+            let mut server = Self::open(self.filesystem.clone(), self.options.clone())?;
+            let connections = connections.clone();
+            connections.fetch_add(1, Ordering::SeqCst);
+            std::thread::spawn(move || {
+                if let Err(error) = server.serve_connection(stream) {
+                    tracing::warn!(
+                        "failed to serve {peer:?}: {error:#}",
+                        peer = peer,
+                        error = error
+                    );
+                }
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        Ok(())
+    }
+
+    fn serve_connection(&mut self, stream: TcpStream) -> anyhow::Result<()> {
+        set_timeouts(&stream, self.options.timeout)?;
+        let reader = BufReader::new(
+            stream
+                .try_clone()
+                .context("cloning connection for reading")?,
+        );
+        self.handle(reader, stream)
+    }
+}
+
+pub(crate) fn set_timeouts(stream: &TcpStream, timeout: Duration) -> anyhow::Result<()> {
+    stream
+        .set_read_timeout(Some(timeout))
+        .context("setting read timeout")?;
+    stream
+        .set_write_timeout(Some(timeout))
+        .context("setting write timeout")
+}
+
+pub(crate) fn refuse_connection(mut stream: TcpStream, timeout: Duration) -> anyhow::Result<()> {
+    set_timeouts(&stream, timeout)?;
+    Response::text(503, "too many connections").write(&mut stream)
+}
+
+/// Compares `a` and `b` in time that depends only on their lengths, so that secrets cannot be
+/// guessed one byte at a time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b.iter())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Parses an identity as it appears in paths and pointer bodies.
+pub(crate) fn parse_identity<IdentityScheme: IdentitySchemeApi>(
+    identity: &str,
+) -> Option<IdentityScheme::Identity> {
+    IdentityScheme::Identity::deserialize(StrDeserializer::<serde::de::value::Error>::new(identity))
+        .ok()
+}
+
+fn lookup_pointer<
+    Filesystem: FilesystemApi,
+    IdentityScheme: IdentitySchemeApi,
+    Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+>(
+    pointer_cache: &mut BlobPointerCache<Filesystem, IdentityScheme, Serialization>,
+    identity: &IdentityScheme::Identity,
+) -> Response<'static> {
+    match pointer_cache.read_blob_pointer(identity) {
+        Ok(destination_identity) => Response::text(200, &destination_identity.to_string()),
+        Err(_) => Response::text(404, "pointer not found"),
+    }
+}
+
+/// Reads one request from `reader` and writes its response to `writer`, serving only `GET /metrics`;
+/// see `crate::metrics::spawn_exporter`.
+pub(crate) fn handle_metrics_request<R: BufRead, W: Write>(
+    metrics: Option<&Metrics>,
+    mut reader: R,
+    mut writer: W,
+) -> anyhow::Result<()> {
+    let response = match read_request(&mut reader, &Limits::default()) {
+        Ok(request) if request.path == "/metrics" => metrics_response(&request, metrics),
+        Ok(_) => Response::text(404, "not found"),
+        Err(response) => response,
+    };
+    response.write(&mut writer)
+}
+
+fn metrics_response(request: &Request, metrics: Option<&Metrics>) -> Response<'static> {
+    match (request.method.as_str(), metrics) {
+        ("GET", Some(metrics)) => Response::new(
+            200,
+            "text/plain; version=0.0.4; charset=utf-8",
+            metrics.render_prometheus().into_bytes(),
+        ),
+        ("GET", None) => Response::text(404, "metrics not enabled"),
+        _ => Response::text(405, "method not allowed"),
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// Gets the value of the header named `name`, which must be lowercase.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header_name, _)| header_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, Response<'static>> {
+    let mut line = String::new();
+    reader
+        .take(MAX_LINE_LENGTH)
+        .read_line(&mut line)
+        .map_err(|_| Response::text(400, "malformed request"))?;
+    if !line.ends_with('\n') {
+        return Err(Response::text(400, "request line or header too long"));
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn read_request<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Request, Response<'static>> {
+    let request_line = read_line(reader)?;
+    let mut parts = request_line.split(' ');
+    let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/1.") => {
+            (method.to_string(), path.to_string())
+        }
+        _ => return Err(Response::text(400, "malformed request line")),
+    };
+
+    let mut headers = vec![];
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            break;
+        }
+        match line.split_once(':') {
+            Some((name, value)) => {
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            }
+            None => return Err(Response::text(400, "malformed header")),
+        }
+    }
+
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: vec![],
+    };
+    if let Some(content_length) = request.header("content-length") {
+        let content_length: u64 = content_length
+            .parse()
+            .map_err(|_| Response::text(400, "malformed content-length"))?;
+        if limits
+            .check_blob_size("request body", content_length)
+            .is_err()
+        {
+            return Err(Response::text(413, "request body too large"));
+        }
+        let mut body = vec![];
+        reader
+            .take(content_length)
+            .read_to_end(&mut body)
+            .map_err(|_| Response::text(400, "reading request body"))?;
+        if body.len() as u64 != content_length {
+            return Err(Response::text(
+                400,
+                "request body shorter than content-length",
+            ));
+        }
+        request.body = body;
+    }
+    Ok(request)
+}
+
+struct Response<'a> {
+    status: u16,
+    content_type: &'static str,
+    body: Body<'a>,
+    /// Write headers, including `Content-Length`, but not the body (for `HEAD` requests).
+    head_only: bool,
+}
+
+enum Body<'a> {
+    Bytes(Vec<u8>),
+    /// Content copied from a reader as the response is written, of a known length or delimited by
+    /// the end of the connection.
+    Stream {
+        reader: Box<dyn Read + 'a>,
+        length: Option<u64>,
+    },
+}
+
+impl<'a> Response<'a> {
+    fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type,
+            body: Body::Bytes(body),
+            head_only: false,
+        }
+    }
+
+    fn stream(
+        status: u16,
+        content_type: &'static str,
+        reader: Box<dyn Read + 'a>,
+        length: Option<u64>,
+    ) -> Self {
+        Self {
+            status,
+            content_type,
+            body: Body::Stream { reader, length },
+            head_only: false,
+        }
+    }
+
+    fn text(status: u16, text: &str) -> Self {
+        Self::new(
+            status,
+            "text/plain; charset=utf-8",
+            text.as_bytes().to_vec(),
+        )
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            201 => "Created",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        }
+    }
+
+    fn write<W: Write>(self, writer: &mut W) -> anyhow::Result<()> {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nConnection: close\r\n",
+            self.status,
+            self.reason(),
+            self.content_type,
+        )?;
+        let length = match &self.body {
+            Body::Bytes(body) => Some(body.len() as u64),
+            Body::Stream { length, .. } => *length,
+        };
+        if let Some(length) = length {
+            write!(writer, "Content-Length: {}\r\n", length)?;
+        }
+        if self.status == 401 {
+            writer.write_all(b"WWW-Authenticate: Bearer\r\n")?;
+        }
+        writer.write_all(b"\r\n")?;
+        if !self.head_only {
+            match self.body {
+                Body::Bytes(body) => writer.write_all(&body)?,
+                Body::Stream { mut reader, .. } => {
+                    std::io::copy(&mut reader, writer).context("streaming response body")?;
+                }
+            }
+        }
+        writer.flush().context("writing response")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::constant_time_eq;
+    use super::CacheServer;
+    use super::ServeOptions;
+    use crate::blob::BlobCache;
+    use crate::blob::BlobPointerCache;
+    use crate::blob::Compression;
+    use crate::blob::JSON;
+    use crate::fs::Filesystem as _;
+    use crate::fs::HostFilesystem;
+    use crate::identity::IdentityScheme as _;
+    use crate::transport::ContentSha256;
+    use std::io::Read as _;
+    use std::io::Write as _;
+    use std::net::TcpListener;
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    fn request(
+        server: &mut CacheServer<HostFilesystem, ContentSha256, JSON>,
+        request: &str,
+    ) -> String {
+        let mut response = vec![];
+        server
+            .handle(request.as_bytes(), &mut response)
+            .expect("handle request");
+        String::from_utf8(response).expect("UTF-8 response")
+    }
+
+    #[test]
+    fn test_cache_server() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let mut filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let mut server = CacheServer::<_, ContentSha256, JSON>::new(
+            filesystem.clone(),
+            ServeOptions {
+                token: Some(String::from("secret")),
+                read_only: false,
+                ..ServeOptions::default()
+            },
+        )
+        .expect("cache server");
+        let mut blob_cache = BlobCache::<_, ContentSha256, JSON>::new(
+            filesystem.sub_system("blobs").expect("blobs filesystem"),
+        );
+        let outputs_identity = blob_cache
+            .write_small_blob(&"outputs")
+            .expect("write outputs blob");
+        let inputs_identity = ContentSha256::identify_content(&b"inputs"[..]).expect("identify");
+        BlobPointerCache::<_, ContentSha256, JSON>::new(
+            filesystem
+                .sub_system("outputs")
+                .expect("outputs filesystem"),
+        )
+        .write_raw_blob_pointer(&inputs_identity, &outputs_identity)
+        .expect("write outputs pointer");
+
+        let authorized = |request_line: &str, body: &str| {
+            format!(
+                "{}\r\nAuthorization: Bearer secret\r\nContent-Length: {}\r\n\r\n{}",
+                request_line,
+                body.len(),
+                body
+            )
+        };
+
+        let response = request(
+            &mut server,
+            &format!(
+                "GET /blobs/{} HTTP/1.1\r\n\r\n",
+                outputs_identity.to_string()
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 401 "), "{}", response);
+        let response = request(
+            &mut server,
+            &format!(
+                "GET /blobs/{} HTTP/1.1\r\nAuthorization: Bearer secreT\r\n\r\n",
+                outputs_identity.to_string()
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 401 "), "{}", response);
+
+        let response = request(
+            &mut server,
+            &authorized(
+                &format!("GET /blobs/{} HTTP/1.1", outputs_identity.to_string()),
+                "",
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+        assert!(response.ends_with("\r\n\r\n\"outputs\""), "{}", response);
+
+        let response = request(
+            &mut server,
+            &authorized(
+                &format!("HEAD /blobs/{} HTTP/1.1", outputs_identity.to_string()),
+                "",
+            ),
+        );
+        assert!(response.contains("Content-Length: 9\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\n"), "{}", response);
+
+        let response = request(
+            &mut server,
+            &authorized(
+                &format!("GET /outputs/{} HTTP/1.1", inputs_identity.to_string()),
+                "",
+            ),
+        );
+        assert!(
+            response.ends_with(&outputs_identity.to_string()),
+            "{}",
+            response
+        );
+
+        let uploaded_identity =
+            ContentSha256::identify_content(&b"uploaded"[..]).expect("identify");
+        let put_request = authorized(
+            &format!("PUT /blobs/{} HTTP/1.1", uploaded_identity.to_string()),
+            "uploaded",
+        );
+        let response = request(&mut server, &put_request);
+        assert!(response.starts_with("HTTP/1.1 201 "), "{}", response);
+        assert!(blob_cache.contains_blob(&uploaded_identity));
+
+        let response = request(
+            &mut server,
+            &authorized(
+                &format!("PUT /metadata/{} HTTP/1.1", inputs_identity.to_string()),
+                &uploaded_identity.to_string(),
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 201 "), "{}", response);
+        let response = request(
+            &mut server,
+            &authorized(
+                &format!("GET /metadata/{} HTTP/1.1", inputs_identity.to_string()),
+                "",
+            ),
+        );
+        assert!(
+            response.ends_with(&uploaded_identity.to_string()),
+            "{}",
+            response
+        );
+        let missing_identity = ContentSha256::identify_content(&b"missing"[..]).expect("identify");
+        let response = request(
+            &mut server,
+            &authorized(
+                &format!("PUT /outputs/{} HTTP/1.1", uploaded_identity.to_string()),
+                &missing_identity.to_string(),
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 400 "), "{}", response);
+
+        let response = request(
+            &mut server,
+            &authorized(
+                &format!("PUT /blobs/{} HTTP/1.1", outputs_identity.to_string()),
+                "tampered",
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 400 "), "{}", response);
+
+        for path in ["/blobs/../salt.digest", "/blobs/not-an-identity", "/other"] {
+            let response = request(
+                &mut server,
+                &authorized(&format!("GET {} HTTP/1.1", path), ""),
+            );
+            assert!(
+                response.starts_with("HTTP/1.1 400 ") || response.starts_with("HTTP/1.1 404 "),
+                "{}",
+                response
+            );
+        }
+
+        let mut read_only_server = CacheServer::<_, ContentSha256, JSON>::new(
+            filesystem,
+            ServeOptions {
+                read_only: true,
+                ..ServeOptions::default()
+            },
+        )
+        .expect("read-only cache server");
+        let response = request(&mut read_only_server, &put_request);
+        assert!(response.starts_with("HTTP/1.1 403 "), "{}", response);
+    }
+
+    #[test]
+    fn test_serve_compressed_blob() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let mut filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let mut server =
+            CacheServer::<_, ContentSha256, JSON>::new(filesystem.clone(), ServeOptions::default())
+                .expect("cache server");
+        let identity = BlobCache::<_, ContentSha256, JSON>::new(
+            filesystem.sub_system("blobs").expect("blobs filesystem"),
+        )
+        .with_compression(Compression::Zstd { level: 3 })
+        .write_small_blob(&"compressed")
+        .expect("write compressed blob");
+
+        let response = request(
+            &mut server,
+            &format!("GET /blobs/{} HTTP/1.1\r\n\r\n", identity.to_string()),
+        );
+        assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+        assert!(!response.contains("Content-Length"), "{}", response);
+        assert!(response.ends_with("\r\n\r\n\"compressed\""), "{}", response);
+    }
+
+    #[test]
+    fn test_serve_connections_concurrently() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let server = CacheServer::<_, ContentSha256, JSON>::new(
+            HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+                .expect("host filesystem"),
+            ServeOptions {
+                timeout: Duration::from_millis(500),
+                max_connections: 2,
+                ..ServeOptions::default()
+            },
+        )
+        .expect("cache server");
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind listener");
+        let address = listener.local_addr().expect("listener address");
+        std::thread::spawn(move || server.serve(listener));
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(address).expect("connect");
+            write!(stream, "GET {} HTTP/1.1\r\n\r\n", path).expect("write request");
+            let mut response = String::new();
+            stream.read_to_string(&mut response).expect("read response");
+            response
+        };
+
+        // A client that never finishes its request neither blocks others nor holds its connection
+        // past the timeout.
+        let mut stalled = TcpStream::connect(address).expect("connect");
+        stalled
+            .write_all(b"GET /metrics")
+            .expect("write partial request");
+        let response = get("/other");
+        assert!(response.starts_with("HTTP/1.1 404 "), "{}", response);
+
+        let _second_stalled = TcpStream::connect(address).expect("connect");
+        std::thread::sleep(Duration::from_millis(100));
+        let mut response = String::new();
+        TcpStream::connect(address)
+            .expect("connect")
+            .read_to_string(&mut response)
+            .expect("read refusal");
+        assert!(response.starts_with("HTTP/1.1 503 "), "{}", response);
+
+        let mut response = vec![];
+        stalled
+            .read_to_end(&mut response)
+            .expect("read after timeout");
+        std::thread::sleep(Duration::from_millis(100));
+        let response = get("/other");
+        assert!(response.starts_with("HTTP/1.1 404 "), "{}", response);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"Bearer secret", b"Bearer secret"));
+        assert!(!constant_time_eq(b"Bearer secret", b"Bearer secreT"));
+        assert!(!constant_time_eq(b"Bearer secret", b"Bearer secret2"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
@@ -0,0 +1,391 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Memory-bounded resolution and identification of input manifests too large to hold in memory.
+//!
+//! `resolve_sorted_paths` iterates include globs lazily, and puts matches into canonical order with
+//! an external merge sort (`PathSpill`) that holds at most `StreamOptions::max_buffered_paths`
+//! paths in memory, spilling sorted runs to temporary files. `write_file_identities` identifies
+//! each path as it is produced and writes it to a line-delimited manifest (see `crate::ndjson`), so
+//! that neither paths nor identities accumulate in memory.
+
+use crate::canonical::FilesManifest;
+use crate::fs::Filesystem as FilesystemApi;
+use crate::identity::FileIdentityStatus;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::ndjson::FileIdentitiesWriter;
+use crate::profile;
+use crate::profile::Phase;
+use crate::transport::Inputs as InputsTransport;
+use crate::transport::SymlinkIdentity;
+use anyhow::Context as _;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Seek as _;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt as _;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Default number of paths held in memory before a sorted run is spilled to disk.
+pub const DEFAULT_MAX_BUFFERED_PATHS: usize = 100_000;
+
+#[derive(Clone, Debug)]
+pub struct StreamOptions {
+    /// Number of paths held in memory before a sorted run is spilled to disk.
+    pub max_buffered_paths: usize,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            max_buffered_paths: DEFAULT_MAX_BUFFERED_PATHS,
+        }
+    }
+}
+
+/// Set of paths that spills to temporary files as it grows, and yields its paths in sorted order.
+pub struct PathSpill {
+    max_buffered_paths: usize,
+    buffer: Vec<PathBuf>,
+    runs: Vec<File>,
+}
+
+impl PathSpill {
+    pub fn new(max_buffered_paths: usize) -> Self {
+        Self {
+            max_buffered_paths: max_buffered_paths.max(1),
+            buffer: vec![],
+            runs: vec![],
+        }
+    }
+
+    pub fn insert(&mut self, path: PathBuf) -> anyhow::Result<()> {
+        self.buffer.push(path);
+        if self.buffer.len() >= self.max_buffered_paths {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Number of sorted runs spilled to disk so far.
+    pub fn num_spilled_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    fn spill(&mut self) -> anyhow::Result<()> {
+        self.buffer.sort_unstable();
+        self.buffer.dedup();
+        let file = tempfile::tempfile().context("creating temporary file for sorted paths")?;
+        let mut writer = BufWriter::new(file);
+        for path in self.buffer.drain(..) {
+            write_path(&mut writer, &path).context("spilling sorted paths")?;
+        }
+        let mut file = writer
+            .into_inner()
+            .map_err(|error| anyhow::Error::from(error.into_error()))
+            .context("flushing sorted paths")?;
+        file.seek(SeekFrom::Start(0))
+            .context("rewinding sorted paths")?;
+        self.runs.push(file);
+        Ok(())
+    }
+
+    /// Merges the spilled runs and the paths still in memory into one sorted, deduplicated stream.
+    pub fn into_sorted(mut self) -> anyhow::Result<SortedPaths> {
+        self.buffer.sort_unstable();
+        self.buffer.dedup();
+        let mut runs: Vec<Run> = self
+            .runs
+            .into_iter()
+            .map(|file| Run::Spilled(BufReader::new(file)))
+            .collect();
+        runs.push(Run::Buffered(self.buffer.into_iter()));
+        let mut heap = BinaryHeap::with_capacity(runs.len());
+        for (index, run) in runs.iter_mut().enumerate() {
+            if let Some(path) = run.next_path()? {
+                heap.push(Reverse((path, index)));
+            }
+        }
+        Ok(SortedPaths {
+            runs,
+            heap,
+            last_path: None,
+        })
+    }
+}
+
+enum Run {
+    Spilled(BufReader<File>),
+    Buffered(std::vec::IntoIter<PathBuf>),
+}
+
+impl Run {
+    fn next_path(&mut self) -> anyhow::Result<Option<PathBuf>> {
+        match self {
+            Self::Spilled(reader) => read_path(reader).context("reading spilled sorted paths"),
+            Self::Buffered(paths) => Ok(paths.next()),
+        }
+    }
+}
+
+/// Paths are spilled as their raw bytes, prefixed by their length, so that paths of any encoding
+/// (and containing any characters) round-trip.
+fn write_path<W: Write>(writer: &mut W, path: &Path) -> std::io::Result<()> {
+    let bytes = path.as_os_str().as_bytes();
+    writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_path<R: Read>(reader: &mut R) -> std::io::Result<Option<PathBuf>> {
+    let mut len = [0; 8];
+    match reader.read_exact(&mut len) {
+        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let mut bytes = vec![0; u64::from_le_bytes(len) as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(Some(PathBuf::from(OsStr::from_bytes(&bytes))))
+}
+
+/// Sorted, deduplicated paths merged from the runs of a `PathSpill`.
+pub struct SortedPaths {
+    runs: Vec<Run>,
+    heap: BinaryHeap<Reverse<(PathBuf, usize)>>,
+    last_path: Option<PathBuf>,
+}
+
+impl Iterator for SortedPaths {
+    type Item = anyhow::Result<PathBuf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Reverse((path, index)) = self.heap.pop()?;
+            match self.runs[index].next_path() {
+                Ok(Some(next_path)) => self.heap.push(Reverse((next_path, index))),
+                Ok(None) => {}
+                Err(error) => return Some(Err(error)),
+            }
+            if self.last_path.as_ref() == Some(&path) {
+                continue;
+            }
+            self.last_path = Some(path.clone());
+            return Some(Ok(path));
+        }
+    }
+}
+
+/// Whether `inputs_config` only uses features that `resolve_sorted_paths` streams. Other features
+/// (e.g., inter-file references, which must look up arbitrary previously matched files) are
+/// resolved in memory.
+fn is_streamable(inputs_config: &InputsTransport) -> bool {
+    inputs_config.inter_file_references.is_empty()
+        && inputs_config.depfiles.is_empty()
+        && inputs_config.groups.is_empty()
+        && inputs_config.include_directories.is_empty()
+}
+
+/// Resolves the files described by `inputs_config`, in canonical order.
+pub fn resolve_sorted_paths<FS: FilesystemApi>(
+    filesystem: &mut FS,
+    inputs_config: &InputsTransport,
+    options: &StreamOptions,
+) -> anyhow::Result<SortedPaths> {
+    let _span = profile::span(Phase::ManifestResolution, || {
+        String::from("resolve sorted paths")
+    });
+    let mut spill = PathSpill::new(options.max_buffered_paths);
+    if !is_streamable(inputs_config) {
+        tracing::debug!("resolving input files in memory: configuration cannot be streamed");
+        let manifest = FilesManifest::try_from((&mut *filesystem, inputs_config))?;
+        for path in manifest.paths() {
+            spill.insert(path.clone())?;
+        }
+        return spill.into_sorted();
+    }
+
+    for path in inputs_config.include_files.iter() {
+        spill.insert(path.clone())?;
+    }
+    for include_glob in inputs_config.include_globs.iter() {
+        for path in filesystem
+            .execute_glob(include_glob)
+            .map_err(anyhow::Error::from)
+            .with_context(|| format!("executing include-glob {:?}", include_glob))?
+        {
+            spill.insert(
+                path.map_err(anyhow::Error::from)
+                    .with_context(|| format!("executing include-glob {:?}", include_glob))?,
+            )?;
+        }
+    }
+
+    // Apply exclusions as paths stream out of the sort, since the glob iterators above borrow the
+    // filesystem that matching exclude-globs needs.
+    let exclude_files: HashSet<&PathBuf> = inputs_config.exclude_files.iter().collect();
+    let mut filtered = PathSpill::new(options.max_buffered_paths);
+    for path in spill.into_sorted()? {
+        let path = path?;
+        if exclude_files.contains(&path) {
+            continue;
+        }
+        let mut is_excluded = false;
+        for exclude_glob in inputs_config.exclude_globs.iter() {
+            if filesystem
+                .glob_matches(exclude_glob, &path)
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("matching exclude-glob {:?}", exclude_glob))?
+            {
+                is_excluded = true;
+                break;
+            }
+        }
+        if !is_excluded {
+            filtered.insert(path)?;
+        }
+    }
+    filtered.into_sorted()
+}
+
+/// Identifies each of `paths`, which must be sorted, writing entries to a line-delimited file
+/// identities manifest as they are identified. As in `FilesManifest::into_identified`, paths that
+/// cannot be identified are recorded as having no identity.
+pub fn write_file_identities<IS, FS, W, I>(
+    filesystem: &mut FS,
+    paths: I,
+    symlink_identity: SymlinkIdentity,
+    writer: W,
+) -> anyhow::Result<W>
+where
+    IS: IdentitySchemeApi,
+    FS: FilesystemApi,
+    W: Write,
+    I: IntoIterator<Item = anyhow::Result<PathBuf>>,
+{
+    let mut writer = FileIdentitiesWriter::<W, IS>::new(writer)?;
+    for path in paths {
+        let path = path?;
+        let identity =
+            match FileIdentityStatus::identify::<IS, FS, _>(filesystem, &path, symlink_identity) {
+                FileIdentityStatus::Identified(identity) => Some(identity),
+                _ => None,
+            };
+        writer.write_entry(&(path, identity))?;
+    }
+    writer.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resolve_sorted_paths;
+    use super::write_file_identities;
+    use super::PathSpill;
+    use super::StreamOptions;
+    use crate::canonical::FilesManifest;
+    use crate::fs::HostFilesystem;
+    use crate::ndjson::FileIdentitiesReader;
+    use crate::transport::ContentSha256;
+    use crate::transport::Inputs as InputsTransport;
+    use crate::transport::SymlinkIdentity;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_path_spill() {
+        let mut spill = PathSpill::new(2);
+        for path in ["d", "b", "a", "d", "c\nwith newline", "b", "a"] {
+            spill.insert(PathBuf::from(path)).expect("insert");
+        }
+        assert_eq!(3, spill.num_spilled_runs());
+        let paths = spill
+            .into_sorted()
+            .expect("sort")
+            .collect::<anyhow::Result<Vec<_>>>()
+            .expect("read sorted paths");
+        assert_eq!(
+            ["a", "b", "c\nwith newline", "d"]
+                .into_iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>(),
+            paths
+        );
+    }
+
+    #[test]
+    fn test_stream_file_identities() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        std::fs::create_dir_all(temporary_directory.path().join("src/generated"))
+            .expect("create directories");
+        for (index, path) in [
+            "src/a.rs",
+            "src/b.rs",
+            "src/c.txt",
+            "src/generated/d.rs",
+            "src/generated/e.rs",
+            "top.rs",
+        ]
+        .iter()
+        .enumerate()
+        {
+            std::fs::write(temporary_directory.path().join(path), index.to_string())
+                .expect("write file");
+        }
+        let mut filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+        let inputs_config = InputsTransport {
+            include_files: vec![PathBuf::from("top.rs"), PathBuf::from("missing.rs")],
+            include_globs: vec![String::from("src/**/*.rs"), String::from("src/*.txt")],
+            exclude_files: vec![PathBuf::from("src/b.rs")],
+            exclude_globs: vec![String::from("src/generated/e.*")],
+            ..InputsTransport::default()
+        };
+
+        let paths = resolve_sorted_paths(
+            &mut filesystem,
+            &inputs_config,
+            &StreamOptions {
+                max_buffered_paths: 2,
+            },
+        )
+        .expect("resolve sorted paths");
+        let contents = write_file_identities::<ContentSha256, _, _, _>(
+            &mut filesystem,
+            paths,
+            SymlinkIdentity::default(),
+            vec![],
+        )
+        .expect("write file identities");
+
+        let streamed: Vec<_> = FileIdentitiesReader::<_, ContentSha256>::new(&contents[..])
+            .expect("read file identities")
+            .collect::<anyhow::Result<_>>()
+            .expect("read entries");
+        let in_memory: Vec<_> = FilesManifest::try_from((&mut filesystem, &inputs_config))
+            .expect("resolve in memory")
+            .into_identified::<ContentSha256, _>(&mut filesystem)
+            .identities()
+            .cloned()
+            .collect();
+        assert_eq!(in_memory, streamed);
+        assert_eq!(
+            vec![
+                PathBuf::from("missing.rs"),
+                PathBuf::from("src/a.rs"),
+                PathBuf::from("src/c.txt"),
+                PathBuf::from("src/generated/d.rs"),
+                PathBuf::from("top.rs"),
+            ],
+            streamed
+                .iter()
+                .map(|(path, _)| path.clone())
+                .collect::<Vec<_>>()
+        );
+    }
+}
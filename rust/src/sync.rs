@@ -0,0 +1,503 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Synchronizes the blobs and task pointers of two caches, transferring only what is missing.
+//!
+//! Caches are accessed through the `SyncPeer` trait, so that the same procedure synchronizes local
+//! cache directories (`CacheDirectory`) and remote caches. Existence checks are batched, and
+//! content that is stored chunked (see `crate::blob::BlobCache::write_chunked_blob`) is
+//! transferred chunk by chunk, so only chunks that the destination lacks are sent.
+//!
+//! Synchronization is resumable: blobs are transferred before any pointer that refers to them, and
+//! each blob becomes visible only once it is complete and verified, so that re-running an
+//! interrupted synchronization transfers only what is still missing.
+
+use crate::blob::BlobPointerCache;
+use crate::blob::BlobReader;
+use crate::blob::BlobWriter;
+use crate::blob::Compression;
+use crate::blob::ReadDeserializer as ReadDeserializerApi;
+use crate::blob::StringSerializer as StringSerializerApi;
+use crate::blob::WriteSerializer as WriteSerializerApi;
+use crate::cache::list_identities;
+use crate::cache::Cache;
+use crate::cache::WriteOnDropIndex;
+use crate::fs::Filesystem as FilesystemApi;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use anyhow::Context as _;
+use std::collections::HashSet;
+use std::io::Read as _;
+use std::io::Write as _;
+use std::path::PathBuf;
+
+/// Number of identities checked for existence per request when none is configured.
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// Collections of pointers from task inputs identities to blobs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PointerKind {
+    Outputs,
+    Metadata,
+}
+
+/// A cache that can take part in synchronization.
+pub trait SyncPeer<IS: IdentitySchemeApi> {
+    /// Lists the identities of all blobs in the cache.
+    fn blob_identities(&mut self) -> anyhow::Result<Vec<IS::Identity>>;
+
+    /// Gets those of `identities` that the cache does not contain.
+    fn missing_blobs(&mut self, identities: &[IS::Identity]) -> anyhow::Result<Vec<IS::Identity>>;
+
+    fn read_blob(&mut self, identity: &IS::Identity) -> anyhow::Result<Vec<u8>>;
+
+    /// Stores `content`, which must have identity `identity`.
+    fn write_blob(&mut self, identity: &IS::Identity, content: &[u8]) -> anyhow::Result<()>;
+
+    /// Lists all pointers of `kind` as (source, destination) pairs.
+    fn pointers(&mut self, kind: PointerKind) -> anyhow::Result<Vec<(IS::Identity, IS::Identity)>>;
+
+    /// Reads the pointer of `kind` from `source`, or `None` if there is none.
+    fn read_pointer(
+        &mut self,
+        kind: PointerKind,
+        source: &IS::Identity,
+    ) -> anyhow::Result<Option<IS::Identity>>;
+
+    fn write_pointer(
+        &mut self,
+        kind: PointerKind,
+        source: &IS::Identity,
+        destination: &IS::Identity,
+    ) -> anyhow::Result<()>;
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SyncOptions {
+    /// Number of identities checked for existence per call to `SyncPeer::missing_blobs`.
+    pub batch_size: usize,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+}
+
+/// What a synchronization transferred.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SyncReport {
+    pub blobs_transferred: usize,
+    pub bytes_transferred: u64,
+    /// Blobs that the destination already contained.
+    pub blobs_present: usize,
+    pub pointers_transferred: usize,
+    /// Pointers that the destination already contained.
+    pub pointers_present: usize,
+    /// Pointers that the destination records differently; the destination's pointers are kept.
+    pub pointer_conflicts: usize,
+    /// Pointers skipped because the blobs to which they refer are missing.
+    pub dangling_pointers: usize,
+}
+
+/// Copies the blobs and pointers of `source` that `destination` lacks to `destination`.
+pub fn sync_caches<IS: IdentitySchemeApi, Source: SyncPeer<IS>, Destination: SyncPeer<IS>>(
+    source: &mut Source,
+    destination: &mut Destination,
+    options: &SyncOptions,
+) -> anyhow::Result<SyncReport> {
+    let batch_size = options.batch_size.max(1);
+    let mut report = SyncReport::default();
+
+    let source_blobs = source.blob_identities().context("listing source blobs")?;
+    for batch in source_blobs.chunks(batch_size) {
+        let missing = destination
+            .missing_blobs(batch)
+            .context("checking for blobs missing from destination")?;
+        report.blobs_present += batch.len() - missing.len();
+        for identity in missing.iter() {
+            let content = source
+                .read_blob(identity)
+                .with_context(|| format!("reading source blob {}", identity.to_string()))?;
+            destination
+                .write_blob(identity, &content)
+                .with_context(|| format!("writing destination blob {}", identity.to_string()))?;
+            report.blobs_transferred += 1;
+            report.bytes_transferred += content.len() as u64;
+        }
+    }
+
+    let source_blobs: HashSet<IS::Identity> = source_blobs.into_iter().collect();
+    for kind in [PointerKind::Outputs, PointerKind::Metadata] {
+        let pointers = source
+            .pointers(kind)
+            .with_context(|| format!("listing source {:?} pointers", kind))?;
+        for batch in pointers.chunks(batch_size) {
+            // Targets that the source lacks may still be present in the destination.
+            let unknown_targets: Vec<IS::Identity> = batch
+                .iter()
+                .map(|(_, target)| target)
+                .filter(|target| !source_blobs.contains(target))
+                .cloned()
+                .collect();
+            let missing_targets: HashSet<IS::Identity> = if unknown_targets.is_empty() {
+                HashSet::new()
+            } else {
+                destination
+                    .missing_blobs(&unknown_targets)
+                    .context("checking for pointer targets missing from destination")?
+                    .into_iter()
+                    .collect()
+            };
+
+            for (source_identity, target) in batch.iter() {
+                if missing_targets.contains(target) {
+                    tracing::warn!(
+                        "skipping {:?} pointer from {} to missing blob {}",
+                        kind,
+                        source_identity.to_string(),
+                        target.to_string()
+                    );
+                    report.dangling_pointers += 1;
+                    continue;
+                }
+                // Unreadable pointers (e.g., partially written by an interrupted synchronization)
+                // are overwritten.
+                match destination
+                    .read_pointer(kind, source_identity)
+                    .ok()
+                    .flatten()
+                {
+                    Some(existing) if &existing == target => report.pointers_present += 1,
+                    Some(_) => report.pointer_conflicts += 1,
+                    None => {
+                        destination
+                            .write_pointer(kind, source_identity, target)
+                            .with_context(|| {
+                                format!(
+                                    "writing destination {:?} pointer from {}",
+                                    kind,
+                                    source_identity.to_string()
+                                )
+                            })?;
+                        report.pointers_transferred += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Synchronizes `first` and `second` in both directions, returning the reports of synchronizing
+/// `first` to `second` and then `second` to `first`.
+pub fn sync_caches_bidirectional<IS: IdentitySchemeApi, P1: SyncPeer<IS>, P2: SyncPeer<IS>>(
+    first: &mut P1,
+    second: &mut P2,
+    options: &SyncOptions,
+) -> anyhow::Result<(SyncReport, SyncReport)> {
+    let first_to_second = sync_caches(first, second, options)?;
+    let second_to_first = sync_caches(second, first, options)?;
+    Ok((first_to_second, second_to_first))
+}
+
+type DefaultCache<FS, IS, S> = Cache<FS, IS, S, WriteOnDropIndex<FS, IS, S>>;
+
+/// A cache directory on a local filesystem, laid out as by `crate::cache::Cache`.
+pub struct CacheDirectory<
+    Filesystem: FilesystemApi,
+    IdentityScheme: IdentitySchemeApi,
+    Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+> {
+    root: Filesystem,
+    blobs: Filesystem,
+    metadata_pointers: BlobPointerCache<Filesystem, IdentityScheme, Serialization>,
+    outputs_pointers: BlobPointerCache<Filesystem, IdentityScheme, Serialization>,
+    metadata_pointers_filesystem: Filesystem,
+    outputs_pointers_filesystem: Filesystem,
+}
+
+impl<
+        Filesystem: FilesystemApi,
+        IdentityScheme: IdentitySchemeApi,
+        Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+    > CacheDirectory<Filesystem, IdentityScheme, Serialization>
+{
+    /// Directory, relative to the cache root, in which blobs are written before they are verified
+    /// and moved into place.
+    pub const DEFAULT_INCOMING_SUBDIR: &str = "incoming";
+
+    /// Opens the cache whose root directory is `root`, creating its subdirectories if necessary.
+    pub fn open(mut root: Filesystem) -> anyhow::Result<Self> {
+        let blobs_subdir =
+            DefaultCache::<Filesystem, IdentityScheme, Serialization>::DEFAULT_BLOBS_SUBDIR;
+        let metadata_subdir = DefaultCache::<Filesystem, IdentityScheme, Serialization>::DEFAULT_METADATA_POINTERS_SUBDIR;
+        let outputs_subdir = DefaultCache::<Filesystem, IdentityScheme, Serialization>::DEFAULT_OUTPUTS_POINTERS_SUBDIR;
+        for subdir in [
+            blobs_subdir,
+            metadata_subdir,
+            outputs_subdir,
+            Self::DEFAULT_INCOMING_SUBDIR,
+        ] {
+            root.create_directories(subdir)
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("creating cache subdirectory {:?}", subdir))?;
+        }
+        let metadata_pointers_filesystem = root.sub_system(metadata_subdir)?;
+        let outputs_pointers_filesystem = root.sub_system(outputs_subdir)?;
+        Ok(Self {
+            blobs: root.sub_system(blobs_subdir)?,
+            metadata_pointers: BlobPointerCache::new(metadata_pointers_filesystem.clone()),
+            outputs_pointers: BlobPointerCache::new(outputs_pointers_filesystem.clone()),
+            metadata_pointers_filesystem,
+            outputs_pointers_filesystem,
+            root,
+        })
+    }
+
+    fn pointer_cache(
+        &mut self,
+        kind: PointerKind,
+    ) -> &mut BlobPointerCache<Filesystem, IdentityScheme, Serialization> {
+        match kind {
+            PointerKind::Outputs => &mut self.outputs_pointers,
+            PointerKind::Metadata => &mut self.metadata_pointers,
+        }
+    }
+}
+
+impl<
+        Filesystem: FilesystemApi,
+        IdentityScheme: IdentitySchemeApi,
+        Serialization: ReadDeserializerApi + StringSerializerApi + WriteSerializerApi,
+    > SyncPeer<IdentityScheme> for CacheDirectory<Filesystem, IdentityScheme, Serialization>
+{
+    fn blob_identities(&mut self) -> anyhow::Result<Vec<IdentityScheme::Identity>> {
+        list_identities::<Filesystem, IdentityScheme>(&mut self.blobs)
+    }
+
+    fn missing_blobs(
+        &mut self,
+        identities: &[IdentityScheme::Identity],
+    ) -> anyhow::Result<Vec<IdentityScheme::Identity>> {
+        Ok(identities
+            .iter()
+            .filter(|identity| !self.blobs.file_exists(identity.to_string()))
+            .cloned()
+            .collect())
+    }
+
+    fn read_blob(&mut self, identity: &IdentityScheme::Identity) -> anyhow::Result<Vec<u8>> {
+        let mut content = vec![];
+        BlobReader::new(
+            self.blobs
+                .open_file_for_read(identity.to_string())
+                .map_err(anyhow::Error::from)?,
+        )?
+        .read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    fn write_blob(
+        &mut self,
+        identity: &IdentityScheme::Identity,
+        content: &[u8],
+    ) -> anyhow::Result<()> {
+        let incoming_path = PathBuf::from(Self::DEFAULT_INCOMING_SUBDIR).join(identity.to_string());
+        let mut incoming_file = BlobWriter::new(
+            self.root
+                .open_file_for_write(&incoming_path)
+                .map_err(anyhow::Error::from)?,
+            Compression::None,
+        )?;
+        incoming_file.write_all(content)?;
+        incoming_file.finish()?;
+        let computed_identity = IdentityScheme::identify_content(content)?;
+        if identity != &computed_identity {
+            self.root
+                .remove_file(&incoming_path)
+                .map_err(anyhow::Error::from)?;
+            anyhow::bail!(
+                "blob identified as {:?} has computed identity {:?}",
+                identity,
+                computed_identity
+            );
+        }
+        let blob_path = PathBuf::from(
+            DefaultCache::<Filesystem, IdentityScheme, Serialization>::DEFAULT_BLOBS_SUBDIR,
+        )
+        .join(identity.to_string());
+        self.root
+            .move_from_to(&incoming_path, &blob_path)
+            .map_err(anyhow::Error::from)
+    }
+
+    fn pointers(
+        &mut self,
+        kind: PointerKind,
+    ) -> anyhow::Result<Vec<(IdentityScheme::Identity, IdentityScheme::Identity)>> {
+        let sources = match kind {
+            PointerKind::Outputs => {
+                list_identities::<Filesystem, IdentityScheme>(&mut self.outputs_pointers_filesystem)
+            }
+            PointerKind::Metadata => list_identities::<Filesystem, IdentityScheme>(
+                &mut self.metadata_pointers_filesystem,
+            ),
+        }?;
+        let pointer_cache = self.pointer_cache(kind);
+        sources
+            .into_iter()
+            .map(|source| {
+                let destination = pointer_cache
+                    .read_blob_pointer(&source)
+                    .with_context(|| format!("reading pointer from {}", source.to_string()))?;
+                Ok((source, destination))
+            })
+            .collect()
+    }
+
+    fn read_pointer(
+        &mut self,
+        kind: PointerKind,
+        source: &IdentityScheme::Identity,
+    ) -> anyhow::Result<Option<IdentityScheme::Identity>> {
+        let exists = match kind {
+            PointerKind::Outputs => self
+                .outputs_pointers_filesystem
+                .file_exists(source.to_string()),
+            PointerKind::Metadata => self
+                .metadata_pointers_filesystem
+                .file_exists(source.to_string()),
+        };
+        if !exists {
+            return Ok(None);
+        }
+        self.pointer_cache(kind).read_blob_pointer(source).map(Some)
+    }
+
+    fn write_pointer(
+        &mut self,
+        kind: PointerKind,
+        source: &IdentityScheme::Identity,
+        destination: &IdentityScheme::Identity,
+    ) -> anyhow::Result<()> {
+        self.pointer_cache(kind)
+            .write_raw_blob_pointer(source, destination)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sync_caches;
+    use super::sync_caches_bidirectional;
+    use super::CacheDirectory;
+    use super::SyncOptions;
+    use super::SyncPeer as _;
+    use super::SyncReport;
+    use crate::blob::JSON;
+    use crate::cache::Cache;
+    use crate::cache::WriteOnDropIndex;
+    use crate::canonical::FileIdentitiesManifest;
+    use crate::canonical::TaskInputs;
+    use crate::canonical::TaskOutputs;
+    use crate::execute::identify_task_inputs;
+    use crate::fs::HostFilesystem;
+    use crate::identity::IdentityScheme as _;
+    use crate::transport::ContentSha256;
+    use std::path::PathBuf;
+
+    type TestCache = Cache<
+        HostFilesystem,
+        ContentSha256,
+        JSON,
+        WriteOnDropIndex<HostFilesystem, ContentSha256, JSON>,
+    >;
+    type TestCacheDirectory = CacheDirectory<HostFilesystem, ContentSha256, JSON>;
+
+    fn put_task(
+        cache: &mut TestCache,
+        argument: &str,
+    ) -> <ContentSha256 as crate::identity::IdentityScheme>::Identity {
+        let inputs = TaskInputs::<ContentSha256>::for_test(argument);
+        let inputs_identity = identify_task_inputs(&inputs).expect("identify inputs");
+        let outputs = TaskOutputs::<ContentSha256>::new(
+            FileIdentitiesManifest::new([(
+                PathBuf::from("output.txt"),
+                Some(ContentSha256::identify_content(argument.as_bytes()).expect("identity")),
+            )]),
+            FileIdentitiesManifest::empty(),
+        );
+        cache.put_task(0, 0, inputs, outputs).expect("put task");
+        inputs_identity
+    }
+
+    #[test]
+    fn test_sync_caches() {
+        let first_directory = tempfile::tempdir().expect("first cache directory");
+        let second_directory = tempfile::tempdir().expect("second cache directory");
+        let filesystem = |directory: &tempfile::TempDir| {
+            HostFilesystem::try_new(directory.path().to_path_buf()).expect("host filesystem")
+        };
+        let first_task = {
+            let mut cache = TestCache::create(filesystem(&first_directory)).expect("create cache");
+            put_task(&mut cache, "first")
+        };
+        let mut first = TestCacheDirectory::open(filesystem(&first_directory)).expect("open first");
+        let mut second =
+            TestCacheDirectory::open(filesystem(&second_directory)).expect("open second");
+        let options = SyncOptions { batch_size: 1 };
+
+        let report = sync_caches(&mut first, &mut second, &options).expect("sync");
+        assert_eq!(3, report.blobs_transferred);
+        assert_eq!(2, report.pointers_transferred);
+        assert_eq!(
+            SyncReport {
+                blobs_present: 3,
+                pointers_present: 2,
+                ..SyncReport::default()
+            },
+            sync_caches(&mut first, &mut second, &options).expect("sync again")
+        );
+        let mut second_cache =
+            TestCache::open_or_create(filesystem(&second_directory)).expect("open second cache");
+        assert!(second_cache
+            .get_outputs(&first_task)
+            .expect("get outputs")
+            .is_some());
+
+        // Resume after an interruption that lost one blob.
+        let blob = second.blob_identities().expect("list blobs")[0].clone();
+        std::fs::remove_file(second_directory.path().join("blobs").join(blob.to_string()))
+            .expect("remove blob");
+        let report = sync_caches(&mut first, &mut second, &options).expect("resume sync");
+        assert_eq!(
+            (1, 2, 0),
+            (
+                report.blobs_transferred,
+                report.blobs_present,
+                report.pointers_transferred
+            )
+        );
+
+        let second_task = put_task(&mut second_cache, "second");
+        drop(second_cache);
+        let (first_to_second, second_to_first) =
+            sync_caches_bidirectional(&mut first, &mut second, &SyncOptions::default())
+                .expect("bidirectional sync");
+        assert_eq!(0, first_to_second.blobs_transferred);
+        // Both tasks' metadata blobs are identical.
+        assert_eq!(2, second_to_first.blobs_transferred);
+        assert_eq!(2, second_to_first.pointers_transferred);
+        assert!(TestCache::open(filesystem(&first_directory))
+            .expect("open first cache")
+            .get_outputs(&second_task)
+            .expect("get outputs")
+            .is_some());
+
+        let blob = first.blob_identities().expect("list blobs")[0].clone();
+        assert!(second.write_blob(&blob, b"corrupt").is_err());
+    }
+}
@@ -0,0 +1,694 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+use crate::canonical_json::to_canonical_pretty_string;
+use crate::error::Error;
+use crate::error::IoOperation;
+use crate::fs::Filesystem as FilesystemApi;
+use crate::limits::Limits;
+use crate::template::TemplateContext;
+use crate::transport::Task;
+use anyhow::Context as _;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::io::Read as _;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Top-level fields of a task description. `Task` flattens several transport types, so unknown
+/// top-level fields cannot be rejected by serde and are checked against this list instead.
+pub const TASK_FIELDS: &[&str] = &[
+    "execution_strategy",
+    "environment_variables",
+    "inherit",
+    "program",
+    "arguments",
+    "argv0",
+    "stdin",
+    "sandbox",
+    "resource_limits",
+    "working_directory",
+    "failure_caching",
+    "label",
+    "tags",
+    "inputs",
+    "outputs",
+];
+
+fn without_nulls(value: Value) -> Value {
+    match value {
+        Value::Array(values) => Value::Array(values.into_iter().map(without_nulls).collect()),
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, without_nulls(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// Explains why a task file could not be deserialized: where the problem is, which field it
+/// concerns, and, for misspelled names, the most likely intended name.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ParseDiagnostic {
+    pub message: String,
+    /// One-based line of the problem, when the format reports it.
+    pub line: Option<usize>,
+    /// One-based column of the problem, when the format reports it.
+    pub column: Option<usize>,
+    /// Dotted path to the offending field (e.g., `inputs.include_glob`).
+    pub field_path: Option<String>,
+    pub suggestion: Option<String>,
+}
+
+impl ParseDiagnostic {
+    fn new(message: String, line: Option<usize>, column: Option<usize>) -> Self {
+        Self {
+            message,
+            line,
+            column,
+            field_path: None,
+            suggestion: None,
+        }
+    }
+
+    /// Fills in the field path and suggestion for unknown field and variant errors, by finding the
+    /// unknown name in `document` and comparing it against the names serde expected.
+    fn annotate(mut self, document: Option<&Value>) -> Self {
+        let Some((unknown_name, expected_names)) = parse_unknown_name_message(&self.message) else {
+            return self;
+        };
+        self.suggestion = closest_name(&unknown_name, &expected_names)
+            .map(|expected| format!("did you mean `{}`?", expected));
+        if self.field_path.is_none() {
+            self.field_path = document.and_then(|document| find_key_path(document, &unknown_name));
+        }
+        self
+    }
+}
+
+impl Display for ParseDiagnostic {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(formatter, "line {}, column {}: ", line, column)?,
+            (Some(line), None) => write!(formatter, "line {}: ", line)?,
+            _ => {}
+        }
+        write!(formatter, "{}", self.message)?;
+        if let Some(field_path) = &self.field_path {
+            write!(formatter, " (at `{}`)", field_path)?;
+        }
+        if let Some(suggestion) = &self.suggestion {
+            write!(formatter, "; {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseDiagnostic {}
+
+/// Formats in which task descriptions may be written, detected from the task file's extension.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TaskFileFormat {
+    Json,
+    Json5,
+    Toml,
+    Yaml,
+}
+
+impl TaskFileFormat {
+    pub const ALL: &'static [TaskFileFormat] = &[
+        TaskFileFormat::Json,
+        TaskFileFormat::Json5,
+        TaskFileFormat::Toml,
+        TaskFileFormat::Yaml,
+    ];
+
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            Self::Json => &["json"],
+            Self::Json5 => &["json5"],
+            Self::Toml => &["toml"],
+            Self::Yaml => &["yaml", "yml"],
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("");
+        Self::ALL
+            .iter()
+            .find(|format| format.extensions().contains(&extension))
+            .copied()
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "task file, {:?}, has unsupported extension; expected one of {:?}",
+                    path,
+                    Self::ALL
+                        .iter()
+                        .flat_map(|format| format.extensions().iter())
+                        .collect::<Vec<_>>()
+                )
+            })
+    }
+
+    /// Parses a task description, rejecting unknown top-level fields. Unknown fields are checked
+    /// first, so that a misspelled required field is reported as such rather than as missing.
+    pub fn parse_task(&self, contents: &str) -> anyhow::Result<Task> {
+        self.parse_task_with_limits(contents, &Limits::default())
+    }
+
+    /// As `parse_task`, rejecting task descriptions that exceed `limits`.
+    pub fn parse_task_with_limits(&self, contents: &str, limits: &Limits) -> anyhow::Result<Task> {
+        limits.check_blob_size("task file", contents.len() as u64)?;
+        if let Ok(document) = self.parse_raw::<Value>(contents) {
+            check_fields(&document, TASK_FIELDS, None)?;
+        }
+        let task = self.parse(contents)?;
+        limits.check_task(&task)?;
+        Ok(task)
+    }
+
+    /// Writes `task` in this format, laid out for people to read and edit. Fields are written in a
+    /// stable (sorted) order, so that formatting the same task always yields the same bytes.
+    pub fn format_task(&self, task: &Task) -> anyhow::Result<String> {
+        self.format(task)
+    }
+
+    /// As `format_task`, for any serializable description.
+    pub fn format<S: Serialize>(&self, data: &S) -> anyhow::Result<String> {
+        // Round-trip through `Value`, whose objects are sorted by key, to fix the field order.
+        let value = serde_json::to_value(data).context("converting description to JSON value")?;
+        match self {
+            Self::Json | Self::Json5 => {
+                to_canonical_pretty_string(&value).map_err(anyhow::Error::from)
+            }
+            Self::Toml => {
+                // TOML has no null; absent optional fields are omitted instead.
+                toml::to_string_pretty(&without_nulls(value)).map_err(anyhow::Error::from)
+            }
+            Self::Yaml => serde_yaml::to_string(&value).map_err(anyhow::Error::from),
+        }
+        .with_context(|| format!("formatting description as {:?}", self))
+    }
+
+    /// Parses any deserializable description (e.g., a task or task graph) written in this format.
+    /// Failures are reported as a `ParseDiagnostic`.
+    pub fn parse<D: DeserializeOwned>(&self, contents: &str) -> anyhow::Result<D> {
+        self.parse_raw(contents).map_err(|diagnostic| {
+            let document = self.parse_raw::<Value>(contents).ok();
+            anyhow::Error::from(diagnostic.annotate(document.as_ref()))
+        })
+    }
+
+    fn parse_raw<D: DeserializeOwned>(&self, contents: &str) -> Result<D, ParseDiagnostic> {
+        match self {
+            Self::Json => serde_json::from_str(contents).map_err(|error| {
+                let (line, column) = (error.line(), error.column());
+                ParseDiagnostic::new(
+                    strip_location_suffix(error.to_string(), line, column),
+                    Some(line),
+                    Some(column),
+                )
+            }),
+            Self::Json5 => {
+                json5::from_str(contents).map_err(|json5::Error::Message { msg, location }| {
+                    ParseDiagnostic::new(
+                        msg,
+                        location.as_ref().map(|location| location.line),
+                        location.as_ref().map(|location| location.column),
+                    )
+                })
+            }
+            Self::Toml => toml::from_str(contents).map_err(|error| {
+                let (line, column) = error
+                    .span()
+                    .map(|span| line_and_column(contents, span.start))
+                    .unzip();
+                ParseDiagnostic::new(String::from(error.message()), line, column)
+            }),
+            Self::Yaml => serde_yaml::from_str(contents).map_err(|error| match error.location() {
+                Some(location) => ParseDiagnostic::new(
+                    strip_location_suffix(error.to_string(), location.line(), location.column()),
+                    Some(location.line()),
+                    Some(location.column()),
+                ),
+                None => ParseDiagnostic::new(error.to_string(), None, None),
+            }),
+        }
+    }
+}
+
+/// Checks that `document`, an object describing a task, has only `known_fields`.
+pub fn check_fields(
+    document: &Value,
+    known_fields: &[&str],
+    path_prefix: Option<&str>,
+) -> Result<(), ParseDiagnostic> {
+    let Value::Object(object) = document else {
+        return Ok(());
+    };
+    for key in object.keys() {
+        if !known_fields.contains(&key.as_str()) {
+            let expected_names: Vec<String> = known_fields
+                .iter()
+                .map(|name| String::from(*name))
+                .collect();
+            return Err(ParseDiagnostic {
+                message: format!(
+                    "unknown field `{}`, expected one of {}",
+                    key,
+                    expected_names
+                        .iter()
+                        .map(|name| format!("`{}`", name))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                line: None,
+                column: None,
+                field_path: Some(match path_prefix {
+                    Some(path_prefix) => format!("{}.{}", path_prefix, key),
+                    None => key.clone(),
+                }),
+                suggestion: closest_name(key, &expected_names)
+                    .map(|expected| format!("did you mean `{}`?", expected)),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Removes the " at line L column C" suffix that some formats append to their messages.
+fn strip_location_suffix(message: String, line: usize, column: usize) -> String {
+    let suffix = format!(" at line {} column {}", line, column);
+    match message.strip_suffix(&suffix) {
+        Some(message) => String::from(message),
+        None => message,
+    }
+}
+
+/// Gets the one-based line and column of the byte at `offset` in `contents`.
+fn line_and_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|index| index + 1).unwrap_or(0);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Extracts the unknown name and the expected names from a serde "unknown field" or "unknown
+/// variant" message.
+fn parse_unknown_name_message(message: &str) -> Option<(String, Vec<String>)> {
+    let rest = message
+        .strip_prefix("unknown field `")
+        .or_else(|| message.strip_prefix("unknown variant `"))?;
+    let (unknown_name, expected) = rest.split_once('`')?;
+    let expected_names = expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(String::from)
+        .collect();
+    Some((String::from(unknown_name), expected_names))
+}
+
+/// Gets the name in `candidates` closest to `name`, if any is close enough to be a likely typo.
+fn closest_name<'a>(name: &str, candidates: &'a [String]) -> Option<&'a String> {
+    let max_distance = (name.chars().count() / 3).max(2);
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between `a` and `b`, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous_row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_character) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, b_character) in b.iter().enumerate() {
+            let substitution = previous_row[j] + usize::from(a_character != *b_character);
+            row.push(substitution.min(previous_row[j + 1] + 1).min(row[j] + 1));
+        }
+        previous_row = row;
+    }
+    previous_row[b.len()]
+}
+
+/// Finds the dotted path of the first object key named `key` in `document`.
+fn find_key_path(document: &Value, key: &str) -> Option<String> {
+    match document {
+        Value::Object(object) => {
+            if object.contains_key(key) {
+                return Some(String::from(key));
+            }
+            object.iter().find_map(|(name, value)| {
+                find_key_path(value, key).map(|path| format!("{}.{}", name, path))
+            })
+        }
+        Value::Array(values) => values.iter().enumerate().find_map(|(index, value)| {
+            find_key_path(value, key).map(|path| format!("[{}].{}", index, path))
+        }),
+        _ => None,
+    }
+}
+
+/// Reads the task description at `path`, interpreting it according to its extension.
+pub fn read_task_file<FS: FilesystemApi, P: AsRef<Path>>(
+    filesystem: &mut FS,
+    path: P,
+) -> anyhow::Result<Task> {
+    let path = path.as_ref();
+    let format = TaskFileFormat::from_path(path)?;
+    let mut contents = String::new();
+    filesystem
+        .open_file_for_read(path)
+        .map_err(|error| Error::io(path, IoOperation::Read, error))
+        .and_then(|mut file| {
+            file.read_to_string(&mut contents)
+                .map_err(|error| Error::io(path, IoOperation::Read, error))
+        })
+        .with_context(|| format!("reading task file {:?}", path))?;
+    format
+        .parse_task(&contents)
+        .map_err(Error::serialization)
+        .with_context(|| format!("parsing task file {:?} as {:?}", path, format))
+}
+
+/// Reads the task description at `path` and expands templated fields against `context`, with
+/// `${inputdir}` resolved relative to the directory containing the task file (see
+/// `task_working_directory`).
+pub fn load_task_file<FS: FilesystemApi, P: AsRef<Path>>(
+    filesystem: &mut FS,
+    path: P,
+    context: &TemplateContext,
+) -> anyhow::Result<Task> {
+    let path = path.as_ref();
+    let task = read_task_file(filesystem, path)?;
+    let input_directory = match task_working_directory(path, &task) {
+        Some(working_directory) => context.working_directory().join(working_directory),
+        None => context.working_directory().to_path_buf(),
+    };
+    context
+        .expand_task_in_directory(task, &input_directory)
+        .with_context(|| format!("expanding templates in task file {:?}", path))
+}
+
+/// Resolves the working directory of `task`, loaded from `task_file_path`, relative to the
+/// directory containing the task file. Returns `None` when the task does not declare a working
+/// directory.
+pub fn task_working_directory<P: AsRef<Path>>(task_file_path: P, task: &Task) -> Option<PathBuf> {
+    let working_directory = task.working_directory.as_ref()?;
+    if working_directory.is_absolute() {
+        return Some(working_directory.clone());
+    }
+    let task_file_directory = task_file_path
+        .as_ref()
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    Some(task_file_directory.join(working_directory))
+}
+
+/// Creates the filesystem in which `task`, loaded from `task_file_path` in `filesystem`, runs: a
+/// sub-system rooted at the task's working directory, or `filesystem` itself when the task does not
+/// declare one.
+pub fn task_filesystem<FS: FilesystemApi, P: AsRef<Path>>(
+    filesystem: &mut FS,
+    task_file_path: P,
+    task: &Task,
+) -> anyhow::Result<FS> {
+    let sub_directory =
+        task_working_directory(task_file_path, task).unwrap_or_else(|| PathBuf::from(""));
+    filesystem.sub_system(&sub_directory).with_context(|| {
+        format!(
+            "creating filesystem for working directory {:?}",
+            sub_directory
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_task_file;
+    use super::task_filesystem;
+    use super::task_working_directory;
+    use super::ParseDiagnostic;
+    use super::TaskFileFormat;
+    use crate::fs::Filesystem as _;
+    use crate::fs::HostFilesystem;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    const JSON_TASK: &str = r#"{
+  "environment_variables": [["PATH", "/usr/bin"]],
+  "program": "/usr/bin/cc",
+  "arguments": ["-c", "main.c"],
+  "inputs": {
+    "include_files": ["main.c"],
+    "exclude_files": [],
+    "include_globs": ["include/*.h"],
+    "exclude_globs": [],
+    "inter_file_references": [
+      {
+        "match_transforms": [
+          {
+            "match_regular_expression": "^#include \"(.*)\"$",
+            "match_transform_expressions": ["$1"]
+          }
+        ]
+      }
+    ]
+  },
+  "outputs": {
+    "include_files": ["main.o"],
+    "include_match_transforms": [],
+    "exclude_matches": []
+  }
+}
+"#;
+
+    const TOML_TASK: &str = r#"
+environment_variables = [["PATH", "/usr/bin"]]
+program = "/usr/bin/cc"
+arguments = ["-c", "main.c"]
+
+[inputs]
+include_files = ["main.c"]
+exclude_files = []
+include_globs = ["include/*.h"]
+exclude_globs = []
+
+[[inputs.inter_file_references]]
+match_transforms = [
+  { match_regular_expression = '^#include "(.*)"$', match_transform_expressions = ["$1"] },
+]
+
+[outputs]
+include_files = ["main.o"]
+include_match_transforms = []
+exclude_matches = []
+"#;
+
+    const YAML_TASK: &str = r#"
+environment_variables:
+  - [PATH, /usr/bin]
+program: /usr/bin/cc
+arguments: [-c, main.c]
+inputs:
+  include_files: [main.c]
+  exclude_files: []
+  include_globs: [include/*.h]
+  exclude_globs: []
+  inter_file_references:
+    - match_transforms:
+        - match_regular_expression: '^#include "(.*)"$'
+          match_transform_expressions: ["$1"]
+outputs:
+  include_files: [main.o]
+  include_match_transforms: []
+  exclude_matches: []
+"#;
+
+    #[test]
+    fn test_task_file_formats() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        for (path, contents) in [
+            ("task.json", JSON_TASK),
+            ("task.json5", JSON_TASK),
+            ("task.toml", TOML_TASK),
+            ("task.yaml", YAML_TASK),
+            ("task.yml", YAML_TASK),
+        ] {
+            std::fs::write(temporary_directory.path().join(path), contents)
+                .expect("manually create task file");
+        }
+        let mut host_filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+
+        let expected = serde_json::to_value(
+            TaskFileFormat::Json
+                .parse_task(JSON_TASK)
+                .expect("parse JSON task"),
+        )
+        .expect("task to JSON value");
+        for path in [
+            "task.json",
+            "task.json5",
+            "task.toml",
+            "task.yaml",
+            "task.yml",
+        ] {
+            let task = read_task_file(&mut host_filesystem, path).expect("read task file");
+            assert_eq!(
+                expected,
+                serde_json::to_value(task).expect("task to JSON value"),
+                "{}",
+                path
+            );
+        }
+
+        assert_eq!(
+            TaskFileFormat::Toml,
+            TaskFileFormat::from_path("a/b.toml").expect("TOML format")
+        );
+        assert!(TaskFileFormat::from_path("task.txt").is_err());
+        assert!(TaskFileFormat::Toml.parse_task(YAML_TASK).is_err());
+    }
+
+    #[test]
+    fn test_format_task() {
+        let task = TaskFileFormat::Yaml
+            .parse_task(YAML_TASK)
+            .expect("parse YAML task");
+        let expected = serde_json::to_value(&task).expect("task to JSON value");
+        for format in TaskFileFormat::ALL {
+            let formatted = format.format_task(&task).expect("format task");
+            let reparsed = format.parse_task(&formatted).expect("parse formatted task");
+            assert_eq!(
+                expected,
+                serde_json::to_value(&reparsed).expect("task to JSON value"),
+                "{:?}",
+                format
+            );
+            // Formatting is stable across round trips.
+            assert_eq!(
+                formatted,
+                format.format_task(&reparsed).expect("format task"),
+                "{:?}",
+                format
+            );
+        }
+
+        let json = TaskFileFormat::Json
+            .format_task(&task)
+            .expect("format JSON task");
+        assert!(
+            json.starts_with("{\n  \"arguments\": [\n    \"-c\",\n"),
+            "{}",
+            json
+        );
+        assert!(json.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_parse_diagnostics() {
+        let diagnostic = |format: TaskFileFormat, contents: &str| {
+            format
+                .parse_task(contents)
+                .expect_err("parse invalid task")
+                .downcast::<ParseDiagnostic>()
+                .expect("parse diagnostic")
+        };
+
+        let misspelled_json = JSON_TASK.replace("\"include_globs\"", "\"include_glob\"");
+        let json_diagnostic = diagnostic(TaskFileFormat::Json, &misspelled_json);
+        assert_eq!(Some(8), json_diagnostic.line);
+        assert_eq!(
+            Some(String::from("inputs.include_glob")),
+            json_diagnostic.field_path
+        );
+        assert_eq!(
+            Some(String::from("did you mean `include_globs`?")),
+            json_diagnostic.suggestion
+        );
+        assert!(json_diagnostic.to_string().starts_with("line 8, column"));
+
+        let misspelled_toml = TOML_TASK.replace("include_globs", "include_glob");
+        let toml_diagnostic = diagnostic(TaskFileFormat::Toml, &misspelled_toml);
+        assert_eq!(
+            Some(String::from("inputs.include_glob")),
+            toml_diagnostic.field_path
+        );
+        assert_eq!(
+            Some(String::from("did you mean `include_globs`?")),
+            toml_diagnostic.suggestion
+        );
+        assert!(toml_diagnostic.line.is_some());
+
+        // Unknown top-level fields are also reported.
+        let misspelled_yaml = YAML_TASK.replace("arguments:", "argumnets:");
+        let yaml_diagnostic = diagnostic(TaskFileFormat::Yaml, &misspelled_yaml);
+        assert_eq!(Some(String::from("argumnets")), yaml_diagnostic.field_path);
+        assert_eq!(
+            Some(String::from("did you mean `arguments`?")),
+            yaml_diagnostic.suggestion
+        );
+
+        // Errors without a plausible correction carry no suggestion.
+        let json5_diagnostic = diagnostic(
+            TaskFileFormat::Json5,
+            &JSON_TASK.replace("\"program\"", "\"executable_path\""),
+        );
+        assert_eq!(None, json5_diagnostic.suggestion);
+    }
+
+    #[test]
+    fn test_task_working_directory() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        std::fs::create_dir_all(temporary_directory.path().join("tasks/build"))
+            .expect("manually create task directory");
+        std::fs::create_dir_all(temporary_directory.path().join("src"))
+            .expect("manually create source directory");
+        std::fs::write(temporary_directory.path().join("src/main.c"), "int main;")
+            .expect("manually create source file");
+        let mut host_filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+
+        let mut task = TaskFileFormat::Json
+            .parse_task(JSON_TASK)
+            .expect("parse JSON task");
+        assert_eq!(None, task_working_directory("tasks/build/task.json", &task));
+
+        task.working_directory = Some(PathBuf::from("/absolute"));
+        assert_eq!(
+            Some(PathBuf::from("/absolute")),
+            task_working_directory("tasks/build/task.json", &task)
+        );
+
+        task.working_directory = Some(PathBuf::from("../../src"));
+        assert_eq!(
+            Some(PathBuf::from("tasks/build/../../src")),
+            task_working_directory("tasks/build/task.json", &task)
+        );
+        let mut task_filesystem =
+            task_filesystem(&mut host_filesystem, "tasks/build/task.json", &task)
+                .expect("task filesystem");
+        assert!(task_filesystem.file_exists(Path::new("main.c")));
+
+        let parsed = TaskFileFormat::Json
+            .parse_task(&JSON_TASK.replacen('{', r#"{"working_directory": "../../src","#, 1))
+            .expect("parse task with working directory");
+        assert_eq!(Some(PathBuf::from("../../src")), parsed.working_directory);
+    }
+}
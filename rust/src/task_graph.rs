@@ -0,0 +1,282 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+use crate::fs::Filesystem as FilesystemApi;
+use crate::schema::migrate;
+use crate::schema::FormatVersion;
+use crate::task_file::check_fields;
+use crate::task_file::TaskFileFormat;
+use crate::task_file::TASK_FIELDS;
+use crate::transport::OutputWiring;
+use crate::transport::Task;
+use crate::transport::TaskGraph as TaskGraphTransport;
+use crate::transport::TaskGraphNode;
+use anyhow::Context as _;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::io::Read as _;
+use std::path::Path;
+
+/// A validated task graph: every dependency and wired output refers to a task in the graph, and
+/// dependencies form no cycles.
+#[derive(Clone, Debug)]
+pub struct TaskGraph {
+    nodes: BTreeMap<String, TaskGraphNode>,
+    dependencies: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl TaskGraph {
+    pub fn labels(&self) -> impl Iterator<Item = &String> {
+        self.nodes.keys()
+    }
+
+    pub fn task(&self, label: &str) -> Option<&Task> {
+        self.nodes.get(label).map(|node| &node.task)
+    }
+
+    pub fn wired_inputs(&self, label: &str) -> impl Iterator<Item = &OutputWiring> {
+        self.nodes
+            .get(label)
+            .into_iter()
+            .flat_map(|node| node.wired_inputs.iter())
+    }
+
+    /// Gets the labels of tasks that must complete before the task labelled `label` runs,
+    /// including those implied by wired inputs.
+    pub fn dependencies(&self, label: &str) -> impl Iterator<Item = &String> {
+        self.dependencies
+            .get(label)
+            .into_iter()
+            .flat_map(|dependencies| dependencies.iter())
+    }
+
+    /// Gets all labels ordered such that each task follows its dependencies. Ties are broken by
+    /// label, so the order is deterministic.
+    pub fn topological_order(&self) -> Vec<&String> {
+        let mut remaining_dependencies: BTreeMap<&String, usize> = self
+            .dependencies
+            .iter()
+            .map(|(label, dependencies)| (label, dependencies.len()))
+            .collect();
+        let mut dependents: BTreeMap<&String, Vec<&String>> = BTreeMap::new();
+        for (label, dependencies) in self.dependencies.iter() {
+            for dependency in dependencies.iter() {
+                dependents.entry(dependency).or_default().push(label);
+            }
+        }
+
+        let mut ready: BTreeSet<&String> = remaining_dependencies
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(label, _)| *label)
+            .collect();
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while let Some(label) = ready.pop_first() {
+            order.push(label);
+            for dependent in dependents.get(label).into_iter().flatten() {
+                let count = remaining_dependencies
+                    .get_mut(dependent)
+                    .expect("dependent is in graph");
+                *count -= 1;
+                if *count == 0 {
+                    ready.insert(dependent);
+                }
+            }
+        }
+        order
+    }
+}
+
+impl TryFrom<TaskGraphTransport> for TaskGraph {
+    type Error = anyhow::Error;
+
+    fn try_from(transport: TaskGraphTransport) -> anyhow::Result<Self> {
+        let mut dependencies = BTreeMap::new();
+        for (label, node) in transport.tasks.iter() {
+            if label.is_empty() {
+                anyhow::bail!("task graph contains task with empty label");
+            }
+            let mut node_dependencies = BTreeSet::new();
+            for dependency in node.dependencies.iter() {
+                if !node_dependencies.insert(dependency.clone()) {
+                    anyhow::bail!(
+                        "task, {:?}, lists dependency, {:?}, twice",
+                        label,
+                        dependency
+                    );
+                }
+            }
+            let mut wired_input_paths = HashSet::new();
+            for wiring in node.wired_inputs.iter() {
+                let input_path = wiring.input.as_ref().unwrap_or(&wiring.output);
+                if !wired_input_paths.insert(input_path) {
+                    anyhow::bail!(
+                        "task, {:?}, has input, {:?}, wired twice",
+                        label,
+                        input_path
+                    );
+                }
+                node_dependencies.insert(wiring.from_task.clone());
+            }
+            for dependency in node_dependencies.iter() {
+                if dependency == label {
+                    anyhow::bail!("task, {:?}, depends on itself", label);
+                }
+                if !transport.tasks.contains_key(dependency) {
+                    anyhow::bail!(
+                        "task, {:?}, depends on unknown task, {:?}",
+                        label,
+                        dependency
+                    );
+                }
+            }
+            dependencies.insert(label.clone(), node_dependencies);
+        }
+
+        let graph = Self {
+            nodes: transport.tasks,
+            dependencies,
+        };
+        let order = graph.topological_order();
+        if order.len() != graph.nodes.len() {
+            let ordered: HashSet<_> = order.into_iter().collect();
+            let cyclic: Vec<_> = graph
+                .labels()
+                .filter(|label| !ordered.contains(label))
+                .collect();
+            anyhow::bail!("task graph contains a dependency cycle among {:?}", cyclic);
+        }
+        Ok(graph)
+    }
+}
+
+impl From<TaskGraph> for TaskGraphTransport {
+    fn from(graph: TaskGraph) -> Self {
+        Self {
+            format_version: FormatVersion::default(),
+            tasks: graph.nodes,
+        }
+    }
+}
+
+/// Reads the task graph at `path`, interpreting it according to its extension. Graphs written
+/// with older schema versions are migrated to the current one.
+pub fn read_task_graph_file<FS: FilesystemApi, P: AsRef<Path>>(
+    filesystem: &mut FS,
+    path: P,
+) -> anyhow::Result<TaskGraph> {
+    let path = path.as_ref();
+    let format = TaskFileFormat::from_path(path)?;
+    let mut contents = String::new();
+    filesystem
+        .open_file_for_read(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut file| {
+            file.read_to_string(&mut contents)
+                .map_err(anyhow::Error::from)
+        })
+        .with_context(|| format!("reading task graph file {:?}", path))?;
+    let value: Value = format
+        .parse(&contents)
+        .with_context(|| format!("parsing task graph file {:?} as {:?}", path, format))?;
+    if let Some(Value::Object(tasks)) = value.get("tasks") {
+        let node_fields: Vec<&str> = TASK_FIELDS
+            .iter()
+            .copied()
+            .chain(["dependencies", "depends_on", "wired_inputs"])
+            .collect();
+        for (label, node) in tasks.iter() {
+            check_fields(node, &node_fields, Some(&format!("tasks.{}", label)))
+                .with_context(|| format!("checking task graph file {:?}", path))?;
+        }
+    }
+    migrate::<TaskGraphTransport>(value)
+        .and_then(TaskGraph::try_from)
+        .with_context(|| format!("loading task graph file {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_task_graph_file;
+    use super::TaskGraph;
+    use crate::fs::HostFilesystem;
+    use crate::transport::TaskGraph as TaskGraphTransport;
+    use std::convert::TryFrom;
+    use std::path::Path;
+
+    const TOML_GRAPH: &str = r#"
+[tasks.compile]
+environment_variables = []
+program = "/usr/bin/cc"
+arguments = ["-c", "main.c", "-o", "main.o"]
+inputs = { include_files = ["main.c"], exclude_files = [], include_globs = [], exclude_globs = [], inter_file_references = [] }
+outputs = { include_files = ["main.o"], include_match_transforms = [], exclude_matches = [] }
+
+[tasks.generate]
+environment_variables = []
+program = "/usr/bin/gen"
+arguments = []
+inputs = { include_files = [], exclude_files = [], include_globs = [], exclude_globs = [], inter_file_references = [] }
+outputs = { include_files = ["gen/config.h"], include_match_transforms = [], exclude_matches = [] }
+
+[tasks.link]
+dependencies = ["compile"]
+environment_variables = []
+program = "/usr/bin/cc"
+arguments = ["main.o", "-o", "main"]
+inputs = { include_files = ["main.o"], exclude_files = [], include_globs = [], exclude_globs = [], inter_file_references = [] }
+outputs = { include_files = ["main"], include_match_transforms = [], exclude_matches = [] }
+
+[[tasks.compile.wired_inputs]]
+from_task = "generate"
+output = "gen/config.h"
+input = "config.h"
+"#;
+
+    #[test]
+    fn test_task_graph() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        std::fs::write(temporary_directory.path().join("graph.toml"), TOML_GRAPH)
+            .expect("manually create file");
+        let mut host_filesystem = HostFilesystem::try_new(temporary_directory.path().to_path_buf())
+            .expect("host filesystem");
+
+        let graph =
+            read_task_graph_file(&mut host_filesystem, "graph.toml").expect("read task graph");
+        assert_eq!(
+            vec!["generate", "compile", "link"],
+            graph.topological_order()
+        );
+        assert_eq!(
+            vec!["generate"],
+            graph.dependencies("compile").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(Path::new("config.h")),
+            graph
+                .wired_inputs("compile")
+                .next()
+                .and_then(|wiring| wiring.input.as_deref())
+        );
+
+        let mut transport = TaskGraphTransport::from(graph);
+        transport
+            .tasks
+            .get_mut("generate")
+            .expect("generate task")
+            .dependencies
+            .push(String::from("link"));
+        assert!(TaskGraph::try_from(transport.clone()).is_err());
+
+        transport
+            .tasks
+            .get_mut("generate")
+            .expect("generate task")
+            .dependencies = vec![String::from("unknown")];
+        assert!(TaskGraph::try_from(transport).is_err());
+    }
+}
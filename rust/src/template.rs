@@ -0,0 +1,410 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+use crate::transport::EnvironmentInheritance;
+use crate::transport::EnvironmentVariables;
+use crate::transport::Task;
+use anyhow::Context as _;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Placeholder replaced by the working directory in which a task is loaded.
+pub const WORKING_DIRECTORY_PLACEHOLDER: &str = "workdir";
+
+/// Placeholder replaced by the directory against which a task's relative input paths are
+/// resolved: its own working directory, when it declares one, or else the working directory in
+/// which it is loaded.
+pub const INPUT_DIRECTORY_PLACEHOLDER: &str = "inputdir";
+
+/// Prefix of placeholders replaced by the value of an environment variable on the loading host.
+pub const ENVIRONMENT_VARIABLE_PLACEHOLDER_PREFIX: &str = "env:";
+
+/// Values substituted into templated task fields when a task is loaded.
+///
+/// Templates may contain `${workdir}`, `${inputdir}`, `${env:NAME}`, and `$$` (a literal `$`).
+/// Expansion happens before a task is converted to its canonical form, so the expanded values,
+/// rather than the templates, contribute to the task's cache key.
+///
+/// Host environment variables are available to `${env:NAME}` and to inheritance only when they
+/// are allowed; see `with_allowed_environment_variables`.
+#[derive(Clone, Debug)]
+pub struct TemplateContext {
+    working_directory: PathBuf,
+    environment_variables: HashMap<String, String>,
+    allowed_environment_variables: Option<HashSet<String>>,
+}
+
+impl TemplateContext {
+    pub fn new<P: AsRef<Path>, I: IntoIterator<Item = (String, String)>>(
+        working_directory: P,
+        environment_variables: I,
+    ) -> Self {
+        Self {
+            working_directory: working_directory.as_ref().to_path_buf(),
+            environment_variables: environment_variables.into_iter().collect(),
+            allowed_environment_variables: None,
+        }
+    }
+
+    /// Restricts the host environment variables that templates and inheritance may read to
+    /// `names`, so that tasks cannot depend on (or leak) arbitrary host state. Default: all host
+    /// environment variables are allowed.
+    pub fn with_allowed_environment_variables<I: IntoIterator<Item = String>>(
+        mut self,
+        names: I,
+    ) -> Self {
+        self.allowed_environment_variables = Some(names.into_iter().collect());
+        self
+    }
+
+    /// The working directory in which tasks are loaded, substituted for `${workdir}`.
+    pub fn working_directory(&self) -> &Path {
+        &self.working_directory
+    }
+
+    /// Creates a context from the environment of the current process.
+    pub fn from_host<P: AsRef<Path>>(working_directory: P) -> Self {
+        Self::new(working_directory, std::env::vars())
+    }
+
+    /// Expands all placeholders in `template`. `${inputdir}` is replaced by the working directory.
+    pub fn expand(&self, template: &str) -> anyhow::Result<String> {
+        self.expand_in_directory(template, &self.working_directory)
+    }
+
+    fn expand_in_directory(
+        &self,
+        template: &str,
+        input_directory: &Path,
+    ) -> anyhow::Result<String> {
+        let mut expanded = String::with_capacity(template.len());
+        let mut remaining = template;
+        while let Some(dollar_index) = remaining.find('$') {
+            expanded.push_str(&remaining[..dollar_index]);
+            let after_dollar = &remaining[dollar_index + 1..];
+            if let Some(after_escape) = after_dollar.strip_prefix('$') {
+                expanded.push('$');
+                remaining = after_escape;
+            } else if let Some(placeholder_start) = after_dollar.strip_prefix('{') {
+                let placeholder_end = placeholder_start.find('}').ok_or_else(|| {
+                    anyhow::anyhow!("unterminated placeholder in template, {:?}", template)
+                })?;
+                let placeholder = &placeholder_start[..placeholder_end];
+                expanded.push_str(
+                    &self
+                        .expand_placeholder(placeholder, input_directory)
+                        .map_err(|error| {
+                            error.context(format!("expanding template, {:?}", template))
+                        })?,
+                );
+                remaining = &placeholder_start[placeholder_end + 1..];
+            } else {
+                expanded.push('$');
+                remaining = after_dollar;
+            }
+        }
+        expanded.push_str(remaining);
+        Ok(expanded)
+    }
+
+    /// Expands templates in `task` environment variable values and arguments, and resolves
+    /// inherited environment variables. `${inputdir}` is replaced by the task's working directory,
+    /// relative to this context's working directory.
+    pub fn expand_task(&self, task: Task) -> anyhow::Result<Task> {
+        let input_directory = match task.working_directory.as_ref() {
+            Some(task_working_directory) => self.working_directory.join(task_working_directory),
+            None => self.working_directory.clone(),
+        };
+        self.expand_task_in_directory(task, &input_directory)
+    }
+
+    /// Like `expand_task`, with `${inputdir}` replaced by `input_directory`. Used for tasks whose
+    /// working directory is not relative to this context's working directory, such as tasks loaded
+    /// from task files in other directories.
+    pub fn expand_task_in_directory(
+        &self,
+        mut task: Task,
+        input_directory: &Path,
+    ) -> anyhow::Result<Task> {
+        for (name, value) in task.environment_variables.environment_variables.iter_mut() {
+            *value = self
+                .expand_in_directory(value, input_directory)
+                .map_err(|error| error.context(format!("in environment variable, {:?}", name)))?;
+        }
+        task.environment_variables =
+            self.resolve_environment_variables(task.environment_variables)?;
+        for (index, argument) in task.arguments.arguments.iter_mut().enumerate() {
+            *argument = self
+                .expand_in_directory(argument, input_directory)
+                .map_err(|error| error.context(format!("in argument {}", index)))?;
+        }
+        Ok(task)
+    }
+
+    /// Adds the variables of this context's environment selected by `environment_variables.inherit`
+    /// to `environment_variables`. Explicitly listed variables take precedence over inherited ones,
+    /// and inherited values are copied verbatim, without expanding placeholders.
+    pub fn resolve_environment_variables(
+        &self,
+        mut environment_variables: EnvironmentVariables,
+    ) -> anyhow::Result<EnvironmentVariables> {
+        let inherit = std::mem::take(&mut environment_variables.inherit);
+        let is_inherited: Box<dyn Fn(&str) -> bool> = match &inherit {
+            EnvironmentInheritance::None => return Ok(environment_variables),
+            EnvironmentInheritance::Names(names) => {
+                Box::new(move |name| names.iter().any(|inherited| inherited == name))
+            }
+            EnvironmentInheritance::Globs(globs) => {
+                let patterns = globs
+                    .iter()
+                    .map(|glob| {
+                        glob::Pattern::new(glob).with_context(|| {
+                            format!("parsing inherited environment variable glob, {:?}", glob)
+                        })
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                Box::new(move |name| patterns.iter().any(|pattern| pattern.matches(name)))
+            }
+        };
+
+        let explicit_names: Vec<String> = environment_variables
+            .environment_variables
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect();
+        let inherited: BTreeMap<&String, &String> = self
+            .environment_variables
+            .iter()
+            .filter(|(name, _)| {
+                !explicit_names.contains(name) && self.is_allowed(name) && is_inherited(name)
+            })
+            .collect();
+        environment_variables.environment_variables.extend(
+            inherited
+                .into_iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+        Ok(environment_variables)
+    }
+
+    fn is_allowed(&self, name: &str) -> bool {
+        self.allowed_environment_variables
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(name))
+    }
+
+    fn expand_placeholder(
+        &self,
+        placeholder: &str,
+        input_directory: &Path,
+    ) -> anyhow::Result<String> {
+        let directory = match placeholder {
+            WORKING_DIRECTORY_PLACEHOLDER => Some(("working", self.working_directory.as_path())),
+            INPUT_DIRECTORY_PLACEHOLDER => Some(("input", input_directory)),
+            _ => None,
+        };
+        if let Some((kind, directory)) = directory {
+            return directory.to_str().map(String::from).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{} directory, {:?}, cannot be encoded as a string",
+                    kind,
+                    directory
+                )
+            });
+        }
+        if let Some(name) = placeholder.strip_prefix(ENVIRONMENT_VARIABLE_PLACEHOLDER_PREFIX) {
+            if !self.is_allowed(name) {
+                anyhow::bail!(
+                    "environment variable, {:?}, is not allowed to be read from the host",
+                    name
+                );
+            }
+            return self
+                .environment_variables
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("environment variable, {:?}, is not set", name));
+        }
+        anyhow::bail!("unknown placeholder, {:?}", placeholder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TemplateContext;
+    use crate::canonical::EnvironmentVariables as CanonicalEnvironmentVariables;
+    use crate::transport::Arguments;
+    use crate::transport::EnvironmentInheritance;
+    use crate::transport::EnvironmentVariables;
+    use crate::transport::ExecutionStrategy;
+    use crate::transport::Inputs;
+    use crate::transport::Outputs;
+    use crate::transport::Program;
+    use crate::transport::Task;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_expand() {
+        let context = TemplateContext::new(
+            "/work",
+            [(String::from("HOME"), String::from("/home/user"))],
+        );
+        assert_eq!(
+            "/home/user/.config:/work/out",
+            context
+                .expand("${env:HOME}/.config:${workdir}/out")
+                .expect("expand")
+        );
+        assert_eq!(
+            "${workdir} costs $5",
+            context.expand("$${workdir} costs $5").expect("expand")
+        );
+        assert_eq!("/work", context.expand("${inputdir}").expect("expand"));
+        assert!(context.expand("${env:UNSET}").is_err());
+        assert!(context.expand("${unknown}").is_err());
+        assert!(context.expand("${workdir").is_err());
+    }
+
+    #[test]
+    fn test_resolve_environment_variables() {
+        let context = TemplateContext::new(
+            "/work",
+            [
+                (String::from("PATH"), String::from("/usr/bin")),
+                (String::from("HOME"), String::from("/home/${user}")),
+                (String::from("LC_ALL"), String::from("C")),
+                (String::from("LC_TIME"), String::from("en_US")),
+                (String::from("SECRET"), String::from("hunter2")),
+            ],
+        );
+        let resolve = |inherit| {
+            context
+                .resolve_environment_variables(EnvironmentVariables {
+                    environment_variables: vec![(String::from("PATH"), String::from("/bin"))],
+                    inherit,
+                })
+                .expect("resolve environment variables")
+        };
+
+        let resolved = resolve(EnvironmentInheritance::Names(vec![
+            String::from("PATH"),
+            String::from("HOME"),
+            String::from("UNSET"),
+        ]));
+        assert_eq!(EnvironmentInheritance::None, resolved.inherit);
+        assert_eq!(
+            vec![
+                (String::from("PATH"), String::from("/bin")),
+                (String::from("HOME"), String::from("/home/${user}")),
+            ],
+            resolved.environment_variables
+        );
+
+        let resolved = resolve(EnvironmentInheritance::Globs(vec![String::from("LC_*")]));
+        assert_eq!(
+            vec![
+                (String::from("PATH"), String::from("/bin")),
+                (String::from("LC_ALL"), String::from("C")),
+                (String::from("LC_TIME"), String::from("en_US")),
+            ],
+            resolved.environment_variables
+        );
+
+        assert_eq!(
+            vec![(String::from("PATH"), String::from("/bin"))],
+            resolve(EnvironmentInheritance::None).environment_variables
+        );
+
+        // Canonical environment variables require inheritance to be resolved first.
+        let unresolved = EnvironmentVariables {
+            environment_variables: vec![],
+            inherit: EnvironmentInheritance::Names(vec![String::from("PATH")]),
+        };
+        assert!(CanonicalEnvironmentVariables::try_from_config(unresolved.clone()).is_err());
+        assert!(CanonicalEnvironmentVariables::try_from_config(
+            context
+                .resolve_environment_variables(unresolved)
+                .expect("resolve environment variables")
+        )
+        .is_ok());
+        assert!(context
+            .resolve_environment_variables(EnvironmentVariables {
+                environment_variables: vec![],
+                inherit: EnvironmentInheritance::Globs(vec![String::from("[")]),
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_allowed_environment_variables() {
+        let context = TemplateContext::new(
+            "/work",
+            [
+                (String::from("PATH"), String::from("/usr/bin")),
+                (String::from("SECRET"), String::from("hunter2")),
+            ],
+        )
+        .with_allowed_environment_variables([String::from("PATH")]);
+        assert_eq!("/usr/bin", context.expand("${env:PATH}").expect("expand"));
+        assert!(context.expand("${env:SECRET}").is_err());
+
+        // Inheritance silently skips variables that are not allowed.
+        assert_eq!(
+            vec![(String::from("PATH"), String::from("/usr/bin"))],
+            context
+                .resolve_environment_variables(EnvironmentVariables {
+                    environment_variables: vec![],
+                    inherit: EnvironmentInheritance::Globs(vec![String::from("*")]),
+                })
+                .expect("resolve environment variables")
+                .environment_variables
+        );
+    }
+
+    #[test]
+    fn test_expand_task() {
+        let context = TemplateContext::new(
+            "/work",
+            [(String::from("HOME"), String::from("/home/user"))],
+        );
+        let task = Task {
+            execution_strategy: ExecutionStrategy::Simple,
+            environment_variables: EnvironmentVariables::from(vec![(
+                String::from("CONFIG"),
+                String::from("${env:HOME}/.config"),
+            )]),
+            program: Program {
+                program: PathBuf::from("${workdir}/program"),
+            },
+            arguments: Arguments::from_iter(["--out=${workdir}/out", "--in=${inputdir}/in"]),
+            stdin: None,
+            sandbox: None,
+            resource_limits: None,
+            working_directory: Some(PathBuf::from("sub")),
+            failure_caching: None,
+            label: None,
+            tags: vec![],
+            inputs: Inputs::default(),
+            outputs: Outputs::empty(),
+        };
+
+        let task = context.expand_task(task).expect("expand task");
+        assert_eq!(
+            vec![(String::from("CONFIG"), String::from("/home/user/.config"))],
+            task.environment_variables.environment_variables
+        );
+        assert_eq!(
+            vec![
+                String::from("--out=/work/out"),
+                String::from("--in=/work/sub/in")
+            ],
+            task.arguments.arguments
+        );
+        // Program paths are not templated.
+        assert_eq!(PathBuf::from("${workdir}/program"), task.program.program);
+    }
+}
@@ -0,0 +1,335 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Consumes the file access traces written by `crate::runner::TracedRunner`.
+//!
+//! fsatrace writes one access per line, `op|path`, where `op` is `r` (read), `w` (write), `t`
+//! (touch), `d` (delete), `q` (query), or `m` (move, as `m|destination|source`). Traced accesses are
+//! compared against the files a task declares, so that undeclared inputs and outputs are either
+//! reported as errors or added to the task's cached manifests.
+
+use crate::canonical::FileIdentitiesManifest;
+use crate::canonical::FilesManifest;
+use crate::canonical::TaskInputs;
+use crate::canonical::TaskOutputs;
+use crate::fs::Filesystem as FilesystemApi;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use anyhow::Context as _;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// What to do about traced accesses to files that a task does not declare.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TraceMode {
+    /// Fail the task.
+    Strict,
+    /// Add the files to the task's cached input and output manifests.
+    #[default]
+    Augment,
+}
+
+/// Configures an executor to consume the trace written by a `crate::runner::TracedRunner` after
+/// each task it runs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceValidation {
+    /// Trace file written by the runner: either absolute, or relative to the task's working
+    /// directory.
+    pub trace_path: PathBuf,
+    pub mode: TraceMode,
+}
+
+/// Files that a traced task read before writing them, and files that it wrote and did not delete.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FileAccesses {
+    reads: BTreeSet<PathBuf>,
+    writes: BTreeSet<PathBuf>,
+}
+
+impl FileAccesses {
+    /// Parses fsatrace output. Queries are ignored, and reads of files that the task had already
+    /// written are not reads of inputs.
+    pub fn parse(trace: &str) -> anyhow::Result<Self> {
+        let mut accesses = Self::default();
+        for (line_index, line) in trace.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split('|');
+            let operation = fields.next().unwrap_or_default();
+            let path = fields.next().map(PathBuf::from).ok_or_else(|| {
+                anyhow::anyhow!("trace line {} has no path: {:?}", line_index + 1, line)
+            })?;
+            match operation {
+                "r" => {
+                    if !accesses.writes.contains(&path) {
+                        accesses.reads.insert(path);
+                    }
+                }
+                "w" | "t" => {
+                    accesses.writes.insert(path);
+                }
+                "d" => {
+                    accesses.writes.remove(&path);
+                }
+                "m" => {
+                    let source = fields.next().map(PathBuf::from).ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "trace line {} has no move source: {:?}",
+                            line_index + 1,
+                            line
+                        )
+                    })?;
+                    accesses.writes.remove(&source);
+                    accesses.writes.insert(path);
+                }
+                "q" => {}
+                _ => anyhow::bail!(
+                    "trace line {} has unknown operation {:?}: {:?}",
+                    line_index + 1,
+                    operation,
+                    line
+                ),
+            }
+        }
+        Ok(accesses)
+    }
+
+    pub fn read_trace_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let trace = std::fs::read_to_string(path)
+            .with_context(|| format!("reading trace file {:?}", path))?;
+        Self::parse(&trace).with_context(|| format!("parsing trace file {:?}", path))
+    }
+
+    /// Restricts accesses to files under `directory`, with paths relative to it. Accesses outside
+    /// the task's working directory (e.g., to system libraries) are not expected to be declared.
+    pub fn relative_to<P: AsRef<Path>>(self, directory: P) -> Self {
+        let directory = directory.as_ref();
+        let relativize = |paths: BTreeSet<PathBuf>| {
+            paths
+                .into_iter()
+                .filter_map(|path| path.strip_prefix(directory).ok().map(Path::to_path_buf))
+                .filter(|path| !path.as_os_str().is_empty())
+                .collect()
+        };
+        Self {
+            reads: relativize(self.reads),
+            writes: relativize(self.writes),
+        }
+    }
+
+    pub fn reads(&self) -> impl Iterator<Item = &PathBuf> {
+        self.reads.iter()
+    }
+
+    pub fn writes(&self) -> impl Iterator<Item = &PathBuf> {
+        self.writes.iter()
+    }
+
+    /// Finds accesses to files that neither `inputs` nor `outputs` declares.
+    pub fn undeclared<IS: IdentitySchemeApi>(
+        &self,
+        inputs: &TaskInputs<IS>,
+        outputs: &TaskOutputs<IS>,
+    ) -> UndeclaredAccesses {
+        let declared_inputs: BTreeSet<&PathBuf> = outputs
+            .input_files_with_program()
+            .map(|(path, _)| path)
+            .chain(inputs.staged_inputs().map(|(_, staged_path)| staged_path))
+            .collect();
+        let declared_outputs: BTreeSet<&PathBuf> =
+            outputs.output_files().map(|(path, _)| path).collect();
+        UndeclaredAccesses {
+            reads: self
+                .reads
+                .iter()
+                .filter(|path| !declared_inputs.contains(path) && !declared_outputs.contains(path))
+                .cloned()
+                .collect(),
+            writes: self
+                .writes
+                .iter()
+                .filter(|path| !declared_outputs.contains(path))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct UndeclaredAccesses {
+    pub reads: Vec<PathBuf>,
+    pub writes: Vec<PathBuf>,
+}
+
+impl UndeclaredAccesses {
+    pub fn is_empty(&self) -> bool {
+        self.reads.is_empty() && self.writes.is_empty()
+    }
+}
+
+/// Checks `accesses` against the files declared by `inputs` and `outputs`, according to `mode`.
+/// Returns `outputs`, with any undeclared files that still exist in `working_directory` added to
+/// its manifests in `TraceMode::Augment`.
+pub fn apply_trace<FS: FilesystemApi, IS: IdentitySchemeApi>(
+    working_directory: &mut FS,
+    mode: TraceMode,
+    accesses: &FileAccesses,
+    inputs: &TaskInputs<IS>,
+    outputs: TaskOutputs<IS>,
+) -> anyhow::Result<TaskOutputs<IS>> {
+    let undeclared = accesses.undeclared(inputs, &outputs);
+    if undeclared.is_empty() {
+        return Ok(outputs);
+    }
+    match mode {
+        TraceMode::Strict => anyhow::bail!(
+            "task accessed undeclared files: reads {:?}, writes {:?}",
+            undeclared.reads,
+            undeclared.writes
+        ),
+        TraceMode::Augment => {
+            tracing::debug!(
+                "adding undeclared files to task manifests: reads {:?}, writes {:?}",
+                undeclared.reads,
+                undeclared.writes
+            );
+            let input_files_with_program = augment_manifest(
+                working_directory,
+                outputs.input_files_with_program().cloned().collect(),
+                undeclared.reads,
+            );
+            let output_files = augment_manifest(
+                working_directory,
+                outputs.output_files().cloned().collect(),
+                undeclared.writes,
+            );
+            Ok(TaskOutputs::new(input_files_with_program, output_files))
+        }
+    }
+}
+
+/// Adds the files in `discovered` that exist in `working_directory` to `declared`.
+fn augment_manifest<FS: FilesystemApi, IS: IdentitySchemeApi>(
+    working_directory: &mut FS,
+    declared: Vec<(PathBuf, Option<IS::Identity>)>,
+    discovered: Vec<PathBuf>,
+) -> FileIdentitiesManifest<IS> {
+    let discovered = FilesManifest::new(
+        discovered
+            .into_iter()
+            .filter(|path| working_directory.metadata(path).is_ok()),
+    )
+    .into_identified::<IS, FS>(working_directory);
+    let identities: BTreeMap<PathBuf, Option<IS::Identity>> = declared
+        .into_iter()
+        .chain(discovered.identities().cloned())
+        .collect();
+    FileIdentitiesManifest::new(identities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::apply_trace;
+    use super::FileAccesses;
+    use super::TraceMode;
+    use crate::canonical::FilesManifest;
+    use crate::canonical::TaskInputsBuilder;
+    use crate::canonical::TaskOutputs;
+    use crate::fs::HostFilesystem;
+    use crate::identity::IdentityScheme as _;
+    use crate::transport::ContentSha256;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_file_accesses() {
+        let accesses = FileAccesses::parse(
+            "r|/work/in.txt\n\
+             q|/work\n\
+             w|/work/tmp.txt\n\
+             r|/work/tmp.txt\n\
+             d|/work/tmp.txt\n\
+             w|/work/partial.txt\n\
+             m|/work/out.txt|/work/partial.txt\n\
+             r|/usr/lib/libc.so\n",
+        )
+        .expect("parse trace")
+        .relative_to("/work");
+        assert_eq!(
+            vec![&PathBuf::from("in.txt")],
+            accesses.reads().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![&PathBuf::from("out.txt")],
+            accesses.writes().collect::<Vec<_>>()
+        );
+
+        assert!(FileAccesses::parse("x|/work/in.txt").is_err());
+        assert!(FileAccesses::parse("m|/work/out.txt").is_err());
+    }
+
+    #[test]
+    fn test_apply_trace() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let working_directory = temporary_directory.path().to_path_buf();
+        for name in ["declared.txt", "undeclared.txt", "out.txt", "extra.txt"] {
+            std::fs::write(working_directory.join(name), name).expect("write file");
+        }
+        let mut filesystem =
+            HostFilesystem::try_new(working_directory.clone()).expect("filesystem");
+        let inputs = TaskInputsBuilder::<ContentSha256>::new()
+            .program("/bin/sh")
+            .identify_input_files(&mut filesystem, FilesManifest::new(["declared.txt"]))
+            .build()
+            .expect("task inputs");
+        let outputs = TaskOutputs::new(
+            FilesManifest::new(["declared.txt"]).into_identified(&mut filesystem),
+            FilesManifest::new(["out.txt"]).into_identified(&mut filesystem),
+        );
+        let accesses = FileAccesses::parse(&format!(
+            "r|{0}/declared.txt\nr|{0}/undeclared.txt\nw|{0}/out.txt\nw|{0}/extra.txt\n",
+            working_directory.display()
+        ))
+        .expect("parse trace")
+        .relative_to(&working_directory);
+
+        assert!(apply_trace(
+            &mut filesystem,
+            TraceMode::Strict,
+            &accesses,
+            &inputs,
+            outputs.clone()
+        )
+        .is_err());
+
+        let augmented = apply_trace(
+            &mut filesystem,
+            TraceMode::Augment,
+            &accesses,
+            &inputs,
+            outputs,
+        )
+        .expect("augment outputs");
+        let identity = |name: &str| ContentSha256::identify_content(name.as_bytes()).ok();
+        assert_eq!(
+            vec![
+                (PathBuf::from("declared.txt"), identity("declared.txt")),
+                (PathBuf::from("undeclared.txt"), identity("undeclared.txt")),
+            ],
+            augmented
+                .input_files_with_program()
+                .cloned()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                (PathBuf::from("extra.txt"), identity("extra.txt")),
+                (PathBuf::from("out.txt"), identity("out.txt")),
+            ],
+            augmented.output_files().cloned().collect::<Vec<_>>()
+        );
+    }
+}
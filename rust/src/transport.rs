@@ -2,8 +2,12 @@
 // Use of this source code is governed by a Apache-style license that can be
 // found in the LICENSE file.
 
+use crate::fs::FileType;
 use crate::identity::Identity as IdentityBound;
 use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::multihash::Multihash;
+use crate::multihash::MultihashIdentity;
+use crate::schema::FormatVersion;
 use anyhow::Context as _;
 use serde::de::DeserializeOwned;
 use serde::de::Deserializer;
@@ -11,6 +15,7 @@ use serde::de::Visitor;
 use serde::ser::Serializer;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::path::PathBuf;
@@ -30,22 +35,137 @@ pub struct Task {
     pub program: Program,
     #[serde(flatten)]
     pub arguments: Arguments,
+    /// Standard input for the task. Default: no standard input.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<Stdin>,
+    /// Isolation applied to the task by `crate::runner::IsolatedRunner`, under which the default
+    /// `crate::runner::PolicyRunner` runs every task that sets it; tasks fail where bubblewrap is
+    /// unavailable. Default: the task is not isolated, unless its executor's runner isolates every
+    /// task.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<Sandbox>,
+    /// Limits on the host resources that the task may use, applied by
+    /// `crate::runner::LimitedRunner`, under which the default `crate::runner::PolicyRunner` runs
+    /// every task that sets them. Default: the runner's default limits, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource_limits: Option<ResourceLimits>,
+    /// Directory in which the task runs, and against which its relative paths are resolved: either
+    /// absolute, or relative to the directory containing the task file. Tasks that are not loaded
+    /// from their own task files (e.g., the tasks of a `TaskGraph`) resolve it against the
+    /// executor's working directory. Default: the directory from which the task is executed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_directory: Option<PathBuf>,
+    /// Caching of the task's failed executions. Default: failures are not cached, and a failed task
+    /// is executed again every time it is run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_caching: Option<FailureCaching>,
+    /// Human-readable name for the task (e.g., `protoc`), recorded in cached metadata.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Free-form tags (e.g., `release`), recorded in cached metadata.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
     pub inputs: Inputs,
     pub outputs: Outputs,
 }
 
+/// Tasks keyed by label, with the dependencies between them. Graph executors consume this format,
+/// and build-file importers produce it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TaskGraph {
+    pub format_version: FormatVersion,
+    pub tasks: BTreeMap<String, TaskGraphNode>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TaskGraphNode {
+    #[serde(flatten)]
+    pub task: Task,
+    /// Labels of tasks that must complete before this task runs, in addition to those implied by
+    /// `wired_inputs`. May also be written as `depends_on`.
+    #[serde(default, alias = "depends_on", skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<String>,
+    /// Outputs of other tasks that this task consumes as inputs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wired_inputs: Vec<OutputWiring>,
+}
+
+/// Wires an output of one task to an input of another.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputWiring {
+    /// Label of the task that produces the output.
+    pub from_task: String,
+    /// Path of the output, as produced by `from_task`.
+    pub output: PathBuf,
+    /// Path at which the consuming task expects to find the output. Default: `output`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<PathBuf>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecutionStrategy {
     Simple,
-    ForEachInput { inputs_filter: Inputs },
+    ForEachInput { inputs_filter: Box<Inputs> },
+}
+
+/// Source of a task's standard input, for filter-style tasks that read from stdin.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stdin {
+    /// Read standard input from a file, which must also be an input of the task, so that its
+    /// identity contributes to the task's cache key.
+    Path(PathBuf),
+    /// Write this content to standard input. The content is part of the task description, and so
+    /// of its cache key.
+    Content(String),
+}
+
+/// Isolation of a task from the host, beyond the files it declares.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sandbox {
+    /// Allow the task to access the network. Default: The task runs in its own network namespace,
+    /// with no interfaces other than loopback.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub network: bool,
+    /// Absolute paths, in addition to system directories, that the task may read (e.g., a
+    /// toolchain installed under `/opt`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub read_only_paths: Vec<PathBuf>,
+}
+
+/// Limits on the host resources that a task may use, so that a runaway task cannot take down the
+/// host. Each limit applies to every process of the task; unset limits are not applied.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceLimits {
+    /// Maximum size, in bytes, of each process's virtual memory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_memory_bytes: Option<u64>,
+    /// Maximum CPU time, in seconds, of each process, past which it is killed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cpu_seconds: Option<u64>,
+    /// Maximum number of files that each process may have open.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_open_files: Option<u64>,
+}
+
+/// Caching of failed executions of a task. Within `ttl_seconds` of a failed execution, running the
+/// task with the same inputs fails again without executing it. Suited to tasks that fail
+/// deterministically, such as compiling a source file with errors.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FailureCaching {
+    pub ttl_seconds: u64,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum InputsFilter {
     All,
-    Matches(Inputs),
+    Matches(Box<Inputs>),
 }
 
 impl Default for ExecutionStrategy {
@@ -54,58 +174,278 @@ impl Default for ExecutionStrategy {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Inputs {
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub include_files: Vec<PathBuf>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub exclude_files: Vec<PathBuf>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub include_globs: Vec<String>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub exclude_globs: Vec<String>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub inter_file_references: Vec<InterFileReferences>,
+    /// Makefile-style dependency files (e.g., from `gcc -MD`) whose prerequisites are added to the
+    /// inputs. Dependency files that do not exist (e.g., before a first build) are ignored.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depfiles: Vec<PathBuf>,
+    /// Maximum length of a chain of inter-file references from an explicitly included file.
+    /// Default: `crate::canonical::DEFAULT_MAX_REFERENCE_DEPTH`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_reference_depth: Option<usize>,
+    /// Fail, rather than log a warning, when inter-file references form a cycle.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fail_on_reference_cycles: bool,
+    /// Maximum number of threads used to scan file contents for inter-file references.
+    /// Default: The available parallelism of the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_scan_threads: Option<usize>,
+    /// Additional include/exclude patterns, each group resolved against its own base directory.
+    /// Files matched by groups are subject to inter-file references scanning, but not to the
+    /// top-level exclusions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<InputsGroup>,
+    /// Directories whose files are included recursively, subject to each directory's filters.
+    /// Included files are subject to the top-level exclusions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_directories: Vec<InputsDirectory>,
+    /// Identities that input files are expected to have, for inputs that must not drift between
+    /// machines (e.g., toolchain binaries). Identities are written in the string form of the
+    /// task's identity scheme (e.g., a hex-encoded digest). Each path must also be matched by the
+    /// inputs description.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub expected_identities: BTreeMap<PathBuf, String>,
+}
+
+/// A directory whose files are included recursively.
+///
+/// Filters are glob patterns matched against paths relative to `directory`, where `*` does not
+/// match `/` (use `**` to match across directories). Subdirectories matched by an exclude pattern
+/// are not traversed. Symbolic links to directories are not followed.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputsDirectory {
+    pub directory: PathBuf,
+    /// Patterns that files must match to be included. Default: All files are included.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_globs: Vec<String>,
+    /// Patterns for files and subdirectories to exclude.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_globs: Vec<String>,
+}
+
+/// Include/exclude patterns resolved against a common base directory, for tasks whose inputs span
+/// several roots (e.g., a source tree, a generated directory, and a toolchain directory).
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputsGroup {
+    /// Directory against which this group's files and globs are resolved. Matched files are
+    /// identified by their paths relative to the working directory. Default: The working directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relative_to: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_files: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_files: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_globs: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_globs: Vec<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Outputs {
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub include_files: Vec<PathBuf>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Output files that the task may or may not produce (e.g., an optional map file). Missing
+    /// optional outputs are omitted from the task's outputs; any other missing output is an error.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub optional_files: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub include_match_transforms: Vec<Vec<MatchTransform>>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// Globs evaluated against the working directory after the task has run, for outputs whose
+    /// names cannot be derived from inputs (e.g., content-hashed file names).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_globs: Vec<String>,
+    /// Directories produced by the task, all of whose files are outputs. Files are collected
+    /// recursively after the task has run, subject to each directory's filters.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_directories: Vec<OutputsDirectory>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub exclude_matches: Vec<Match>,
+    /// Outputs larger than this are not collected. Default: No size limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size_bytes: Option<u64>,
+    /// Kinds of filesystem entries that are not collected, even when named or matched.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_file_types: Vec<FileType>,
+    /// Path, relative to the working directory, to which the task's stdout is written after it
+    /// runs, so that it is collected and identified like any other output. Default: Stdout is not an
+    /// output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout_file: Option<PathBuf>,
+    /// As `stdout_file`, but for the task's stderr.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr_file: Option<PathBuf>,
+    /// How strictly the outputs a task actually produces are checked against this description.
+    #[serde(default, skip_serializing_if = "OutputsVerification::is_default")]
+    pub verification: OutputsVerification,
+}
+
+/// Policies for checking the outputs a task produces against its outputs description.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputsVerification {
+    /// Every described output, including optional ones, must be produced, and outputs that were
+    /// not described must not be reported.
+    Strict,
+    /// As `Strict`, except that undescribed outputs reported by the executor (e.g., by a remote
+    /// execution service) are collected.
+    AllowExtra,
+    /// As `Strict`, except that optional outputs may be missing.
+    #[default]
+    AllowMissingOptional,
+}
+
+impl OutputsVerification {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether outputs in the description's `optional_files` may be missing.
+    pub fn allows_missing_optional(&self) -> bool {
+        *self == Self::AllowMissingOptional
+    }
+
+    /// Whether outputs that the description does not name may be collected.
+    pub fn allows_extra(&self) -> bool {
+        *self == Self::AllowExtra
+    }
 }
 
 impl Outputs {
     pub fn empty() -> Self {
         Self {
             include_files: vec![],
+            optional_files: vec![],
             include_match_transforms: vec![],
+            include_globs: vec![],
+            include_directories: vec![],
             exclude_matches: vec![],
+            max_file_size_bytes: None,
+            exclude_file_types: vec![],
+            stdout_file: None,
+            stderr_file: None,
+            verification: OutputsVerification::AllowMissingOptional,
         }
     }
 }
 
+/// A directory produced by a task whose files are outputs, collected recursively.
+///
+/// Exclude patterns are matched against paths relative to `directory`, as for `InputsDirectory`.
+/// Subdirectories matched by an exclude pattern are not traversed.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputsDirectory {
+    pub directory: PathBuf,
+    /// Patterns for files and subdirectories to exclude.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_globs: Vec<String>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct InterFileReferences {
     /// Default: Use matched files from containing object.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub files_to_match: Option<Inputs>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub match_transforms: Vec<MatchTransform>,
+    /// Default: Do not scan for C/C++ `#include` directives.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub c_includes: Option<CIncludes>,
+    /// Default: Do not scan with a WebAssembly plugin.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scanner_plugin: Option<ScannerPlugin>,
     /// Default: Use working directory according to containing context.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub directories_to_search: Option<Vec<PathBuf>>,
+    /// Scan files that appear to be binary (i.e., contain NUL bytes near their start), which are
+    /// skipped by default. Invalid UTF-8 in scanned files is replaced rather than rejected.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub scan_binary_files: bool,
+}
+
+/// Scans matched files for C/C++ `#include` directives, resolving them the way a C preprocessor
+/// would. `InterFileReferences::directories_to_search`, when present, is searched after
+/// `include_directories`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CIncludes {
+    /// Directories searched for `#include "..."` after the including file's directory (like
+    /// `-iquote`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quote_directories: Vec<PathBuf>,
+    /// Directories searched for both `#include "..."` and `#include <...>` (like `-I`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_directories: Vec<PathBuf>,
+}
+
+/// Scans matched files with a sandboxed WebAssembly plugin that reports the paths each file
+/// references; see `crate::plugin` for the interface that plugins implement. Reported paths are
+/// resolved like those produced by match transforms.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScannerPlugin {
+    /// Path to the plugin's `.wasm` module, which is itself an input of the task.
+    pub module: PathBuf,
+    /// Default: `crate::plugin::DEFAULT_MAX_FUEL`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_fuel: Option<u64>,
+    /// Default: `crate::plugin::DEFAULT_MAX_MEMORY_BYTES`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_memory_bytes: Option<u64>,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct MatchTransform {
     pub match_regular_expression: String,
+    /// Replacement expressions applied to each match. These may refer to capture groups by index
+    /// (`$1`) or by name (`$name` or `${name}`); `$$` denotes a literal `$`.
     pub match_transform_expressions: Vec<String>,
+    #[serde(default, skip_serializing_if = "MatchFlags::is_default")]
+    pub flags: MatchFlags,
+}
+
+/// Options that change how a regular expression matches.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize,
+)]
+#[serde(deny_unknown_fields)]
+pub struct MatchFlags {
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub case_insensitive: bool,
+    /// `^` and `$` match at the beginning and end of lines rather than only of the whole text.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub multi_line: bool,
+    /// `.` matches `\n`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dot_matches_new_line: bool,
+}
+
+impl MatchFlags {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Match {
     pub match_regular_expression: String,
 }
@@ -116,12 +456,21 @@ pub struct Match {
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Arguments {
+    /// Arguments passed to the program. `{inputs}` and `{outputs}` are replaced by the task's
+    /// input and output file paths when it is executed; see
+    /// `canonical::TaskInputs::with_expanded_argument_placeholders`.
     pub arguments: Vec<String>,
+    /// Value passed to the program as `argv[0]` in place of the program path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub argv0: Option<String>,
 }
 
 impl Arguments {
     pub fn empty() -> Self {
-        Self { arguments: vec![] }
+        Self {
+            arguments: vec![],
+            argv0: None,
+        }
     }
 
     pub fn from_iter<
@@ -133,6 +482,7 @@ impl Arguments {
     ) -> Self {
         Self {
             arguments: arguments.into_iter().map(|s| s.into()).collect(),
+            argv0: None,
         }
     }
 }
@@ -140,20 +490,46 @@ impl Arguments {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct EnvironmentVariables {
     pub environment_variables: Vec<(String, String)>,
+    /// Variables to copy from the host environment when the task is loaded. Inherited values are
+    /// resolved before the task is converted to its canonical form, so they contribute to the
+    /// task's cache key like any other environment variable.
+    #[serde(default, skip_serializing_if = "EnvironmentInheritance::is_none")]
+    pub inherit: EnvironmentInheritance,
 }
 
 impl EnvironmentVariables {
     pub fn empty() -> Self {
         Self {
             environment_variables: vec![],
+            inherit: EnvironmentInheritance::None,
         }
     }
 }
 
+/// Selects variables of the host environment that a task inherits.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvironmentInheritance {
+    /// Inherit nothing from the host environment.
+    #[default]
+    None,
+    /// Inherit variables with exactly these names, if they are set.
+    Names(Vec<String>),
+    /// Inherit variables whose names match any of these glob patterns (e.g., `LC_*`).
+    Globs(Vec<String>),
+}
+
+impl EnvironmentInheritance {
+    pub fn is_none(&self) -> bool {
+        *self == Self::None
+    }
+}
+
 impl From<Vec<(String, String)>> for EnvironmentVariables {
     fn from(environment_variables: Vec<(String, String)>) -> Self {
         Self {
             environment_variables,
+            inherit: EnvironmentInheritance::None,
         }
     }
 }
@@ -170,10 +546,25 @@ impl From<PathBuf> for Program {
 }
 
 /// Enum that enumerates all available identity schemes.
-#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum IdentityScheme {
     ContentSha256,
+    ContentBlake2b256,
+}
+
+impl IdentityScheme {
+    pub const ALL: &'static [IdentityScheme] = &[
+        IdentityScheme::ContentSha256,
+        IdentityScheme::ContentBlake2b256,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ContentSha256 => "content_sha256",
+            Self::ContentBlake2b256 => "content_blake2b256",
+        }
+    }
 }
 
 impl Default for IdentityScheme {
@@ -182,6 +573,43 @@ impl Default for IdentityScheme {
     }
 }
 
+impl std::fmt::Display for IdentityScheme {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for IdentityScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(identity_scheme_str: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|identity_scheme| identity_scheme.as_str() == identity_scheme_str)
+            .copied()
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "unknown identity scheme, {:?}; expected one of {:?}",
+                    identity_scheme_str,
+                    Self::ALL.iter().map(Self::as_str).collect::<Vec<_>>()
+                )
+            })
+    }
+}
+
+/// How symbolic links are identified when computing file identities.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkIdentity {
+    /// Identify the contents of the file that the link resolves to. Links that do not resolve to a
+    /// file are dangling, and cannot be identified.
+    #[default]
+    ResolvedContents,
+    /// Identify the target path stored in the link, without resolving it. Dangling links are
+    /// identified like any other link.
+    TargetPath,
+}
+
 /// A `crate::identity::IdentityScheme` type for sha256-digest-of-contents.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct ContentSha256;
@@ -224,6 +652,27 @@ impl TryFrom<String> for Sha256 {
     }
 }
 
+impl MultihashIdentity for Sha256 {
+    const MULTIHASH_CODE: u64 = crate::multihash::SHA2_256;
+
+    fn digest(&self) -> &[u8] {
+        &self.0
+    }
+
+    fn try_from_digest(digest: &[u8]) -> anyhow::Result<Self> {
+        let sha256: [u8; 32] = digest
+            .try_into()
+            .map_err(anyhow::Error::from)
+            .with_context(|| {
+                format!(
+                    "expected sha256 digest of 32 bytes, but got {} bytes",
+                    digest.len()
+                )
+            })?;
+        Ok(Sha256(sha256))
+    }
+}
+
 impl ToString for Sha256 {
     fn to_string(&self) -> String {
         hex::encode(self.0)
@@ -245,14 +694,24 @@ impl<'de> Visitor<'de> for Sha256Visitor {
     type Value = Sha256;
 
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        formatter.write_str("a hex string containing a sha-256 hash")
+        formatter.write_str(
+            "a hex string or multibase-encoded multihash string containing a sha-256 hash",
+        )
     }
 
-    fn visit_str<E>(self, hex_str: &str) -> Result<Self::Value, E>
+    /// Accepts plain hex digests (64 characters) as well as multibase-encoded multihashes.
+    fn visit_str<E>(self, encoded_str: &str) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
     {
-        Sha256::try_from(hex_str).map_err(|err| E::custom(format!("{:?}", err)))
+        if encoded_str.len() == 64 {
+            Sha256::try_from(encoded_str)
+        } else {
+            encoded_str
+                .parse::<Multihash>()
+                .and_then(|multihash| Sha256::try_from_multihash(&multihash))
+        }
+        .map_err(|err| E::custom(format!("{:?}", err)))
     }
 }
 
@@ -265,6 +724,110 @@ impl<'de> Deserialize<'de> for Sha256 {
     }
 }
 
+/// A `crate::identity::IdentityScheme` type for blake2b-256-digest-of-contents.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ContentBlake2b256;
+
+/// A `crate::identity::IdentityScheme::Identity`-compatible type for blake2b-256 digests.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Blake2b256([u8; 32]);
+
+impl Blake2b256 {
+    pub fn new(hash: [u8; 32]) -> Self {
+        Self(hash)
+    }
+}
+
+impl TryFrom<&str> for Blake2b256 {
+    type Error = anyhow::Error;
+
+    fn try_from(hex_str: &str) -> Result<Self, Self::Error> {
+        let bytes_vec = hex::decode(hex_str)?;
+        Self::try_from_digest(&bytes_vec)
+    }
+}
+
+impl TryFrom<String> for Blake2b256 {
+    type Error = anyhow::Error;
+
+    fn try_from(hex_string: String) -> Result<Self, Self::Error> {
+        let hex_str: &str = &hex_string;
+        Blake2b256::try_from(hex_str)
+    }
+}
+
+impl MultihashIdentity for Blake2b256 {
+    const MULTIHASH_CODE: u64 = crate::multihash::BLAKE2B_256;
+
+    fn digest(&self) -> &[u8] {
+        &self.0
+    }
+
+    fn try_from_digest(digest: &[u8]) -> anyhow::Result<Self> {
+        let blake2b256: [u8; 32] = digest
+            .try_into()
+            .map_err(anyhow::Error::from)
+            .with_context(|| {
+                format!(
+                    "expected blake2b-256 digest of 32 bytes, but got {} bytes",
+                    digest.len()
+                )
+            })?;
+        Ok(Blake2b256(blake2b256))
+    }
+}
+
+impl std::fmt::Display for Blake2b256 {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str(&hex::encode(self.0))
+    }
+}
+
+impl Serialize for Blake2b256 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+struct Blake2b256Visitor;
+
+impl<'de> Visitor<'de> for Blake2b256Visitor {
+    type Value = Blake2b256;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str(
+            "a hex string or multibase-encoded multihash string containing a blake2b-256 hash",
+        )
+    }
+
+    /// Accepts plain hex digests (64 characters) as well as multibase-encoded multihashes.
+    fn visit_str<E>(self, encoded_str: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        if encoded_str.len() == 64 {
+            Blake2b256::try_from(encoded_str)
+        } else {
+            encoded_str
+                .parse::<Multihash>()
+                .and_then(|multihash| Blake2b256::try_from_multihash(&multihash))
+        }
+        .map_err(|err| E::custom(format!("{:?}", err)))
+    }
+}
+
+impl<'de> Deserialize<'de> for Blake2b256 {
+    fn deserialize<D>(deserializer: D) -> Result<Blake2b256, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(Blake2b256Visitor)
+    }
+}
+
 //
 // Output formats
 //
@@ -275,7 +838,59 @@ pub struct Listing<Identity>
 where
     Identity: IdentityBound,
 {
+    pub format_version: FormatVersion,
     pub entries: Vec<Identity>,
+    /// Times of each entry, in the same order as `entries`. Empty in listings that do not track
+    /// times, such as those written before times were tracked.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub times: Vec<ListingTimes>,
+}
+
+/// When a listing entry was inserted and last accessed (i.e., last hit), in nanoseconds since the
+/// Unix epoch. Times are unknown for entries written before times were tracked.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ListingTimes {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inserted_nanos: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_accessed_nanos: Option<i64>,
+}
+
+/// A file identities manifest stored as changes relative to a parent manifest blob, so that
+/// successive, nearly identical manifests need not each be stored in full. A delta with no parent is
+/// relative to the empty manifest, and so holds a full manifest in `added`. All entries are sorted
+/// by path.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(bound = "IS: IdentitySchemeApi")]
+pub struct FileIdentitiesManifestDelta<IS: IdentitySchemeApi> {
+    pub format_version: FormatVersion,
+    pub identity_scheme: IdentityScheme,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<IS::Identity>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub added: Vec<(PathBuf, Option<IS::Identity>)>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changed: Vec<(PathBuf, Option<IS::Identity>)>,
+    /// All symbolic link targets of the manifest, which replace those of the parent.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub symlinks: BTreeMap<PathBuf, PathBuf>,
+}
+
+/// First line of a line-delimited listing (see `crate::ndjson`); each subsequent line is one
+/// entry.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ListingHeader {
+    pub format_version: FormatVersion,
+}
+
+/// First line of a line-delimited file identities manifest (see `crate::ndjson`); each subsequent
+/// line is one `[path, identity]` pair, in sorted path order.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileIdentitiesManifestHeader {
+    pub format_version: FormatVersion,
+    pub identity_scheme: IdentityScheme,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -285,9 +900,90 @@ pub struct TaskSummary<IS: IdentitySchemeApi> {
     pub output: TaskOutputs<IS>,
 }
 
+/// Trace in the Chrome trace event format, as loaded by `chrome://tracing` and Perfetto.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChromeTrace {
+    pub trace_events: Vec<ChromeTraceEvent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_time_unit: Option<String>,
+}
+
+/// A complete event (phase `X`): a span with a start time and a duration, both in microseconds.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ChromeTraceEvent {
+    pub name: String,
+    pub cat: String,
+    pub ph: String,
+    pub ts: f64,
+    pub dur: f64,
+    pub pid: u64,
+    pub tid: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub args: BTreeMap<String, serde_json::Value>,
+}
+
+/// Request sent to a daemon (see `crate::daemon`), one JSON object per line.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum DaemonRequest {
+    Ping,
+    /// Identify files. Relative paths are resolved against the daemon's working directory.
+    IdentifyFiles {
+        paths: Vec<PathBuf>,
+    },
+    /// Resolve and identify the input files of the task described by a task file. A relative path
+    /// is resolved against the daemon's working directory.
+    IdentifyInputs {
+        task_file: PathBuf,
+    },
+    /// Look up the outputs cached for the task whose inputs have the given identity.
+    LookupOutputs {
+        inputs_identity: String,
+    },
+    /// Load the outputs of the task described by a task file from the cache, executing the task if
+    /// they are not cached. A relative path is resolved against the daemon's working directory.
+    ExecuteTask {
+        task_file: PathBuf,
+    },
+    Shutdown,
+}
+
+/// Response to a `DaemonRequest`, one JSON object per line.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum DaemonResponse {
+    Pong {
+        process_id: u32,
+    },
+    /// Identities of files, in request order; `None` for files that could not be identified.
+    FileIdentities {
+        identities: Vec<(PathBuf, Option<String>)>,
+    },
+    /// Identities of a task's input files, sorted by path and relative to its working directory,
+    /// with the identity of the manifest they form.
+    InputFiles {
+        manifest_identity: String,
+        identities: Vec<(PathBuf, Option<String>)>,
+    },
+    /// Identities of the cached output files, or `None` when the task is not cached.
+    Outputs {
+        identities: Option<Vec<(PathBuf, Option<String>)>>,
+    },
+    /// Identities of the output files of a task that was loaded from the cache or executed.
+    TaskOutputs {
+        identities: Vec<(PathBuf, Option<String>)>,
+    },
+    ShuttingDown,
+    Error {
+        message: String,
+    },
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(bound = "IS: IdentitySchemeApi")]
 pub struct TaskInputs<IS: IdentitySchemeApi> {
+    pub format_version: FormatVersion,
     #[serde(flatten)]
     pub environment_variables: EnvironmentVariables,
     #[serde(flatten)]
@@ -295,12 +991,23 @@ pub struct TaskInputs<IS: IdentitySchemeApi> {
     #[serde(flatten)]
     pub arguments: Arguments,
     pub input_files: FileIdentitiesManifest<IS>,
+    /// Maps input file paths to the paths at which the task expects to find them, for inputs that
+    /// are staged under a different name (e.g., `out/gen/foo.h` staged as `include/foo.h`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub staged_inputs: BTreeMap<PathBuf, PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<Stdin>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<Sandbox>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource_limits: Option<ResourceLimits>,
     pub outputs_description: Outputs,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(bound = "IS: IdentitySchemeApi")]
 pub struct TaskOutputs<IS: IdentitySchemeApi> {
+    pub format_version: FormatVersion,
     pub input_files_with_program: FileIdentitiesManifest<IS>,
     pub output_files: FileIdentitiesManifest<IS>,
 }
@@ -321,6 +1028,10 @@ impl FilesManifest {
 pub struct FileIdentitiesManifest<IS: IdentitySchemeApi> {
     pub identity_scheme: IdentityScheme,
     pub identities: Vec<(PathBuf, Option<IS::Identity>)>,
+    /// Targets of the paths in `identities` that are symbolic links. Omitted when empty, so that
+    /// manifests without links keep the identities they had before links were recorded.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub symlinks: BTreeMap<PathBuf, PathBuf>,
 }
 
 impl<IS: IdentitySchemeApi> FileIdentitiesManifest<IS> {
@@ -328,6 +1039,7 @@ impl<IS: IdentitySchemeApi> FileIdentitiesManifest<IS> {
         Self {
             identity_scheme: IS::IDENTITY_SCHEME,
             identities: vec![],
+            symlinks: BTreeMap::new(),
         }
     }
 }
@@ -339,15 +1051,121 @@ impl<IS: IdentitySchemeApi> From<Vec<(PathBuf, Option<IS::Identity>)>>
         Self {
             identity_scheme: IS::IDENTITY_SCHEME,
             identities,
+            symlinks: BTreeMap::new(),
         }
     }
 }
 
+/// A contiguous range of a chunked blob, stored as a blob of its own.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(bound = "IS::Identity: Clone + DeserializeOwned + Serialize")]
+pub struct Chunk<IS: IdentitySchemeApi> {
+    pub offset: u64,
+    pub length: u64,
+    pub identity: IS::Identity,
+}
+
+/// Files consumed and produced by each task of a build, recorded across invocations (see
+/// `crate::build_graph`).
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(bound = "IS::Identity: Clone + DeserializeOwned + Serialize")]
+pub struct BuildGraph<IS: IdentitySchemeApi> {
+    pub format_version: FormatVersion,
+    pub identity_scheme: IdentityScheme,
+    pub tasks: BTreeMap<String, BuildGraphNode<IS>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(bound = "IS::Identity: Clone + DeserializeOwned + Serialize")]
+pub struct BuildGraphNode<IS: IdentitySchemeApi> {
+    /// Input files, with the identities they had when the task last executed.
+    pub inputs: Vec<(PathBuf, Option<IS::Identity>)>,
+    /// Output files, with the identities the task last produced.
+    pub outputs: Vec<(PathBuf, Option<IS::Identity>)>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(bound = "IS::Identity: Clone + DeserializeOwned + Serialize")]
+pub struct ChunkManifest<IS: IdentitySchemeApi> {
+    pub format_version: FormatVersion,
+    pub identity_scheme: IdentityScheme,
+    pub chunks: Vec<Chunk<IS>>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Metadata {
+    pub format_version: FormatVersion,
     pub timestamp_nanos: i64,
     pub execution_duration_nanos: u128,
     pub system: System,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_memory_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+/// Result of an execution of a task, recorded whether or not the task succeeded.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TaskResult {
+    pub format_version: FormatVersion,
+    /// Time at which the execution completed, in nanoseconds since the Unix epoch.
+    #[serde(default)]
+    pub timestamp_nanos: i64,
+    /// Exit code of the task process, if it exited normally.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Signal that terminated the task process, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    pub duration_nanos: u128,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_memory_bytes: Option<u64>,
+}
+
+impl TaskResult {
+    /// Whether the execution succeeded: The task exited with code zero, or no process was spawned.
+    pub fn success(&self) -> bool {
+        self.signal.is_none() && self.exit_code.unwrap_or(0) == 0
+    }
+}
+
+/// Report of one invocation of the `execute` subcommand, written by `--report` for ingestion by CI
+/// systems.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(bound = "IS::Identity: Clone + DeserializeOwned + Serialize")]
+pub struct ExecutionReport<IS: IdentitySchemeApi> {
+    pub format_version: FormatVersion,
+    pub identity_scheme: IdentityScheme,
+    /// Identity of the task's inputs, which is its cache key.
+    pub inputs_identity: IS::Identity,
+    /// Whether the task's outputs were loaded from the cache rather than produced by executing it.
+    pub cache_hit: bool,
+    /// Time taken to load or execute the task, in nanoseconds.
+    pub wall_duration_nanos: u128,
+    /// Duration of the execution that produced the task's outputs, in nanoseconds, if recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub execution_duration_nanos: Option<u128>,
+    pub output_files: Vec<OutputFileReport<IS>>,
+    pub system: System,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(bound = "IS::Identity: Clone + DeserializeOwned + Serialize")]
+pub struct OutputFileReport<IS: IdentitySchemeApi> {
+    pub path: PathBuf,
+    /// Identity of the file's content; absent for optional outputs that the task did not produce.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<IS::Identity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_bytes: Option<u64>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -355,9 +1173,9 @@ pub struct System {
     pub name: Option<String>,
     pub long_os_version: Option<String>,
     pub kernel_version: Option<String>,
-    pub distribution_id: String,
-    pub total_memory: u64,
-    pub estimated_num_cpu_cores: usize,
+    pub distribution_id: Option<String>,
+    pub total_memory: Option<u64>,
+    pub estimated_num_cpu_cores: Option<usize>,
 }
 
 impl From<sysinfo::System> for System {
@@ -366,11 +1184,13 @@ impl From<sysinfo::System> for System {
             name: system.name(),
             long_os_version: system.long_os_version(),
             kernel_version: system.kernel_version(),
-            distribution_id: system.distribution_id(),
-            total_memory: system.total_memory(),
-            estimated_num_cpu_cores: system
-                .physical_core_count()
-                .unwrap_or_else(|| system.cpus().len()),
+            distribution_id: Some(system.distribution_id()),
+            total_memory: Some(system.total_memory()),
+            estimated_num_cpu_cores: Some(
+                system
+                    .physical_core_count()
+                    .unwrap_or_else(|| system.cpus().len()),
+            ),
         }
     }
 }
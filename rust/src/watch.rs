@@ -0,0 +1,566 @@
+// Copyright 2023 The Artifact Executor Authors. All rights reserved.
+// Use of this source code is governed by a Apache-style license that can be
+// found in the LICENSE file.
+
+//! Watch mode: re-resolve a task's inputs as files change and notify a callback, which typically
+//! re-executes the task.
+//!
+//! Changes are detected by re-resolving the inputs description and checking the size and
+//! modification time of each input file, so that files that appear, disappear, or are newly
+//! referenced are noticed as well as edits to existing files. Inputs are re-resolved when the
+//! operating system reports a change beneath the working directory (via inotify, on Linux), or
+//! periodically where such notifications are unavailable. Input file identities are only
+//! recomputed when sizes or modification times change, and a change is only reported when an
+//! identity differs. A change is reported only once inputs have been stable for the debounce
+//! period, so that a burst of writes (e.g., an editor saving several files) triggers one execution.
+
+use crate::canonical::FileIdentitiesManifest;
+use crate::canonical::FilesManifest;
+use crate::fs::Filesystem as FilesystemApi;
+use crate::identity::IdentityScheme as IdentitySchemeApi;
+use crate::transport::Inputs as InputsTransport;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+#[cfg(target_os = "linux")]
+use linux::Notifier;
+#[cfg(not(target_os = "linux"))]
+use unsupported::Notifier;
+
+/// Default for `WatchOptions::poll_interval`.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Default for `WatchOptions::debounce`.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct WatchOptions {
+    /// Time between checks for changes to inputs, where changes to files are not reported by the
+    /// operating system (e.g., on filesystems without a directory on the host).
+    pub poll_interval: Duration,
+    /// Time for which inputs must be unchanged before a change is reported. When polling, changes
+    /// are checked for once per poll interval, so a debounce no longer than the poll interval has no
+    /// effect.
+    pub debounce: Duration,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            debounce: DEFAULT_DEBOUNCE,
+        }
+    }
+}
+
+/// A change to a watched task's inputs.
+#[derive(Clone, Debug)]
+pub struct WatchEvent<IS: IdentitySchemeApi> {
+    /// The task's current input files.
+    pub input_files: FileIdentitiesManifest<IS>,
+    /// Input files that were added, removed, or changed identity since the previous event. Every
+    /// input file is listed in the first event.
+    pub changed_paths: Vec<PathBuf>,
+}
+
+/// Watches the input files described by an inputs description.
+pub struct Watcher<FS: FilesystemApi, IS: IdentitySchemeApi> {
+    filesystem: FS,
+    inputs: InputsTransport,
+    options: WatchOptions,
+    input_files: Option<FileIdentitiesManifest<IS>>,
+    input_stats: Option<InputStats>,
+}
+
+/// Size and modification time of each input file, or `None` for files that cannot be read.
+type InputStats = BTreeMap<PathBuf, Option<(u64, Option<SystemTime>)>>;
+
+impl<FS: FilesystemApi, IS: IdentitySchemeApi> Watcher<FS, IS> {
+    pub fn new(filesystem: FS, inputs: InputsTransport, options: WatchOptions) -> Self {
+        Self {
+            filesystem,
+            inputs,
+            options,
+            input_files: None,
+            input_stats: None,
+        }
+    }
+
+    /// Re-resolves input files, returning an event if they differ from those last observed. Input
+    /// files whose sizes and modification times are unchanged are not re-identified.
+    pub fn poll(&mut self) -> anyhow::Result<Option<WatchEvent<IS>>> {
+        let input_stats = self.input_stats()?;
+        if self.input_files.is_some() && self.input_stats.as_ref() == Some(&input_stats) {
+            return Ok(None);
+        }
+        self.input_stats = Some(input_stats);
+        let input_files =
+            FileIdentitiesManifest::try_from_inputs(&mut self.filesystem, &self.inputs)?;
+        let changed_paths = changed_paths(self.input_files.as_ref(), &input_files);
+        if self.input_files.is_some() && changed_paths.is_empty() {
+            return Ok(None);
+        }
+        self.input_files = Some(input_files.clone());
+        Ok(Some(WatchEvent {
+            input_files,
+            changed_paths,
+        }))
+    }
+
+    fn input_stats(&mut self) -> anyhow::Result<InputStats> {
+        let paths = FilesManifest::try_from((&mut self.filesystem, &self.inputs))?;
+        Ok(paths
+            .paths()
+            .map(|path| {
+                let stat = self
+                    .filesystem
+                    .metadata(path)
+                    .ok()
+                    .map(|metadata| (metadata.size, metadata.modified));
+                (path.clone(), stat)
+            })
+            .collect())
+    }
+
+    /// Starts watching for notifications of changes beneath the filesystem's working directory, or
+    /// returns `None` where they are unavailable and inputs must be polled.
+    fn notifier(&mut self) -> Option<Notifier> {
+        let root = self.filesystem.working_directory()?;
+        match Notifier::try_new(&root) {
+            Ok(notifier) => Some(notifier),
+            Err(error) => {
+                tracing::debug!("polling for changes to inputs under {:?}: {}", root, error);
+                None
+            }
+        }
+    }
+
+    /// Invokes `on_change` after each debounced change to inputs, starting with their first
+    /// resolution, until `on_change` breaks or fails.
+    pub fn run<F: FnMut(&WatchEvent<IS>) -> anyhow::Result<ControlFlow<()>>>(
+        &mut self,
+        mut on_change: F,
+    ) -> anyhow::Result<()> {
+        // Watch before the first resolution, so that no change after it goes unreported.
+        let mut notifier = self.notifier();
+        let mut pending: Option<WatchEvent<IS>> = None;
+        let mut last_change = Instant::now();
+        loop {
+            if let Some(event) = self.poll()? {
+                if let Some(notifier) = notifier.as_mut() {
+                    watch_absolute_inputs(notifier, &event.input_files);
+                }
+                last_change = Instant::now();
+                pending = Some(match pending {
+                    // Report every path changed while debouncing, not only the latest.
+                    Some(previous) => WatchEvent {
+                        changed_paths: previous
+                            .changed_paths
+                            .into_iter()
+                            .chain(event.changed_paths)
+                            .collect::<BTreeSet<_>>()
+                            .into_iter()
+                            .collect(),
+                        input_files: event.input_files,
+                    },
+                    None => event,
+                });
+            }
+            if pending.is_some() && last_change.elapsed() >= self.options.debounce {
+                let event = pending.take().expect("pending watch event");
+                if on_change(&event)?.is_break() {
+                    return Ok(());
+                }
+            }
+            // Wait for a change, or until a pending change has been stable for the debounce period.
+            let timeout = pending
+                .as_ref()
+                .map(|_| self.options.debounce.saturating_sub(last_change.elapsed()));
+            match notifier.as_mut().map(|notifier| notifier.wait(timeout)) {
+                Some(Ok(_)) => {}
+                Some(Err(error)) => {
+                    tracing::warn!("polling for changes to inputs: {}", error);
+                    notifier = None;
+                }
+                None => std::thread::sleep(self.options.poll_interval),
+            }
+        }
+    }
+}
+
+/// Watches the directories of absolute input files, which may lie outside the watched working
+/// directory.
+fn watch_absolute_inputs<IS: IdentitySchemeApi>(
+    notifier: &mut Notifier,
+    input_files: &FileIdentitiesManifest<IS>,
+) {
+    let directories: BTreeSet<&Path> = input_files
+        .identities()
+        .filter(|(path, _)| path.is_absolute())
+        .filter_map(|(path, _)| path.parent())
+        .collect();
+    for directory in directories {
+        if let Err(error) = notifier.watch_directory(directory) {
+            tracing::debug!("not watching {:?} for changes: {}", directory, error);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::collections::HashMap;
+    use std::ffi::CString;
+    use std::ffi::OsStr;
+    use std::io;
+    use std::os::fd::AsRawFd as _;
+    use std::os::fd::FromRawFd as _;
+    use std::os::fd::OwnedFd;
+    use std::os::unix::ffi::OsStrExt as _;
+    use std::path::Path;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    /// Events that may change the files in a watched directory.
+    const WATCH_MASK: u32 = libc::IN_ATTRIB
+        | libc::IN_CLOSE_WRITE
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_DELETE_SELF
+        | libc::IN_MODIFY
+        | libc::IN_MOVE_SELF
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO;
+
+    /// Size of `struct inotify_event`, which precedes the event's NUL-padded name.
+    const EVENT_HEADER_BYTES: usize = 16;
+
+    /// Reports changes to the files in a directory tree, and in other watched directories, via
+    /// inotify. Directories created in the tree are watched as they appear.
+    pub(super) struct Notifier {
+        inotify: OwnedFd,
+        root: PathBuf,
+        /// Watched directories, by watch descriptor.
+        directories: HashMap<libc::c_int, PathBuf>,
+    }
+
+    impl Notifier {
+        /// Watches `root` and every directory beneath it.
+        pub(super) fn try_new(root: &Path) -> io::Result<Self> {
+            // SAFETY: `inotify_init1` has no memory-safety preconditions.
+            let inotify = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+            if inotify < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut notifier = Self {
+                // SAFETY: `inotify` is a newly opened descriptor that nothing else owns.
+                inotify: unsafe { OwnedFd::from_raw_fd(inotify) },
+                root: root.to_path_buf(),
+                directories: HashMap::new(),
+            };
+            notifier.watch_tree(root)?;
+            Ok(notifier)
+        }
+
+        /// Watches `directory`, but not its subdirectories. Watching a directory again has no
+        /// effect.
+        pub(super) fn watch_directory(&mut self, directory: &Path) -> io::Result<()> {
+            let path = CString::new(directory.as_os_str().as_bytes())?;
+            // SAFETY: `path` is a NUL-terminated string that outlives the call.
+            let watch = unsafe {
+                libc::inotify_add_watch(self.inotify.as_raw_fd(), path.as_ptr(), WATCH_MASK)
+            };
+            if watch < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.directories.insert(watch, directory.to_path_buf());
+            Ok(())
+        }
+
+        /// Watches `directory` and every directory beneath it, without following symbolic links.
+        /// Subdirectories that disappear or cannot be read while they are walked are skipped.
+        fn watch_tree(&mut self, directory: &Path) -> io::Result<()> {
+            let mut directories = vec![directory.to_path_buf()];
+            while let Some(subdirectory) = directories.pop() {
+                let entries = self
+                    .watch_directory(&subdirectory)
+                    .and_then(|()| std::fs::read_dir(&subdirectory));
+                match entries {
+                    Ok(entries) => directories.extend(
+                        entries
+                            .filter_map(Result::ok)
+                            .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+                            .map(|entry| entry.path()),
+                    ),
+                    Err(error)
+                        if subdirectory != directory
+                            && matches!(
+                                error.kind(),
+                                io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+                            ) => {}
+                    Err(error) => return Err(error),
+                }
+            }
+            Ok(())
+        }
+
+        /// Waits until a change is reported, or until `timeout` passes (or indefinitely, if it is
+        /// `None`), returning whether a change was reported.
+        pub(super) fn wait(&mut self, timeout: Option<Duration>) -> io::Result<bool> {
+            let mut poll_fd = libc::pollfd {
+                fd: self.inotify.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout_millis = match timeout {
+                // Round up, so that a wait never ends before `timeout`.
+                Some(timeout) => libc::c_int::try_from(timeout.as_micros().div_ceil(1000))
+                    .unwrap_or(libc::c_int::MAX),
+                None => -1,
+            };
+            // SAFETY: `poll_fd` is a valid `pollfd` for the duration of the call.
+            let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout_millis) };
+            if ready < 0 {
+                let error = io::Error::last_os_error();
+                return match error.kind() {
+                    io::ErrorKind::Interrupted => Ok(false),
+                    _ => Err(error),
+                };
+            }
+            if ready == 0 {
+                return Ok(false);
+            }
+            self.read_events()?;
+            Ok(true)
+        }
+
+        /// Drains the reported events, watching the directories that they report as created.
+        fn read_events(&mut self) -> io::Result<()> {
+            // Holds at least one event, whose name is at most `NAME_MAX` bytes.
+            let mut buffer = [0u8; 4096];
+            loop {
+                // SAFETY: `buffer` is writable for its whole length.
+                let length = unsafe {
+                    libc::read(
+                        self.inotify.as_raw_fd(),
+                        buffer.as_mut_ptr().cast(),
+                        buffer.len(),
+                    )
+                };
+                if length < 0 {
+                    let error = io::Error::last_os_error();
+                    match error.kind() {
+                        io::ErrorKind::WouldBlock => return Ok(()),
+                        io::ErrorKind::Interrupted => continue,
+                        _ => return Err(error),
+                    }
+                }
+                let events = &buffer[..length as usize];
+                let mut offset = 0;
+                while offset + EVENT_HEADER_BYTES <= events.len() {
+                    let field = |index: usize| {
+                        let start = offset + 4 * index;
+                        u32::from_ne_bytes(events[start..start + 4].try_into().expect("4 bytes"))
+                    };
+                    let watch = field(0) as libc::c_int;
+                    let mask = field(1);
+                    let name_start = offset + EVENT_HEADER_BYTES;
+                    offset = name_start + field(3) as usize;
+                    let name = events[name_start..offset]
+                        .split(|byte| *byte == 0)
+                        .next()
+                        .unwrap_or_default();
+
+                    if mask & libc::IN_Q_OVERFLOW != 0 {
+                        // Events were dropped, possibly including those of new directories.
+                        let root = self.root.clone();
+                        self.watch_tree(&root)?;
+                    } else if mask & libc::IN_IGNORED != 0 {
+                        self.directories.remove(&watch);
+                    } else if mask & libc::IN_ISDIR != 0
+                        && mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0
+                    {
+                        if let Some(parent) = self.directories.get(&watch) {
+                            let directory = parent.join(OsStr::from_bytes(name));
+                            self.watch_tree(&directory)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod unsupported {
+    use std::io;
+    use std::path::Path;
+    use std::time::Duration;
+
+    /// Stands in for the notifier of platforms whose file change notifications are not supported,
+    /// so that inputs are polled.
+    pub(super) struct Notifier;
+
+    impl Notifier {
+        pub(super) fn try_new(_root: &Path) -> io::Result<Self> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "file change notifications are not supported on this platform",
+            ))
+        }
+
+        pub(super) fn watch_directory(&mut self, _directory: &Path) -> io::Result<()> {
+            Ok(())
+        }
+
+        pub(super) fn wait(&mut self, _timeout: Option<Duration>) -> io::Result<bool> {
+            Ok(false)
+        }
+    }
+}
+
+fn changed_paths<IS: IdentitySchemeApi>(
+    before: Option<&FileIdentitiesManifest<IS>>,
+    after: &FileIdentitiesManifest<IS>,
+) -> Vec<PathBuf> {
+    let before: BTreeMap<&PathBuf, &Option<IS::Identity>> = before
+        .into_iter()
+        .flat_map(|manifest| manifest.identities())
+        .map(|(path, identity)| (path, identity))
+        .collect();
+    let after: BTreeMap<&PathBuf, &Option<IS::Identity>> = after
+        .identities()
+        .map(|(path, identity)| (path, identity))
+        .collect();
+    before
+        .keys()
+        .chain(after.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|path| before.get(*path) != after.get(*path))
+        .map(|path| (*path).clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WatchOptions;
+    use super::Watcher;
+    use crate::fs::HostFilesystem;
+    use crate::transport::ContentSha256;
+    use crate::transport::Inputs as InputsTransport;
+    use std::ops::ControlFlow;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn test_watcher() {
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let directory = temporary_directory.path().to_path_buf();
+        std::fs::write(directory.join("a.txt"), "a").expect("write a.txt");
+        let filesystem = HostFilesystem::try_new(directory.clone()).expect("host filesystem");
+        let inputs = InputsTransport {
+            include_globs: vec![String::from("*.txt")],
+            ..InputsTransport::default()
+        };
+        let options = WatchOptions {
+            poll_interval: Duration::from_millis(1),
+            debounce: Duration::from_millis(5),
+        };
+        let mut watcher =
+            Watcher::<_, ContentSha256>::new(filesystem.clone(), inputs.clone(), options);
+
+        let event = watcher.poll().expect("poll").expect("initial event");
+        assert_eq!(vec![PathBuf::from("a.txt")], event.changed_paths);
+        assert!(watcher.poll().expect("poll").is_none());
+
+        // Rewriting a file without changing its content is not a change.
+        std::fs::write(directory.join("a.txt"), "a").expect("rewrite a.txt");
+        assert!(watcher.poll().expect("poll").is_none());
+
+        std::fs::write(directory.join("a.txt"), "changed").expect("change a.txt");
+        std::fs::write(directory.join("b.txt"), "b").expect("write b.txt");
+        let event = watcher.poll().expect("poll").expect("change event");
+        assert_eq!(
+            vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")],
+            event.changed_paths
+        );
+        assert_eq!(2, event.input_files.identities().count());
+
+        let mut events = vec![];
+        Watcher::<_, ContentSha256>::new(filesystem, inputs, options)
+            .run(|event| {
+                events.push(event.changed_paths.clone());
+                if events.len() == 1 {
+                    std::fs::remove_file(directory.join("b.txt")).expect("remove b.txt");
+                    Ok(ControlFlow::Continue(()))
+                } else {
+                    Ok(ControlFlow::Break(()))
+                }
+            })
+            .expect("run watcher");
+        assert_eq!(
+            vec![
+                vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")],
+                vec![PathBuf::from("b.txt")],
+            ],
+            events
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_watcher_notifications() {
+        use std::time::Instant;
+
+        let temporary_directory = tempfile::tempdir().expect("temporary directory");
+        let directory = temporary_directory.path().to_path_buf();
+        std::fs::write(directory.join("a.txt"), "a").expect("write a.txt");
+        let filesystem = HostFilesystem::try_new(directory.clone()).expect("host filesystem");
+        let inputs = InputsTransport {
+            include_globs: vec![String::from("**/*.txt")],
+            ..InputsTransport::default()
+        };
+        // Changes are reported long before inputs would next be polled.
+        let options = WatchOptions {
+            poll_interval: Duration::from_secs(60),
+            debounce: Duration::from_millis(5),
+        };
+
+        let start = Instant::now();
+        let mut events = vec![];
+        Watcher::<_, ContentSha256>::new(filesystem, inputs, options)
+            .run(|event| {
+                events.push(event.changed_paths.clone());
+                match events.len() {
+                    1 => {
+                        std::fs::create_dir(directory.join("sub")).expect("create sub");
+                        std::fs::write(directory.join("sub/b.txt"), "b").expect("write sub/b.txt");
+                        Ok(ControlFlow::Continue(()))
+                    }
+                    // Files in new directories are watched.
+                    2 => {
+                        std::fs::write(directory.join("sub/b.txt"), "changed")
+                            .expect("change sub/b.txt");
+                        Ok(ControlFlow::Continue(()))
+                    }
+                    _ => Ok(ControlFlow::Break(())),
+                }
+            })
+            .expect("run watcher");
+        assert_eq!(
+            vec![
+                vec![PathBuf::from("a.txt")],
+                vec![PathBuf::from("sub/b.txt")],
+                vec![PathBuf::from("sub/b.txt")],
+            ],
+            events
+        );
+        assert!(start.elapsed() < options.poll_interval / 2);
+    }
+}